@@ -0,0 +1,45 @@
+// Framebuffer to RGBA conversion, per frame: the lookup table against
+// expanding every pixel's channels.
+//
+//   cargo bench -p roba-core --bench color_conversion
+
+use roba_core::video::{bgr555_to_rgba8888, framebuffer_rgb555_to_rgba, ColorProfile, ColorTable, GBA_SCREEN_H, GBA_SCREEN_W};
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::hint::black_box;
+
+const PIXELS: usize = GBA_SCREEN_W * GBA_SCREEN_H;
+
+fn frame() -> Vec<u16> {
+    // Deterministic noise so every table entry is equally likely.
+    let mut x = 0x1234_5678u32;
+    (0..PIXELS)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            x as u16 & 0x7FFF
+        })
+        .collect()
+}
+
+fn expand_per_pixel(dst: &mut [u8], src: &[u16]) {
+    for (i, &px) in src.iter().enumerate() {
+        dst[i * 4..i * 4 + 4].copy_from_slice(&bgr555_to_rgba8888(px));
+    }
+}
+
+fn bench(c: &mut Criterion) {
+    let src = frame();
+    let mut dst = vec![0u8; PIXELS * 4];
+    let table = ColorTable::new(ColorProfile::GbaSp);
+
+    let mut group = c.benchmark_group("rgb555_to_rgba");
+    group.bench_function("expand_per_pixel", |b| b.iter(|| expand_per_pixel(black_box(&mut dst), black_box(&src))));
+    group.bench_function("table", |b| {
+        b.iter(|| framebuffer_rgb555_to_rgba(black_box(&mut dst), black_box(&src), &table))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
@@ -0,0 +1,24 @@
+// Replays arbitrary bus accesses: each 7-byte record is an access kind, an
+// address and a value.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use roba_core::bus::{Bus, BusAccess};
+
+fuzz_target!(|data: &[u8]| {
+    let mut bus = Bus::new();
+    bus.load_rom(&[0xAA; 0x100]);
+    for record in data.chunks_exact(7) {
+        let addr = u32::from_le_bytes([record[1], record[2], record[3], record[4]]);
+        let value = u16::from_le_bytes([record[5], record[6]]);
+        match record[0] % 6 {
+            0 => { bus.read8(addr); }
+            1 => { bus.read16(addr); }
+            2 => { bus.read32(addr); }
+            3 => bus.write8(addr, value as u8),
+            4 => bus.write16(addr, value),
+            _ => bus.write32(addr, value as u32 * 0x0001_0001),
+        }
+        bus.run_pending_dma();
+    }
+});
@@ -0,0 +1,13 @@
+// Runs an arbitrary ROM for a few frames.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use roba_core::Emulator;
+
+fuzz_target!(|rom: &[u8]| {
+    let mut emu = Emulator::new();
+    emu.load_rom_bytes(rom);
+    for _ in 0..2 {
+        emu.run_frame();
+    }
+});
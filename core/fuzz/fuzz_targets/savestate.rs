@@ -0,0 +1,19 @@
+// Loads an arbitrary savestate body and runs a frame if it is accepted. The
+// header is supplied so the input goes to the machine fields.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use roba_core::state::{STATE_MAGIC, STATE_VERSION};
+use roba_core::Emulator;
+
+fuzz_target!(|body: &[u8]| {
+    let mut emu = Emulator::new();
+    emu.load_rom_bytes(&[0xFE, 0xFF, 0xFF, 0xEA]);
+    let mut data = STATE_MAGIC.to_vec();
+    data.extend_from_slice(&STATE_VERSION.to_le_bytes());
+    data.extend_from_slice(&emu.rom_crc32().to_le_bytes());
+    data.extend_from_slice(body);
+    if emu.load_state(&data).is_ok() {
+        emu.run_frame();
+    }
+});
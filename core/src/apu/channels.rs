@@ -0,0 +1,162 @@
+// Per-channel controls for debugging and listening: muting or soloing each
+// of the six sound sources before they reach the mixer, and a short history
+// of what each one output, for oscilloscope views and level meters. The
+// history records the sources as they play, muted or not.
+
+use super::mixer::Levels;
+use std::collections::VecDeque;
+
+/// Host samples of history kept per channel.
+pub const SCOPE_LEN: usize = 512;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Square1,
+    Square2,
+    Wave,
+    Noise,
+    FifoA,
+    FifoB,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 6] =
+        [Channel::Square1, Channel::Square2, Channel::Wave, Channel::Noise, Channel::FifoA, Channel::FifoB];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Channel::Square1 => "Square 1",
+            Channel::Square2 => "Square 2",
+            Channel::Wave => "Wave",
+            Channel::Noise => "Noise",
+            Channel::FifoA => "FIFO A",
+            Channel::FifoB => "FIFO B",
+        }
+    }
+
+    /// Largest level the channel outputs: 15 for the PSG channels, 128 for
+    /// the FIFOs.
+    pub fn full_scale(self) -> i32 {
+        match self {
+            Channel::FifoA | Channel::FifoB => 128,
+            _ => 15,
+        }
+    }
+
+    fn index(self) -> usize { self as usize }
+
+    fn level(self, levels: &Levels) -> i8 {
+        match self {
+            Channel::FifoA => levels.fifo[0],
+            Channel::FifoB => levels.fifo[1],
+            psg => levels.psg[psg.index()],
+        }
+    }
+
+    fn silence(self, levels: &mut Levels) {
+        match self {
+            Channel::FifoA => levels.fifo[0] = 0,
+            Channel::FifoB => levels.fifo[1] = 0,
+            psg => levels.psg[psg.index()] = 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Channels {
+    muted: [bool; 6],
+    solo: Option<Channel>,
+    scopes: [VecDeque<i8>; 6],
+}
+
+impl Default for Channels {
+    fn default() -> Self {
+        Self { muted: [false; 6], solo: None, scopes: std::array::from_fn(|_| VecDeque::with_capacity(SCOPE_LEN)) }
+    }
+}
+
+impl Channels {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn is_muted(&self, channel: Channel) -> bool { self.muted[channel.index()] }
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) { self.muted[channel.index()] = muted; }
+
+    /// The only channel heard, whatever is muted.
+    pub fn solo(&self) -> Option<Channel> { self.solo }
+    pub fn set_solo(&mut self, solo: Option<Channel>) { self.solo = solo; }
+
+    pub fn is_audible(&self, channel: Channel) -> bool {
+        match self.solo {
+            Some(solo) => solo == channel,
+            None => !self.is_muted(channel),
+        }
+    }
+
+    /// The channel's last `SCOPE_LEN` levels at most, oldest first.
+    pub fn scope(&self, channel: Channel) -> &VecDeque<i8> { &self.scopes[channel.index()] }
+
+    /// Loudest level in the channel's history, 0.0 to 1.0 of full scale.
+    pub fn peak(&self, channel: Channel) -> f32 {
+        let peak = self.scope(channel).iter().map(|&l| (l as i32).abs()).max().unwrap_or(0);
+        (peak as f32 / channel.full_scale() as f32).min(1.0)
+    }
+
+    /// `levels` with the channels that cannot be heard silenced.
+    pub(crate) fn apply(&self, levels: &Levels) -> Levels {
+        let mut heard = *levels;
+        for channel in Channel::ALL.into_iter().filter(|&c| !self.is_audible(c)) {
+            channel.silence(&mut heard);
+        }
+        heard
+    }
+
+    /// Adds `samples` host samples of `levels` to the history.
+    pub(crate) fn record(&mut self, levels: &Levels, samples: usize) {
+        for channel in Channel::ALL {
+            let scope = &mut self.scopes[channel.index()];
+            for _ in 0..samples.min(SCOPE_LEN) {
+                if scope.len() == SCOPE_LEN {
+                    scope.pop_front();
+                }
+                scope.push_back(channel.level(levels));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOUD: Levels = Levels { psg: [1, 2, 3, 4], fifo: [5, 6] };
+
+    #[test]
+    fn muted_and_unsoloed_channels_are_silenced() {
+        let mut channels = Channels::new();
+        assert_eq!(channels.apply(&LOUD), LOUD);
+        channels.set_muted(Channel::Wave, true);
+        channels.set_muted(Channel::FifoA, true);
+        assert_eq!(channels.apply(&LOUD), Levels { psg: [1, 2, 0, 4], fifo: [0, 6] });
+
+        // Solo wins over muting.
+        channels.set_solo(Some(Channel::FifoA));
+        assert!(channels.is_audible(Channel::FifoA));
+        assert_eq!(channels.apply(&LOUD), Levels { psg: [0; 4], fifo: [5, 0] });
+        channels.set_solo(None);
+        assert!(!channels.is_audible(Channel::FifoA));
+    }
+
+    #[test]
+    fn scopes_keep_the_latest_levels() {
+        let mut channels = Channels::new();
+        channels.record(&LOUD, 3);
+        channels.record(&Levels { psg: [-15, 0, 0, 0], fifo: [-128, 0] }, SCOPE_LEN - 1);
+        let scope = channels.scope(Channel::Square1);
+        assert_eq!(scope.len(), SCOPE_LEN);
+        assert_eq!(scope[0], 1);
+        assert_eq!(scope[1], -15);
+        assert_eq!(channels.peak(Channel::Square1), 1.0);
+        assert_eq!(channels.peak(Channel::Square2), 2.0 / 15.0);
+        assert_eq!(channels.peak(Channel::FifoA), 1.0);
+    }
+}
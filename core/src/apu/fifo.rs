@@ -0,0 +1,151 @@
+// Direct Sound: the two 32-byte FIFOs of signed 8-bit PCM behind sound
+// channels A and B. The game pushes samples to FIFO_A or FIFO_B, normally
+// with DMA 1 or 2 in sound FIFO mode; each overflow of the timer a FIFO
+// follows (SOUNDCNT_H bits 10 and 14) plays its next sample, and a FIFO left
+// half full or less asks for four more words.
+
+use crate::io::Io;
+use crate::state::impl_savestate;
+
+pub const FIFO_A: u32 = 0x0400_00A0;
+pub const FIFO_B: u32 = 0x0400_00A4;
+pub const FIFO_END: u32 = 0x0400_00A8;
+
+/// Bytes each FIFO holds.
+pub const FIFO_LEN: usize = 32;
+// Fill level at or below which a FIFO requests a refill.
+const REFILL_LEVEL: usize = 16;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Fifo {
+    data: [i8; FIFO_LEN],
+    read: u8,
+    len: u8,
+    // The sample playing, kept when the FIFO runs dry.
+    sample: i8,
+}
+
+impl_savestate!(Fifo { data, read, len, sample });
+
+impl Fifo {
+    pub fn len(&self) -> usize { self.len as usize }
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+    pub fn sample(&self) -> i8 { self.sample }
+
+    // Bytes pushed to a full FIFO are lost.
+    fn push(&mut self, value: i8) {
+        if self.len() < FIFO_LEN {
+            self.data[(self.read as usize + self.len()) % FIFO_LEN] = value;
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) {
+        if !self.is_empty() {
+            self.sample = self.data[self.read as usize];
+            self.read = (self.read + 1) % FIFO_LEN as u8;
+            self.len -= 1;
+        }
+    }
+
+    // Empties the FIFO; the last sample keeps playing.
+    fn reset(&mut self) {
+        self.read = 0;
+        self.len = 0;
+    }
+}
+
+#[derive(Default)]
+pub struct DirectSound {
+    fifos: [Fifo; 2],
+}
+
+impl_savestate!(DirectSound { fifos });
+
+impl DirectSound {
+    pub fn fifo(&self, index: usize) -> &Fifo { &self.fifos[index] }
+
+    /// What FIFOs A and B are playing.
+    pub fn levels(&self) -> [i8; 2] { self.fifos.map(|f| f.sample) }
+
+    /// Byte writes to FIFO_A..FIFO_END, in address order within a word.
+    pub fn write8(&mut self, addr: u32, value: u8) { self.fifos[((addr - FIFO_A) >> 2) as usize].push(value as i8); }
+
+    pub fn reset(&mut self, index: usize) { self.fifos[index].reset(); }
+
+    /// Plays the next sample of the FIFOs following `timer`. Returns the
+    /// addresses of the FIFOs that want refilling. Nothing plays while sound
+    /// is off in SOUNDCNT_X.
+    pub fn timer_overflow(&mut self, timer: usize, io: &Io) -> impl Iterator<Item = u32> + use<> {
+        let mut refill = [None; 2];
+        if io.soundcnt_x & 0x80 != 0 {
+            for (index, fifo) in self.fifos.iter_mut().enumerate() {
+                if (io.soundcnt_h >> (10 + 4 * index)) as usize & 1 == timer {
+                    fifo.pop();
+                    if fifo.len() <= REFILL_LEVEL {
+                        refill[index] = Some(FIFO_A + 4 * index as u32);
+                    }
+                }
+            }
+        }
+        refill.into_iter().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn io(soundcnt_h: u16) -> Io { Io { soundcnt_h, soundcnt_x: 0x80, ..Io::default() } }
+
+    fn push(sound: &mut DirectSound, fifo: u32, bytes: &[u8]) {
+        for &b in bytes {
+            sound.write8(fifo, b);
+        }
+    }
+
+    #[test]
+    fn timers_play_their_fifos_and_ask_for_more() {
+        let mut sound = DirectSound::default();
+        // A follows timer 0, B timer 1.
+        let io = io(0x4000);
+        push(&mut sound, FIFO_A, &[1, 2, 0xFF]);
+        push(&mut sound, FIFO_B, &[0x80; 20]);
+
+        assert!(sound.timer_overflow(0, &io).eq([FIFO_A]));
+        assert_eq!(sound.levels(), [1, 0]);
+        assert_eq!(sound.timer_overflow(1, &io).count(), 0);
+        assert_eq!(sound.fifo(1).len(), 19);
+        assert_eq!(sound.levels(), [1, -128]);
+
+        sound.timer_overflow(0, &io).for_each(drop);
+        sound.timer_overflow(0, &io).for_each(drop);
+        assert_eq!(sound.levels()[0], -1);
+        // Dry: the last sample holds.
+        assert!(sound.timer_overflow(0, &io).eq([FIFO_A]));
+        assert_eq!(sound.levels()[0], -1);
+        assert!(sound.fifo(0).is_empty());
+
+        // Nothing plays with sound off.
+        let off = Io { soundcnt_x: 0, ..io };
+        assert_eq!(sound.timer_overflow(1, &off).count(), 0);
+        assert_eq!(sound.fifo(1).len(), 19);
+    }
+
+    #[test]
+    fn full_fifos_drop_pushes_and_resets_empty_them() {
+        let mut sound = DirectSound::default();
+        let bytes: Vec<u8> = (0..40).collect();
+        push(&mut sound, FIFO_B, &bytes);
+        assert_eq!(sound.fifo(1).len(), FIFO_LEN);
+        let io = io(0x0000);
+        for expected in 0..FIFO_LEN as i8 {
+            sound.timer_overflow(0, &io).for_each(drop);
+            assert_eq!(sound.levels()[1], expected);
+        }
+        push(&mut sound, FIFO_B, &[7, 8]);
+        sound.reset(1);
+        assert!(sound.fifo(1).is_empty());
+        assert_eq!(sound.levels()[1], 31);
+    }
+}
@@ -0,0 +1,125 @@
+// Output stage of the sound hardware. The four PSG channels and the two
+// Direct Sound FIFOs are summed per side as SOUNDCNT_L/H route and scale
+// them, SOUNDBIAS shifts the sum into the 10-bit range of the PWM DAC, which
+// clips it and drops as many low bits as the amplitude resolution gives up
+// for a higher sampling rate.
+
+use crate::io::Io;
+
+const DAC_MAX: i32 = 0x3FF;
+// 10-bit DAC levels to i16 host samples.
+const OUTPUT_SHIFT: u32 = 6;
+
+/// What each sound source outputs at one instant.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Levels {
+    /// PSG channels 1-4, -15..=15 at their current envelope volume.
+    pub psg: [i8; 4],
+    /// Direct Sound A and B, the last sample each FIFO played.
+    pub fifo: [i8; 2],
+}
+
+/// Left and right host samples for `levels` under the current sound
+/// registers. Silent while the master enable in SOUNDCNT_X is off.
+pub fn mix(io: &Io, levels: &Levels) -> [i16; 2] {
+    if io.soundcnt_x & 0x80 == 0 {
+        return [0, 0];
+    }
+    let bias = bias_level(io.soundbias);
+    let resolution = (io.soundbias >> 14) as u32;
+    // Right is bit 0 of each routing pair, left bit 1.
+    [1, 0].map(|side| {
+        let level = psg_level(io, levels, side) + fifo_level(io, levels, side);
+        let dac = quantize((level + bias).clamp(0, DAC_MAX), resolution);
+        ((dac - bias) << OUTPUT_SHIFT) as i16
+    })
+}
+
+/// DAC sampling rate in Hz for the amplitude resolution in SOUNDBIAS:
+/// 9 bits at 32768 Hz down to 6 bits at 262144 Hz.
+pub fn output_rate(soundbias: u16) -> u32 { 32_768 << (soundbias >> 14) }
+
+fn bias_level(soundbias: u16) -> i32 { (soundbias & 0x03FE) as i32 }
+
+fn psg_level(io: &Io, levels: &Levels, side: usize) -> i32 {
+    let enables = io.soundcnt_l >> (8 + 4 * side);
+    let sum: i32 = (0..4).filter(|i| enables & (1 << i) != 0).map(|i| levels.psg[i] as i32).sum();
+    let master = ((io.soundcnt_l >> (4 * side)) & 7) as i32 + 1;
+    // 25%, 50%, 100%; 3 is prohibited and behaves like 100%.
+    let shift = match io.soundcnt_h & 3 {
+        0 => 2,
+        1 => 1,
+        _ => 0,
+    };
+    (sum * master) >> shift
+}
+
+fn fifo_level(io: &Io, levels: &Levels, side: usize) -> i32 {
+    (0..2)
+        .filter(|fifo| io.soundcnt_h & (1 << (8 + 4 * fifo + side)) != 0)
+        .map(|fifo| {
+            // 50% or 100%: a full-scale sample spans half or all of the DAC.
+            let full = io.soundcnt_h & (4 << fifo) != 0;
+            levels.fifo[fifo] as i32 * if full { 4 } else { 2 }
+        })
+        .sum()
+}
+
+fn quantize(dac: i32, resolution: u32) -> i32 { dac & !((2 << resolution) - 1) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn io(soundcnt_l: u16, soundcnt_h: u16, soundbias: u16) -> Io {
+        Io { soundcnt_l, soundcnt_h, soundcnt_x: 0x80, soundbias, ..Io::default() }
+    }
+
+    #[test]
+    fn fifos_are_routed_and_scaled() {
+        // A at 100% to both sides, B at 50% to the left only.
+        let io = io(0, 0x2304, 0x0200);
+        let levels = Levels { fifo: [64, -64], ..Levels::default() };
+        assert_eq!(mix(&io, &levels), [(256 - 128) << 6, 256 << 6]);
+    }
+
+    #[test]
+    fn psg_uses_master_volume_and_ratio() {
+        // Channels 1 and 2 left, channel 1 right; left volume 7, right 3;
+        // PSG at 50%.
+        let io = io(0x3173, 0x0001, 0x0200);
+        let levels = Levels { psg: [15, 10, 0, 0], ..Levels::default() };
+        assert_eq!(mix(&io, &levels), [(25 * 8 / 2) << 6, (15 * 4 / 2) << 6]);
+    }
+
+    #[test]
+    fn bias_clips_loud_samples() {
+        let levels = Levels { fifo: [127, 0], ..Levels::default() };
+        // Centered, +508 still fits under the 0x3FF ceiling...
+        assert_eq!(mix(&io(0, 0x0304, 0x0200), &levels), [508 << 6; 2]);
+        // ...but a higher bias clips it at 0x3FF, and 9-bit output drops bit 0.
+        assert_eq!(mix(&io(0, 0x0304, 0x0300), &levels), [0xFE << 6; 2]);
+        // Negative samples clip at 0 with a low bias.
+        let quiet = Levels { fifo: [-128, 0], ..Levels::default() };
+        assert_eq!(mix(&io(0, 0x0304, 0x0100), &quiet), [-0x100 << 6; 2]);
+    }
+
+    #[test]
+    fn resolution_drops_low_bits() {
+        let levels = Levels { fifo: [5, 0], ..Levels::default() };
+        // 9 bits: 0x200 + 20 keeps bit 1 and up.
+        assert_eq!(mix(&io(0, 0x0304, 0x0200), &levels), [20 << 6; 2]);
+        // 6 bits: steps of 16.
+        assert_eq!(mix(&io(0, 0x0304, 0xC200), &levels), [16 << 6; 2]);
+        assert_eq!(output_rate(0xC200), 262_144);
+        assert_eq!(output_rate(0x0200), 32_768);
+    }
+
+    #[test]
+    fn master_enable_silences_everything() {
+        let mut io = io(0xFF77, 0x3302, 0x0200);
+        io.soundcnt_x = 0;
+        let levels = Levels { psg: [15; 4], fifo: [127, 127] };
+        assert_eq!(mix(&io, &levels), [0, 0]);
+    }
+}
@@ -1,6 +1,81 @@
-#[derive(Default)]
-pub struct Apu;
+pub mod channels;
+pub mod fifo;
+pub mod mixer;
+
+use crate::audio::StereoSample;
+use crate::config::DEFAULT_SAMPLE_RATE;
+use crate::io::Io;
+use crate::CPU_CLOCK;
+use mixer::Levels;
+
+pub use channels::{Channel, Channels};
+pub use fifo::DirectSound;
+
+pub struct Apu {
+    sample_rate: u32,
+    // CPU cycles times the sample rate not yet worth a whole sample.
+    phase: u64,
+    levels: Levels,
+    channels: Channels,
+}
+
+impl Default for Apu {
+    fn default() -> Self { Self::new(DEFAULT_SAMPLE_RATE) }
+}
 
 impl Apu {
-    pub fn new() -> Self { Self }
+    pub fn new(sample_rate: u32) -> Self { Self { sample_rate: sample_rate.max(1), phase: 0, levels: Levels::default(), channels: Channels::new() } }
+
+    /// Host output rate in Hz.
+    pub fn sample_rate(&self) -> u32 { self.sample_rate }
+    pub fn set_sample_rate(&mut self, hz: u32) { self.sample_rate = hz.max(1); }
+
+    /// Mute and solo controls and the recent output of each channel.
+    pub fn channels(&self) -> &Channels { &self.channels }
+    pub fn channels_mut(&mut self) -> &mut Channels { &mut self.channels }
+
+    /// Appends the samples due over the next `cycles` CPU cycles, with the
+    /// Direct Sound FIFOs playing `fifo`. The PSG channels are not emulated
+    /// yet and sit at level 0.
+    pub fn run(&mut self, io: &Io, fifo: [i8; 2], cycles: u64, out: &mut Vec<StereoSample>) {
+        self.levels.fifo = fifo;
+        self.phase += cycles * self.sample_rate as u64;
+        let due = self.phase / CPU_CLOCK;
+        self.phase %= CPU_CLOCK;
+        self.channels.record(&self.levels, due as usize);
+        let sample = mixer::mix(io, &self.channels.apply(&self.levels));
+        out.extend(std::iter::repeat_n(sample, due as usize));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn produces_samples_at_the_host_rate() {
+        let mut apu = Apu::new(32_768);
+        let mut out = Vec::new();
+        apu.run(&Io::default(), [0; 2], 511, &mut out);
+        assert!(out.is_empty());
+        apu.run(&Io::default(), [0; 2], 1, &mut out);
+        assert_eq!(out.len(), 1);
+        apu.run(&Io::default(), [0; 2], CPU_CLOCK - 512, &mut out);
+        assert_eq!(out.len(), 32_768);
+        assert!(out.iter().all(|&s| s == [0, 0]));
+    }
+
+    #[test]
+    fn muted_channels_leave_the_mix() {
+        // FIFO A at 100% to both sides.
+        let io = Io { soundcnt_h: 0x0304, soundcnt_x: 0x80, soundbias: 0x0200, ..Io::default() };
+        let mut apu = Apu::new(32_768);
+        let mut out = Vec::new();
+        apu.run(&io, [64, 0], 512, &mut out);
+        apu.channels_mut().set_muted(Channel::FifoA, true);
+        apu.run(&io, [64, 0], 512, &mut out);
+        assert_eq!(out, [[256 << 6; 2], [0; 2]]);
+        // The scope still shows what the channel plays.
+        assert!(apu.channels().scope(Channel::FifoA).iter().eq(&[64, 64]));
+    }
 }
@@ -0,0 +1,247 @@
+// ROMs inside .zip and .gz files. `extract_rom` takes the bytes of a file as
+// read and returns the ROM image: the first `.gba` entry of a zip, the content
+// of a gzip stream, or the input unchanged when it is not compressed.
+
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::cart::crc32;
+use crate::mem::ROM_MAX_SIZE;
+
+const ZIP_LOCAL: u32 = 0x0403_4B50;
+const ZIP_CENTRAL: u32 = 0x0201_4B50;
+const ZIP_END: u32 = 0x0605_4B50;
+const ZIP_END_LEN: usize = 22;
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ArchiveError {
+    /// A zip without any `.gba` entry.
+    NoRom,
+    /// A compression method other than stored or deflate.
+    Unsupported(u16),
+    Corrupt(&'static str),
+    /// Extracts to more than the 32 MiB cartridge space.
+    TooLarge,
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveError::NoRom => write!(f, "archive contains no .gba file"),
+            ArchiveError::Unsupported(method) => write!(f, "unsupported compression method {}", method),
+            ArchiveError::Corrupt(what) => write!(f, "archive is corrupt: {}", what),
+            ArchiveError::TooLarge => write!(f, "extracted ROM is larger than 32 MiB"),
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+/// Whether `data` starts like a zip or gzip file.
+pub fn is_archive(data: &[u8]) -> bool { data.starts_with(&GZIP_MAGIC) || read_u32(data, 0) == Some(ZIP_LOCAL) }
+
+/// The ROM image in `data`, decompressing it if it is a zip or gzip file.
+pub fn extract_rom(data: &[u8]) -> Result<Cow<'_, [u8]>, ArchiveError> {
+    if data.starts_with(&GZIP_MAGIC) {
+        gunzip(data).map(Cow::Owned)
+    } else if read_u32(data, 0) == Some(ZIP_LOCAL) {
+        unzip_rom(data).map(Cow::Owned)
+    } else {
+        Ok(Cow::Borrowed(data))
+    }
+}
+
+fn read_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn inflate(data: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    miniz_oxide::inflate::decompress_to_vec_with_limit(data, ROM_MAX_SIZE).map_err(|e| match e.status {
+        miniz_oxide::inflate::TINFLStatus::HasMoreOutput => ArchiveError::TooLarge,
+        _ => ArchiveError::Corrupt("bad deflate stream"),
+    })
+}
+
+// RFC 1952: a header with optional fields, a deflate stream, then the CRC and
+// length of the content.
+fn gunzip(data: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    const FHCRC: u8 = 0x02;
+    const FEXTRA: u8 = 0x04;
+    const FNAME: u8 = 0x08;
+    const FCOMMENT: u8 = 0x10;
+    let truncated = || ArchiveError::Corrupt("truncated gzip file");
+
+    if data.len() < 18 {
+        return Err(truncated());
+    }
+    if data[2] != 8 {
+        return Err(ArchiveError::Unsupported(data[2] as u16));
+    }
+    let flags = data[3];
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        pos += 2 + read_u16(data, pos).ok_or_else(truncated)? as usize;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let len = data.get(pos..).and_then(|rest| rest.iter().position(|&b| b == 0)).ok_or_else(truncated)?;
+            pos += len + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+    let body = data.get(pos..data.len() - 8).ok_or_else(truncated)?;
+    let rom = inflate(body)?;
+    let trailer = data.len() - 8;
+    if read_u32(data, trailer) != Some(crc32(&rom)) || read_u32(data, trailer + 4) != Some(rom.len() as u32) {
+        return Err(ArchiveError::Corrupt("gzip checksum mismatch"));
+    }
+    Ok(rom)
+}
+
+// Entries are found through the central directory at the end of the file,
+// whose sizes are reliable even when the local headers defer them.
+fn unzip_rom(data: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    let search_from = data.len().saturating_sub(ZIP_END_LEN + u16::MAX as usize);
+    let end = (search_from..=data.len().saturating_sub(ZIP_END_LEN))
+        .rev()
+        .find(|&i| read_u32(data, i) == Some(ZIP_END))
+        .ok_or(ArchiveError::Corrupt("no zip directory"))?;
+    let entries = read_u16(data, end + 10).unwrap_or(0);
+    let mut pos = read_u32(data, end + 16).unwrap_or(0) as usize;
+    let corrupt = || ArchiveError::Corrupt("bad zip directory");
+
+    for _ in 0..entries {
+        if read_u32(data, pos) != Some(ZIP_CENTRAL) {
+            return Err(corrupt());
+        }
+        let method = read_u16(data, pos + 10).ok_or_else(corrupt)?;
+        let crc = read_u32(data, pos + 16).ok_or_else(corrupt)?;
+        let packed_len = read_u32(data, pos + 20).ok_or_else(corrupt)? as usize;
+        let len = read_u32(data, pos + 24).ok_or_else(corrupt)? as usize;
+        let name_len = read_u16(data, pos + 28).ok_or_else(corrupt)? as usize;
+        let extra_len = read_u16(data, pos + 30).ok_or_else(corrupt)? as usize;
+        let comment_len = read_u16(data, pos + 32).ok_or_else(corrupt)? as usize;
+        let local = read_u32(data, pos + 42).ok_or_else(corrupt)? as usize;
+        let name = data.get(pos + 46..pos + 46 + name_len).ok_or_else(corrupt)?;
+        pos += 46 + name_len + extra_len + comment_len;
+
+        if !name.to_ascii_lowercase().ends_with(b".gba") {
+            continue;
+        }
+        if len > ROM_MAX_SIZE {
+            return Err(ArchiveError::TooLarge);
+        }
+        if read_u32(data, local) != Some(ZIP_LOCAL) {
+            return Err(ArchiveError::Corrupt("bad zip entry"));
+        }
+        let start = local
+            + 30
+            + read_u16(data, local + 26).ok_or_else(corrupt)? as usize
+            + read_u16(data, local + 28).ok_or_else(corrupt)? as usize;
+        let packed = data.get(start..start + packed_len).ok_or(ArchiveError::Corrupt("truncated zip entry"))?;
+        let rom = match method {
+            0 => packed.to_vec(),
+            8 => inflate(packed)?,
+            _ => return Err(ArchiveError::Unsupported(method)),
+        };
+        if rom.len() != len || crc32(&rom) != crc {
+            return Err(ArchiveError::Corrupt("zip checksum mismatch"));
+        }
+        return Ok(rom);
+    }
+    Err(ArchiveError::NoRom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miniz_oxide::deflate::compress_to_vec;
+
+    // A zip holding each `(name, content)` deflated.
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut directory = Vec::new();
+        for (name, content) in files {
+            let packed = compress_to_vec(content, 6);
+            let mut fields = Vec::new();
+            fields.extend_from_slice(&20u16.to_le_bytes()); // version needed
+            fields.extend_from_slice(&0u16.to_le_bytes()); // flags
+            fields.extend_from_slice(&8u16.to_le_bytes()); // deflate
+            fields.extend_from_slice(&[0; 4]); // time and date
+            fields.extend_from_slice(&crc32(content).to_le_bytes());
+            fields.extend_from_slice(&(packed.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(content.len() as u32).to_le_bytes());
+            fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            fields.extend_from_slice(&0u16.to_le_bytes()); // extra length
+
+            directory.extend_from_slice(&ZIP_CENTRAL.to_le_bytes());
+            directory.extend_from_slice(&20u16.to_le_bytes()); // version made by
+            directory.extend_from_slice(&fields);
+            directory.extend_from_slice(&[0; 6]); // comment length, disk, internal attributes
+            directory.extend_from_slice(&[0; 4]); // external attributes
+            directory.extend_from_slice(&(out.len() as u32).to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+
+            out.extend_from_slice(&ZIP_LOCAL.to_le_bytes());
+            out.extend_from_slice(&fields);
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(&packed);
+        }
+        let directory_at = out.len() as u32;
+        out.extend_from_slice(&directory);
+        out.extend_from_slice(&ZIP_END.to_le_bytes());
+        out.extend_from_slice(&[0; 4]); // disk numbers
+        out.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        out.extend_from_slice(&directory_at.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes()); // comment length
+        out
+    }
+
+    fn rom() -> Vec<u8> { (0..0x1000u32).map(|i| (i * 7 % 251) as u8).collect() }
+
+    #[test]
+    fn plain_roms_pass_through() {
+        let rom = rom();
+        assert!(!is_archive(&rom));
+        assert!(matches!(extract_rom(&rom), Ok(Cow::Borrowed(data)) if data == rom.as_slice()));
+    }
+
+    #[test]
+    fn takes_the_first_gba_entry_of_a_zip() {
+        let rom = rom();
+        let archive = zip(&[("readme.txt", b"hello"), ("Game (USA).GBA", &rom), ("other.gba", b"x")]);
+        assert!(is_archive(&archive));
+        assert_eq!(extract_rom(&archive).unwrap().as_ref(), rom.as_slice());
+
+        assert_eq!(extract_rom(&zip(&[("readme.txt", b"hello")])), Err(ArchiveError::NoRom));
+        let mut damaged = archive.clone();
+        let name = b"Game (USA).GBA";
+        let at = damaged.windows(name.len()).position(|w| w == name).unwrap() + name.len() + 100;
+        damaged[at] ^= 0xFF;
+        assert!(matches!(extract_rom(&damaged), Err(ArchiveError::Corrupt(_))));
+    }
+
+    #[test]
+    fn decompresses_gzip_with_a_file_name() {
+        let rom = rom();
+        let mut gz = vec![0x1F, 0x8B, 8, 0x08, 0, 0, 0, 0, 0, 0xFF];
+        gz.extend_from_slice(b"game.gba\0");
+        gz.extend_from_slice(&compress_to_vec(&rom, 6));
+        gz.extend_from_slice(&crc32(&rom).to_le_bytes());
+        gz.extend_from_slice(&(rom.len() as u32).to_le_bytes());
+        assert_eq!(extract_rom(&gz).unwrap().as_ref(), rom.as_slice());
+
+        let len = gz.len();
+        gz[len - 1] ^= 1;
+        assert_eq!(extract_rom(&gz), Err(ArchiveError::Corrupt("gzip checksum mismatch")));
+    }
+}
@@ -1,6 +1,27 @@
-#[derive(Default)]
-pub struct Audio;
+// Host audio output. The APU mixes interleaved i16 stereo at the configured
+// sample rate as the emulator runs, and hands each frame's samples to the
+// installed `AudioSink`. Frontends either push them on themselves or pull
+// from a ring buffer on their audio thread.
 
-impl Audio {
-    pub fn new() -> Self { Self }
+pub mod ring;
+pub mod wav;
+
+pub use ring::{ring, RingReader, RingSink};
+pub use wav::WavSink;
+
+/// Left and right.
+pub type StereoSample = [i16; 2];
+
+/// Receives the samples of every completed frame, oldest first, at
+/// `Apu::sample_rate`.
+pub trait AudioSink: Send {
+    fn push(&mut self, samples: &[StereoSample]);
+}
+
+/// Discards everything, for headless runs that only need the APU clocked.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn push(&mut self, _samples: &[StereoSample]) {}
 }
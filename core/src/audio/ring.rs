@@ -0,0 +1,97 @@
+// Bounded sample queue between the emulation thread and an audio callback.
+// The emulator pushes through the `RingSink`; the callback pulls from the
+// `RingReader`. When the reader falls behind, new samples are dropped rather
+// than letting latency grow without bound.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use super::{AudioSink, StereoSample};
+
+struct Shared {
+    samples: VecDeque<StereoSample>,
+    capacity: usize,
+    dropped: u64,
+}
+
+/// A ring holding up to `latency` worth of samples at `sample_rate`.
+pub fn ring(sample_rate: u32, latency: Duration) -> (RingSink, RingReader) {
+    let sample_rate = sample_rate.max(1);
+    let capacity = ((sample_rate as f64 * latency.as_secs_f64()) as usize).max(1);
+    let shared = Arc::new(Mutex::new(Shared { samples: VecDeque::with_capacity(capacity), capacity, dropped: 0 }));
+    (RingSink(Arc::clone(&shared)), RingReader { shared, sample_rate })
+}
+
+fn lock(shared: &Mutex<Shared>) -> MutexGuard<'_, Shared> {
+    shared.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub struct RingSink(Arc<Mutex<Shared>>);
+
+impl AudioSink for RingSink {
+    fn push(&mut self, samples: &[StereoSample]) {
+        let mut shared = lock(&self.0);
+        let room = shared.capacity - shared.samples.len();
+        let (fits, dropped) = samples.split_at(room.min(samples.len()));
+        shared.samples.extend(fits);
+        shared.dropped += dropped.len() as u64;
+    }
+}
+
+pub struct RingReader {
+    shared: Arc<Mutex<Shared>>,
+    sample_rate: u32,
+}
+
+impl RingReader {
+    /// Moves the oldest samples into `out`, returning how many there were.
+    pub fn pull(&self, out: &mut [StereoSample]) -> usize {
+        let mut shared = lock(&self.shared);
+        let n = out.len().min(shared.samples.len());
+        for (slot, sample) in out.iter_mut().zip(shared.samples.drain(..n)) {
+            *slot = sample;
+        }
+        n
+    }
+
+    /// Discards up to `n` of the oldest samples, returning how many there were.
+    pub fn skip(&self, n: usize) -> usize {
+        let mut shared = lock(&self.shared);
+        let n = n.min(shared.samples.len());
+        shared.samples.drain(..n);
+        n
+    }
+
+    pub fn queued(&self) -> usize { lock(&self.shared).samples.len() }
+    pub fn capacity(&self) -> usize { lock(&self.shared).capacity }
+    pub fn sample_rate(&self) -> u32 { self.sample_rate }
+
+    /// How long the queued samples take to play.
+    pub fn latency(&self) -> Duration { Duration::from_secs_f64(self.queued() as f64 / self.sample_rate as f64) }
+
+    /// Samples dropped on a full ring since the last call.
+    pub fn take_dropped(&self) -> u64 { std::mem::take(&mut lock(&self.shared).dropped) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queues_up_to_the_latency_and_drops_the_rest() {
+        let (mut sink, reader) = ring(1000, Duration::from_millis(4));
+        assert_eq!(reader.capacity(), 4);
+        sink.push(&[[1, -1], [2, -2], [3, -3]]);
+        assert_eq!(reader.latency(), Duration::from_millis(3));
+        sink.push(&[[4, -4], [5, -5]]);
+        assert_eq!(reader.take_dropped(), 1);
+
+        let mut out = [[0; 2]; 3];
+        assert_eq!(reader.pull(&mut out), 3);
+        assert_eq!(out, [[1, -1], [2, -2], [3, -3]]);
+        assert_eq!(reader.skip(5), 1);
+        assert_eq!(reader.pull(&mut out), 0);
+        assert_eq!(reader.take_dropped(), 0);
+    }
+}
@@ -0,0 +1,113 @@
+// 16-bit stereo PCM WAV writer. The header goes out first with empty sizes,
+// which `finish` fills in once the length is known.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::{AudioSink, StereoSample};
+
+const HEADER_LEN: u32 = 44;
+const BYTES_PER_SAMPLE: u32 = 4;
+
+pub struct WavSink<W: Write + Seek> {
+    writer: W,
+    samples: u32,
+    // The first write error; later samples are ignored and `finish`
+    // returns it.
+    error: Option<io::Error>,
+}
+
+impl WavSink<BufWriter<File>> {
+    pub fn create(path: &Path, sample_rate: u32) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavSink<W> {
+    pub fn new(mut writer: W, sample_rate: u32) -> io::Result<Self> {
+        writer.write_all(&header(sample_rate, 0))?;
+        Ok(Self { writer, samples: 0, error: None })
+    }
+
+    /// Stereo samples written so far.
+    pub fn samples(&self) -> u32 { self.samples }
+
+    /// Completes the header and returns the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        let data_len = self.samples * BYTES_PER_SAMPLE;
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(HEADER_LEN - 8 + data_len).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&data_len.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write(&mut self, samples: &[StereoSample]) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(samples.len() * BYTES_PER_SAMPLE as usize);
+        for [left, right] in samples {
+            bytes.extend_from_slice(&left.to_le_bytes());
+            bytes.extend_from_slice(&right.to_le_bytes());
+        }
+        self.writer.write_all(&bytes)
+    }
+}
+
+impl<W: Write + Seek + Send> AudioSink for WavSink<W> {
+    fn push(&mut self, samples: &[StereoSample]) {
+        if self.error.is_some() {
+            return;
+        }
+        match self.write(samples) {
+            Ok(()) => self.samples = self.samples.saturating_add(samples.len() as u32),
+            Err(e) => {
+                log::error!("WAV dump failed: {}", e);
+                self.error = Some(e);
+            }
+        }
+    }
+}
+
+fn header(sample_rate: u32, data_len: u32) -> [u8; HEADER_LEN as usize] {
+    let mut h = [0u8; HEADER_LEN as usize];
+    h[0..4].copy_from_slice(b"RIFF");
+    h[4..8].copy_from_slice(&(HEADER_LEN - 8 + data_len).to_le_bytes());
+    h[8..16].copy_from_slice(b"WAVEfmt ");
+    h[16..20].copy_from_slice(&16u32.to_le_bytes());
+    h[20..22].copy_from_slice(&1u16.to_le_bytes()); // PCM
+    h[22..24].copy_from_slice(&2u16.to_le_bytes()); // channels
+    h[24..28].copy_from_slice(&sample_rate.to_le_bytes());
+    h[28..32].copy_from_slice(&(sample_rate * BYTES_PER_SAMPLE).to_le_bytes());
+    h[32..34].copy_from_slice(&(BYTES_PER_SAMPLE as u16).to_le_bytes()); // block align
+    h[34..36].copy_from_slice(&16u16.to_le_bytes()); // bits per sample
+    h[36..40].copy_from_slice(b"data");
+    h[40..44].copy_from_slice(&data_len.to_le_bytes());
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn writes_a_complete_header_on_finish() {
+        let mut sink = WavSink::new(Cursor::new(Vec::new()), 32_768).unwrap();
+        sink.push(&[[1, -1], [0x1234, 0]]);
+        sink.push(&[[-32768, 32767]]);
+        assert_eq!(sink.samples(), 3);
+        let bytes = sink.finish().unwrap().into_inner();
+
+        assert_eq!(bytes.len(), 44 + 12);
+        assert_eq!(&bytes[..44], &header(32_768, 12));
+        assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()), 48);
+        assert_eq!(u32::from_le_bytes(bytes[28..32].try_into().unwrap()), 131_072);
+        assert_eq!(&bytes[44..48], &[1, 0, 0xFF, 0xFF]);
+        assert_eq!(&bytes[52..56], &[0x00, 0x80, 0xFF, 0x7F]);
+    }
+}
@@ -0,0 +1,96 @@
+// BIOS identification. Only the official dump behaves exactly like hardware;
+// open-source replacements (e.g. the Cult-of-GBA BIOS) implement the same
+// SWI calls and boot path but differ in timing, open-bus values and checksum.
+
+use crate::mem::BIOS_SIZE;
+
+/// SHA-1 of the official GBA BIOS (gba_bios.bin, 16 KiB).
+pub const OFFICIAL_SHA1: [u8; 20] = [
+    0x30, 0x0C, 0x20, 0xDF, 0x67, 0x31, 0xA3, 0x39, 0x52, 0xDE, 0xD8, 0xC4, 0x36, 0xF7, 0xF1, 0x86, 0xD2, 0x5D,
+    0x34, 0x92,
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BiosKind {
+    Official,
+    /// A replacement, a bad dump or some other file.
+    Other,
+}
+
+impl BiosKind {
+    pub fn identify(data: &[u8]) -> Self {
+        if data.len() == BIOS_SIZE && sha1(data) == OFFICIAL_SHA1 { BiosKind::Official } else { BiosKind::Other }
+    }
+}
+
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let t = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+            (e, d, c, b, a) = (d, c, b.rotate_left(30), a, t);
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (out, h) in digest.chunks_exact_mut(4).zip(h) {
+        out.copy_from_slice(&h.to_be_bytes());
+    }
+    digest
+}
+
+/// Lowercase hex, as SHA-1 sums are usually printed.
+pub fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha1_known_answers() {
+        assert_eq!(hex(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(hex(&sha1(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        let long = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+        assert_eq!(hex(&sha1(long)), "84983e441c3bd26ebaae4aa1f95129e5e54670f1");
+    }
+
+    #[test]
+    fn bundled_dump_is_official() {
+        let Ok(data) = std::fs::read("assets/gba_bios.bin") else {
+            return;
+        };
+        assert_eq!(BiosKind::identify(&data), BiosKind::Official);
+    }
+
+    #[test]
+    fn other_files_are_not_official() {
+        assert_eq!(BiosKind::identify(&[0; BIOS_SIZE]), BiosKind::Other);
+        assert_eq!(BiosKind::identify(&[]), BiosKind::Other);
+    }
+}
@@ -1,5 +1,22 @@
-use crate::mem::{Mem, BIOS_SIZE, EWRAM_SIZE, IWRAM_SIZE, VRAM_SIZE, PALETTE_SIZE, OAM_SIZE};
+mod timing;
+pub mod watch;
+
+pub use timing::BusTiming;
+
+use crate::apu::fifo::{DirectSound, FIFO_A, FIFO_END};
+use crate::cart::eeprom::{EEPROM_BASE, EEPROM_BASE_LARGE_ROM};
+use crate::cart::gpio::{GPIO_BASE, GPIO_END};
+use crate::cart::{Cart, Quirks};
+use crate::dma::{Dma, DMA_BASE, DMA_END};
+use crate::guest_log::GuestLog;
+use crate::mem::{self, Mem, Tracked, BIOS_SIZE};
+use crate::peripherals::CartridgeDevice;
 use crate::io::Io;
+use crate::scheduler::Scheduler;
+use crate::sio::{Sio, SIOCNT};
+use crate::state::impl_savestate;
+use crate::timer::{Timers, TIMER_BASE, TIMER_END};
+use watch::{AccessKind, HookFn, HookId, MemoryAccess, MemoryHooks, Watchpoint, Watchpoints};
 
 fn io_register_name(addr: u32) -> Option<&'static str> {
     match addr {
@@ -12,8 +29,27 @@ fn io_register_name(addr: u32) -> Option<&'static str> {
         0x0400_000E..=0x0400_000F => Some("BG3CNT"),
         0x0400_004C..=0x0400_004D => Some("MOSAIC"),
         0x0400_0050..=0x0400_0051 => Some("BLDCNT"),
+        0x0400_0080..=0x0400_0081 => Some("SOUNDCNT_L"),
+        0x0400_0082..=0x0400_0083 => Some("SOUNDCNT_H"),
+        0x0400_0084..=0x0400_0085 => Some("SOUNDCNT_X"),
+        0x0400_0088..=0x0400_0089 => Some("SOUNDBIAS"),
+        0x0400_00A0..=0x0400_00A3 => Some("FIFO_A"),
+        0x0400_00A4..=0x0400_00A7 => Some("FIFO_B"),
+        0x0400_0100..=0x0400_0101 => Some("TM0CNT_L"),
+        0x0400_0102..=0x0400_0103 => Some("TM0CNT_H"),
+        0x0400_0104..=0x0400_0105 => Some("TM1CNT_L"),
+        0x0400_0106..=0x0400_0107 => Some("TM1CNT_H"),
+        0x0400_0108..=0x0400_0109 => Some("TM2CNT_L"),
+        0x0400_010A..=0x0400_010B => Some("TM2CNT_H"),
+        0x0400_010C..=0x0400_010D => Some("TM3CNT_L"),
+        0x0400_010E..=0x0400_010F => Some("TM3CNT_H"),
+        0x0400_0120..=0x0400_0123 => Some("SIODATA32"),
+        0x0400_0128..=0x0400_0129 => Some("SIOCNT"),
+        0x0400_012A..=0x0400_012B => Some("SIODATA8"),
+        0x0400_0134..=0x0400_0135 => Some("RCNT"),
         0x0400_0200..=0x0400_0201 => Some("IE"),
         0x0400_0202..=0x0400_0203 => Some("IF"),
+        0x0400_0204..=0x0400_0205 => Some("WAITCNT"),
         0x0400_0208..=0x0400_0209 => Some("IME"),
         _ => None,
     }
@@ -23,42 +59,104 @@ pub trait BusAccess {
     fn read32(&mut self, addr: u32) -> u32;
     fn read16(&mut self, addr: u32) -> u16;
     fn read8(&mut self, addr: u32) -> u8;
+    // Stores keep the low address bits the CPU put out. Memory ignores them,
+    // but the 8-bit SRAM and flash bus stores the byte they select.
     fn write32(&mut self, addr: u32, value: u32);
     fn write16(&mut self, addr: u32, value: u16);
     fn write8(&mut self, addr: u32, value: u8);
     fn set_ppu_rendering(&mut self, _rendering: bool) {}
+
+    // The internal reference point of affine BG 2 or 3, for buses that keep
+    // one; without it the first line rendered starts at BGxX/BGxY.
+    fn affine_ref(&mut self, _bg_num: usize) -> Option<(i32, i32)> { None }
+
+    // Internal (I) cycles the CPU spends without using the bus, and the
+    // cycles charged since the last `take_cycles`, for buses with a timing
+    // model.
+    fn idle(&mut self, _cycles: u64) {}
+    fn take_cycles(&mut self) -> u64 { 0 }
+
+    // Opcode fetches; buses with a timing model treat these differently from
+    // data accesses.
+    fn fetch32(&mut self, addr: u32) -> u32 { self.read32(addr) }
+    fn fetch16(&mut self, addr: u32) -> u16 { self.read16(addr) }
+
+    // Reads for inspection (debuggers, the idle loop detector) that must not
+    // be charged any cycles.
+    fn peek32(&mut self, addr: u32) -> u32 { self.read32(addr) }
+    fn peek16(&mut self, addr: u32) -> u16 { self.read16(addr) }
+    fn peek8(&mut self, addr: u32) -> u8 { self.read8(addr) }
 }
 
 const EWRAM_BASE: u32 = 0x0200_0000;
-const IWRAM_BASE: u32 = 0x0300_0000;
 const IO_BASE: u32 = 0x0400_0000;
 const PALETTE_BASE: u32 = 0x0500_0000;
 const VRAM_BASE: u32 = 0x0600_0000;
 const OAM_BASE: u32 = 0x0700_0000;
-const SRAM_BASE: u32 = 0x0E00_0000;
+const SOUNDCNT_H: u32 = 0x0400_0082;
+const WAITCNT: u32 = 0x0400_0204;
+const MEMCNT: u32 = 0x0400_0800;
+
+// The memory control register repeats every 64 KiB of the I/O area.
+fn is_memcnt(addr: u32) -> bool { addr >> 24 == 0x04 && addr & 0xFFFC == MEMCNT & 0xFFFC }
 
 pub struct Bus {
     pub mem: Mem,
     pub io: Io,
+    pub timers: Timers,
+    pub(crate) scheduler: Scheduler,
+    pub(crate) timing: BusTiming,
+    pub sio: Sio,
+    pub cart: Cart,
+    // Add-on hardware in the cartridge slot; host-side like the link peer.
+    pub cart_device: Option<Box<dyn CartridgeDevice>>,
+    pub dma: Dma,
+    pub sound: DirectSound,
+    pub watchpoints: Watchpoints,
+    hooks: Option<Box<MemoryHooks>>,
+    guest_log: GuestLog,
     ppu_rendering: bool,
     can_access_vram: bool,
     can_access_palette: bool,
     can_access_oam: bool,
     bios_readable: bool,
     last_bios_read: u32,
+    // Unmapped reads return `last_fetch` rather than 0; a setting, so not
+    // part of the saved state.
+    open_bus: bool,
+    // The last opcode fetched, halfwords repeated in Thumb state.
+    last_fetch: u32,
 }
 
+impl_savestate!(Bus {
+    mem, io, timers, scheduler, timing, sio, cart, dma, sound, guest_log, ppu_rendering, can_access_vram,
+    can_access_palette, can_access_oam, bios_readable, last_bios_read, last_fetch,
+});
+
 impl Default for Bus {
     fn default() -> Self {
         Self {
             mem: Mem::new(),
             io: Io::new(),
+            timers: Timers::new(),
+            scheduler: Scheduler::new(),
+            timing: BusTiming::new(),
+            sio: Sio::new(),
+            cart: Cart::new(),
+            cart_device: None,
+            dma: Dma::new(),
+            sound: DirectSound::default(),
+            watchpoints: Watchpoints::new(),
+            hooks: None,
+            guest_log: GuestLog::new(),
             ppu_rendering: false,
             can_access_vram: true,
             can_access_palette: true,
             can_access_oam: true,
             bios_readable: true,
             last_bios_read: 0,
+            open_bus: false,
+            last_fetch: 0,
         }
     }
 }
@@ -80,6 +178,8 @@ impl Bus {
         self.bios_readable = readable;
     }
 
+    pub fn set_open_bus(&mut self, enabled: bool) { self.open_bus = enabled; }
+
     fn check_vram_access(&self) -> bool {
         self.ppu_rendering || self.can_access_vram
     }
@@ -100,23 +200,118 @@ impl Bus {
     pub fn load_rom(&mut self, data: &[u8]) {
         log::info!("Bus: loading ROM ({} bytes, {} KB)", data.len(), data.len() / 1024);
         self.mem.load_rom(data);
+        self.cart.load(data);
+    }
+
+    /// I/O register halfword for debuggers: reading has no side effects, and
+    /// the write-only DMA registers show what was last written.
+    pub fn peek_io16(&self, addr: u32) -> u16 {
+        let byte = |addr: u32| {
+            if (TIMER_BASE..TIMER_END).contains(&addr) {
+                self.timers.peek8(addr, self.scheduler.now())
+            } else if (DMA_BASE..DMA_END).contains(&addr) {
+                self.dma.peek8(addr)
+            } else {
+                self.io.read8(addr)
+            }
+        };
+        let aligned = addr & !1;
+        u16::from_le_bytes([byte(aligned), byte(aligned + 1)])
+    }
+
+    /// Runs the flagged DMA channels to completion, highest priority first.
+    /// The CPU is stopped meanwhile: every transfer's cycles are charged to
+    /// the bus timing for the caller to take.
+    pub fn run_pending_dma(&mut self) {
+        while let Some(index) = self.dma.next_pending() {
+            self.run_dma(index);
+        }
+    }
+
+    fn run_dma(&mut self, index: usize) {
+        let t = self.dma.start(index);
+        if index == 3 && self.is_eeprom(t.dst) {
+            self.cart.eeprom.observe_dma(t.len);
+        }
+        // Internal processing: 2I, or 4I when both ends are in the gamepak.
+        let gamepak = |addr: u32| (0x08..=0x0D).contains(&(addr >> 24));
+        self.timing.idle(if gamepak(t.src) && gamepak(t.dst) { 4 } else { 2 });
+        let (mut src, mut dst) = (t.src, t.dst);
+        for _ in 0..t.len {
+            if t.word {
+                let value = self.read32(src & !3);
+                self.write32(dst & !3, value);
+            } else {
+                let value = self.read16(src & !1);
+                self.write16(dst & !1, value);
+            }
+            src = src.wrapping_add_signed(t.src_step);
+            dst = dst.wrapping_add_signed(t.dst_step);
+        }
+        let irq = self.dma.finish(index, src, dst);
+        if irq != 0 {
+            self.io.request_interrupt(irq);
+        }
+    }
+
+    fn cart_device_handles(&self, addr: u32) -> bool {
+        self.cart_device.as_ref().is_some_and(|device| device.handles(addr))
+    }
+
+    // The EEPROM sits in the top ROM mirror, or only in its last 256 bytes
+    // when the ROM is larger than 16 MiB.
+    fn is_eeprom(&self, addr: u32) -> bool {
+        let base = if self.mem.rom.len() > 0x0100_0000 { EEPROM_BASE_LARGE_ROM } else { EEPROM_BASE };
+        self.cart.eeprom.enabled() && addr >> 24 == 0x0D && addr >= base
+    }
+
+    /// Byte past the end of the ROM at `off` within a 32 MiB image. Unused
+    /// cart address lines read back the halfword address; carts flagged
+    /// `ROM_MIRROR` repeat the ROM instead.
+    fn rom_out_of_bounds(&self, off: usize) -> u8 {
+        let len = self.mem.rom.len();
+        if self.cart.config().quirks.contains(Quirks::ROM_MIRROR) && len > 0 {
+            let mirrored = off & (len.next_power_of_two() - 1);
+            if mirrored < len {
+                return self.mem.rom[mirrored];
+            }
+        }
+        ((off >> 1) as u16 >> ((off & 1) * 8)) as u8
     }
 }
 
-impl BusAccess for Bus {
-    fn read32(&mut self, addr: u32) -> u32 {
+// Where OBJ VRAM starts; the frame buffers of the bitmap modes (3-5) take
+// the first 16 KiB the tile modes give to OBJ tiles.
+const OBJ_VRAM_BITMAP: usize = 0x1_4000;
+const OBJ_VRAM_TILED: usize = 0x1_0000;
+
+// SRAM, flash and the tilt sensor sit on an 8-bit bus: wider reads repeat the
+// addressed byte and wider writes store only the byte the address selects.
+fn is_8bit_bus(addr: u32) -> bool { matches!(addr >> 24, 0x0E | 0x0F) }
+
+impl Bus {
+    fn load32(&mut self, addr: u32) -> u32 {
+        if is_8bit_bus(addr) {
+            return self.load8(addr) as u32 * 0x0101_0101;
+        }
         let aligned = addr & !3;
-        let lo = self.read16(aligned) as u32;
-        let hi = self.read16(aligned.wrapping_add(2)) as u32;
+        let lo = self.load16(aligned) as u32;
+        let hi = self.load16(aligned.wrapping_add(2)) as u32;
         let value = lo | (hi << 16);
         let rotation = (addr & 3) * 8;
         value.rotate_right(rotation)
     }
 
-    fn read16(&mut self, addr: u32) -> u16 {
+    fn load16(&mut self, addr: u32) -> u16 {
+        if self.is_eeprom(addr) {
+            return self.cart.eeprom.read();
+        }
+        if is_8bit_bus(addr) {
+            return self.load8(addr) as u16 * 0x0101;
+        }
         let aligned = addr & !1;
-        let b0 = self.read8(aligned) as u16;
-        let b1 = self.read8(aligned + 1) as u16;
+        let b0 = self.load8(aligned) as u16;
+        let b1 = self.load8(aligned + 1) as u16;
         let value = b0 | (b1 << 8);
         if addr & 1 != 0 {
             value.rotate_right(8)
@@ -125,151 +320,312 @@ impl BusAccess for Bus {
         }
     }
 
-    fn read8(&mut self, addr: u32) -> u8 {
+    fn load8(&mut self, addr: u32) -> u8 {
         match addr >> 24 {
-            0x00 => {
-                if addr < BIOS_SIZE as u32 {
-                    if self.bios_readable {
-                        let v = self.mem.bios[addr as usize];
-                        self.last_bios_read = self.read32_direct_bios(addr & !3);
-                        v
-                    } else {
-                        ((self.last_bios_read >> ((addr & 3) * 8)) & 0xFF) as u8
-                    }
+            0x00 if addr < BIOS_SIZE as u32 => {
+                if self.bios_readable {
+                    let v = self.mem.bios[addr as usize];
+                    self.last_bios_read = self.read32_direct_bios(addr & !3);
+                    v
                 } else {
-                    0
+                    ((self.last_bios_read >> ((addr & 3) * 8)) & 0xFF) as u8
                 }
             }
-            0x02 => {
-                let off = ((addr - EWRAM_BASE) as usize) % EWRAM_SIZE;
-                self.mem.ewram[off]
-            }
-            0x03 => {
-                let off = ((addr - IWRAM_BASE) as usize) % IWRAM_SIZE;
-                self.mem.iwram[off]
-            }
-            0x04 => {
-                if addr < IO_BASE + 0x400 {
-                    self.io.read8(addr)
-                } else {
-                    0
-                }
+            0x02 if self.io.ewram_enabled() => mem::mirrored(&self.mem.ewram, (addr - EWRAM_BASE) as usize),
+            0x02 | 0x03 if self.io.wram_enabled() => mem::mirrored(&self.mem.iwram, (addr & 0x00FF_FFFF) as usize),
+            0x04 if (TIMER_BASE..TIMER_END).contains(&addr) => {
+                self.timers.read8(addr, self.scheduler.now())
             }
+            0x04 if (DMA_BASE..DMA_END).contains(&addr) => self.dma.read8(addr),
+            0x04 if addr < IO_BASE + 0x400 => self.io.read8(addr),
+            0x04 if is_memcnt(addr) => self.io.read8(MEMCNT | (addr & 3)),
+            0x04 if GuestLog::handles(addr) => self.guest_log.read8(addr),
             0x05 => {
                 if !self.check_palette_access() {
                     return 0;
                 }
-                let off = ((addr - PALETTE_BASE) as usize) % PALETTE_SIZE;
-                self.mem.palette[off]
+                self.mem.palette8((addr - PALETTE_BASE) as usize)
             }
             0x06 => {
                 if !self.check_vram_access() {
                     return 0;
                 }
-                let raw_off = (addr - VRAM_BASE) as usize;
-                let off = if raw_off >= 0x18000 {
-                    0x10000 + ((raw_off - 0x10000) % 0x8000)
-                } else {
-                    raw_off % VRAM_SIZE
-                };
-                self.mem.vram[off]
+                self.mem.vram8((addr - VRAM_BASE) as usize)
             }
             0x07 => {
                 if !self.check_oam_access() {
                     return 0;
                 }
-                let off = ((addr - OAM_BASE) as usize) % OAM_SIZE;
-                self.mem.oam[off]
+                self.mem.oam8((addr - OAM_BASE) as usize)
             }
+            0x0D if self.is_eeprom(addr) => self.cart.eeprom.read() as u8,
             0x08..=0x0D => {
+                if (GPIO_BASE..GPIO_END).contains(&addr)
+                    && let Some(value) = self.cart.gpio.read8(addr)
+                {
+                    return value;
+                }
+                // 0x08, 0x0A and 0x0C are the same 32 MiB image with
+                // different wait states.
                 let off = (addr & 0x01FF_FFFF) as usize;
-                if off < self.mem.rom.len() {
-                    self.mem.rom[off]
-                } else {
-                    let halfword_idx = (addr >> 1) as u16;
-                    ((halfword_idx >> ((addr & 1) * 8)) & 0xFF) as u8
+                match self.mem.rom.get(off) {
+                    Some(&value) => value,
+                    None => self.rom_out_of_bounds(off),
                 }
             }
-            0x0E | 0x0F => {
-                let off = ((addr - SRAM_BASE) as usize) % self.mem.sram.len();
-                self.mem.sram[off]
+            0x0E | 0x0F if self.cart_device_handles(addr) => {
+                self.cart_device.as_mut().map_or(0xFF, |device| device.read8(addr))
             }
+            0x0E | 0x0F if self.cart.tilt.handles(addr) => self.cart.tilt.read8(addr),
+            0x0E | 0x0F if self.cart.flash.enabled() => self.cart.flash.read8(addr),
+            0x0E | 0x0F => self.cart.sram.read8(addr),
+            _ if self.open_bus => (self.last_fetch >> ((addr & 3) * 8)) as u8,
             _ => 0,
         }
     }
 
-    fn write32(&mut self, addr: u32, value: u32) {
+    fn store32(&mut self, addr: u32, value: u32) {
+        if is_8bit_bus(addr) {
+            self.store8(addr, (value >> ((addr & 3) * 8)) as u8);
+            return;
+        }
         let aligned = addr & !3;
-        self.write16(aligned, value as u16);
-        self.write16(aligned.wrapping_add(2), (value >> 16) as u16);
+        self.store16(aligned, value as u16);
+        self.store16(aligned.wrapping_add(2), (value >> 16) as u16);
     }
 
-    fn write16(&mut self, addr: u32, value: u16) {
+    fn store16(&mut self, addr: u32, value: u16) {
+        if self.is_eeprom(addr) {
+            self.cart.eeprom.write(value);
+            self.cart.mark_dirty();
+            return;
+        }
+        if is_8bit_bus(addr) {
+            self.store8(addr, (value >> ((addr & 1) * 8)) as u8);
+            return;
+        }
         let aligned = addr & !1;
-        self.write8(aligned, (value & 0xFF) as u8);
-        self.write8(aligned.wrapping_add(1), (value >> 8) as u8);
+        self.store8(aligned, (value & 0xFF) as u8);
+        self.store8(aligned.wrapping_add(1), (value >> 8) as u8);
     }
 
-    fn write8(&mut self, addr: u32, value: u8) {
+    // Palette RAM, VRAM and OAM sit on a 16-bit bus. A CPU byte store reaches
+    // palette RAM and BG VRAM as the byte in both halves of the halfword, and
+    // is dropped by OBJ VRAM and OAM.
+    fn store_video8(&mut self, addr: u32, value: u8) {
+        let obj_vram = if (self.io.dispcnt & 7) >= 3 { OBJ_VRAM_BITMAP } else { OBJ_VRAM_TILED };
+        let ignored = match addr >> 24 {
+            0x06 => mem::vram_offset(addr) >= obj_vram,
+            0x07 => true,
+            _ => false,
+        };
+        if !ignored {
+            self.store16(addr & !1, u16::from_le_bytes([value, value]));
+        }
+    }
+
+    fn store8(&mut self, addr: u32, value: u8) {
         match addr >> 24 {
             0x00 => {}
-            0x02 => {
-                let off = ((addr - EWRAM_BASE) as usize) % EWRAM_SIZE;
-                self.mem.ewram[off] = value;
+            0x02 if self.io.ewram_enabled() => {
+                self.mem.store_tracked(Tracked::Ewram, (addr - EWRAM_BASE) as usize, value)
             }
-            0x03 => {
-                let off = ((addr - IWRAM_BASE) as usize) % IWRAM_SIZE;
-                self.mem.iwram[off] = value;
+            0x02 | 0x03 if self.io.wram_enabled() => {
+                self.mem.store_tracked(Tracked::Iwram, (addr & 0x00FF_FFFF) as usize, value)
             }
-            0x04 => {
-                if addr < IO_BASE + 0x400 {
-                    if let Some(name) = io_register_name(addr) {
-                        log::trace!("IO write8 {} ({:#010x}) = {:#04x}", name, addr, value);
+            0x02 | 0x03 => {}
+            0x04 if addr < IO_BASE + 0x400 => {
+                if let Some(name) = io_register_name(addr) {
+                    log::trace!("IO write8 {} ({:#010x}) = {:#04x}", name, addr, value);
+                }
+                if (TIMER_BASE..TIMER_END).contains(&addr) {
+                    self.timers.write8(addr, value, &mut self.scheduler);
+                } else if (DMA_BASE..DMA_END).contains(&addr) {
+                    if self.dma.write8(addr, value) {
+                        self.run_pending_dma();
                     }
+                } else if (FIFO_A..FIFO_END).contains(&addr) {
+                    self.sound.write8(addr, value);
+                } else {
                     self.io.write8(addr, value);
+                    if addr == SOUNDCNT_H + 1 {
+                        // Bits 11 and 15 empty FIFO A and B.
+                        for fifo in (0..2).filter(|fifo| value & (0x08 << (4 * fifo)) != 0) {
+                            self.sound.reset(fifo);
+                        }
+                    } else if addr & !1 == SIOCNT {
+                        self.sio.check_start(&self.io, &mut self.scheduler);
+                    } else if addr & !1 == WAITCNT {
+                        self.timing.set_waitcnt(self.io.waitcnt);
+                    }
                 }
             }
+            0x04 if is_memcnt(addr) => {
+                self.io.write8(MEMCNT | (addr & 3), value);
+                self.timing.set_memcnt(self.io.memcnt);
+            }
+            0x04 if GuestLog::handles(addr) => self.guest_log.write8(addr, value),
             0x05 => {
                 if !self.check_palette_access() {
                     return;
                 }
-                let off = ((addr - PALETTE_BASE) as usize) % PALETTE_SIZE;
-                self.mem.palette[off] = value;
+                self.mem.store_palette8((addr - PALETTE_BASE) as usize, value);
             }
             0x06 => {
                 if !self.check_vram_access() {
                     return;
                 }
-                let raw_off = (addr - VRAM_BASE) as usize;
-                let off = if raw_off >= 0x18000 {
-                    0x10000 + ((raw_off - 0x10000) % 0x8000)
-                } else {
-                    raw_off % VRAM_SIZE
-                };
-                self.mem.vram[off] = value;
+                self.mem.store_vram8((addr - VRAM_BASE) as usize, value);
             }
             0x07 => {
                 if !self.check_oam_access() {
                     return;
                 }
-                let off = ((addr - OAM_BASE) as usize) % OAM_SIZE;
-                self.mem.oam[off] = value;
+                self.mem.store_oam8((addr - OAM_BASE) as usize, value);
+            }
+            0x08 if (GPIO_BASE..GPIO_END).contains(&addr) => self.cart.gpio.write8(addr, value),
+            0x0D if self.is_eeprom(addr) => {
+                self.cart.eeprom.write(value as u16);
+                self.cart.mark_dirty();
             }
             0x08..=0x0D => {}
+            0x0E | 0x0F if self.cart_device_handles(addr) => {
+                if let Some(device) = &mut self.cart_device {
+                    device.write8(addr, value);
+                }
+            }
+            0x0E | 0x0F if self.cart.tilt.handles(addr) => self.cart.tilt.write8(addr, value),
+            0x0E | 0x0F if self.cart.flash.enabled() => {
+                self.cart.flash.write8(addr, value);
+                self.cart.mark_dirty();
+            }
             0x0E | 0x0F => {
-                let off = ((addr - SRAM_BASE) as usize) % self.mem.sram.len();
-                self.mem.sram[off] = value;
+                self.cart.sram.write8(addr, value);
+                self.cart.mark_dirty();
             }
             _ => {}
         }
     }
+}
+
+impl BusAccess for Bus {
+    fn read32(&mut self, addr: u32) -> u32 {
+        self.charge(addr, 4, false);
+        let value = self.load32(addr);
+        self.watch(addr, 4, value, AccessKind::Read);
+        value
+    }
+
+    fn read16(&mut self, addr: u32) -> u16 {
+        self.charge(addr, 2, false);
+        let value = self.load16(addr);
+        self.watch(addr, 2, value as u32, AccessKind::Read);
+        value
+    }
+
+    fn read8(&mut self, addr: u32) -> u8 {
+        self.charge(addr, 1, false);
+        let value = self.load8(addr);
+        self.watch(addr, 1, value as u32, AccessKind::Read);
+        value
+    }
+
+    fn write32(&mut self, addr: u32, value: u32) {
+        self.charge(addr & !3, 4, false);
+        self.watch(addr & !3, 4, value, AccessKind::Write);
+        self.store32(addr, value);
+    }
+
+    fn write16(&mut self, addr: u32, value: u16) {
+        self.charge(addr & !1, 2, false);
+        self.watch(addr & !1, 2, value as u32, AccessKind::Write);
+        self.store16(addr, value);
+    }
+
+    fn write8(&mut self, addr: u32, value: u8) {
+        self.charge(addr, 1, false);
+        self.watch(addr, 1, value as u32, AccessKind::Write);
+        match addr >> 24 {
+            0x05..=0x07 => self.store_video8(addr, value),
+            _ => self.store8(addr, value),
+        }
+    }
+
+    fn fetch32(&mut self, addr: u32) -> u32 {
+        self.charge(addr, 4, true);
+        self.last_fetch = self.load32(addr);
+        self.last_fetch
+    }
+
+    fn fetch16(&mut self, addr: u32) -> u16 {
+        self.charge(addr, 2, true);
+        let value = self.load16(addr);
+        self.last_fetch = value as u32 * 0x0001_0001;
+        value
+    }
+
+    fn peek32(&mut self, addr: u32) -> u32 { self.load32(addr) }
+    fn peek16(&mut self, addr: u32) -> u16 { self.load16(addr) }
+    fn peek8(&mut self, addr: u32) -> u8 { self.load8(addr) }
 
     fn set_ppu_rendering(&mut self, rendering: bool) {
         Bus::set_ppu_rendering(self, rendering);
     }
+
+    fn affine_ref(&mut self, bg_num: usize) -> Option<(i32, i32)> { Some(self.io.affine_ref(bg_num)) }
+
+    fn idle(&mut self, cycles: u64) { self.timing.idle(cycles); }
+    fn take_cycles(&mut self) -> u64 { self.timing.take_cycles() }
 }
 
 impl Bus {
+    // Writes from outside the CPU (cheats, debuggers); no cycles are charged.
+    pub fn poke8(&mut self, addr: u32, value: u8) { self.store8(addr, value); }
+    pub fn poke16(&mut self, addr: u32, value: u16) { self.store16(addr, value); }
+    pub fn poke32(&mut self, addr: u32, value: u32) { self.store32(addr, value); }
+
+    /// Overwrites a ROM halfword, returning the previous value, or `None`
+    /// when `addr` is past the end of the ROM.
+    pub fn patch_rom16(&mut self, addr: u32, value: u16) -> Option<u16> {
+        let off = (addr & 0x01FF_FFFE) as usize;
+        let bytes = self.mem.rom.get_mut(off..off + 2)?;
+        let old = u16::from_le_bytes([bytes[0], bytes[1]]);
+        bytes.copy_from_slice(&value.to_le_bytes());
+        Some(old)
+    }
+
+    /// Calls `callback` for every data access overlapping `point`, as it
+    /// happens. Same coverage as the watchpoints: no fetches, peeks or pokes.
+    pub fn add_memory_hook(&mut self, point: Watchpoint, callback: HookFn) -> HookId {
+        self.hooks.get_or_insert_with(Default::default).add(point, callback)
+    }
+
+    pub fn remove_memory_hook(&mut self, id: HookId) -> bool {
+        let Some(hooks) = &mut self.hooks else {
+            return false;
+        };
+        let removed = hooks.remove(id);
+        if hooks.is_empty() {
+            self.hooks = None;
+        }
+        removed
+    }
+
+    fn watch(&mut self, addr: u32, width: u32, value: u32, kind: AccessKind) {
+        if !self.watchpoints.is_empty() {
+            self.watchpoints.check(addr, width, value, kind);
+        }
+        if let Some(hooks) = &mut self.hooks {
+            hooks.call(&MemoryAccess { addr, value, width, kind });
+        }
+    }
+
+    fn charge(&mut self, addr: u32, width: u32, code: bool) {
+        if !self.ppu_rendering {
+            self.timing.access(addr, width, code);
+        }
+    }
+
     fn read32_direct_bios(&self, addr: u32) -> u32 {
         if addr as usize + 3 < self.mem.bios.len() {
             let b0 = self.mem.bios[addr as usize] as u32;
@@ -282,3 +638,95 @@ impl Bus {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_writes_to_palette_and_bg_vram_fill_the_halfword() {
+        let mut bus = Bus::new();
+        bus.write8(0x0500_0003, 0x12);
+        assert_eq!(bus.read16(0x0500_0002), 0x1212);
+        bus.write8(0x0600_0004, 0x34);
+        assert_eq!(bus.read16(0x0600_0004), 0x3434);
+        // Bitmap modes extend BG VRAM into the first OBJ tiles.
+        bus.write8(0x0601_2001, 0x56);
+        assert_eq!(bus.read16(0x0601_2000), 0);
+        bus.write16(0x0400_0000, 0x0003);
+        bus.write8(0x0601_2001, 0x56);
+        assert_eq!(bus.read16(0x0601_2000), 0x5656);
+    }
+
+    #[test]
+    fn palette_keeps_halfword_and_word_writes_as_written() {
+        let mut bus = Bus::new();
+        bus.write16(0x0500_0010, 0x7FFF);
+        bus.write32(0x0500_0020, 0x1234_5678);
+        assert_eq!(bus.read16(0x0500_0010), 0x7FFF);
+        assert_eq!(bus.read32(0x0500_0020), 0x1234_5678);
+        // A byte store replaces the whole halfword, in either half and in
+        // the mirrors.
+        bus.write8(0x0500_0022, 0xAB);
+        assert_eq!(bus.read32(0x0500_0020), 0xABAB_5678);
+        bus.write8(0x0500_0411, 0x0C);
+        assert_eq!(bus.read16(0x0500_0010), 0x0C0C);
+        assert_eq!(bus.read16(0x0500_0012), 0);
+    }
+
+    #[test]
+    fn unmapped_reads_return_the_last_fetch_with_open_bus() {
+        let mut bus = Bus::new();
+        bus.write32(0x0300_0000, 0xE3A0_0001);
+        bus.write16(0x0300_0010, 0x2001);
+        bus.fetch32(0x0300_0000);
+        assert_eq!(bus.read32(0x1000_0000), 0);
+        bus.set_open_bus(true);
+        assert_eq!(bus.read32(0x1000_0000), 0xE3A0_0001);
+        bus.fetch16(0x0300_0010);
+        assert_eq!(bus.read32(0x0100_0000), 0x2001_2001);
+        assert_eq!(bus.read8(0x0400_0901), 0x20);
+    }
+
+    #[test]
+    fn memory_control_remaps_work_ram() {
+        let mut bus = Bus::new();
+        bus.write8(0x0200_0000, 0xEE);
+        bus.write8(0x0300_0000, 0x33);
+        assert_eq!(bus.read32(0x0400_0800), 0x0D00_0020);
+        // Mirrored every 64 KiB; bit 4 reads as 0.
+        bus.write32(0x0401_0800, 0x0E00_0030);
+        assert_eq!(bus.read32(0x0400_0800), 0x0E00_0020);
+        bus.take_cycles();
+        bus.read32(0x0200_0000);
+        assert_eq!(bus.take_cycles(), 4);
+
+        // With bit 5 clear, IWRAM answers for EWRAM.
+        bus.write8(0x0400_0800, 0x00);
+        assert_eq!(bus.read8(0x0200_0000), 0x33);
+        assert_eq!(bus.read8(0x0204_8000), 0x33);
+        bus.write8(0x0200_0001, 0x44);
+        assert_eq!(bus.read8(0x0300_0001), 0x44);
+        // Bit 0 disables both.
+        bus.write8(0x0400_0800, 0x21);
+        assert_eq!(bus.read8(0x0300_0000), 0);
+        bus.write8(0x0300_0000, 0x55);
+        bus.write8(0x0400_0800, 0x20);
+        assert_eq!(bus.read8(0x0300_0000), 0x33);
+        assert_eq!(bus.read8(0x0200_0000), 0xEE);
+    }
+
+    #[test]
+    fn byte_writes_to_obj_vram_and_oam_are_ignored() {
+        let mut bus = Bus::new();
+        bus.write16(0x0400_0000, 0x0003);
+        bus.write8(0x0601_4000, 0x12);
+        bus.write8(0x0601_C000, 0x12);
+        bus.write8(0x0700_0000, 0x34);
+        assert_eq!(bus.read16(0x0601_4000), 0);
+        assert_eq!(bus.read16(0x0700_0000), 0);
+        // Pokes from outside the CPU still store single bytes.
+        bus.poke8(0x0700_0001, 0x34);
+        assert_eq!(bus.read16(0x0700_0000), 0x3400);
+    }
+}
@@ -0,0 +1,333 @@
+// Access cycle accounting for the CPU side of the bus, including the gamepak
+// prefetch buffer. All costs include the base cycle, i.e. a zero-waitstate
+// access costs 1.
+
+use crate::state::impl_savestate;
+
+// Waitstates selected by the WAITCNT fields. Nonsequential and SRAM waits
+// share a 2-bit encoding; each ROM region has its own pair of sequential
+// waits. The reset value 0 gives WS0 4/2, WS1 4/4, WS2 4/8, SRAM 4.
+const N_WAIT: [u64; 4] = [4, 3, 2, 8];
+const ROM_S_WAIT: [[u64; 2]; 3] = [[2, 1], [4, 1], [8, 1]];
+
+const PREFETCH_CAPACITY: u32 = 8;
+
+#[derive(Default, Clone, Copy)]
+struct Prefetch {
+    active: bool,
+    // Address of the oldest halfword in the buffer (or the one in flight).
+    head: u32,
+    // Completed halfwords available starting at `head`.
+    count: u32,
+    // Cycles spent on the halfword currently being fetched.
+    progress: u64,
+    ws: usize,
+}
+
+pub struct BusTiming {
+    waitcnt: u16,
+    // EWRAM waitstates per halfword, from the internal memory control
+    // register; 0 while IWRAM or nothing is mapped in its place.
+    ewram_wait: u64,
+    cycles: u64,
+    next_seq_addr: u32,
+    prefetch: Prefetch,
+    force_nonseq: bool,
+    // Fixed ROM costs instead of bursts and the prefetch buffer; a setting,
+    // so not part of the saved state.
+    flat: bool,
+}
+
+impl_savestate!(Prefetch { active, head, count, progress, ws });
+impl_savestate!(BusTiming { waitcnt, ewram_wait, cycles, next_seq_addr, prefetch, force_nonseq });
+
+impl Default for BusTiming {
+    fn default() -> Self {
+        Self {
+            waitcnt: 0,
+            ewram_wait: 2,
+            cycles: 0,
+            next_seq_addr: 0,
+            prefetch: Prefetch::default(),
+            force_nonseq: false,
+            flat: false,
+        }
+    }
+}
+
+impl BusTiming {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn waitcnt(&self) -> u16 { self.waitcnt }
+    /// Takes the timing settings from the WAITCNT register.
+    pub fn set_waitcnt(&mut self, value: u16) {
+        self.waitcnt = value & 0x5FFF;
+        if !self.prefetch_enabled() {
+            self.prefetch.active = false;
+        }
+    }
+
+    /// Takes the EWRAM waitstates from the internal memory control register:
+    /// 15 minus bits 24-27. Setting 15 locks up a real console, so it is
+    /// refused and the previous waits stay.
+    pub fn set_memcnt(&mut self, value: u32) {
+        let control = (value >> 24) & 0xF;
+        if value & 0x21 != 0x20 {
+            self.ewram_wait = 0;
+        } else if control == 15 {
+            log::warn!("EWRAM wait control 15 would lock up the console; keeping {} waitstates", self.ewram_wait);
+        } else {
+            self.ewram_wait = 15 - control as u64;
+        }
+    }
+
+    /// Switches between the cycle-accurate gamepak model and fixed costs per
+    /// access.
+    pub fn set_cycle_accurate(&mut self, enabled: bool) {
+        self.flat = !enabled;
+        self.prefetch.active = false;
+    }
+
+    pub fn prefetch_enabled(&self) -> bool { self.waitcnt & 0x4000 != 0 }
+
+    fn sram_wait(&self) -> u64 { N_WAIT[(self.waitcnt & 3) as usize] }
+
+    fn rom_n_wait(&self, ws: usize) -> u64 { N_WAIT[((self.waitcnt >> (2 + 3 * ws)) & 3) as usize] }
+
+    fn rom_s_wait(&self, ws: usize) -> u64 { ROM_S_WAIT[ws][((self.waitcnt >> (4 + 3 * ws)) & 1) as usize] }
+
+    /// Returns the cycles accumulated since the last call and resets the count.
+    pub fn take_cycles(&mut self) -> u64 { std::mem::take(&mut self.cycles) }
+
+    /// Packs the prefetch state, so callers can tell whether two points in
+    /// time will see identical gamepak timing.
+    pub fn state(&self) -> u64 {
+        let p = &self.prefetch;
+        (p.active as u64)
+            | ((p.count as u64) << 1)
+            | ((p.progress & 0xF) << 5)
+            | ((p.ws as u64) << 9)
+            | (((p.head & 0x01FF_FFFF) as u64) << 11)
+            | ((self.force_nonseq as u64) << 36)
+    }
+
+    /// Charges internal (I) cycles. The prefetcher keeps running during them;
+    /// with prefetch disabled they instead break the next opcode fetch's
+    /// sequential burst, as on hardware.
+    pub fn idle(&mut self, cycles: u64) {
+        if self.prefetch_enabled() {
+            self.run_prefetch(cycles);
+        } else if cycles > 0 && !self.flat {
+            self.force_nonseq = true;
+        }
+        self.cycles += cycles;
+    }
+
+    /// Charges an access of `width` bytes at `addr`. `code` marks opcode
+    /// fetches, which are the only accesses served by the prefetch buffer.
+    pub fn access(&mut self, addr: u32, width: u32, code: bool) {
+        let sequential = addr == self.next_seq_addr;
+        self.next_seq_addr = addr.wrapping_add(width);
+
+        let cost = match addr >> 24 {
+            0x08..=0x0D if self.flat => self.flat_rom_cost(addr, width, code),
+            0x08..=0x0D => return self.rom_access(addr, width, code, sequential),
+            0x0E | 0x0F => 1 + self.sram_wait(),
+            0x02 if self.ewram_wait == 0 => 1,
+            0x02 if width == 4 => 2 * (1 + self.ewram_wait),
+            0x02 => 1 + self.ewram_wait,
+            0x05 | 0x06 if width == 4 => 2,
+            _ => 1,
+        };
+        self.run_prefetch(cost);
+        self.cycles += cost;
+    }
+
+    fn rom_access(&mut self, addr: u32, width: u32, code: bool, sequential: bool) {
+        let ws = ((addr >> 25) - 4) as usize;
+        let n = 1 + self.rom_n_wait(ws);
+        let s = 1 + self.rom_s_wait(ws);
+        // Crossing a 128K page always restarts the burst.
+        let mut sequential = sequential && (addr & 0x1_FFFF) != 0;
+        if code && std::mem::take(&mut self.force_nonseq) {
+            sequential = false;
+        }
+
+        let mut addr = addr & !1;
+        for _ in 0..width.div_ceil(2) {
+            let cost = if code && self.prefetch_enabled() {
+                self.prefetch_fetch(addr, ws, if sequential { s } else { n })
+            } else {
+                if sequential { s } else { n }
+            };
+            self.cycles += cost;
+            sequential = true;
+            addr = addr.wrapping_add(2);
+        }
+
+        if !code {
+            self.prefetch.active = false;
+        }
+    }
+
+    // Opcodes cost what the prefetch buffer or a sequential burst would
+    // charge, data a nonsequential access.
+    fn flat_rom_cost(&self, addr: u32, width: u32, code: bool) -> u64 {
+        let ws = ((addr >> 25) - 4) as usize;
+        let halfwords = width.div_ceil(2) as u64;
+        let s = 1 + self.rom_s_wait(ws);
+        match (code, self.prefetch_enabled()) {
+            (true, true) => halfwords,
+            (true, false) => halfwords * s,
+            (false, _) => 1 + self.rom_n_wait(ws) + (halfwords - 1) * s,
+        }
+    }
+
+    fn prefetch_fetch(&mut self, addr: u32, ws: usize, miss_cost: u64) -> u64 {
+        let s = 1 + self.rom_s_wait(ws);
+        let p = &mut self.prefetch;
+        if p.active && p.head == addr {
+            p.head = addr.wrapping_add(2);
+            if p.count > 0 {
+                p.count -= 1;
+                self.run_prefetch(1);
+                1
+            } else {
+                let remaining = (s - p.progress).max(1);
+                p.progress = 0;
+                remaining
+            }
+        } else {
+            *p = Prefetch { active: true, head: addr.wrapping_add(2), count: 0, progress: 0, ws };
+            miss_cost
+        }
+    }
+
+    fn run_prefetch(&mut self, cycles: u64) {
+        let s = 1 + self.rom_s_wait(self.prefetch.ws);
+        let p = &mut self.prefetch;
+        if !p.active || self.waitcnt & 0x4000 == 0 {
+            return;
+        }
+        p.progress += cycles;
+        while p.count < PREFETCH_CAPACITY && p.progress >= s {
+            p.progress -= s;
+            p.count += 1;
+        }
+        if p.count == PREFETCH_CAPACITY {
+            p.progress = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rom_sequential_and_nonsequential_costs() {
+        let mut t = BusTiming::new();
+        t.access(0x0800_0000, 2, true);
+        assert_eq!(t.take_cycles(), 5);
+        t.access(0x0800_0002, 2, true);
+        assert_eq!(t.take_cycles(), 3);
+        t.access(0x0800_0004, 4, true);
+        assert_eq!(t.take_cycles(), 6);
+        t.access(0x0800_0100, 4, false);
+        assert_eq!(t.take_cycles(), 8);
+    }
+
+    #[test]
+    fn waitcnt_selects_rom_and_sram_waits() {
+        let mut t = BusTiming::new();
+        // SRAM 8, WS0 3/1, WS2 2/8.
+        t.set_waitcnt((2 << 8) | (1 << 4) | (1 << 2) | 3);
+        t.access(0x0800_0000, 4, false);
+        assert_eq!(t.take_cycles(), 4 + 2);
+        t.access(0x0C00_0000, 2, false);
+        assert_eq!(t.take_cycles(), 3);
+        t.access(0x0E00_0000, 1, false);
+        assert_eq!(t.take_cycles(), 9);
+    }
+
+    #[test]
+    fn flat_timing_ignores_bursts_and_the_prefetch_state() {
+        let mut t = BusTiming::new();
+        t.set_cycle_accurate(false);
+        t.access(0x0800_0000, 4, true);
+        t.access(0x0800_0100, 2, true);
+        assert_eq!(t.take_cycles(), 3 + 3 + 3);
+        t.access(0x0800_0004, 4, false);
+        assert_eq!(t.take_cycles(), 5 + 3);
+        t.set_waitcnt(0x4000);
+        t.access(0x0800_0000, 4, true);
+        t.access(0x0800_0200, 2, true);
+        assert_eq!(t.take_cycles(), 3);
+        assert_eq!(t.state(), 0);
+    }
+
+    #[test]
+    fn ram_regions_have_fixed_costs() {
+        let mut t = BusTiming::new();
+        t.access(0x0300_0000, 4, false);
+        t.access(0x0200_0000, 4, false);
+        t.access(0x0200_0004, 2, false);
+        t.access(0x0600_0000, 4, false);
+        assert_eq!(t.take_cycles(), 1 + 6 + 3 + 2);
+    }
+
+    #[test]
+    fn memcnt_sets_the_ewram_waits() {
+        let mut t = BusTiming::new();
+        t.set_memcnt(0x0E00_0020);
+        t.access(0x0200_0000, 4, false);
+        assert_eq!(t.take_cycles(), 4);
+        t.set_memcnt(0x0F00_0020);
+        t.access(0x0200_0000, 2, false);
+        assert_eq!(t.take_cycles(), 2);
+        // IWRAM mirrored in EWRAM's place has IWRAM timing.
+        t.set_memcnt(0x0D00_0000);
+        t.access(0x0200_0000, 4, false);
+        assert_eq!(t.take_cycles(), 1);
+        t.set_memcnt(0x0000_0020);
+        t.access(0x0200_0000, 2, false);
+        assert_eq!(t.take_cycles(), 16);
+    }
+
+    #[test]
+    fn prefetch_serves_buffered_opcodes_in_one_cycle() {
+        let mut t = BusTiming::new();
+        t.set_waitcnt(0x4000);
+        t.access(0x0800_0000, 2, true);
+        assert_eq!(t.take_cycles(), 5);
+        // Enough non-ROM cycles for two halfwords to arrive.
+        t.access(0x0300_0000, 4, false);
+        t.idle(5);
+        t.take_cycles();
+        t.access(0x0800_0002, 2, true);
+        t.access(0x0800_0004, 2, true);
+        assert_eq!(t.take_cycles(), 2);
+    }
+
+    #[test]
+    fn rom_data_access_flushes_prefetch() {
+        let mut t = BusTiming::new();
+        t.set_waitcnt(0x4000);
+        t.access(0x0800_0000, 2, true);
+        t.idle(20);
+        t.access(0x0800_1000, 2, false);
+        t.take_cycles();
+        t.access(0x0800_0002, 2, true);
+        assert_eq!(t.take_cycles(), 5);
+    }
+
+    #[test]
+    fn idle_without_prefetch_breaks_sequential_fetch() {
+        let mut t = BusTiming::new();
+        t.access(0x0800_0000, 2, true);
+        t.idle(1);
+        t.take_cycles();
+        t.access(0x0800_0002, 2, true);
+        assert_eq!(t.take_cycles(), 5);
+    }
+}
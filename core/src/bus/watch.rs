@@ -0,0 +1,189 @@
+// Memory watchpoints. Data accesses (CPU loads/stores and DMA) that touch a
+// watched range are recorded while the frame runs; frontends collect them
+// afterwards. Instruction fetches and peeks/pokes are not recorded.
+//
+// Hooks are the callback form of the same thing, for tools that want to see
+// accesses as they happen (tracing, scripting). The bus keeps them behind an
+// `Option<Box<_>>` so nothing is paid until the first one is added.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u32,
+    /// Exclusive.
+    pub end: u32,
+    pub kind: AccessKind,
+}
+
+impl Watchpoint {
+    pub fn new(start: u32, len: u32, kind: AccessKind) -> Self {
+        Self { start, end: start.saturating_add(len.max(1)), kind }
+    }
+
+    pub fn matches(&self, access: &MemoryAccess) -> bool {
+        self.kind == access.kind && access.addr < self.end && access.addr.wrapping_add(access.width) > self.start
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub addr: u32,
+    pub value: u32,
+    /// 1, 2 or 4 bytes.
+    pub width: u32,
+    pub kind: AccessKind,
+}
+
+// A watch on a busy address can fire tens of thousands of times per frame;
+// keep the first hits and count the rest.
+const MAX_HITS: usize = 4096;
+
+#[derive(Default)]
+pub struct Watchpoints {
+    points: Vec<Watchpoint>,
+    hits: Vec<MemoryAccess>,
+    dropped: usize,
+}
+
+impl Watchpoints {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn add(&mut self, point: Watchpoint) {
+        if !self.points.contains(&point) {
+            self.points.push(point);
+        }
+    }
+
+    pub fn remove(&mut self, point: Watchpoint) { self.points.retain(|p| *p != point); }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+        self.hits.clear();
+        self.dropped = 0;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Watchpoint> { self.points.iter() }
+    pub fn is_empty(&self) -> bool { self.points.is_empty() }
+
+    /// Accesses recorded since the last call, oldest first.
+    pub fn take_hits(&mut self) -> Vec<MemoryAccess> {
+        if self.dropped > 0 {
+            log::debug!("Watchpoints: dropped {} hits", self.dropped);
+            self.dropped = 0;
+        }
+        std::mem::take(&mut self.hits)
+    }
+
+    pub(crate) fn check(&mut self, addr: u32, width: u32, value: u32, kind: AccessKind) {
+        let access = MemoryAccess { addr, value, width, kind };
+        if !self.points.iter().any(|p| p.matches(&access)) {
+            return;
+        }
+        if self.hits.len() < MAX_HITS {
+            self.hits.push(access);
+        } else {
+            self.dropped += 1;
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HookId(u32);
+
+pub type HookFn = Box<dyn FnMut(&MemoryAccess) + Send>;
+
+struct Hook {
+    id: HookId,
+    point: Watchpoint,
+    callback: HookFn,
+}
+
+#[derive(Default)]
+pub struct MemoryHooks {
+    hooks: Vec<Hook>,
+    next_id: u32,
+}
+
+impl MemoryHooks {
+    pub fn add(&mut self, point: Watchpoint, callback: HookFn) -> HookId {
+        let id = HookId(self.next_id);
+        self.next_id += 1;
+        self.hooks.push(Hook { id, point, callback });
+        id
+    }
+
+    /// Returns whether a hook was removed.
+    pub fn remove(&mut self, id: HookId) -> bool {
+        let before = self.hooks.len();
+        self.hooks.retain(|h| h.id != id);
+        self.hooks.len() != before
+    }
+
+    pub fn is_empty(&self) -> bool { self.hooks.is_empty() }
+
+    pub(crate) fn call(&mut self, access: &MemoryAccess) {
+        for hook in &mut self.hooks {
+            if hook.point.matches(access) {
+                (hook.callback)(access);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{Bus, BusAccess};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn records_overlapping_data_accesses() {
+        let mut bus = Bus::new();
+        bus.watchpoints.add(Watchpoint::new(0x0200_0012, 1, AccessKind::Write));
+        bus.watchpoints.add(Watchpoint::new(0x0300_0000, 4, AccessKind::Read));
+
+        bus.write32(0x0200_0010, 0x1234_5678);
+        bus.write8(0x0200_0013, 1);
+        bus.read16(0x0300_0002);
+        bus.poke8(0x0200_0012, 9);
+        bus.peek32(0x0300_0000);
+
+        let hits = bus.watchpoints.take_hits();
+        assert_eq!(
+            hits,
+            vec![
+                MemoryAccess { addr: 0x0200_0010, value: 0x1234_5678, width: 4, kind: AccessKind::Write },
+                MemoryAccess { addr: 0x0300_0002, value: 0, width: 2, kind: AccessKind::Read },
+            ]
+        );
+        assert!(bus.watchpoints.take_hits().is_empty());
+    }
+
+    #[test]
+    fn hooks_see_matching_accesses_until_removed() {
+        let mut bus = Bus::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = seen.clone();
+        let id = bus.add_memory_hook(
+            Watchpoint::new(0x0400_0000, 0x400, AccessKind::Write),
+            Box::new(move |access| log.lock().unwrap().push(*access)),
+        );
+
+        bus.write16(0x0400_0000, 0x0403);
+        bus.read16(0x0400_0000);
+        bus.write8(0x0200_0000, 1);
+        assert!(bus.remove_memory_hook(id));
+        bus.write16(0x0400_0008, 0x1F00);
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![MemoryAccess { addr: 0x0400_0000, value: 0x0403, width: 2, kind: AccessKind::Write }]
+        );
+        assert!(!bus.remove_memory_hook(id));
+    }
+}
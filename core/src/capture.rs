@@ -0,0 +1,85 @@
+// Offline dumps of what the emulator outputs, for diffing audio and video
+// regressions and attaching them to bug reports. A capture records next to
+// whatever frame and audio sinks are installed, from the frame after it is
+// started until it is stopped or has the frames it asked for.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, Write};
+use std::path::Path;
+
+use crate::audio::{AudioSink, StereoSample, WavSink};
+use crate::video::Y4mWriter;
+
+/// Anything a WAV dump can be written to.
+pub trait SeekWrite: Write + Seek + Send {}
+impl<T: Write + Seek + Send> SeekWrite for T {}
+
+#[derive(Default)]
+pub struct Capture {
+    audio: Option<WavSink<Box<dyn SeekWrite>>>,
+    video: Option<Y4mWriter<Box<dyn Write + Send>>>,
+    frames_left: Option<u32>,
+}
+
+impl Capture {
+    pub fn new() -> Self { Self::default() }
+
+    /// Records the audio as 16-bit stereo WAV at `sample_rate`, which should
+    /// be the APU's.
+    pub fn audio(mut self, writer: impl SeekWrite + 'static, sample_rate: u32) -> io::Result<Self> {
+        self.audio = Some(WavSink::new(Box::new(writer) as Box<dyn SeekWrite>, sample_rate)?);
+        Ok(self)
+    }
+
+    pub fn audio_file(self, path: &Path, sample_rate: u32) -> io::Result<Self> {
+        self.audio(BufWriter::new(File::create(path)?), sample_rate)
+    }
+
+    /// Records the frames as Y4M video.
+    pub fn video(mut self, writer: impl Write + Send + 'static) -> io::Result<Self> {
+        self.video = Some(Y4mWriter::new(Box::new(writer) as Box<dyn Write + Send>)?);
+        Ok(self)
+    }
+
+    pub fn video_file(self, path: &Path) -> io::Result<Self> { self.video(BufWriter::new(File::create(path)?)) }
+
+    /// Stops by itself after `frames` frames.
+    pub fn frames(mut self, frames: u32) -> Self {
+        self.frames_left = Some(frames);
+        self
+    }
+
+    pub fn wants_audio(&self) -> bool { self.audio.is_some() }
+
+    /// Frames recorded so far.
+    pub fn recorded_frames(&self) -> u32 { self.video.as_ref().map_or(0, Y4mWriter::frames) }
+
+    /// Records one frame; returns true once the capture has all it asked for.
+    pub(crate) fn record(&mut self, framebuffer: &[u16], samples: &[StereoSample]) -> io::Result<bool> {
+        if self.frames_left == Some(0) {
+            return Ok(true);
+        }
+        if let Some(audio) = &mut self.audio {
+            audio.push(samples);
+        }
+        if let Some(video) = &mut self.video {
+            video.push_bgr555(framebuffer)?;
+        }
+        if let Some(left) = &mut self.frames_left {
+            *left -= 1;
+            return Ok(*left == 0);
+        }
+        Ok(false)
+    }
+
+    /// Completes the files.
+    pub fn finish(self) -> io::Result<()> {
+        if let Some(audio) = self.audio {
+            audio.finish()?;
+        }
+        if let Some(video) = self.video {
+            video.finish()?;
+        }
+        Ok(())
+    }
+}
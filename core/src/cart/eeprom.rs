@@ -0,0 +1,217 @@
+// Serial EEPROM (512 bytes or 8 KiB), accessed one bit per halfword in the
+// 0x0D000000 region, normally with DMA3. Requests start with two command
+// bits (11 = read, 10 = write), then the block address MSB first (6 or 14
+// bits), then for writes 64 data bits, and end with a 0 bit. After a read
+// request the chip returns 4 junk bits and the 64 bits of the block.
+
+use super::BackupType;
+use crate::state::impl_savestate;
+
+pub const EEPROM_BASE: u32 = 0x0D00_0000;
+// On ROMs larger than 16 MiB only the top 256 bytes of the region select it.
+pub const EEPROM_BASE_LARGE_ROM: u32 = 0x0DFF_FF00;
+const MAX_SIZE: usize = 8 * 1024;
+const READ_BITS: u16 = 68;
+
+pub struct Eeprom {
+    enabled: bool,
+    // 6 (512 bytes) or 14 (8 KiB).
+    addr_bits: u8,
+    // Set once the size is known: configured explicitly or seen in a DMA.
+    size_known: bool,
+    data: Vec<u8>,
+    // Request bits received so far, oldest in the highest position.
+    request: u128,
+    request_len: u8,
+    read_block: u16,
+    read_pos: u16,
+}
+
+impl_savestate!(Eeprom { addr_bits, size_known, data, request, request_len, read_block, read_pos });
+
+impl Default for Eeprom {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            addr_bits: 14,
+            size_known: false,
+            data: vec![0xFF; MAX_SIZE],
+            request: 0,
+            request_len: 0,
+            read_block: 0,
+            read_pos: READ_BITS,
+        }
+    }
+}
+
+impl Eeprom {
+    pub fn new() -> Self { Self::default() }
+
+    /// `explicit` is set when the backup type came from the game database or
+    /// the user rather than detection, which cannot tell the sizes apart.
+    pub fn configure(&mut self, backup: BackupType, explicit: bool) {
+        *self = Self::default();
+        self.enabled = matches!(backup, BackupType::Eeprom512 | BackupType::Eeprom8K);
+        self.addr_bits = if backup == BackupType::Eeprom512 { 6 } else { 14 };
+        self.size_known = explicit;
+    }
+
+    pub fn enabled(&self) -> bool { self.enabled }
+
+    pub fn size(&self) -> usize { if self.addr_bits == 6 { 512 } else { MAX_SIZE } }
+
+    /// Contents as stored in a `.sav` file.
+    pub fn data(&self) -> &[u8] { &self.data[..self.size()] }
+
+    pub fn load_data(&mut self, data: &[u8]) {
+        let len = data.len().min(MAX_SIZE);
+        self.data[..len].copy_from_slice(&data[..len]);
+        if !self.size_known && (len == 512 || len == MAX_SIZE) {
+            self.addr_bits = if len == 512 { 6 } else { 14 };
+            self.size_known = true;
+        }
+    }
+
+    /// Picks the address width from the length of the first DMA sending a
+    /// request: 9 or 73 units for 6-bit addresses, 17 or 81 for 14-bit.
+    pub fn observe_dma(&mut self, units: u32) {
+        if self.size_known {
+            return;
+        }
+        self.addr_bits = match units {
+            9 | 73 => 6,
+            17 | 81 => 14,
+            _ => return,
+        };
+        self.size_known = true;
+        log::info!("EEPROM: detected {} bytes", self.size());
+    }
+
+    pub fn read(&mut self) -> u16 {
+        if self.read_pos >= READ_BITS {
+            // Ready (writes complete instantly).
+            return 1;
+        }
+        let pos = self.read_pos;
+        self.read_pos += 1;
+        if pos < 4 {
+            return 0;
+        }
+        let bit = (pos - 4) as usize;
+        let byte = self.data[self.read_block as usize * 8 + bit / 8];
+        ((byte >> (7 - bit % 8)) & 1) as u16
+    }
+
+    pub fn write(&mut self, value: u16) {
+        self.request = (self.request << 1) | (value & 1) as u128;
+        self.request_len += 1;
+        if self.request_len < 2 {
+            return;
+        }
+        let read_len = 2 + self.addr_bits + 1;
+        let write_len = read_len + 64;
+        match self.request >> (self.request_len - 2) {
+            0b11 if self.request_len == read_len => {
+                self.read_block = self.block(self.request >> 1);
+                self.read_pos = 0;
+                self.reset_request();
+            }
+            0b10 if self.request_len == write_len => {
+                let block = self.block(self.request >> 65) as usize;
+                let bits = (self.request >> 1) as u64;
+                self.data[block * 8..block * 8 + 8].copy_from_slice(&bits.to_be_bytes());
+                self.read_pos = READ_BITS;
+                self.reset_request();
+            }
+            0b10 | 0b11 => {}
+            _ => self.reset_request(),
+        }
+    }
+
+    // Block number from the address bits at the bottom of `bits`. Only the
+    // low 10 bits of a 14-bit address are decoded.
+    fn block(&self, bits: u128) -> u16 {
+        let mask = if self.addr_bits == 6 { 0x3F } else { 0x3FF };
+        (bits as u16) & mask
+    }
+
+    fn reset_request(&mut self) {
+        self.request = 0;
+        self.request_len = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(eeprom: &mut Eeprom, bits: u128, len: u8) {
+        for i in (0..len).rev() {
+            eeprom.write(((bits >> i) & 1) as u16);
+        }
+    }
+
+    fn read_block(eeprom: &mut Eeprom, addr: u128, addr_bits: u8) -> u64 {
+        send(eeprom, (0b11 << (addr_bits + 1)) | (addr << 1), 2 + addr_bits + 1);
+        let junk: Vec<u16> = (0..4).map(|_| eeprom.read()).collect();
+        assert_eq!(junk, [0; 4]);
+        (0..64).fold(0, |v, _| (v << 1) | eeprom.read() as u64)
+    }
+
+    fn write_block(eeprom: &mut Eeprom, addr: u128, addr_bits: u8, value: u64) {
+        let bits = (0b10 << (addr_bits + 65)) | (addr << 65) | ((value as u128) << 1);
+        send(eeprom, bits, 2 + addr_bits + 64 + 1);
+    }
+
+    #[test]
+    fn writes_and_reads_back_blocks() {
+        for (backup, bits) in [(BackupType::Eeprom512, 6), (BackupType::Eeprom8K, 14)] {
+            let mut eeprom = Eeprom::new();
+            eeprom.configure(backup, true);
+            write_block(&mut eeprom, 5, bits, 0x0123_4567_89AB_CDEF);
+            assert_eq!(eeprom.read(), 1, "ready after a write");
+            assert_eq!(read_block(&mut eeprom, 5, bits), 0x0123_4567_89AB_CDEF);
+            assert_eq!(read_block(&mut eeprom, 4, bits), u64::MAX);
+            assert_eq!(&eeprom.data()[40..48], &[0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF]);
+        }
+    }
+
+    #[test]
+    fn size_is_detected_from_dma_length() {
+        let mut eeprom = Eeprom::new();
+        eeprom.configure(BackupType::Eeprom8K, false);
+        eeprom.observe_dma(9);
+        assert_eq!(eeprom.size(), 512);
+        eeprom.observe_dma(17);
+        assert_eq!(eeprom.size(), 512);
+
+        let mut eeprom = Eeprom::new();
+        eeprom.configure(BackupType::Eeprom512, true);
+        eeprom.observe_dma(81);
+        assert_eq!(eeprom.size(), 512);
+    }
+
+    #[test]
+    fn dma3_sends_requests_through_the_bus() {
+        use crate::bus::{Bus, BusAccess};
+        let mut bus = Bus::new();
+        bus.cart.eeprom.configure(BackupType::Eeprom8K, false);
+        bus.cart.eeprom.load_data(&[0xA5; 4]);
+        // Read request for block 0 with a 6-bit address: 1 1 000000 0.
+        let request = [1u16, 1, 0, 0, 0, 0, 0, 0, 0];
+        for (i, bit) in request.iter().enumerate() {
+            bus.write16(0x0200_0000 + i as u32 * 2, *bit);
+        }
+        let dma3 = crate::dma::DMA_BASE + 36;
+        bus.write32(dma3, 0x0200_0000);
+        bus.write32(dma3 + 4, EEPROM_BASE);
+        bus.write32(dma3 + 8, 0x8000_0000 | request.len() as u32);
+        assert_eq!(bus.cart.eeprom.size(), 512);
+
+        bus.write32(dma3, EEPROM_BASE);
+        bus.write32(dma3 + 4, 0x0300_0000);
+        bus.write32(dma3 + 8, 0x8000_0000 | 68);
+        let bits: Vec<u16> = (4..12).map(|i| bus.read16(0x0300_0000 + i * 2)).collect();
+        assert_eq!(bits, [1, 0, 1, 0, 0, 1, 0, 1]);
+    }
+}
@@ -0,0 +1,294 @@
+// Flash backup (64 KiB or 128 KiB) at 0x0E000000. Commands are written to
+// 0x0E005555 after the unlock sequence AA -> 0x5555, 55 -> 0x2AAA. 128 KiB
+// chips have two 64 KiB banks selected with command B0. Games identify the
+// chip by manufacturer/device ID and pick their save routines from it, so the
+// reported chip is configurable.
+
+use super::BackupType;
+use crate::state::impl_savestate;
+
+const BANK_SIZE: usize = 0x1_0000;
+const SECTOR_SIZE: usize = 0x1000;
+const ATMEL_PAGE_SIZE: u8 = 128;
+
+const CMD_ADDR: u32 = 0x5555;
+const UNLOCK_ADDR: u32 = 0x2AAA;
+const CMD_ENTER_ID: u8 = 0x90;
+const CMD_EXIT_ID: u8 = 0xF0;
+const CMD_ERASE: u8 = 0x80;
+const CMD_ERASE_CHIP: u8 = 0x10;
+const CMD_ERASE_SECTOR: u8 = 0x30;
+const CMD_WRITE: u8 = 0xA0;
+const CMD_BANK: u8 = 0xB0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FlashChip {
+    /// Panasonic MN63F805MNP, 64 KiB.
+    Panasonic,
+    /// Atmel AT29LV512, 64 KiB. Writes whole 128-byte pages.
+    Atmel,
+    /// Macronix MX29L010, 128 KiB.
+    Macronix,
+    /// Sanyo LE26FV10N1TS, 128 KiB.
+    Sanyo,
+}
+
+impl FlashChip {
+    pub const ALL: [FlashChip; 4] = [FlashChip::Panasonic, FlashChip::Atmel, FlashChip::Macronix, FlashChip::Sanyo];
+
+    /// The chip reported when none is configured.
+    pub fn default_for(backup: BackupType) -> Self {
+        if backup == BackupType::Flash128K { FlashChip::Sanyo } else { FlashChip::Panasonic }
+    }
+
+    pub fn manufacturer(self) -> u8 {
+        match self {
+            FlashChip::Panasonic => 0x32,
+            FlashChip::Atmel => 0x1F,
+            FlashChip::Macronix => 0xC2,
+            FlashChip::Sanyo => 0x62,
+        }
+    }
+
+    pub fn device(self) -> u8 {
+        match self {
+            FlashChip::Panasonic => 0x1B,
+            FlashChip::Atmel => 0x3D,
+            FlashChip::Macronix => 0x09,
+            FlashChip::Sanyo => 0x13,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FlashChip::Panasonic => "panasonic",
+            FlashChip::Atmel => "atmel",
+            FlashChip::Macronix => "macronix",
+            FlashChip::Sanyo => "sanyo",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.name().eq_ignore_ascii_case(name))
+    }
+}
+
+pub struct Flash {
+    enabled: bool,
+    chip: FlashChip,
+    data: Vec<u8>,
+    // Unlock cycles seen before the next command (0-2).
+    unlock: u8,
+    id_mode: bool,
+    erase_armed: bool,
+    // Bytes the next data writes program (a page on Atmel chips).
+    program_left: u8,
+    bank_select: bool,
+    bank: u8,
+}
+
+// The chip is configuration rather than state.
+impl_savestate!(Flash { data, unlock, id_mode, erase_armed, program_left, bank_select, bank });
+
+impl Default for Flash {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            chip: FlashChip::Panasonic,
+            data: vec![0xFF; BANK_SIZE],
+            unlock: 0,
+            id_mode: false,
+            erase_armed: false,
+            program_left: 0,
+            bank_select: false,
+            bank: 0,
+        }
+    }
+}
+
+impl Flash {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn configure(&mut self, backup: BackupType, chip: Option<FlashChip>) {
+        *self = Self::default();
+        self.enabled = matches!(backup, BackupType::Flash64K | BackupType::Flash128K);
+        self.chip = chip.unwrap_or_else(|| FlashChip::default_for(backup));
+        if backup == BackupType::Flash128K {
+            self.data = vec![0xFF; 2 * BANK_SIZE];
+        }
+    }
+
+    pub fn enabled(&self) -> bool { self.enabled }
+    pub fn chip(&self) -> FlashChip { self.chip }
+
+    /// Contents as stored in a `.sav` file.
+    pub fn data(&self) -> &[u8] { &self.data }
+
+    pub fn load_data(&mut self, data: &[u8]) {
+        let len = data.len().min(self.data.len());
+        self.data[..len].copy_from_slice(&data[..len]);
+    }
+
+    pub fn read8(&self, addr: u32) -> u8 {
+        let offset = addr & 0xFFFF;
+        if self.id_mode && offset < 2 {
+            return if offset == 0 { self.chip.manufacturer() } else { self.chip.device() };
+        }
+        self.data[self.bank_offset(offset)]
+    }
+
+    pub fn write8(&mut self, addr: u32, value: u8) {
+        let offset = addr & 0xFFFF;
+        if self.program_left > 0 {
+            let index = self.bank_offset(offset);
+            self.data[index] = value;
+            self.program_left -= 1;
+            return;
+        }
+        if self.bank_select {
+            self.bank_select = false;
+            if offset == 0 {
+                self.bank = value & (self.data.len() / BANK_SIZE - 1) as u8;
+            }
+            return;
+        }
+        match (self.unlock, offset, value) {
+            (0, CMD_ADDR, 0xAA) => self.unlock = 1,
+            (1, UNLOCK_ADDR, 0x55) => self.unlock = 2,
+            (2, CMD_ADDR, command) => {
+                self.unlock = 0;
+                self.command(command);
+            }
+            (2, _, CMD_ERASE_SECTOR) if self.erase_armed => {
+                self.unlock = 0;
+                self.erase_armed = false;
+                let start = self.bank_offset(offset & !(SECTOR_SIZE as u32 - 1));
+                self.data[start..start + SECTOR_SIZE].fill(0xFF);
+            }
+            (_, _, CMD_EXIT_ID) => {
+                self.unlock = 0;
+                self.id_mode = false;
+            }
+            _ => self.unlock = 0,
+        }
+    }
+
+    fn command(&mut self, command: u8) {
+        let erase_armed = std::mem::take(&mut self.erase_armed);
+        match command {
+            CMD_ENTER_ID => self.id_mode = true,
+            CMD_EXIT_ID => self.id_mode = false,
+            CMD_ERASE => self.erase_armed = true,
+            CMD_ERASE_CHIP if erase_armed => self.data.fill(0xFF),
+            CMD_WRITE => {
+                self.program_left = if self.chip == FlashChip::Atmel { ATMEL_PAGE_SIZE } else { 1 };
+            }
+            CMD_BANK if self.data.len() > BANK_SIZE => self.bank_select = true,
+            _ => log::debug!("Flash: ignoring command {:#04x}", command),
+        }
+    }
+
+    fn bank_offset(&self, offset: u32) -> usize { self.bank as usize * BANK_SIZE + offset as usize }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{Bus, BusAccess};
+
+    const BASE: u32 = 0x0E00_0000;
+
+    fn flash_bus(backup: BackupType, chip: Option<FlashChip>) -> Bus {
+        let mut bus = Bus::new();
+        bus.cart.flash.configure(backup, chip);
+        bus
+    }
+
+    fn command(bus: &mut Bus, command: u8) {
+        bus.write8(BASE + CMD_ADDR, 0xAA);
+        bus.write8(BASE + UNLOCK_ADDR, 0x55);
+        bus.write8(BASE + CMD_ADDR, command);
+    }
+
+    fn write_byte(bus: &mut Bus, addr: u32, value: u8) {
+        command(bus, CMD_WRITE);
+        bus.write8(addr, value);
+    }
+
+    #[test]
+    fn id_mode_reports_the_configured_chip() {
+        for (backup, chip, id) in [
+            (BackupType::Flash64K, None, [0x32, 0x1B]),
+            (BackupType::Flash64K, Some(FlashChip::Atmel), [0x1F, 0x3D]),
+            (BackupType::Flash128K, None, [0x62, 0x13]),
+            (BackupType::Flash128K, Some(FlashChip::Macronix), [0xC2, 0x09]),
+        ] {
+            let mut bus = flash_bus(backup, chip);
+            command(&mut bus, CMD_ENTER_ID);
+            assert_eq!([bus.read8(BASE), bus.read8(BASE + 1)], id);
+            command(&mut bus, CMD_EXIT_ID);
+            assert_eq!([bus.read8(BASE), bus.read8(BASE + 1)], [0xFF, 0xFF]);
+        }
+    }
+
+    #[test]
+    fn writes_need_the_unlock_sequence() {
+        let mut bus = flash_bus(BackupType::Flash64K, None);
+        bus.write8(BASE + 0x10, 0x12);
+        assert_eq!(bus.read8(BASE + 0x10), 0xFF);
+        write_byte(&mut bus, BASE + 0x10, 0x12);
+        bus.write8(BASE + 0x11, 0x34);
+        assert_eq!([bus.read8(BASE + 0x10), bus.read8(BASE + 0x11)], [0x12, 0xFF]);
+    }
+
+    #[test]
+    fn atmel_writes_a_page_per_command() {
+        let mut bus = flash_bus(BackupType::Flash64K, Some(FlashChip::Atmel));
+        command(&mut bus, CMD_WRITE);
+        for i in 0..129 {
+            bus.write8(BASE + 0x100 + i, i as u8);
+        }
+        assert_eq!(bus.read8(BASE + 0x17F), 0x7F);
+        assert_eq!(bus.read8(BASE + 0x180), 0xFF);
+    }
+
+    #[test]
+    fn erases_sectors_and_the_chip() {
+        let mut bus = flash_bus(BackupType::Flash64K, None);
+        write_byte(&mut bus, BASE + 0x1000, 1);
+        write_byte(&mut bus, BASE + 0x2000, 2);
+
+        command(&mut bus, CMD_ERASE);
+        bus.write8(BASE + CMD_ADDR, 0xAA);
+        bus.write8(BASE + UNLOCK_ADDR, 0x55);
+        bus.write8(BASE + 0x1000, CMD_ERASE_SECTOR);
+        assert_eq!([bus.read8(BASE + 0x1000), bus.read8(BASE + 0x2000)], [0xFF, 2]);
+
+        command(&mut bus, CMD_ERASE_CHIP);
+        assert_eq!(bus.read8(BASE + 0x2000), 2, "chip erase needs the erase command first");
+        command(&mut bus, CMD_ERASE);
+        command(&mut bus, CMD_ERASE_CHIP);
+        assert_eq!(bus.read8(BASE + 0x2000), 0xFF);
+    }
+
+    #[test]
+    fn bank_switching_on_128k() {
+        let mut bus = flash_bus(BackupType::Flash128K, None);
+        write_byte(&mut bus, BASE + 0x20, 0xAB);
+        command(&mut bus, CMD_BANK);
+        bus.write8(BASE, 1);
+        assert_eq!(bus.read8(BASE + 0x20), 0xFF);
+        write_byte(&mut bus, BASE + 0x20, 0xCD);
+        assert_eq!(bus.cart.flash.data()[BANK_SIZE + 0x20], 0xCD);
+
+        command(&mut bus, CMD_BANK);
+        bus.write8(BASE, 0);
+        assert_eq!(bus.read8(BASE + 0x20), 0xAB);
+
+        let mut bus = flash_bus(BackupType::Flash64K, None);
+        command(&mut bus, CMD_BANK);
+        bus.write8(BASE, 1);
+        assert_eq!(bus.read8(BASE), 0xFF);
+        assert_eq!(bus.cart.flash.data().len(), BANK_SIZE);
+    }
+}
@@ -0,0 +1,46 @@
+// Built-in per-title overrides for carts whose hardware cannot be detected
+// from the ROM alone. Keyed by the 4-character game code in the header.
+
+use super::{BackupType, CartConfig, Quirks};
+
+const fn entry(backup: Option<BackupType>, rtc: bool, quirks: Quirks) -> CartConfig {
+    CartConfig { backup, flash_chip: None, rtc, quirks }
+}
+
+const POKEMON_RTC: CartConfig = entry(Some(BackupType::Flash128K), true, Quirks::NONE);
+const POKEMON: CartConfig = entry(Some(BackupType::Flash128K), false, Quirks::NONE);
+const BOKTAI: CartConfig = entry(None, true, Quirks::SOLAR_SENSOR);
+const WARIOWARE_TWISTED: CartConfig = entry(Some(BackupType::Sram), false, Quirks::GYRO.union(Quirks::RUMBLE));
+const DRILL_DOZER: CartConfig = entry(Some(BackupType::Sram), false, Quirks::RUMBLE);
+const YOSHI_TILT: CartConfig = entry(None, false, Quirks::TILT);
+const NES_CLASSIC: CartConfig = entry(None, false, Quirks::ROM_MIRROR);
+
+static GAMES: &[(&str, CartConfig)] = &[
+    // Pokémon Ruby / Sapphire / Emerald
+    ("AXVE", POKEMON_RTC), ("AXVJ", POKEMON_RTC), ("AXVP", POKEMON_RTC),
+    ("AXPE", POKEMON_RTC), ("AXPJ", POKEMON_RTC), ("AXPP", POKEMON_RTC),
+    ("BPEE", POKEMON_RTC), ("BPEJ", POKEMON_RTC), ("BPEP", POKEMON_RTC),
+    // Pokémon FireRed / LeafGreen
+    ("BPRE", POKEMON), ("BPRJ", POKEMON), ("BPRP", POKEMON),
+    ("BPGE", POKEMON), ("BPGJ", POKEMON), ("BPGP", POKEMON),
+    // Boktai 1-3
+    ("U3IE", BOKTAI), ("U3IJ", BOKTAI), ("U3IP", BOKTAI),
+    ("U32E", BOKTAI), ("U32J", BOKTAI), ("U32P", BOKTAI),
+    ("U33J", BOKTAI),
+    // WarioWare: Twisted!
+    ("RZWE", WARIOWARE_TWISTED), ("RZWJ", WARIOWARE_TWISTED), ("RZWP", WARIOWARE_TWISTED),
+    // Drill Dozer
+    ("V49E", DRILL_DOZER), ("V49J", DRILL_DOZER), ("V49P", DRILL_DOZER),
+    // Yoshi's Universal Gravitation / Topsy-Turvy, Koro Koro Puzzle
+    ("KYGE", YOSHI_TILT), ("KYGJ", YOSHI_TILT), ("KYGP", YOSHI_TILT),
+    ("KHPJ", YOSHI_TILT),
+    // Classic NES Series / Famicom Mini
+    ("FBME", NES_CLASSIC), ("FDKE", NES_CLASSIC), ("FDME", NES_CLASSIC),
+    ("FEBE", NES_CLASSIC), ("FICE", NES_CLASSIC), ("FMRE", NES_CLASSIC),
+    ("FP7E", NES_CLASSIC), ("FSME", NES_CLASSIC), ("FXVE", NES_CLASSIC),
+    ("FZLE", NES_CLASSIC), ("FSMJ", NES_CLASSIC), ("FZLJ", NES_CLASSIC),
+];
+
+pub fn lookup_game(game_code: &str) -> Option<CartConfig> {
+    GAMES.iter().find(|(code, _)| *code == game_code).map(|&(_, config)| config)
+}
@@ -0,0 +1,299 @@
+// Cartridge GPIO port (0x080000C4-0x080000C9) and the sensors and RTC wired
+// to it.
+// The tilt sensor is not on the GPIO port but mapped into the SRAM region;
+// it lives here with the other motion/light inputs.
+
+use super::Quirks;
+use super::rtc::Rtc;
+use crate::state::impl_savestate;
+
+pub const GPIO_BASE: u32 = 0x0800_00C4;
+pub const GPIO_END: u32 = 0x0800_00CA;
+
+const SOLAR_CLK: u8 = 1 << 0;
+const SOLAR_RST: u8 = 1 << 1;
+const SOLAR_FLAG: u8 = 1 << 3;
+const GYRO_LATCH: u8 = 1 << 0;
+const GYRO_CLK: u8 = 1 << 1;
+const GYRO_DATA: u8 = 1 << 2;
+const RUMBLE_PIN: u8 = 1 << 3;
+
+/// Host-side values for cartridge sensors.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PeripheralInput {
+    /// Sunlight on the Boktai solar sensor, 0 (dark) to 255 (full sun).
+    SolarLevel(u8),
+    /// Rotation rate around the gyro axis; positive is clockwise.
+    Gyro(i16),
+    /// Tilt angle on both axes, full scale at the ends of the i16 range.
+    Tilt { x: i16, y: i16 },
+}
+
+#[derive(Default)]
+pub struct Gpio {
+    quirks: Quirks,
+    has_rtc: bool,
+    rtc: Rtc,
+    pins: u8,
+    direction: u8,
+    readable: bool,
+    solar_level: u8,
+    light_counter: u8,
+    light_sample: u8,
+    light_edge: bool,
+    gyro_rate: i16,
+    gyro_sample: u16,
+    gyro_edge: bool,
+    rumble: bool,
+}
+
+// Sensor readings (solar level, gyro rate, tilt) and the RTC's clock are host
+// input and are not part of the state.
+impl_savestate!(Gpio {
+    rtc, pins, direction, readable, light_counter, light_sample, light_edge, gyro_sample, gyro_edge, rumble,
+});
+
+impl Gpio {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn configure(&mut self, quirks: Quirks, rtc: bool) {
+        let mut clock = Rtc::new();
+        clock.set_time(self.rtc.time());
+        *self = Self {
+            quirks,
+            has_rtc: rtc,
+            rtc: clock,
+            solar_level: self.solar_level,
+            gyro_rate: self.gyro_rate,
+            ..Self::default()
+        };
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.has_rtc
+            || self.quirks.contains(Quirks::SOLAR_SENSOR)
+            || self.quirks.contains(Quirks::GYRO)
+            || self.quirks.contains(Quirks::RUMBLE)
+    }
+
+    pub fn rumble(&self) -> bool { self.rumble }
+
+    /// Sets the RTC's clock, in seconds since the Unix epoch.
+    pub fn set_rtc_time(&mut self, time: u64) { self.rtc.set_time(time); }
+
+    pub fn set_input(&mut self, input: PeripheralInput) {
+        match input {
+            PeripheralInput::SolarLevel(level) => self.solar_level = level,
+            PeripheralInput::Gyro(rate) => self.gyro_rate = rate,
+            PeripheralInput::Tilt { .. } => {}
+        }
+    }
+
+    /// Returns `None` when the port is write-only, so the ROM shows through.
+    pub fn read8(&self, addr: u32) -> Option<u8> {
+        if !self.is_present() || !self.readable {
+            return None;
+        }
+        match addr - GPIO_BASE {
+            0 => Some(self.pins),
+            2 => Some(self.direction),
+            4 => Some(self.readable as u8),
+            _ => Some(0),
+        }
+    }
+
+    pub fn write8(&mut self, addr: u32, value: u8) {
+        if !self.is_present() {
+            return;
+        }
+        match addr - GPIO_BASE {
+            0 => {
+                self.pins = (self.pins & !self.direction) | (value & self.direction & 0xF);
+                self.update_devices();
+            }
+            2 => self.direction = value & 0xF,
+            4 => self.readable = value & 1 != 0,
+            _ => {}
+        }
+    }
+
+    fn output(&mut self, pins: u8) {
+        self.pins = (self.pins & self.direction) | (pins & !self.direction & 0xF);
+    }
+
+    fn update_devices(&mut self) {
+        if self.quirks.contains(Quirks::SOLAR_SENSOR) {
+            self.update_solar();
+        }
+        if self.quirks.contains(Quirks::GYRO) {
+            self.update_gyro();
+        }
+        if self.quirks.contains(Quirks::RUMBLE) && self.direction & RUMBLE_PIN != 0 {
+            self.rumble = self.pins & RUMBLE_PIN != 0;
+        }
+        if self.has_rtc
+            && let Some(pins) = self.rtc.write_pins(self.pins)
+        {
+            self.output(pins);
+        }
+    }
+
+    // The sensor's counter is clocked by the game; FLAG goes high once it
+    // passes the light sample, so brighter light trips it sooner.
+    fn update_solar(&mut self) {
+        if self.pins & SOLAR_RST != 0 {
+            self.light_counter = 0;
+            self.light_sample = 0xE9 - (self.solar_level as u32 * 139 / 255) as u8;
+        }
+        let clk = self.pins & SOLAR_CLK != 0;
+        if clk && self.light_edge {
+            self.light_counter = self.light_counter.wrapping_add(1);
+        }
+        self.light_edge = !clk;
+        let flag = self.light_counter >= self.light_sample;
+        self.output(if flag { SOLAR_FLAG } else { 0 });
+    }
+
+    // The gyro sample is latched, then shifted out MSB first on falling clock
+    // edges. At rest it reads 0x6C0.
+    fn update_gyro(&mut self) {
+        if self.pins & GYRO_LATCH != 0 {
+            self.gyro_sample = (0x6C0 + (self.gyro_rate as i32 >> 5)) as u16;
+        }
+        let clk = self.pins & GYRO_CLK != 0;
+        if self.gyro_edge && !clk {
+            let bit = (self.gyro_sample >> 15) as u8;
+            self.gyro_sample <<= 1;
+            self.output(bit * GYRO_DATA);
+        }
+        self.gyro_edge = clk;
+    }
+}
+
+/// Two-axis tilt sensor of Yoshi Topsy-Turvy, at 0x0E008000-0x0E0085FF.
+#[derive(Default)]
+pub struct TiltSensor {
+    enabled: bool,
+    x: i16,
+    y: i16,
+    armed: bool,
+    sample_x: u16,
+    sample_y: u16,
+}
+
+impl_savestate!(TiltSensor { armed, sample_x, sample_y });
+
+impl TiltSensor {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn configure(&mut self, quirks: Quirks) {
+        *self = Self { enabled: quirks.contains(Quirks::TILT), x: self.x, y: self.y, ..Self::default() };
+    }
+
+    pub fn set_input(&mut self, input: PeripheralInput) {
+        if let PeripheralInput::Tilt { x, y } = input {
+            self.x = x;
+            self.y = y;
+        }
+    }
+
+    pub fn handles(&self, addr: u32) -> bool {
+        self.enabled && (0x8000..0x8600).contains(&(addr & 0xFFFF))
+    }
+
+    pub fn read8(&self, addr: u32) -> u8 {
+        match addr & 0xFF00 {
+            0x8200 => self.sample_x as u8,
+            0x8300 => ((self.sample_x >> 8) as u8 & 0xF) | 0x80,
+            0x8400 => self.sample_y as u8,
+            0x8500 => (self.sample_y >> 8) as u8 & 0xF,
+            _ => 0,
+        }
+    }
+
+    pub fn write8(&mut self, addr: u32, value: u8) {
+        match (addr & 0xFF00, value) {
+            (0x8000, 0x55) => self.armed = true,
+            (0x8100, 0xAA) if self.armed => {
+                self.armed = false;
+                self.sample_x = (0x3A0 + (self.x as i32 >> 5)) as u16;
+                self.sample_y = (0x3A0 + (self.y as i32 >> 5)) as u16;
+            }
+            _ => log::debug!("Tilt: unexpected write {:#04x} to {:#010x}", value, addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_pins(gpio: &mut Gpio, pins: u8) { gpio.write8(GPIO_BASE, pins); }
+
+    fn solar_cycles_until_flag(level: u8) -> u32 {
+        let mut gpio = Gpio::new();
+        gpio.configure(Quirks::SOLAR_SENSOR, false);
+        gpio.set_input(PeripheralInput::SolarLevel(level));
+        gpio.write8(GPIO_BASE + 2, 0x7);
+        gpio.write8(GPIO_BASE + 4, 1);
+        write_pins(&mut gpio, SOLAR_RST);
+        write_pins(&mut gpio, 0);
+        let mut cycles = 0;
+        while gpio.read8(GPIO_BASE).unwrap() & SOLAR_FLAG == 0 {
+            write_pins(&mut gpio, SOLAR_CLK);
+            write_pins(&mut gpio, 0);
+            cycles += 1;
+        }
+        cycles
+    }
+
+    #[test]
+    fn brighter_sunlight_trips_solar_flag_sooner() {
+        assert!(solar_cycles_until_flag(255) < solar_cycles_until_flag(0));
+        assert_eq!(solar_cycles_until_flag(0), 0xE9);
+    }
+
+    #[test]
+    fn gyro_shifts_out_latched_sample() {
+        let mut gpio = Gpio::new();
+        gpio.configure(Quirks::GYRO | Quirks::RUMBLE, false);
+        gpio.set_input(PeripheralInput::Gyro(0x100));
+        gpio.write8(GPIO_BASE + 2, 0xB);
+        gpio.write8(GPIO_BASE + 4, 1);
+        write_pins(&mut gpio, GYRO_LATCH | GYRO_CLK);
+        let mut value = 0u16;
+        for _ in 0..16 {
+            write_pins(&mut gpio, 0);
+            value = (value << 1) | ((gpio.read8(GPIO_BASE).unwrap() & GYRO_DATA) >> 2) as u16;
+            write_pins(&mut gpio, GYRO_CLK);
+        }
+        assert_eq!(value, 0x6C0 + 8);
+
+        write_pins(&mut gpio, RUMBLE_PIN);
+        assert!(gpio.rumble());
+    }
+
+    #[test]
+    fn write_only_port_reads_back_rom() {
+        let mut gpio = Gpio::new();
+        gpio.configure(Quirks::SOLAR_SENSOR, false);
+        assert_eq!(gpio.read8(GPIO_BASE), None);
+        gpio.configure(Quirks::NONE, false);
+        gpio.write8(GPIO_BASE + 4, 1);
+        assert_eq!(gpio.read8(GPIO_BASE), None);
+    }
+
+    #[test]
+    fn tilt_sample_latches_after_handshake() {
+        let mut tilt = TiltSensor::new();
+        tilt.configure(Quirks::TILT);
+        tilt.set_input(PeripheralInput::Tilt { x: 0, y: -0x800 });
+        assert!(tilt.handles(0x0E00_8200));
+        tilt.write8(0x0E00_8000, 0x55);
+        tilt.write8(0x0E00_8100, 0xAA);
+        assert_eq!(tilt.read8(0x0E00_8200), 0xA0);
+        assert_eq!(tilt.read8(0x0E00_8300), 0x83);
+        assert_eq!(tilt.read8(0x0E00_8400), 0x60);
+        assert_eq!(tilt.read8(0x0E00_8500), 0x03);
+    }
+}
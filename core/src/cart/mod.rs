@@ -1,6 +1,461 @@
-#[derive(Default)]
-pub struct Cart;
+use std::ops::BitOr;
+
+pub mod eeprom;
+pub mod flash;
+mod gamedb;
+pub mod gpio;
+pub mod patch;
+pub mod rtc;
+pub mod sram;
+
+pub use flash::FlashChip;
+pub use gamedb::lookup_game;
+pub use gpio::PeripheralInput;
+
+use eeprom::Eeprom;
+use flash::Flash;
+use gpio::{Gpio, TiltSensor};
+use sram::Sram;
+
+use crate::state::impl_savestate;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RomHeader {
+    pub title: String,
+    pub game_code: String,
+    pub maker_code: String,
+    pub version: u8,
+}
+
+impl RomHeader {
+    pub fn parse(rom: &[u8]) -> Option<Self> {
+        if rom.len() < 0xC0 {
+            return None;
+        }
+        let text = |range: std::ops::Range<usize>| {
+            rom[range]
+                .iter()
+                .take_while(|&&b| b != 0)
+                .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '?' })
+                .collect::<String>()
+        };
+        Some(Self {
+            title: text(0xA0..0xAC),
+            game_code: text(0xAC..0xB0),
+            maker_code: text(0xB0..0xB2),
+            version: rom[0xBC],
+        })
+    }
+}
+
+/// The compressed Nintendo logo at 0x04 of every licensed ROM header. The
+/// BIOS refuses to start a cartridge whose copy differs.
+pub const NINTENDO_LOGO: [u8; 156] = [
+    0x24, 0xFF, 0xAE, 0x51, 0x69, 0x9A, 0xA2, 0x21, 0x3D, 0x84, 0x82, 0x0A, 0x84, 0xE4, 0x09, 0xAD, 0x11, 0x24,
+    0x8B, 0x98, 0xC0, 0x81, 0x7F, 0x21, 0xA3, 0x52, 0xBE, 0x19, 0x93, 0x09, 0xCE, 0x20, 0x10, 0x46, 0x4A, 0x4A,
+    0xF8, 0x27, 0x31, 0xEC, 0x58, 0xC7, 0xE8, 0x33, 0x82, 0xE3, 0xCE, 0xBF, 0x85, 0xF4, 0xDF, 0x94, 0xCE, 0x4B,
+    0x09, 0xC1, 0x94, 0x56, 0x8A, 0xC0, 0x13, 0x72, 0xA7, 0xFC, 0x9F, 0x84, 0x4D, 0x73, 0xA3, 0xCA, 0x9A, 0x61,
+    0x58, 0x97, 0xA3, 0x27, 0xFC, 0x03, 0x98, 0x76, 0x23, 0x1D, 0xC7, 0x61, 0x03, 0x04, 0xAE, 0x56, 0xBF, 0x38,
+    0x84, 0x00, 0x40, 0xA7, 0x0E, 0xFD, 0xFF, 0x52, 0xFE, 0x03, 0x6F, 0x95, 0x30, 0xF1, 0x97, 0xFB, 0xC0, 0x85,
+    0x60, 0xD6, 0x80, 0x25, 0xA9, 0x63, 0xBE, 0x03, 0x01, 0x4E, 0x38, 0xE2, 0xF9, 0xA2, 0x34, 0xFF, 0xBB, 0x3E,
+    0x03, 0x44, 0x78, 0x00, 0x90, 0xCB, 0x88, 0x11, 0x3A, 0x94, 0x65, 0xC0, 0x7C, 0x63, 0x87, 0xF0, 0x3C, 0xAF,
+    0xD6, 0x25, 0xE4, 0x8B, 0x38, 0x0A, 0xAC, 0x72, 0x21, 0xD4, 0xF8, 0x07,
+];
+const LOGO_OFFSET: usize = 0x04;
+const CHECKSUM_OFFSET: usize = 0xBD;
+// Bits 2 and 7 of the logo's byte 0x9C enable the debugging handlers; the
+// BIOS accepts either setting.
+const LOGO_DEBUG_BYTE: usize = 0x9C - LOGO_OFFSET;
+const LOGO_DEBUG_BITS: u8 = 0x84;
+
+/// The header complement check byte (0xBD) the BIOS expects for `rom`.
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[0xA0..CHECKSUM_OFFSET].iter().fold(0u8, |sum, &b| sum.wrapping_sub(b)).wrapping_sub(0x19)
+}
+
+/// Why the BIOS would refuse to boot `rom`, if it would: the Nintendo logo
+/// or the header checksum is wrong, as in a lot of homebrew. A real BIOS
+/// then hangs at the logo.
+pub fn header_problem(rom: &[u8]) -> Option<&'static str> {
+    if rom.len() < 0xC0 {
+        return Some("the header is missing");
+    }
+    let logo_ok = rom[LOGO_OFFSET..0xA0]
+        .iter()
+        .zip(NINTENDO_LOGO)
+        .enumerate()
+        .all(|(i, (&byte, logo))| (byte ^ logo) & if i == LOGO_DEBUG_BYTE { !LOGO_DEBUG_BITS } else { 0xFF } == 0);
+    let checksum_ok = rom[CHECKSUM_OFFSET] == header_checksum(rom);
+    match (logo_ok, checksum_ok) {
+        (true, true) => None,
+        (false, true) => Some("the Nintendo logo does not match"),
+        (true, false) => Some("the header checksum is wrong"),
+        (false, false) => Some("the Nintendo logo and the header checksum are wrong"),
+    }
+}
+
+/// Writes the Nintendo logo and the header checksum into `rom`, as
+/// homebrew header fixers do, so the BIOS accepts it. Returns whether
+/// anything changed; images too short to have a header are left alone.
+pub fn fix_header(rom: &mut [u8]) -> bool {
+    if rom.len() < 0xC0 || header_problem(rom).is_none() {
+        return false;
+    }
+    rom[LOGO_OFFSET..0xA0].copy_from_slice(&NINTENDO_LOGO);
+    rom[CHECKSUM_OFFSET] = header_checksum(rom);
+    true
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BackupType {
+    None,
+    /// 32 KiB, what the SDK's SRAM library expects.
+    Sram,
+    Sram64K,
+    Flash64K,
+    Flash128K,
+    Eeprom512,
+    Eeprom8K,
+}
+
+impl BackupType {
+    pub const ALL: [BackupType; 7] = [
+        BackupType::None,
+        BackupType::Sram,
+        BackupType::Sram64K,
+        BackupType::Flash64K,
+        BackupType::Flash128K,
+        BackupType::Eeprom512,
+        BackupType::Eeprom8K,
+    ];
+
+    /// Guesses the backup type from the library ID strings the official SDK
+    /// links into every ROM. EEPROM size cannot be told this way; 8K is
+    /// assumed until the game's first access says otherwise.
+    pub fn detect(rom: &[u8]) -> Self {
+        const IDS: [(&[u8], BackupType); 5] = [
+            (b"EEPROM_V", BackupType::Eeprom8K),
+            (b"SRAM_V", BackupType::Sram),
+            (b"SRAM_F_V", BackupType::Sram),
+            (b"FLASH_V", BackupType::Flash64K),
+            (b"FLASH512_V", BackupType::Flash64K),
+        ];
+        if contains(rom, b"FLASH1M_V") {
+            return BackupType::Flash128K;
+        }
+        IDS.iter()
+            .find(|(id, _)| contains(rom, id))
+            .map_or(BackupType::None, |&(_, kind)| kind)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BackupType::None => "none",
+            BackupType::Sram => "sram",
+            BackupType::Sram64K => "sram64k",
+            BackupType::Flash64K => "flash64k",
+            BackupType::Flash128K => "flash128k",
+            BackupType::Eeprom512 => "eeprom512",
+            BackupType::Eeprom8K => "eeprom8k",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Bytes in a raw `.sav` file for this chip.
+    pub fn size(self) -> usize {
+        match self {
+            BackupType::None => 0,
+            BackupType::Sram => 32 * 1024,
+            BackupType::Sram64K | BackupType::Flash64K => 64 * 1024,
+            BackupType::Flash128K => 128 * 1024,
+            BackupType::Eeprom512 => 512,
+            BackupType::Eeprom8K => 8 * 1024,
+        }
+    }
+}
+
+/// CRC-32 (IEEE 802.3), as printed by No-Intro and most ROM tools.
+pub fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut c = i as u32;
+            let mut k = 0;
+            while k < 8 {
+                c = if c & 1 != 0 { 0xEDB8_8320 ^ (c >> 1) } else { c >> 1 };
+                k += 1;
+            }
+            table[i] = c;
+            i += 1;
+        }
+        table
+    };
+    !data.iter().fold(!0u32, |crc, &b| TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+/// Per-title hardware and behavior flags.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Quirks(u32);
+
+impl Quirks {
+    pub const NONE: Quirks = Quirks(0);
+    pub const SOLAR_SENSOR: Quirks = Quirks(1 << 0);
+    pub const GYRO: Quirks = Quirks(1 << 1);
+    pub const TILT: Quirks = Quirks(1 << 2);
+    pub const RUMBLE: Quirks = Quirks(1 << 3);
+    /// Reads past the end of the ROM repeat the image (rounded up to a power
+    /// of two) instead of returning the address-based open-bus value. The
+    /// Classic NES Series checks for this.
+    pub const ROM_MIRROR: Quirks = Quirks(1 << 4);
+
+    const NAMES: [(&'static str, Quirks); 5] = [
+        ("solar_sensor", Quirks::SOLAR_SENSOR),
+        ("gyro", Quirks::GYRO),
+        ("tilt", Quirks::TILT),
+        ("rumble", Quirks::RUMBLE),
+        ("rom_mirror", Quirks::ROM_MIRROR),
+    ];
+
+    pub const fn union(self, other: Quirks) -> Quirks { Quirks(self.0 | other.0) }
+    pub fn contains(self, other: Quirks) -> bool { self.0 & other.0 == other.0 }
+    pub fn bits(self) -> u32 { self.0 }
+
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        Self::NAMES.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|&(_, q)| q)
+    }
+
+    pub fn names(self) -> Vec<&'static str> {
+        Self::NAMES.iter().filter(|(_, q)| self.contains(*q)).map(|&(n, _)| n).collect()
+    }
+}
+
+impl BitOr for Quirks {
+    type Output = Quirks;
+    fn bitor(self, rhs: Quirks) -> Quirks { self.union(rhs) }
+}
+
+/// Cartridge hardware description. `backup: None` means auto-detect;
+/// `flash_chip: None` reports the usual chip for the flash size.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CartConfig {
+    pub backup: Option<BackupType>,
+    pub flash_chip: Option<FlashChip>,
+    pub rtc: bool,
+    pub quirks: Quirks,
+}
+
+pub struct Cart {
+    header: Option<RomHeader>,
+    crc32: u32,
+    config: CartConfig,
+    backup: BackupType,
+    pub gpio: Gpio,
+    pub tilt: TiltSensor,
+    pub eeprom: Eeprom,
+    pub flash: Flash,
+    pub sram: Sram,
+    // Set by writes to the backup chip; cleared by `take_dirty`.
+    dirty: bool,
+}
+
+impl Default for Cart {
+    fn default() -> Self {
+        Self {
+            header: None,
+            crc32: 0,
+            config: CartConfig::default(),
+            backup: BackupType::None,
+            gpio: Gpio::new(),
+            tilt: TiltSensor::new(),
+            eeprom: Eeprom::new(),
+            flash: Flash::new(),
+            sram: Sram::new(),
+            dirty: false,
+        }
+    }
+}
+
+impl_savestate!(Cart { gpio, tilt, eeprom, flash, sram });
 
 impl Cart {
-    pub fn new() -> Self { Self }
+    pub fn new() -> Self { Self::default() }
+
+    pub fn header(&self) -> Option<&RomHeader> { self.header.as_ref() }
+    /// CRC32 of the whole ROM image, identifying the exact dump.
+    pub fn rom_crc32(&self) -> u32 { self.crc32 }
+    pub fn config(&self) -> CartConfig { self.config }
+    pub fn backup_type(&self) -> BackupType { self.backup }
+
+    pub fn set_peripheral_input(&mut self, input: PeripheralInput) {
+        self.gpio.set_input(input);
+        self.tilt.set_input(input);
+    }
+
+    /// Reads the header and picks the configuration from the built-in game
+    /// database, falling back to auto-detection.
+    pub fn load(&mut self, rom: &[u8]) {
+        self.header = RomHeader::parse(rom);
+        self.crc32 = crc32(rom);
+        let config = self
+            .header
+            .as_ref()
+            .and_then(|h| lookup_game(&h.game_code))
+            .unwrap_or_default();
+        self.set_config(config, rom);
+    }
+
+    pub fn set_config(&mut self, config: CartConfig, rom: &[u8]) {
+        self.config = config;
+        self.backup = config.backup.unwrap_or_else(|| BackupType::detect(rom));
+        self.gpio.configure(config.quirks, config.rtc);
+        self.tilt.configure(config.quirks);
+        self.eeprom.configure(self.backup, config.backup.is_some());
+        self.flash.configure(self.backup, config.flash_chip);
+        self.sram.configure(self.backup);
+        self.dirty = false;
+        log::info!(
+            "Cart: {} backup={} rtc={} quirks={:?}",
+            self.header.as_ref().map_or("????", |h| h.game_code.as_str()),
+            self.backup.name(),
+            config.rtc,
+            config.quirks.names()
+        );
+    }
+
+    /// Contents of the backup chip as a raw `.sav` file: the SRAM, the
+    /// whole flash, or the EEPROM at its detected size.
+    pub fn backup_data(&self) -> Vec<u8> {
+        match self.backup {
+            BackupType::None => Vec::new(),
+            BackupType::Sram | BackupType::Sram64K => self.sram.data().to_vec(),
+            BackupType::Flash64K | BackupType::Flash128K => self.flash.data().to_vec(),
+            BackupType::Eeprom512 | BackupType::Eeprom8K => self.eeprom.data().to_vec(),
+        }
+    }
+
+    /// Loads a raw `.sav` file. Bytes past the chip size, such as the RTC
+    /// footer some emulators append, are ignored.
+    pub fn load_backup(&mut self, data: &[u8]) {
+        match self.backup {
+            BackupType::None => {}
+            BackupType::Sram | BackupType::Sram64K => self.sram.load_data(data),
+            BackupType::Flash64K | BackupType::Flash128K => self.flash.load_data(data),
+            BackupType::Eeprom512 | BackupType::Eeprom8K => self.eeprom.load_data(data),
+        }
+    }
+
+    pub(crate) fn mark_dirty(&mut self) { self.dirty = true; }
+
+    /// Whether the game wrote to the backup chip since the last call.
+    pub fn take_dirty(&mut self) -> bool { std::mem::take(&mut self.dirty) }
+
+    /// The `.sav` contents if the game wrote to the backup chip since the
+    /// last call, for frontends that flush saves as they change.
+    pub fn take_dirty_backup(&mut self) -> Option<Vec<u8>> {
+        self.take_dirty().then(|| self.backup_data())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom_with_header(code: &[u8; 4], extra: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0u8; 0x200];
+        rom[0xA0..0xA8].copy_from_slice(b"TESTGAME");
+        rom[0xAC..0xB0].copy_from_slice(code);
+        rom[0xB0..0xB2].copy_from_slice(b"01");
+        rom.extend_from_slice(extra);
+        rom
+    }
+
+    #[test]
+    fn parses_header_fields() {
+        let header = RomHeader::parse(&rom_with_header(b"ABCE", &[])).unwrap();
+        assert_eq!(header.title, "TESTGAME");
+        assert_eq!(header.game_code, "ABCE");
+        assert_eq!(header.maker_code, "01");
+        assert!(RomHeader::parse(&[0u8; 16]).is_none());
+    }
+
+    #[test]
+    fn header_fix_satisfies_the_bios_checks() {
+        let mut rom = rom_with_header(b"ABCE", &[]);
+        assert_eq!(header_problem(&rom), Some("the Nintendo logo and the header checksum are wrong"));
+        assert_eq!(header_problem(&rom[..0x80]), Some("the header is missing"));
+        assert!(fix_header(&mut rom));
+        assert_eq!(header_problem(&rom), None);
+        assert!(!fix_header(&mut rom));
+        assert_eq!(RomHeader::parse(&rom).unwrap().game_code, "ABCE");
+
+        // The debugging bits of the logo are not checked; the title is.
+        rom[0x9C] |= 0x84;
+        assert_eq!(header_problem(&rom), None);
+        rom[0xA0] = b'X';
+        assert_eq!(header_problem(&rom), Some("the header checksum is wrong"));
+        rom[0xBD] = header_checksum(&rom);
+        rom[0x10] ^= 1;
+        assert_eq!(header_problem(&rom), Some("the Nintendo logo does not match"));
+    }
+
+    #[test]
+    fn crc32_matches_reference() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn detects_backup_from_library_ids() {
+        assert_eq!(BackupType::detect(b"..FLASH1M_V103.."), BackupType::Flash128K);
+        assert_eq!(BackupType::detect(b"..FLASH512_V131.."), BackupType::Flash64K);
+        assert_eq!(BackupType::detect(b"..SRAM_V113.."), BackupType::Sram);
+        assert_eq!(BackupType::detect(b"..EEPROM_V124.."), BackupType::Eeprom8K);
+        assert_eq!(BackupType::detect(b"nothing here"), BackupType::None);
+    }
+
+    #[test]
+    fn database_entry_overrides_detection() {
+        let mut cart = Cart::new();
+        let rom = rom_with_header(b"AXVE", b"SRAM_V113");
+        cart.load(&rom);
+        assert_eq!(cart.backup_type(), BackupType::Flash128K);
+        assert!(cart.config().rtc);
+
+        cart.set_config(CartConfig { backup: None, ..CartConfig::default() }, &rom);
+        assert_eq!(cart.backup_type(), BackupType::Sram);
+        assert!(!cart.config().rtc);
+    }
+
+    #[test]
+    fn dirty_backup_is_taken_once() {
+        let mut cart = Cart::new();
+        let rom = rom_with_header(b"TEST", b"SRAM_V113");
+        cart.load(&rom);
+        assert_eq!(cart.backup_data().len(), 0x8000);
+        assert_eq!(cart.take_dirty_backup(), None);
+        cart.sram.write8(0x0E00_0004, 0x77);
+        cart.mark_dirty();
+        assert_eq!(cart.take_dirty_backup().unwrap()[4], 0x77);
+        assert_eq!(cart.take_dirty_backup(), None);
+
+        cart.set_config(CartConfig { backup: Some(BackupType::Sram64K), ..CartConfig::default() }, &rom);
+        assert_eq!(cart.backup_data().len(), 0x10000);
+        assert_eq!(cart.backup_data()[4], 0xFF);
+    }
+
+    #[test]
+    fn quirk_names_round_trip() {
+        let q = Quirks::GYRO | Quirks::RUMBLE;
+        assert_eq!(q.names(), vec!["gyro", "rumble"]);
+        assert_eq!(Quirks::from_name("Rumble"), Some(Quirks::RUMBLE));
+        assert_eq!(BackupType::from_name("EEPROM512"), Some(BackupType::Eeprom512));
+    }
 }
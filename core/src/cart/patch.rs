@@ -0,0 +1,408 @@
+// ROM hacks and translations as IPS, UPS and BPS patches, applied to the ROM
+// image in memory. UPS and BPS carry CRC-32s of the ROM they expect, the
+// result and the patch itself, which are all checked; IPS has none, so an
+// IPS patch for another ROM revision applies but will not run.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::crc32;
+use crate::mem::ROM_MAX_SIZE;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: u32 = 0x454F46;
+const UPS_MAGIC: &[u8] = b"UPS1";
+const BPS_MAGIC: &[u8] = b"BPS1";
+// Source, target and patch CRC-32s.
+const FOOTER_LEN: usize = 12;
+
+/// Extensions of patch files, in the order they are looked for.
+pub const PATCH_EXTENSIONS: [&str; 3] = ["ips", "ups", "bps"];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PatchFormat {
+    Ips,
+    Ups,
+    Bps,
+}
+
+impl PatchFormat {
+    pub const ALL: [PatchFormat; 3] = [PatchFormat::Ips, PatchFormat::Ups, PatchFormat::Bps];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PatchFormat::Ips => "ips",
+            PatchFormat::Ups => "ups",
+            PatchFormat::Bps => "bps",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> { Self::ALL.into_iter().find(|f| f.name() == name) }
+
+    /// The format `patch` is in, by its magic.
+    pub fn detect(patch: &[u8]) -> Option<Self> {
+        if patch.starts_with(IPS_MAGIC) {
+            Some(PatchFormat::Ips)
+        } else if patch.starts_with(UPS_MAGIC) {
+            Some(PatchFormat::Ups)
+        } else if patch.starts_with(BPS_MAGIC) {
+            Some(PatchFormat::Bps)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PatchError {
+    /// Not an IPS, UPS or BPS file.
+    UnknownFormat,
+    Corrupt(&'static str),
+    /// The patch was made for another ROM (or revision).
+    WrongRom { expected: u32, found: u32 },
+    /// The patch applied but did not produce the ROM it describes.
+    BadResult,
+    /// The patched ROM would not fit the 32 MiB cartridge space.
+    TooLarge,
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::UnknownFormat => write!(f, "not an IPS, UPS or BPS patch"),
+            PatchError::Corrupt(what) => write!(f, "patch is corrupt: {}", what),
+            PatchError::WrongRom { expected, found } => {
+                write!(f, "patch is for the ROM with CRC32 {:08X}, not {:08X}", expected, found)
+            }
+            PatchError::BadResult => write!(f, "patched ROM does not match the checksum in the patch"),
+            PatchError::TooLarge => write!(f, "patched ROM is larger than 32 MiB"),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+/// The first patch file next to `rom`: `game.ips`, `game.ups` or `game.bps`
+/// for `game.gba`.
+pub fn find_patch(rom: &Path) -> Option<PathBuf> {
+    PATCH_EXTENSIONS.iter().map(|ext| rom.with_extension(ext)).find(|path| path.is_file())
+}
+
+/// `rom` with `patch` applied.
+pub fn apply_patch(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    match PatchFormat::detect(patch) {
+        Some(PatchFormat::Ips) => apply_ips(rom, patch),
+        Some(PatchFormat::Ups) => apply_ups(rom, patch),
+        Some(PatchFormat::Bps) => apply_bps(rom, patch),
+        None => Err(PatchError::UnknownFormat),
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], PatchError> {
+        let bytes = self.data.get(self.pos..self.pos + len).ok_or(PatchError::Corrupt("truncated"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, PatchError> { Ok(self.bytes(1)?[0]) }
+
+    fn be(&mut self, len: usize) -> Result<u32, PatchError> {
+        Ok(self.bytes(len)?.iter().fold(0, |value, &b| value << 8 | b as u32))
+    }
+
+    // UPS/BPS numbers: 7 bits per byte, least significant first, the last
+    // byte flagged with bit 7, and each continuation adding one so that
+    // every value has a single encoding.
+    fn number(&mut self) -> Result<usize, PatchError> {
+        let (mut value, mut shift) = (0u64, 1u64);
+        loop {
+            let b = self.byte()?;
+            value += (b & 0x7F) as u64 * shift;
+            if b & 0x80 != 0 {
+                return usize::try_from(value).map_err(|_| PatchError::Corrupt("number out of range"));
+            }
+            shift <<= 7;
+            value += shift;
+            if shift > 1 << 56 {
+                return Err(PatchError::Corrupt("number out of range"));
+            }
+        }
+    }
+}
+
+// "PATCH", then records of a 24-bit offset and a 16-bit length followed by
+// that many bytes, or a zero length, a 16-bit count and one byte to repeat.
+// "EOF" ends the records, optionally followed by a 24-bit size to truncate
+// the ROM to. Writes past the end grow the ROM.
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let mut out = rom.to_vec();
+    let mut r = Reader { data: patch, pos: IPS_MAGIC.len() };
+    loop {
+        let offset = r.be(3)?;
+        if offset == IPS_EOF {
+            break;
+        }
+        let offset = offset as usize;
+        let len = r.be(2)? as usize;
+        let (len, fill) = if len == 0 { (r.be(2)? as usize, Some(r.byte()?)) } else { (len, None) };
+        if offset + len > ROM_MAX_SIZE {
+            return Err(PatchError::TooLarge);
+        }
+        if out.len() < offset + len {
+            out.resize(offset + len, 0);
+        }
+        match fill {
+            Some(b) => out[offset..offset + len].fill(b),
+            None => out[offset..offset + len].copy_from_slice(r.bytes(len)?),
+        }
+    }
+    if r.pos + 3 <= patch.len() {
+        out.truncate(r.be(3)? as usize);
+    }
+    Ok(out)
+}
+
+// Checks the CRC-32s of the patch and of the ROM it applies to, and returns
+// the one the result must have.
+fn check_footer(rom: &[u8], patch: &[u8]) -> Result<u32, PatchError> {
+    let at = patch.len().checked_sub(FOOTER_LEN).ok_or(PatchError::Corrupt("truncated"))?;
+    let word = |i: usize| u32::from_le_bytes(patch[at + i * 4..at + i * 4 + 4].try_into().unwrap());
+    if crc32(&patch[..at + 8]) != word(2) {
+        return Err(PatchError::Corrupt("checksum mismatch"));
+    }
+    let (source, target) = (word(0), word(1));
+    let found = crc32(rom);
+    if found != source {
+        return Err(PatchError::WrongRom { expected: source, found });
+    }
+    Ok(target)
+}
+
+fn target_buffer(size: usize) -> Result<Vec<u8>, PatchError> {
+    if size > ROM_MAX_SIZE {
+        return Err(PatchError::TooLarge);
+    }
+    Ok(vec![0; size])
+}
+
+// "UPS1", the source and target sizes, then runs of a distance to skip and
+// bytes to XOR into the ROM up to a zero byte, which also counts as one.
+fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    let target_crc = check_footer(rom, patch)?;
+    let end = patch.len() - FOOTER_LEN;
+    let mut r = Reader { data: &patch[..end], pos: UPS_MAGIC.len() };
+    let source_size = r.number()?;
+    let target_size = r.number()?;
+    if source_size != rom.len() {
+        return Err(PatchError::Corrupt("source size mismatch"));
+    }
+    let mut out = target_buffer(target_size)?;
+    let len = out.len().min(rom.len());
+    out[..len].copy_from_slice(&rom[..len]);
+
+    let mut at = 0usize;
+    while r.pos < end {
+        at = at.saturating_add(r.number()?);
+        loop {
+            let xor = r.byte()?;
+            if let Some(b) = out.get_mut(at) {
+                *b ^= xor;
+            }
+            at = at.saturating_add(1);
+            if xor == 0 {
+                break;
+            }
+        }
+    }
+    if crc32(&out) != target_crc {
+        return Err(PatchError::BadResult);
+    }
+    Ok(out)
+}
+
+// "BPS1", the source, target and metadata sizes, the metadata, then actions
+// that build the target in order: copy the source at the same offset, copy
+// bytes from the patch, or copy from a moving offset into the source or the
+// target built so far.
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+    const SOURCE_READ: usize = 0;
+    const TARGET_READ: usize = 1;
+    const SOURCE_COPY: usize = 2;
+
+    let target_crc = check_footer(rom, patch)?;
+    let end = patch.len() - FOOTER_LEN;
+    let mut r = Reader { data: &patch[..end], pos: BPS_MAGIC.len() };
+    let source_size = r.number()?;
+    let target_size = r.number()?;
+    if source_size != rom.len() {
+        return Err(PatchError::Corrupt("source size mismatch"));
+    }
+    let metadata_size = r.number()?;
+    r.bytes(metadata_size)?;
+    let mut out = target_buffer(target_size)?;
+
+    let overrun = || PatchError::Corrupt("action out of bounds");
+    // Relative offsets move by a signed distance, sign in bit 0.
+    let seek = |offset: usize, delta: usize| {
+        let distance = delta >> 1;
+        if delta & 1 != 0 { offset.checked_sub(distance) } else { offset.checked_add(distance) }.ok_or_else(overrun)
+    };
+    let (mut at, mut source_at, mut target_at) = (0usize, 0usize, 0usize);
+    while r.pos < end {
+        let action = r.number()?;
+        let len = (action >> 2) + 1;
+        let dest = out.get_mut(at..at.saturating_add(len)).ok_or_else(overrun)?;
+        match action & 3 {
+            SOURCE_READ => dest.copy_from_slice(rom.get(at..at + len).ok_or_else(overrun)?),
+            TARGET_READ => dest.copy_from_slice(r.bytes(len)?),
+            SOURCE_COPY => {
+                source_at = seek(source_at, r.number()?)?;
+                dest.copy_from_slice(rom.get(source_at..source_at.saturating_add(len)).ok_or_else(overrun)?);
+                source_at += len;
+            }
+            _ => {
+                // Byte by byte: the copy may overlap what it writes, which
+                // repeats a pattern.
+                target_at = seek(target_at, r.number()?)?;
+                if target_at >= at {
+                    return Err(overrun());
+                }
+                for i in 0..len {
+                    out[at + i] = out[target_at + i];
+                }
+                target_at += len;
+            }
+        }
+        at += len;
+    }
+    if crc32(&out) != target_crc {
+        return Err(PatchError::BadResult);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(mut value: usize, out: &mut Vec<u8>) {
+        loop {
+            let bits = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(0x80 | bits);
+                return;
+            }
+            out.push(bits);
+            value -= 1;
+        }
+    }
+
+    fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend(crc32(source).to_le_bytes());
+        patch.extend(crc32(target).to_le_bytes());
+        patch.extend(crc32(&patch).to_le_bytes());
+        patch
+    }
+
+    fn rom() -> Vec<u8> { (0..0x400u32).map(|i| (i * 13 % 256) as u8).collect() }
+
+    #[test]
+    fn numbers_have_one_encoding_each() {
+        for value in [0, 1, 0x7F, 0x80, 0x407F, 0x4080, 0x0123_4567] {
+            let mut encoded = Vec::new();
+            number(value, &mut encoded);
+            assert_eq!(Reader { data: &encoded, pos: 0 }.number(), Ok(value));
+        }
+    }
+
+    #[test]
+    fn applies_ips_records_fills_and_truncation() {
+        let rom = rom();
+        let mut patch = b"PATCH".to_vec();
+        patch.extend([0x00, 0x00, 0x10, 0x00, 0x03, 0xAA, 0xBB, 0xCC]);
+        patch.extend([0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x08, 0xEE]);
+        patch.extend(b"EOF");
+        let out = apply_patch(&rom, &patch).unwrap();
+        assert_eq!(&out[0x10..0x13], [0xAA, 0xBB, 0xCC]);
+        assert_eq!(&out[0x200..0x208], [0xEE; 8]);
+        assert_eq!(out[..0x10], rom[..0x10]);
+        assert_eq!(out.len(), rom.len());
+
+        // Growing past the end, then truncating.
+        let mut patch = b"PATCH".to_vec();
+        patch.extend([0x00, 0x04, 0x02, 0x00, 0x01, 0x55]);
+        patch.extend(b"EOF");
+        assert_eq!(apply_patch(&rom, &patch).unwrap().len(), 0x403);
+        patch.extend([0x00, 0x01, 0x00]);
+        assert_eq!(apply_patch(&rom, &patch).unwrap(), rom[..0x100]);
+
+        assert_eq!(apply_patch(&rom, b"PATCH\x00\x00"), Err(PatchError::Corrupt("truncated")));
+        assert_eq!(apply_patch(&rom, b"NOPE"), Err(PatchError::UnknownFormat));
+    }
+
+    #[test]
+    fn applies_ups_and_checks_its_checksums() {
+        let rom = rom();
+        let mut target = rom.clone();
+        target[0x20] ^= 0x0F;
+        target[0x21] ^= 0xF0;
+        target.extend([1, 2, 3]);
+
+        let mut body = b"UPS1".to_vec();
+        number(rom.len(), &mut body);
+        number(target.len(), &mut body);
+        number(0x20, &mut body);
+        body.extend([0x0F, 0xF0, 0x00]);
+        number(0x400 - 0x23, &mut body);
+        body.extend([1, 2, 3, 0]);
+        let patch = with_footer(body, &rom, &target);
+        assert_eq!(apply_patch(&rom, &patch).unwrap(), target);
+
+        let mut other = rom.clone();
+        other[0] ^= 1;
+        assert!(matches!(apply_patch(&other, &patch), Err(PatchError::WrongRom { .. })));
+        let mut damaged = patch.clone();
+        damaged[10] ^= 1;
+        assert_eq!(apply_patch(&rom, &damaged), Err(PatchError::Corrupt("checksum mismatch")));
+    }
+
+    #[test]
+    fn applies_every_bps_action() {
+        let rom = rom();
+        // The first 0x10 bytes unchanged, "HI" repeated from the patch and
+        // then the target, and 8 bytes of the source from 0x100.
+        let mut target = rom[..0x10].to_vec();
+        target.extend(b"HIHIHIHI");
+        target.extend(&rom[0x100..0x108]);
+
+        let mut body = b"BPS1".to_vec();
+        number(rom.len(), &mut body);
+        number(target.len(), &mut body);
+        number(3, &mut body);
+        body.extend(b"abc");
+        number((0x10 - 1) << 2, &mut body);
+        number((2 - 1) << 2 | 1, &mut body);
+        body.extend(b"HI");
+        number((6 - 1) << 2 | 3, &mut body);
+        number(0x10 << 1, &mut body);
+        number((8 - 1) << 2 | 2, &mut body);
+        number(0x100 << 1, &mut body);
+        let patch = with_footer(body, &rom, &target);
+        assert_eq!(apply_patch(&rom, &patch).unwrap(), target);
+
+        let mut bad_target = patch.clone();
+        let at = bad_target.len() - 8;
+        bad_target[at] ^= 1;
+        let crc = crc32(&bad_target[..bad_target.len() - 4]);
+        let len = bad_target.len();
+        bad_target[len - 4..].copy_from_slice(&crc.to_le_bytes());
+        assert_eq!(apply_patch(&rom, &bad_target), Err(PatchError::BadResult));
+    }
+}
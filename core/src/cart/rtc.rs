@@ -0,0 +1,251 @@
+// Seiko S-3511 real-time clock on the cartridge GPIO port (Pokémon
+// Ruby/Sapphire/Emerald, Boktai). The game bit-bangs it over three pins.
+// Raising CS while SCK is high starts a transfer. Each bit is put on SIO
+// while SCK is low and taken on the next high, LSB first; when the game
+// reads, the chip drives SIO instead. The first byte is the command: 0110 in
+// the low nibble, the register in bits 4-6 and bit 7 set for a read. Data
+// bytes for the register follow, and lowering CS ends the transfer.
+//
+// The date and time are latched from the host-side clock when the game asks
+// for them, as BCD in UTC.
+
+use crate::state::impl_savestate;
+
+const SCK: u8 = 1 << 0;
+const SIO: u8 = 1 << 1;
+const CS: u8 = 1 << 2;
+
+const COMMAND_MAGIC: u8 = 0x6;
+const REG_RESET: u8 = 0;
+const REG_DATETIME: u8 = 2;
+const REG_CONTROL: u8 = 4;
+const REG_TIME: u8 = 6;
+// Data bytes that follow the command byte, by register.
+const REG_BYTES: [u8; 8] = [0, 0, 7, 0, 1, 0, 3, 0];
+const CONTROL_24H: u8 = 0x40;
+
+pub struct Rtc {
+    // Unix time from the emulator's clock; not part of the state.
+    time: u64,
+    // 0 idle, 1 SCK high with CS low, 2 in a transfer.
+    step: u8,
+    bits: u8,
+    bits_read: u8,
+    // The command byte while its data bytes are going, else 0.
+    command: u8,
+    bytes_left: u8,
+    control: u8,
+    // Year, month, day, weekday, hour, minute and second in BCD.
+    latched: [u8; 7],
+}
+
+impl_savestate!(Rtc { step, bits, bits_read, command, bytes_left, control, latched });
+
+impl Default for Rtc {
+    fn default() -> Self {
+        Self { time: 0, step: 0, bits: 0, bits_read: 0, command: 0, bytes_left: 0, control: CONTROL_24H, latched: [0; 7] }
+    }
+}
+
+impl Rtc {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn time(&self) -> u64 { self.time }
+    /// Sets the clock, in seconds since the Unix epoch.
+    pub fn set_time(&mut self, time: u64) { self.time = time; }
+
+    /// Handles the game setting the pins. Returns the pins the chip drives,
+    /// if it drives any.
+    pub fn write_pins(&mut self, pins: u8) -> Option<u8> {
+        match self.step {
+            0 => {
+                if pins & (SCK | CS) == SCK {
+                    self.step = 1;
+                }
+                None
+            }
+            1 => {
+                if pins & (SCK | CS) == SCK | CS {
+                    self.step = 2;
+                } else if pins & (SCK | CS) != SCK {
+                    self.step = 0;
+                }
+                None
+            }
+            _ if pins & SCK == 0 => {
+                let bit = (pins & SIO) >> 1;
+                self.bits = (self.bits & !(1 << self.bits_read)) | (bit << self.bits_read);
+                None
+            }
+            _ if pins & CS == 0 => {
+                // SCK high with CS low may already begin the next transfer.
+                self.step = 1;
+                self.bits_read = 0;
+                self.command = 0;
+                self.bytes_left = 0;
+                Some(SCK)
+            }
+            _ if self.command & 0x80 != 0 => {
+                let bit = (self.output_byte() >> self.bits_read) & 1;
+                self.bits_read += 1;
+                if self.bits_read == 8 {
+                    self.bits_read = 0;
+                    self.bytes_left -= 1;
+                    if self.bytes_left == 0 {
+                        self.command = 0;
+                    }
+                }
+                Some(SCK | CS | bit << 1)
+            }
+            _ => {
+                self.bits_read += 1;
+                if self.bits_read == 8 {
+                    self.take_byte();
+                }
+                None
+            }
+        }
+    }
+
+    fn take_byte(&mut self) {
+        let byte = self.bits;
+        self.bits = 0;
+        self.bits_read = 0;
+        if self.command == 0 {
+            if byte & 0xF != COMMAND_MAGIC {
+                log::debug!("RTC: ignoring command byte {:#04x}", byte);
+                return;
+            }
+            let reg = (byte >> 4) & 7;
+            match reg {
+                REG_RESET => self.control = 0,
+                REG_DATETIME | REG_TIME => self.latch(),
+                _ => {}
+            }
+            self.bytes_left = REG_BYTES[reg as usize];
+            if self.bytes_left != 0 {
+                self.command = byte;
+            }
+            return;
+        }
+        // Writes to the date and time are dropped: the clock follows the
+        // emulator's.
+        if (self.command >> 4) & 7 == REG_CONTROL {
+            self.control = byte;
+        }
+        self.bytes_left -= 1;
+        if self.bytes_left == 0 {
+            self.command = 0;
+        }
+    }
+
+    fn output_byte(&self) -> u8 {
+        match (self.command >> 4) & 7 {
+            REG_CONTROL => self.control,
+            REG_DATETIME | REG_TIME => self.latched[7 - self.bytes_left as usize],
+            _ => 0,
+        }
+    }
+
+    fn latch(&mut self) {
+        let days = self.time / 86_400;
+        let secs = self.time % 86_400;
+        let (year, month, day) = civil_from_days(days);
+        let hour = secs / 3600;
+        let hour = if self.control & CONTROL_24H != 0 { hour } else { hour % 12 };
+        // 1970-01-01 was a Thursday; Sunday is 0.
+        let weekday = (days + 4) % 7;
+        let fields = [year % 100, month, day, weekday, hour, secs / 60 % 60, secs % 60];
+        self.latched = fields.map(|v| (v / 10 * 16 + v % 10) as u8);
+    }
+}
+
+// Year, month and day of the `days`th day after 1970-01-01 in the proleptic
+// Gregorian calendar (Howard Hinnant's algorithm).
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sends a command byte and reads `len` bytes back, as the games do.
+    fn read(rtc: &mut Rtc, register: u8, len: usize) -> Vec<u8> {
+        rtc.write_pins(SCK);
+        rtc.write_pins(SCK | CS);
+        let command = COMMAND_MAGIC | register << 4 | 0x80;
+        for i in 0..8 {
+            rtc.write_pins(CS | ((command >> i) & 1) << 1);
+            rtc.write_pins(SCK | CS);
+        }
+        let mut out = vec![0u8; len];
+        for byte in &mut out {
+            for i in 0..8 {
+                rtc.write_pins(CS);
+                let pins = rtc.write_pins(SCK | CS).unwrap();
+                *byte |= ((pins & SIO) >> 1) << i;
+            }
+        }
+        rtc.write_pins(SCK);
+        out
+    }
+
+    fn write(rtc: &mut Rtc, register: u8, data: &[u8]) {
+        rtc.write_pins(SCK);
+        rtc.write_pins(SCK | CS);
+        for byte in [COMMAND_MAGIC | register << 4].iter().chain(data) {
+            for i in 0..8 {
+                rtc.write_pins(CS | ((byte >> i) & 1) << 1);
+                rtc.write_pins(SCK | CS);
+            }
+        }
+        rtc.write_pins(SCK);
+    }
+
+    #[test]
+    fn date_and_time_read_back_in_bcd() {
+        let mut rtc = Rtc::new();
+        // Sunday 2004-02-29 13:45:07 UTC.
+        rtc.set_time(1_078_062_307);
+        assert_eq!(read(&mut rtc, REG_DATETIME, 7), [0x04, 0x02, 0x29, 0x00, 0x13, 0x45, 0x07]);
+        assert_eq!(read(&mut rtc, REG_TIME, 3), [0x13, 0x45, 0x07]);
+
+        rtc.set_time(946_684_800 + 86_400 * 366 - 1);
+        assert_eq!(read(&mut rtc, REG_DATETIME, 7), [0x00, 0x12, 0x31, 0x00, 0x23, 0x59, 0x59]);
+    }
+
+    #[test]
+    fn control_selects_the_hour_format() {
+        let mut rtc = Rtc::new();
+        rtc.set_time(1_078_062_307);
+        assert_eq!(read(&mut rtc, REG_CONTROL, 1), [CONTROL_24H]);
+        write(&mut rtc, REG_RESET, &[]);
+        assert_eq!(read(&mut rtc, REG_CONTROL, 1), [0]);
+        assert_eq!(read(&mut rtc, REG_TIME, 3), [0x01, 0x45, 0x07]);
+        write(&mut rtc, REG_CONTROL, &[CONTROL_24H]);
+        assert_eq!(read(&mut rtc, REG_TIME, 3), [0x13, 0x45, 0x07]);
+    }
+
+    #[test]
+    fn bad_command_bytes_are_ignored() {
+        let mut rtc = Rtc::new();
+        rtc.write_pins(SCK);
+        rtc.write_pins(SCK | CS);
+        for _ in 0..8 {
+            rtc.write_pins(CS | SIO);
+            rtc.write_pins(SCK | CS);
+        }
+        assert_eq!(rtc.command, 0);
+        assert_eq!(rtc.control, CONTROL_24H);
+    }
+}
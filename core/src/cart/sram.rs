@@ -0,0 +1,68 @@
+// Battery-backed SRAM (32 KiB, or 64 KiB on some later carts) on the 8-bit
+// bus at 0x0E000000. Smaller chips repeat over the 64 KiB window.
+
+use super::BackupType;
+use crate::mem;
+use crate::state::impl_savestate;
+
+pub const SRAM_BASE: u32 = 0x0E00_0000;
+const SIZE: usize = 32 * 1024;
+const SIZE_64K: usize = 64 * 1024;
+
+pub struct Sram {
+    data: Vec<u8>,
+}
+
+impl_savestate!(Sram { data });
+
+impl Default for Sram {
+    fn default() -> Self { Self { data: vec![0; SIZE] } }
+}
+
+impl Sram {
+    pub fn new() -> Self { Self::default() }
+
+    /// Sizes and erases the chip for `backup`. Carts without SRAM still get
+    /// the 32 KiB, so games whose backup went undetected keep working.
+    pub fn configure(&mut self, backup: BackupType) {
+        let size = if backup == BackupType::Sram64K { SIZE_64K } else { SIZE };
+        self.data = vec![0xFF; size];
+    }
+
+    pub fn size(&self) -> usize { self.data.len() }
+
+    /// Contents as stored in a `.sav` file.
+    pub fn data(&self) -> &[u8] { &self.data }
+
+    pub fn load_data(&mut self, data: &[u8]) {
+        let len = data.len().min(self.data.len());
+        self.data[..len].copy_from_slice(&data[..len]);
+    }
+
+    pub fn read8(&self, addr: u32) -> u8 { mem::mirrored(&self.data, (addr - SRAM_BASE) as usize) }
+
+    pub fn write8(&mut self, addr: u32, value: u8) {
+        if let Some(byte) = mem::mirrored_mut(&mut self.data, (addr - SRAM_BASE) as usize) {
+            *byte = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_follows_the_backup_type() {
+        let mut sram = Sram::new();
+        sram.configure(BackupType::Sram64K);
+        sram.write8(0x0E00_8000, 0x12);
+        assert_eq!(sram.read8(0x0E00_0000), 0xFF);
+        assert_eq!(sram.data().len(), 0x10000);
+
+        sram.configure(BackupType::Sram);
+        sram.write8(0x0E00_8000, 0x34);
+        assert_eq!(sram.read8(0x0E00_0000), 0x34);
+        assert_eq!(sram.data().len(), 0x8000);
+    }
+}
@@ -0,0 +1,25 @@
+// CodeBreaker codes: `Taaaaaaa vvvv`, code type in the top nibble.
+
+use super::{Cond, Op, Width};
+
+pub(super) fn decode(op1: u32, value: u16) -> Result<Option<Op>, String> {
+    let addr = op1 & 0x0FFF_FFFF;
+    let v = value as u32;
+    let op = match op1 >> 28 {
+        // Master code and hook; only needed by the real device.
+        0x0 | 0x1 => return Ok(None),
+        0x2 => Op::Or16 { addr, value },
+        0x3 => Op::write(addr, Width::Byte, v),
+        0x6 => Op::And16 { addr, value },
+        0x7 => Op::test(addr, Width::Half, Cond::Eq, v, 1),
+        0x8 => Op::write(addr, Width::Half, v),
+        0x9 => return Err("encrypted CodeBreaker codes are not supported".to_string()),
+        0xA => Op::test(addr, Width::Half, Cond::Ne, v, 1),
+        0xB => Op::test(addr, Width::Half, Cond::UGt, v, 1),
+        0xC => Op::test(addr, Width::Half, Cond::ULt, v, 1),
+        0xE => Op::Add { addr, width: Width::Half, value: v },
+        0xF => Op::test(addr, Width::Half, Cond::And, v, 1),
+        t => return Err(format!("unsupported CodeBreaker code type {:X}", t)),
+    };
+    Ok(Some(op))
+}
@@ -0,0 +1,157 @@
+// GameShark / Action Replay codes. Both generations encrypt each line with
+// TEA under a fixed key; v3 also packs the address and operation into op1.
+
+use super::{CheatFormat, Cond, Op, Width};
+
+const V1_SEEDS: [u32; 4] = [0x09F4_FBBD, 0x9681_884A, 0x3520_27E9, 0xF3DE_E5A7];
+const V3_SEEDS: [u32; 4] = [0x7AA9_648F, 0x7FAE_6994, 0xC0EF_AAD5, 0x4271_2C57];
+const TEA_DELTA: u32 = 0x9E37_79B9;
+
+// Seed change line; needs the devices' seed tables, which are not emulated.
+const RESEED: u32 = 0xDEAD_FACE;
+
+pub(super) struct Decoder {
+    v3: bool,
+    seeds: Option<[u32; 4]>,
+}
+
+impl Decoder {
+    pub fn new(format: CheatFormat, encrypted: bool) -> Self {
+        let v3 = format == CheatFormat::GameSharkV3;
+        let seeds = encrypted.then_some(if v3 { V3_SEEDS } else { V1_SEEDS });
+        Self { v3, seeds }
+    }
+
+    pub fn decode(&mut self, op1: u32, op2: u32) -> Result<Option<Op>, String> {
+        let (op1, op2) = match &self.seeds {
+            Some(seeds) => decrypt(op1, op2, seeds),
+            None => (op1, op2),
+        };
+        if op1 == RESEED {
+            return Err("seed change (DEADFACE) codes are not supported".to_string());
+        }
+        if self.v3 { decode_v3(op1, op2) } else { decode_v1(op1, op2) }
+    }
+}
+
+pub(super) fn decrypt(mut op1: u32, mut op2: u32, seeds: &[u32; 4]) -> (u32, u32) {
+    let mut sum = TEA_DELTA.wrapping_mul(32);
+    for _ in 0..32 {
+        op2 = op2.wrapping_sub(
+            (op1 << 4).wrapping_add(seeds[2]) ^ op1.wrapping_add(sum) ^ (op1 >> 5).wrapping_add(seeds[3]),
+        );
+        op1 = op1.wrapping_sub(
+            (op2 << 4).wrapping_add(seeds[0]) ^ op2.wrapping_add(sum) ^ (op2 >> 5).wrapping_add(seeds[1]),
+        );
+        sum = sum.wrapping_sub(TEA_DELTA);
+    }
+    (op1, op2)
+}
+
+fn decode_v1(op1: u32, op2: u32) -> Result<Option<Op>, String> {
+    let addr = op1 & 0x0FFF_FFFF;
+    let op = match op1 >> 28 {
+        0x0 => Op::write(addr, Width::Byte, op2),
+        0x1 => Op::write(addr, Width::Half, op2),
+        0x2 => Op::write(addr, Width::Word, op2),
+        0x6 => Op::RomPatch { addr: 0x0800_0000 + ((op1 & 0x00FF_FFFF) << 1), value: op2 as u16 },
+        0xD => Op::test(addr, Width::Half, Cond::Eq, op2, 1),
+        // E0zzvvvv 0aaaaaaa: run the next zz lines if [a] == vvvv.
+        0xE => Op::test(op2 & 0x0FFF_FFFF, Width::Half, Cond::Eq, op1, ((op1 >> 16) & 0xFF) as usize),
+        // Hook/master code; only needed by the real device.
+        0xF => return Ok(None),
+        _ => return Err(format!("unsupported GameShark code type {:X}", op1 >> 28)),
+    };
+    Ok(Some(op))
+}
+
+// v3 addresses keep the region in bits 20-23: 0x0212345 is 0x02012345.
+fn v3_addr(op1: u32) -> u32 { ((op1 & 0x00F0_0000) << 4) | (op1 & 0x000F_FFFF) }
+
+fn decode_v3(op1: u32, op2: u32) -> Result<Option<Op>, String> {
+    if op1 == 0 {
+        return match op2 {
+            0 => Ok(None),
+            _ => Err(format!("unsupported GameShark v3 special code {:08X}", op2)),
+        };
+    }
+    let addr = v3_addr(op1);
+    let width = match (op1 >> 25) & 3 {
+        0 => Width::Byte,
+        1 => Width::Half,
+        2 => Width::Word,
+        _ => return Err("always-false conditions are not supported".to_string()),
+    };
+    let cond = match op1 & 0x3800_0000 {
+        0x0000_0000 => None,
+        0x0800_0000 => Some(Cond::Eq),
+        0x1000_0000 => Some(Cond::Ne),
+        0x1800_0000 => Some(Cond::Lt),
+        0x2000_0000 => Some(Cond::Gt),
+        0x2800_0000 => Some(Cond::ULt),
+        0x3000_0000 => Some(Cond::UGt),
+        _ => Some(Cond::And),
+    };
+    let op = match (cond, op1 >> 30) {
+        (None, 0) => Op::write(addr, width, op2),
+        (None, 2) => Op::Add { addr, width, value: op2 & width.mask() },
+        // Hook/master code.
+        (None, 3) => return Ok(None),
+        (None, _) => return Err("pointer writes are not supported".to_string()),
+        (Some(cond), action) => {
+            let count = match action {
+                0 => 1,
+                1 => 2,
+                2 => usize::MAX,
+                _ => return Err("conditional disable codes are not supported".to_string()),
+            };
+            Op::test(addr, width, cond, op2, count)
+        }
+    };
+    Ok(Some(op))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encrypt(mut op1: u32, mut op2: u32, seeds: &[u32; 4]) -> (u32, u32) {
+        let mut sum = 0u32;
+        for _ in 0..32 {
+            sum = sum.wrapping_add(TEA_DELTA);
+            op1 = op1.wrapping_add(
+                (op2 << 4).wrapping_add(seeds[0]) ^ op2.wrapping_add(sum) ^ (op2 >> 5).wrapping_add(seeds[1]),
+            );
+            op2 = op2.wrapping_add(
+                (op1 << 4).wrapping_add(seeds[2]) ^ op1.wrapping_add(sum) ^ (op1 >> 5).wrapping_add(seeds[3]),
+            );
+        }
+        (op1, op2)
+    }
+
+    #[test]
+    fn decrypt_inverts_tea() {
+        for seeds in [&V1_SEEDS, &V3_SEEDS] {
+            let (e1, e2) = encrypt(0x1200_0010, 0x0000_BEEF, seeds);
+            assert_ne!((e1, e2), (0x1200_0010, 0x0000_BEEF));
+            assert_eq!(decrypt(e1, e2, seeds), (0x1200_0010, 0x0000_BEEF));
+        }
+    }
+
+    #[test]
+    fn encrypted_lines_decode_like_raw_ones() {
+        let (e1, e2) = encrypt(0x1200_0010, 0x0000_BEEF, &V1_SEEDS);
+        let mut decoder = Decoder::new(CheatFormat::GameSharkV1, true);
+        assert_eq!(decoder.decode(e1, e2), Ok(Some(Op::write(0x0200_0010, Width::Half, 0xBEEF))));
+    }
+
+    #[test]
+    fn v3_packs_region_and_operation() {
+        assert_eq!(decode_v3(0x0221_2345, 0x1234), Ok(Some(Op::write(0x0201_2345, Width::Half, 0x1234))));
+        assert_eq!(
+            decode_v3(0x4831_0000, 0x7F),
+            Ok(Some(Op::test(0x0301_0000, Width::Byte, Cond::Eq, 0x7F, 2)))
+        );
+        assert_eq!(decode_v3(0, 0), Ok(None));
+    }
+}
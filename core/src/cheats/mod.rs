@@ -0,0 +1,356 @@
+// Cheat devices. Codes from each device are decoded into a small common set
+// of operations that run once per frame, before the frame is emulated.
+
+use std::fmt;
+
+use crate::bus::{Bus, BusAccess};
+
+mod codebreaker;
+mod gameshark;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CheatFormat {
+    /// GameShark and Action Replay v1/v2: `XXXXXXXX YYYYYYYY`.
+    GameSharkV1,
+    /// GameShark v3 and Action Replay MAX: `XXXXXXXX YYYYYYYY`.
+    GameSharkV3,
+    /// CodeBreaker: `XXXXXXXX YYYY`.
+    CodeBreaker,
+}
+
+impl CheatFormat {
+    pub const ALL: [CheatFormat; 3] =
+        [CheatFormat::GameSharkV1, CheatFormat::GameSharkV3, CheatFormat::CodeBreaker];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CheatFormat::GameSharkV1 => "gsv1",
+            CheatFormat::GameSharkV3 => "gsv3",
+            CheatFormat::CodeBreaker => "codebreaker",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|f| f.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Digits in the value half of a code line.
+    fn value_digits(self) -> usize {
+        match self {
+            CheatFormat::CodeBreaker => 4,
+            _ => 8,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CheatError {
+    /// 1-based line of the code that failed to parse.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for CheatError {}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Width {
+    Byte,
+    Half,
+    Word,
+}
+
+impl Width {
+    fn mask(self) -> u32 {
+        match self {
+            Width::Byte => 0xFF,
+            Width::Half => 0xFFFF,
+            Width::Word => 0xFFFF_FFFF,
+        }
+    }
+
+    fn sign_extend(self, v: u32) -> i32 {
+        match self {
+            Width::Byte => v as u8 as i8 as i32,
+            Width::Half => v as u16 as i16 as i32,
+            Width::Word => v as i32,
+        }
+    }
+
+    fn load(self, bus: &mut Bus, addr: u32) -> u32 {
+        match self {
+            Width::Byte => bus.peek8(addr) as u32,
+            Width::Half => bus.peek16(addr) as u32,
+            Width::Word => bus.peek32(addr),
+        }
+    }
+
+    fn store(self, bus: &mut Bus, addr: u32, value: u32) {
+        match self {
+            Width::Byte => bus.poke8(addr, value as u8),
+            Width::Half => bus.poke16(addr, value as u16),
+            Width::Word => bus.poke32(addr, value),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Cond {
+    Eq,
+    Ne,
+    /// Signed comparisons.
+    Lt,
+    Gt,
+    ULt,
+    UGt,
+    /// Any of the value's bits set.
+    And,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Op {
+    Write { addr: u32, width: Width, value: u32 },
+    Add { addr: u32, width: Width, value: u32 },
+    Or16 { addr: u32, value: u16 },
+    And16 { addr: u32, value: u16 },
+    /// Runs the next `count` operations only when the condition holds.
+    If { addr: u32, width: Width, cond: Cond, value: u32, count: usize },
+    RomPatch { addr: u32, value: u16 },
+}
+
+impl Op {
+    fn write(addr: u32, width: Width, value: u32) -> Self {
+        Op::Write { addr, width, value: value & width.mask() }
+    }
+
+    fn test(addr: u32, width: Width, cond: Cond, value: u32, count: usize) -> Self {
+        Op::If { addr, width, cond, value: value & width.mask(), count }
+    }
+}
+
+/// One named code, possibly several lines long.
+#[derive(Clone, Debug)]
+pub struct Cheat {
+    pub name: String,
+    pub enabled: bool,
+    format: CheatFormat,
+    encrypted: bool,
+    code: String,
+    ops: Vec<Op>,
+    // ROM halfwords replaced while enabled, to put back when disabled.
+    patched: Vec<(u32, u16)>,
+}
+
+impl Cheat {
+    /// Parses `code`, one code per line. GameShark codes are decrypted when
+    /// `encrypted` is set, as they usually are when published.
+    pub fn parse(name: &str, format: CheatFormat, encrypted: bool, code: &str) -> Result<Self, CheatError> {
+        let mut ops = Vec::new();
+        let mut decoder = gameshark::Decoder::new(format, encrypted);
+        for (i, line) in code.lines().enumerate() {
+            let error = |message: String| CheatError { line: i + 1, message };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (op1, op2) = split_line(line, format.value_digits()).map_err(error)?;
+            let op = match format {
+                CheatFormat::CodeBreaker => codebreaker::decode(op1, op2 as u16),
+                _ => decoder.decode(op1, op2),
+            };
+            ops.extend(op.map_err(error)?);
+        }
+        Ok(Self {
+            name: name.to_string(),
+            enabled: true,
+            format,
+            encrypted,
+            code: code.to_string(),
+            ops,
+            patched: Vec::new(),
+        })
+    }
+
+    pub fn format(&self) -> CheatFormat { self.format }
+    pub fn encrypted(&self) -> bool { self.encrypted }
+    pub fn code(&self) -> &str { &self.code }
+
+    fn run(&mut self, bus: &mut Bus) {
+        let mut i = 0;
+        while i < self.ops.len() {
+            match self.ops[i] {
+                Op::Write { addr, width, value } => width.store(bus, addr, value),
+                Op::Add { addr, width, value } => {
+                    let v = width.load(bus, addr).wrapping_add(value);
+                    width.store(bus, addr, v);
+                }
+                Op::Or16 { addr, value } => {
+                    let v = bus.peek16(addr) | value;
+                    bus.poke16(addr, v);
+                }
+                Op::And16 { addr, value } => {
+                    let v = bus.peek16(addr) & value;
+                    bus.poke16(addr, v);
+                }
+                Op::If { addr, width, cond, value, count } => {
+                    let v = width.load(bus, addr);
+                    let (sv, sval) = (width.sign_extend(v), width.sign_extend(value));
+                    let holds = match cond {
+                        Cond::Eq => v == value,
+                        Cond::Ne => v != value,
+                        Cond::Lt => sv < sval,
+                        Cond::Gt => sv > sval,
+                        Cond::ULt => v < value,
+                        Cond::UGt => v > value,
+                        Cond::And => v & value != 0,
+                    };
+                    if !holds {
+                        i = i.saturating_add(count);
+                    }
+                }
+                Op::RomPatch { addr, value } => {
+                    if !self.patched.iter().any(|&(a, _)| a == addr)
+                        && let Some(old) = bus.patch_rom16(addr, value)
+                    {
+                        self.patched.push((addr, old));
+                    }
+                }
+            }
+            i = i.saturating_add(1);
+        }
+    }
+
+    fn unpatch(&mut self, bus: &mut Bus) {
+        for (addr, old) in self.patched.drain(..).rev() {
+            bus.patch_rom16(addr, old);
+        }
+    }
+}
+
+// Splits `XXXXXXXX YYYY[YYYY]`, with or without the space.
+fn split_line(line: &str, value_digits: usize) -> Result<(u32, u32), String> {
+    let digits: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.len() != 8 + value_digits || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("expected {} hex digits, got {:?}", 8 + value_digits, line));
+    }
+    let (op1, op2) = digits.split_at(8);
+    Ok((u32::from_str_radix(op1, 16).unwrap(), u32::from_str_radix(op2, 16).unwrap()))
+}
+
+/// The cheats attached to the running game.
+#[derive(Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+    // Patches of removed cheats, undone on the next frame.
+    removed: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn add(&mut self, cheat: Cheat) { self.cheats.push(cheat); }
+
+    pub fn remove(&mut self, index: usize) -> Cheat {
+        let cheat = self.cheats.remove(index);
+        self.removed.push(cheat.clone());
+        cheat
+    }
+
+    pub fn clear(&mut self) {
+        while !self.cheats.is_empty() {
+            self.remove(0);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Cheat> { self.cheats.iter() }
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Cheat> { self.cheats.get_mut(index) }
+    pub fn len(&self) -> usize { self.cheats.len() }
+    pub fn is_empty(&self) -> bool { self.cheats.is_empty() }
+
+    pub fn apply(&mut self, bus: &mut Bus) {
+        for mut cheat in self.removed.drain(..) {
+            cheat.unpatch(bus);
+        }
+        for cheat in &mut self.cheats {
+            if cheat.enabled {
+                cheat.run(bus);
+            } else {
+                cheat.unpatch(bus);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bus_with_rom() -> Bus {
+        let mut bus = Bus::new();
+        bus.mem.rom = vec![0x11; 0x200];
+        bus
+    }
+
+    #[test]
+    fn raw_gameshark_writes_every_frame() {
+        let mut bus = bus_with_rom();
+        let mut cheats = Cheats::new();
+        let code = "02001000 000000FF\n1300000C 00001234\n22000100 DEADBEEF";
+        cheats.add(Cheat::parse("hp", CheatFormat::GameSharkV1, false, code).unwrap());
+        cheats.apply(&mut bus);
+        assert_eq!(bus.mem.ewram[0x1000], 0xFF);
+        assert_eq!(bus.peek16(0x0300_000C), 0x1234);
+        assert_eq!(bus.peek32(0x0200_0100), 0xDEAD_BEEF);
+
+        bus.poke8(0x0200_1000, 3);
+        cheats.apply(&mut bus);
+        assert_eq!(bus.mem.ewram[0x1000], 0xFF);
+    }
+
+    #[test]
+    fn codebreaker_conditions_gate_the_next_line() {
+        let mut bus = bus_with_rom();
+        let mut cheats = Cheats::new();
+        let code = "72000000 0001\n82000010 BEEF\n32000020 0042";
+        cheats.add(Cheat::parse("cond", CheatFormat::CodeBreaker, false, code).unwrap());
+        cheats.apply(&mut bus);
+        assert_eq!(bus.peek16(0x0200_0010), 0);
+        assert_eq!(bus.peek8(0x0200_0020), 0x42);
+
+        bus.poke16(0x0200_0000, 1);
+        cheats.apply(&mut bus);
+        assert_eq!(bus.peek16(0x0200_0010), 0xBEEF);
+    }
+
+    #[test]
+    fn rom_patches_are_undone_when_disabled() {
+        let mut bus = bus_with_rom();
+        let mut cheats = Cheats::new();
+        // GameShark v1 ROM patch at 0x08000000 + (0x40 << 1).
+        cheats.add(Cheat::parse("patch", CheatFormat::GameSharkV1, false, "60000040 0000ABCD").unwrap());
+        cheats.apply(&mut bus);
+        assert_eq!(bus.peek16(0x0800_0080), 0xABCD);
+
+        cheats.get_mut(0).unwrap().enabled = false;
+        cheats.apply(&mut bus);
+        assert_eq!(bus.peek16(0x0800_0080), 0x1111);
+
+        cheats.get_mut(0).unwrap().enabled = true;
+        cheats.apply(&mut bus);
+        cheats.remove(0);
+        cheats.apply(&mut bus);
+        assert_eq!(bus.peek16(0x0800_0080), 0x1111);
+    }
+
+    #[test]
+    fn bad_lines_report_their_position() {
+        let err = Cheat::parse("x", CheatFormat::CodeBreaker, false, "82000010 BEEF\nnot a code").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert!(Cheat::parse("x", CheatFormat::CodeBreaker, false, "92000010 BEEF").is_err());
+    }
+}
@@ -0,0 +1,360 @@
+use crate::cart::BackupType;
+use crate::video::ColorProfile;
+use crate::Emulator;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+/// Where the RTC starts in deterministic mode: 2000-01-01 00:00:00 UTC.
+pub const DETERMINISTIC_EPOCH: u64 = 946_684_800;
+
+/// Frontend-facing emulation options, applied when a ROM is loaded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EmulatorConfig {
+    pub boot_mode: BootMode,
+    /// Before booting through the BIOS, writes the Nintendo logo and the
+    /// header checksum into ROMs the BIOS would refuse (see
+    /// `cart::header_problem`), so homebrew with a bad header starts instead
+    /// of hanging at the logo. The game then reads the fixed header back.
+    pub patch_header: bool,
+    pub idle_loop_skip: bool,
+    /// Where battery saves go; `None` keeps them next to the ROM.
+    pub save_dir: Option<PathBuf>,
+    pub color_profile: ColorProfile,
+    /// Backup chip to emulate regardless of the game database and the ROM.
+    pub backup: Option<BackupType>,
+    /// Forces the cartridge RTC on or off; `None` follows the game database.
+    pub rtc: Option<bool>,
+    pub rtc_clock: RtcClock,
+    /// Host output rate the APU resamples to, in Hz.
+    pub sample_rate: u32,
+    pub accuracy: Accuracy,
+    /// Charges gamepak accesses with sequential bursts and the prefetch
+    /// buffer. Off, every ROM access has a fixed cost: opcodes as if
+    /// prefetched, data as a nonsequential access.
+    pub cycle_timing: bool,
+    /// Reads from unmapped addresses return the last opcode fetched, as on
+    /// hardware, instead of 0.
+    pub open_bus: bool,
+    /// Logs every BIOS call (SWI) with its name and arguments, at info
+    /// level. The per-frame counts in `Cpu::swi_stats` are kept either way.
+    pub swi_log: bool,
+    /// Global `log` level to set when the config is applied; `None` leaves
+    /// it to the frontend.
+    pub log_level: Option<log::LevelFilter>,
+    /// Fixes every input the host would otherwise supply, so the same ROM,
+    /// BIOS and key presses give the same machine states on every run: the
+    /// RTC runs from `DETERMINISTIC_EPOCH` in emulated time whatever
+    /// `rtc_clock` says. Memory already powers on with fixed contents, and
+    /// host timings only feed `FrameReport`. Link cable peers and sensor
+    /// readings stay up to the frontend.
+    pub deterministic: bool,
+}
+
+impl Default for EmulatorConfig {
+    fn default() -> Self {
+        Self {
+            boot_mode: BootMode::default(),
+            patch_header: false,
+            idle_loop_skip: false,
+            save_dir: None,
+            color_profile: ColorProfile::default(),
+            backup: None,
+            rtc: None,
+            rtc_clock: RtcClock::default(),
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            accuracy: Accuracy::default(),
+            cycle_timing: true,
+            open_bus: false,
+            swi_log: false,
+            log_level: None,
+            deterministic: false,
+        }
+    }
+}
+
+impl EmulatorConfig {
+    pub fn new() -> Self { Self::default() }
+
+    /// The preset these options match, if any.
+    pub fn preset(&self) -> Option<AccuracyPreset> {
+        AccuracyPreset::ALL.into_iter().find(|preset| {
+            let mut config = self.clone();
+            preset.apply(&mut config);
+            config == *self
+        })
+    }
+
+    pub fn save_path(&self, rom_path: &Path) -> PathBuf {
+        let file = rom_path.with_extension("sav");
+        match (&self.save_dir, file.file_name()) {
+            (Some(dir), Some(name)) => dir.join(name),
+            _ => file,
+        }
+    }
+}
+
+/// Where the cartridge RTC gets the time from.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum RtcClock {
+    /// The host's wall clock.
+    #[default]
+    Host,
+    /// Starts at the given Unix time on reset and advances with emulated
+    /// time, so movies and netplay see the same clock on every run.
+    Emulated(u64),
+}
+
+/// How the machine starts on `load_rom` and `reset`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum BootMode {
+    /// Runs the loaded BIOS, which sets up the machine itself.
+    #[default]
+    Bios,
+    /// Jumps straight to the cartridge entry point with the registers and
+    /// memory the BIOS leaves behind. Always used without a BIOS.
+    SkipBios,
+}
+
+impl BootMode {
+    pub const ALL: [BootMode; 2] = [BootMode::Bios, BootMode::SkipBios];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BootMode::Bios => "bios",
+            BootMode::SkipBios => "skip-bios",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// Trade-off between faithfulness and speed.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Accuracy {
+    /// Draws the whole frame at the end of VBlank; mid-frame register and
+    /// VRAM changes show up only from the next frame on. Display timing is
+    /// approximated: HBlank (flag, IRQ and DMA) starts at cycle 960 and the
+    /// VBlank flag stays set through line 227.
+    #[default]
+    Fast,
+    /// Draws each scanline when it is reached, so raster effects (HBlank
+    /// scrolling, palette swaps) come out right, and follows hardware display
+    /// timing: HBlank starts at cycle 1006 and the VBlank flag clears on
+    /// line 227.
+    Accurate,
+}
+
+impl Accuracy {
+    pub const ALL: [Accuracy; 2] = [Accuracy::Fast, Accuracy::Accurate];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Accuracy::Fast => "fast",
+            Accuracy::Accurate => "accurate",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|a| a.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// Settings for every option that trades faithfulness for speed: `accuracy`,
+/// `cycle_timing`, `idle_loop_skip` and `open_bus`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AccuracyPreset {
+    /// Frame-at-once drawing, flat bus timing and idle loop skipping.
+    Speed,
+    /// Hardware bus timing and open bus on top of the fast renderer; what
+    /// most games need.
+    Balanced,
+    /// Everything as on hardware: per-scanline drawing and no idle loop
+    /// skipping.
+    Accuracy,
+}
+
+impl AccuracyPreset {
+    pub const ALL: [AccuracyPreset; 3] = [AccuracyPreset::Speed, AccuracyPreset::Balanced, AccuracyPreset::Accuracy];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AccuracyPreset::Speed => "speed",
+            AccuracyPreset::Balanced => "balanced",
+            AccuracyPreset::Accuracy => "accuracy",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Sets the options the preset covers, leaving the others alone.
+    pub fn apply(self, config: &mut EmulatorConfig) {
+        let (accuracy, cycle_timing, idle_loop_skip, open_bus) = match self {
+            AccuracyPreset::Speed => (Accuracy::Fast, false, true, false),
+            AccuracyPreset::Balanced => (Accuracy::Fast, true, true, true),
+            AccuracyPreset::Accuracy => (Accuracy::Accurate, true, false, true),
+        };
+        config.accuracy = accuracy;
+        config.cycle_timing = cycle_timing;
+        config.idle_loop_skip = idle_loop_skip;
+        config.open_bus = open_bus;
+    }
+}
+
+/// Builds an `Emulator` with its options set up front, e.g.
+/// `Emulator::builder().boot_mode(BootMode::SkipBios).accuracy(Accuracy::Accurate).build()`.
+#[derive(Clone, Debug, Default)]
+pub struct EmulatorBuilder {
+    config: EmulatorConfig,
+}
+
+impl EmulatorBuilder {
+    pub fn new() -> Self { Self::default() }
+
+    /// Starts from a complete config, e.g. one a frontend loaded.
+    pub fn config(mut self, config: EmulatorConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn boot_mode(mut self, mode: BootMode) -> Self {
+        self.config.boot_mode = mode;
+        self
+    }
+
+    pub fn patch_header(mut self, enabled: bool) -> Self {
+        self.config.patch_header = enabled;
+        self
+    }
+
+    pub fn idle_loop_skip(mut self, enabled: bool) -> Self {
+        self.config.idle_loop_skip = enabled;
+        self
+    }
+
+    pub fn save_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.save_dir = Some(dir.into());
+        self
+    }
+
+    pub fn color_profile(mut self, profile: ColorProfile) -> Self {
+        self.config.color_profile = profile;
+        self
+    }
+
+    pub fn backup_type(mut self, backup: BackupType) -> Self {
+        self.config.backup = Some(backup);
+        self
+    }
+
+    pub fn rtc(mut self, enabled: bool) -> Self {
+        self.config.rtc = Some(enabled);
+        self
+    }
+
+    pub fn rtc_clock(mut self, clock: RtcClock) -> Self {
+        self.config.rtc_clock = clock;
+        self
+    }
+
+    pub fn sample_rate(mut self, hz: u32) -> Self {
+        self.config.sample_rate = hz;
+        self
+    }
+
+    pub fn accuracy(mut self, accuracy: Accuracy) -> Self {
+        self.config.accuracy = accuracy;
+        self
+    }
+
+    pub fn cycle_timing(mut self, enabled: bool) -> Self {
+        self.config.cycle_timing = enabled;
+        self
+    }
+
+    pub fn open_bus(mut self, enabled: bool) -> Self {
+        self.config.open_bus = enabled;
+        self
+    }
+
+    pub fn preset(mut self, preset: AccuracyPreset) -> Self {
+        preset.apply(&mut self.config);
+        self
+    }
+
+    pub fn swi_log(mut self, enabled: bool) -> Self {
+        self.config.swi_log = enabled;
+        self
+    }
+
+    pub fn log_level(mut self, level: log::LevelFilter) -> Self {
+        self.config.log_level = Some(level);
+        self
+    }
+
+    pub fn deterministic(mut self, enabled: bool) -> Self {
+        self.config.deterministic = enabled;
+        self
+    }
+
+    pub fn build(self) -> Emulator { Emulator::with_config(self.config) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_path_honors_save_dir() {
+        let mut config = EmulatorConfig::new();
+        let rom = Path::new("/games/advance/zelda.gba");
+        assert_eq!(config.save_path(rom), PathBuf::from("/games/advance/zelda.sav"));
+        config.save_dir = Some(PathBuf::from("/saves"));
+        assert_eq!(config.save_path(rom), PathBuf::from("/saves/zelda.sav"));
+    }
+
+    #[test]
+    fn builder_sets_options() {
+        let emu = EmulatorBuilder::new()
+            .boot_mode(BootMode::SkipBios)
+            .backup_type(BackupType::Flash128K)
+            .rtc(false)
+            .rtc_clock(RtcClock::Emulated(1_000_000_000))
+            .sample_rate(32_768)
+            .accuracy(Accuracy::Accurate)
+            .build();
+        let config = emu.config();
+        assert_eq!(config.boot_mode, BootMode::SkipBios);
+        assert_eq!(config.backup, Some(BackupType::Flash128K));
+        assert_eq!(config.rtc, Some(false));
+        assert_eq!(config.accuracy, Accuracy::Accurate);
+        assert_eq!(emu.rtc_time(), 1_000_000_000);
+        assert_eq!(EmulatorConfig::new().sample_rate, DEFAULT_SAMPLE_RATE);
+        assert_eq!(Accuracy::from_name("Accurate"), Some(Accuracy::Accurate));
+        assert_eq!(BootMode::from_name("skip-bios"), Some(BootMode::SkipBios));
+    }
+
+    #[test]
+    fn presets_set_and_recognize_their_options() {
+        assert_eq!(EmulatorConfig::new().preset(), None);
+        for preset in AccuracyPreset::ALL {
+            let config = EmulatorBuilder::new().sample_rate(32_768).preset(preset).build().config().clone();
+            assert_eq!(config.preset(), Some(preset));
+            assert_eq!(config.sample_rate, 32_768);
+            assert_eq!(AccuracyPreset::from_name(preset.name()), Some(preset));
+        }
+        let speed = EmulatorBuilder::new().preset(AccuracyPreset::Speed).build();
+        assert!(!speed.config().cycle_timing && speed.idle_loop_skip());
+        let custom = EmulatorBuilder::new().preset(AccuracyPreset::Accuracy).idle_loop_skip(true).build();
+        assert_eq!(custom.config().preset(), None);
+    }
+
+    #[test]
+    fn deterministic_mode_overrides_the_host_clock() {
+        let emu = EmulatorBuilder::new().rtc_clock(RtcClock::Host).deterministic(true).build();
+        assert_eq!(emu.rtc_time(), DETERMINISTIC_EPOCH);
+    }
+}
@@ -0,0 +1,183 @@
+// ARM instruction classes, as in the GBATEK opcode table. The class depends
+// only on bits 27-20 and 7-4, so decoding is a lookup in a 4096-entry table
+// built at compile time. Encodings ARMv4T leaves undefined (the ARMv5 DSP
+// and CLZ space, multiplies with bits 23-22 = 01, bit 4 set in the register
+// form of LDR/STR) are `Undefined`; the GBA has no coprocessors, so the
+// coprocessor space traps the same way.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ArmFormat {
+    /// AND..MVN, immediate or shifted register operand
+    DataProcessing,
+    /// MRS, MSR
+    PsrTransfer,
+    /// MUL, MLA
+    Multiply,
+    /// UMULL, UMLAL, SMULL, SMLAL
+    MultiplyLong,
+    /// SWP, SWPB
+    Swap,
+    /// BX
+    BranchExchange,
+    /// STRH, LDRH, LDRSB, LDRSH, register or immediate offset
+    HalfwordTransfer,
+    /// STR, LDR, STRB, LDRB
+    SingleDataTransfer,
+    /// STM, LDM
+    BlockTransfer,
+    /// B, BL
+    Branch,
+    /// CDP, MRC, MCR, LDC, STC
+    Coprocessor,
+    /// SWI
+    SoftwareInterrupt,
+    Undefined,
+}
+
+static DECODE_TABLE: [ArmFormat; 4096] = build_table();
+
+const fn build_table() -> [ArmFormat; 4096] {
+    let mut table = [ArmFormat::Undefined; 4096];
+    let mut index = 0;
+    while index < table.len() {
+        table[index] = classify(index as u32);
+        index += 1;
+    }
+    table
+}
+
+// `index` holds bits 27-20 of the instruction above bits 7-4.
+const fn classify(index: u32) -> ArmFormat {
+    use ArmFormat::*;
+    let op = index >> 4;
+    let low = index & 0xF;
+    // TST/TEQ/CMP/CMN without S, either operand form: the PSR transfer and
+    // miscellaneous space.
+    let misc = op & 0xD9 == 0x10;
+    match op >> 5 {
+        0b000 => match low {
+            0b1001 => match op >> 3 {
+                0b00000 if op & 0x04 == 0 => Multiply,
+                0b00001 => MultiplyLong,
+                0b00010 if op & 0x03 == 0 => Swap,
+                _ => Undefined,
+            },
+            0b1011 | 0b1101 | 0b1111 => HalfwordTransfer,
+            0b0000 if misc => PsrTransfer,
+            0b0001 if op == 0x12 => BranchExchange,
+            _ if misc => Undefined,
+            _ => DataProcessing,
+        },
+        0b001 if misc && op & 0x02 != 0 => PsrTransfer,
+        0b001 if misc => Undefined,
+        0b001 => DataProcessing,
+        0b010 => SingleDataTransfer,
+        0b011 if low & 1 != 0 => Undefined,
+        0b011 => SingleDataTransfer,
+        0b100 => BlockTransfer,
+        0b101 => Branch,
+        0b110 => Coprocessor,
+        _ if op & 0x10 == 0 => Coprocessor,
+        _ => SoftwareInterrupt,
+    }
+}
+
+impl ArmFormat {
+    pub const ALL: [ArmFormat; 13] = [
+        ArmFormat::DataProcessing,
+        ArmFormat::PsrTransfer,
+        ArmFormat::Multiply,
+        ArmFormat::MultiplyLong,
+        ArmFormat::Swap,
+        ArmFormat::BranchExchange,
+        ArmFormat::HalfwordTransfer,
+        ArmFormat::SingleDataTransfer,
+        ArmFormat::BlockTransfer,
+        ArmFormat::Branch,
+        ArmFormat::Coprocessor,
+        ArmFormat::SoftwareInterrupt,
+        ArmFormat::Undefined,
+    ];
+
+    pub fn decode(instr: u32) -> ArmFormat {
+        DECODE_TABLE[(((instr >> 16) & 0xFF0) | ((instr >> 4) & 0xF)) as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ArmFormat::*;
+
+    // (mask, value) patterns over the table index (bits 27-20, then 7-4),
+    // transcribed from the GBATEK opcode table.
+    fn patterns() -> Vec<(u32, u32, ArmFormat)> {
+        let mut p = vec![
+            (0xFCF, 0x009, Multiply),
+            (0xFCF, 0x049, Undefined),
+            (0xF8F, 0x089, MultiplyLong),
+            (0xFBF, 0x109, Swap),
+            (0xF9F, 0x119, Undefined),
+            (0xFBF, 0x129, Undefined),
+            (0xF8F, 0x189, Undefined),
+            (0xE0F, 0x00B, HalfwordTransfer),
+            (0xE0F, 0x00D, HalfwordTransfer),
+            (0xE0F, 0x00F, HalfwordTransfer),
+            (0xFBF, 0x100, PsrTransfer),
+            (0xFBF, 0x120, PsrTransfer),
+            (0xFFF, 0x121, BranchExchange),
+            (0xFFF, 0x101, Undefined),
+            (0xFFF, 0x141, Undefined),
+            (0xFFF, 0x161, Undefined),
+            (0xFB0, 0x320, PsrTransfer),
+            (0xFB0, 0x300, Undefined),
+            (0xE10, 0x210, DataProcessing),
+            (0xE11, 0x010, DataProcessing),
+            (0xE19, 0x011, DataProcessing),
+            (0xE00, 0x400, SingleDataTransfer),
+            (0xE01, 0x600, SingleDataTransfer),
+            (0xE01, 0x601, Undefined),
+            (0xE00, 0x800, BlockTransfer),
+            (0xE00, 0xA00, Branch),
+            (0xE00, 0xC00, Coprocessor),
+            (0xF00, 0xE00, Coprocessor),
+            (0xF00, 0xF00, SoftwareInterrupt),
+        ];
+        // Without S, only TST..CMN (bits 24-23 = 10) leave data processing.
+        for ops in [0x00, 0x08, 0x18] {
+            p.push((0xF90, 0x200 | ops << 4, DataProcessing));
+            p.push((0xF91, ops << 4, DataProcessing));
+            p.push((0xF99, 0x001 | ops << 4, DataProcessing));
+        }
+        for low in [0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0x8, 0xA, 0xC, 0xE] {
+            p.push((0xF9F, 0x100 | low, Undefined));
+        }
+        p
+    }
+
+    #[test]
+    fn every_index_has_exactly_one_class() {
+        let patterns = patterns();
+        for index in 0..4096u32 {
+            let matches: Vec<_> = patterns.iter().filter(|&&(mask, value, _)| index & mask == value).collect();
+            assert_eq!(matches.len(), 1, "{:03X} matches {:?}", index, matches);
+            let instr = (index & 0xFF0) << 16 | (index & 0xF) << 4;
+            assert_eq!(ArmFormat::decode(instr), matches[0].2, "{:03X}", index);
+        }
+    }
+
+    #[test]
+    fn decodes_previously_misrouted_opcodes() {
+        assert_eq!(ArmFormat::decode(0xE19100B2), HalfwordTransfer); // ldrh r0, [r1, r2]
+        assert_eq!(ArmFormat::decode(0xE10100B2), HalfwordTransfer); // strh r0, [r1, -r2]
+        assert_eq!(ArmFormat::decode(0xE0010293), Multiply); // mul r1, r3, r2
+        assert_eq!(ArmFormat::decode(0xE0110293), Multiply); // muls r1, r3, r2
+        assert_eq!(ArmFormat::decode(0xE12FFF1E), BranchExchange); // bx lr
+        assert_eq!(ArmFormat::decode(0xE10F0000), PsrTransfer); // mrs r0, cpsr
+        assert_eq!(ArmFormat::decode(0xE329F01F), PsrTransfer); // msr cpsr_fc, #0x1F
+        assert_eq!(ArmFormat::decode(0xE1500001), DataProcessing); // cmp r0, r1
+        assert_eq!(ArmFormat::decode(0xE7F000F0), Undefined);
+        assert_eq!(ArmFormat::decode(0xEE010F10), Coprocessor); // mcr p15, ...
+        assert_eq!(ArmFormat::decode(0xEF000005), SoftwareInterrupt);
+    }
+}
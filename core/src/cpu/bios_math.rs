@@ -0,0 +1,158 @@
+// The BIOS math functions (Div, DivArm, Sqrt, ArcTan and ArcTan2) for the
+// HLE SWI handlers, computed the way the BIOS code does so that games get the
+// same bits back, rounding included: ArcTan is a fixed-point polynomial
+// truncated at each step, and ArcTan2 divides with truncation before it.
+
+/// Div: `num / den`, giving r0 (quotient), r1 (remainder) and r3 (absolute
+/// quotient). The BIOS hangs dividing by zero unless `num` is -1, 0 or 1,
+/// when it returns the sign of `num` (1 for 0), `num` and 1; that result is
+/// used for every `num` rather than hanging.
+pub fn div(num: i32, den: i32) -> [u32; 3] {
+    if den == 0 {
+        return [if num < 0 { -1i32 as u32 } else { 1 }, num as u32, 1];
+    }
+    let quotient = num.wrapping_div(den);
+    [quotient as u32, num.wrapping_rem(den) as u32, quotient.unsigned_abs()]
+}
+
+/// Sqrt: the integer square root of `x`, rounded down.
+pub fn sqrt(x: u32) -> u32 {
+    let mut x = x;
+    let mut result = 0u32;
+    let mut one = 1u32 << 30;
+    while one > x {
+        one >>= 2;
+    }
+    while one != 0 {
+        if x >= result + one {
+            x -= result + one;
+            result = (result >> 1) + one;
+        } else {
+            result >>= 1;
+        }
+        one >>= 2;
+    }
+    result
+}
+
+/// ArcTan of a 1.14 fixed-point tangent, -0x4000..=0x4000 for -pi/2..=pi/2,
+/// giving r0 and the polynomial's terms the BIOS leaves in r1 and r3.
+pub fn arctan(tan: i32) -> [u32; 3] {
+    let a = -(tan.wrapping_mul(tan) >> 14);
+    let mut b = (0xA9i32.wrapping_mul(a) >> 14) + 0x390;
+    for c in [0x91C, 0xFB6, 0x16AA, 0x2081, 0x3651, 0xA2F9] {
+        b = (b.wrapping_mul(a) >> 14).wrapping_add(c);
+    }
+    [(tan.wrapping_mul(b) >> 16) as u32, a as u32, b as u32]
+}
+
+/// ArcTan2 of the point (`x`, `y`) in 1.14 fixed point: the angle,
+/// 0..=0xFFFF for a full turn counterclockwise from the positive x axis,
+/// then r1 and r3 as the BIOS leaves them. r1 is the ArcTan term, or still
+/// `y` when the point is on an axis; r3 always ends up 0x170 (as in mGBA).
+pub fn arctan2(x: i32, y: i32) -> [u32; 3] {
+    const R3: u32 = 0x170;
+    let atan = |num: i32, den: i32| arctan(div(num.wrapping_shl(14), den)[0] as i32);
+    if y == 0 {
+        return [if x >= 0 { 0 } else { 0x8000 }, 0, R3];
+    }
+    if x == 0 {
+        return [if y >= 0 { 0x4000 } else { 0xC000 }, y as u32, R3];
+    }
+    let (angle, r1) = if y >= 0 {
+        if x >= y {
+            let [a, r1, _] = atan(y, x);
+            (a, r1)
+        } else if x < 0 && -x >= y {
+            let [a, r1, _] = atan(y, x);
+            (a.wrapping_add(0x8000), r1)
+        } else {
+            let [a, r1, _] = atan(x, y);
+            (0x4000u32.wrapping_sub(a), r1)
+        }
+    } else if x <= 0 && -x > -y {
+        let [a, r1, _] = atan(y, x);
+        (a.wrapping_add(0x8000), r1)
+    } else if x > 0 && x >= -y {
+        let [a, r1, _] = atan(y, x);
+        (a.wrapping_add(0x1_0000), r1)
+    } else {
+        let [a, r1, _] = atan(x, y);
+        (0xC000u32.wrapping_sub(a), r1)
+    };
+    [angle & 0xFFFF, r1, R3]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn div_matches_the_bios() {
+        let cases: [(i32, i32, [u32; 3]); 7] = [
+            (7, 2, [3, 1, 3]),
+            (-7, 2, [-3i32 as u32, -1i32 as u32, 3]),
+            (7, -2, [-3i32 as u32, 1, 3]),
+            (-7, -2, [3, -1i32 as u32, 3]),
+            (i32::MIN, -1, [0x8000_0000, 0, 0x8000_0000]),
+            (5, 0, [1, 5, 1]),
+            (-1, 0, [-1i32 as u32, -1i32 as u32, 1]),
+        ];
+        for (num, den, expected) in cases {
+            assert_eq!(div(num, den), expected, "{} / {}", num, den);
+        }
+    }
+
+    #[test]
+    fn sqrt_rounds_down() {
+        for (x, expected) in [(0, 0), (1, 1), (3, 1), (4, 2), (99, 9), (0x4000_0000, 0x8000), (u32::MAX, 0xFFFF)] {
+            assert_eq!(sqrt(x), expected, "sqrt({})", x);
+        }
+    }
+
+    #[test]
+    fn arctan_follows_the_bios_polynomial() {
+        // (tangent, r0, r1, r3)
+        let cases: [(i32, u32, u32, u32); 7] = [
+            (0, 0, 0, 0xA2F9),
+            // Too small to move the truncated result.
+            (1, 0, 0, 0xA2F9),
+            (0x1000, 0x09FB, 0xFFFF_FC00, 0x9FB3),
+            (0x2000, 0x12E4, 0xFFFF_F000, 0x9720),
+            (0x4000, 0x2000, 0xFFFF_C000, 0x8000),
+            (0x3FFF, 0x1FFF, 0xFFFF_C002, 0x8001),
+            (-0x2000, 0xFFFF_ED1C, 0xFFFF_F000, 0x9720),
+        ];
+        for (tan, r0, r1, r3) in cases {
+            assert_eq!(arctan(tan), [r0, r1, r3], "ArcTan({:#X})", tan);
+        }
+    }
+
+    #[test]
+    fn arctan2_covers_each_octant_with_bios_rounding() {
+        // (x, y, r0, r1); r3 is always 0x170.
+        let cases: [(i32, i32, u32, u32); 16] = [
+            (0, 0, 0, 0),
+            (0x4000, 0, 0, 0),
+            (0, 0x4000, 0x4000, 0x4000),
+            (-0x4000, 0, 0x8000, 0),
+            (0, -0x4000, 0xC000, 0xFFFF_C000),
+            (0x4000, 0x4000, 0x2000, 0xFFFF_C000),
+            (-0x4000, 0x4000, 0x6000, 0xFFFF_C000),
+            (-0x4000, -0x4000, 0xA000, 0xFFFF_C000),
+            (0x4000, -0x4000, 0xE000, 0xFFFF_C000),
+            (0x4000, 0x2000, 0x12E4, 0xFFFF_F000),
+            // 0x32E4 exactly; the BIOS rounds the other way.
+            (0x1000, 0x3000, 0x32E5, 0xFFFF_F8E4),
+            (-0x3000, 0x1000, 0x72E4, 0xFFFF_F8E4),
+            (-0x1000, -0x3000, 0xB2E5, 0xFFFF_F8E4),
+            (0x3000, -0x1000, 0xF2E4, 0xFFFF_F8E4),
+            // Dividing first truncates 1/100 of a quarter turn to 0x67, not 0x68.
+            (100, 1, 0x67, 0xFFFF_FFFF),
+            (-100, -1, 0x8067, 0xFFFF_FFFF),
+        ];
+        for (x, y, r0, r1) in cases {
+            assert_eq!(arctan2(x, y), [r0, r1, 0x170], "ArcTan2({:#X}, {:#X})", x, y);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+// Instruction class coverage: how often each ARM and Thumb format ran, and a
+// few of the addresses it ran at. Used to find out which encodings a game
+// library actually depends on. Recording is off unless a `Coverage` is
+// installed with `Cpu::set_coverage`.
+
+use super::{ArmFormat, ThumbFormat};
+
+/// Distinct example PCs kept per format.
+pub const EXAMPLE_PCS: usize = 4;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FormatStats {
+    pub count: u64,
+    /// The first distinct addresses the format ran at, with the opcode.
+    pub examples: Vec<(u32, u32)>,
+}
+
+impl FormatStats {
+    fn record(&mut self, pc: u32, instr: u32) {
+        self.count += 1;
+        if self.examples.len() < EXAMPLE_PCS && !self.examples.iter().any(|&(p, _)| p == pc) {
+            self.examples.push((pc, instr));
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Coverage {
+    arm: [FormatStats; ArmFormat::ALL.len()],
+    thumb: [FormatStats; ThumbFormat::ALL.len()],
+}
+
+impl Coverage {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn arm(&self, format: ArmFormat) -> &FormatStats { &self.arm[arm_index(format)] }
+    pub fn thumb(&self, format: ThumbFormat) -> &FormatStats { &self.thumb[thumb_index(format)] }
+
+    /// Instructions recorded in either state.
+    pub fn total(&self) -> u64 { self.arm.iter().chain(&self.thumb).map(|s| s.count).sum() }
+
+    pub(crate) fn record_arm(&mut self, pc: u32, instr: u32) {
+        self.arm[arm_index(ArmFormat::decode(instr))].record(pc, instr);
+    }
+
+    pub(crate) fn record_thumb(&mut self, pc: u32, instr: u16) {
+        self.thumb[thumb_index(ThumbFormat::decode(instr))].record(pc, instr as u32);
+    }
+}
+
+fn arm_index(format: ArmFormat) -> usize { ArmFormat::ALL.iter().position(|&f| f == format).unwrap_or(0) }
+fn thumb_index(format: ThumbFormat) -> usize { ThumbFormat::ALL.iter().position(|&f| f == format).unwrap_or(0) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_formats_and_keeps_distinct_examples() {
+        let mut coverage = Coverage::new();
+        for _ in 0..3 {
+            coverage.record_arm(0x0800_0000, 0xE280_0001);
+        }
+        for i in 0..6 {
+            coverage.record_thumb(0x0800_0100 + i * 2, 0xDF05);
+        }
+        coverage.record_arm(0x0800_0004, 0xEAFF_FFFE);
+
+        let add = coverage.arm(ArmFormat::DataProcessing);
+        assert_eq!(add.count, 3);
+        assert_eq!(add.examples, vec![(0x0800_0000, 0xE280_0001)]);
+        let swi = coverage.thumb(ThumbFormat::SoftwareInterrupt);
+        assert_eq!(swi.count, 6);
+        assert_eq!(swi.examples.len(), EXAMPLE_PCS);
+        assert_eq!(swi.examples[3], (0x0800_0106, 0xDF05));
+        assert_eq!(coverage.arm(ArmFormat::Branch).count, 1);
+        assert_eq!(coverage.total(), 10);
+    }
+}
@@ -1,5 +1,29 @@
 use std::fmt;
 use crate::bus::BusAccess;
+use crate::guest_log;
+use crate::state::impl_savestate;
+
+pub mod arm;
+mod bios_math;
+pub mod coverage;
+pub mod swi;
+pub mod thumb;
+pub mod trace;
+
+pub use arm::ArmFormat;
+pub use coverage::Coverage;
+pub use swi::{swi_name, SwiStats};
+pub use thumb::ThumbFormat;
+pub use trace::{Trace, TraceEntry, TRACE_LEN};
+
+// BIOS work area in IWRAM, used by the interrupt path when no BIOS is loaded.
+const BIOS_IRQ_HANDLER: u32 = 0x0300_7FFC;
+const BIOS_IF: u32 = 0x0300_7FF8;
+// Where the BIOS IRQ dispatcher's epilogue sits. The HLE dispatcher passes it
+// to the user handler as the return address and finishes the IRQ there.
+const HLE_IRQ_RETURN: u32 = 0x0000_0138;
+const IME: u32 = 0x0400_0208;
+const HALTCNT: u32 = 0x0400_0301;
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum CpuState { Arm, Thumb }
@@ -173,8 +197,23 @@ pub struct Cpu {
     arm_pipe: ArmPipeline,
     thumb_pipe: ThumbPipeline,
     swi_hle: bool,
+    // Halted inside an HLE IntrWait; old flags have already been discarded.
+    intr_wait: bool,
+    // Host-side instrumentation; not part of savestates.
+    coverage: Option<Box<Coverage>>,
+    trace: Option<Box<Trace>>,
+    // The last undefined instruction executed, until taken.
+    undefined: Option<TraceEntry>,
+    swi_stats: Box<SwiStats>,
+    swi_log: bool,
 }
 
+impl_savestate!(Cpsr { 0 });
+impl_savestate!(BankedRegs { r8_fiq, r8_shared, r13_banked, r14_banked, spsr_banked });
+impl_savestate!(ArmPipeline { fetch, decode, valid });
+impl_savestate!(ThumbPipeline { fetch, decode, valid });
+impl_savestate!(Cpu { regs, cpsr, banked, arm_pipe, thumb_pipe, swi_hle, intr_wait });
+
 impl Default for Cpu {
     fn default() -> Self {
         let mut cpu = Self {
@@ -184,6 +223,12 @@ impl Default for Cpu {
             arm_pipe: ArmPipeline::default(),
             thumb_pipe: ThumbPipeline::default(),
             swi_hle: false,
+            intr_wait: false,
+            coverage: None,
+            trace: None,
+            undefined: None,
+            swi_stats: Box::default(),
+            swi_log: false,
         };
         cpu.cpsr.set_mode(CpuMode::System);
         cpu.banked.r8_shared.copy_from_slice(&cpu.regs[8..=12]);
@@ -204,6 +249,33 @@ impl Cpu {
 
     pub fn set_swi_hle(&mut self, enabled: bool) { self.swi_hle = enabled; }
 
+    /// Records which instruction formats run into `coverage` from now on.
+    pub fn set_coverage(&mut self, coverage: Coverage) { self.coverage = Some(Box::new(coverage)); }
+    pub fn coverage(&self) -> Option<&Coverage> { self.coverage.as_deref() }
+    /// Stops recording and returns what was recorded.
+    pub fn take_coverage(&mut self) -> Option<Coverage> { self.coverage.take().map(|c| *c) }
+
+    /// Records the instructions run into `trace` from now on.
+    pub fn set_trace(&mut self, trace: Trace) { self.trace = Some(Box::new(trace)); }
+    pub fn trace(&self) -> Option<&Trace> { self.trace.as_deref() }
+    pub fn take_trace(&mut self) -> Option<Trace> { self.trace.take().map(|t| *t) }
+
+    /// The last undefined instruction run since the previous call. The CPU
+    /// takes the Undefined exception as hardware does; games only get there
+    /// by crashing, or when the emulator lacks an instruction.
+    pub fn take_undefined(&mut self) -> Option<TraceEntry> { self.undefined.take() }
+
+    fn note_undefined(&mut self, pc: u32, opcode: u32, thumb: bool) {
+        let entry = TraceEntry { pc, opcode, thumb };
+        log::warn!("Undefined instruction {}", entry);
+        self.undefined = Some(entry);
+    }
+
+    /// Logs every SWI from now on, with its name and r0-r3.
+    pub fn set_swi_log(&mut self, enabled: bool) { self.swi_log = enabled; }
+    pub fn swi_stats(&self) -> &SwiStats { &self.swi_stats }
+    pub fn swi_stats_mut(&mut self) -> &mut SwiStats { &mut self.swi_stats }
+
     pub fn mode(&self) -> CpuMode { self.cpsr.mode() }
     pub fn state(&self) -> CpuState { self.cpsr.state() }
     pub fn set_state(&mut self, state: CpuState) {
@@ -227,8 +299,9 @@ impl Cpu {
         let lr_offset: u32 = match exception {
             Exception::Reset => 0,
             Exception::Swi | Exception::Undefined => 0,
-            Exception::PrefetchAbort | Exception::Irq | Exception::Fiq => 0,
-            Exception::DataAbort => 4,
+            Exception::PrefetchAbort => 0,
+            // Taken between instructions: handlers return with `subs pc, lr, #4`.
+            Exception::Irq | Exception::Fiq | Exception::DataAbort => 4,
         };
         let return_addr = self.pc().wrapping_add(lr_offset);
 
@@ -255,13 +328,91 @@ impl Cpu {
         );
     }
 
-    pub fn trigger_irq<B: BusAccess>(&mut self, bus: &mut B) {
-        if !self.cpsr.i() {
-            self.enter_exception(bus, Exception::Irq);
+    /// Takes the IRQ exception unless IRQs are masked in the CPSR. Returns
+    /// whether it was taken.
+    pub fn trigger_irq<B: BusAccess>(&mut self, bus: &mut B) -> bool {
+        if self.cpsr.i() {
+            return false;
+        }
+        self.enter_exception(bus, Exception::Irq);
+        if self.swi_hle {
+            self.hle_irq_entry(bus);
+        }
+        true
+    }
+
+    // What the BIOS does at 0x18: save the scratch registers on the IRQ stack
+    // and call the user handler with r0 = 0x04000000.
+    fn hle_irq_entry<B: BusAccess>(&mut self, bus: &mut B) {
+        let sp = self.regs[13].wrapping_sub(24);
+        for (i, &reg) in [0, 1, 2, 3, 12, 14].iter().enumerate() {
+            bus.write32(sp.wrapping_add(i as u32 * 4), self.regs[reg]);
         }
+        self.regs[13] = sp;
+        self.regs[0] = 0x0400_0000;
+        self.regs[14] = HLE_IRQ_RETURN;
+        self.regs[15] = bus.read32(BIOS_IRQ_HANDLER) & !3;
+        self.flush_pipeline(bus);
+    }
+
+    // The dispatcher's epilogue: `ldmfd sp!, {r0-r3, r12, lr}` and
+    // `subs pc, lr, #4`.
+    fn hle_irq_return<B: BusAccess>(&mut self, bus: &mut B) {
+        let sp = self.regs[13];
+        for (i, &reg) in [0, 1, 2, 3, 12, 14].iter().enumerate() {
+            self.regs[reg] = bus.read32(sp.wrapping_add(i as u32 * 4));
+        }
+        self.regs[13] = sp.wrapping_add(24);
+        let target = self.regs[14].wrapping_sub(4);
+        let spsr = self.spsr().unwrap_or(self.cpsr.raw());
+        self.set_mode(CpuMode::from_bits(spsr));
+        self.cpsr.set_raw(spsr);
+        self.regs[15] = target;
+        self.flush_pipeline(bus);
+    }
+
+    // IntrWait: r0 != 0 discards flags already raised, r1 selects the
+    // interrupts to wait for. The game's handler ORs the IF bits it serviced
+    // into the BIOS flags at 0x03007FF8. The SWI halts and reruns after each
+    // interrupt until one of the flags shows up.
+    fn hle_intr_wait<B: BusAccess>(&mut self, bus: &mut B, swi_len: u32) {
+        let wanted = self.regs[1] as u16;
+        let flags = bus.read16(BIOS_IF);
+        if self.regs[0] != 0 && !self.intr_wait {
+            bus.write16(BIOS_IF, flags & !wanted);
+        } else if flags & wanted != 0 {
+            bus.write16(BIOS_IF, flags & !wanted);
+            self.intr_wait = false;
+            return;
+        }
+        self.intr_wait = true;
+        bus.write16(IME, 1);
+        bus.write8(HALTCNT, 0);
+        self.regs[15] = self.regs[15].wrapping_sub(swi_len);
+        self.flush_pipeline(bus);
     }
 
     fn handle_swi<B: BusAccess>(&mut self, bus: &mut B, swi_num: u8) {
+        // Debug print (VBA, no$gba): r0 points to a NUL-terminated string.
+        if swi_num == 0xFF {
+            let bytes: Vec<u8> = (0..256).map(|i| bus.peek8(self.regs[0].wrapping_add(i))).collect();
+            guest_log::print(log::Level::Info, &guest_log::c_string(&bytes));
+            return;
+        }
+        self.swi_stats.record(swi_num);
+        if self.swi_log {
+            let len = if self.state() == CpuState::Thumb { 2 } else { 4 };
+            log::info!(
+                "SWI {:#04X} {} r0={:08X} r1={:08X} r2={:08X} r3={:08X} at {:08X}",
+                swi_num,
+                swi_name(swi_num).unwrap_or("(invalid)"),
+                self.regs[0],
+                self.regs[1],
+                self.regs[2],
+                self.regs[3],
+                self.regs[15].wrapping_sub(len)
+            );
+        }
         if self.swi_hle {
             self.handle_swi_hle(bus, swi_num);
         } else {
@@ -270,71 +421,39 @@ impl Cpu {
     }
 
     fn handle_swi_hle<B: BusAccess>(&mut self, bus: &mut B, swi_num: u8) {
-        log::trace!("SWI HLE #{:#04x} r0={:#x} r1={:#x} r2={:#x}",
-            swi_num, self.regs[0], self.regs[1], self.regs[2]);
-
         match swi_num {
-            0x00 => { /* SoftReset - skip for test ROMs */ }
+            0x00 => self.soft_reset(bus),
             0x01 => { /* RegisterRamReset - skip */ }
             0x02 => { /* Halt - skip */ }
-            0x03 => { /* Stop - skip */ }
-            0x04 => { /* IntrWait - skip */ }
-            0x05 => { /* VBlankIntrWait - skip (test ROM polling DISPSTAT.vblank) */ }
-            0x06 => {
-                let numerator = self.regs[0] as i32;
-                let denominator = self.regs[1] as i32;
-                if denominator != 0 {
-                    self.regs[0] = (numerator / denominator) as u32;
-                    self.regs[1] = (numerator % denominator) as u32;
-                    self.regs[3] = (numerator / denominator).unsigned_abs();
-                }
-            }
-            0x07 => {
-                let numerator = self.regs[0] as i32;
-                let denominator = self.regs[1] as i32;
-                if denominator != 0 {
-                    self.regs[0] = (numerator % denominator) as u32;
-                    self.regs[1] = (numerator / denominator) as u32;
-                    self.regs[3] = (numerator / denominator).unsigned_abs();
-                }
-            }
-            0x08 => {
-                let input = self.regs[0];
-                let mut x = input;
-                let mut result = 0u32;
-                let mut one = 1u32 << 30;
-                while one > x { one >>= 2; }
-                while one != 0 {
-                    if x >= result + one {
-                        x -= result + one;
-                        result = (result >> 1) + one;
-                    } else {
-                        result >>= 1;
-                    }
-                    one >>= 2;
+            0x03 => bus.write8(HALTCNT, 0x80),
+            0x04 | 0x05 => {
+                if swi_num == 0x05 {
+                    self.regs[0] = 1;
+                    self.regs[1] = 1;
                 }
-                self.regs[0] = result;
-            }
+                let swi_len = if self.state() == CpuState::Thumb { 2 } else { 4 };
+                self.hle_intr_wait(bus, swi_len);
+            }
+            0x06 | 0x07 => {
+                // DivArm takes the operands the other way round.
+                let (num, den) = if swi_num == 0x06 { (0, 1) } else { (1, 0) };
+                let [quotient, remainder, abs] = bios_math::div(self.regs[num] as i32, self.regs[den] as i32);
+                self.regs[0] = quotient;
+                self.regs[1] = remainder;
+                self.regs[3] = abs;
+            }
+            0x08 => self.regs[0] = bios_math::sqrt(self.regs[0]),
             0x09 => {
-                let angle = self.regs[0];
-                let result_r = (self.regs[1] & 0xFFFF) as u32;
-                let theta = ((angle as i32) << 16 >> 16) as f64 * std::f64::consts::PI / 32768.0;
-                let sin_val = (theta.sin() * (1 << 14) as f64) as i32;
-                let cos_val = (theta.cos() * (1 << 14) as f64) as i32;
-                bus.write16(result_r, sin_val as u16);
-                bus.write16(result_r + 2, cos_val as u16);
-                self.regs[0] = sin_val as u32;
-                self.regs[1] = cos_val as u32;
-                self.regs[3] = angle >> 16;
+                let [angle, r1, r3] = bios_math::arctan(self.regs[0] as i32);
+                self.regs[0] = angle;
+                self.regs[1] = r1;
+                self.regs[3] = r3;
             }
             0x0A => {
-                let angle = self.regs[0];
-                let result_r = (self.regs[1] & 0xFFFF) as u32;
-                let theta = ((angle as i32) << 16 >> 16) as f64 * std::f64::consts::PI / 32768.0;
-                let sin_val = (theta.sin() * (1 << 14) as f64) as i32;
-                let cos_val = (theta.cos() * (1 << 14) as f64) as i32;
-                bus.write16(result_r, sin_val as u16);
-                bus.write16(result_r + 2, cos_val as u16);
+                let [angle, r1, r3] = bios_math::arctan2(self.regs[0] as i32, self.regs[1] as i32);
+                self.regs[0] = angle;
+                self.regs[1] = r1;
+                self.regs[3] = r3;
             }
             0x0B | 0x0C => {
                 let src = self.regs[0];
@@ -345,8 +464,8 @@ impl Cpu {
                 let unit_size = if (len_mode >> 26) & 1 != 0 { 4 } else { 2 };
 
                 for i in 0..count {
-                    let src_addr = if fixed_src { src } else { src + i * unit_size };
-                    let dst_addr = dst + i * unit_size;
+                    let src_addr = if fixed_src { src } else { src.wrapping_add(i * unit_size) };
+                    let dst_addr = dst.wrapping_add(i * unit_size);
                     if unit_size == 4 {
                         let v = bus.read32(src_addr);
                         bus.write32(dst_addr, v);
@@ -356,8 +475,8 @@ impl Cpu {
                     }
                 }
             }
-            0x0D | 0x0E | 0x0F => { /* CpuFastSet / BitUnPack / LZ77 - skip */ }
-            0x10 | 0x11 | 0x12 | 0x13 | 0x14 => { /* Decompression - skip */ }
+            0x0D..=0x0F => { /* CpuFastSet / BitUnPack / LZ77 - skip */ }
+            0x10..=0x14 => { /* Decompression - skip */ }
             0x19 => { /* SoundBias */ }
             0x1F => { /* MidiKey2Freq */ }
             0x2A => { /* SoundDriverVSyncOff */ }
@@ -623,19 +742,21 @@ impl Cpu {
             if by_reg {
                 let rs = ((opcode >> 8) & 0xF) as usize;
                 let amount = self.regs[rs] & 0xFF;
+                let value = self.late_operand_reg(rm);
                 match typ {
-                    0 => Self::lsl_with_carry(self.regs[rm], amount, self.cpsr.c(), false),
-                    1 => Self::lsr_with_carry(self.regs[rm], amount, self.cpsr.c(), false),
-                    2 => Self::asr_with_carry(self.regs[rm], amount, self.cpsr.c(), false),
-                    _ => Self::ror_with_carry(self.regs[rm], amount, self.cpsr.c(), false),
+                    0 => Self::lsl_with_carry(value, amount, self.cpsr.c(), false),
+                    1 => Self::lsr_with_carry(value, amount, self.cpsr.c(), false),
+                    2 => Self::asr_with_carry(value, amount, self.cpsr.c(), false),
+                    _ => Self::ror_with_carry(value, amount, self.cpsr.c(), false),
                 }
             } else {
                 let imm5 = (opcode >> 7) & 0x1F;
+                let value = self.operand_reg(rm);
                 match typ {
-                    0 => Self::lsl_with_carry(self.regs[rm], imm5, self.cpsr.c(), true),
-                    1 => Self::lsr_with_carry(self.regs[rm], imm5, self.cpsr.c(), true),
-                    2 => Self::asr_with_carry(self.regs[rm], imm5, self.cpsr.c(), true),
-                    _ => Self::ror_with_carry(self.regs[rm], imm5, self.cpsr.c(), true),
+                    0 => Self::lsl_with_carry(value, imm5, self.cpsr.c(), true),
+                    1 => Self::lsr_with_carry(value, imm5, self.cpsr.c(), true),
+                    2 => Self::asr_with_carry(value, imm5, self.cpsr.c(), true),
+                    _ => Self::ror_with_carry(value, imm5, self.cpsr.c(), true),
                 }
             }
         }
@@ -686,7 +807,8 @@ impl Cpu {
 
         let mut write_result = true;
         let result: u32;
-        let rn_val = self.regs[rn];
+        let shift_by_reg = opcode & (1 << 25) == 0 && opcode & (1 << 4) != 0;
+        let rn_val = if shift_by_reg { self.late_operand_reg(rn) } else { self.operand_reg(rn) };
         match op {
             0x0 => { result = rn_val & op2; if s { self.cpsr.set_c(sh_carry); } },              // AND
             0x1 => { result = rn_val ^ op2; if s { self.cpsr.set_c(sh_carry); } },              // EOR
@@ -707,6 +829,17 @@ impl Cpu {
             _ => { return; }
         }
 
+        // With S set, writing the PC returns from an exception: the CPSR
+        // comes back from the SPSR instead of taking the result's flags.
+        if s && write_result && rd == 15 {
+            if let Some(spsr) = self.spsr() {
+                self.set_mode(CpuMode::from_bits(spsr));
+                self.cpsr.set_raw(spsr);
+            }
+            self.regs[15] = result;
+            return;
+        }
+
         // N and Z set for S=1 and for test ops (write_result=false)
         if s || !write_result {
             self.cpsr.set_n((result >> 31) != 0);
@@ -787,6 +920,57 @@ impl Cpu {
     pub fn pc(&self) -> u32 { self.regs[15] }
     pub fn set_pc(&mut self, value: u32) { self.regs[15] = value; }
 
+    // r15 as an instruction operand: two fetches past the executing
+    // instruction, so +8 in ARM state and +4 in Thumb state. `regs[15]`
+    // already holds the next instruction's address while one executes.
+    fn operand_pc(&self) -> u32 { self.regs[15].wrapping_add(if self.cpsr.t() { 2 } else { 4 }) }
+
+    fn operand_reg(&self, index: usize) -> u32 {
+        if index == 15 { self.operand_pc() } else { self.regs[index] }
+    }
+
+    // Operands read a cycle into the instruction, the value shifted by a
+    // register and the data of a store, see the PC one fetch further on.
+    fn late_operand_reg(&self, index: usize) -> u32 {
+        if index == 15 { self.operand_pc().wrapping_add(4) } else { self.regs[index] }
+    }
+
+    // Misaligned loads on the ARM7TDMI: a word comes from the aligned
+    // address rotated so the addressed byte is lowest, a halfword likewise.
+    fn load_word<B: BusAccess>(bus: &mut B, address: u32) -> u32 {
+        bus.read32(address & !3).rotate_right((address & 3) * 8)
+    }
+
+    fn load_half<B: BusAccess>(bus: &mut B, address: u32) -> u32 {
+        (bus.read16(address & !1) as u32).rotate_right((address & 1) * 8)
+    }
+
+    // A signed halfword load from an odd address sign-extends the addressed
+    // byte instead.
+    fn load_signed_half<B: BusAccess>(bus: &mut B, address: u32) -> u32 {
+        if address & 1 != 0 { bus.read8(address) as i8 as u32 } else { bus.read16(address) as i16 as u32 }
+    }
+
+    /// The BIOS SoftReset call (SWI 0): clears the top 512 bytes of IWRAM,
+    /// resets the stacks and registers, and restarts in ARM System mode at
+    /// the ROM, or at EWRAM when the byte at 0x03007FFA is set. Other memory
+    /// and I/O are left alone.
+    pub fn soft_reset<B: BusAccess>(&mut self, bus: &mut B) {
+        let from_ewram = bus.peek8(0x0300_7FFA) != 0;
+        for addr in (0x0300_7E00..0x0300_8000).step_by(4) {
+            bus.write32(addr, 0);
+        }
+        for (mode, sp) in [(CpuMode::Supervisor, 0x0300_7FE0), (CpuMode::Irq, 0x0300_7FA0), (CpuMode::System, 0x0300_7F00)] {
+            self.set_mode(mode);
+            self.regs[13] = sp;
+            self.regs[14] = 0;
+            self.set_spsr(0);
+        }
+        self.regs[..13].fill(0);
+        self.cpsr.set_raw(CpuMode::System.to_bits());
+        self.set_entry_point(bus, if from_ewram { 0x0200_0000 } else { 0x0800_0000 });
+    }
+
     pub fn set_entry_point<B: BusAccess>(&mut self, bus: &mut B, addr: u32) {
         let aligned = addr & !3;
         self.regs[15] = aligned;
@@ -797,16 +981,16 @@ impl Cpu {
         match self.state() {
             CpuState::Arm => {
                 let pc = self.pc() & !3;
-                let decode = bus.read32(pc);
-                let fetch = bus.read32(pc.wrapping_add(4));
+                let decode = bus.fetch32(pc);
+                let fetch = bus.fetch32(pc.wrapping_add(4));
                 self.arm_pipe.fetch = fetch;
                 self.arm_pipe.decode = decode;
                 self.arm_pipe.valid = true;
             }
             CpuState::Thumb => {
                 let pc = self.pc() & !1;
-                let decode = bus.read16(pc) as u32;
-                let fetch = bus.read16(pc.wrapping_add(2)) as u32;
+                let decode = bus.fetch16(pc) as u32;
+                let fetch = bus.fetch16(pc.wrapping_add(2)) as u32;
                 self.thumb_pipe.fetch = fetch as u16;
                 self.thumb_pipe.decode = decode as u16;
                 self.thumb_pipe.valid = true;
@@ -831,13 +1015,13 @@ impl Cpu {
         let l = ((instr >> 20) & 1) != 0; // load/store
         let rn = ((instr >> 16) & 0xF) as usize;
         let rd = ((instr >> 12) & 0xF) as usize;
-        let base = self.regs[rn];
+        let base = self.operand_reg(rn);
 
         let offset = if i {
             let rm = (instr & 0xF) as usize;
             let shift_type = (instr >> 5) & 0x3;
             let shift_amount = (instr >> 7) & 0x1F;
-            let rm_val = self.regs[rm];
+            let rm_val = self.operand_reg(rm);
             let (shifted, _) = match shift_type {
                 0 => Self::lsl_with_carry(rm_val, shift_amount, self.cpsr.c(), true),
                 1 => Self::lsr_with_carry(rm_val, shift_amount, self.cpsr.c(), true),
@@ -854,8 +1038,7 @@ impl Cpu {
 
         if l {
             if b {
-                let value = (bus.read16(address & !1) >> ((address & 1) * 8)) as u8 as u32;
-                self.regs[rd] = value;
+                self.regs[rd] = bus.read8(address) as u32;
             } else {
                 let aligned = address & !3;
                 let raw = bus.read32(aligned);
@@ -867,12 +1050,10 @@ impl Cpu {
                 }
             }
         } else if b {
-            bus.write8(address, (self.regs[rd] & 0xFF) as u8);
+            bus.write8(address, (self.late_operand_reg(rd) & 0xFF) as u8);
         } else {
-            let aligned = address & !3;
-            let rotate = (address & 3) * 8;
-            let value = if rotate != 0 { self.regs[rd].rotate_left(rotate) } else { self.regs[rd] };
-            bus.write32(aligned, value);
+            // Stored unrotated; the bus aligns the address.
+            bus.write32(address, self.late_operand_reg(rd));
         }
 
         if p && w {
@@ -900,9 +1081,9 @@ impl Cpu {
             (((instr >> 8) & 0xF) << 4) | (instr & 0xF)
         } else {
             let rm = (instr & 0xF) as usize;
-            self.regs[rm]
+            self.operand_reg(rm)
         };
-        let base = self.regs[rn];
+        let base = self.operand_reg(rn);
         let off = if u { offset } else { 0u32.wrapping_sub(offset) };
         let address = if p { base.wrapping_add(off) } else { base };
 
@@ -925,7 +1106,7 @@ impl Cpu {
      } else {
          // STRH only
          if h {
-             bus.write16(address & !1, (self.regs[rd] & 0xFFFF) as u16);
+             bus.write16(address, (self.late_operand_reg(rd) & 0xFFFF) as u16);
          }
      }
 
@@ -956,45 +1137,38 @@ impl Cpu {
     fn execute_arm_psr_transfer(&mut self, instr: u32) {
         let cond = (instr >> 28) & 0xF;
         if !self.condition_passed(cond) { return; }
-        let r = ((instr >> 22) & 1) != 0; // 0=CPSR, 1=SPSR (unsupported)
-        let mrs = ((instr >> 21) & 1) == 0 && (((instr >> 4) & 0xFF) == 0);
-        if mrs {
-            if r { return; }
+        let spsr = ((instr >> 22) & 1) != 0;
+        let msr = ((instr >> 21) & 1) != 0;
+        if !msr {
             let rd = ((instr >> 12) & 0xF) as usize;
-            self.regs[rd] = self.cpsr.raw();
+            // User and System mode have no SPSR; reading it gives the CPSR.
+            self.regs[rd] = if spsr { self.spsr().unwrap_or(self.cpsr.raw()) } else { self.cpsr.raw() };
             return;
         }
-        // MSR
-        let immediate = ((instr >> 25) & 1) == 1;
-        if r { return; }
-        let field_mask = (instr >> 16) & 0xF; // f,s,x,c
-        let operand = if immediate {
+        let operand = if ((instr >> 25) & 1) != 0 {
             let imm8 = instr & 0xFF;
             let rot = ((instr >> 8) & 0xF) * 2;
             imm8.rotate_right(rot)
         } else {
-            let rm = (instr & 0xF) as usize;
-            self.regs[rm]
+            self.regs[(instr & 0xF) as usize]
         };
-        let mut cpsr = self.cpsr.raw();
-        // Only handle f (flags) and c (control) minimally; here apply flags when bit3 (f) set
-        if (field_mask & 0b1000) != 0 {
-            // Derive NZCV from operand. Prefer bits31..28; if zero (immediate low form), use bits7..4 mapping.
-            let nzcv = if (operand & 0xF000_0000) != 0 {
-                (operand >> 28) & 0xF
-            } else {
-                (operand >> 4) & 0xF
-            };
-            // Clear flags then set from nzcv
-            cpsr &= 0x0FFF_FFFF;
-            cpsr |= nzcv << 28;
+        // One byte per field: flags, status, extension, control.
+        let mut mask = (0..4)
+            .filter(|i| (instr >> (16 + i)) & 1 != 0)
+            .fold(0u32, |mask, i| mask | (0xFF << (i * 8)));
+        if spsr {
+            if let Some(old) = self.spsr() {
+                self.set_spsr((old & !mask) | (operand & mask));
+            }
+            return;
         }
-        // Optionally update I,F,T and mode if c bit set (lowest nibble). For safety, ignore mode changes here.
-        if (field_mask & 0b0001) != 0 {
-            // Update only I,F,T bits (7,6,5)
-            let mask = (1<<7) | (1<<6) | (1<<5);
-            cpsr = (cpsr & !mask) | (operand & mask);
+        // User mode may only change the flags, and MSR never changes state.
+        if self.mode() == CpuMode::User {
+            mask &= 0xFF00_0000;
         }
+        mask &= !(1 << 5);
+        let cpsr = (self.cpsr.raw() & !mask) | (operand & mask);
+        self.set_mode(CpuMode::from_bits(cpsr));
         self.cpsr.set_raw(cpsr);
     }
 
@@ -1003,111 +1177,65 @@ impl Cpu {
         if !self.condition_passed(cond) { return; }
         let p = ((instr >> 24) & 1) != 0; // pre
         let u = ((instr >> 23) & 1) != 0; // up
-        let s = ((instr >> 22) & 1) != 0; // s (user mode registers)
+        let s = ((instr >> 22) & 1) != 0; // PSR / user bank
         let w = ((instr >> 21) & 1) != 0; // writeback
         let l = ((instr >> 20) & 1) != 0; // load
         let rn = ((instr >> 16) & 0xF) as usize;
-        let reg_list = instr & 0xFFFF;
-
-        // Handle empty register list - special case
-        if reg_list == 0 {
-            if l {
-                // LDM with empty list: load PC from address
-                let addr = if p {
-                    if u { self.regs[rn].wrapping_add(4) } else { self.regs[rn].wrapping_sub(4) }
-                } else {
-                    self.regs[rn]
-                };
-                let pc_val = bus.read32(addr & !3);
-                self.regs[15] = pc_val;
-                if w {
-                    self.regs[rn] = if u {
-                        self.regs[rn].wrapping_add(4)
-                    } else {
-                        self.regs[rn].wrapping_sub(4)
-                    };
-                }
-            } else {
-                // STM with empty list: store PC+12 to address
-                let addr = if p {
-                    if u { self.regs[rn].wrapping_add(4) } else { self.regs[rn].wrapping_sub(4) }
-                } else {
-                    self.regs[rn]
-                };
-                bus.write32(addr & !3, self.regs[15].wrapping_add(12));
-                if w {
-                    self.regs[rn] = if u {
-                        self.regs[rn].wrapping_add(4)
-                    } else {
-                        self.regs[rn].wrapping_sub(4)
-                    };
-                }
-            }
-            return;
-        }
+        // An empty list transfers r15 and moves the base by 0x40, as if all
+        // sixteen registers were listed.
+        let reg_list = if instr & 0xFFFF == 0 { 1 << 15 } else { instr & 0xFFFF };
+        let size = if instr & 0xFFFF == 0 { 0x40 } else { reg_list.count_ones() * 4 };
 
+        // Registers always go out in ascending order from the lowest address.
         let base = self.regs[rn];
-
-        // Collect registers in ascending order
-        let mut regs: Vec<usize> = Vec::new();
-        for r in 0..16 {
-            if (reg_list >> r) & 1 == 1 { regs.push(r as usize); }
-        }
-        let count = regs.len() as u32;
-
-        // Calculate start address based on addressing mode
-        let start_addr = match (u, p) {
-            (true, false) => base,                          // IA (Increment After)
-            (true, true)  => base.wrapping_add(4),          // IB (Increment Before)
-            (false, false)=> base.wrapping_sub(4 * count),  // DA (Decrement After)
-            (false, true) => base.wrapping_sub(4).wrapping_sub(4 * count), // DB (Decrement Before)
+        let (start_addr, new_base) = match (u, p) {
+            (true, false) => (base, base.wrapping_add(size)),
+            (true, true) => (base.wrapping_add(4), base.wrapping_add(size)),
+            (false, false) => (base.wrapping_sub(size).wrapping_add(4), base.wrapping_sub(size)),
+            (false, true) => (base.wrapping_sub(size), base.wrapping_sub(size)),
         };
 
-        // Perform transfers in ascending register order
-        for (i, &reg) in regs.iter().enumerate() {
-            let addr = start_addr.wrapping_add((i as u32) * 4);
+        // With S set, LDM with r15 restores the CPSR from the SPSR; any other
+        // form transfers the User bank registers.
+        let loads_pc = l && reg_list & (1 << 15) != 0;
+        let user_bank = s && !loads_pc;
+        let mode = self.mode();
+        if user_bank { self.set_mode(CpuMode::User); }
 
+        let first = reg_list.trailing_zeros() as usize;
+        let mut addr = start_addr;
+        for reg in (0..16).filter(|r| reg_list & (1 << r) != 0) {
             if l {
-                // Load operation
-                let val = bus.read32(addr & !3);
-                self.regs[reg] = val;
-
-                // Special handling for PC load
-                if reg == 15 {
-                    // PC load causes pipeline flush
-                    self.flush_pipeline(bus);
-            }
-        } else {
-                // Store operation
-                let val = if reg == 15 {
-                    // Store PC+12 for return address
-                    self.regs[15].wrapping_add(12)
-                } else {
-                    self.regs[reg]
-                };
+                self.regs[reg] = bus.read32(addr & !3);
+            } else {
+                // ARMv4 stores the old base if it is first in the list, the
+                // written-back one otherwise.
+                let val = if reg == rn && w && reg != first { new_base } else { self.late_operand_reg(reg) };
                 bus.write32(addr & !3, val);
             }
+            addr = addr.wrapping_add(4);
         }
 
-        // Update base register if writeback is enabled
-        if w {
-            let new_base = match (u, p) {
-                (true, false) => base.wrapping_add(4 * count),      // IA: base + count*4
-                (true, true)  => base.wrapping_add(4).wrapping_add(4 * count), // IB: base + 4 + count*4
-                (false, false)=> base.wrapping_sub(4 * count),      // DA: base - count*4
-                (false, true) => base.wrapping_sub(4).wrapping_sub(4 * count), // DB: base - 4 - count*4
-            };
+        if user_bank { self.set_mode(mode); }
+        // A loaded base wins over the writeback.
+        if w && !(l && reg_list & (1 << rn) != 0) {
             self.regs[rn] = new_base;
         }
-
-        // Note: S bit (user mode registers) not implemented yet
-        let _ = s;
+        if loads_pc {
+            if s && let Some(spsr) = self.spsr() {
+                self.set_mode(CpuMode::from_bits(spsr));
+                self.cpsr.set_raw(spsr);
+            }
+            // ARMv4 ignores the low bits rather than switching state.
+            self.regs[15] &= if self.cpsr.t() { !1 } else { !3 };
+            self.flush_pipeline(bus);
+        }
     }
 
     // THUMB instruction implementations
 
     fn execute_thumb_move_shifted_register(&mut self, instr: u32) {
-        let op = (instr >> 11) & 0x3; // 00=LSL, 01=LSR, 10=ASR, 11=ADD/SUB
+        let op = (instr >> 11) & 0x3; // 00=LSL, 01=LSR, 10=ASR
         let offset5 = (instr >> 6) & 0x1F;
         let rs = (instr >> 3) & 0x7;
         let rd = instr & 0x7;
@@ -1134,40 +1262,19 @@ impl Cpu {
                 self.cpsr.set_z(result == 0);
                 self.cpsr.set_c(carry);
             }
-            3 => { // ADD/SUB
-                let op2 = (instr >> 9) & 0x1; // 0=ADD, 1=SUB
-                let rm = (instr >> 6) & 0x7;
-                let rs_val = self.regs[rs as usize];
-                let rm_val = self.regs[rm as usize];
-
-                if op2 == 0 { // ADD
-                    let (result, carry, overflow) = Self::add_with_carry(rs_val, rm_val, false);
-                    self.regs[rd as usize] = result;
-                    self.cpsr.set_n((result >> 31) != 0);
-                    self.cpsr.set_z(result == 0);
-                    self.cpsr.set_c(carry);
-                    self.cpsr.set_v(overflow);
-                } else { // SUB
-                    let (result, carry, overflow) = Self::sub_with_borrow(rs_val, rm_val, true);
-                    self.regs[rd as usize] = result;
-                    self.cpsr.set_n((result >> 31) != 0);
-                    self.cpsr.set_z(result == 0);
-                    self.cpsr.set_c(carry);
-                    self.cpsr.set_v(overflow);
-                }
-            }
             _ => {}
         }
     }
 
     fn execute_thumb_add_subtract(&mut self, instr: u32) {
+        let immediate = (instr >> 10) & 0x1; // 0=register, 1=3-bit immediate
         let op = (instr >> 9) & 0x1; // 0=ADD, 1=SUB
         let rn = (instr >> 6) & 0x7;
         let rs = (instr >> 3) & 0x7;
         let rd = instr & 0x7;
 
         let rs_val = self.regs[rs as usize];
-        let rn_val = self.regs[rn as usize];
+        let rn_val = if immediate == 1 { rn } else { self.regs[rn as usize] };
 
         if op == 0 { // ADD
             let (result, carry, overflow) = Self::add_with_carry(rs_val, rn_val, false);
@@ -1187,7 +1294,7 @@ impl Cpu {
     }
 
     fn execute_thumb_move_compare_add_subtract_immediate(&mut self, instr: u32) {
-        let op = (instr >> 10) & 0x3; // 00=MOV, 01=CMP, 10=ADD, 11=SUB
+        let op = (instr >> 11) & 0x3; // 00=MOV, 01=CMP, 10=ADD, 11=SUB
         let rd = (instr >> 8) & 0x7;
         let imm8 = instr & 0xFF;
 
@@ -1352,6 +1459,26 @@ impl Cpu {
         }
     }
 
+    fn execute_arm_branch<B: BusAccess>(&mut self, bus: &mut B, instr: u32) {
+        let cond = (instr >> 28) & 0xF;
+        if !self.condition_passed(cond) { return; }
+        let l = ((instr >> 24) & 1) != 0;
+        let offset = ((((instr & 0x00FF_FFFF) as i32) << 8) >> 6) as u32;
+        let base = self.operand_pc();
+        if l { self.regs[14] = self.pc(); }
+        self.regs[15] = base.wrapping_add(offset);
+        self.flush_pipeline(bus);
+    }
+
+    fn execute_arm_branch_exchange<B: BusAccess>(&mut self, bus: &mut B, instr: u32) {
+        let cond = (instr >> 28) & 0xF;
+        if !self.condition_passed(cond) { return; }
+        let target = self.operand_reg((instr & 0xF) as usize);
+        self.set_state(if (target & 1) != 0 { CpuState::Thumb } else { CpuState::Arm });
+        self.regs[15] = target & !1;
+        self.flush_pipeline(bus);
+    }
+
     fn execute_thumb_hi_register_operations_branch_exchange(&mut self, instr: u32) {
         let op = (instr >> 8) & 0x3;
         let h1 = (instr >> 7) & 0x1;
@@ -1364,10 +1491,10 @@ impl Cpu {
 
         match op {
             0 => { // ADD
-                let rd_val = self.regs[rd_idx];
-                let rs_val = self.regs[rs_idx];
+                let rd_val = self.operand_reg(rd_idx);
+                let rs_val = self.operand_reg(rs_idx);
                 let (result, carry, overflow) = Self::add_with_carry(rd_val, rs_val, false);
-                self.regs[rd_idx] = result;
+                self.regs[rd_idx] = if rd_idx == 15 { result & !1 } else { result };
                 if rd_idx < 8 { // Only set flags for low registers
                     self.cpsr.set_n((result >> 31) != 0);
                     self.cpsr.set_z(result == 0);
@@ -1376,8 +1503,8 @@ impl Cpu {
                 }
             }
             1 => { // CMP
-                let rd_val = self.regs[rd_idx];
-                let rs_val = self.regs[rs_idx];
+                let rd_val = self.operand_reg(rd_idx);
+                let rs_val = self.operand_reg(rs_idx);
                 let (result, carry, overflow) = Self::sub_with_borrow(rd_val, rs_val, true);
                 self.cpsr.set_n((result >> 31) != 0);
                 self.cpsr.set_z(result == 0);
@@ -1385,15 +1512,15 @@ impl Cpu {
                 self.cpsr.set_v(overflow);
             }
             2 => { // MOV
-                let rs_val = self.regs[rs_idx];
-                self.regs[rd_idx] = rs_val;
+                let rs_val = self.operand_reg(rs_idx);
+                self.regs[rd_idx] = if rd_idx == 15 { rs_val & !1 } else { rs_val };
                 if rd_idx < 8 { // Only set flags for low registers
                     self.cpsr.set_n((rs_val >> 31) != 0);
                     self.cpsr.set_z(rs_val == 0);
                 }
             }
             3 => { // BX
-                let rs_val = self.regs[rs_idx];
+                let rs_val = self.operand_reg(rs_idx);
                 let new_pc = rs_val & !1; // Clear bit 0
                 let new_state = if (rs_val & 1) != 0 { CpuState::Thumb } else { CpuState::Arm };
 
@@ -1409,87 +1536,70 @@ impl Cpu {
         let rd = (instr >> 8) & 0x7;
         let imm8 = instr & 0xFF;
 
-        let pc = (self.regs[15] & !3) + 4; // PC + 4, word aligned
-        let address = pc + (imm8 << 2);
+        let address = (self.operand_pc() & !3).wrapping_add(imm8 << 2);
 
         let value = bus.read32(address & !3);
         self.regs[rd as usize] = value;
     }
 
+    // Format 7: bit 11 is L (load), bit 10 is B (byte).
     fn execute_thumb_load_store_register_offset<B: BusAccess>(&mut self, bus: &mut B, instr: u32) {
-        let op = (instr >> 10) & 0x3; // 00=STR, 01=STRH, 10=STRB, 11=LDRSB
-        let ro = (instr >> 6) & 0x7;
-        let rb = (instr >> 3) & 0x7;
-        let rd = instr & 0x7;
-
-        let rb_val = self.regs[rb as usize];
-        let ro_val = self.regs[ro as usize];
-        let address = rb_val + ro_val;
+        let load = instr & (1 << 11) != 0;
+        let byte = instr & (1 << 10) != 0;
+        let ro = ((instr >> 6) & 0x7) as usize;
+        let rb = ((instr >> 3) & 0x7) as usize;
+        let rd = (instr & 0x7) as usize;
+        let address = self.regs[rb].wrapping_add(self.regs[ro]);
 
-        match op {
-            0 => { // STR
-                let value = self.regs[rd as usize];
-                bus.write32(address & !3, value);
-            }
-            1 => { // STRH
-                let value = self.regs[rd as usize] as u16;
-                bus.write16(address & !1, value);
-            }
-            2 => { // STRB
-                let value = self.regs[rd as usize] as u8;
-                bus.write8(address, value);
-            }
-            3 => { // LDRSB
-                let value = bus.read8(address) as i8 as i32 as u32;
-                self.regs[rd as usize] = value;
-            }
-            _ => {}
+        match (load, byte) {
+            (false, false) => bus.write32(address, self.regs[rd]), // STR
+            (false, true) => bus.write8(address, self.regs[rd] as u8), // STRB
+            (true, false) => self.regs[rd] = Self::load_word(bus, address), // LDR
+            (true, true) => self.regs[rd] = bus.read8(address) as u32, // LDRB
         }
     }
 
+    // Format 8: bit 11 is H (halfword), bit 10 is S (sign-extend); the
+    // combination with neither is the store.
     fn execute_thumb_load_store_sign_extended<B: BusAccess>(&mut self, bus: &mut B, instr: u32) {
-        let op = (instr >> 10) & 0x3; // 00=LDRH, 01=LDSB, 10=LDRB, 11=LDSH
-        let ro = (instr >> 6) & 0x7;
-        let rb = (instr >> 3) & 0x7;
-        let rd = instr & 0x7;
+        let half = instr & (1 << 11) != 0;
+        let signed = instr & (1 << 10) != 0;
+        let ro = ((instr >> 6) & 0x7) as usize;
+        let rb = ((instr >> 3) & 0x7) as usize;
+        let rd = (instr & 0x7) as usize;
+        let address = self.regs[rb].wrapping_add(self.regs[ro]);
 
-        let rb_val = self.regs[rb as usize];
-        let ro_val = self.regs[ro as usize];
-        let address = rb_val + ro_val;
-
-        match op {
-            0 => { // LDRH
-                let value = bus.read16(address & !1) as u32;
-                self.regs[rd as usize] = value;
-            }
-            1 => { // LDSB (LDRSB)
-                let value = bus.read8(address) as i8 as i32 as u32;
-                self.regs[rd as usize] = value;
-            }
-            2 => { // LDRB
-                let value = bus.read8(address) as u32;
-                self.regs[rd as usize] = value;
-            }
-            3 => { // LDSH (LDRSH)
-                let value = bus.read16(address & !1) as i16 as i32 as u32;
-                self.regs[rd as usize] = value;
-            }
-            _ => {}
+        match (half, signed) {
+            (false, false) => bus.write16(address, self.regs[rd] as u16), // STRH
+            (false, true) => self.regs[rd] = bus.read8(address) as i8 as u32, // LDSB
+            (true, false) => self.regs[rd] = Self::load_half(bus, address), // LDRH
+            (true, true) => self.regs[rd] = Self::load_signed_half(bus, address), // LDSH
         }
     }
 
     fn execute_thumb_load_store_immediate_offset<B: BusAccess>(&mut self, bus: &mut B, instr: u32) {
+        let byte = (instr >> 12) & 0x1; // 0=word, 1=byte
         let op = (instr >> 11) & 0x1; // 0=STR, 1=LDR
         let imm5 = (instr >> 6) & 0x1F;
         let rb = (instr >> 3) & 0x7;
         let rd = instr & 0x7;
 
         let rb_val = self.regs[rb as usize];
-        let address = rb_val + (imm5 << 2);
 
+        if byte == 1 {
+            let address = rb_val.wrapping_add(imm5);
+            if op == 0 { // STRB
+                bus.write8(address, self.regs[rd as usize] as u8);
+            } else { // LDRB
+                self.regs[rd as usize] = bus.read8(address) as u32;
+            }
+            return;
+        }
+
+        let address = rb_val.wrapping_add(imm5 << 2);
         if op == 0 { // STR
             let value = self.regs[rd as usize];
-            bus.write32(address & !3, value);
+            bus.write32(address, value);
         } else { // LDR
             let value = bus.read32(address & !3);
             self.regs[rd as usize] = value;
@@ -1503,11 +1613,11 @@ impl Cpu {
         let rd = instr & 0x7;
 
         let rb_val = self.regs[rb as usize];
-        let address = rb_val + (imm5 << 1);
+        let address = rb_val.wrapping_add(imm5 << 1);
 
         if op == 0 { // STRH
             let value = self.regs[rd as usize] as u16;
-            bus.write16(address & !1, value);
+            bus.write16(address, value);
         } else { // LDRH
             let value = bus.read16(address & !1) as u32;
             self.regs[rd as usize] = value;
@@ -1520,11 +1630,11 @@ impl Cpu {
         let imm8 = instr & 0xFF;
 
         let sp = self.regs[13];
-        let address = sp + (imm8 << 2);
+        let address = sp.wrapping_add(imm8 << 2);
 
         if op == 0 { // STR
             let value = self.regs[rd as usize];
-            bus.write32(address & !3, value);
+            bus.write32(address, value);
         } else { // LDR
             let value = bus.read32(address & !3);
             self.regs[rd as usize] = value;
@@ -1537,12 +1647,11 @@ impl Cpu {
         let imm8 = instr & 0xFF;
 
         if sp == 0 { // ADD to PC
-            let pc = (self.regs[15] & !3) + 4; // PC + 4, word aligned
-            let address = pc + (imm8 << 2);
+            let address = (self.operand_pc() & !3).wrapping_add(imm8 << 2);
             self.regs[rd as usize] = address;
         } else { // ADD to SP
             let sp_val = self.regs[13];
-            let address = sp_val + (imm8 << 2);
+            let address = sp_val.wrapping_add(imm8 << 2);
             self.regs[rd as usize] = address;
         }
     }
@@ -1555,9 +1664,9 @@ impl Cpu {
         let offset = imm7 << 2;
 
         if s == 0 { // ADD
-            self.regs[13] = sp + offset;
+            self.regs[13] = sp.wrapping_add(offset);
         } else { // SUB
-            self.regs[13] = sp - offset;
+            self.regs[13] = sp.wrapping_sub(offset);
         }
     }
 
@@ -1577,13 +1686,13 @@ impl Cpu {
             }
             if r == 1 { count += 1; } // LR
 
-            let start_addr = sp - (count << 2);
+            let start_addr = sp.wrapping_sub(count << 2);
             let mut addr = start_addr;
 
             for i in 0..8 {
                 if (reg_list >> i) & 1 == 1 {
                     bus.write32(addr & !3, self.regs[i]);
-                    addr += 4;
+                    addr = addr.wrapping_add(4);
                 }
             }
             if r == 1 { // LR
@@ -1598,12 +1707,13 @@ impl Cpu {
                 if (reg_list >> i) & 1 == 1 {
                     let value = bus.read32(addr & !3);
                     self.regs[i] = value;
-                    addr += 4;
+                    addr = addr.wrapping_add(4);
                 }
             }
             if r == 1 { // PC
                 let value = bus.read32(addr & !3);
                 self.regs[15] = value;
+                addr = addr.wrapping_add(4);
                 // Pipeline flush will be handled by the step function
             }
 
@@ -1619,11 +1729,27 @@ impl Cpu {
         let rb_val = self.regs[rb as usize];
         let mut addr = rb_val;
 
+        // An empty list transfers r15 and moves the base by 0x40.
+        if reg_list == 0 {
+            if l == 0 {
+                bus.write32(addr & !3, self.operand_pc().wrapping_add(2));
+            } else {
+                self.regs[15] = bus.read32(addr & !3);
+            }
+            self.regs[rb as usize] = rb_val.wrapping_add(0x40);
+            return;
+        }
+
         if l == 0 { // STMIA
+            // The old base is stored if it is first in the list, the
+            // written-back one otherwise.
+            let new_base = rb_val.wrapping_add(reg_list.count_ones() * 4);
+            let first = reg_list.trailing_zeros();
             for i in 0..8 {
                 if (reg_list >> i) & 1 == 1 {
-                    bus.write32(addr & !3, self.regs[i]);
-                    addr += 4;
+                    let value = if i == rb && i != first { new_base } else { self.regs[i as usize] };
+                    bus.write32(addr & !3, value);
+                    addr = addr.wrapping_add(4);
                 }
             }
             self.regs[rb as usize] = addr; // Writeback
@@ -1632,10 +1758,13 @@ impl Cpu {
                 if (reg_list >> i) & 1 == 1 {
                     let value = bus.read32(addr & !3);
                     self.regs[i] = value;
-                    addr += 4;
+                    addr = addr.wrapping_add(4);
                 }
             }
-            self.regs[rb as usize] = addr; // Writeback
+            // A loaded base wins over the writeback.
+            if (reg_list >> rb) & 1 == 0 {
+                self.regs[rb as usize] = addr;
+            }
         }
     }
 
@@ -1645,8 +1774,7 @@ impl Cpu {
 
         if self.condition_passed(cond) {
             let offset = ((imm8 as i8) as i32) << 1;
-            let pc = self.regs[15]; // PC is already advanced by 2, so this is PC+2
-            self.regs[15] = (pc as i32 + offset) as u32;
+            self.regs[15] = self.operand_pc().wrapping_add_signed(offset);
             // Pipeline flush will be handled by the step function
         }
     }
@@ -1656,175 +1784,213 @@ impl Cpu {
         self.handle_swi(bus, swi_num);
     }
 
-    #[allow(dead_code)]
     fn execute_thumb_unconditional_branch<B: BusAccess>(&mut self, _bus: &mut B, instr: u32) {
         let imm11 = instr & 0x7FF;
         let offset = ((imm11 as i16) << 5) >> 4; // Sign extend 11-bit to 16-bit, then to 32-bit
-        let pc = self.regs[15] - 2; // PC is already advanced by 2
-        self.regs[15] = (pc as i32 + offset as i32) as u32;
+        self.regs[15] = self.operand_pc().wrapping_add_signed(offset as i32);
         // Pipeline flush will be handled by the step function
     }
 
-    #[allow(dead_code)]
     fn execute_thumb_long_branch_with_link<B: BusAccess>(&mut self, _bus: &mut B, instr: u32) {
         let h = (instr >> 11) & 0x1;
         let imm11 = instr & 0x7FF;
 
-        if h == 0 { // First instruction
-            let offset = ((imm11 as i16) << 5) >> 4; // Sign extend
-            let pc = self.regs[15] - 2;
-            self.regs[14] = pc.wrapping_add(offset as u32);
+        // The halves are separate instructions; between them LR holds the
+        // upper part of the target, and an interrupt taken there returns to
+        // the second half with it intact.
+        if h == 0 { // First instruction: LR = PC + (sign-extended offset << 12)
+            let offset = (((imm11 << 21) as i32) >> 9) as u32;
+            self.regs[14] = self.operand_pc().wrapping_add(offset);
         } else { // Second instruction
-            let offset = ((imm11 as i16) << 5) >> 4; // Sign extend
-            let lr = self.regs[14];
-            let pc = self.regs[15] - 2;
-            let new_pc = lr.wrapping_add(offset as u32);
-
-            self.regs[14] = pc | 1; // Set bit 0 to indicate THUMB return
+            let new_pc = self.regs[14].wrapping_add(imm11 << 1);
+            self.regs[14] = self.regs[15] | 1; // The next instruction, in Thumb state
             self.regs[15] = new_pc;
             // Pipeline flush will be handled by the step function
         }
     }
 
-    fn execute_thumb_instruction<B: BusAccess>(&mut self, bus: &mut B, instr: u32) {
-        let opcode = (instr >> 11) & 0x1F;
-
-        match opcode {
-            0x00..=0x07 => {
-                self.execute_thumb_move_shifted_register(instr);
-            }
-            0x08..=0x0F => {
-                self.execute_thumb_add_subtract(instr);
-            }
-            0x10..=0x11 => {
-                self.execute_thumb_move_compare_add_subtract_immediate(instr);
-            }
-            0x12..=0x13 => {
-                self.execute_thumb_alu_operations(instr);
-            }
-            0x14..=0x15 => {
-                self.execute_thumb_hi_register_operations_branch_exchange(instr);
-            }
-            0x16..=0x17 => {
-                self.execute_thumb_pc_relative_load(bus, instr);
-            }
-            0x18..=0x19 => {
-                self.execute_thumb_load_store_register_offset(bus, instr);
-            }
-            0x1B => {
-                let cond = (instr >> 8) & 0xF;
-                if cond == 0xF {
-                    self.execute_thumb_software_interrupt(bus, instr);
-                } else {
-                    self.execute_thumb_load_store_sign_extended(bus, instr);
-                }
-            }
-            0x1C..=0x1D => {
-                self.execute_thumb_load_store_immediate_offset(bus, instr);
-            }
-            0x1E..=0x1F => {
-                self.execute_thumb_load_store_halfword(bus, instr);
-            }
-            0x20..=0x21 => {
-                self.execute_thumb_sp_relative_load_store(bus, instr);
-            }
-            0x22..=0x23 => {
-                self.execute_thumb_load_address(instr);
-            }
-            0x24..=0x25 => {
-                self.execute_thumb_add_offset_to_sp(instr);
-            }
-            0x26..=0x27 => {
-                self.execute_thumb_push_pop_registers(bus, instr);
-            }
-            0x28..=0x2F => {
-                self.execute_thumb_multiple_load_store(bus, instr);
-            }
-            0x1A => {
-                self.execute_thumb_conditional_branch(bus, instr);
-            }
-            _ => {}
+    fn execute_thumb_instruction<B: BusAccess>(&mut self, bus: &mut B, format: ThumbFormat, instr: u32) {
+        match format {
+            ThumbFormat::MoveShiftedRegister => self.execute_thumb_move_shifted_register(instr),
+            ThumbFormat::AddSubtract => self.execute_thumb_add_subtract(instr),
+            ThumbFormat::Immediate => self.execute_thumb_move_compare_add_subtract_immediate(instr),
+            ThumbFormat::Alu => self.execute_thumb_alu_operations(instr),
+            ThumbFormat::HiRegisterBx => self.execute_thumb_hi_register_operations_branch_exchange(instr),
+            ThumbFormat::PcRelativeLoad => self.execute_thumb_pc_relative_load(bus, instr),
+            ThumbFormat::LoadStoreRegisterOffset => self.execute_thumb_load_store_register_offset(bus, instr),
+            ThumbFormat::LoadStoreSignExtended => self.execute_thumb_load_store_sign_extended(bus, instr),
+            ThumbFormat::LoadStoreImmediateOffset => self.execute_thumb_load_store_immediate_offset(bus, instr),
+            ThumbFormat::LoadStoreHalfword => self.execute_thumb_load_store_halfword(bus, instr),
+            ThumbFormat::SpRelativeLoadStore => self.execute_thumb_sp_relative_load_store(bus, instr),
+            ThumbFormat::LoadAddress => self.execute_thumb_load_address(instr),
+            ThumbFormat::AddOffsetToSp => self.execute_thumb_add_offset_to_sp(instr),
+            ThumbFormat::PushPop => self.execute_thumb_push_pop_registers(bus, instr),
+            ThumbFormat::MultipleLoadStore => self.execute_thumb_multiple_load_store(bus, instr),
+            ThumbFormat::ConditionalBranch => self.execute_thumb_conditional_branch(bus, instr),
+            ThumbFormat::SoftwareInterrupt => self.execute_thumb_software_interrupt(bus, instr),
+            ThumbFormat::UnconditionalBranch => self.execute_thumb_unconditional_branch(bus, instr),
+            ThumbFormat::LongBranchWithLink => self.execute_thumb_long_branch_with_link(bus, instr),
+            ThumbFormat::Undefined => {
+                self.note_undefined(self.pc().wrapping_sub(2), instr, true);
+                self.enter_exception(bus, Exception::Undefined);
+            }
+        }
+    }
+
+    // Internal cycles an ARM instruction takes on top of its bus accesses.
+    // Loads spend one writing the register back, a register-specified
+    // shift one reading the third register, and multiplies 1-4 depending on
+    // how many bytes of the multiplier are significant, plus one for each
+    // extra register written or accumulated.
+    fn arm_internal_cycles(&self, format: ArmFormat, instr: u32) -> u64 {
+        if !self.condition_passed(instr >> 28) {
+            return 0;
+        }
+        let load = instr & (1 << 20) != 0;
+        let accumulate = ((instr >> 21) & 1) as u64;
+        let rs = self.regs[((instr >> 8) & 0xF) as usize];
+        match format {
+            ArmFormat::DataProcessing => (instr & (1 << 25) == 0 && instr & (1 << 4) != 0) as u64,
+            ArmFormat::Multiply => multiply_cycles(rs, true) + accumulate,
+            ArmFormat::MultiplyLong => multiply_cycles(rs, instr & (1 << 22) != 0) + 1 + accumulate,
+            ArmFormat::Swap => 1,
+            ArmFormat::HalfwordTransfer | ArmFormat::SingleDataTransfer | ArmFormat::BlockTransfer => load as u64,
+            _ => 0,
+        }
+    }
+
+    // Internal cycles of a Thumb instruction, by the same rules as ARM.
+    fn thumb_internal_cycles(&self, format: ThumbFormat, instr: u32) -> u64 {
+        let load = instr & (1 << 11) != 0;
+        match format {
+            ThumbFormat::Alu => match (instr >> 6) & 0xF {
+                // LSL, LSR, ASR and ROR by register.
+                2 | 3 | 4 | 7 => 1,
+                13 => multiply_cycles(self.regs[(instr & 7) as usize], true),
+                _ => 0,
+            },
+            ThumbFormat::PcRelativeLoad => 1,
+            // Only STRH (H and S clear) stores.
+            ThumbFormat::LoadStoreSignExtended => (instr & (3 << 10) != 0) as u64,
+            ThumbFormat::LoadStoreRegisterOffset
+            | ThumbFormat::LoadStoreImmediateOffset
+            | ThumbFormat::LoadStoreHalfword
+            | ThumbFormat::SpRelativeLoadStore
+            | ThumbFormat::PushPop
+            | ThumbFormat::MultipleLoadStore => load as u64,
+            _ => 0,
+        }
+    }
+
+    /// Runs one instruction and returns the cycles it took: its opcode
+    /// fetches and data accesses as the bus timed them (nonsequential,
+    /// sequential and waitstates, including the refill after a branch) plus
+    /// its internal cycles. Always at least 1, also on buses without a
+    /// timing model.
+    pub fn step<B: BusAccess>(&mut self, bus: &mut B) -> u64 {
+        self.execute_next(bus);
+        bus.take_cycles().max(1)
+    }
+
+    fn execute_next<B: BusAccess>(&mut self, bus: &mut B) {
+        if self.swi_hle && self.regs[15] == HLE_IRQ_RETURN && self.mode() == CpuMode::Irq {
+            self.hle_irq_return(bus);
+            return;
         }
-    }
-
-    pub fn step<B: BusAccess>(&mut self, bus: &mut B) {
         match self.state() {
             CpuState::Arm => {
                 if !self.arm_pipe.valid { self.reset_pipeline(bus); }
                 let instr = self.arm_pipe.decode;
+                if let Some(coverage) = &mut self.coverage {
+                    coverage.record_arm(self.regs[15] & !3, instr);
+                }
+                if let Some(trace) = &mut self.trace {
+                    trace.record(TraceEntry { pc: self.regs[15] & !3, opcode: instr, thumb: false });
+                }
                 let next_pc = (self.pc() & !3).wrapping_add(4);
                 let new_decode = self.arm_pipe.fetch;
-                let new_fetch = bus.read32(next_pc.wrapping_add(4));
+                let new_fetch = bus.fetch32(next_pc.wrapping_add(4));
                 self.arm_pipe.decode = new_decode;
                 self.arm_pipe.fetch = new_fetch;
                 self.regs[15] = next_pc;
 
-                let top2 = (instr >> 26) & 0x3;
-                let top3 = (instr >> 25) & 0x7;
-                if ((instr >> 22) & 0x3F) == 0 && ((instr >> 4) & 0xF) == 0b1001 {
-                    let before_pc = self.pc();
-                    self.execute_arm_multiply(instr);
-                    if self.pc() != before_pc { self.flush_pipeline(bus); }
-                } else if ((instr >> 23) & 0x1F) == 0b00001 && ((instr >> 4) & 0xF) == 0b1001 {
-                    // UMULL/UMLAL/SMULL/SMLAL
-                    self.execute_arm_multiply_long(instr);
-                } else if (((instr >> 23) & 0x1F) == 0b00010) && (((instr >> 21) & 0x3) == 0) && (((instr >> 4) & 0xF) == 0b1001) {
-                    self.execute_arm_swp(bus, instr);
-                } else if (instr & 0x0FBF0FFF) == 0x010F0000
-                    || (instr & 0x0FBFF000) == 0x0320F000
-                    || (instr & 0x0FBFF000) == 0x0120F000
-                {
-                    self.execute_arm_psr_transfer(instr);
-                } else if (instr & 0x0E400090) == 0x00400090 && (((instr >> 4) & 0xF) != 0b1001) {
-                    self.execute_arm_halfword_transfer(bus, instr);
-                } else if top3 == 0b100 {
-                    self.execute_arm_block_transfer(bus, instr);
-                } else if top2 == 0 {
-                    let before_pc = self.pc();
-                    self.execute_arm_data_processing(instr);
-                    if self.pc() != before_pc { self.flush_pipeline(bus); }
-                } else if top3 == 0b101 {
-                    let cond = (instr >> 28) & 0xF;
-                    if self.condition_passed(cond) {
-                        let l = ((instr >> 24) & 1) != 0;
-                        let imm24 = instr & 0x00FF_FFFF;
-                        let offset = (((imm24 as i32) << 8) >> 6) as u32;
-                        let base = next_pc.wrapping_add(4);
-                        if l { self.regs[14] = base.wrapping_sub(4); }
-                        self.regs[15] = base.wrapping_add(offset);
-                        self.flush_pipeline(bus);
+                let format = ArmFormat::decode(instr);
+                let internal = self.arm_internal_cycles(format, instr);
+                match format {
+                    ArmFormat::DataProcessing => {
+                        self.execute_arm_data_processing(instr);
+                        if self.pc() != next_pc { self.flush_pipeline(bus); }
                     }
-                } else if top2 == 0b01 {
-                    self.execute_arm_single_data_transfer(bus, instr);
-                } else if (instr >> 24) & 0xF == 0xF {
-                    let cond = (instr >> 28) & 0xF;
-                    if self.condition_passed(cond) {
-                        let swi_num = (instr & 0xFF) as u8;
-                        self.handle_swi(bus, swi_num);
+                    ArmFormat::PsrTransfer => self.execute_arm_psr_transfer(instr),
+                    ArmFormat::Multiply => {
+                        self.execute_arm_multiply(instr);
+                        if self.pc() != next_pc { self.flush_pipeline(bus); }
+                    }
+                    ArmFormat::MultiplyLong => self.execute_arm_multiply_long(instr),
+                    ArmFormat::Swap => self.execute_arm_swp(bus, instr),
+                    ArmFormat::BranchExchange => self.execute_arm_branch_exchange(bus, instr),
+                    ArmFormat::HalfwordTransfer => self.execute_arm_halfword_transfer(bus, instr),
+                    ArmFormat::SingleDataTransfer => self.execute_arm_single_data_transfer(bus, instr),
+                    ArmFormat::BlockTransfer => self.execute_arm_block_transfer(bus, instr),
+                    ArmFormat::Branch => self.execute_arm_branch(bus, instr),
+                    ArmFormat::SoftwareInterrupt => {
+                        if self.condition_passed(instr >> 28) {
+                            self.handle_swi(bus, (instr & 0xFF) as u8);
+                        }
+                    }
+                    ArmFormat::Coprocessor | ArmFormat::Undefined => {
+                        if self.condition_passed(instr >> 28) {
+                            self.note_undefined(next_pc.wrapping_sub(4), instr, false);
+                            self.enter_exception(bus, Exception::Undefined);
+                        }
                     }
                 }
+                bus.idle(internal);
             }
             CpuState::Thumb => {
                 if !self.thumb_pipe.valid { self.reset_pipeline(bus); }
                 let instr = self.thumb_pipe.decode as u32;
                 let current_pc = self.pc();
+                if let Some(coverage) = &mut self.coverage {
+                    coverage.record_thumb(current_pc & !1, instr as u16);
+                }
+                if let Some(trace) = &mut self.trace {
+                    trace.record(TraceEntry { pc: current_pc & !1, opcode: instr, thumb: true });
+                }
                 let next_pc = (current_pc & !1).wrapping_add(2);
                 let new_decode = self.thumb_pipe.fetch as u32;
-                let new_fetch = bus.read16(next_pc.wrapping_add(2)) as u32;
+                let new_fetch = bus.fetch16(next_pc.wrapping_add(2)) as u32;
                 self.thumb_pipe.decode = new_decode as u16;
                 self.thumb_pipe.fetch = new_fetch as u16;
                 self.regs[15] = next_pc;
 
-                self.execute_thumb_instruction(bus, instr);
+                let format = ThumbFormat::decode(instr as u16);
+                let internal = self.thumb_internal_cycles(format, instr);
+                self.execute_thumb_instruction(bus, format, instr);
                 if self.pc() != next_pc {
                     self.flush_pipeline(bus);
                 }
+                bus.idle(internal);
             }
         }
     }
 }
 
+// The multiplier array retires 8 bits of the multiplier per cycle and stops
+// early once the remaining bits are all zeros, or all ones for signed
+// multiplies.
+fn multiply_cycles(multiplier: u32, signed: bool) -> u64 {
+    let m = if signed && (multiplier as i32) < 0 { !multiplier } else { multiplier };
+    match m {
+        0..=0xFF => 1,
+        0x100..=0xFFFF => 2,
+        0x1_0000..=0xFF_FFFF => 3,
+        _ => 4,
+    }
+}
+
 #[cfg(test)]
+#[allow(clippy::identity_op, clippy::assertions_on_constants)]
 mod tests {
     use super::*;
 
@@ -1858,6 +2024,7 @@ mod tests {
             self.mem[addr as usize]
         }
         fn write32(&mut self, addr: u32, value: u32) {
+            let addr = addr & !3;
             self.ensure_size(addr, 4);
             let a = addr as usize;
             self.mem[a] = (value & 0xFF) as u8;
@@ -1866,6 +2033,7 @@ mod tests {
             self.mem[a + 3] = ((value >> 24) & 0xFF) as u8;
         }
         fn write16(&mut self, addr: u32, value: u16) {
+            let addr = addr & !1;
             self.ensure_size(addr, 2);
             let a = addr as usize;
             self.mem[a] = (value & 0xFF) as u8;
@@ -1906,8 +2074,8 @@ mod tests {
         let mut cpu = Cpu::new();
         cpu.set_state(CpuState::Thumb);
         let mut bus = MockBus::new(64);
-        bus.mem[0] = 0x00;
-        bus.mem[1] = 0xB5; // push {lr} example encoding upper byte pattern, unused
+        bus.mem[0] = 0xC0;
+        bus.mem[1] = 0x46; // mov r8, r8 (the usual Thumb nop)
         cpu.set_pc(0);
         cpu.step(&mut bus);
         assert_eq!(cpu.pc(), 2);
@@ -1921,7 +2089,7 @@ mod tests {
 
         // MOV r1, #0x42 (Format 3: Move/Compare/Add/Subtract Immediate)
         // op=00 (MOV), rd=1, imm8=0x42
-        let mov_instr = (0x10 << 11) | (1 << 8) | 0x42;
+        let mov_instr = (0b00100 << 11) | (1 << 8) | 0x42;
         bus.write16(0, mov_instr as u16);
 
         cpu.set_pc(0);
@@ -1942,7 +2110,7 @@ mod tests {
 
         // ADD r1, r1, #0x20 (Format 3: Move/Compare/Add/Subtract Immediate)
         // op=10 (ADD), rd=1, imm8=0x20
-        let add_instr = (0x10 << 11) | (2 << 10) | (1 << 8) | 0x20;
+        let add_instr = (0b001 << 13) | (2 << 11) | (1 << 8) | 0x20;
         bus.write16(0, add_instr as u16);
 
         cpu.set_pc(0);
@@ -1986,7 +2154,7 @@ mod tests {
 
         // LDR r1, [r0, #8] (Format 9: Load/Store with Immediate Offset)
         // op=1 (LDR), imm5=2, rb=0, rd=1
-        let ldr_instr = (0x1D << 11) | (2 << 6) | (0 << 3) | 1;
+        let ldr_instr = (0b01101 << 11) | (2 << 6) | (0 << 3) | 1;
         bus.write16(0, ldr_instr as u16);
 
         cpu.set_pc(0);
@@ -1994,6 +2162,117 @@ mod tests {
         assert_eq!(cpu.read_reg(1), 0xDEADBEEF);
     }
 
+    #[test]
+    fn thumb_register_offset_loads_and_stores() {
+        let mut cpu = Cpu::new();
+        cpu.cpsr_mut().set_state(CpuState::Thumb);
+        let mut bus = MockBus::new(256);
+        cpu.write_reg(0, 0x80);
+        cpu.write_reg(1, 0x8);
+        // op in bits 11-9, ro = r1, rb = r0, rd = r2.
+        let op = |bits: u32| (0b0101 << 12) | (bits << 9) | (1 << 6) | 2;
+
+        cpu.write_reg(2, 0x1234_5678);
+        cpu.execute_thumb_load_store_register_offset(&mut bus, op(0b000)); // str
+        assert_eq!(bus.read32(0x88), 0x1234_5678);
+        cpu.write_reg(2, 0xAB);
+        cpu.execute_thumb_load_store_register_offset(&mut bus, op(0b010)); // strb
+        assert_eq!(bus.read32(0x88), 0x1234_56AB);
+        cpu.write_reg(2, 0xCDEF);
+        cpu.execute_thumb_load_store_sign_extended(&mut bus, op(0b001)); // strh
+        assert_eq!(bus.read32(0x88), 0x1234_CDEF);
+
+        cpu.execute_thumb_load_store_register_offset(&mut bus, op(0b100)); // ldr
+        assert_eq!(cpu.read_reg(2), 0x1234_CDEF);
+        cpu.execute_thumb_load_store_register_offset(&mut bus, op(0b110)); // ldrb
+        assert_eq!(cpu.read_reg(2), 0xEF);
+        cpu.execute_thumb_load_store_sign_extended(&mut bus, op(0b011)); // ldsb
+        assert_eq!(cpu.read_reg(2), 0xFFFF_FFEF);
+        cpu.execute_thumb_load_store_sign_extended(&mut bus, op(0b101)); // ldrh
+        assert_eq!(cpu.read_reg(2), 0xCDEF);
+        cpu.execute_thumb_load_store_sign_extended(&mut bus, op(0b111)); // ldsh
+        assert_eq!(cpu.read_reg(2), 0xFFFF_CDEF);
+    }
+
+    #[test]
+    fn thumb_register_offset_sub_ops_dispatch_by_bit_9() {
+        // (bits 11-9, value in r2 after the instruction, word at 0x88 after)
+        // with r2 = 0x8899_AABB and 0x1122_F344 at 0x88 before.
+        let cases = [
+            (0b000, 0x8899_AABB, 0x8899_AABB), // str
+            (0b001, 0x8899_AABB, 0x1122_AABB), // strh
+            (0b010, 0x8899_AABB, 0x1122_F3BB), // strb
+            (0b011, 0x0000_0044, 0x1122_F344), // ldsb
+            (0b100, 0x1122_F344, 0x1122_F344), // ldr
+            (0b101, 0x0000_F344, 0x1122_F344), // ldrh
+            (0b110, 0x0000_0044, 0x1122_F344), // ldrb
+            (0b111, 0xFFFF_F344, 0x1122_F344), // ldsh
+        ];
+        for (bits, r2, word) in cases {
+            let mut cpu = Cpu::new();
+            cpu.cpsr_mut().set_state(CpuState::Thumb);
+            let mut bus = MockBus::new(256);
+            // op [r0, r1] with rd = r2.
+            bus.write16(0, ((0b0101 << 12) | (bits << 9) | (1 << 6) | 2) as u16);
+            bus.write32(0x88, 0x1122_F344);
+            cpu.write_reg(0, 0x80);
+            cpu.write_reg(1, 0x8);
+            cpu.write_reg(2, 0x8899_AABB);
+            cpu.set_pc(0);
+            cpu.step(&mut bus);
+            assert_eq!(cpu.read_reg(2), r2, "op {:03b}", bits);
+            assert_eq!(bus.read32(0x88), word, "op {:03b}", bits);
+        }
+    }
+
+    #[test]
+    fn thumb_misaligned_register_offset_loads() {
+        let mut cpu = Cpu::new();
+        cpu.cpsr_mut().set_state(CpuState::Thumb);
+        let mut bus = MockBus::new(256);
+        bus.write32(0x80, 0x8877_6655);
+        cpu.write_reg(0, 0x80);
+        let op = |bits: u32| (0b0101 << 12) | (bits << 9) | (1 << 6) | 2;
+
+        // ldr rotates the aligned word; ldrh the aligned halfword.
+        cpu.write_reg(1, 1);
+        cpu.execute_thumb_load_store_register_offset(&mut bus, op(0b100));
+        assert_eq!(cpu.read_reg(2), 0x5588_7766);
+        cpu.write_reg(1, 3);
+        cpu.execute_thumb_load_store_register_offset(&mut bus, op(0b100));
+        assert_eq!(cpu.read_reg(2), 0x7766_5588);
+        cpu.write_reg(1, 1);
+        cpu.execute_thumb_load_store_sign_extended(&mut bus, op(0b101));
+        assert_eq!(cpu.read_reg(2), 0x5500_0066);
+        // ldsh from an odd address sign-extends the byte there.
+        cpu.write_reg(1, 3);
+        cpu.execute_thumb_load_store_sign_extended(&mut bus, op(0b111));
+        assert_eq!(cpu.read_reg(2), 0xFFFF_FF88);
+        cpu.write_reg(1, 2);
+        cpu.execute_thumb_load_store_sign_extended(&mut bus, op(0b111));
+        assert_eq!(cpu.read_reg(2), 0xFFFF_8877);
+        // Stores ignore the low address bits.
+        cpu.write_reg(1, 3);
+        cpu.write_reg(2, 0xAABB_CCDD);
+        cpu.execute_thumb_load_store_register_offset(&mut bus, op(0b000));
+        assert_eq!(bus.read32(0x80), 0xAABB_CCDD);
+        cpu.execute_thumb_load_store_sign_extended(&mut bus, op(0b001));
+        assert_eq!(bus.read32(0x80), 0xCCDD_CCDD);
+    }
+
+    #[test]
+    fn thumb_pop_pc_moves_sp_past_it() {
+        let mut cpu = Cpu::new();
+        cpu.cpsr_mut().set_state(CpuState::Thumb);
+        let mut bus = MockBus::new(256);
+        cpu.write_reg(13, 0x80);
+        bus.write32(0x80, 0x1111_1111);
+        bus.write32(0x84, 0x41);
+        cpu.execute_thumb_push_pop_registers(&mut bus, 0xBD01); // pop {r0, pc}
+        assert_eq!(cpu.read_reg(0), 0x1111_1111);
+        assert_eq!(cpu.read_reg(13), 0x88);
+    }
+
     #[test]
     fn thumb_bx_branch_exchange() {
         let mut cpu = Cpu::new();
@@ -2005,7 +2284,7 @@ mod tests {
 
         // BX r0 (Format 5: Hi Register Operations/Branch Exchange)
         // op=3 (BX), h1=0, h2=0, rs=0, rd=0
-        let bx_instr = (0x15 << 11) | (3 << 8) | (0 << 7) | (0 << 6) | (0 << 3) | 0;
+        let bx_instr = (0b010001 << 10) | (3 << 8) | (0 << 7) | (0 << 6) | (0 << 3) | 0;
         bus.write16(0, bx_instr as u16);
 
         cpu.set_pc(0);
@@ -2030,8 +2309,8 @@ mod tests {
 
         cpu.set_pc(0);
         cpu.step(&mut bus);
-        // PC should be 0 + 2 (advance) + 4*2 (offset) = 10
-        assert_eq!(cpu.pc(), 10);
+        // PC should be 0 + 4 (pipeline) + 4*2 (offset) = 12
+        assert_eq!(cpu.pc(), 12);
     }
 
     #[test]
@@ -2462,9 +2741,10 @@ mod tests {
         cpu.step(&mut bus);
         assert_eq!(cpu.read_reg(1), 0x4411_2233);
 
+        // Stores are not rotated, only aligned.
         cpu.step(&mut bus);
         let stored = bus.read32(0x40);
-        assert_eq!(stored, 0xDDAA_BBCC);
+        assert_eq!(stored, 0xAABB_CCDD);
     }
 
     #[test]
@@ -2548,6 +2828,37 @@ mod tests {
         assert_eq!(cpu.read_reg(3), 0xFFFF_FFF0);
     }
 
+    #[test]
+    fn arm_ldrh_register_offset_step_dispatch() {
+        let mut cpu = Cpu::new();
+        let mut bus = MockBus::new(64);
+        cpu.write_reg(1, 0x20);
+        cpu.write_reg(2, 0x10);
+        bus.mem[0x30] = 0x34;
+        bus.mem[0x31] = 0x12;
+        write32_le(&mut bus.mem, 0, 0xE19100B2); // ldrh r0, [r1, r2]
+        cpu.set_pc(0);
+        cpu.step(&mut bus);
+        assert_eq!(cpu.read_reg(0), 0x1234);
+    }
+
+    #[test]
+    fn arm_undefined_instruction_traps() {
+        let mut cpu = Cpu::new();
+        let mut bus = MockBus::new(64);
+        write32_le(&mut bus.mem, 0x10, 0xE7F000F0);
+        cpu.set_pc(0x10);
+        cpu.set_trace(Trace::new(4));
+        cpu.step(&mut bus);
+        assert_eq!(cpu.mode(), CpuMode::Undefined);
+        assert_eq!(cpu.read_reg(14), 0x14);
+        assert_eq!(cpu.pc(), 0x04);
+        let undefined = TraceEntry { pc: 0x10, opcode: 0xE7F000F0, thumb: false };
+        assert_eq!(cpu.take_undefined(), Some(undefined));
+        assert_eq!(cpu.take_undefined(), None);
+        assert_eq!(cpu.trace().unwrap().last(), Some(&undefined));
+    }
+
     #[test]
     fn arm_swp_and_swpb() {
         let mut cpu = Cpu::new();
@@ -2570,9 +2881,9 @@ mod tests {
     #[test]
     fn arm_psr_mrs_msr_flags() {
         let mut cpu = Cpu::new();
-        // MSR CPSR_f, #imm set N and C
-        let imm8 = 0b1010_0000; // N=1,C=1 after rotation 0
-        let msr_imm = (0xE << 28) | (0b00110 << 23) | (1 << 21) | (0xF << 16) | (0 << 8) | imm8;
+        // MSR CPSR_f, #0xA0000000 sets N and C
+        let imm8 = 0b1010_0000;
+        let msr_imm = (0xE << 28) | (0b00110 << 23) | (1 << 21) | (0x8 << 16) | (4 << 8) | imm8;
         cpu.execute_arm_psr_transfer(msr_imm);
         assert!(cpu.cpsr().n());
         assert!(cpu.cpsr().c());
@@ -2582,6 +2893,36 @@ mod tests {
         assert_eq!(cpu.read_reg(1) & 0xF000_0000, 0xA000_0000);
     }
 
+    #[test]
+    fn arm_msr_switches_modes_and_banks_registers() {
+        let mut cpu = Cpu::new();
+        cpu.set_mode(CpuMode::System);
+        cpu.write_reg(14, 0xA0);
+        // msr cpsr_c, #0xD3 (Supervisor), mov lr, #0, msr cpsr_c, #0x1F
+        cpu.execute_arm_psr_transfer(0xE321_F0D3);
+        assert_eq!(cpu.mode(), CpuMode::Supervisor);
+        assert!(cpu.cpsr().i());
+        cpu.write_reg(14, 0);
+        cpu.execute_arm_psr_transfer(0xE321_F01F);
+        assert_eq!(cpu.mode(), CpuMode::System);
+        assert_eq!(cpu.read_reg(14), 0xA0);
+
+        // msr spsr_fc, r0 / mrs r1, spsr in IRQ mode; System has no SPSR.
+        cpu.set_mode(CpuMode::Irq);
+        cpu.write_reg(0, 0x6000_0010);
+        cpu.execute_arm_psr_transfer(0xE169_F000);
+        cpu.execute_arm_psr_transfer(0xE14F_1000);
+        assert_eq!(cpu.read_reg(1), 0x6000_0010);
+        cpu.execute_arm_psr_transfer(0xE321_F01F);
+        cpu.execute_arm_psr_transfer(0xE14F_1000);
+        assert_eq!(cpu.read_reg(1), cpu.cpsr().raw());
+
+        // User mode can only change the flags.
+        cpu.execute_arm_psr_transfer(0xE321_F010);
+        cpu.execute_arm_psr_transfer(0xE329_F01F);
+        assert_eq!(cpu.mode(), CpuMode::User);
+    }
+
     #[test]
     fn arm_block_transfer_stmia_ldmia() {
         let mut cpu = Cpu::new();
@@ -2625,7 +2966,7 @@ mod tests {
         cpu.execute_arm_block_transfer(&mut bus, stmib);
         assert_eq!(bus.read32(0x204), 0x3333_3333);
         assert_eq!(bus.read32(0x208), 0x4444_4444);
-        assert_eq!(cpu.read_reg(0), 0x20C); // writeback enabled
+        assert_eq!(cpu.read_reg(0), 0x208); // writeback enabled
 
         // Test STMDA (Decrement After)
         cpu.write_reg(0, 0x300); // base
@@ -2634,8 +2975,8 @@ mod tests {
         let stmda = (0xE << 28) | (0b100 << 25) | (0 << 24) | (0 << 23) | (0 << 22) | (0 << 21) | (0 << 20)
             | (0 << 16) | ((1<<5)|(1<<6));
         cpu.execute_arm_block_transfer(&mut bus, stmda);
-        assert_eq!(bus.read32(0x2F8), 0x5555_5555);
-        assert_eq!(bus.read32(0x2FC), 0x6666_6666);
+        assert_eq!(bus.read32(0x2FC), 0x5555_5555);
+        assert_eq!(bus.read32(0x300), 0x6666_6666);
         assert_eq!(cpu.read_reg(0), 0x300); // no writeback
 
         // Test STMDB (Decrement Before) with writeback
@@ -2645,9 +2986,9 @@ mod tests {
         let stmdb = (0xE << 28) | (0b100 << 25) | (1 << 24) | (0 << 23) | (0 << 22) | (1 << 21) | (0 << 20)
             | (0 << 16) | ((1<<7)|(1<<8));
         cpu.execute_arm_block_transfer(&mut bus, stmdb);
-        assert_eq!(bus.read32(0x3F4), 0x7777_7777); // r7 at start address
-        assert_eq!(bus.read32(0x3F8), 0x8888_8888); // r8 at start address + 4
-        assert_eq!(cpu.read_reg(0), 0x3F4); // writeback enabled
+        assert_eq!(bus.read32(0x3F8), 0x7777_7777); // r7 at start address
+        assert_eq!(bus.read32(0x3FC), 0x8888_8888); // r8 at start address + 4
+        assert_eq!(cpu.read_reg(0), 0x3F8); // writeback enabled
     }
 
     #[test]
@@ -2657,7 +2998,7 @@ mod tests {
 
         // Test STM with PC (should store PC+12)
         cpu.write_reg(0, 0x100); // base
-        cpu.set_pc(0x1000);
+        cpu.set_pc(0x1004); // executing at 0x1000; r15 holds the next instruction
         let stm_pc = (0xE << 28) | (0b100 << 25) | (0 << 24) | (1 << 23) | (0 << 22) | (0 << 21) | (0 << 20)
             | (0 << 16) | (1<<15); // store PC
         cpu.execute_arm_block_transfer(&mut bus, stm_pc);
@@ -2687,7 +3028,7 @@ mod tests {
 
         // Test STM with empty list (should store PC+12 to address)
         cpu.write_reg(0, 0x200); // base
-        cpu.set_pc(0x4000);
+        cpu.set_pc(0x4004); // executing at 0x4000
         let stm_empty = (0xE << 28) | (0b100 << 25) | (0 << 24) | (1 << 23) | (0 << 22) | (0 << 21) | (0 << 20)
             | (0 << 16) | 0; // empty register list
         cpu.execute_arm_block_transfer(&mut bus, stm_empty);
@@ -2714,7 +3055,7 @@ mod tests {
         let stmib_wb = (0xE << 28) | (0b100 << 25) | (1 << 24) | (1 << 23) | (0 << 22) | (1 << 21) | (0 << 20)
             | (0 << 16) | (1<<3);
         cpu.execute_arm_block_transfer(&mut bus, stmib_wb);
-        assert_eq!(cpu.read_reg(0), 0x208); // base + 4 + 1*4
+        assert_eq!(cpu.read_reg(0), 0x204); // base + 1*4
 
         // Test STMDA with writeback
         cpu.write_reg(0, 0x300); // base
@@ -2731,7 +3072,7 @@ mod tests {
         let stmdb_wb = (0xE << 28) | (0b100 << 25) | (1 << 24) | (0 << 23) | (0 << 22) | (1 << 21) | (0 << 20)
             | (0 << 16) | (1<<6);
         cpu.execute_arm_block_transfer(&mut bus, stmdb_wb);
-        assert_eq!(cpu.read_reg(0), 0x3F8); // base - 4 - 1*4
+        assert_eq!(cpu.read_reg(0), 0x3FC); // base - 1*4
     }
 
     #[test]
@@ -2744,7 +3085,7 @@ mod tests {
         cpu.write_reg(1, 0x1111_1111);
         cpu.write_reg(3, 0x3333_3333);
         cpu.write_reg(7, 0x7777_7777);
-        cpu.set_pc(0x1000); // Set PC to a known value
+        cpu.set_pc(0x1004); // executing at 0x1000
 
         // Register list: r1, r3, r7, r15 (not in bit order)
         let reg_list = (1<<1) | (1<<3) | (1<<7) | (1<<15);
@@ -2759,6 +3100,199 @@ mod tests {
         assert_eq!(bus.read32(0x10C), 0x100C); // r15 (PC+12)
     }
 
+    #[test]
+    fn arm_block_transfer_base_in_list_and_psr_restore() {
+        let mut cpu = Cpu::new();
+        let mut bus = MockBus::new(256);
+
+        // ldmia r0!, {r0, r1}: the loaded base wins over the writeback.
+        cpu.write_reg(0, 0x40);
+        bus.write32(0x40, 0xAAAA_AAAA);
+        bus.write32(0x44, 0xBBBB_BBBB);
+        cpu.execute_arm_block_transfer(&mut bus, 0xE8B0_0003);
+        assert_eq!(cpu.read_reg(0), 0xAAAA_AAAA);
+        assert_eq!(cpu.read_reg(1), 0xBBBB_BBBB);
+
+        // stmia r0!, {r0, r1} stores the old base; stmia r1!, {r0, r1} the new one.
+        cpu.write_reg(0, 0x80);
+        cpu.execute_arm_block_transfer(&mut bus, 0xE8A0_0003);
+        assert_eq!(bus.read32(0x80), 0x80);
+        cpu.write_reg(1, 0x90);
+        cpu.execute_arm_block_transfer(&mut bus, 0xE8A1_0003);
+        assert_eq!(bus.read32(0x94), 0x98);
+
+        // ldmfd sp!, {pc}^ returns from an exception with the SPSR.
+        cpu.set_mode(CpuMode::Irq);
+        cpu.set_spsr(0x6000_001F);
+        cpu.write_reg(13, 0xC0);
+        bus.write32(0xC0, 0x100);
+        cpu.execute_arm_block_transfer(&mut bus, 0xE8FD_8000);
+        assert_eq!(cpu.mode(), CpuMode::System);
+        assert_eq!(cpu.cpsr().raw(), 0x6000_001F);
+        assert_eq!(cpu.read_reg(15), 0x100);
+    }
+
+    // An LDM/STM with the P, U, S, W and L bits given as 0 or 1.
+    fn block_transfer(p: u32, u: u32, s: u32, w: u32, l: u32, rn: u32, list: u32) -> u32 {
+        0xE800_0000 | p << 24 | u << 23 | s << 22 | w << 21 | l << 20 | rn << 16 | list
+    }
+
+    #[test]
+    fn arm_block_transfer_every_addressing_mode() {
+        // (P, U, lowest address, written-back base) for r1-r3 from base 0x100.
+        let modes = [(0, 1, 0x100, 0x10C), (1, 1, 0x104, 0x10C), (0, 0, 0xF8, 0xF4), (1, 0, 0xF4, 0xF4)];
+        for (p, u, low, new_base) in modes {
+            for w in 0..2 {
+                let mut cpu = Cpu::new();
+                let mut bus = MockBus::new(0x200);
+                cpu.write_reg(0, 0x100);
+                for reg in 1..4 {
+                    cpu.write_reg(reg, 0x1111_1111 * reg as u32);
+                }
+                cpu.execute_arm_block_transfer(&mut bus, block_transfer(p, u, 0, w, 0, 0, 0b1110));
+                for (i, reg) in (1..4).enumerate() {
+                    assert_eq!(bus.read32(low + i as u32 * 4), 0x1111_1111 * reg, "store P={} U={}", p, u);
+                }
+                assert_eq!(cpu.read_reg(0), if w == 1 { new_base } else { 0x100 });
+
+                cpu.write_reg(0, 0x100);
+                for reg in 4..7 {
+                    cpu.write_reg(reg, 0);
+                }
+                cpu.execute_arm_block_transfer(&mut bus, block_transfer(p, u, 0, w, 1, 0, 0b111_0000));
+                for reg in 4..7 {
+                    assert_eq!(cpu.read_reg(reg), 0x1111_1111 * (reg as u32 - 3), "load P={} U={}", p, u);
+                }
+                assert_eq!(cpu.read_reg(0), if w == 1 { new_base } else { 0x100 });
+            }
+        }
+    }
+
+    #[test]
+    fn arm_block_transfer_empty_list_moves_the_base_by_0x40() {
+        // (P, U, address r15 goes to, written-back base) from base 0x100.
+        let modes = [(0, 1, 0x100, 0x140), (1, 1, 0x104, 0x140), (0, 0, 0xC4, 0xC0), (1, 0, 0xC0, 0xC0)];
+        for (p, u, addr, new_base) in modes {
+            let mut cpu = Cpu::new();
+            let mut bus = MockBus::new(0x200);
+            cpu.write_reg(0, 0x100);
+            cpu.set_pc(0x1004);
+            cpu.execute_arm_block_transfer(&mut bus, block_transfer(p, u, 0, 1, 0, 0, 0));
+            assert_eq!(bus.read32(addr), 0x100C, "P={} U={}", p, u);
+            assert_eq!(cpu.read_reg(0), new_base);
+
+            cpu.write_reg(0, 0x100);
+            bus.write32(addr, 0x0000_0800);
+            cpu.execute_arm_block_transfer(&mut bus, block_transfer(p, u, 0, 1, 1, 0, 0));
+            assert_eq!(cpu.pc(), 0x800);
+            assert_eq!(cpu.read_reg(0), new_base);
+        }
+    }
+
+    #[test]
+    fn arm_block_transfer_base_in_list_per_addressing_mode() {
+        for (p, u) in [(0, 1), (1, 1), (0, 0), (1, 0)] {
+            let mut cpu = Cpu::new();
+            let mut bus = MockBus::new(0x200);
+            let new_base = if u == 1 { 0x108 } else { 0xF8 };
+            let low = [0x100, 0x104, 0xFC, 0xF8][(p + 2 * (1 - u)) as usize];
+
+            // First in the list: the old base is stored.
+            cpu.write_reg(2, 0x100);
+            cpu.write_reg(5, 0x55);
+            cpu.execute_arm_block_transfer(&mut bus, block_transfer(p, u, 0, 1, 0, 2, 1 << 2 | 1 << 5));
+            assert_eq!([bus.read32(low), bus.read32(low + 4)], [0x100, 0x55], "P={} U={}", p, u);
+            assert_eq!(cpu.read_reg(2), new_base);
+
+            // Later in the list: the written-back base is stored.
+            cpu.write_reg(2, 0x100);
+            cpu.write_reg(1, 0x11);
+            cpu.execute_arm_block_transfer(&mut bus, block_transfer(p, u, 0, 1, 0, 2, 1 << 1 | 1 << 2));
+            assert_eq!([bus.read32(low), bus.read32(low + 4)], [0x11, new_base], "P={} U={}", p, u);
+
+            // Loaded: the loaded value wins over the writeback, with or
+            // without W.
+            for w in 0..2 {
+                cpu.write_reg(2, 0x100);
+                bus.write32(low, 0xAAAA);
+                bus.write32(low + 4, 0xBBBB);
+                cpu.execute_arm_block_transfer(&mut bus, block_transfer(p, u, 0, w, 1, 2, 1 << 1 | 1 << 2));
+                assert_eq!([cpu.read_reg(1), cpu.read_reg(2)], [0xAAAA, 0xBBBB], "P={} U={} W={}", p, u, w);
+            }
+        }
+    }
+
+    #[test]
+    fn arm_block_transfer_s_bit_uses_the_user_bank() {
+        let mut cpu = Cpu::new();
+        let mut bus = MockBus::new(0x200);
+        cpu.set_mode(CpuMode::User);
+        for reg in 8..15 {
+            cpu.write_reg(reg, 0x100 + reg as u32);
+        }
+        cpu.set_mode(CpuMode::Fiq);
+        for reg in 8..15 {
+            cpu.write_reg(reg, 0xF00 + reg as u32);
+        }
+
+        // stmia r0!, {r8-r14}^ stores the User registers and writes back the
+        // current mode's base.
+        cpu.write_reg(0, 0x40);
+        cpu.execute_arm_block_transfer(&mut bus, block_transfer(0, 1, 1, 1, 0, 0, 0x7F00));
+        for (i, reg) in (8..15).enumerate() {
+            assert_eq!(bus.read32(0x40 + i as u32 * 4), 0x100 + reg);
+        }
+        assert_eq!(cpu.read_reg(0), 0x5C);
+        assert_eq!(cpu.mode(), CpuMode::Fiq);
+
+        // ldmdb r0, {r8-r14}^ loads into the User bank and leaves FIQ's alone.
+        for (i, reg) in (8..15).enumerate() {
+            bus.write32(0x40 + i as u32 * 4, 0x200 + reg);
+        }
+        cpu.execute_arm_block_transfer(&mut bus, block_transfer(1, 0, 1, 0, 1, 0, 0x7F00));
+        for reg in 8..15 {
+            assert_eq!(cpu.read_reg(reg), 0xF00 + reg as u32);
+        }
+        cpu.set_mode(CpuMode::User);
+        for reg in 8..15 {
+            assert_eq!(cpu.read_reg(reg), 0x200 + reg as u32);
+        }
+    }
+
+    #[test]
+    fn arm_ldm_pc_with_s_bit_restores_the_cpsr() {
+        let mut cpu = Cpu::new();
+        let mut bus = MockBus::new(0x200);
+
+        // ldmfd sp!, {r0, pc}^ back to Thumb code: r0 comes from the IRQ
+        // mode's view, then the SPSR replaces the CPSR.
+        cpu.set_mode(CpuMode::Supervisor);
+        cpu.write_reg(13, 0x1F00);
+        cpu.set_mode(CpuMode::Irq);
+        cpu.set_spsr(0x2000_0033);
+        cpu.write_reg(13, 0x80);
+        bus.write32(0x80, 0x1234);
+        bus.write32(0x84, 0x0000_0123);
+        cpu.execute_arm_block_transfer(&mut bus, block_transfer(0, 1, 1, 1, 1, 13, 1 << 0 | 1 << 15));
+        assert_eq!(cpu.cpsr().raw(), 0x2000_0033);
+        assert_eq!(cpu.mode(), CpuMode::Supervisor);
+        assert_eq!(cpu.state(), CpuState::Thumb);
+        assert_eq!(cpu.read_reg(0), 0x1234);
+        assert_eq!(cpu.read_reg(13), 0x1F00);
+        assert_eq!(cpu.pc(), 0x122);
+        cpu.set_mode(CpuMode::Irq);
+        assert_eq!(cpu.read_reg(13), 0x88);
+
+        // Without S, the state stays and the low bits of the address are
+        // ignored.
+        let mut cpu = Cpu::new();
+        cpu.write_reg(0, 0x80);
+        bus.write32(0x80, 0x0000_0803);
+        cpu.execute_arm_block_transfer(&mut bus, block_transfer(0, 1, 0, 0, 1, 0, 1 << 15));
+        assert_eq!(cpu.state(), CpuState::Arm);
+        assert_eq!(cpu.pc(), 0x800);
+    }
+
     #[test]
     fn thumb_pipeline_advancement() {
         let mut cpu = Cpu::new();
@@ -2766,9 +3300,9 @@ mod tests {
         let mut bus = MockBus::new(128);
 
         // Write three instructions
-        let mov_r1 = (0x10 << 11) | (1 << 8) | 0x01; // MOV r1, #1
-        let mov_r2 = (0x10 << 11) | (2 << 8) | 0x02; // MOV r2, #2
-        let mov_r3 = (0x10 << 11) | (3 << 8) | 0x03; // MOV r3, #3
+        let mov_r1 = (0b00100 << 11) | (1 << 8) | 0x01; // MOV r1, #1
+        let mov_r2 = (0b00100 << 11) | (2 << 8) | 0x02; // MOV r2, #2
+        let mov_r3 = (0b00100 << 11) | (3 << 8) | 0x03; // MOV r3, #3
         bus.write16(0, mov_r1 as u16);
         bus.write16(2, mov_r2 as u16);
         bus.write16(4, mov_r3 as u16);
@@ -2802,7 +3336,7 @@ mod tests {
         cpu.set_pc(0);
         cpu.write_reg(0, 0x1000);
         // BX r0 to switch to ARM mode
-        let bx = (0x15 << 11) | (3 << 8) | (0 << 7) | (0 << 6) | (0 << 3) | 0;
+        let bx = (0b010001 << 10) | (3 << 8) | (0 << 7) | (0 << 6) | (0 << 3) | 0;
         bus.write16(0, bx as u16);
 
         cpu.set_pc(0);
@@ -2978,6 +3512,114 @@ mod tests {
         assert!(!cpu.cpsr().f());
     }
 
+    const ARM_NOP: u32 = 0xE1A0_0000;
+    const ARM_BX_LR: u32 = 0xE12F_FF1E;
+
+    // A CPU without BIOS running ARM code from EWRAM, with its IRQ handler
+    // at 0x02000100.
+    fn hle_cpu(bus: &mut crate::bus::Bus, code: &[u32]) -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.set_swi_hle(true);
+        cpu.set_mode(CpuMode::Irq);
+        cpu.write_reg(13, 0x0300_7FA0);
+        cpu.set_mode(CpuMode::System);
+        cpu.cpsr_mut().set_i(false);
+        for (i, &word) in code.iter().chain(&[ARM_NOP; 4]).enumerate() {
+            bus.write32(0x0200_0000 + i as u32 * 4, word);
+        }
+        bus.write32(0x0200_0100, ARM_BX_LR);
+        bus.write32(BIOS_IRQ_HANDLER, 0x0200_0100);
+        cpu.set_entry_point(bus, 0x0200_0000);
+        cpu
+    }
+
+    #[test]
+    fn hle_irq_calls_the_user_handler_and_returns() {
+        let mut bus = crate::bus::Bus::new();
+        let mut cpu = hle_cpu(&mut bus, &[ARM_NOP, ARM_NOP]);
+        cpu.step(&mut bus);
+        cpu.write_reg(0, 0x1234);
+        cpu.write_reg(14, 0x5678);
+
+        cpu.trigger_irq(&mut bus);
+        assert_eq!(cpu.mode(), CpuMode::Irq);
+        assert_eq!(cpu.pc(), 0x0200_0100);
+        assert_eq!(cpu.read_reg(0), 0x0400_0000);
+        assert_eq!(cpu.read_reg(14), HLE_IRQ_RETURN);
+
+        cpu.step(&mut bus);
+        cpu.step(&mut bus);
+        assert_eq!(cpu.mode(), CpuMode::System);
+        assert!(!cpu.cpsr().i());
+        assert_eq!(cpu.pc(), 0x0200_0004);
+        assert_eq!([cpu.read_reg(0), cpu.read_reg(14)], [0x1234, 0x5678]);
+        cpu.set_mode(CpuMode::Irq);
+        assert_eq!(cpu.read_reg(13), 0x0300_7FA0);
+    }
+
+    #[test]
+    fn hle_math_swis_set_the_bios_registers() {
+        let mut bus = crate::bus::Bus::new();
+        // swi 0x06; swi 0x07; swi 0x08; swi 0x0A
+        let mut cpu = hle_cpu(&mut bus, &[0xEF00_0006, 0xEF00_0007, 0xEF00_0008, 0xEF00_000A]);
+        cpu.write_reg(0, -7i32 as u32);
+        cpu.write_reg(1, 2);
+        cpu.step(&mut bus);
+        assert_eq!([cpu.read_reg(0), cpu.read_reg(1), cpu.read_reg(3)], [-3i32 as u32, -1i32 as u32, 3]);
+
+        // DivArm: the denominator comes in r0.
+        cpu.write_reg(0, 2);
+        cpu.write_reg(1, -7i32 as u32);
+        cpu.step(&mut bus);
+        assert_eq!([cpu.read_reg(0), cpu.read_reg(1), cpu.read_reg(3)], [-3i32 as u32, -1i32 as u32, 3]);
+
+        cpu.write_reg(0, 1000);
+        cpu.step(&mut bus);
+        assert_eq!(cpu.read_reg(0), 31);
+
+        cpu.write_reg(0, 100);
+        cpu.write_reg(1, 1);
+        cpu.step(&mut bus);
+        assert_eq!(cpu.read_reg(0), 0x67);
+    }
+
+    #[test]
+    fn hle_vblank_intr_wait_halts_until_the_flag_is_set() {
+        let mut bus = crate::bus::Bus::new();
+        // swi 0x05
+        let mut cpu = hle_cpu(&mut bus, &[0xEF00_0005]);
+        bus.io.ie = 1;
+        // Flags raised before the call are discarded.
+        bus.write16(BIOS_IF, 1);
+        cpu.step(&mut bus);
+        assert!(bus.io.is_halted());
+        assert_eq!(bus.io.ime, 1);
+        assert_eq!(bus.read16(BIOS_IF), 0);
+        assert_eq!(cpu.pc(), 0x0200_0000);
+
+        // An interrupt other than VBlank wakes the CPU but the wait goes on.
+        bus.io.ie = 3;
+        bus.io.request_interrupt(2);
+        cpu.trigger_irq(&mut bus);
+        bus.write16(BIOS_IF, 2);
+        cpu.step(&mut bus);
+        cpu.step(&mut bus);
+        bus.io.if_ = 0;
+        cpu.step(&mut bus);
+        assert!(bus.io.is_halted());
+        assert_eq!(cpu.pc(), 0x0200_0000);
+
+        bus.io.request_interrupt(1);
+        cpu.trigger_irq(&mut bus);
+        bus.write16(BIOS_IF, 3);
+        cpu.step(&mut bus);
+        cpu.step(&mut bus);
+        cpu.step(&mut bus);
+        assert!(!bus.io.is_halted());
+        assert_eq!(cpu.pc(), 0x0200_0004);
+        assert_eq!(bus.read16(BIOS_IF), 2);
+    }
+
     #[test]
     fn irq_not_triggered_when_disabled() {
         let mut cpu = Cpu::new();
@@ -3048,4 +3690,174 @@ mod tests {
         assert_eq!(cpu.mode(), CpuMode::System);
         assert_eq!(cpu.pc(), 0x104);
     }
+
+    fn thumb_at(bus: &mut MockBus, addr: u32, program: &[u16]) -> Cpu {
+        for (i, &instr) in program.iter().enumerate() {
+            bus.write16(addr + 2 * i as u32, instr);
+        }
+        let mut cpu = Cpu::new();
+        cpu.set_state(CpuState::Thumb);
+        cpu.set_pc(addr);
+        cpu
+    }
+
+    #[test]
+    fn thumb_bl_survives_an_irq_between_halves() {
+        let mut bus = MockBus::new(0x300);
+        write32_le(&mut bus.mem, 0x18, 0xE25E_F004); // subs pc, lr, #4
+        // bl 0x200 from 0x100
+        let mut cpu = thumb_at(&mut bus, 0x100, &[0xF000, 0xF87E]);
+        cpu.cpsr_mut().set_i(false);
+
+        cpu.step(&mut bus);
+        assert_eq!(cpu.read_reg(14), 0x104);
+        assert!(cpu.trigger_irq(&mut bus));
+        assert_eq!(cpu.mode(), CpuMode::Irq);
+        assert_eq!(cpu.read_reg(14), 0x106);
+
+        cpu.step(&mut bus);
+        assert_eq!(cpu.mode(), CpuMode::System);
+        assert_eq!(cpu.state(), CpuState::Thumb);
+        assert_eq!(cpu.pc(), 0x102);
+        assert_eq!(cpu.read_reg(14), 0x104);
+
+        cpu.step(&mut bus);
+        assert_eq!(cpu.pc(), 0x200);
+        assert_eq!(cpu.read_reg(14), 0x105);
+    }
+
+    #[test]
+    fn thumb_bl_reaches_far_backwards() {
+        let mut bus = MockBus::new(0x2010);
+        // bl 0x1000 from 0x2000: the first half carries offset bits 22-12
+        let mut cpu = thumb_at(&mut bus, 0x2000, &[0xF7FE, 0xFFFE]);
+        cpu.step(&mut bus);
+        assert_eq!(cpu.read_reg(14), 0x0004);
+        cpu.step(&mut bus);
+        assert_eq!(cpu.pc(), 0x1000);
+        assert_eq!(cpu.read_reg(14), 0x2005);
+    }
+
+    #[test]
+    fn thumb_reads_pc_as_instruction_plus_4() {
+        let mut bus = MockBus::new(0x200);
+        write32_le(&mut bus.mem, 0x10C, 0xCAFE_F00D);
+        let mut cpu = thumb_at(&mut bus, 0x100, &[
+            0x46C0, // nop
+            0x4802, // 0x102: ldr r0, [pc, #8], from (0x106 & !3) + 8
+            0xA102, // 0x104: add r1, pc, #8
+            0x467A, // 0x106: mov r2, pc
+            0xE7FE, // 0x108: b .
+        ]);
+        for _ in 0..6 {
+            cpu.step(&mut bus);
+        }
+        assert_eq!(cpu.read_reg(0), 0xCAFE_F00D);
+        assert_eq!(cpu.read_reg(1), 0x110);
+        assert_eq!(cpu.read_reg(2), 0x10A);
+        assert_eq!(cpu.pc(), 0x108);
+    }
+
+    #[test]
+    fn arm_reads_pc_as_instruction_plus_8_or_12() {
+        let mut bus = MockBus::new(0x200);
+        for (i, instr) in [
+            0xE1A0_000F, // 0x100: mov r0, pc
+            0xE1A0_121F, // 0x104: mov r1, pc, lsl r2
+            0xE584_F000, // 0x108: str pc, [r4]
+            0xE885_8000, // 0x10C: stmia r5, {pc}
+            0xE59F_6000, // 0x110: ldr r6, [pc]
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            write32_le(&mut bus.mem, 0x100 + 4 * i, instr);
+        }
+        write32_le(&mut bus.mem, 0x118, 0x1234_5678);
+        let mut cpu = Cpu::new();
+        cpu.write_reg(4, 0x180);
+        cpu.write_reg(5, 0x184);
+        cpu.set_pc(0x100);
+        for _ in 0..5 {
+            cpu.step(&mut bus);
+        }
+        assert_eq!(cpu.read_reg(0), 0x108);
+        assert_eq!(cpu.read_reg(1), 0x110);
+        assert_eq!(bus.read32(0x180), 0x114);
+        assert_eq!(bus.read32(0x184), 0x118);
+        assert_eq!(cpu.read_reg(6), 0x1234_5678);
+    }
+
+    // Cycles `step` reports for one instruction run from IWRAM, where every
+    // access takes a single cycle, with r1 and r13 pointing at IWRAM data.
+    fn iwram_cycles(thumb: bool, instr: u32, regs: &[(usize, u32)]) -> u64 {
+        let mut bus = crate::bus::Bus::new();
+        let mut cpu = Cpu::new();
+        if thumb {
+            bus.write16(0x0300_0000, instr as u16);
+            cpu.set_state(CpuState::Thumb);
+        } else {
+            bus.write32(0x0300_0000, instr);
+        }
+        cpu.write_reg(1, 0x0300_0100);
+        cpu.write_reg(13, 0x0300_0100);
+        for &(r, value) in regs {
+            cpu.write_reg(r, value);
+        }
+        cpu.set_entry_point(&mut bus, 0x0300_0000);
+        bus.take_cycles();
+        cpu.step(&mut bus)
+    }
+
+    #[test]
+    fn arm_cycles_by_instruction_class() {
+        let arm = |instr, regs: &[(usize, u32)]| iwram_cycles(false, instr, regs);
+        // mov r0, r1 / mov r0, r1, lsl r2
+        assert_eq!(arm(0xE1A0_0001, &[]), 1);
+        assert_eq!(arm(0xE1A0_0211, &[]), 2);
+        // mul r0, r1, r2: the multiplier's significant bytes set the time.
+        assert_eq!(arm(0xE000_0291, &[(2, 0x10)]), 2);
+        assert_eq!(arm(0xE000_0291, &[(2, 0x1234)]), 3);
+        assert_eq!(arm(0xE000_0291, &[(2, 0x12_3456)]), 4);
+        assert_eq!(arm(0xE000_0291, &[(2, 0x1234_5678)]), 5);
+        assert_eq!(arm(0xE000_0291, &[(2, 0xFFFF_FFF0)]), 2);
+        // mla r0, r1, r2, r3
+        assert_eq!(arm(0xE020_3291, &[(2, 0x10)]), 3);
+        // umull/smull r0, r3, r1, r2: only signed multiplies stop early on ones.
+        assert_eq!(arm(0xE083_0291, &[(2, 0xFFFF_FFFF)]), 6);
+        assert_eq!(arm(0xE0C3_0291, &[(2, 0xFFFF_FFFF)]), 3);
+        // umlal r0, r3, r1, r2
+        assert_eq!(arm(0xE0A3_0291, &[(2, 0x10)]), 4);
+        // muleq r0, r1, r2 with Z clear only fetches.
+        assert_eq!(arm(0x0000_0291, &[(2, 0x1234_5678)]), 1);
+        // ldr r0, [r1] / str r0, [r1] / ldmia r1, {r2-r5} / stmia r1, {r2-r5}
+        assert_eq!(arm(0xE591_0000, &[]), 3);
+        assert_eq!(arm(0xE581_0000, &[]), 2);
+        assert_eq!(arm(0xE891_003C, &[]), 6);
+        assert_eq!(arm(0xE881_003C, &[]), 5);
+        // swp r0, r2, [r1]
+        assert_eq!(arm(0xE101_0092, &[]), 4);
+        // b +8 refills the pipeline.
+        assert_eq!(arm(0xEA00_0000, &[]), 3);
+    }
+
+    #[test]
+    fn thumb_cycles_by_instruction_class() {
+        let thumb = |instr, regs: &[(usize, u32)]| iwram_cycles(true, instr, regs);
+        // lsl r0, r1 (register shift) / lsl r0, r1, #1
+        assert_eq!(thumb(0x4088, &[]), 2);
+        assert_eq!(thumb(0x0048, &[]), 1);
+        // mul r0, r1 times by r0.
+        assert_eq!(thumb(0x4348, &[(0, 0x1_2345)]), 4);
+        // ldr r0, [r1] / str r0, [r1] / ldrh r0, [r1, r2] / strh r0, [r1, r2]
+        assert_eq!(thumb(0x6808, &[]), 3);
+        assert_eq!(thumb(0x6008, &[]), 2);
+        assert_eq!(thumb(0x5A88, &[(2, 0)]), 3);
+        assert_eq!(thumb(0x5288, &[(2, 0)]), 2);
+        // pop {r0, r1} / push {r0, r1}
+        assert_eq!(thumb(0xBC03, &[]), 4);
+        assert_eq!(thumb(0xB403, &[]), 3);
+        // b +4
+        assert_eq!(thumb(0xE000, &[]), 3);
+    }
 }
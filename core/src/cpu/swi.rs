@@ -0,0 +1,125 @@
+// BIOS calls: the names of the SWI functions and per-frame counts of the
+// calls a game makes, whether the BIOS or the HLE handlers serve them. Used
+// to check the HLE against what games actually need. Counting is always on;
+// logging each call is enabled with `Cpu::set_swi_log`.
+
+const NAMES: [&str; 0x2B] = [
+    "SoftReset",
+    "RegisterRamReset",
+    "Halt",
+    "Stop",
+    "IntrWait",
+    "VBlankIntrWait",
+    "Div",
+    "DivArm",
+    "Sqrt",
+    "ArcTan",
+    "ArcTan2",
+    "CpuSet",
+    "CpuFastSet",
+    "GetBiosChecksum",
+    "BgAffineSet",
+    "ObjAffineSet",
+    "BitUnPack",
+    "LZ77UnCompWram",
+    "LZ77UnCompVram",
+    "HuffUnComp",
+    "RLUnCompWram",
+    "RLUnCompVram",
+    "Diff8bitUnFilterWram",
+    "Diff8bitUnFilterVram",
+    "Diff16bitUnFilter",
+    "SoundBias",
+    "SoundDriverInit",
+    "SoundDriverMode",
+    "SoundDriverMain",
+    "SoundDriverVSync",
+    "SoundChannelClear",
+    "MidiKey2Freq",
+    "SoundWhatever0",
+    "SoundWhatever1",
+    "SoundWhatever2",
+    "SoundWhatever3",
+    "SoundWhatever4",
+    "MultiBoot",
+    "HardReset",
+    "CustomHalt",
+    "SoundDriverVSyncOff",
+    "SoundDriverVSyncOn",
+    "SoundGetJumpList",
+];
+
+/// The BIOS function SWI `number` calls, e.g. "CpuSet" for 0x0B.
+pub fn swi_name(number: u8) -> Option<&'static str> { NAMES.get(number as usize).copied() }
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SwiStats {
+    // Calls per SWI number in the frame running now, the last complete frame
+    // and since the counts were cleared.
+    current: [u32; 256],
+    last_frame: [u32; 256],
+    total: [u64; 256],
+}
+
+impl Default for SwiStats {
+    fn default() -> Self { Self { current: [0; 256], last_frame: [0; 256], total: [0; 256] } }
+}
+
+impl SwiStats {
+    pub fn new() -> Self { Self::default() }
+
+    pub(crate) fn record(&mut self, number: u8) {
+        self.current[number as usize] += 1;
+        self.total[number as usize] += 1;
+    }
+
+    pub(crate) fn end_frame(&mut self) { self.last_frame = std::mem::replace(&mut self.current, [0; 256]); }
+
+    /// The SWIs called during the last complete frame with their call
+    /// counts, most called first.
+    pub fn last_frame(&self) -> Vec<(u8, u32)> { histogram(&self.last_frame) }
+
+    /// Like `last_frame`, since the counts were last cleared.
+    pub fn totals(&self) -> Vec<(u8, u64)> { histogram(&self.total) }
+
+    pub fn clear(&mut self) { *self = Self::default(); }
+}
+
+fn histogram<T: Copy + Ord + Default>(counts: &[T; 256]) -> Vec<(u8, T)> {
+    let mut calls: Vec<(u8, T)> =
+        counts.iter().enumerate().map(|(i, &n)| (i as u8, n)).filter(|&(_, n)| n != T::default()).collect();
+    calls.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    calls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_follow_the_bios_table() {
+        assert_eq!(swi_name(0x00), Some("SoftReset"));
+        assert_eq!(swi_name(0x0B), Some("CpuSet"));
+        assert_eq!(swi_name(0x1F), Some("MidiKey2Freq"));
+        assert_eq!(swi_name(0x2A), Some("SoundGetJumpList"));
+        assert_eq!(swi_name(0x2B), None);
+    }
+
+    #[test]
+    fn counts_calls_per_frame_and_in_total() {
+        let mut stats = SwiStats::new();
+        for number in [0x06, 0x05, 0x06, 0x0B, 0x06] {
+            stats.record(number);
+        }
+        assert!(stats.last_frame().is_empty());
+        stats.end_frame();
+        assert_eq!(stats.last_frame(), [(0x06, 3), (0x05, 1), (0x0B, 1)]);
+
+        stats.record(0x05);
+        stats.end_frame();
+        assert_eq!(stats.last_frame(), [(0x05, 1)]);
+        assert_eq!(stats.totals(), [(0x06, 3), (0x05, 2), (0x0B, 1)]);
+        stats.clear();
+        assert!(stats.totals().is_empty());
+    }
+}
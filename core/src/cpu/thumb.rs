@@ -0,0 +1,168 @@
+// Thumb instruction classes, numbered as in the GBATEK format tables.
+// Every 16-bit opcode belongs to exactly one class; the ones ARMv4T does not
+// define (BKPT, BLX and the other ARMv5 additions) are `Undefined`.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ThumbFormat {
+    /// 1: LSL/LSR/ASR Rd, Rs, #offset
+    MoveShiftedRegister,
+    /// 2: ADD/SUB Rd, Rs, Rn or #imm3
+    AddSubtract,
+    /// 3: MOV/CMP/ADD/SUB Rd, #imm8
+    Immediate,
+    /// 4: ALU operations on low registers
+    Alu,
+    /// 5: ADD/CMP/MOV with high registers, BX
+    HiRegisterBx,
+    /// 6: `LDR Rd, [PC, #imm]`
+    PcRelativeLoad,
+    /// 7: STR/STRB/LDR/LDRB with register offset
+    LoadStoreRegisterOffset,
+    /// 8: STRH/LDSB/LDRH/LDSH with register offset
+    LoadStoreSignExtended,
+    /// 9: STR/LDR/STRB/LDRB with immediate offset
+    LoadStoreImmediateOffset,
+    /// 10: STRH/LDRH with immediate offset
+    LoadStoreHalfword,
+    /// 11: `STR/LDR Rd, [SP, #imm]`
+    SpRelativeLoadStore,
+    /// 12: ADD Rd, PC/SP, #imm
+    LoadAddress,
+    /// 13: ADD SP, #±imm
+    AddOffsetToSp,
+    /// 14: PUSH/POP
+    PushPop,
+    /// 15: STMIA/LDMIA
+    MultipleLoadStore,
+    /// 16: `B<cond>`
+    ConditionalBranch,
+    /// 17: SWI
+    SoftwareInterrupt,
+    /// 18: B
+    UnconditionalBranch,
+    /// 19: BL, one half per instruction
+    LongBranchWithLink,
+    Undefined,
+}
+
+impl ThumbFormat {
+    pub const ALL: [ThumbFormat; 20] = [
+        ThumbFormat::MoveShiftedRegister,
+        ThumbFormat::AddSubtract,
+        ThumbFormat::Immediate,
+        ThumbFormat::Alu,
+        ThumbFormat::HiRegisterBx,
+        ThumbFormat::PcRelativeLoad,
+        ThumbFormat::LoadStoreRegisterOffset,
+        ThumbFormat::LoadStoreSignExtended,
+        ThumbFormat::LoadStoreImmediateOffset,
+        ThumbFormat::LoadStoreHalfword,
+        ThumbFormat::SpRelativeLoadStore,
+        ThumbFormat::LoadAddress,
+        ThumbFormat::AddOffsetToSp,
+        ThumbFormat::PushPop,
+        ThumbFormat::MultipleLoadStore,
+        ThumbFormat::ConditionalBranch,
+        ThumbFormat::SoftwareInterrupt,
+        ThumbFormat::UnconditionalBranch,
+        ThumbFormat::LongBranchWithLink,
+        ThumbFormat::Undefined,
+    ];
+
+    /// GBATEK format number; None for undefined opcodes.
+    pub fn number(self) -> Option<u8> {
+        let index = Self::ALL.iter().position(|&f| f == self)?;
+        (self != ThumbFormat::Undefined).then_some(index as u8 + 1)
+    }
+
+    pub fn decode(instr: u16) -> ThumbFormat {
+        use ThumbFormat::*;
+        match instr >> 8 {
+            0x00..=0x17 => MoveShiftedRegister,
+            0x18..=0x1F => AddSubtract,
+            0x20..=0x3F => Immediate,
+            0x40..=0x43 => Alu,
+            0x44..=0x47 => HiRegisterBx,
+            0x48..=0x4F => PcRelativeLoad,
+            0x50..=0x5F if instr & 0x0200 == 0 => LoadStoreRegisterOffset,
+            0x50..=0x5F => LoadStoreSignExtended,
+            0x60..=0x7F => LoadStoreImmediateOffset,
+            0x80..=0x8F => LoadStoreHalfword,
+            0x90..=0x9F => SpRelativeLoadStore,
+            0xA0..=0xAF => LoadAddress,
+            0xB0 => AddOffsetToSp,
+            0xB4 | 0xB5 | 0xBC | 0xBD => PushPop,
+            0xC0..=0xCF => MultipleLoadStore,
+            // Condition 1110 is undefined; 1111 is SWI.
+            0xD0..=0xDD => ConditionalBranch,
+            0xDF => SoftwareInterrupt,
+            0xE0..=0xE7 => UnconditionalBranch,
+            0xF0..=0xFF => LongBranchWithLink,
+            _ => Undefined,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ThumbFormat::*;
+
+    // (mask, value) patterns transcribed from the GBATEK format tables.
+    fn patterns() -> Vec<(u16, u16, ThumbFormat)> {
+        let mut p = vec![
+            (0xF800, 0x0000, MoveShiftedRegister),
+            (0xF800, 0x0800, MoveShiftedRegister),
+            (0xF800, 0x1000, MoveShiftedRegister),
+            (0xF800, 0x1800, AddSubtract),
+            (0xE000, 0x2000, Immediate),
+            (0xFC00, 0x4000, Alu),
+            (0xFC00, 0x4400, HiRegisterBx),
+            (0xF800, 0x4800, PcRelativeLoad),
+            (0xF200, 0x5000, LoadStoreRegisterOffset),
+            (0xF200, 0x5200, LoadStoreSignExtended),
+            (0xE000, 0x6000, LoadStoreImmediateOffset),
+            (0xF000, 0x8000, LoadStoreHalfword),
+            (0xF000, 0x9000, SpRelativeLoadStore),
+            (0xF000, 0xA000, LoadAddress),
+            (0xFF00, 0xB000, AddOffsetToSp),
+            (0xF600, 0xB400, PushPop),
+            (0xF000, 0xC000, MultipleLoadStore),
+            (0xFF00, 0xDF00, SoftwareInterrupt),
+            (0xF800, 0xE000, UnconditionalBranch),
+            (0xF000, 0xF000, LongBranchWithLink),
+            (0xFF00, 0xDE00, Undefined),
+            (0xF800, 0xE800, Undefined),
+        ];
+        for cond in 0..14 {
+            p.push((0xFF00, 0xD000 | cond << 8, ConditionalBranch));
+        }
+        for op in [0xB1, 0xB2, 0xB3, 0xB6, 0xB7, 0xB8, 0xB9, 0xBA, 0xBB, 0xBE, 0xBF] {
+            p.push((0xFF00, op << 8, Undefined));
+        }
+        p
+    }
+
+    #[test]
+    fn every_opcode_has_exactly_one_class() {
+        let patterns = patterns();
+        for instr in 0..=u16::MAX {
+            let matches: Vec<_> = patterns.iter().filter(|&&(mask, value, _)| instr & mask == value).collect();
+            assert_eq!(matches.len(), 1, "{:04X} matches {:?}", instr, matches);
+            assert_eq!(ThumbFormat::decode(instr), matches[0].2, "{:04X}", instr);
+        }
+    }
+
+    #[test]
+    fn decodes_previously_misrouted_opcodes() {
+        assert_eq!(ThumbFormat::decode(0x4088), Alu); // lsl r0, r1
+        assert_eq!(ThumbFormat::decode(0x4770), HiRegisterBx); // bx lr
+        assert_eq!(ThumbFormat::decode(0xDF05), SoftwareInterrupt);
+        assert_eq!(ThumbFormat::decode(0x5E08), LoadStoreSignExtended); // ldsh r0, [r1, r0]
+        assert_eq!(ThumbFormat::decode(0xE7FE), UnconditionalBranch);
+        assert_eq!(ThumbFormat::decode(0xF000), LongBranchWithLink);
+        assert_eq!(ThumbFormat::decode(0xB500), PushPop);
+        assert_eq!(ThumbFormat::number(LongBranchWithLink), Some(19));
+        assert_eq!(ThumbFormat::number(Undefined), None);
+    }
+}
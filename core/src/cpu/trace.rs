@@ -0,0 +1,72 @@
+// Execution trace: the last instructions the CPU ran, oldest first, for crash
+// reports and post-mortem debugging. Recording is off unless a `Trace` is
+// installed with `Cpu::set_trace`.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Instructions kept by `Trace::default`.
+pub const TRACE_LEN: usize = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub pc: u32,
+    pub opcode: u32,
+    pub thumb: bool,
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.thumb {
+            write!(f, "{:08X}  {:04X}      (Thumb)", self.pc, self.opcode)
+        } else {
+            write!(f, "{:08X}  {:08X}  (ARM)", self.pc, self.opcode)
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trace {
+    entries: VecDeque<TraceEntry>,
+    capacity: usize,
+}
+
+impl Default for Trace {
+    fn default() -> Self { Self::new(TRACE_LEN) }
+}
+
+impl Trace {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self { entries: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &TraceEntry> { self.entries.iter() }
+    pub fn last(&self) -> Option<&TraceEntry> { self.entries.back() }
+
+    pub(crate) fn record(&mut self, entry: TraceEntry) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_latest_instructions() {
+        let mut trace = Trace::new(3);
+        for i in 0..5 {
+            trace.record(TraceEntry { pc: 0x0800_0000 + i * 4, opcode: i, thumb: false });
+        }
+        let pcs: Vec<u32> = trace.entries().map(|e| e.pc).collect();
+        assert_eq!(pcs, vec![0x0800_0008, 0x0800_000C, 0x0800_0010]);
+        assert_eq!(trace.last().unwrap().opcode, 4);
+        assert_eq!(trace.last().unwrap().to_string(), "08000010  00000004  (ARM)");
+        let thumb = TraceEntry { pc: 0x0800_0102, opcode: 0xDEFE, thumb: true };
+        assert_eq!(thumb.to_string(), "08000102  DEFE      (Thumb)");
+    }
+}
@@ -0,0 +1,311 @@
+// Watch expressions: integer arithmetic over registers, memory and symbols,
+// such as `[0x03001234]+r2` or `u16[gPlayer+4] & 0xFF`. They are parsed
+// once and then evaluated against the running machine as often as needed.
+//
+//     expr    := or
+//     or      := xor ('|' xor)*          (and likewise for ^, &)
+//     shift   := sum (('<<' | '>>') sum)*
+//     sum     := product (('+' | '-') product)*
+//     product := unary ('*' unary)*
+//     unary   := ('-' | '~')* primary
+//     primary := number | register | symbol | '(' expr ')' | size? '[' expr ']'
+//
+// Numbers are decimal or `0x`/`$` hex. Registers are r0-r15, sp, lr, pc and
+// cpsr; `pc` is the next instruction to run. Memory reads are 32-bit unless
+// prefixed with u8 or u16, and have no side effects.
+
+use super::SymbolTable;
+use crate::bus::BusAccess;
+use crate::Emulator;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExprError {
+    /// Byte offset in the expression where parsing failed.
+    pub pos: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.pos + 1, self.message)
+    }
+}
+
+impl std::error::Error for ExprError {}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Op {
+    Or,
+    Xor,
+    And,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+}
+
+impl Op {
+    fn apply(self, a: u32, b: u32) -> u32 {
+        match self {
+            Op::Or => a | b,
+            Op::Xor => a ^ b,
+            Op::And => a & b,
+            Op::Shl => a.checked_shl(b).unwrap_or(0),
+            Op::Shr => a.checked_shr(b).unwrap_or(0),
+            Op::Add => a.wrapping_add(b),
+            Op::Sub => a.wrapping_sub(b),
+            Op::Mul => a.wrapping_mul(b),
+        }
+    }
+}
+
+// Binary operators from the loosest binding level to the tightest.
+const LEVELS: [&[(&str, Op)]; 6] = [
+    &[("|", Op::Or)],
+    &[("^", Op::Xor)],
+    &[("&", Op::And)],
+    &[("<<", Op::Shl), (">>", Op::Shr)],
+    &[("+", Op::Add), ("-", Op::Sub)],
+    &[("*", Op::Mul)],
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Node {
+    Const(u32),
+    Reg(usize),
+    Cpsr,
+    Neg(Box<Node>),
+    Not(Box<Node>),
+    /// Little-endian read of 1, 2 or 4 bytes.
+    Load(u32, Box<Node>),
+    Binary(Op, Box<Node>, Box<Node>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WatchExpr {
+    text: String,
+    root: Node,
+}
+
+impl WatchExpr {
+    /// Parses `text`, resolving symbol names with `symbols`.
+    pub fn parse(text: &str, symbols: &SymbolTable) -> Result<Self, ExprError> {
+        let mut parser = Parser { text, pos: 0, symbols };
+        let root = parser.expr(0)?;
+        parser.skip_space();
+        if parser.pos < text.len() {
+            return Err(parser.error("unexpected input"));
+        }
+        Ok(Self { text: text.to_string(), root })
+    }
+
+    pub fn text(&self) -> &str { &self.text }
+
+    pub fn eval(&self, core: &mut Emulator) -> u32 { eval(&self.root, core) }
+}
+
+fn eval(node: &Node, core: &mut Emulator) -> u32 {
+    match node {
+        Node::Const(value) => *value,
+        Node::Reg(index) => core.cpu().read_reg(*index),
+        Node::Cpsr => core.cpu().cpsr().raw(),
+        Node::Neg(inner) => eval(inner, core).wrapping_neg(),
+        Node::Not(inner) => !eval(inner, core),
+        Node::Load(width, addr) => {
+            let addr = eval(addr, core);
+            let bus = core.bus_mut();
+            (0..*width).fold(0, |value, i| value | (bus.peek8(addr.wrapping_add(i)) as u32) << (i * 8))
+        }
+        Node::Binary(op, a, b) => {
+            let a = eval(a, core);
+            op.apply(a, eval(b, core))
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    symbols: &'a SymbolTable,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> ExprError { ExprError { pos: self.pos, message: message.to_string() } }
+
+    fn rest(&self) -> &'a str { &self.text[self.pos..] }
+
+    fn skip_space(&mut self) { self.pos = self.text.len() - self.rest().trim_start().len(); }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_space();
+        let found = self.rest().starts_with(token);
+        if found {
+            self.pos += token.len();
+        }
+        found
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), ExprError> {
+        if self.eat(token) { Ok(()) } else { Err(self.error(&format!("expected `{}`", token))) }
+    }
+
+    fn expr(&mut self, level: usize) -> Result<Node, ExprError> {
+        let Some(ops) = LEVELS.get(level) else {
+            return self.unary();
+        };
+        let mut node = self.expr(level + 1)?;
+        'operators: loop {
+            for &(token, op) in ops.iter() {
+                if self.eat(token) {
+                    node = Node::Binary(op, Box::new(node), Box::new(self.expr(level + 1)?));
+                    continue 'operators;
+                }
+            }
+            return Ok(node);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, ExprError> {
+        if self.eat("-") {
+            Ok(Node::Neg(Box::new(self.unary()?)))
+        } else if self.eat("~") {
+            Ok(Node::Not(Box::new(self.unary()?)))
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Node, ExprError> {
+        self.skip_space();
+        let start = self.pos;
+        if self.eat("(") {
+            let node = self.expr(0)?;
+            self.expect(")")?;
+            return Ok(node);
+        }
+        if self.eat("[") {
+            return self.load(4);
+        }
+        if self.eat("$") {
+            return self.number(16);
+        }
+        let rest = self.rest();
+        if rest.starts_with(|c: char| c.is_ascii_digit()) {
+            return match rest.get(..2) {
+                Some("0x" | "0X") => {
+                    self.pos += 2;
+                    self.number(16)
+                }
+                _ => self.number(10),
+            };
+        }
+
+        let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.')).unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.error("expected a value"));
+        }
+        let name = &rest[..len];
+        self.pos += len;
+        let width = match name {
+            "u8" => Some(1),
+            "u16" => Some(2),
+            "u32" => Some(4),
+            _ => None,
+        };
+        if let Some(width) = width
+            && self.eat("[")
+        {
+            return self.load(width);
+        }
+        if let Some(node) = register(name) {
+            return Ok(node);
+        }
+        match self.symbols.address_of(name) {
+            Some(addr) => Ok(Node::Const(addr)),
+            None => {
+                self.pos = start;
+                Err(self.error(&format!("unknown register or symbol `{}`", name)))
+            }
+        }
+    }
+
+    // After the opening bracket.
+    fn load(&mut self, width: u32) -> Result<Node, ExprError> {
+        let addr = self.expr(0)?;
+        self.expect("]")?;
+        Ok(Node::Load(width, Box::new(addr)))
+    }
+
+    fn number(&mut self, radix: u32) -> Result<Node, ExprError> {
+        let rest = self.rest();
+        let len = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+        let value = u32::from_str_radix(&rest[..len], radix).map_err(|_| self.error("invalid number"))?;
+        self.pos += len;
+        Ok(Node::Const(value))
+    }
+}
+
+fn register(name: &str) -> Option<Node> {
+    let name = name.to_ascii_lowercase();
+    let index = match name.as_str() {
+        "sp" => 13,
+        "lr" => 14,
+        "pc" => 15,
+        "cpsr" => return Some(Node::Cpsr),
+        _ => name.strip_prefix('r')?.parse().ok().filter(|&i| i < 16)?,
+    };
+    Some(Node::Reg(index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval_str(text: &str, core: &mut Emulator) -> u32 {
+        WatchExpr::parse(text, &SymbolTable::new()).unwrap().eval(core)
+    }
+
+    #[test]
+    fn arithmetic_follows_c_precedence() {
+        let mut emu = Emulator::new();
+        assert_eq!(eval_str("1 + 2 * 3", &mut emu), 7);
+        assert_eq!(eval_str("(1 + 2) * 3", &mut emu), 9);
+        assert_eq!(eval_str("0x10 | 1 << 2 + 1", &mut emu), 0x18);
+        assert_eq!(eval_str("$FF & ~0x0F ^ 1", &mut emu), 0xF1);
+        assert_eq!(eval_str("-1 >> 28", &mut emu), 0xF);
+        assert_eq!(eval_str("1 << 40", &mut emu), 0);
+    }
+
+    #[test]
+    fn reads_registers_memory_and_symbols() {
+        let mut emu = Emulator::new();
+        emu.cpu_mut().write_reg(2, 0x10);
+        emu.bus_mut().poke32(0x0300_1234, 0x1122_3344);
+        emu.bus_mut().poke32(0x0300_2000, 0xAABB_CCDD);
+        assert_eq!(eval_str("[0x03001234]+r2", &mut emu), 0x1122_3354);
+        assert_eq!(eval_str("u8[0x03001235]", &mut emu), 0x33);
+        assert_eq!(eval_str("u16[0x03001236]", &mut emu), 0x1122);
+        assert_eq!(eval_str("R2 + sp - r13", &mut emu), 0x10);
+        assert_eq!(eval_str("cpsr & 0x1F", &mut emu), emu.cpu().cpsr().raw() & 0x1F);
+
+        let symbols = SymbolTable::parse_sym("03002000 gScore\n03001234 g.data").unwrap();
+        let expr = WatchExpr::parse("[gScore] + u8[g.data]", &symbols).unwrap();
+        assert_eq!(expr.eval(&mut emu), 0xAABB_CCDD + 0x44);
+        assert_eq!(expr.text(), "[gScore] + u8[g.data]");
+    }
+
+    #[test]
+    fn reports_where_parsing_failed() {
+        let symbols = SymbolTable::new();
+        let error = |text| WatchExpr::parse(text, &symbols).unwrap_err();
+        assert_eq!(error("r2 + gScore").pos, 5);
+        assert_eq!(error("r2 + gScore").message, "unknown register or symbol `gScore`");
+        assert_eq!(error("[r0").message, "expected `]`");
+        assert_eq!(error("1 2").pos, 2);
+        assert_eq!(error("0xZZ").message, "invalid number");
+        assert_eq!(error("r16").message, "unknown register or symbol `r16`");
+        assert_eq!(error("").message, "expected a value");
+    }
+}
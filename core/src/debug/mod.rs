@@ -0,0 +1,8 @@
+// Debugging aids for homebrew: symbol tables to name addresses, and watch
+// expressions evaluated against the running machine.
+
+mod expr;
+mod symbols;
+
+pub use expr::{ExprError, WatchExpr};
+pub use symbols::{Symbol, SymbolError, SymbolTable};
@@ -0,0 +1,268 @@
+// Symbol tables for homebrew debugging: the symbols of an ELF image as
+// devkitARM links it, or a no$gba `.sym` file (`08000000 main` per line).
+
+use std::fmt;
+
+// An address this far past an unsized symbol (one from a `.sym` file or a
+// label) is no longer described by it.
+const MAX_UNSIZED_OFFSET: u32 = 0x1000;
+
+const ELF_MAGIC: [u8; 4] = *b"\x7FELF";
+const SHT_SYMTAB: u32 = 2;
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SymbolError {
+    /// 1-based line of a `.sym` file that is not `<hex address> <name>`.
+    Line(usize),
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SymbolError::Line(line) => write!(f, "line {}: expected an address and a name", line),
+            SymbolError::Corrupt(what) => write!(f, "corrupt ELF file: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for SymbolError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: u32,
+    /// Bytes the symbol covers; 0 when unknown.
+    pub size: u32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SymbolTable {
+    // Sorted by address.
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self { Self::default() }
+
+    /// Reads an ELF image or, failing the magic, a `.sym` file.
+    pub fn parse(data: &[u8]) -> Result<Self, SymbolError> {
+        if data.starts_with(&ELF_MAGIC) {
+            Self::parse_elf(data)
+        } else {
+            Self::parse_sym(&String::from_utf8_lossy(data))
+        }
+    }
+
+    /// no$gba format: one `<8 hex digits> <name>` per line, `;` comments.
+    /// Names starting with `.` (`.arm`, `.thumb`, `.byt:0004`, ...) mark
+    /// code and data ranges rather than naming anything, and are skipped.
+    pub fn parse_sym(text: &str) -> Result<Self, SymbolError> {
+        let mut symbols = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (addr, name) = line
+                .split_once(char::is_whitespace)
+                .and_then(|(addr, name)| Some((u32::from_str_radix(addr, 16).ok()?, name.trim())))
+                .ok_or(SymbolError::Line(i + 1))?;
+            if !name.starts_with('.') {
+                symbols.push(Symbol { name: name.to_string(), addr, size: 0 });
+            }
+        }
+        Ok(Self::from_symbols(symbols))
+    }
+
+    /// The functions, objects and labels in the `.symtab` sections of a
+    /// 32-bit little-endian ELF file. Thumb functions are listed at their
+    /// address, without the Thumb bit; ARM mapping symbols (`$a`, `$t`,
+    /// `$d`) are skipped.
+    pub fn parse_elf(data: &[u8]) -> Result<Self, SymbolError> {
+        if data.get(4..6) != Some(&[1, 1]) {
+            return Err(SymbolError::Corrupt("not a 32-bit little-endian image"));
+        }
+        let header_offset = read32(data, 0x20)? as usize;
+        let header_size = read16(data, 0x2E)? as usize;
+        let header_count = read16(data, 0x30)? as usize;
+        let section = |index: usize| -> Result<Section, SymbolError> {
+            let base = header_offset + index * header_size;
+            Ok(Section {
+                kind: read32(data, base + 4)?,
+                offset: read32(data, base + 16)? as usize,
+                size: read32(data, base + 20)? as usize,
+                link: read32(data, base + 24)? as usize,
+            })
+        };
+
+        let mut symbols = Vec::new();
+        for index in 0..header_count {
+            let table = section(index)?;
+            if table.kind != SHT_SYMTAB {
+                continue;
+            }
+            let strings = section(table.link)?;
+            let strings = data
+                .get(strings.offset..strings.offset + strings.size)
+                .ok_or(SymbolError::Corrupt("string table out of bounds"))?;
+            for entry in (table.offset..table.offset + table.size).step_by(16) {
+                let name_offset = read32(data, entry)? as usize;
+                let value = read32(data, entry + 4)?;
+                let size = read32(data, entry + 8)?;
+                let kind = data.get(entry + 12).ok_or(SymbolError::Corrupt("symbol out of bounds"))? & 0xF;
+                let section_index = read16(data, entry + 14)?;
+                if !matches!(kind, STT_NOTYPE | STT_OBJECT | STT_FUNC) || section_index == 0 {
+                    continue;
+                }
+                let name = strings
+                    .get(name_offset..)
+                    .and_then(|s| s.split(|&b| b == 0).next())
+                    .ok_or(SymbolError::Corrupt("symbol name out of bounds"))?;
+                if name.is_empty() || name[0] == b'$' {
+                    continue;
+                }
+                let addr = if kind == STT_FUNC { value & !1 } else { value };
+                symbols.push(Symbol { name: String::from_utf8_lossy(name).into_owned(), addr, size });
+            }
+        }
+        Ok(Self::from_symbols(symbols))
+    }
+
+    fn from_symbols(mut symbols: Vec<Symbol>) -> Self {
+        symbols.sort_by_key(|s| s.addr);
+        Self { symbols }
+    }
+
+    pub fn len(&self) -> usize { self.symbols.len() }
+
+    pub fn is_empty(&self) -> bool { self.symbols.is_empty() }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> { self.symbols.iter() }
+
+    pub fn address_of(&self, name: &str) -> Option<u32> {
+        self.symbols.iter().find(|s| s.name == name).map(|s| s.addr)
+    }
+
+    /// The symbol `addr` falls in, and how far into it: within its size, or
+    /// for an unsized symbol, a little past it in the same memory region.
+    pub fn lookup(&self, addr: u32) -> Option<(&Symbol, u32)> {
+        let end = self.symbols.partition_point(|s| s.addr <= addr);
+        self.symbols[..end].iter().rev().find_map(|s| {
+            let offset = addr - s.addr;
+            let inside = if s.size > 0 {
+                offset < s.size
+            } else {
+                offset < MAX_UNSIZED_OFFSET && s.addr >> 24 == addr >> 24
+            };
+            inside.then_some((s, offset))
+        })
+    }
+
+    /// `name` or `name+0x1C` for `addr`, if a symbol covers it.
+    pub fn describe(&self, addr: u32) -> Option<String> {
+        self.lookup(addr).map(|(s, offset)| match offset {
+            0 => s.name.clone(),
+            _ => format!("{}+{:#X}", s.name, offset),
+        })
+    }
+}
+
+struct Section {
+    kind: u32,
+    offset: usize,
+    size: usize,
+    link: usize,
+}
+
+fn read16(data: &[u8], at: usize) -> Result<u16, SymbolError> {
+    let bytes = data.get(at..at + 2).ok_or(SymbolError::Corrupt("header out of bounds"))?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read32(data: &[u8], at: usize) -> Result<u32, SymbolError> {
+    let bytes = data.get(at..at + 4).ok_or(SymbolError::Corrupt("header out of bounds"))?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An ELF header, a string table and a symbol table, with the section
+    // headers (null, .strtab, .symtab) at the end.
+    fn elf(symbols: &[(&str, u32, u32, u8, u16)]) -> Vec<u8> {
+        let mut strings = vec![0u8];
+        let mut table = vec![0u8; 16];
+        for &(name, value, size, kind, section) in symbols {
+            table.extend((strings.len() as u32).to_le_bytes());
+            table.extend(value.to_le_bytes());
+            table.extend(size.to_le_bytes());
+            table.extend([0x10 | kind, 0]);
+            table.extend(section.to_le_bytes());
+            strings.extend(name.as_bytes());
+            strings.push(0);
+        }
+        let mut data = vec![0u8; 0x34];
+        data[..6].copy_from_slice(&[0x7F, b'E', b'L', b'F', 1, 1]);
+        let strings_at = data.len();
+        data.extend(&strings);
+        let table_at = data.len();
+        data.extend(&table);
+        let headers_at = data.len();
+        data[0x20..0x24].copy_from_slice(&(headers_at as u32).to_le_bytes());
+        data[0x2E..0x30].copy_from_slice(&40u16.to_le_bytes());
+        data[0x30..0x32].copy_from_slice(&3u16.to_le_bytes());
+        data.extend([0u8; 40]);
+        for (kind, offset, size, link) in [(3u32, strings_at, strings.len(), 0u32), (2, table_at, table.len(), 1)] {
+            let mut header = [0u8; 40];
+            header[4..8].copy_from_slice(&kind.to_le_bytes());
+            header[16..20].copy_from_slice(&(offset as u32).to_le_bytes());
+            header[20..24].copy_from_slice(&(size as u32).to_le_bytes());
+            header[24..28].copy_from_slice(&link.to_le_bytes());
+            data.extend(header);
+        }
+        data
+    }
+
+    #[test]
+    fn reads_no_gba_sym_files() {
+        let text = "; generated\n08000000 _start\n08000000 .arm\n03000100 gPlayer ; struct\n\n080001C0 .thumb\n";
+        let table = SymbolTable::parse(text.as_bytes()).unwrap();
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.address_of("gPlayer"), Some(0x0300_0100));
+        assert_eq!(table.describe(0x0800_0010).as_deref(), Some("_start+0x10"));
+        assert_eq!(SymbolTable::parse_sym("08000000 a\nzzz").unwrap_err(), SymbolError::Line(2));
+    }
+
+    #[test]
+    fn reads_elf_symbol_tables() {
+        let data = elf(&[
+            ("main", 0x0800_0201, 0x40, STT_FUNC, 1),
+            ("gScore", 0x0300_0010, 4, STT_OBJECT, 2),
+            ("$t", 0x0800_0200, 0, STT_NOTYPE, 1),
+            ("printf", 0, 0, STT_FUNC, 0),
+            ("main.c", 0, 0, 4, 0xFFF1),
+        ]);
+        let table = SymbolTable::parse(&data).unwrap();
+        let names: Vec<&str> = table.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["gScore", "main"]);
+        assert_eq!(table.address_of("main"), Some(0x0800_0200));
+        assert_eq!(table.describe(0x0800_023E).as_deref(), Some("main+0x3E"));
+        assert_eq!(table.describe(0x0800_0240), None);
+        assert_eq!(table.describe(0x0300_0010).as_deref(), Some("gScore"));
+        assert!(SymbolTable::parse(&data[..0x40]).is_err());
+    }
+
+    #[test]
+    fn unsized_symbols_cover_a_little_of_their_region() {
+        let table = SymbolTable::parse_sym("03007FF0 top\n02000000 heap").unwrap();
+        assert_eq!(table.describe(0x0300_7FFC).as_deref(), Some("top+0xC"));
+        assert_eq!(table.describe(0x0400_0000), None);
+        assert_eq!(table.describe(0x0200_2000), None);
+        assert_eq!(table.describe(0x0100_0000), None);
+    }
+}
@@ -0,0 +1,313 @@
+use crate::state::impl_savestate;
+
+pub const DMA_BASE: u32 = 0x0400_00B0;
+pub const DMA_END: u32 = 0x0400_00E0;
+
+const ENABLE: u16 = 0x8000;
+const IRQ: u16 = 0x4000;
+const WORD: u16 = 0x0400;
+const REPEAT: u16 = 0x0200;
+
+/// Cycles between an HBlank, VBlank or video capture trigger and the first
+/// transfer access.
+pub const DMA_START_DELAY: u64 = 2;
+
+/// Scanlines on which DMA3 in special timing mode captures video; the
+/// channel disables itself after the last one.
+pub const VIDEO_CAPTURE_LINES: std::ops::RangeInclusive<u16> = 2..=161;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DmaTiming {
+    Immediate,
+    VBlank,
+    HBlank,
+    /// Sound FIFO (channels 1, 2) or video capture (channel 3).
+    Special,
+}
+
+#[derive(Default, Clone, Copy)]
+pub struct DmaChannel {
+    sad: u32,
+    dad: u32,
+    count: u16,
+    control: u16,
+    // Internal registers, latched when the channel is enabled.
+    src: u32,
+    dst: u32,
+}
+
+impl_savestate!(DmaChannel { sad, dad, count, control, src, dst });
+
+impl DmaChannel {
+    pub fn control(&self) -> u16 { self.control }
+    pub fn enabled(&self) -> bool { self.control & ENABLE != 0 }
+
+    pub fn timing(&self) -> DmaTiming {
+        match (self.control >> 12) & 3 {
+            0 => DmaTiming::Immediate,
+            1 => DmaTiming::VBlank,
+            2 => DmaTiming::HBlank,
+            _ => DmaTiming::Special,
+        }
+    }
+
+    fn step(control: u16, width: u32) -> i32 {
+        match control & 3 {
+            0 | 3 => width as i32,
+            1 => -(width as i32),
+            _ => 0,
+        }
+    }
+}
+
+/// One run of a channel, carried out by the bus.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Transfer {
+    pub src: u32,
+    pub dst: u32,
+    /// Number of units.
+    pub len: u32,
+    /// 32-bit units instead of 16-bit.
+    pub word: bool,
+    pub src_step: i32,
+    pub dst_step: i32,
+}
+
+/// The four DMA channels. Channels waiting for the bus are flagged pending;
+/// lower channels have priority.
+#[derive(Default)]
+pub struct Dma {
+    channels: [DmaChannel; 4],
+    pending: u8,
+    // Transfers started since power-on; a statistic, not saved.
+    transfers: u64,
+}
+
+impl_savestate!(Dma { channels, pending });
+
+impl Dma {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn channel(&self, index: usize) -> &DmaChannel { &self.channels[index] }
+    pub fn transfers(&self) -> u64 { self.transfers }
+
+    pub fn read8(&self, addr: u32) -> u8 {
+        let index = ((addr - DMA_BASE) / 12) as usize;
+        match (addr - DMA_BASE) % 12 {
+            10 => self.channels[index].control as u8,
+            11 => (self.channels[index].control >> 8) as u8,
+            // Addresses and counts are write-only.
+            _ => 0,
+        }
+    }
+
+    /// Register contents for debuggers, including the write-only addresses
+    /// and counts as last written.
+    pub fn peek8(&self, addr: u32) -> u8 {
+        let ch = &self.channels[((addr - DMA_BASE) / 12) as usize];
+        let offset = (addr - DMA_BASE) % 12;
+        let shift = (offset & 3) * 8;
+        match offset {
+            0..=3 => (ch.sad >> shift) as u8,
+            4..=7 => (ch.dad >> shift) as u8,
+            8 | 9 => (ch.count >> shift) as u8,
+            _ => (ch.control >> (shift - 16)) as u8,
+        }
+    }
+
+    /// Returns true when the write started an immediate transfer.
+    pub fn write8(&mut self, addr: u32, value: u8) -> bool {
+        let index = ((addr - DMA_BASE) / 12) as usize;
+        let offset = (addr - DMA_BASE) % 12;
+        let ch = &mut self.channels[index];
+        let shift = (offset & 3) * 8;
+        match offset {
+            0..=3 => ch.sad = (ch.sad & !(0xFF << shift)) | ((value as u32) << shift),
+            4..=7 => ch.dad = (ch.dad & !(0xFF << shift)) | ((value as u32) << shift),
+            8 | 9 => ch.count = (ch.count & !(0xFF << shift)) | ((value as u16) << shift),
+            _ => {
+                let shift = shift - 16;
+                let mask = if index == 3 { 0xFFE0 } else { 0xF7E0 };
+                let was_enabled = ch.enabled();
+                ch.control = ((ch.control & !(0xFF << shift)) | ((value as u16) << shift)) & mask;
+                if !ch.enabled() {
+                    self.pending &= !(1 << index);
+                } else if !was_enabled {
+                    ch.src = ch.sad & if index == 0 { 0x07FF_FFFF } else { 0x0FFF_FFFF };
+                    ch.dst = ch.dad & if index == 3 { 0x0FFF_FFFF } else { 0x07FF_FFFF };
+                    if ch.timing() == DmaTiming::Immediate {
+                        self.pending |= 1 << index;
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    /// Flags the enabled channels waiting for `timing`.
+    pub fn trigger(&mut self, timing: DmaTiming) {
+        for (i, ch) in self.channels.iter().enumerate() {
+            if ch.enabled() && ch.timing() == timing {
+                self.pending |= 1 << i;
+            }
+        }
+    }
+
+    /// Flags DMA3 when it is set up for video capture. The other special
+    /// channels belong to the sound FIFOs.
+    pub fn trigger_video_capture(&mut self) {
+        if self.channels[3].enabled() && self.channels[3].timing() == DmaTiming::Special {
+            self.pending |= 1 << 3;
+        }
+    }
+
+    /// Flags DMA1 and DMA2 when they are set up to refill the sound FIFO at
+    /// `fifo`.
+    pub fn trigger_fifo(&mut self, fifo: u32) {
+        for index in 1..3 {
+            if Self::feeds_fifo(&self.channels[index], index) && self.channels[index].dad & !3 == fifo {
+                self.pending |= 1 << index;
+            }
+        }
+    }
+
+    // Sound FIFO mode: DMA1 or DMA2 with special timing, which always
+    // moves four words to a fixed destination.
+    fn feeds_fifo(ch: &DmaChannel, index: usize) -> bool {
+        (index == 1 || index == 2) && ch.enabled() && ch.timing() == DmaTiming::Special
+    }
+
+    /// Disables DMA3 at the end of a video capture frame, even with repeat set.
+    pub fn end_video_capture(&mut self) {
+        let ch = &mut self.channels[3];
+        if ch.enabled() && ch.timing() == DmaTiming::Special {
+            ch.control &= !ENABLE;
+            self.pending &= !(1 << 3);
+        }
+    }
+
+    pub fn next_pending(&self) -> Option<usize> {
+        (self.pending != 0).then(|| self.pending.trailing_zeros() as usize)
+    }
+
+    pub fn start(&mut self, index: usize) -> Transfer {
+        self.pending &= !(1 << index);
+        self.transfers += 1;
+        let ch = &self.channels[index];
+        if Self::feeds_fifo(ch, index) {
+            let src_step = DmaChannel::step(ch.control >> 7, 4);
+            return Transfer { src: ch.src, dst: ch.dst, len: 4, word: true, src_step, dst_step: 0 };
+        }
+        let word = ch.control & WORD != 0;
+        let width = if word { 4 } else { 2 };
+        let len = match (index, ch.count) {
+            (3, 0) => 0x1_0000,
+            (_, 0) => 0x4000,
+            (3, n) => n as u32,
+            (_, n) => (n & 0x3FFF).max(1) as u32,
+        };
+        Transfer {
+            src: ch.src,
+            dst: ch.dst,
+            len,
+            word,
+            src_step: DmaChannel::step(ch.control >> 7, width),
+            dst_step: DmaChannel::step(ch.control >> 5, width),
+        }
+    }
+
+    /// Stores the addresses a transfer stopped at and rearms or disables the
+    /// channel. Returns the IF bits to raise.
+    pub fn finish(&mut self, index: usize, src: u32, dst: u32) -> u16 {
+        let ch = &mut self.channels[index];
+        ch.src = src;
+        ch.dst = dst;
+        if ch.control & REPEAT != 0 && ch.timing() != DmaTiming::Immediate {
+            if (ch.control >> 5) & 3 == 3 {
+                ch.dst = ch.dad & if index == 3 { 0x0FFF_FFFF } else { 0x07FF_FFFF };
+            }
+        } else {
+            ch.control &= !ENABLE;
+        }
+        if ch.control & IRQ != 0 { 0x0100 << index } else { 0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{Bus, BusAccess};
+
+    fn setup(bus: &mut Bus, index: u32, src: u32, dst: u32, count: u16, control: u16) {
+        let base = DMA_BASE + index * 12;
+        bus.write32(base, src);
+        bus.write32(base + 4, dst);
+        bus.write16(base + 8, count);
+        bus.write16(base + 10, control);
+    }
+
+    #[test]
+    fn immediate_transfer_copies_and_disables() {
+        let mut bus = Bus::new();
+        for i in 0..8u32 {
+            bus.write16(0x0200_0000 + i * 2, i as u16 + 1);
+        }
+        setup(&mut bus, 3, 0x0200_0000, 0x0300_0100, 8, 0x8000 | 0x4000);
+        assert_eq!(bus.read16(0x0300_0100), 1);
+        assert_eq!(bus.read16(0x0300_010E), 8);
+        assert_eq!(bus.read16(0x0300_0110), 0);
+        assert!(!bus.dma.channel(3).enabled());
+        assert_eq!(bus.io.if_ & 0x0800, 0x0800);
+    }
+
+    #[test]
+    fn fixed_and_decrementing_addresses() {
+        let mut bus = Bus::new();
+        bus.write32(0x0200_0000, 0xAABB_CCDD);
+        // Word units, source fixed, destination decrementing.
+        setup(&mut bus, 0, 0x0200_0000, 0x0300_000C, 3, 0x8000 | WORD | (2 << 7) | (1 << 5));
+        assert_eq!(bus.read32(0x0300_0004), 0xAABB_CCDD);
+        assert_eq!(bus.read32(0x0300_000C), 0xAABB_CCDD);
+        assert_eq!(bus.read32(0x0300_0000), 0);
+    }
+
+    #[test]
+    fn hblank_repeat_reloads_destination() {
+        let mut bus = Bus::new();
+        bus.write16(0x0200_0000, 0x1111);
+        bus.write16(0x0200_0002, 0x2222);
+        // HBlank, repeat, destination increment/reload.
+        setup(&mut bus, 1, 0x0200_0000, 0x0300_0000, 1, 0x8000 | REPEAT | (2 << 12) | (3 << 5));
+        assert_eq!(bus.read16(0x0300_0000), 0);
+
+        bus.dma.trigger(DmaTiming::HBlank);
+        bus.run_pending_dma();
+        assert_eq!(bus.read16(0x0300_0000), 0x1111);
+        bus.dma.trigger(DmaTiming::HBlank);
+        bus.run_pending_dma();
+        assert_eq!(bus.read16(0x0300_0000), 0x2222);
+        assert_eq!(bus.read16(0x0300_0002), 0);
+        assert!(bus.dma.channel(1).enabled());
+    }
+
+    #[test]
+    fn sound_fifo_mode_moves_four_words() {
+        use crate::apu::fifo::{FIFO_A, FIFO_B};
+        let mut bus = Bus::new();
+        for i in 0..8u32 {
+            bus.write32(0x0200_0000 + i * 4, 0x0101_0101 * (i + 1));
+        }
+        // Special timing, repeat; the count and halfword width are ignored.
+        setup(&mut bus, 2, 0x0200_0000, FIFO_A, 1, 0x8000 | REPEAT | (3 << 12));
+        bus.dma.trigger_fifo(FIFO_B);
+        assert_eq!(bus.dma.next_pending(), None);
+        bus.dma.trigger_fifo(FIFO_A);
+        bus.run_pending_dma();
+        assert_eq!(bus.sound.fifo(0).len(), 16);
+        bus.dma.trigger_fifo(FIFO_A);
+        bus.run_pending_dma();
+        assert_eq!(bus.sound.fifo(0).len(), 32);
+        assert!(bus.dma.channel(2).enabled());
+    }
+}
@@ -0,0 +1,74 @@
+// Errors from the public `Emulator` API, so frontends can report why loading
+// a ROM, BIOS, save or savestate failed instead of finding it in the log.
+
+use std::fmt;
+use std::io;
+
+use crate::archive::ArchiveError;
+use crate::cart::patch::PatchError;
+use crate::state::StateError;
+
+#[derive(Debug)]
+pub enum CoreError {
+    Io(io::Error),
+    /// Empty, too large or an archive that could not be extracted.
+    InvalidRom(String),
+    InvalidBios(String),
+    /// A ROM patch that could not be read or does not fit the ROM.
+    InvalidPatch(PatchError),
+    StateVersionMismatch { found: u16, expected: u16 },
+    /// A savestate that is not a version mismatch but still cannot be loaded.
+    InvalidState(StateError),
+    /// The request does not apply to the loaded cartridge.
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for CoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoreError::Io(e) => write!(f, "{}", e),
+            CoreError::InvalidRom(why) => write!(f, "invalid ROM: {}", why),
+            CoreError::InvalidBios(why) => write!(f, "invalid BIOS: {}", why),
+            CoreError::InvalidPatch(e) => write!(f, "invalid patch: {}", e),
+            CoreError::StateVersionMismatch { found, expected } => {
+                write!(f, "savestate version {} is not supported (expected {})", found, expected)
+            }
+            CoreError::InvalidState(e) => write!(f, "{}", e),
+            CoreError::Unsupported(what) => write!(f, "{}", what),
+        }
+    }
+}
+
+impl std::error::Error for CoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CoreError::Io(e) => Some(e),
+            CoreError::InvalidPatch(e) => Some(e),
+            CoreError::InvalidState(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CoreError {
+    fn from(e: io::Error) -> Self { CoreError::Io(e) }
+}
+
+impl From<ArchiveError> for CoreError {
+    fn from(e: ArchiveError) -> Self { CoreError::InvalidRom(e.to_string()) }
+}
+
+impl From<PatchError> for CoreError {
+    fn from(e: PatchError) -> Self { CoreError::InvalidPatch(e) }
+}
+
+impl From<StateError> for CoreError {
+    fn from(e: StateError) -> Self {
+        match e {
+            StateError::Version(found) => {
+                CoreError::StateVersionMismatch { found, expected: crate::state::STATE_VERSION }
+            }
+            e => CoreError::InvalidState(e),
+        }
+    }
+}
@@ -0,0 +1,41 @@
+// Per-frame statistics returned by `Emulator::run_frame`, for performance
+// overlays and for tests that check timing budgets.
+
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameReport {
+    /// Emulated cycles the frame took, including time spent halted.
+    pub cycles: u64,
+    /// Cycles spent halted (HALTCNT or `IntrWait`) waiting for an interrupt.
+    pub halted_cycles: u64,
+    pub instructions: u64,
+    /// DMA transfers started, counting each run of a repeating channel.
+    pub dma_transfers: u64,
+    /// Interrupts the CPU took.
+    pub irqs: u64,
+    /// False when the frame ran without drawing; see
+    /// `Emulator::set_skip_render`.
+    pub rendered: bool,
+    /// Host time for the whole `run_frame` call.
+    pub duration: Duration,
+    /// Host time running the CPU and handling events, less `apu_time`.
+    pub cpu_time: Duration,
+    /// Host time mixing audio.
+    pub apu_time: Duration,
+    /// Host time rendering the frame.
+    pub ppu_time: Duration,
+    /// Host time converting the frame to RGBA.
+    pub convert_time: Duration,
+}
+
+impl FrameReport {
+    /// Emulated frame length as a fraction of the host time it took; above
+    /// 1.0 the emulator keeps up with real time.
+    pub fn speed(&self) -> f64 {
+        const CYCLES_PER_SECOND: f64 = 16_777_216.0;
+        let emulated = self.cycles as f64 / CYCLES_PER_SECOND;
+        let host = self.duration.as_secs_f64();
+        if host > 0.0 { emulated / host } else { 0.0 }
+    }
+}
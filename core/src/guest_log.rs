@@ -0,0 +1,132 @@
+// Debug output from the running game. Homebrew and test ROMs print through
+// mGBA's debug registers or the `swi 0xFF` string call (VBA/no$gba); the
+// messages go to the `log` crate under their own target so frontends can
+// tell them apart from emulator messages.
+
+use crate::state::impl_savestate;
+
+pub const GUEST_LOG_TARGET: &str = "guest";
+
+pub const MGBA_BUFFER: u32 = 0x04FF_F600;
+pub const MGBA_FLAGS: u32 = 0x04FF_F700;
+pub const MGBA_ENABLE: u32 = 0x04FF_F780;
+const MGBA_BUFFER_LEN: usize = 0x100;
+const MGBA_END: u32 = MGBA_ENABLE + 2;
+// Written to MGBA_ENABLE to turn the registers on; read back while on.
+const MGBA_MAGIC: u16 = 0xC0DE;
+const MGBA_ACK: u16 = 0x1DEA;
+const MGBA_SEND: u16 = 0x100;
+
+pub struct GuestLog {
+    enabled: bool,
+    buffer: Vec<u8>,
+    flags: u16,
+    enable: u16,
+}
+
+impl_savestate!(GuestLog { enabled, buffer, flags, enable });
+
+impl Default for GuestLog {
+    fn default() -> Self {
+        Self { enabled: false, buffer: vec![0; MGBA_BUFFER_LEN], flags: 0, enable: 0 }
+    }
+}
+
+fn level(mgba_level: u16) -> log::Level {
+    match mgba_level & 7 {
+        0 | 1 => log::Level::Error,
+        2 => log::Level::Warn,
+        3 => log::Level::Info,
+        _ => log::Level::Debug,
+    }
+}
+
+/// Text up to the first NUL, with invalid UTF-8 replaced.
+pub fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+pub fn print(level: log::Level, message: &str) {
+    log::log!(target: GUEST_LOG_TARGET, level, "{}", message);
+}
+
+impl GuestLog {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn handles(addr: u32) -> bool { (MGBA_BUFFER..MGBA_END).contains(&addr) }
+
+    pub fn read8(&self, addr: u32) -> u8 {
+        if addr >= MGBA_ENABLE {
+            let ack = if self.enabled { MGBA_ACK } else { 0 };
+            return (ack >> ((addr & 1) * 8)) as u8;
+        }
+        0
+    }
+
+    pub fn write8(&mut self, addr: u32, value: u8) {
+        let shift = (addr & 1) * 8;
+        match addr {
+            MGBA_ENABLE.. => {
+                self.enable = (self.enable & !(0xFF << shift)) | ((value as u16) << shift);
+                self.enabled = self.enable == MGBA_MAGIC;
+            }
+            MGBA_FLAGS.. => {
+                if addr >= MGBA_FLAGS + 2 {
+                    return;
+                }
+                self.flags = (self.flags & !(0xFF << shift)) | ((value as u16) << shift);
+                if addr & 1 == 1
+                    && let Some((level, message)) = self.send()
+                {
+                    print(level, &message);
+                }
+            }
+            _ if self.enabled => self.buffer[(addr - MGBA_BUFFER) as usize] = value,
+            _ => {}
+        }
+    }
+
+    // Takes the buffered message once the send bit is written.
+    fn send(&mut self) -> Option<(log::Level, String)> {
+        if !self.enabled || self.flags & MGBA_SEND == 0 {
+            return None;
+        }
+        let message = c_string(&self.buffer);
+        self.buffer.fill(0);
+        Some((level(self.flags), message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_str(log: &mut GuestLog, text: &str) {
+        for (i, b) in text.bytes().enumerate() {
+            log.write8(MGBA_BUFFER + i as u32, b);
+        }
+    }
+
+    #[test]
+    fn mgba_registers_need_enabling() {
+        let mut log = GuestLog::new();
+        write_str(&mut log, "ignored");
+        assert_eq!(log.read8(MGBA_ENABLE), 0);
+        log.write8(MGBA_ENABLE, 0xDE);
+        log.write8(MGBA_ENABLE + 1, 0xC0);
+        assert_eq!([log.read8(MGBA_ENABLE), log.read8(MGBA_ENABLE + 1)], [0xEA, 0x1D]);
+        assert_eq!(c_string(&log.buffer), "");
+    }
+
+    #[test]
+    fn mgba_flags_send_the_buffer() {
+        let mut log = GuestLog::new();
+        log.write8(MGBA_ENABLE, 0xDE);
+        log.write8(MGBA_ENABLE + 1, 0xC0);
+        write_str(&mut log, "hello");
+        log.flags = MGBA_SEND | 2;
+        assert_eq!(log.send(), Some((log::Level::Warn, "hello".to_string())));
+        assert_eq!(log.send(), Some((log::Level::Warn, String::new())));
+    }
+}
@@ -0,0 +1,83 @@
+use std::ops::BitOr;
+
+const KEYPAD_IRQ: u16 = 0x1000;
+
+/// Set of pressed GBA buttons, using KEYINPUT bit positions (1 = pressed).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct KeyState(u16);
+
+impl KeyState {
+    pub const NONE: KeyState = KeyState(0);
+    pub const A: KeyState = KeyState(1 << 0);
+    pub const B: KeyState = KeyState(1 << 1);
+    pub const SELECT: KeyState = KeyState(1 << 2);
+    pub const START: KeyState = KeyState(1 << 3);
+    pub const RIGHT: KeyState = KeyState(1 << 4);
+    pub const LEFT: KeyState = KeyState(1 << 5);
+    pub const UP: KeyState = KeyState(1 << 6);
+    pub const DOWN: KeyState = KeyState(1 << 7);
+    pub const R: KeyState = KeyState(1 << 8);
+    pub const L: KeyState = KeyState(1 << 9);
+
+    pub const BUTTONS: [(&'static str, KeyState); 10] = [
+        ("A", KeyState::A),
+        ("B", KeyState::B),
+        ("Select", KeyState::SELECT),
+        ("Start", KeyState::START),
+        ("Right", KeyState::RIGHT),
+        ("Left", KeyState::LEFT),
+        ("Up", KeyState::UP),
+        ("Down", KeyState::DOWN),
+        ("R", KeyState::R),
+        ("L", KeyState::L),
+    ];
+
+    pub const fn from_bits(bits: u16) -> Self { KeyState(bits & 0x3FF) }
+    pub fn bits(self) -> u16 { self.0 }
+    pub fn contains(self, other: KeyState) -> bool { self.0 & other.0 == other.0 }
+    pub fn set(&mut self, keys: KeyState, pressed: bool) {
+        if pressed { self.0 |= keys.0 } else { self.0 &= !keys.0 }
+    }
+
+    /// The value KEYINPUT reads with these keys held (0 = pressed).
+    pub fn keyinput(self) -> u16 { !self.0 & 0x3FF }
+
+    /// IF bits raised for these keys under the given KEYCNT.
+    pub fn keypad_irq(self, keycnt: u16) -> u16 {
+        if keycnt & 0x4000 == 0 {
+            return 0;
+        }
+        let mask = keycnt & 0x3FF;
+        let hit = if keycnt & 0x8000 != 0 {
+            mask != 0 && self.0 & mask == mask
+        } else {
+            self.0 & mask != 0
+        };
+        if hit { KEYPAD_IRQ } else { 0 }
+    }
+}
+
+impl BitOr for KeyState {
+    type Output = KeyState;
+    fn bitor(self, rhs: KeyState) -> KeyState { KeyState(self.0 | rhs.0) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyinput_is_active_low() {
+        assert_eq!(KeyState::NONE.keyinput(), 0x3FF);
+        assert_eq!((KeyState::A | KeyState::START).keyinput(), 0x3F6);
+    }
+
+    #[test]
+    fn keypad_irq_or_and_modes() {
+        let keys = KeyState::A;
+        assert_eq!(keys.keypad_irq(0x0003), 0);
+        assert_eq!(keys.keypad_irq(0x4003), KEYPAD_IRQ);
+        assert_eq!(keys.keypad_irq(0xC003), 0);
+        assert_eq!((keys | KeyState::B).keypad_irq(0xC003), KEYPAD_IRQ);
+    }
+}
@@ -0,0 +1,371 @@
+// Names and bit fields of the PPU, interrupt, timer and DMA registers, for
+// register viewers. `IoSnapshot` captures them all at once so a frontend can
+// highlight what changed since the previous frame.
+
+use crate::bus::Bus;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RegisterGroup {
+    Display,
+    Background,
+    Window,
+    Effects,
+    Dma,
+    Timer,
+    Interrupt,
+}
+
+impl RegisterGroup {
+    pub const ALL: [RegisterGroup; 7] = [
+        RegisterGroup::Display,
+        RegisterGroup::Background,
+        RegisterGroup::Window,
+        RegisterGroup::Effects,
+        RegisterGroup::Dma,
+        RegisterGroup::Timer,
+        RegisterGroup::Interrupt,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            RegisterGroup::Display => "Display",
+            RegisterGroup::Background => "Backgrounds",
+            RegisterGroup::Window => "Windows",
+            RegisterGroup::Effects => "Mosaic and blending",
+            RegisterGroup::Dma => "DMA",
+            RegisterGroup::Timer => "Timers",
+            RegisterGroup::Interrupt => "Interrupts and system",
+        }
+    }
+}
+
+/// A bit field; one-bit fields are flags.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Field {
+    pub name: &'static str,
+    pub lsb: u8,
+    pub width: u8,
+}
+
+impl Field {
+    pub fn get(&self, value: u32) -> u32 { (value >> self.lsb) & ((1 << self.width) - 1) }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Register {
+    pub addr: u32,
+    pub name: &'static str,
+    pub group: RegisterGroup,
+    /// 2 or 4 bytes.
+    pub size: u8,
+    pub fields: &'static [Field],
+}
+
+impl Register {
+    /// Set flags and the values of wider fields, e.g. `Mode=3 BG2 OBJ`.
+    /// Registers without fields (scroll and affine parameters) show as hex.
+    pub fn describe(&self, value: u32) -> String {
+        if self.fields.is_empty() {
+            return format!("{:#X}", value);
+        }
+        let mut parts = Vec::new();
+        for field in self.fields {
+            match (field.width, field.get(value)) {
+                (1, 0) => {}
+                (1, _) => parts.push(field.name.to_string()),
+                (_, v) => parts.push(format!("{}={}", field.name, v)),
+            }
+        }
+        parts.join(" ")
+    }
+}
+
+const fn f(name: &'static str, lsb: u8, width: u8) -> Field { Field { name, lsb, width } }
+
+const fn reg(addr: u32, name: &'static str, group: RegisterGroup, size: u8, fields: &'static [Field]) -> Register {
+    Register { addr: 0x0400_0000 + addr, name, group, size, fields }
+}
+
+const DISPCNT: &[Field] = &[
+    f("Mode", 0, 3),
+    f("Frame1", 4, 1),
+    f("HBlankFree", 5, 1),
+    f("OBJ1D", 6, 1),
+    f("ForcedBlank", 7, 1),
+    f("BG0", 8, 1),
+    f("BG1", 9, 1),
+    f("BG2", 10, 1),
+    f("BG3", 11, 1),
+    f("OBJ", 12, 1),
+    f("WIN0", 13, 1),
+    f("WIN1", 14, 1),
+    f("OBJWIN", 15, 1),
+];
+const DISPSTAT: &[Field] = &[
+    f("VBlank", 0, 1),
+    f("HBlank", 1, 1),
+    f("VCounter", 2, 1),
+    f("VBlankIRQ", 3, 1),
+    f("HBlankIRQ", 4, 1),
+    f("VCounterIRQ", 5, 1),
+    f("LYC", 8, 8),
+];
+const VCOUNT: &[Field] = &[f("Line", 0, 8)];
+const BGCNT: &[Field] = &[
+    f("Priority", 0, 2),
+    f("CharBase", 2, 2),
+    f("Mosaic", 6, 1),
+    f("8bpp", 7, 1),
+    f("ScreenBase", 8, 5),
+    f("Wrap", 13, 1),
+    f("Size", 14, 2),
+];
+const WINH: &[Field] = &[f("Right", 0, 8), f("Left", 8, 8)];
+const WINV: &[Field] = &[f("Bottom", 0, 8), f("Top", 8, 8)];
+const WININ: &[Field] = &[
+    f("W0.BG0", 0, 1),
+    f("W0.BG1", 1, 1),
+    f("W0.BG2", 2, 1),
+    f("W0.BG3", 3, 1),
+    f("W0.OBJ", 4, 1),
+    f("W0.FX", 5, 1),
+    f("W1.BG0", 8, 1),
+    f("W1.BG1", 9, 1),
+    f("W1.BG2", 10, 1),
+    f("W1.BG3", 11, 1),
+    f("W1.OBJ", 12, 1),
+    f("W1.FX", 13, 1),
+];
+const WINOUT: &[Field] = &[
+    f("Out.BG0", 0, 1),
+    f("Out.BG1", 1, 1),
+    f("Out.BG2", 2, 1),
+    f("Out.BG3", 3, 1),
+    f("Out.OBJ", 4, 1),
+    f("Out.FX", 5, 1),
+    f("OW.BG0", 8, 1),
+    f("OW.BG1", 9, 1),
+    f("OW.BG2", 10, 1),
+    f("OW.BG3", 11, 1),
+    f("OW.OBJ", 12, 1),
+    f("OW.FX", 13, 1),
+];
+const MOSAIC: &[Field] = &[f("BG.H", 0, 4), f("BG.V", 4, 4), f("OBJ.H", 8, 4), f("OBJ.V", 12, 4)];
+const BLDCNT: &[Field] = &[
+    f("A.BG0", 0, 1),
+    f("A.BG1", 1, 1),
+    f("A.BG2", 2, 1),
+    f("A.BG3", 3, 1),
+    f("A.OBJ", 4, 1),
+    f("A.BD", 5, 1),
+    f("Effect", 6, 2),
+    f("B.BG0", 8, 1),
+    f("B.BG1", 9, 1),
+    f("B.BG2", 10, 1),
+    f("B.BG3", 11, 1),
+    f("B.OBJ", 12, 1),
+    f("B.BD", 13, 1),
+];
+const BLDALPHA: &[Field] = &[f("EVA", 0, 5), f("EVB", 8, 5)];
+const BLDY: &[Field] = &[f("EVY", 0, 5)];
+const DMACNT_L: &[Field] = &[f("Count", 0, 16)];
+const DMACNT_H: &[Field] = &[
+    f("DstCtl", 5, 2),
+    f("SrcCtl", 7, 2),
+    f("Repeat", 9, 1),
+    f("32bit", 10, 1),
+    f("DRQ", 11, 1),
+    f("Timing", 12, 2),
+    f("IRQ", 14, 1),
+    f("Enable", 15, 1),
+];
+const TMCNT_L: &[Field] = &[f("Counter", 0, 16)];
+const TMCNT_H: &[Field] = &[f("Prescaler", 0, 2), f("Cascade", 2, 1), f("IRQ", 6, 1), f("Enable", 7, 1)];
+const IRQ_BITS: &[Field] = &[
+    f("VBlank", 0, 1),
+    f("HBlank", 1, 1),
+    f("VCounter", 2, 1),
+    f("Timer0", 3, 1),
+    f("Timer1", 4, 1),
+    f("Timer2", 5, 1),
+    f("Timer3", 6, 1),
+    f("Serial", 7, 1),
+    f("DMA0", 8, 1),
+    f("DMA1", 9, 1),
+    f("DMA2", 10, 1),
+    f("DMA3", 11, 1),
+    f("Keypad", 12, 1),
+    f("GamePak", 13, 1),
+];
+const WAITCNT: &[Field] = &[
+    f("SRAM", 0, 2),
+    f("WS0.N", 2, 2),
+    f("WS0.S", 4, 1),
+    f("WS1.N", 5, 2),
+    f("WS1.S", 7, 1),
+    f("WS2.N", 8, 2),
+    f("WS2.S", 10, 1),
+    f("PHI", 11, 2),
+    f("Prefetch", 14, 1),
+];
+const IME: &[Field] = &[f("Enable", 0, 1)];
+const MEMCNT: &[Field] = &[f("WRAMOff", 0, 1), f("EWRAM", 5, 1), f("EWRAMWait", 24, 4)];
+
+use RegisterGroup::*;
+
+/// Every described register, in address order.
+pub const REGISTERS: &[Register] = &[
+    reg(0x000, "DISPCNT", Display, 2, DISPCNT),
+    reg(0x004, "DISPSTAT", Display, 2, DISPSTAT),
+    reg(0x006, "VCOUNT", Display, 2, VCOUNT),
+    reg(0x008, "BG0CNT", Background, 2, BGCNT),
+    reg(0x00A, "BG1CNT", Background, 2, BGCNT),
+    reg(0x00C, "BG2CNT", Background, 2, BGCNT),
+    reg(0x00E, "BG3CNT", Background, 2, BGCNT),
+    reg(0x010, "BG0HOFS", Background, 2, &[]),
+    reg(0x012, "BG0VOFS", Background, 2, &[]),
+    reg(0x014, "BG1HOFS", Background, 2, &[]),
+    reg(0x016, "BG1VOFS", Background, 2, &[]),
+    reg(0x018, "BG2HOFS", Background, 2, &[]),
+    reg(0x01A, "BG2VOFS", Background, 2, &[]),
+    reg(0x01C, "BG3HOFS", Background, 2, &[]),
+    reg(0x01E, "BG3VOFS", Background, 2, &[]),
+    reg(0x020, "BG2PA", Background, 2, &[]),
+    reg(0x022, "BG2PB", Background, 2, &[]),
+    reg(0x024, "BG2PC", Background, 2, &[]),
+    reg(0x026, "BG2PD", Background, 2, &[]),
+    reg(0x028, "BG2X", Background, 4, &[]),
+    reg(0x02C, "BG2Y", Background, 4, &[]),
+    reg(0x030, "BG3PA", Background, 2, &[]),
+    reg(0x032, "BG3PB", Background, 2, &[]),
+    reg(0x034, "BG3PC", Background, 2, &[]),
+    reg(0x036, "BG3PD", Background, 2, &[]),
+    reg(0x038, "BG3X", Background, 4, &[]),
+    reg(0x03C, "BG3Y", Background, 4, &[]),
+    reg(0x040, "WIN0H", Window, 2, WINH),
+    reg(0x042, "WIN1H", Window, 2, WINH),
+    reg(0x044, "WIN0V", Window, 2, WINV),
+    reg(0x046, "WIN1V", Window, 2, WINV),
+    reg(0x048, "WININ", Window, 2, WININ),
+    reg(0x04A, "WINOUT", Window, 2, WINOUT),
+    reg(0x04C, "MOSAIC", Effects, 2, MOSAIC),
+    reg(0x050, "BLDCNT", Effects, 2, BLDCNT),
+    reg(0x052, "BLDALPHA", Effects, 2, BLDALPHA),
+    reg(0x054, "BLDY", Effects, 2, BLDY),
+    reg(0x0B0, "DMA0SAD", Dma, 4, &[]),
+    reg(0x0B4, "DMA0DAD", Dma, 4, &[]),
+    reg(0x0B8, "DMA0CNT_L", Dma, 2, DMACNT_L),
+    reg(0x0BA, "DMA0CNT_H", Dma, 2, DMACNT_H),
+    reg(0x0BC, "DMA1SAD", Dma, 4, &[]),
+    reg(0x0C0, "DMA1DAD", Dma, 4, &[]),
+    reg(0x0C4, "DMA1CNT_L", Dma, 2, DMACNT_L),
+    reg(0x0C6, "DMA1CNT_H", Dma, 2, DMACNT_H),
+    reg(0x0C8, "DMA2SAD", Dma, 4, &[]),
+    reg(0x0CC, "DMA2DAD", Dma, 4, &[]),
+    reg(0x0D0, "DMA2CNT_L", Dma, 2, DMACNT_L),
+    reg(0x0D2, "DMA2CNT_H", Dma, 2, DMACNT_H),
+    reg(0x0D4, "DMA3SAD", Dma, 4, &[]),
+    reg(0x0D8, "DMA3DAD", Dma, 4, &[]),
+    reg(0x0DC, "DMA3CNT_L", Dma, 2, DMACNT_L),
+    reg(0x0DE, "DMA3CNT_H", Dma, 2, DMACNT_H),
+    reg(0x100, "TM0CNT_L", Timer, 2, TMCNT_L),
+    reg(0x102, "TM0CNT_H", Timer, 2, TMCNT_H),
+    reg(0x104, "TM1CNT_L", Timer, 2, TMCNT_L),
+    reg(0x106, "TM1CNT_H", Timer, 2, TMCNT_H),
+    reg(0x108, "TM2CNT_L", Timer, 2, TMCNT_L),
+    reg(0x10A, "TM2CNT_H", Timer, 2, TMCNT_H),
+    reg(0x10C, "TM3CNT_L", Timer, 2, TMCNT_L),
+    reg(0x10E, "TM3CNT_H", Timer, 2, TMCNT_H),
+    reg(0x200, "IE", Interrupt, 2, IRQ_BITS),
+    reg(0x202, "IF", Interrupt, 2, IRQ_BITS),
+    reg(0x204, "WAITCNT", Interrupt, 2, WAITCNT),
+    reg(0x208, "IME", Interrupt, 2, IME),
+    reg(0x800, "MEMCNT", Interrupt, 4, MEMCNT),
+];
+
+/// The register starting at `addr`, if it is one of `REGISTERS`.
+pub fn describe_register(addr: u32) -> Option<&'static Register> {
+    REGISTERS.binary_search_by_key(&addr, |r| r.addr).ok().map(|i| &REGISTERS[i])
+}
+
+/// Values of all `REGISTERS` at one point in time, in the same order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IoSnapshot {
+    values: Vec<u32>,
+}
+
+impl IoSnapshot {
+    pub fn capture(bus: &Bus) -> Self {
+        let values = REGISTERS
+            .iter()
+            .map(|r| {
+                let lo = bus.peek_io16(r.addr) as u32;
+                if r.size == 4 { lo | (bus.peek_io16(r.addr + 2) as u32) << 16 } else { lo }
+            })
+            .collect();
+        Self { values }
+    }
+
+    /// Registers with their values, in `REGISTERS` order.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static Register, u32)> + '_ {
+        REGISTERS.iter().zip(self.values.iter().copied())
+    }
+
+    pub fn get(&self, addr: u32) -> Option<u32> {
+        let index = REGISTERS.iter().position(|r| r.addr == addr)?;
+        self.values.get(index).copied()
+    }
+
+    /// Per register, the bits that differ from `previous`. An empty
+    /// `previous` (the default) counts as all zero.
+    pub fn diff(&self, previous: &IoSnapshot) -> Vec<u32> {
+        self.values
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| value ^ previous.values.get(i).copied().unwrap_or(0))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::BusAccess;
+
+    #[test]
+    fn registers_are_sorted_and_looked_up_by_address() {
+        assert!(REGISTERS.windows(2).all(|w| w[0].addr + w[0].size as u32 <= w[1].addr));
+        assert_eq!(describe_register(0x0400_0000).map(|r| r.name), Some("DISPCNT"));
+        assert_eq!(describe_register(0x0400_00DE).map(|r| r.name), Some("DMA3CNT_H"));
+        assert!(describe_register(0x0400_0001).is_none());
+    }
+
+    #[test]
+    fn describes_fields() {
+        let dispcnt = describe_register(0x0400_0000).unwrap();
+        assert_eq!(dispcnt.describe(0x1403), "Mode=3 BG2 OBJ");
+        let bg2x = describe_register(0x0400_0028).unwrap();
+        assert_eq!(bg2x.describe(0x1234), "0x1234");
+        let memcnt = describe_register(0x0400_0800).unwrap();
+        assert_eq!(memcnt.describe(0x0D00_0020), "EWRAM EWRAMWait=13");
+    }
+
+    #[test]
+    fn snapshot_diff_shows_changed_bits() {
+        let mut bus = Bus::new();
+        bus.write16(0x0400_0000, 0x0100);
+        bus.write32(0x0400_00D4, 0x0800_0000);
+        let before = IoSnapshot::capture(&bus);
+        assert_eq!(before.get(0x0400_00D4), Some(0x0800_0000));
+
+        bus.write16(0x0400_0000, 0x0503);
+        let after = IoSnapshot::capture(&bus);
+        let changed: Vec<_> = after
+            .iter()
+            .zip(after.diff(&before))
+            .filter(|&(_, bits)| bits != 0)
+            .map(|((r, _), bits)| (r.name, bits))
+            .collect();
+        assert_eq!(changed, vec![("DISPCNT", 0x0403)]);
+    }
+}
@@ -1,3 +1,25 @@
+use crate::input::KeyState;
+use crate::state::impl_savestate;
+
+pub mod describe;
+
+pub use describe::{describe_register, IoSnapshot};
+
+pub const DISPSTAT_VBLANK: u16 = 1 << 0;
+pub const DISPSTAT_HBLANK: u16 = 1 << 1;
+pub const DISPSTAT_VCOUNT: u16 = 1 << 2;
+const DISPSTAT_FLAGS: u16 = DISPSTAT_VBLANK | DISPSTAT_HBLANK | DISPSTAT_VCOUNT;
+
+/// Cycles from an enabled interrupt being flagged to the CPU noticing it.
+pub const IRQ_SYNC_DELAY: u64 = 3;
+
+// Only the serial, keypad and game pak interrupts end stop mode.
+const STOP_WAKE_IRQS: u16 = 0x3080;
+
+/// Internal memory control after reset: both work RAMs on, EWRAM at 2
+/// waitstates.
+pub const MEMCNT_RESET: u32 = 0x0D00_0020;
+
 pub struct Io {
     pub dispcnt: u16,
     pub dispstat: u16,
@@ -26,20 +48,75 @@ pub struct Io {
     pub bg3pd: i16,
     pub bg3x: i32,
     pub bg3y: i32,
+    // The internal reference points the affine BGs draw from: BGxX/BGxY as
+    // of the last VBlank or write, advanced by PB/PD after each line drawn.
+    pub bg2x_ref: i32,
+    pub bg2y_ref: i32,
+    pub bg3x_ref: i32,
+    pub bg3y_ref: i32,
     pub mosaic: u16,
+    pub win0h: u16,
+    pub win1h: u16,
+    pub win0v: u16,
+    pub win1v: u16,
+    pub winin: u16,
+    pub winout: u16,
+    pub bldcnt: u16,
+    pub bldalpha: u16,
+    pub bldy: u16,
+
+    /// PSG master volume and channel routing.
+    pub soundcnt_l: u16,
+    /// PSG and Direct Sound volume ratios and routing.
+    pub soundcnt_h: u16,
+    /// Master enable (bit 7); channel status bits 0-3 are not emulated.
+    pub soundcnt_x: u16,
+    /// Bias level (bits 1-9) and amplitude resolution (bits 14-15) of the
+    /// sound output.
+    pub soundbias: u16,
+
+    pub siomulti: [u16; 4],
+    pub siocnt: u16,
+    pub siodata8: u16,
 
     pub keyinput: u16,
     pub keycnt: u16,
 
+    pub rcnt: u16,
+    pub joycnt: u16,
+    pub joy_recv: u32,
+    pub joy_trans: u32,
+    pub joystat: u16,
+
     pub ie: u16,
     pub if_: u16,
     pub ime: u16,
+    /// Gamepak waitstates and prefetch. The PHI terminal output bits (11-12)
+    /// are stored but have no effect.
+    pub waitcnt: u16,
+    /// Internal memory control at 0x04000800, mirrored every 64 KiB of the
+    /// I/O area: bit 0 disables both work RAMs, bit 5 clear puts IWRAM in
+    /// EWRAM's place, and bits 24-27 set the EWRAM waitstates.
+    pub memcnt: u32,
 
     pub postflg: u8,
     pub haltcnt: u8,
     pub halted: bool,
+    // Whether IE, IF and IME have agreed on an interrupt since they last
+    // stopped doing so, and the cycle the CPU sees it from.
+    pub(crate) irq_raised: bool,
+    pub(crate) irq_line_at: u64,
 }
 
+impl_savestate!(Io {
+    dispcnt, dispstat, vcount, bg0cnt, bg1cnt, bg2cnt, bg3cnt, bg0hofs, bg0vofs, bg1hofs,
+    bg1vofs, bg2hofs, bg2vofs, bg3hofs, bg3vofs, bg2pa, bg2pb, bg2pc, bg2pd, bg2x, bg2y, bg3pa,
+    bg3pb, bg3pc, bg3pd, bg3x, bg3y, bg2x_ref, bg2y_ref, bg3x_ref, bg3y_ref, mosaic, win0h, win1h, win0v, win1v, winin, winout, bldcnt,
+    bldalpha, bldy, soundcnt_l, soundcnt_h, soundcnt_x, soundbias, siomulti, siocnt, siodata8, keyinput, keycnt, rcnt,
+    joycnt, joy_recv, joy_trans, joystat, ie, if_, ime, waitcnt, memcnt, postflg, haltcnt, halted,
+    irq_raised, irq_line_at,
+});
+
 impl Default for Io {
     fn default() -> Self {
         Self {
@@ -70,18 +147,50 @@ impl Default for Io {
             bg3pd: 0x0100,
             bg3x: 0,
             bg3y: 0,
+            bg2x_ref: 0,
+            bg2y_ref: 0,
+            bg3x_ref: 0,
+            bg3y_ref: 0,
             mosaic: 0,
+            win0h: 0,
+            win1h: 0,
+            win0v: 0,
+            win1v: 0,
+            winin: 0,
+            winout: 0,
+            bldcnt: 0,
+            bldalpha: 0,
+            bldy: 0,
+
+            soundcnt_l: 0,
+            soundcnt_h: 0,
+            soundcnt_x: 0,
+            soundbias: 0,
+
+            siomulti: [0; 4],
+            siocnt: 0,
+            siodata8: 0,
 
             keyinput: 0x03FF,
             keycnt: 0,
 
+            rcnt: 0,
+            joycnt: 0,
+            joy_recv: 0,
+            joy_trans: 0,
+            joystat: 0,
+
             ie: 0,
             if_: 0,
             ime: 0,
+            waitcnt: 0,
+            memcnt: MEMCNT_RESET,
 
             postflg: 0,
             haltcnt: 0,
             halted: false,
+            irq_raised: false,
+            irq_line_at: 0,
         }
     }
 }
@@ -155,22 +264,70 @@ impl Io {
             0x0400_003F => ((self.bg3y as u32 >> 24) & 0xFF) as u8,
             0x0400_004C => (self.mosaic & 0xFF) as u8,
             0x0400_004D => (self.mosaic >> 8) as u8,
+            0x0400_0040 => (self.win0h & 0xFF) as u8,
+            0x0400_0041 => (self.win0h >> 8) as u8,
+            0x0400_0042 => (self.win1h & 0xFF) as u8,
+            0x0400_0043 => (self.win1h >> 8) as u8,
+            0x0400_0044 => (self.win0v & 0xFF) as u8,
+            0x0400_0045 => (self.win0v >> 8) as u8,
+            0x0400_0046 => (self.win1v & 0xFF) as u8,
+            0x0400_0047 => (self.win1v >> 8) as u8,
+            0x0400_0048 => (self.winin & 0xFF) as u8,
+            0x0400_0049 => (self.winin >> 8) as u8,
+            0x0400_004A => (self.winout & 0xFF) as u8,
+            0x0400_004B => (self.winout >> 8) as u8,
+            0x0400_0050 => (self.bldcnt & 0xFF) as u8,
+            0x0400_0051 => (self.bldcnt >> 8) as u8,
+            0x0400_0052 => (self.bldalpha & 0xFF) as u8,
+            0x0400_0053 => (self.bldalpha >> 8) as u8,
+            0x0400_0054 => (self.bldy & 0xFF) as u8,
+            0x0400_0055 => (self.bldy >> 8) as u8,
+
+            0x0400_0080 => (self.soundcnt_l & 0xFF) as u8,
+            0x0400_0081 => (self.soundcnt_l >> 8) as u8,
+            0x0400_0082 => (self.soundcnt_h & 0xFF) as u8,
+            0x0400_0083 => (self.soundcnt_h >> 8) as u8,
+            0x0400_0084 => (self.soundcnt_x & 0xFF) as u8,
+            0x0400_0088 => (self.soundbias & 0xFF) as u8,
+            0x0400_0089 => (self.soundbias >> 8) as u8,
+
+            0x0400_0120..=0x0400_0127 => {
+                let reg = self.siomulti[((addr - 0x0400_0120) >> 1) as usize];
+                (reg >> ((addr & 1) * 8)) as u8
+            }
+            0x0400_0128 => (self.siocnt & 0xFF) as u8,
+            0x0400_0129 => (self.siocnt >> 8) as u8,
+            0x0400_012A => (self.siodata8 & 0xFF) as u8,
+            0x0400_012B => (self.siodata8 >> 8) as u8,
 
             0x0400_0130 => (self.keyinput & 0xFF) as u8,
             0x0400_0131 => (self.keyinput >> 8) as u8,
             0x0400_0132 => (self.keycnt & 0xFF) as u8,
             0x0400_0133 => (self.keycnt >> 8) as u8,
 
+            0x0400_0134 => (self.rcnt & 0xFF) as u8,
+            0x0400_0135 => (self.rcnt >> 8) as u8,
+            0x0400_0140 => (self.joycnt & 0xFF) as u8,
+            0x0400_0141 => (self.joycnt >> 8) as u8,
+            0x0400_0150..=0x0400_0153 => (self.joy_recv >> ((addr & 3) * 8)) as u8,
+            0x0400_0154..=0x0400_0157 => (self.joy_trans >> ((addr & 3) * 8)) as u8,
+            0x0400_0158 => (self.joystat & 0xFF) as u8,
+            0x0400_0159 => (self.joystat >> 8) as u8,
+
             0x0400_0200 => (self.ie & 0xFF) as u8,
             0x0400_0201 => (self.ie >> 8) as u8,
             0x0400_0202 => (self.if_ & 0xFF) as u8,
             0x0400_0203 => (self.if_ >> 8) as u8,
+            0x0400_0204 => (self.waitcnt & 0xFF) as u8,
+            0x0400_0205 => (self.waitcnt >> 8) as u8,
             0x0400_0208 => (self.ime & 0xFF) as u8,
             0x0400_0209 => (self.ime >> 8) as u8,
 
             0x0400_0300 => self.postflg,
             0x0400_0301 => 0,
 
+            0x0400_0800..=0x0400_0803 => (self.memcnt >> ((addr & 3) * 8)) as u8,
+
             _ => 0,
         }
     }
@@ -179,8 +336,10 @@ impl Io {
         match addr {
             0x0400_0000 => self.dispcnt = (self.dispcnt & 0xFF00) | value as u16,
             0x0400_0001 => self.dispcnt = (self.dispcnt & 0x00FF) | ((value as u16) << 8),
-            0x0400_0004 => self.dispstat = (self.dispstat & 0xFF00) | value as u16,
+            // The status flags in bits 0-2 belong to the PPU.
+            0x0400_0004 => self.dispstat = (self.dispstat & 0xFF07) | (value as u16 & 0xF8),
             0x0400_0005 => self.dispstat = (self.dispstat & 0x00FF) | ((value as u16) << 8),
+            // VCOUNT is read-only.
             0x0400_0006 => {}
             0x0400_0007 => {}
             0x0400_0008 => self.bg0cnt = (self.bg0cnt & 0xFF00) | value as u16,
@@ -293,43 +452,303 @@ impl Io {
             }
             0x0400_004C => self.mosaic = (self.mosaic & 0xFF00) | value as u16,
             0x0400_004D => self.mosaic = (self.mosaic & 0x00FF) | ((value as u16) << 8),
+            0x0400_0040 => self.win0h = (self.win0h & 0xFF00) | value as u16,
+            0x0400_0041 => self.win0h = (self.win0h & 0x00FF) | ((value as u16) << 8),
+            0x0400_0042 => self.win1h = (self.win1h & 0xFF00) | value as u16,
+            0x0400_0043 => self.win1h = (self.win1h & 0x00FF) | ((value as u16) << 8),
+            0x0400_0044 => self.win0v = (self.win0v & 0xFF00) | value as u16,
+            0x0400_0045 => self.win0v = (self.win0v & 0x00FF) | ((value as u16) << 8),
+            0x0400_0046 => self.win1v = (self.win1v & 0xFF00) | value as u16,
+            0x0400_0047 => self.win1v = (self.win1v & 0x00FF) | ((value as u16) << 8),
+            0x0400_0048 => self.winin = (self.winin & 0xFF00) | (value & 0x3F) as u16,
+            0x0400_0049 => self.winin = (self.winin & 0x00FF) | (((value & 0x3F) as u16) << 8),
+            0x0400_004A => self.winout = (self.winout & 0xFF00) | (value & 0x3F) as u16,
+            0x0400_004B => self.winout = (self.winout & 0x00FF) | (((value & 0x3F) as u16) << 8),
+            0x0400_0050 => self.bldcnt = (self.bldcnt & 0xFF00) | value as u16,
+            0x0400_0051 => self.bldcnt = (self.bldcnt & 0x00FF) | (((value & 0x3F) as u16) << 8),
+            0x0400_0052 => self.bldalpha = (self.bldalpha & 0xFF00) | (value & 0x1F) as u16,
+            0x0400_0053 => self.bldalpha = (self.bldalpha & 0x00FF) | (((value & 0x1F) as u16) << 8),
+            0x0400_0054 => self.bldy = (value & 0x1F) as u16,
+            0x0400_0055 => {}
+
+            0x0400_0080 => self.soundcnt_l = (self.soundcnt_l & 0xFF00) | (value & 0x77) as u16,
+            0x0400_0081 => self.soundcnt_l = (self.soundcnt_l & 0x00FF) | ((value as u16) << 8),
+            0x0400_0082 => self.soundcnt_h = (self.soundcnt_h & 0xFF00) | (value & 0x0F) as u16,
+            // Bits 11 and 15 reset the FIFOs and read as 0.
+            0x0400_0083 => self.soundcnt_h = (self.soundcnt_h & 0x00FF) | (((value & 0x77) as u16) << 8),
+            0x0400_0084 => self.soundcnt_x = (value & 0x80) as u16,
+            0x0400_0088 => self.soundbias = (self.soundbias & 0xFF00) | (value & 0xFE) as u16,
+            0x0400_0089 => self.soundbias = (self.soundbias & 0x00FF) | (((value & 0xC3) as u16) << 8),
+
+            0x0400_0120..=0x0400_0127 => {
+                let reg = &mut self.siomulti[((addr - 0x0400_0120) >> 1) as usize];
+                let shift = (addr & 1) * 8;
+                *reg = (*reg & !(0xFF << shift)) | ((value as u16) << shift);
+            }
+            0x0400_0128 => self.siocnt = (self.siocnt & 0xFF00) | value as u16,
+            0x0400_0129 => self.siocnt = (self.siocnt & 0x00FF) | (((value as u16) & 0x7F) << 8),
+            0x0400_012A => self.siodata8 = (self.siodata8 & 0xFF00) | value as u16,
+            0x0400_012B => self.siodata8 = (self.siodata8 & 0x00FF) | ((value as u16) << 8),
 
             0x0400_0130 => {}
             0x0400_0131 => {}
-            0x0400_0132 => self.keycnt = (self.keycnt & 0xFF00) | value as u16,
-            0x0400_0133 => self.keycnt = (self.keycnt & 0x00FF) | ((value as u16) << 8),
+            0x0400_0132 => {
+                self.keycnt = (self.keycnt & 0xFF00) | value as u16;
+                self.update_keypad_irq();
+            }
+            0x0400_0133 => {
+                self.keycnt = (self.keycnt & 0x00FF) | ((value as u16) << 8);
+                self.update_keypad_irq();
+            }
 
-            0x0400_0200 => self.ie = (self.ie & 0xFF00) | value as u16,
-            0x0400_0201 => self.ie = (self.ie & 0x00FF) | ((value as u16) << 8),
+            0x0400_0134 => self.rcnt = (self.rcnt & 0xFF00) | value as u16,
+            0x0400_0135 => self.rcnt = (self.rcnt & 0x00FF) | (((value as u16) & 0xC1) << 8),
+            0x0400_0140 => self.joycnt &= !(value as u16 & 0x07),
+            0x0400_0141 => {}
+            0x0400_0154..=0x0400_0157 => {
+                let shift = (addr & 3) * 8;
+                self.joy_trans = (self.joy_trans & !(0xFF << shift)) | ((value as u32) << shift);
+            }
+            0x0400_0158 => self.joystat = (self.joystat & !0x30) | (value as u16 & 0x30),
+            0x0400_0159 => {}
+
+            0x0400_0200 => self.write_ie((self.ie & 0xFF00) | value as u16),
+            0x0400_0201 => self.write_ie((self.ie & 0x00FF) | ((value as u16) << 8)),
             0x0400_0202 => self.if_ &= !(value as u16),
             0x0400_0203 => self.if_ &= !((value as u16) << 8),
+            // Bit 15 (gamepak type) reads as 0 for GBA carts.
+            0x0400_0204 => self.waitcnt = (self.waitcnt & 0xFF00) | value as u16,
+            0x0400_0205 => self.waitcnt = (self.waitcnt & 0x00FF) | (((value as u16) << 8) & 0x5F00),
             0x0400_0208 => self.ime = value as u16 & 1,
             0x0400_0209 => {}
 
             0x0400_0300 => self.postflg = value & 1,
             0x0400_0301 => {
                 self.haltcnt = value;
-                if (value & 0x80) == 0 {
-                    self.halted = true;
-                }
+                // Halt (or stop, with bit 7 set) ends as soon as an enabled
+                // interrupt that can wake it is flagged, so with one already
+                // pending it does not start.
+                self.halted = (self.ie & self.if_ & self.wake_irqs()) == 0;
+            }
+
+            // Bit 4 always reads as 0.
+            0x0400_0800..=0x0400_0803 => {
+                let shift = (addr & 3) * 8;
+                self.memcnt = (self.memcnt & !(0xFF << shift)) | ((value as u32) << shift) & !0x10;
             }
 
             _ => {}
         }
+        // Writing a reference point reloads its internal register at once,
+        // even mid-frame.
+        match addr {
+            0x0400_0028..=0x0400_002B => self.bg2x_ref = (self.bg2x << 4) >> 4,
+            0x0400_002C..=0x0400_002F => self.bg2y_ref = (self.bg2y << 4) >> 4,
+            0x0400_0038..=0x0400_003B => self.bg3x_ref = (self.bg3x << 4) >> 4,
+            0x0400_003C..=0x0400_003F => self.bg3y_ref = (self.bg3y << 4) >> 4,
+            _ => {}
+        }
+    }
+
+    /// Reloads the internal reference points from BGxX/BGxY, as the PPU
+    /// does at the start of VBlank.
+    pub(crate) fn latch_affine_refs(&mut self) {
+        self.bg2x_ref = (self.bg2x << 4) >> 4;
+        self.bg2y_ref = (self.bg2y << 4) >> 4;
+        self.bg3x_ref = (self.bg3x << 4) >> 4;
+        self.bg3y_ref = (self.bg3y << 4) >> 4;
+    }
+
+    /// Moves the internal reference points down one line, by PB and PD.
+    pub(crate) fn advance_affine_refs(&mut self) {
+        self.bg2x_ref = self.bg2x_ref.wrapping_add(self.bg2pb as i32);
+        self.bg2y_ref = self.bg2y_ref.wrapping_add(self.bg2pd as i32);
+        self.bg3x_ref = self.bg3x_ref.wrapping_add(self.bg3pb as i32);
+        self.bg3y_ref = self.bg3y_ref.wrapping_add(self.bg3pd as i32);
+    }
+
+    /// The internal reference point of affine BG `bg_num` (2 or 3).
+    pub fn affine_ref(&self, bg_num: usize) -> (i32, i32) {
+        if bg_num == 3 { (self.bg3x_ref, self.bg3y_ref) } else { (self.bg2x_ref, self.bg2y_ref) }
+    }
+
+    /// Replaces the DISPSTAT status flags (VBlank, HBlank, VCount match),
+    /// which the CPU cannot write.
+    pub(crate) fn set_dispstat_flags(&mut self, flags: u16) {
+        self.dispstat = (self.dispstat & !DISPSTAT_FLAGS) | (flags & DISPSTAT_FLAGS);
+    }
+
+    // Halt ends when an enabled interrupt is flagged, whatever IME says.
+    fn write_ie(&mut self, value: u16) {
+        self.ie = value;
+        if (self.ie & self.if_ & self.wake_irqs()) != 0 {
+            self.halted = false;
+        }
     }
 
     pub fn request_interrupt(&mut self, irq: u16) {
         self.if_ |= irq;
-        if (self.ie & irq) != 0 {
+        if (self.ie & irq & self.wake_irqs()) != 0 {
             self.halted = false;
         }
     }
 
+    fn wake_irqs(&self) -> u16 { if (self.haltcnt & 0x80) != 0 { STOP_WAKE_IRQS } else { 0x3FFF } }
+
+    /// Flags the keypad interrupt while the keys KEYINPUT reads meet the
+    /// KEYCNT condition; call whenever either changes.
+    pub(crate) fn update_keypad_irq(&mut self) {
+        let irq = KeyState::from_bits(!self.keyinput).keypad_irq(self.keycnt);
+        if irq != 0 {
+            self.request_interrupt(irq);
+        }
+    }
+
     pub fn pending_interrupts(&self) -> bool {
         (self.ime & 1) != 0 && (self.ie & self.if_) != 0
     }
 
+    /// Whether the CPU sees its IRQ line asserted at cycle `now`. The line
+    /// goes through a synchronizer, so it lags `pending_interrupts` by
+    /// `IRQ_SYNC_DELAY` cycles; call this at every instruction boundary.
+    pub(crate) fn irq_line(&mut self, now: u64) -> bool {
+        if !self.pending_interrupts() {
+            self.irq_raised = false;
+            return false;
+        }
+        if !self.irq_raised {
+            self.irq_raised = true;
+            self.irq_line_at = now + IRQ_SYNC_DELAY;
+        }
+        now >= self.irq_line_at
+    }
+
     pub fn is_halted(&self) -> bool {
         self.halted
     }
+
+    /// Whether IWRAM and EWRAM answer at all; MEMCNT bit 0 disables both.
+    pub fn wram_enabled(&self) -> bool { self.memcnt & 1 == 0 }
+
+    /// Whether EWRAM is mapped at 0x02000000 rather than a mirror of IWRAM.
+    pub fn ewram_enabled(&self) -> bool { self.wram_enabled() && self.memcnt & 0x20 != 0 }
+
+    /// Halted in stop mode, which only the keypad, serial and game pak
+    /// interrupts end.
+    pub fn is_stopped(&self) -> bool { self.halted && (self.haltcnt & 0x80) != 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dispstat_flags_ignore_cpu_writes() {
+        let mut io = Io::new();
+        io.set_dispstat_flags(DISPSTAT_VBLANK | DISPSTAT_VCOUNT);
+        io.write8(0x0400_0004, 0xFA);
+        io.write8(0x0400_0005, 0x64);
+        assert_eq!(io.dispstat, 0x64FD);
+        io.write8(0x0400_0004, 0x00);
+        assert_eq!(io.dispstat, 0x6405);
+    }
+
+    #[test]
+    fn dispstat_flags_keep_the_cpu_bits() {
+        let mut io = Io::new();
+        io.write8(0x0400_0004, 0x38);
+        io.set_dispstat_flags(0xFFFF);
+        assert_eq!(io.dispstat, 0x003F);
+        io.set_dispstat_flags(DISPSTAT_HBLANK);
+        assert_eq!(io.dispstat, 0x003A);
+    }
+
+    #[test]
+    fn affine_reference_points_advance_per_line_and_reload() {
+        let mut io = Io::new();
+        io.write8(0x0400_0022, 0x10);
+        io.write8(0x0400_0026, 0x80);
+        io.write8(0x0400_0027, 0x00);
+        io.write8(0x0400_002F, 0x08);
+        // Bit 27 is the sign.
+        assert_eq!(io.affine_ref(2), (0, -0x0800_0000));
+        io.advance_affine_refs();
+        io.advance_affine_refs();
+        assert_eq!(io.affine_ref(2), (0x20, -0x0800_0000 + 0x100));
+        io.write8(0x0400_0028, 0x40);
+        assert_eq!(io.affine_ref(2), (0x40, -0x0800_0000 + 0x100));
+        io.latch_affine_refs();
+        assert_eq!(io.affine_ref(2), (0x40, -0x0800_0000));
+        assert_eq!(io.affine_ref(3), (0, 0));
+    }
+
+    #[test]
+    fn irq_line_lags_by_the_sync_delay() {
+        let mut io = Io { ie: 0x0009, ime: 1, ..Io::default() };
+        io.request_interrupt(0x0008);
+        assert!(!io.irq_line(100));
+        assert!(!io.irq_line(102));
+        assert!(io.irq_line(103));
+        // The line stays asserted while another interrupt is still pending.
+        io.request_interrupt(0x0001);
+        io.write8(0x0400_0202, 0x08);
+        assert!(io.irq_line(104));
+        io.write8(0x0400_0202, 0x01);
+        assert!(!io.irq_line(105));
+        io.request_interrupt(0x0001);
+        assert!(!io.irq_line(106));
+        assert!(io.irq_line(109));
+    }
+
+    #[test]
+    fn enabling_a_flagged_interrupt_ends_halt_without_ime() {
+        let mut io = Io::new();
+        io.request_interrupt(0x0004);
+        io.write8(0x0400_0301, 0x00);
+        assert!(io.is_halted());
+        io.write8(0x0400_0200, 0x04);
+        assert!(!io.is_halted());
+        assert!(!io.pending_interrupts());
+    }
+
+    #[test]
+    fn stop_mode_ends_only_on_keypad_serial_or_game_pak_irqs() {
+        let mut io = Io::new();
+        io.write8(0x0400_0200, 0x81);
+        io.write8(0x0400_0201, 0x30);
+        io.write8(0x0400_0301, 0x80);
+        assert!(io.is_stopped());
+        io.request_interrupt(0x0001);
+        assert!(io.is_stopped());
+        io.request_interrupt(0x1000);
+        assert!(!io.is_halted());
+
+        // A wake-up interrupt already flagged keeps stop from starting.
+        io.write8(0x0400_0301, 0x80);
+        assert!(!io.is_halted());
+    }
+
+    #[test]
+    fn keycnt_writes_check_the_held_keys() {
+        let mut io = Io::new();
+        io.keyinput = (KeyState::A | KeyState::B).keyinput();
+        let write_keycnt = |io: &mut Io, value: u16| {
+            io.write8(0x0400_0133, (value >> 8) as u8);
+            io.write8(0x0400_0132, value as u8);
+            io.if_
+        };
+        assert_eq!(write_keycnt(&mut io, 0x4004), 0);
+        assert_eq!(write_keycnt(&mut io, 0xC005), 0);
+        assert_eq!(write_keycnt(&mut io, 0xC003), 0x1000);
+    }
+
+    #[test]
+    fn vcount_ignores_writes() {
+        let mut io = Io::new();
+        io.vcount = 100;
+        io.write8(0x0400_0006, 0x12);
+        io.write8(0x0400_0007, 0x01);
+        assert_eq!(io.read8(0x0400_0006), 100);
+        assert_eq!(io.read8(0x0400_0007), 0);
+    }
 }
@@ -1,105 +1,390 @@
+//! Game Boy Advance emulator core.
+//!
+//! [`Emulator`] is the whole machine: load a BIOS and a ROM, set the keys,
+//! run frames and take the video, audio and savestates it produces. The
+//! [`prelude`] brings in what a frontend needs for that. The hardware
+//! modules (`cpu`, `bus`, `ppu`, ...) are public but hidden from these docs:
+//! they serve the desktop debugger and tests that drive components on their
+//! own, and are not a stable API.
+//!
+//! ```no_run
+//! use roba_core::prelude::*;
+//!
+//! let mut emu = Emulator::builder().boot_mode(BootMode::SkipBios).build();
+//! emu.load_rom(std::path::Path::new("game.gba"))?;
+//! emu.set_keys(KeyState::default());
+//! emu.run_frame();
+//! let rgba = emu.framebuffer_rgba();
+//! # let _ = rgba;
+//! # Ok::<(), CoreError>(())
+//! ```
+//!
+//! # Features
+//!
+//! - `debugger` (default): symbol tables and watch expressions, in `debug`.
+//! - `netplay` (default): two-player sessions over UDP, in `netplay`.
+//! - `search` (default): RAM search for cheat finding, in `search`.
+//! - `trace_cpu`, `trace_bus`, `trace_ppu`, `trace_all`: verbose tracing
+//!   compiled into the hot paths.
+
 #![forbid(unsafe_code)]
+#![cfg_attr(docsrs, feature(doc_cfg))]
 
+use std::borrow::Cow;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 
+use crate::apu::Apu;
+use crate::audio::{AudioSink, StereoSample};
+use crate::capture::Capture;
+use crate::bios::BiosKind;
 use crate::cpu::Cpu;
+use crate::dma::{DMA_START_DELAY, Dma, DmaTiming, VIDEO_CAPTURE_LINES};
+use crate::error::CoreError;
+use crate::frame_report::FrameReport;
 use crate::ppu::Ppu;
-use crate::video::{framebuffer_rgb555_to_rgba, GBA_SCREEN_H, GBA_SCREEN_W};
-use crate::bus::Bus;
+use crate::video::{
+    framebuffer_rgb555_to_rgba, ColorTable, Frame, FrameSink, Image, PixelFormat, GBA_SCREEN_H, GBA_SCREEN_W,
+};
+use crate::bus::{Bus, BusAccess};
+use crate::cart::patch;
+use crate::cart::{BackupType, CartConfig, PeripheralInput, RomHeader};
+use crate::cheats::Cheats;
+use crate::config::{Accuracy, BootMode, EmulatorBuilder, EmulatorConfig, RtcClock, DETERMINISTIC_EPOCH};
+use crate::input::KeyState;
+use crate::io::{Io, IoSnapshot, DISPSTAT_HBLANK, DISPSTAT_VBLANK, DISPSTAT_VCOUNT};
+use crate::mem::{DirtyPages, Mem};
+use crate::movie::{Movie, MovieError, MovieSession, MovieStatus};
+use crate::peripherals::{gbp, CartridgeDevice, Peripheral};
+use crate::scheduler::{EventKind, Scheduler};
+use crate::sio::gbp::GameBoyPlayer;
+use crate::sio::SerialDevice;
+use crate::state::{StateError, StateReader, StateWriter, STATE_MAGIC, STATE_VERSION};
+use crate::timer::Timers;
+use crate::timing::IdleLoopDetector;
 
 pub mod apu;
+pub mod archive;
 pub mod audio;
+pub mod bios;
+#[doc(hidden)]
 pub mod bus;
+pub mod capture;
 pub mod cart;
+pub mod cheats;
+pub mod config;
+#[doc(hidden)]
 pub mod cpu;
+#[cfg(feature = "debugger")]
+#[cfg_attr(docsrs, doc(cfg(feature = "debugger")))]
+pub mod debug;
+#[doc(hidden)]
+pub mod dma;
+pub mod error;
+pub mod frame_report;
+pub mod guest_log;
+pub mod input;
+#[doc(hidden)]
 pub mod io;
 pub mod log_buffer;
+#[doc(hidden)]
 pub mod mem;
+pub mod movie;
+#[cfg(feature = "netplay")]
+#[cfg_attr(docsrs, doc(cfg(feature = "netplay")))]
+pub mod netplay;
+pub mod peripherals;
+#[doc(hidden)]
 pub mod ppu;
-pub mod timing;
+pub mod prelude;
+pub(crate) mod scheduler;
+#[cfg(feature = "search")]
+#[cfg_attr(docsrs, doc(cfg(feature = "search")))]
+pub mod search;
+pub mod sio;
+#[doc(hidden)]
+pub mod state;
+pub mod timer;
+pub(crate) mod timing;
 pub mod video;
 
-const CYCLES_PER_SCANLINE: usize = 1232;
-const SCANLINES_PER_FRAME: usize = 228;
-const VISIBLE_SCANLINES: usize = 160;
-const HBLANK_START_CYCLE: usize = 960;
+const CYCLES_PER_SCANLINE: u64 = 1232;
+const SCANLINES_PER_FRAME: u16 = 228;
+const VISIBLE_SCANLINES: u16 = 160;
+// HBlank starts at cycle 960 as far as the PPU is concerned, but hardware
+// raises the DISPSTAT flag, the IRQ and HBlank DMA only at cycle 1006. Fast
+// accuracy keeps the older approximation of firing everything at 960.
+const HBLANK_START_CYCLE: u64 = 960;
+const HBLANK_FLAG_CYCLE: u64 = 1006;
+const CPU_CLOCK: u64 = 16_777_216;
+// Start of the IWRAM the BIOS uses for its stacks and interrupt handler.
+const IWRAM_BIOS_AREA: usize = 0x7E00;
 
 pub struct Emulator {
     cpu: Cpu,
     ppu: Ppu,
     bus: Bus,
+    apu: Apu,
     rgba_frame: Vec<u8>,
-    cycles: usize,
+    colors: ColorTable,
     frame_count: u64,
+    paused: bool,
+    // Frames `step_frame` still runs while paused.
+    queued_frames: u32,
     frame_ready: bool,
+    // Frames run without drawing; see `set_skip_render`.
+    skip_render: bool,
     bios_loaded: bool,
+    bios_kind: Option<BiosKind>,
     rom_loaded: bool,
+    // Image loaded with `load_multiboot`, copied to EWRAM again on reset.
+    multiboot: Option<Vec<u8>>,
+    config: EmulatorConfig,
+    rom_path: Option<PathBuf>,
+    rom_patch: Option<PathBuf>,
+    idle_loop: IdleLoopDetector,
+    rumble: bool,
+    rumble_callback: Option<Box<dyn FnMut(bool) + Send>>,
+    // Buttons held on the pad, before peripherals filter KEYINPUT.
+    keys: KeyState,
+    peripherals: Vec<Box<dyn Peripheral>>,
+    frame_sink: Option<Box<dyn FrameSink>>,
+    audio_sink: Option<Box<dyn AudioSink>>,
+    // Samples of the frame being run, mixed only while a sink is installed.
+    samples: Vec<StereoSample>,
+    // Scheduler time the APU has produced samples up to.
+    audio_cycle: u64,
+    capture: Option<Capture>,
+    movie: Option<MovieSession>,
+    cheats: Cheats,
+    // Statistics for the frame being run, then for the last one run.
+    report: FrameReport,
 }
 
 impl Emulator {
-    pub fn new() -> Self {
+    /// Emulator with the default options; see `builder` to change them.
+    pub fn new() -> Self { Self::builder().build() }
+
+    pub fn builder() -> EmulatorBuilder { EmulatorBuilder::new() }
+
+    pub(crate) fn with_config(config: EmulatorConfig) -> Self {
         log::info!("Emulator instance created");
-        Self {
+        let mut emu = Self {
             cpu: Cpu::new(),
             ppu: Ppu::new(),
             bus: Bus::new(),
+            apu: Apu::new(config.sample_rate),
             rgba_frame: vec![0u8; GBA_SCREEN_W * GBA_SCREEN_H * 4],
-            cycles: 0,
+            colors: ColorTable::default(),
             frame_count: 0,
+            paused: false,
+            queued_frames: 0,
             frame_ready: false,
+            skip_render: false,
             bios_loaded: false,
+            bios_kind: None,
             rom_loaded: false,
-        }
+            multiboot: None,
+            config: EmulatorConfig::new(),
+            rom_path: None,
+            rom_patch: None,
+            idle_loop: IdleLoopDetector::new(),
+            rumble: false,
+            rumble_callback: None,
+            keys: KeyState::NONE,
+            peripherals: Vec::new(),
+            frame_sink: None,
+            audio_sink: None,
+            samples: Vec::new(),
+            audio_cycle: 0,
+            capture: None,
+            movie: None,
+            cheats: Cheats::new(),
+            report: FrameReport::default(),
+        };
+        emu.set_config(config);
+        emu.reset_timing();
+        emu
     }
 
-    pub fn reset(&mut self) {
-        log::info!("Emulator reset");
+    /// Power cycle: clears all RAM, restores I/O defaults and boots again
+    /// through the BIOS or straight into the ROM. Battery saves survive.
+    pub fn hard_reset(&mut self) {
+        log::info!("Emulator hard reset");
+        let coverage = self.cpu.take_coverage();
+        let trace = self.cpu.take_trace();
         self.cpu = Cpu::new();
+        if let Some(coverage) = coverage {
+            self.cpu.set_coverage(coverage);
+        }
+        if let Some(trace) = trace {
+            self.cpu.set_trace(trace);
+        }
         self.ppu = Ppu::new();
-        self.cycles = 0;
         self.frame_count = 0;
         self.frame_ready = false;
+        self.idle_loop.reset();
+        self.power_on();
+        self.reset_timing();
+        for peripheral in &mut self.peripherals {
+            peripheral.reset();
+        }
 
-        if self.bios_loaded {
+        if self.multiboot.is_some() {
+            self.init_multiboot();
+            log::info!("Entry point: multiboot image (EWRAM)");
+        } else if self.bios_loaded && self.config.boot_mode == BootMode::Bios {
             self.cpu.set_entry_point(&mut self.bus, 0x0000_0000);
             log::info!("Entry point: BIOS (0x00000000)");
         } else if self.rom_loaded {
-            self.cpu.set_entry_point(&mut self.bus, 0x0800_0000);
+            self.init_without_bios();
             log::info!("Entry point: ROM (0x08000000)");
         }
     }
 
-    pub fn load_bios(&mut self, path: &Path) -> Result<(), std::io::Error> {
+    /// Restarts the game as the BIOS SoftReset call (SWI 0) does, like the
+    /// A+B+Select+Start reset games offer. Memory and I/O are kept, except
+    /// for the BIOS area at the top of IWRAM.
+    pub fn soft_reset(&mut self) {
+        log::info!("Emulator soft reset");
+        self.bus.io.halted = false;
+        self.idle_loop.reset();
+        self.cpu.soft_reset(&mut self.bus);
+    }
+
+    /// Loads a BIOS image. Dumps other than the official one are accepted
+    /// with a warning.
+    pub fn load_bios(&mut self, path: &Path) -> Result<(), CoreError> {
         let data = std::fs::read(path)?;
+        if data.is_empty() || data.len() > mem::BIOS_SIZE {
+            return Err(CoreError::InvalidBios(format!("{} bytes, expected 16 KiB", data.len())));
+        }
         log::info!("BIOS loaded: {} bytes from {:?}", data.len(), path);
+        let kind = BiosKind::identify(&data);
+        if kind != BiosKind::Official {
+            log::warn!(
+                "BIOS {:?} is not the official dump (SHA-1 {}); boot and SWI timing may differ from hardware",
+                path,
+                bios::hex(&bios::sha1(&data))
+            );
+        }
         self.bus.load_bios(&data);
         self.bios_loaded = true;
+        self.bios_kind = Some(kind);
         self.cpu.set_entry_point(&mut self.bus, 0x0000_0000);
         Ok(())
     }
 
-    pub fn load_rom(&mut self, rom_path: &PathBuf) {
-        match std::fs::read(rom_path) {
-            Ok(data) => {
-                log::info!("ROM loaded: {} bytes from {:?}", data.len(), rom_path);
-                self.bus.load_rom(&data);
-                self.rom_loaded = true;
+    /// Loads a ROM file; `.zip` and `.gz` archives are extracted first, and
+    /// `.mb` files are loaded as multiboot images. An IPS, UPS or BPS patch
+    /// with the ROM's name next to it is applied. On error the previous ROM
+    /// stays loaded.
+    pub fn load_rom(&mut self, rom_path: &Path) -> Result<(), CoreError> {
+        self.load_rom_patched(rom_path, patch::find_patch(rom_path).as_deref())
+    }
 
-                if !self.bios_loaded {
-                    self.init_without_bios();
-                    log::info!("Entry point: ROM (0x08000000) - no BIOS");
-                }
-            }
-            Err(e) => {
-                log::error!("Failed to load ROM {:?}: {}", rom_path, e);
-            }
+    /// `load_rom` with `patch` applied instead of the patch found next to
+    /// the ROM, or with no patch at all.
+    pub fn load_rom_patched(&mut self, rom_path: &Path, patch: Option<&Path>) -> Result<(), CoreError> {
+        let data = std::fs::read(rom_path)?;
+        let mut rom = archive::extract_rom(&data)?;
+        if let Some(patch_path) = patch {
+            rom = Cow::Owned(patch::apply_patch(&rom, &std::fs::read(patch_path)?)?);
+            log::info!("Patch applied: {:?}", patch_path);
+        }
+        if rom_path.extension().is_some_and(|e| e.eq_ignore_ascii_case("mb")) {
+            self.load_multiboot(&rom)?;
+        } else {
+            self.load_rom_bytes(&rom)?;
+        }
+        self.rom_path = Some(rom_path.to_path_buf());
+        self.rom_patch = patch.map(Path::to_path_buf);
+        log::info!("ROM loaded: {} bytes from {:?}", rom.len(), rom_path);
+        Ok(())
+    }
+
+    /// Loads a ROM image that did not come from a file; battery saves then
+    /// have no default location.
+    pub fn load_rom_bytes(&mut self, data: &[u8]) -> Result<(), CoreError> {
+        if data.is_empty() {
+            return Err(CoreError::InvalidRom("image is empty".to_string()));
+        }
+        if data.len() > mem::ROM_MAX_SIZE {
+            return Err(CoreError::InvalidRom(format!("{} bytes, larger than 32 MiB", data.len())));
+        }
+        self.bus.load_rom(data);
+        if self.config.backup.is_some() || self.config.rtc.is_some() {
+            self.set_cart_config(self.cart_config());
+        }
+        self.cheats = Cheats::new();
+        self.rom_loaded = true;
+        self.multiboot = None;
+        self.rom_path = None;
+        self.rom_patch = None;
+
+        if !self.bios_loaded {
+            self.init_without_bios();
+            log::info!("Entry point: ROM (0x08000000) - no BIOS");
+        } else if self.config.boot_mode == BootMode::SkipBios {
+            self.init_without_bios();
+            log::info!("Entry point: ROM (0x08000000) - BIOS skipped");
+        } else if self.config.patch_header && cart::fix_header(&mut self.bus.mem.rom) {
+            log::info!("ROM header patched with the Nintendo logo and checksum for the BIOS");
+        } else if let Some(problem) = self.header_problem() {
+            log::warn!("The BIOS will not start this ROM: {}", problem);
+        }
+        Ok(())
+    }
+
+    /// Loads a multiboot image (a `.mb` file, as sent over the link cable)
+    /// and starts it from EWRAM with the cartridge slot empty. The BIOS
+    /// transfer itself is skipped, even in BIOS boot mode: the machine is
+    /// left as the BIOS leaves it once an image has been received.
+    pub fn load_multiboot(&mut self, data: &[u8]) -> Result<(), CoreError> {
+        if data.is_empty() {
+            return Err(CoreError::InvalidRom("image is empty".to_string()));
         }
+        if data.len() > mem::EWRAM_SIZE {
+            return Err(CoreError::InvalidRom(format!("{} bytes, larger than 256 KiB", data.len())));
+        }
+        // The header identifies the game as a cartridge's would.
+        self.bus.load_rom(&[]);
+        self.bus.cart.load(data);
+        self.cheats = Cheats::new();
+        self.rom_loaded = true;
+        self.multiboot = Some(data.to_vec());
+        self.rom_path = None;
+        self.rom_patch = None;
+        self.init_multiboot();
+        log::info!("Entry point: multiboot image (EWRAM)");
+        Ok(())
+    }
+
+    // Memory and I/O registers as the machine powers up; the CPU and timing
+    // are reset separately.
+    fn power_on(&mut self) {
+        self.bus.mem.power_on();
+        self.bus.io = Io::new();
+        self.bus.io.keyinput = self.filtered_keyinput();
+        self.bus.timing.set_waitcnt(self.bus.io.waitcnt);
+        self.bus.timing.set_memcnt(self.bus.io.memcnt);
+        self.bus.dma = Dma::new();
     }
 
+    // Leaves the machine as the BIOS does when it hands over to the cartridge.
     fn init_without_bios(&mut self) {
         use crate::cpu::CpuMode;
 
-        self.cpu.set_swi_hle(true);
+        self.cpu.set_swi_hle(!self.bios_loaded);
+
+        // The BIOS clears its stacks and the interrupt vector area, turns on
+        // forced blank and centers the sound output.
+        self.bus.mem.iwram[IWRAM_BIOS_AREA..].fill(0);
+        self.bus.mem.dirty.mark_all();
+        let io = &mut self.bus.io;
+        io.dispcnt = 0x0080;
+        io.soundbias = 0x0200;
+        io.postflg = 1;
 
         self.cpu.set_mode(CpuMode::Supervisor);
         self.cpu.write_reg(13, 0x0300_7FE0);
@@ -113,71 +398,630 @@ impl Emulator {
         self.cpu.set_entry_point(&mut self.bus, 0x0800_0000);
     }
 
-    pub fn step_cpu(&mut self) {
-        self.cpu.step(&mut self.bus);
+    // Leaves the machine as the BIOS does after a multiboot transfer. The
+    // BIOS enters through the multiboot vector at 0xC0, having written the
+    // transfer mode (multiplay) and slave number to the header, and makes
+    // SoftReset restart from EWRAM. Images that leave the vector blank were
+    // built for loaders that jump straight to 0x02000000, so they start at
+    // the ROM entry vector instead.
+    fn init_multiboot(&mut self) {
+        let Some(image) = &self.multiboot else {
+            return;
+        };
+        let has_vector = image.get(0xC3) == Some(&0xEA);
+        self.bus.mem.ewram[..image.len()].copy_from_slice(image);
+        self.init_without_bios();
+
+        if has_vector {
+            self.bus.mem.ewram[0xC4] = 0x03;
+            self.bus.mem.ewram[0xC5] = 0x01;
+        }
+        self.bus.mem.iwram[0x7FFA] = 1;
+        self.bus.mem.dirty.mark_all();
+        let entry = if has_vector { 0x0200_00C0 } else { 0x0200_0000 };
+        self.cpu.set_entry_point(&mut self.bus, entry);
     }
 
-    pub fn run_frame(&mut self) {
-        self.frame_ready = false;
-        self.bus.set_access_permissions(true, true, true);
+    /// Runs one CPU instruction and returns the cycles it took.
+    pub fn step_cpu(&mut self) -> u64 { self.cpu.step(&mut self.bus) }
+
+    /// Enables fast-forwarding through loops that only poll for an event
+    /// (VCOUNT/IF/DISPSTAT spins, branch-to-self). Off by default.
+    pub fn set_idle_loop_skip(&mut self, enabled: bool) {
+        self.config.idle_loop_skip = enabled;
+        self.idle_loop.reset();
+    }
+
+    pub fn idle_loop_skip(&self) -> bool { self.config.idle_loop_skip }
+
+    /// Replaces the emulation options. The boot mode and the cartridge
+    /// overrides take effect on the next `load_rom`/`reset`; everything else
+    /// immediately.
+    pub fn set_config(&mut self, config: EmulatorConfig) {
+        self.set_idle_loop_skip(config.idle_loop_skip);
+        self.bus.timing.set_cycle_accurate(config.cycle_timing);
+        self.bus.set_open_bus(config.open_bus);
+        self.cpu.set_swi_log(config.swi_log);
+        if config.color_profile != self.colors.profile() {
+            self.colors = ColorTable::new(config.color_profile);
+        }
+        self.apu.set_sample_rate(config.sample_rate);
+        if let Some(level) = config.log_level {
+            log::set_max_level(level);
+        }
+        self.config = config;
+    }
+
+    pub fn config(&self) -> &EmulatorConfig { &self.config }
+
+    /// Serializes the whole machine. BIOS, ROM and host-side devices (link
+    /// peer, sensor readings) are not included.
+    pub fn save_state(&self) -> Vec<u8> { self.write_state(StateWriter::new()) }
+
+    /// Restores a state from `save_state`. On error the emulator is left as
+    /// it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> { self.read_state(StateReader::new(data)) }
+
+    /// Pages of EWRAM, IWRAM and VRAM written since the last call (or
+    /// overwritten wholesale by a reset or a state load). Meant for one
+    /// consumer at a time, such as `RewindBuffer`.
+    #[doc(hidden)]
+    pub fn take_dirty_pages(&mut self) -> DirtyPages { std::mem::take(&mut self.bus.mem.dirty) }
+
+    /// `save_state` without EWRAM, IWRAM and VRAM, which the caller keeps
+    /// itself with the help of `take_dirty_pages`.
+    pub(crate) fn save_snapshot(&self) -> Vec<u8> { self.write_state(StateWriter::without_ram()) }
+
+    /// Restores a `save_snapshot` along with the tracked RAM, given as its
+    /// pages back to back, and clears the dirty pages.
+    pub(crate) fn load_snapshot(&mut self, data: &[u8], ram: &[u8]) -> Result<(), StateError> {
+        self.read_state(StateReader::without_ram(data))?;
+        for (i, page) in ram.chunks_exact(mem::PAGE_SIZE).enumerate() {
+            self.bus.mem.page_mut(i).copy_from_slice(page);
+        }
+        self.bus.mem.dirty = DirtyPages::default();
+        Ok(())
+    }
+
+    fn write_state(&self, mut w: StateWriter) -> Vec<u8> {
+        w.bytes(&STATE_MAGIC);
+        w.put(&STATE_VERSION);
+        w.put(&self.bus.cart.rom_crc32());
+        self.save_machine(&mut w);
+        w.finish()
+    }
+
+    fn read_state(&mut self, mut r: StateReader) -> Result<(), StateError> {
+        if r.bytes(STATE_MAGIC.len())? != STATE_MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let (mut version, mut crc) = (0u16, 0u32);
+        r.take(&mut version)?;
+        if version != STATE_VERSION {
+            return Err(StateError::Version(version));
+        }
+        r.take(&mut crc)?;
+        let expected = self.bus.cart.rom_crc32();
+        if crc != expected {
+            return Err(StateError::RomMismatch { expected, found: crc });
+        }
 
-        let mut prev_vblank = false;
-        let mut prev_hblank = false;
+        let mut backup = StateWriter::new();
+        self.save_machine(&mut backup);
+        if let Err(e) = self.load_machine(&mut r) {
+            let backup = backup.finish();
+            self.load_machine(&mut StateReader::new(&backup)).expect("reloading own state");
+            return Err(e);
+        }
+        self.idle_loop.reset();
+        framebuffer_rgb555_to_rgba(&mut self.rgba_frame, self.ppu.framebuffer(), &self.colors);
+        self.frame_ready = true;
+        self.update_rumble();
+        Ok(())
+    }
 
-        for scanline in 0..SCANLINES_PER_FRAME {
-            self.bus.io.vcount = scanline as u16;
+    fn save_machine(&self, w: &mut StateWriter) {
+        w.put(&self.cpu);
+        w.put(&self.ppu);
+        w.put(&self.bus);
+        w.put(&self.frame_count);
+    }
 
-            let in_vblank = scanline >= VISIBLE_SCANLINES;
-            let lyc = (self.bus.io.dispstat >> 8) as usize;
-            let vcounter_match = scanline == lyc;
+    fn load_machine(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.take(&mut self.cpu)?;
+        r.take(&mut self.ppu)?;
+        r.take(&mut self.bus)?;
+        r.take(&mut self.frame_count)?;
+        if !r.is_empty() {
+            return Err(StateError::Corrupt("trailing data"));
+        }
+        Ok(())
+    }
+
+    /// Cheats for the loaded ROM, applied at the start of every frame.
+    /// Cleared when a new ROM is loaded.
+    pub fn cheats(&self) -> &Cheats { &self.cheats }
+    pub fn cheats_mut(&mut self) -> &mut Cheats { &mut self.cheats }
+
+    /// Starts recording input into a movie, either from power-on (resets the
+    /// emulator) or from the current state.
+    pub fn record_movie(&mut self, from_power_on: bool) {
+        let start_state = if from_power_on {
+            self.hard_reset();
+            None
+        } else {
+            Some(self.save_state())
+        };
+        let movie = Movie::new(self.bus.cart.rom_crc32(), start_state);
+        self.movie = Some(MovieSession { movie, start_frame: self.frame_count, playing: false });
+    }
 
-            if in_vblank && !prev_vblank {
-                if (self.bus.io.dispstat & 0x08) != 0 {
-                    self.bus.io.request_interrupt(0x0001);
+    /// Rewinds to the movie's start and replays its input from then on,
+    /// ignoring `set_keys` until it ends.
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), MovieError> {
+        let expected = self.bus.cart.rom_crc32();
+        if movie.rom_crc32 != expected {
+            return Err(MovieError::RomMismatch { expected, found: movie.rom_crc32 });
+        }
+        match &movie.start_state {
+            Some(state) => self.load_state(state)?,
+            None => self.hard_reset(),
+        }
+        self.movie = Some(MovieSession { movie, start_frame: self.frame_count, playing: true });
+        Ok(())
+    }
+
+    /// Stops recording or playback, returning the movie.
+    pub fn stop_movie(&mut self) -> Option<Movie> { self.movie.take().map(|s| s.movie) }
+
+    pub fn movie_status(&self) -> MovieStatus {
+        match &self.movie {
+            None => MovieStatus::Idle,
+            Some(s) if s.playing => MovieStatus::Playing {
+                frame: self.frame_count.saturating_sub(s.start_frame) as usize,
+                frames: s.movie.frames.len(),
+            },
+            Some(s) => MovieStatus::Recording { frames: s.movie.frames.len() },
+        }
+    }
+
+    // Feeds (or records) this frame's movie input. Both directions go through
+    // `set_keys` once per frame so the keypad interrupt fires identically.
+    fn update_movie(&mut self) {
+        let Some(session) = &mut self.movie else {
+            return;
+        };
+        let Some(index) = self.frame_count.checked_sub(session.start_frame) else {
+            return;
+        };
+        let index = index as usize;
+        let keys = if session.playing {
+            match session.movie.frames.get(index) {
+                Some(&keys) => keys,
+                None => {
+                    log::info!("Movie playback finished after {} frames", index);
+                    self.movie = None;
+                    return;
                 }
             }
+        } else {
+            let keys = self.keys;
+            session.movie.frames.truncate(index);
+            session.movie.frames.push(keys);
+            keys
+        };
+        self.set_keys(keys);
+    }
 
-            if vcounter_match {
-                if (self.bus.io.dispstat & 0x20) != 0 {
-                    self.bus.io.request_interrupt(0x0004);
-                }
+    /// Patch applied to the loaded ROM by `load_rom`.
+    pub fn rom_patch(&self) -> Option<&Path> { self.rom_patch.as_deref() }
+
+    /// Battery save file for the loaded ROM, per the configured save directory.
+    pub fn save_path(&self) -> Option<PathBuf> {
+        self.rom_path.as_deref().map(|rom| self.config.save_path(rom))
+    }
+
+    /// Updates the held buttons, raising the keypad interrupt per KEYCNT.
+    pub fn set_keys(&mut self, keys: KeyState) {
+        self.keys = keys;
+        self.bus.io.keyinput = self.filtered_keyinput();
+        self.bus.io.update_keypad_irq();
+    }
+
+    fn filtered_keyinput(&self) -> u16 {
+        self.peripherals.iter().fold(self.keys.keyinput(), |keyinput, p| p.keyinput(keyinput))
+    }
+
+    /// Overrides the cartridge hardware picked from the game database or
+    /// auto-detection, e.g. from a user-supplied database entry. The backup
+    /// type and RTC forced in the emulator config still win.
+    pub fn set_cart_config(&mut self, mut config: CartConfig) {
+        if let Some(backup) = self.config.backup {
+            config.backup = Some(backup);
+        }
+        if let Some(rtc) = self.config.rtc {
+            config.rtc = rtc;
+        }
+        let Bus { cart, mem, .. } = &mut self.bus;
+        cart.set_config(config, &mem.rom);
+    }
+
+    pub fn cart_config(&self) -> CartConfig { self.bus.cart.config() }
+    pub fn rom_header(&self) -> Option<&RomHeader> { self.bus.cart.header() }
+
+    /// Why the BIOS will refuse the loaded ROM's header and hang at the
+    /// logo, when it is about to boot it; `EmulatorConfig::patch_header` and
+    /// `BootMode::SkipBios` get such ROMs running.
+    pub fn header_problem(&self) -> Option<&'static str> {
+        let bios_boot = self.bios_loaded && self.config.boot_mode == BootMode::Bios;
+        if !bios_boot || !self.rom_loaded || self.multiboot.is_some() {
+            return None;
+        }
+        cart::header_problem(&self.bus.mem.rom)
+    }
+    pub fn rom_crc32(&self) -> u32 { self.bus.cart.rom_crc32() }
+
+    /// Current time for the cartridge RTC, in seconds since the Unix epoch.
+    pub fn rtc_time(&self) -> u64 {
+        match self.config.rtc_clock {
+            RtcClock::Host if self.config.deterministic => {
+                DETERMINISTIC_EPOCH + self.bus.scheduler.now() / CPU_CLOCK
             }
+            RtcClock::Host => SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            RtcClock::Emulated(start) => start + self.bus.scheduler.now() / CPU_CLOCK,
+        }
+    }
+    /// PPU, DMA, timer and interrupt registers, for register viewers.
+    #[doc(hidden)]
+    pub fn io_snapshot(&self) -> IoSnapshot { IoSnapshot::capture(&self.bus) }
+
+    /// Battery save in the raw `.sav` layout mGBA and VBA use; empty for
+    /// carts without a backup chip.
+    pub fn battery_save(&self) -> Vec<u8> { self.bus.cart.backup_data() }
+
+    /// Loads a `.sav` file into the backup chip. Fails for carts without one,
+    /// where the save would otherwise be dropped without a trace.
+    pub fn load_battery_save(&mut self, data: &[u8]) -> Result<(), CoreError> {
+        if self.bus.cart.backup_type() == BackupType::None {
+            return Err(CoreError::Unsupported("this cartridge has no backup memory"));
+        }
+        self.bus.cart.load_backup(data);
+        Ok(())
+    }
+    /// Whether the game wrote to its backup chip since the last call, i.e.
+    /// the battery save needs writing out.
+    pub fn take_battery_dirty(&mut self) -> bool { self.bus.cart.take_dirty() }
+    /// The battery save if it changed since the last call.
+    pub fn take_dirty_battery_save(&mut self) -> Option<Vec<u8>> { self.bus.cart.take_dirty_backup() }
+
+    /// Feeds a host-side value (sunlight, rotation, tilt) to the cartridge
+    /// sensors. Ignored when the loaded cart has no such sensor.
+    pub fn set_peripheral_input(&mut self, input: PeripheralInput) {
+        self.bus.cart.set_peripheral_input(input);
+    }
+
+    /// Registers a callback invoked with the new motor state whenever the cart
+    /// (GPIO rumble) or a Game Boy Player on the link port starts or stops
+    /// rumbling. Checked once per frame.
+    pub fn set_rumble_callback(&mut self, callback: impl FnMut(bool) + Send + 'static) {
+        self.rumble_callback = Some(Box::new(callback));
+    }
+
+    pub fn rumble_active(&self) -> bool { self.rumble }
+
+    fn update_rumble(&mut self) {
+        let rumble = self.bus.cart.gpio.rumble() || self.bus.sio.rumble();
+        if rumble == self.rumble {
+            return;
+        }
+        self.rumble = rumble;
+        log::debug!("Rumble {}", if rumble { "on" } else { "off" });
+        if let Some(callback) = &mut self.rumble_callback {
+            callback(rumble);
+        }
+    }
+
+    /// Connects a device to the link port, replacing the default dummy peer.
+    pub fn set_serial_device(&mut self, device: Box<dyn SerialDevice>) {
+        self.bus.sio.set_device(device);
+    }
+
+    /// Puts add-on hardware (e.g. an e-Reader) in the cartridge slot, or
+    /// removes it with None.
+    pub fn set_cartridge_device(&mut self, device: Option<Box<dyn CartridgeDevice>>) {
+        if let Some(device) = &device {
+            log::info!("Cartridge slot: {} attached", device.name());
+        }
+        self.bus.cart_device = device;
+    }
+
+    /// Hands a scanned card strip to the card reader in the cartridge slot.
+    /// Returns false without a reader or if it rejected the data.
+    pub fn scan_dot_code(&mut self, data: &[u8]) -> bool {
+        self.bus.cart_device.as_mut().is_some_and(|device| device.scan_dot_code(data))
+    }
+
+    /// Attaches hardware that drives the keypad or watches frames; see
+    /// `peripherals::Peripheral`.
+    pub fn attach_peripheral(&mut self, peripheral: Box<dyn Peripheral>) {
+        log::info!("Peripheral attached: {}", peripheral.name());
+        self.peripherals.push(peripheral);
+        self.bus.io.keyinput = self.filtered_keyinput();
+    }
 
-            self.bus.io.dispstat = (self.bus.io.dispstat & 0xFFF8)
-                | (if in_vblank { 1 } else { 0 })
-                | (if vcounter_match { 4 } else { 0 });
+    pub fn detach_peripherals(&mut self) {
+        self.peripherals.clear();
+        self.bus.io.keyinput = self.filtered_keyinput();
+    }
 
-            prev_vblank = in_vblank;
+    /// Plugs in a Game Boy Player: its boot detection on the keypad and its
+    /// rumble on the link port.
+    pub fn attach_game_boy_player(&mut self) {
+        self.set_serial_device(Box::new(GameBoyPlayer::new()));
+        self.attach_peripheral(Box::new(gbp::BootDetector::new()));
+    }
 
-            for cycle_in_line in 0..CYCLES_PER_SCANLINE {
-                let in_hblank = cycle_in_line >= HBLANK_START_CYCLE;
+    fn update_peripherals(&mut self) {
+        if self.peripherals.is_empty() {
+            return;
+        }
+        for peripheral in &mut self.peripherals {
+            peripheral.end_frame(&self.bus.io);
+        }
+        self.bus.io.keyinput = self.filtered_keyinput();
+    }
+
+    // The RTC only counts seconds, so setting its clock once per run call
+    // (a frame at most, normally) is close enough.
+    fn update_rtc(&mut self) {
+        if self.bus.cart.config().rtc {
+            let time = self.rtc_time();
+            self.bus.cart.gpio.set_rtc_time(time);
+        }
+    }
+
+    fn hblank_cycle(&self) -> u64 {
+        match self.config.accuracy {
+            Accuracy::Fast => HBLANK_START_CYCLE,
+            Accuracy::Accurate => HBLANK_FLAG_CYCLE,
+        }
+    }
 
-                if in_hblank && !prev_hblank {
-                    if (self.bus.io.dispstat & 0x10) != 0 {
-                        self.bus.io.request_interrupt(0x0002);
+    fn reset_timing(&mut self) {
+        self.bus.scheduler = Scheduler::new();
+        self.bus.timers = Timers::new();
+        self.bus.sio.reset();
+        self.bus.io.vcount = 0;
+        self.bus.scheduler.schedule(self.hblank_cycle(), EventKind::HBlank);
+        self.bus.scheduler.schedule(CYCLES_PER_SCANLINE, EventKind::HDraw);
+    }
+
+    // Skips whole iterations of an idle loop, stopping short of the next
+    // event so the remaining steps run normally.
+    fn skip_idle_loop(&mut self) {
+        let now = self.bus.scheduler.now();
+        let external = (self.bus.timers.counter_reads(), self.bus.timing.state());
+        let Some(period) = self.idle_loop.observe(&self.cpu, &mut self.bus, now, external) else {
+            return;
+        };
+        let next_event = self.bus.scheduler.next_event_time();
+        let skipped = (next_event - 1 - now) / period * period;
+        self.idle_loop.record_skip(skipped);
+        self.bus.scheduler.advance(skipped);
+    }
+
+    // Runs the CPU until the next scheduled event, charging each instruction
+    // the bus cycles it used.
+    fn run_until_next_event(&mut self) {
+        while self.bus.scheduler.now() < self.bus.scheduler.next_event_time() {
+            self.run_step(u64::MAX);
+        }
+    }
+
+    // Takes an IRQ the CPU sees, then runs one instruction, or idles up to
+    // the next event (or `limit`, if sooner) while halted.
+    fn run_step(&mut self, limit: u64) {
+        if self.bus.io.irq_line(self.bus.scheduler.now()) && self.cpu.trigger_irq(&mut self.bus) {
+            self.report.irqs += 1;
+            // Exception entry refills the pipeline from the vector.
+            let cycles = self.bus.timing.take_cycles();
+            self.bus.scheduler.advance(cycles);
+        }
+        if self.bus.io.is_halted() {
+            let now = self.bus.scheduler.now();
+            let next_event = self.bus.scheduler.next_event_time().min(limit);
+            self.bus.timing.idle(next_event - now);
+            self.bus.timing.take_cycles();
+            self.bus.scheduler.advance_to(next_event);
+            self.report.halted_cycles += next_event - now;
+        } else {
+            if self.config.idle_loop_skip {
+                self.skip_idle_loop();
+            }
+            let cycles = self.step_cpu();
+            self.report.instructions += 1;
+            self.bus.scheduler.advance(cycles);
+        }
+    }
+
+    // Returns true when the event completes a frame.
+    fn handle_event(&mut self, kind: EventKind, time: u64) -> bool {
+        match kind {
+            EventKind::HBlank => {
+                let line = self.bus.io.vcount as usize;
+                if self.config.accuracy == Accuracy::Accurate && line < VISIBLE_SCANLINES as usize && self.draws_frame() {
+                    self.ppu.render_lines_with_bus(&mut self.bus, line..line + 1);
+                }
+                if line < VISIBLE_SCANLINES as usize {
+                    self.bus.io.advance_affine_refs();
+                }
+                let flags = self.bus.io.dispstat | DISPSTAT_HBLANK;
+                self.bus.io.set_dispstat_flags(flags);
+                if (self.bus.io.dispstat & 0x10) != 0 {
+                    self.bus.io.request_interrupt(0x0002);
+                }
+                if self.bus.io.vcount < VISIBLE_SCANLINES {
+                    self.bus.dma.trigger(DmaTiming::HBlank);
+                    self.schedule_dma(time);
+                }
+                false
+            }
+            EventKind::HDraw => {
+                self.bus.scheduler.schedule_at(time + self.hblank_cycle(), EventKind::HBlank);
+                self.bus.scheduler.schedule_at(time + CYCLES_PER_SCANLINE, EventKind::HDraw);
+                let scanline = (self.bus.io.vcount + 1) % SCANLINES_PER_FRAME;
+                self.start_scanline(scanline);
+                self.schedule_dma(time);
+                let irq = self.bus.sio.poll(&mut self.bus.io);
+                if irq != 0 {
+                    self.bus.io.request_interrupt(irq);
+                }
+                scanline == 0
+            }
+            EventKind::TimerOverflow(index) => {
+                let irq = self.bus.timers.handle_overflow(index, time, &mut self.bus.scheduler);
+                if irq != 0 {
+                    self.bus.io.request_interrupt(irq);
+                }
+                if index < 2 {
+                    for fifo in self.bus.sound.timer_overflow(index, &self.bus.io) {
+                        self.bus.dma.trigger_fifo(fifo);
                     }
+                    self.schedule_dma(time);
                 }
-
-                if in_hblank {
-                    self.bus.io.dispstat |= 2;
-                } else {
-                    self.bus.io.dispstat &= !2;
+                false
+            }
+            EventKind::SerialTransfer => {
+                let irq = self.bus.sio.complete(&mut self.bus.io);
+                if irq != 0 {
+                    self.bus.io.request_interrupt(irq);
                 }
-                prev_hblank = in_hblank;
+                false
+            }
+            EventKind::Dma => {
+                self.bus.run_pending_dma();
+                // The CPU waits for the bus, so later events see the stall.
+                let cycles = self.bus.timing.take_cycles();
+                self.bus.scheduler.advance(cycles);
+                false
+            }
+        }
+    }
 
-                if !self.bus.io.is_halted() {
-                    self.step_cpu();
-                }
+    // Starts the channels a display or sound FIFO trigger flagged once the
+    // DMA unit has woken up, so a transfer fired at HBlank lands before the
+    // next line. A start already pending runs every flagged channel.
+    fn schedule_dma(&mut self, time: u64) {
+        if self.bus.dma.next_pending().is_some() && self.bus.scheduler.next_time_of(EventKind::Dma).is_none() {
+            self.bus.scheduler.schedule_at(time + DMA_START_DELAY, EventKind::Dma);
+        }
+    }
+
+    fn start_scanline(&mut self, scanline: u16) {
+        self.bus.io.vcount = scanline;
+
+        let lyc = self.bus.io.dispstat >> 8;
+        let vcounter_match = scanline == lyc;
+
+        if scanline == VISIBLE_SCANLINES && (self.bus.io.dispstat & 0x08) != 0 {
+            self.bus.io.request_interrupt(0x0001);
+        }
+        if scanline == VISIBLE_SCANLINES {
+            self.bus.dma.trigger(DmaTiming::VBlank);
+            self.bus.io.latch_affine_refs();
+        }
+        if VIDEO_CAPTURE_LINES.contains(&scanline) {
+            self.bus.dma.trigger_video_capture();
+        } else if scanline == VIDEO_CAPTURE_LINES.end() + 1 {
+            self.bus.dma.end_video_capture();
+        }
+
+        if vcounter_match && (self.bus.io.dispstat & 0x20) != 0 {
+            self.bus.io.request_interrupt(0x0004);
+        }
+
+        // The VBlank flag drops on the last line, though VCOUNT still says
+        // VBlank; Fast accuracy keeps it set until line 0.
+        let vblank = scanline >= VISIBLE_SCANLINES
+            && (scanline != SCANLINES_PER_FRAME - 1 || self.config.accuracy == Accuracy::Fast);
+        self.bus.io.set_dispstat_flags(
+            (if vblank { DISPSTAT_VBLANK } else { 0 })
+                | (if vcounter_match { DISPSTAT_VCOUNT } else { 0 }),
+        );
+    }
+
+    // Mixes the samples due since the last call, up to the current event.
+    // Sample ticks are not scheduler events: at host rates they would split
+    // the CPU's slices every ~350 cycles. Instead every sample since the last
+    // event is mixed from the levels at this one, so a sound register write
+    // takes effect up to one event gap (under a scanline) early. The FIFOs
+    // only change at timer overflows, which are events, and stay exact.
+    fn run_apu(&mut self) {
+        let now = self.bus.scheduler.now();
+        if self.audio_sink.is_some() || self.capture.as_ref().is_some_and(Capture::wants_audio) {
+            self.apu.run(&self.bus.io, self.bus.sound.levels(), now - self.audio_cycle, &mut self.samples);
+        }
+        self.audio_cycle = now;
+    }
 
-                if self.bus.io.pending_interrupts() {
-                    self.cpu.trigger_irq(&mut self.bus);
+    fn record_capture(&mut self) {
+        let Some(capture) = &mut self.capture else {
+            return;
+        };
+        match capture.record(self.ppu.framebuffer(), &self.samples) {
+            Ok(false) => {}
+            Ok(true) => {
+                if let Err(e) = self.stop_capture() {
+                    log::error!("Capture failed: {}", e);
                 }
             }
+            Err(e) => {
+                log::error!("Capture failed: {}", e);
+                self.capture = None;
+            }
         }
+    }
 
-        self.ppu.render_frame_with_bus(&mut self.bus);
+    /// Runs until the next frame is complete and returns statistics about it
+    /// (also available from `last_frame_report`).
+    pub fn run_frame(&mut self) -> FrameReport {
+        self.update_rtc();
+        let start = Instant::now();
+        let start_cycles = self.bus.scheduler.now();
+        let start_dma = self.bus.dma.transfers();
+        self.report = FrameReport::default();
+        log_buffer::set_frame(self.frame_count);
+
+        self.update_movie();
+        self.cheats.apply(&mut self.bus);
+        self.frame_ready = false;
+        self.bus.set_access_permissions(true, true, true);
+
+        self.audio_cycle = start_cycles;
+        let mut frame_done = false;
+        while !frame_done {
+            self.run_until_next_event();
+            let apu_start = Instant::now();
+            self.run_apu();
+            self.report.apu_time += apu_start.elapsed();
+            while let Some((kind, time)) = self.bus.scheduler.pop_due() {
+                frame_done |= self.handle_event(kind, time);
+            }
+        }
+        let cpu_done = Instant::now();
+
+        let draw = self.draws_frame();
+        if self.config.accuracy == Accuracy::Fast && draw {
+            self.ppu.render_frame_with_bus(&mut self.bus);
+        }
+        let ppu_done = Instant::now();
         self.frame_ready = true;
         self.frame_count += 1;
+        self.cpu.swi_stats_mut().end_frame();
+        self.update_rumble();
+        self.update_peripherals();
 
         if self.frame_count.is_multiple_of(60) {
             log::debug!(
@@ -188,15 +1032,216 @@ impl Emulator {
             );
         }
 
-        framebuffer_rgb555_to_rgba(&mut self.rgba_frame, self.ppu.framebuffer());
+        let wants_rgba = self.frame_sink.as_ref().is_none_or(|sink| sink.format() == PixelFormat::Rgba8888);
+        if wants_rgba && draw {
+            framebuffer_rgb555_to_rgba(&mut self.rgba_frame, self.ppu.framebuffer(), &self.colors);
+        }
+        if let Some(sink) = &mut self.frame_sink
+            && draw
+        {
+            sink.present(match sink.format() {
+                PixelFormat::Bgr555 => Frame::bgr555(self.ppu.framebuffer()),
+                PixelFormat::Rgba8888 => Frame::rgba8888(&self.rgba_frame),
+            });
+        }
+        if let Some(sink) = &mut self.audio_sink {
+            sink.push(&self.samples);
+        }
+        self.record_capture();
+        self.samples.clear();
+
+        let end = Instant::now();
+        self.report.cycles = self.bus.scheduler.now() - start_cycles;
+        self.report.rendered = draw;
+        self.report.dma_transfers = self.bus.dma.transfers() - start_dma;
+        self.report.duration = end - start;
+        self.report.cpu_time = (cpu_done - start).saturating_sub(self.report.apu_time);
+        self.report.ppu_time = ppu_done - cpu_done;
+        self.report.convert_time = end - ppu_done;
+        self.report
+    }
+
+    /// Runs the CPU and hardware events for at least `cycles` cycles, stopping
+    /// at the first instruction boundary past them, and returns the cycles
+    /// actually run. Unlike `run_frame`, it does none of the end-of-frame work
+    /// (drawing in Fast accuracy, sinks, audio, the frame count); it is meant
+    /// for timing tests and debuggers.
+    pub fn run_cycles(&mut self, cycles: u64) -> u64 {
+        self.update_rtc();
+        let start = self.bus.scheduler.now();
+        let target = start + cycles;
+        while self.bus.scheduler.now() < target {
+            self.run_step(target);
+            while let Some((kind, time)) = self.bus.scheduler.pop_due() {
+                self.handle_event(kind, time);
+            }
+        }
+        self.bus.scheduler.now() - start
+    }
+
+    /// Runs until scanline `line` (VCOUNT) next starts, stopping at the first
+    /// instruction boundary from there, and returns the cycles run. From the
+    /// start of `line` itself, that is a whole frame. Like `run_cycles`, it
+    /// does no end-of-frame work, even when it crosses into a new frame.
+    ///
+    /// Panics if `line` is 228 or more.
+    pub fn run_to_scanline(&mut self, line: u16) -> u64 {
+        assert!(line < SCANLINES_PER_FRAME, "no scanline {}", line);
+        self.update_rtc();
+        let start = self.bus.scheduler.now();
+        let mut reached = false;
+        while !reached {
+            self.run_step(u64::MAX);
+            while let Some((kind, time)) = self.bus.scheduler.pop_due() {
+                self.handle_event(kind, time);
+                reached |= kind == EventKind::HDraw && self.bus.io.vcount == line;
+            }
+        }
+        self.bus.scheduler.now() - start
+    }
+
+    /// Runs until VBlank next starts (line 160), as `run_to_scanline`.
+    pub fn run_to_vblank(&mut self) -> u64 { self.run_to_scanline(VISIBLE_SCANLINES) }
+
+    /// The current scanline (VCOUNT, 0..228) and the cycle within it
+    /// (0..1232).
+    pub fn scanline_position(&self) -> (u16, u64) {
+        let line_end = self.bus.scheduler.next_time_of(EventKind::HDraw).unwrap_or(CYCLES_PER_SCANLINE);
+        (self.bus.io.vcount, (self.bus.scheduler.now() + CYCLES_PER_SCANLINE).saturating_sub(line_end))
+    }
+
+    /// Statistics for the last frame `run_frame` completed.
+    pub fn last_frame_report(&self) -> &FrameReport { &self.report }
+
+    /// Runs the next frame unless paused. While paused, runs only frames
+    /// queued by `advance_frames`; returns None once there are none left.
+    pub fn step_frame(&mut self) -> Option<FrameReport> {
+        if self.paused {
+            self.queued_frames = self.queued_frames.checked_sub(1)?;
+        }
+        Some(self.run_frame())
+    }
+
+    /// Pausing drops frames queued by `advance_frames`.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.queued_frames = 0;
+    }
+
+    pub fn is_paused(&self) -> bool { self.paused }
+
+    /// Lets `step_frame` run `frames` more frames while paused.
+    pub fn advance_frames(&mut self, frames: u32) {
+        self.queued_frames = self.queued_frames.saturating_add(frames);
     }
 
+    pub fn queued_frames(&self) -> u32 { self.queued_frames }
+
+    /// While set, frames are emulated without drawing them, for fast-forward:
+    /// the PPU composes nothing, frame sinks are not called, and the
+    /// framebuffer keeps the last drawn frame. Sound still plays, and frames
+    /// are always drawn while a capture is recording.
+    pub fn set_skip_render(&mut self, skip: bool) { self.skip_render = skip; }
+    pub fn skip_render(&self) -> bool { self.skip_render }
+
+    fn draws_frame(&self) -> bool { !self.skip_render || self.capture.is_some() }
+
+    /// Time the emulated machine has run since power-on or the last reset.
+    pub fn emulated_time(&self) -> Duration {
+        Duration::from_secs_f64(self.bus.scheduler.now() as f64 / CPU_CLOCK as f64)
+    }
+
+    /// CPU cycles idle loop skipping has jumped over since the last reset.
+    pub fn idle_cycles_skipped(&self) -> u64 { self.idle_loop.skipped_cycles() }
+
+    /// The ROM image as the game reads it, with any patch or header fix
+    /// applied.
+    pub fn rom(&self) -> &[u8] { &self.bus.mem.rom }
+    /// The machine's memories, for viewers and RAM search.
+    #[doc(hidden)]
+    pub fn mem(&self) -> &Mem { &self.bus.mem }
+
+    /// Reads memory the way the CPU does, side effects of I/O reads
+    /// included.
+    pub fn read8(&mut self, addr: u32) -> u8 { self.bus.read8(addr) }
+    pub fn read16(&mut self, addr: u32) -> u16 { self.bus.read16(addr) }
+    pub fn read32(&mut self, addr: u32) -> u32 { self.bus.read32(addr) }
+    /// Writes memory the way the CPU does, so I/O registers take effect.
+    pub fn write8(&mut self, addr: u32, value: u8) { self.bus.write8(addr, value) }
+    pub fn write16(&mut self, addr: u32, value: u16) { self.bus.write16(addr, value) }
+    pub fn write32(&mut self, addr: u32, value: u32) { self.bus.write32(addr, value) }
+    /// Register `index` (0-15) of the CPU's current mode.
+    pub fn reg(&self, index: usize) -> u32 { self.cpu.read_reg(index) }
+    pub fn cpsr(&self) -> u32 { self.cpu.cpsr().raw() }
+
+    // The components themselves, for the desktop debugger.
+    #[doc(hidden)]
+    pub fn ppu(&self) -> &Ppu { &self.ppu }
+    #[doc(hidden)]
     pub fn ppu_mut(&mut self) -> &mut Ppu { &mut self.ppu }
+    pub fn apu(&self) -> &Apu { &self.apu }
+    pub fn apu_mut(&mut self) -> &mut Apu { &mut self.apu }
+    #[doc(hidden)]
+    pub fn bus(&self) -> &Bus { &self.bus }
+    #[doc(hidden)]
     pub fn bus_mut(&mut self) -> &mut Bus { &mut self.bus }
+    #[doc(hidden)]
+    pub fn cpu(&self) -> &Cpu { &self.cpu }
+    #[doc(hidden)]
     pub fn cpu_mut(&mut self) -> &mut Cpu { &mut self.cpu }
+    pub fn frame_count(&self) -> u64 { self.frame_count }
     pub fn framebuffer_rgba(&self) -> &[u8] { &self.rgba_frame }
+    /// The last frame as the PPU drew it, before color correction.
+    pub fn framebuffer_bgr555(&self) -> Frame<'_> { Frame::bgr555(self.ppu.framebuffer()) }
+
+    /// Hands every completed frame to `sink` from `run_frame` on.
+    pub fn set_frame_sink(&mut self, sink: Box<dyn FrameSink>) { self.frame_sink = Some(sink); }
+    pub fn take_frame_sink(&mut self) -> Option<Box<dyn FrameSink>> { self.frame_sink.take() }
+
+    /// Samples are only mixed while a sink is installed.
+    pub fn set_audio_sink(&mut self, sink: Box<dyn AudioSink>) { self.audio_sink = Some(sink); }
+    pub fn take_audio_sink(&mut self) -> Option<Box<dyn AudioSink>> { self.audio_sink.take() }
+
+    /// Records from the next frame on, completing any capture in progress.
+    pub fn start_capture(&mut self, capture: Capture) -> std::io::Result<()> {
+        let previous = self.capture.replace(capture);
+        previous.map_or(Ok(()), Capture::finish)
+    }
+
+    /// Completes the capture's files. Captures given a frame count stop by
+    /// themselves.
+    pub fn stop_capture(&mut self) -> std::io::Result<()> { self.capture.take().map_or(Ok(()), Capture::finish) }
+
+    pub fn is_capturing(&self) -> bool { self.capture.is_some() }
+
+    /// Converts the current frame straight into `dst` (240 * 160 * 4 bytes),
+    /// e.g. a mapped texture, with the configured color profile.
+    pub fn fill_rgba(&self, dst: &mut [u8]) {
+        framebuffer_rgb555_to_rgba(dst, self.ppu.framebuffer(), &self.colors);
+    }
+
+    /// Stable hash of the RGB555 framebuffer, independent of color correction.
+    pub fn frame_hash(&self) -> u64 {
+        let bytes: Vec<u8> = self.ppu.framebuffer().iter().flat_map(|px| px.to_le_bytes()).collect();
+        state::hash64(&bytes)
+    }
+
+    /// Stable hash of the CPU and bus state (registers, memory, I/O, timers).
+    pub fn state_hash(&self) -> u64 {
+        let mut w = StateWriter::new();
+        w.put(&self.cpu);
+        w.put(&self.bus);
+        state::hash64(&w.finish())
+    }
+
+    /// Copy of the last completed frame.
+    pub fn screenshot(&self) -> Image {
+        Image { width: GBA_SCREEN_W, height: GBA_SCREEN_H, rgba: self.rgba_frame.clone() }
+    }
     pub fn is_frame_ready(&self) -> bool { self.frame_ready }
     pub fn is_rom_loaded(&self) -> bool { self.rom_loaded }
+    /// Which BIOS is loaded; None when BIOS calls are emulated (HLE).
+    pub fn bios_kind(&self) -> Option<BiosKind> { self.bios_kind }
 }
 
 impl Default for Emulator {
@@ -210,6 +1255,7 @@ mod tests {
     use super::*;
     use std::path::PathBuf;
     use crate::bus::BusAccess;
+    use std::sync::{Arc, Mutex};
 
     #[test]
     fn emulator_loads_rom_and_executes() {
@@ -220,10 +1266,10 @@ mod tests {
             return;
         }
 
-        emu.load_rom(&rom_path);
+        emu.load_rom(&rom_path).unwrap();
         assert!(emu.is_rom_loaded());
 
-        let rom_len = emu.bus.mem.rom.len();
+        let rom_len = emu.rom().len();
         assert!(rom_len > 0, "ROM should be loaded: len={}", rom_len);
 
         let first_instr = emu.bus.read32(0x0800_0000);
@@ -272,6 +1318,393 @@ mod tests {
         assert_eq!(bus.io.dispcnt, 0x0405, "DISPCNT should be 0x0405 after u32 write");
     }
 
+    #[test]
+    fn rom_reads_past_the_end_are_open_bus_unless_mirrored() {
+        use crate::cart::Quirks;
+        let mut bus = Bus::new();
+        let rom: Vec<u8> = (0..0x300).map(|i| i as u8).collect();
+        bus.load_rom(&rom);
+        assert_eq!(bus.read16(0x0A00_0102), 0x0302);
+        assert_eq!(bus.read16(0x0800_0400), 0x0200);
+        assert_eq!(bus.read16(0x0C12_3456), 0x1A2B);
+
+        bus.cart.set_config(CartConfig { quirks: Quirks::ROM_MIRROR, ..CartConfig::default() }, &rom);
+        // 0x300 bytes mirror every 0x400; the gap above 0x300 stays open bus.
+        assert_eq!(bus.read16(0x0800_0400), 0x0100);
+        assert_eq!(bus.read16(0x0C00_1C02), 0x0302);
+        assert_eq!(bus.read16(0x0800_0700), 0x0380);
+    }
+
+    #[test]
+    fn sram_is_an_8bit_bus() {
+        let mut bus = Bus::new();
+        bus.write32(0x0E00_0001, 0x4433_2211);
+        assert_eq!(bus.read8(0x0E00_0000), 0);
+        assert_eq!(bus.read8(0x0E00_0001), 0x22);
+        assert_eq!(bus.read8(0x0E00_0002), 0);
+        bus.write16(0x0E00_0003, 0xBBAA);
+        assert_eq!(bus.read8(0x0E00_0003), 0xBB);
+
+        assert_eq!(bus.read16(0x0E00_0001), 0x2222);
+        assert_eq!(bus.read32(0x0E00_0003), 0xBBBB_BBBB);
+    }
+
+    struct RecordingSink {
+        format: PixelFormat,
+        frames: Arc<Mutex<Vec<(PixelFormat, usize, usize)>>>,
+    }
+
+    impl FrameSink for RecordingSink {
+        fn format(&self) -> PixelFormat { self.format }
+        fn present(&mut self, frame: Frame<'_>) {
+            let len = match frame.pixels {
+                video::FramePixels::Bgr555(px) => px.len(),
+                video::FramePixels::Rgba8888(px) => px.len(),
+            };
+            self.frames.lock().unwrap().push((frame.format(), len, frame.pitch()));
+        }
+    }
+
+    #[test]
+    fn frame_sink_gets_its_format_and_skips_unneeded_conversion() {
+        let mut emu = Emulator::new();
+        emu.load_rom_bytes(&[0xFE, 0xFF, 0xFF, 0xEA]).unwrap(); // b .
+        let frames = Arc::default();
+        emu.set_frame_sink(Box::new(RecordingSink { format: PixelFormat::Bgr555, frames: Arc::clone(&frames) }));
+        emu.run_frame();
+        assert_eq!(frames.lock().unwrap()[0], (PixelFormat::Bgr555, 240 * 160, 480));
+        assert!(emu.framebuffer_rgba().iter().all(|&b| b == 0), "RGBA conversion should be skipped");
+
+        emu.set_frame_sink(Box::new(RecordingSink { format: PixelFormat::Rgba8888, frames: Arc::clone(&frames) }));
+        emu.run_frame();
+        assert_eq!(frames.lock().unwrap()[1], (PixelFormat::Rgba8888, 240 * 160 * 4, 960));
+        assert_eq!(emu.framebuffer_rgba()[3], 0xFF);
+        assert!(emu.take_frame_sink().is_some());
+    }
+
+    #[test]
+    fn skipped_frames_are_not_drawn() {
+        let mut emu = emulator_with_program(&[0xEAFF_FFFE]);
+        let frames = Arc::default();
+        emu.set_frame_sink(Box::new(RecordingSink { format: PixelFormat::Rgba8888, frames: Arc::clone(&frames) }));
+        emu.run_frame();
+        let drawn = emu.framebuffer_rgba().to_vec();
+
+        emu.bus.write16(0x0500_0000, 0x001F);
+        emu.set_skip_render(true);
+        assert!(!emu.run_frame().rendered);
+        assert_eq!(emu.framebuffer_rgba(), drawn);
+        assert_eq!(frames.lock().unwrap().len(), 1);
+
+        emu.set_skip_render(false);
+        assert!(emu.run_frame().rendered);
+        assert_ne!(emu.framebuffer_rgba(), drawn);
+        assert_eq!(frames.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn audio_sink_gets_each_frames_samples() {
+        let mut emu = emulator_with_program(&[0xEAFF_FFFE]);
+        let (sink, reader) = audio::ring(48_000, Duration::from_secs(1));
+        emu.set_audio_sink(Box::new(sink));
+        emu.run_frame();
+        // 48000 Hz at 280896 cycles per frame is 803.6 samples.
+        assert!((803..=804).contains(&reader.queued()), "{} samples", reader.queued());
+        emu.run_frame();
+        assert_eq!(reader.queued(), 1607);
+        assert!(emu.take_audio_sink().is_some());
+        emu.run_frame();
+        assert_eq!(reader.queued(), 1607);
+    }
+
+    // Shares what a capture writes with the test.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<std::io::Cursor<Vec<u8>>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { self.0.lock().unwrap().write(buf) }
+        fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+
+    impl std::io::Seek for SharedBuffer {
+        fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> { self.0.lock().unwrap().seek(pos) }
+    }
+
+    #[test]
+    fn capture_stops_after_its_frames() {
+        let mut emu = emulator_with_program(&[0xEAFF_FFFE]);
+        let (wav, y4m) = (SharedBuffer::default(), SharedBuffer::default());
+        let capture = Capture::new().audio(wav.clone(), 32_768).unwrap().video(y4m.clone()).unwrap().frames(2);
+        emu.apu_mut().set_sample_rate(32_768);
+        emu.start_capture(capture).unwrap();
+        for _ in 0..3 {
+            emu.run_frame();
+        }
+        assert!(!emu.is_capturing());
+
+        let wav = wav.0.lock().unwrap().get_ref().clone();
+        // 32768 Hz is exactly 548.625 samples per frame.
+        let data_len = u32::from_le_bytes(wav[40..44].try_into().unwrap());
+        assert_eq!(data_len, 1097 * 4);
+        assert_eq!(wav.len(), 44 + 1097 * 4);
+        let y4m = y4m.0.lock().unwrap().get_ref().clone();
+        let frame_len = b"FRAME\n".len() + 240 * 160 * 3;
+        let header_len = y4m.iter().position(|&b| b == b'\n').unwrap() + 1;
+        assert_eq!(y4m.len(), header_len + 2 * frame_len);
+    }
+
+    #[test]
+    fn skipping_the_bios_leaves_the_post_boot_state() {
+        let mut emu = Emulator::new();
+        emu.bus.mem.iwram[0x7FF0] = 0xAA;
+        emu.load_rom_bytes(&[0xFE, 0xFF, 0xFF, 0xEA]).unwrap();
+        assert_eq!(emu.bus.io.dispcnt, 0x0080);
+        assert_eq!(emu.bus.io.soundbias, 0x0200);
+        assert_eq!(emu.bus.io.postflg, 1);
+        assert_eq!(emu.bus.mem.iwram[0x7FF0], 0);
+        assert_eq!(emu.cpu.read_reg(13), 0x0300_7F00);
+    }
+
+    #[test]
+    fn hard_reset_clears_memory_and_registers() {
+        let mut emu = Emulator::new();
+        emu.load_rom_bytes(&[0xFE, 0xFF, 0xFF, 0xEA]).unwrap();
+        emu.bus.write32(0x0200_0000, 0xDEAD_BEEF);
+        emu.bus.write16(0x0400_0000, 0x0403);
+        emu.bus.write16(0x0400_0088, 0x4100);
+        emu.hard_reset();
+        assert_eq!(emu.bus.read32(0x0200_0000), 0);
+        assert_eq!(emu.bus.io.dispcnt, 0x0080);
+        assert_eq!(emu.bus.io.soundbias, 0x0200);
+    }
+
+    // 0x100 bytes of image: `b .` at the ROM entry vector and, optionally,
+    // `add r0, r0, #1; b .` behind the multiboot vector.
+    fn multiboot_image(with_vector: bool) -> Vec<u8> {
+        let mut image = vec![0u8; 0x100];
+        image[..4].copy_from_slice(&0xEAFF_FFFEu32.to_le_bytes());
+        if with_vector {
+            image[0xC0..0xC4].copy_from_slice(&0xEA00_0002u32.to_le_bytes());
+            image[0xD0..0xD4].copy_from_slice(&0xE280_0001u32.to_le_bytes());
+            image[0xD4..0xD8].copy_from_slice(&0xEAFF_FFFEu32.to_le_bytes());
+        }
+        image
+    }
+
+    #[test]
+    fn multiboot_images_start_from_ewram() {
+        let mut emu = Emulator::new();
+        emu.load_multiboot(&multiboot_image(true)).unwrap();
+        assert!(emu.is_rom_loaded());
+        assert!(emu.rom().is_empty());
+        assert_eq!(emu.cpu.pc(), 0x0200_00C0);
+        assert_eq!((emu.bus.read8(0x0200_00C4), emu.bus.read8(0x0200_00C5)), (0x03, 0x01));
+        assert_eq!(emu.bus.read8(0x0300_7FFA), 1);
+        emu.run_frame();
+        assert_eq!(emu.cpu.read_reg(0), 1);
+
+        // The image survives a power cycle, and SoftReset comes back to it.
+        emu.hard_reset();
+        assert_eq!(emu.cpu.pc(), 0x0200_00C0);
+        assert_eq!(emu.bus.read32(0x0200_00D0), 0xE280_0001);
+        emu.soft_reset();
+        assert_eq!(emu.cpu.pc(), 0x0200_0000);
+
+        emu.load_multiboot(&multiboot_image(false)).unwrap();
+        assert_eq!(emu.cpu.pc(), 0x0200_0000);
+        assert_eq!(emu.bus.read8(0x0200_00C4), 0);
+
+        emu.load_rom_bytes(&[0xFE, 0xFF, 0xFF, 0xEA]).unwrap();
+        emu.hard_reset();
+        assert_eq!(emu.cpu.pc(), 0x0800_0000);
+    }
+
+    #[test]
+    fn oversized_multiboot_images_are_rejected() {
+        let mut emu = Emulator::new();
+        assert!(matches!(emu.load_multiboot(&[]), Err(CoreError::InvalidRom(_))));
+        assert!(matches!(emu.load_multiboot(&vec![0; mem::EWRAM_SIZE + 1]), Err(CoreError::InvalidRom(_))));
+        assert!(!emu.is_rom_loaded());
+    }
+
+    #[test]
+    fn bad_headers_are_reported_or_patched_before_a_bios_boot() {
+        let rom = vec![0u8; 0x200];
+        let mut emu = Emulator::new();
+        emu.load_rom_bytes(&rom).unwrap();
+        assert_eq!(emu.header_problem(), None, "no BIOS to refuse it");
+
+        emu.bus.load_bios(&[0; 16]);
+        emu.bios_loaded = true;
+        emu.load_rom_bytes(&rom).unwrap();
+        assert_eq!(emu.header_problem(), Some("the Nintendo logo and the header checksum are wrong"));
+
+        emu.set_config(EmulatorConfig { patch_header: true, ..Default::default() });
+        emu.load_rom_bytes(&rom).unwrap();
+        assert_eq!(emu.header_problem(), None);
+        assert_eq!(emu.bus.read8(0x0800_0004), cart::NINTENDO_LOGO[0]);
+        assert_eq!(emu.rom_crc32(), cart::crc32(&rom), "the dump is identified before patching");
+    }
+
+    #[test]
+    fn load_errors_are_reported() {
+        let mut emu = Emulator::new();
+        assert!(matches!(emu.load_rom_bytes(&[]), Err(CoreError::InvalidRom(_))));
+        assert!(!emu.is_rom_loaded());
+        let missing = std::env::temp_dir().join("roba-missing-rom.gba");
+        assert!(matches!(emu.load_rom(&missing), Err(CoreError::Io(_))));
+        assert!(matches!(emu.load_bios(&missing), Err(CoreError::Io(_))));
+
+        emu.load_rom_bytes(&[0xFE, 0xFF, 0xFF, 0xEA]).unwrap();
+        assert!(matches!(emu.load_battery_save(&[0; 16]), Err(CoreError::Unsupported(_))));
+        let mut state = emu.save_state();
+        state[STATE_MAGIC.len()] ^= 0xFF;
+        let error = CoreError::from(emu.load_state(&state).unwrap_err());
+        assert!(matches!(error, CoreError::StateVersionMismatch { expected: STATE_VERSION, .. }));
+    }
+
+    #[test]
+    fn patches_next_to_the_rom_are_applied() {
+        let dir = std::env::temp_dir().join(format!("roba-patch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rom = dir.join("game.gba");
+        std::fs::write(&rom, [0xFE, 0xFF, 0xFF, 0xEA]).unwrap();
+        let mut emu = Emulator::new();
+        emu.load_rom(&rom).unwrap();
+        assert_eq!(emu.rom_patch(), None);
+
+        let patch = dir.join("game.ips");
+        std::fs::write(&patch, b"PATCH\x00\x00\x00\x00\x01\x00EOF").unwrap();
+        emu.load_rom(&rom).unwrap();
+        assert_eq!(emu.rom_patch(), Some(patch.as_path()));
+        assert_eq!(emu.bus.read32(0x0800_0000), 0xEAFF_FF00);
+        emu.load_rom_patched(&rom, None).unwrap();
+        assert_eq!(emu.bus.read32(0x0800_0000), 0xEAFF_FFFE);
+
+        std::fs::write(&patch, b"BPS1").unwrap();
+        assert!(matches!(emu.load_rom(&rom), Err(CoreError::InvalidPatch(_))));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bios_calls_are_counted_per_frame() {
+        let mut emu = Emulator::new();
+        // swi 0x06 (Div) in a loop.
+        emu.load_rom_bytes(&[0x06, 0x00, 0x00, 0xEF, 0xFD, 0xFF, 0xFF, 0xEA]).unwrap();
+        assert!(emu.cpu().swi_stats().last_frame().is_empty());
+        emu.run_frame();
+        let calls = emu.cpu().swi_stats().last_frame();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].0, 0x06);
+        emu.run_frame();
+        assert_eq!(emu.cpu().swi_stats().totals()[0].1, calls[0].1 as u64 + emu.cpu().swi_stats().last_frame()[0].1 as u64);
+    }
+
+    #[test]
+    fn soft_reset_keeps_memory_and_restarts_the_rom() {
+        let mut emu = Emulator::new();
+        emu.load_rom_bytes(&[0xFE, 0xFF, 0xFF, 0xEA]).unwrap();
+        emu.bus.write32(0x0200_0000, 0xDEAD_BEEF);
+        emu.bus.write16(0x0400_0000, 0x0403);
+        emu.bus.write32(0x0300_7F10, 0x1234_5678);
+        emu.cpu.write_reg(0, 7);
+        emu.run_frame();
+        emu.soft_reset();
+        assert_eq!(emu.bus.read32(0x0200_0000), 0xDEAD_BEEF);
+        assert_eq!(emu.bus.io.dispcnt, 0x0403);
+        assert_eq!(emu.bus.read32(0x0300_7F10), 0);
+        assert_eq!(emu.cpu.read_reg(0), 0);
+        assert_eq!(emu.cpu.read_reg(13), 0x0300_7F00);
+        assert_eq!(emu.cpu.mode(), crate::cpu::CpuMode::System);
+        assert_eq!(emu.cpu.pc(), 0x0800_0000);
+
+        emu.bus.write8(0x0300_7FFA, 1);
+        emu.soft_reset();
+        assert_eq!(emu.cpu.pc(), 0x0200_0000);
+    }
+
+    #[test]
+    fn game_boy_player_holds_all_directions_during_detection() {
+        let mut emu = Emulator::new();
+        emu.attach_game_boy_player();
+        emu.set_keys(KeyState::A);
+        assert_eq!(emu.bus.io.keyinput, 0x030E);
+        emu.detach_peripherals();
+        assert_eq!(emu.bus.io.keyinput, 0x03FE);
+    }
+
+    #[test]
+    fn keypad_irq_wakes_the_cpu_from_stop_mode() {
+        let mut emu = emulator_with_program(&[
+            0xE3A0_1301, // mov r1, #0x04000000
+            0xE281_1C03, // add r1, r1, #0x300
+            0xE3A0_2080, // mov r2, #0x80
+            0xE5C1_2001, // strb r2, [r1, #1] (HALTCNT: stop)
+            0xE280_0001, // add r0, r0, #1
+            0xEAFF_FFFE, // b .
+        ]);
+        // Keypad IRQ for A, and VBlank IRQs, which do not end stop mode.
+        emu.bus.write16(0x0400_0132, 0x4001);
+        emu.bus.write16(0x0400_0004, 0x0008);
+        emu.bus.write16(0x0400_0200, 0x1001);
+        emu.run_frame();
+        assert!(emu.bus.io.is_stopped());
+        assert_eq!(emu.bus.io.if_, 0x0001);
+
+        emu.set_keys(KeyState::B);
+        emu.run_frame();
+        assert!(emu.bus.io.is_stopped());
+        assert_eq!(emu.cpu.read_reg(0), 0);
+        emu.set_keys(KeyState::A | KeyState::B);
+        assert_eq!(emu.bus.io.if_ & 0x1000, 0x1000);
+        emu.run_frame();
+        assert_eq!(emu.cpu.read_reg(0), 1);
+    }
+
+    #[test]
+    fn paused_emulator_runs_only_queued_frames() {
+        let mut emu = Emulator::new();
+        emu.load_rom_bytes(&[0xFE, 0xFF, 0xFF, 0xEA]).unwrap(); // b .
+        assert!(emu.step_frame().is_some());
+        emu.set_paused(true);
+        assert!(emu.step_frame().is_none());
+        emu.advance_frames(2);
+        assert!(emu.step_frame().is_some());
+        assert!(emu.step_frame().is_some());
+        assert!(emu.step_frame().is_none());
+        assert_eq!(emu.frame_count(), 3);
+        // 280,896 cycles per frame.
+        assert_eq!(emu.emulated_time().as_micros(), 3 * 280_896 * 1_000_000 / CPU_CLOCK as u128);
+
+        emu.advance_frames(5);
+        emu.set_paused(false);
+        emu.set_paused(true);
+        assert!(emu.step_frame().is_none());
+    }
+
+    #[test]
+    fn battery_save_round_trips_raw_sav_files() {
+        use crate::cart::BackupType;
+        let mut bus = Bus::new();
+        let rom = b"....SRAM_V113....".to_vec();
+        bus.load_rom(&rom);
+        assert_eq!(bus.cart.backup_type(), BackupType::Sram);
+        assert!(!bus.cart.take_dirty());
+        bus.write8(0x0E00_0010, 0x5A);
+        assert!(bus.cart.take_dirty());
+        assert!(!bus.cart.take_dirty());
+
+        let mut sav = bus.cart.backup_data();
+        assert_eq!(sav.len(), 0x8000);
+        assert_eq!(sav[0x10], 0x5A);
+        sav[0x20] = 0xC3;
+        sav.extend_from_slice(&[0xFF; 16]);
+        bus.cart.load_backup(&sav);
+        assert_eq!(bus.read8(0x0E00_0020), 0xC3);
+        assert_eq!(bus.read8(0x0E00_8020), 0xC3);
+        assert!(!bus.cart.take_dirty());
+    }
+
     #[test]
     fn cpu_str_writes_to_io() {
         let mut emu = Emulator::new();
@@ -320,7 +1753,7 @@ mod tests {
             return;
         }
 
-        emu.load_rom(&rom_path);
+        emu.load_rom(&rom_path).unwrap();
         emu.run_frame();
 
         let fb = emu.ppu_mut().framebuffer();
@@ -337,7 +1770,7 @@ mod tests {
             return;
         }
 
-        emu.load_rom(&rom_path);
+        emu.load_rom(&rom_path).unwrap();
         emu.run_frame();
 
         let fb = emu.ppu_mut().framebuffer();
@@ -350,4 +1783,543 @@ mod tests {
         assert!(unique_colors.len() >= 10, "Expected at least 10 colors, got {}", unique_colors.len());
     }
 
+    fn emulator_with_program(words: &[u32]) -> Emulator {
+        let mut emu = Emulator::new();
+        emu.bus.mem.rom = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        emu.rom_loaded = true;
+        emu.cpu.set_entry_point(&mut emu.bus, 0x0800_0000);
+        emu
+    }
+
+    fn assert_same_state(a: &Emulator, b: &Emulator) {
+        for r in 0..16 {
+            assert_eq!(a.cpu.read_reg(r), b.cpu.read_reg(r), "r{} differs", r);
+        }
+        assert_eq!(a.cpu.cpsr().raw(), b.cpu.cpsr().raw());
+        assert_eq!(a.bus.io.dispstat, b.bus.io.dispstat);
+        assert_eq!(a.bus.io.if_, b.bus.io.if_);
+        assert_eq!(a.bus.mem.iwram, b.bus.mem.iwram);
+    }
+
+    #[test]
+    fn frame_report_counts_cycles_and_dma() {
+        // b .
+        let mut emu = emulator_with_program(&[0xEAFF_FFFE]);
+        // DMA0 at every VBlank, repeating.
+        emu.bus.write32(dma::DMA_BASE, 0x0200_0000);
+        emu.bus.write32(dma::DMA_BASE + 4, 0x0300_0000);
+        emu.bus.write16(dma::DMA_BASE + 8, 4);
+        emu.bus.write16(dma::DMA_BASE + 10, 0x8000 | 0x0200 | (1 << 12));
+
+        let frame = CYCLES_PER_SCANLINE * SCANLINES_PER_FRAME as u64;
+        for _ in 0..2 {
+            let report = emu.run_frame();
+            assert!((frame..frame + 16).contains(&report.cycles), "{} cycles", report.cycles);
+            assert!(report.instructions > 0);
+            assert_eq!(report.halted_cycles, 0);
+            assert_eq!(report.dma_transfers, 1);
+            assert_eq!(report.irqs, 0);
+            assert!(report.duration >= report.cpu_time + report.ppu_time);
+            assert_eq!(emu.last_frame_report(), &report);
+        }
+    }
+
+    #[test]
+    fn dma_stalls_the_cpu() {
+        // add r0, r0, #1; b -8
+        let program = [0xE280_0001, 0xEAFF_FFFD];
+        let mut free = emulator_with_program(&program);
+        let mut stalled = emulator_with_program(&program);
+        // DMA3 copies 4K words at VBlank.
+        stalled.bus.write32(dma::DMA_BASE + 36, 0x0200_0000);
+        stalled.bus.write32(dma::DMA_BASE + 40, 0x0202_0000);
+        stalled.bus.write16(dma::DMA_BASE + 44, 0x1000);
+        stalled.bus.write16(dma::DMA_BASE + 46, 0x8000 | 0x0400 | (1 << 12));
+
+        let free = free.run_frame();
+        let stalled = stalled.run_frame();
+        assert_eq!(stalled.dma_transfers, 1);
+        // Each word is a 6-cycle EWRAM read plus a 6-cycle EWRAM write, time
+        // the loop no longer gets.
+        let lost = (free.instructions - stalled.instructions) * free.cycles / free.instructions;
+        assert!(lost >= 0x1000 * 12 - 64, "{} cycles lost", lost);
+    }
+
+    // A raster effect the way games do it: HBlank DMA feeds BG2X a new value
+    // after every line, shifting each line of an affine background one tile
+    // further than the one above.
+    #[test]
+    fn hblank_dma_scrolls_affine_background_per_line() {
+        let mut emu = emulator_with_program(&[0xEAFF_FFFE]);
+        emu.set_config(EmulatorConfig { accuracy: Accuracy::Accurate, ..Default::default() });
+        // Mode 2, BG2 on: 128x128 wrapping map at screen block 1.
+        emu.bus.write16(0x0400_0000, 0x0402);
+        emu.bus.write16(0x0400_000C, (1 << 13) | (1 << 8));
+        emu.bus.write16(0x0400_0020, 0x100);
+        emu.bus.write16(0x0400_0026, 0x100);
+        for t in 0..16u32 {
+            emu.bus.write16(0x0500_0002 + t * 2, 0x421 * (t as u16 + 1));
+            for i in 0..32 {
+                emu.bus.write16(0x0600_0000 + t * 64 + i * 2, (t as u16 + 1) * 0x0101);
+            }
+        }
+        // Map column c shows tile c.
+        for i in 0..128u32 {
+            let c = (i * 2) % 16;
+            emu.bus.write16(0x0600_0800 + i * 2, c as u16 | ((c as u16 + 1) << 8));
+        }
+        // Entry n is BG2X for line n + 1, written at the end of line n.
+        for n in 0..160u32 {
+            emu.bus.write32(0x0200_0000 + n * 4, ((n + 1) * 8) << 8);
+        }
+        emu.bus.write32(dma::DMA_BASE, 0x0200_0000);
+        emu.bus.write32(dma::DMA_BASE + 4, 0x0400_0028);
+        emu.bus.write16(dma::DMA_BASE + 8, 1);
+        emu.bus.write16(dma::DMA_BASE + 10, 0x8000 | 0x0400 | 0x0200 | (2 << 12) | (2 << 5));
+
+        let report = emu.run_frame();
+        assert_eq!(report.dma_transfers, 160);
+        let frame = emu.ppu.framebuffer();
+        for y in 0..160 {
+            let expected = 0x421 * ((y % 16) as u16 + 1);
+            assert_eq!(frame[y * 240], expected, "line {}", y);
+            assert_eq!(frame[y * 240 + 8], 0x421 * (((y + 1) % 16) as u16 + 1), "line {}", y);
+        }
+    }
+
+    #[test]
+    fn video_capture_runs_on_lines_2_to_161() {
+        let mut emu = emulator_with_program(&[0xEAFF_FFFE]);
+        // DMA3, special timing, repeat, destination fixed.
+        emu.bus.write32(dma::DMA_BASE + 36, 0x0200_0000);
+        emu.bus.write32(dma::DMA_BASE + 40, 0x0300_0000);
+        emu.bus.write16(dma::DMA_BASE + 44, 1);
+        emu.bus.write16(dma::DMA_BASE + 46, 0x8000 | 0x0200 | (3 << 12) | (2 << 5));
+
+        assert_eq!(emu.run_frame().dma_transfers, 160);
+        assert!(!emu.bus.dma.channel(3).enabled());
+        assert_eq!(emu.run_frame().dma_transfers, 0);
+    }
+
+    #[test]
+    fn hashes_are_deterministic() {
+        // add r0, r0, #1; b -8
+        let program = [0xE280_0001, 0xEAFF_FFFD];
+        let mut a = emulator_with_program(&program);
+        let mut b = emulator_with_program(&program);
+        assert_eq!(a.state_hash(), b.state_hash());
+        a.run_frame();
+        assert_ne!(a.state_hash(), b.state_hash());
+        b.run_frame();
+        assert_eq!(a.state_hash(), b.state_hash());
+        assert_eq!(a.frame_hash(), b.frame_hash());
+
+        a.bus.mem.ewram[0] = 1;
+        assert_ne!(a.state_hash(), b.state_hash());
+        assert_eq!(a.frame_hash(), b.frame_hash());
+    }
+
+    #[test]
+    fn load_state_replays_identically() {
+        // add r0, r0, #1; b -8
+        let mut emu = emulator_with_program(&[0xE280_0001, 0xEAFF_FFFD]);
+        emu.run_frame();
+        let state = emu.save_state();
+        emu.run_frame();
+        emu.run_frame();
+        let (r0, now) = (emu.cpu.read_reg(0), emu.bus.scheduler.now());
+
+        emu.load_state(&state).unwrap();
+        assert_eq!(emu.frame_count, 1);
+        emu.run_frame();
+        emu.run_frame();
+        assert_eq!((emu.cpu.read_reg(0), emu.bus.scheduler.now()), (r0, now));
+    }
+
+    #[test]
+    fn state_size_does_not_change_while_running() {
+        // add r0, r0, #1; b -8
+        let mut emu = emulator_with_program(&[0xE280_0001, 0xEAFF_FFFD]);
+        let size = emu.save_state().len();
+        for _ in 0..100 {
+            emu.run_cycles(977);
+            assert_eq!(emu.save_state().len(), size);
+        }
+    }
+
+    #[test]
+    fn bad_states_are_rejected_without_side_effects() {
+        let mut emu = emulator_with_program(&[0xE280_0001, 0xEAFF_FFFD]);
+        emu.run_frame();
+        let state = emu.save_state();
+        emu.run_frame();
+        let before = emu.save_state();
+
+        assert_eq!(emu.load_state(b"nope"), Err(StateError::BadMagic));
+        let mut other_rom = state.clone();
+        other_rom[6] ^= 1;
+        assert!(matches!(emu.load_state(&other_rom), Err(StateError::RomMismatch { .. })));
+        assert_eq!(emu.load_state(&state[..state.len() - 1]), Err(StateError::Truncated));
+        assert_eq!(emu.save_state(), before);
+    }
+
+    // Sums KEYINPUT into r2 in a loop.
+    const KEY_SUM_PROGRAM: [u32; 5] = [
+        0xE3A0_1301, // mov r1, #0x04000000
+        0xE281_1E13, // add r1, r1, #0x130
+        0xE1D1_00B0, // ldrh r0, [r1]
+        0xE082_2000, // add r2, r2, r0
+        0xEAFF_FFFC, // b ldrh
+    ];
+
+    #[test]
+    fn movie_replays_recorded_input() {
+        let mut emu = emulator_with_program(&KEY_SUM_PROGRAM);
+        emu.record_movie(true);
+        for keys in [KeyState::A, KeyState::NONE, KeyState::UP | KeyState::B, KeyState::START] {
+            emu.set_keys(keys);
+            emu.run_frame();
+        }
+        let recorded = emu.cpu.read_reg(2);
+        let movie = emu.stop_movie().unwrap();
+        assert_eq!(movie.frames.len(), 4);
+
+        let mut replay = emulator_with_program(&KEY_SUM_PROGRAM);
+        replay.play_movie(movie).unwrap();
+        replay.set_keys(KeyState::L);
+        for _ in 0..4 {
+            replay.run_frame();
+        }
+        assert_eq!(replay.movie_status(), MovieStatus::Playing { frame: 4, frames: 4 });
+        assert_eq!(replay.cpu.read_reg(2), recorded);
+        replay.run_frame();
+        assert_eq!(replay.movie_status(), MovieStatus::Idle);
+    }
+
+    #[test]
+    fn loading_a_state_while_recording_truncates_the_movie() {
+        let mut emu = emulator_with_program(&KEY_SUM_PROGRAM);
+        emu.record_movie(true);
+        emu.run_frame();
+        let state = emu.save_state();
+        emu.run_frame();
+        emu.run_frame();
+        emu.load_state(&state).unwrap();
+        emu.run_frame();
+        assert_eq!(emu.movie_status(), MovieStatus::Recording { frames: 2 });
+    }
+
+    #[test]
+    fn frame_spans_fixed_cycle_count() {
+        let frame = CYCLES_PER_SCANLINE * SCANLINES_PER_FRAME as u64;
+        let mut emu = emulator_with_program(&[0xEAFF_FFFE]);
+        emu.run_frame();
+        // The last instruction may overrun the frame boundary by its cost.
+        assert!((frame..frame + 32).contains(&emu.bus.scheduler.now()));
+        assert_eq!(emu.bus.io.vcount, 0);
+        emu.run_frame();
+        assert!((2 * frame..2 * frame + 32).contains(&emu.bus.scheduler.now()));
+    }
+
+    #[test]
+    fn coverage_survives_reset() {
+        use crate::cpu::{ArmFormat, Coverage};
+        let mut emu = emulator_with_program(&[0xE280_0001, 0xEAFF_FFFE]);
+        emu.cpu_mut().set_coverage(Coverage::new());
+        for _ in 0..5 {
+            emu.step_cpu();
+        }
+        emu.hard_reset();
+        let coverage = emu.cpu_mut().take_coverage().unwrap();
+        assert_eq!(coverage.arm(ArmFormat::DataProcessing).count, 1);
+        assert_eq!(coverage.arm(ArmFormat::Branch).count, 4);
+        assert_eq!(coverage.arm(ArmFormat::Branch).examples, vec![(0x0800_0004, 0xEAFF_FFFE)]);
+        assert!(emu.cpu().coverage().is_none());
+    }
+
+    #[test]
+    fn trace_survives_reset() {
+        use crate::cpu::Trace;
+        let mut emu = emulator_with_program(&[0xE280_0001, 0xEAFF_FFFE]);
+        emu.cpu_mut().set_trace(Trace::new(2));
+        for _ in 0..3 {
+            emu.step_cpu();
+        }
+        emu.hard_reset();
+        let pcs: Vec<u32> = emu.cpu().trace().unwrap().entries().map(|e| e.pc).collect();
+        assert_eq!(pcs, vec![0x0800_0004, 0x0800_0004]);
+    }
+
+    // An emulator halted with no interrupts enabled, so the scheduler alone
+    // moves time forward, starting at cycle 0 of line 0.
+    fn halted_emulator(accuracy: Accuracy) -> Emulator {
+        let mut emu = Emulator::new();
+        emu.set_config(EmulatorConfig { accuracy, ..Default::default() });
+        emu.reset_timing();
+        emu.bus.write8(0x0400_0301, 0);
+        emu
+    }
+
+    fn dispstat_flags(emu: &Emulator) -> u16 { emu.bus.io.dispstat & 7 }
+
+    #[test]
+    fn scanline_timing_follows_hardware_when_accurate() {
+        let mut emu = halted_emulator(Accuracy::Accurate);
+        // LYC = 5, HBlank and VCount IRQs enabled.
+        emu.bus.write16(0x0400_0004, 0x0530);
+        assert_eq!(emu.scanline_position(), (0, 0));
+
+        emu.run_cycles(1005);
+        assert_eq!(emu.scanline_position(), (0, 1005));
+        assert_eq!((dispstat_flags(&emu), emu.bus.io.if_), (0, 0));
+        emu.run_cycles(1);
+        assert_eq!(emu.scanline_position(), (0, 1006));
+        assert_eq!((dispstat_flags(&emu), emu.bus.io.if_), (DISPSTAT_HBLANK, 0x0002));
+        emu.run_cycles(CYCLES_PER_SCANLINE - 1006);
+        assert_eq!(emu.scanline_position(), (1, 0));
+        assert_eq!(dispstat_flags(&emu), 0);
+
+        // The VCount match is evaluated as the line starts.
+        emu.bus.io.if_ = 0;
+        emu.run_cycles(4 * CYCLES_PER_SCANLINE - 1);
+        assert_eq!(emu.scanline_position(), (4, CYCLES_PER_SCANLINE - 1));
+        assert_eq!(emu.bus.io.if_ & 0x0004, 0);
+        emu.run_cycles(1);
+        assert_eq!(emu.scanline_position(), (5, 0));
+        assert_eq!((dispstat_flags(&emu), emu.bus.io.if_ & 0x0004), (DISPSTAT_VCOUNT, 0x0004));
+
+        emu.run_cycles(221 * CYCLES_PER_SCANLINE);
+        assert_eq!(emu.scanline_position(), (226, 0));
+        assert_eq!(dispstat_flags(&emu), DISPSTAT_VBLANK);
+        // The VBlank flag clears on the last line, VCOUNT wraps after it.
+        emu.run_cycles(CYCLES_PER_SCANLINE);
+        assert_eq!(emu.scanline_position(), (227, 0));
+        assert_eq!(dispstat_flags(&emu), 0);
+        emu.run_cycles(CYCLES_PER_SCANLINE - 1);
+        assert_eq!(emu.scanline_position(), (227, CYCLES_PER_SCANLINE - 1));
+        emu.run_cycles(1);
+        assert_eq!(emu.scanline_position(), (0, 0));
+        assert_eq!(emu.bus.scheduler.now(), CYCLES_PER_SCANLINE * SCANLINES_PER_FRAME as u64);
+    }
+
+    #[test]
+    fn runs_stop_at_raster_positions() {
+        let mut emu = halted_emulator(Accuracy::Accurate);
+        assert_eq!(emu.run_cycles(100), 100);
+        assert_eq!(emu.run_to_vblank(), VISIBLE_SCANLINES as u64 * CYCLES_PER_SCANLINE - 100);
+        assert_eq!(emu.scanline_position(), (VISIBLE_SCANLINES, 0));
+        assert_eq!(dispstat_flags(&emu), DISPSTAT_VBLANK);
+
+        // Wraps into the next frame, and a whole frame from the line itself.
+        assert_eq!(emu.run_to_scanline(5), (SCANLINES_PER_FRAME - VISIBLE_SCANLINES + 5) as u64 * CYCLES_PER_SCANLINE);
+        assert_eq!(emu.scanline_position(), (5, 0));
+        assert_eq!(emu.run_to_scanline(5), SCANLINES_PER_FRAME as u64 * CYCLES_PER_SCANLINE);
+        assert_eq!(emu.scanline_position(), (5, 0));
+        assert_eq!(emu.frame_count(), 0);
+
+        // A running CPU stops after the instruction that crosses the mark.
+        let mut emu = emulator_with_program(&[0xEAFF_FFFE]);
+        emu.reset_timing();
+        let ran = emu.run_cycles(1);
+        assert!(ran > 1 && ran == emu.bus.scheduler.now(), "ran {} cycles", ran);
+        let before = emu.bus.scheduler.now();
+        let ran = emu.run_to_scanline(1);
+        assert_eq!(emu.bus.scheduler.now(), before + ran);
+        let (line, cycle) = emu.scanline_position();
+        assert_eq!(line, 1);
+        assert!(cycle < 8, "stopped {} cycles into the line", cycle);
+    }
+
+    #[test]
+    fn fast_scanline_timing_keeps_the_approximations() {
+        let mut emu = halted_emulator(Accuracy::Fast);
+        emu.run_cycles(959);
+        assert_eq!(dispstat_flags(&emu), 0);
+        emu.run_cycles(1);
+        assert_eq!(emu.scanline_position(), (0, 960));
+        assert_eq!(dispstat_flags(&emu), DISPSTAT_HBLANK);
+        emu.run_cycles(227 * CYCLES_PER_SCANLINE - 960);
+        assert_eq!(emu.scanline_position(), (227, 0));
+        assert_eq!(dispstat_flags(&emu), DISPSTAT_VBLANK);
+    }
+
+    #[test]
+    fn cpu_writes_do_not_touch_dispstat_flags() {
+        let mut emu = emulator_with_program(&[0xEAFF_FFFE]);
+        emu.run_frame();
+        // Line 0 of the next frame: LYC is 0, so only the VCount flag is set.
+        emu.bus.write16(0x0400_0004, 0x0003);
+        assert_eq!(emu.bus.io.dispstat, 0x0004);
+        emu.bus.write16(0x0400_0004, 0xA038);
+        assert_eq!(emu.bus.io.dispstat, 0xA03C);
+        emu.bus.write16(0x0400_0006, 0x00A0);
+        assert_eq!(emu.bus.io.vcount, 0);
+    }
+
+    // Runs `add r0, r0, #1` from IWRAM, one cycle each, until the CPU takes
+    // timer 0's overflow IRQ. Returns the adds run before the overflow and
+    // before the exception.
+    fn adds_until_timer_irq(ime: bool) -> (u32, u32) {
+        let mut emu = Emulator::new();
+        emu.cpu.set_swi_hle(false);
+        for i in 0..64 {
+            emu.bus.write32(0x0300_0000 + i * 4, 0xE280_0001);
+        }
+        emu.cpu.set_entry_point(&mut emu.bus, 0x0300_0000);
+        emu.bus.io.ie = 0x0008;
+        emu.bus.io.ime = ime as u16;
+        emu.bus.write16(0x0400_0100, 0xFFF8);
+        emu.bus.write16(0x0400_0102, 0x00C0);
+        let mut at_overflow = None;
+        while emu.cpu.mode() != crate::cpu::CpuMode::Irq && emu.cpu.read_reg(0) < 48 {
+            emu.run_step(u64::MAX);
+            while let Some((kind, time)) = emu.bus.scheduler.pop_due() {
+                emu.handle_event(kind, time);
+            }
+            if emu.bus.io.if_ & 0x0008 != 0 && at_overflow.is_none() {
+                at_overflow = Some(emu.cpu.read_reg(0));
+            }
+        }
+        (at_overflow.unwrap(), emu.cpu.read_reg(0))
+    }
+
+    #[test]
+    fn timer_irq_is_taken_after_the_sync_delay() {
+        let (overflow, entry) = adds_until_timer_irq(true);
+        assert_eq!(entry - overflow, crate::io::IRQ_SYNC_DELAY as u32);
+    }
+
+    #[test]
+    fn timer_irq_is_not_taken_without_ime() {
+        let (_, entry) = adds_until_timer_irq(false);
+        assert_eq!(entry, 48);
+    }
+
+    #[test]
+    fn siocnt_start_raises_serial_irq() {
+        let mut emu = emulator_with_program(&[0xEAFF_FFFE]);
+        emu.bus.write16(0x0400_0128, 0x4083);
+        assert!(emu.bus.sio.is_busy());
+        emu.run_frame();
+        assert!(!emu.bus.sio.is_busy());
+        assert_eq!(emu.bus.io.if_ & 0x0080, 0x0080);
+        assert_eq!(emu.bus.io.siocnt & 0x80, 0);
+    }
+
+    #[test]
+    fn screenshot_copies_last_frame() {
+        let mut emu = emulator_with_program(&[0xEAFF_FFFE]);
+        emu.run_frame();
+        let shot = emu.screenshot();
+        assert_eq!((shot.width, shot.height), (GBA_SCREEN_W, GBA_SCREEN_H));
+        assert_eq!(shot.rgba, emu.framebuffer_rgba());
+    }
+
+    #[test]
+    fn cartridge_rtc_reads_the_configured_clock() {
+        let mut emu = emulator_with_program(&[0xEAFF_FFFE]);
+        emu.config.rtc_clock = RtcClock::Emulated(1_078_062_307);
+        emu.set_cart_config(CartConfig { rtc: true, ..CartConfig::default() });
+        emu.run_cycles(3 * CPU_CLOCK);
+        emu.run_cycles(1);
+
+        // Command 0xE6 (read the time), LSB first over SCK/SIO/CS.
+        let pins = |emu: &mut Emulator, value: u16| emu.bus.write16(0x0800_00C4, value);
+        emu.bus.write16(0x0800_00C8, 1);
+        emu.bus.write16(0x0800_00C6, 0x7);
+        pins(&mut emu, 0x1);
+        pins(&mut emu, 0x5);
+        for i in 0..8 {
+            pins(&mut emu, 0x4 | (0xE6 >> i & 1) << 1);
+            pins(&mut emu, 0x5);
+        }
+        emu.bus.write16(0x0800_00C6, 0x5);
+        let mut time = [0u8; 3];
+        for byte in &mut time {
+            for i in 0..8 {
+                pins(&mut emu, 0x4);
+                pins(&mut emu, 0x5);
+                *byte |= ((emu.bus.read16(0x0800_00C4) >> 1 & 1) as u8) << i;
+            }
+        }
+        assert_eq!(time, [0x13, 0x45, 0x10]);
+    }
+
+    #[test]
+    fn gpio_rumble_reaches_callback() {
+        use crate::cart::Quirks;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let mut emu = emulator_with_program(&[0xEAFF_FFFE]);
+        emu.set_cart_config(CartConfig { quirks: Quirks::RUMBLE, ..CartConfig::default() });
+        let seen = Arc::new(AtomicBool::new(false));
+        let flag = seen.clone();
+        emu.set_rumble_callback(move |on| flag.store(on, Ordering::Relaxed));
+
+        emu.bus.write16(0x0800_00C6, 0x8);
+        emu.bus.write16(0x0800_00C4, 0x8);
+        emu.run_frame();
+        assert!(emu.rumble_active());
+        assert!(seen.load(Ordering::Relaxed));
+
+        emu.bus.write16(0x0800_00C4, 0x0);
+        emu.run_frame();
+        assert!(!seen.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn idle_skip_branch_to_self_matches_full_emulation() {
+        let program = [0xE3A0_0005, 0xEAFF_FFFE]; // mov r0, #5; b .
+        let mut plain = emulator_with_program(&program);
+        let mut fast = emulator_with_program(&program);
+        fast.set_idle_loop_skip(true);
+
+        for _ in 0..2 {
+            plain.run_frame();
+            fast.run_frame();
+            assert_same_state(&plain, &fast);
+        }
+        assert_eq!(fast.cpu.read_reg(0), 5);
+        assert!(fast.idle_loop.skipped_cycles() > 0);
+    }
+
+    #[test]
+    fn idle_skip_vcount_poll_matches_full_emulation() {
+        let program = [
+            0xE3A0_0301, // mov r0, #0x04000000
+            0xE1D0_10B6, // ldrh r1, [r0, #6]
+            0xE351_0064, // cmp r1, #100
+            0x1AFF_FFFC, // bne -> ldrh
+            0xE282_2001, // add r2, r2, #1
+            0xE1D0_10B6, // ldrh r1, [r0, #6]
+            0xE351_0064, // cmp r1, #100
+            0x0AFF_FFFC, // beq -> ldrh
+            0xEAFF_FFF7, // b -> first ldrh
+        ];
+        let mut plain = emulator_with_program(&program);
+        let mut fast = emulator_with_program(&program);
+        fast.set_idle_loop_skip(true);
+
+        for frame in 1..=3 {
+            plain.run_frame();
+            fast.run_frame();
+            assert_same_state(&plain, &fast);
+            assert_eq!(fast.cpu.read_reg(2), frame);
+        }
+        assert!(fast.idle_loop.skipped_cycles() > 0);
+    }
+
+    #[test]
+    fn idle_skip_ignores_loops_that_store() {
+        let program = [
+            0xE3A0_0403, // mov r0, #0x03000000
+            0xE580_1000, // str r1, [r0]
+            0xEAFF_FFFD, // b -> str
+        ];
+        let mut emu = emulator_with_program(&program);
+        emu.set_idle_loop_skip(true);
+        emu.run_frame();
+        assert_eq!(emu.idle_loop.skipped_cycles(), 0);
+    }
 }
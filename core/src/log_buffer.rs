@@ -1,11 +1,47 @@
+// `log` backend for frontends with a log viewer. Records go through a bounded
+// channel to a writer thread, which keeps the most recent ones in memory for
+// `drain_logs` and can mirror them to a size-capped log file. A full channel
+// drops records instead of blocking, so heavy tracing never stalls the thread
+// doing the logging. Levels can be set per target (`core::cpu=trace`).
+
 use std::collections::VecDeque;
-use std::sync::{Mutex, OnceLock};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+use log::LevelFilter;
+
+/// Records in flight between the logging threads and the writer thread.
+const QUEUE_LEN: usize = 4096;
 
 #[derive(Clone, Debug)]
 pub struct LogEntry {
     pub level: log::Level,
     pub target: String,
     pub message: String,
+    /// Host time since the logger was installed.
+    pub time: Duration,
+    /// Emulator frame the record was made in; see `set_frame`.
+    pub frame: u64,
+}
+
+impl LogEntry {
+    /// One line of the log file: `12.345 #678 INFO core::bus: message`.
+    pub fn format_line(&self) -> String {
+        format!(
+            "{}.{:03} #{} {} {}: {}",
+            self.time.as_secs(),
+            self.time.subsec_millis(),
+            self.frame,
+            self.level,
+            self.target,
+            self.message
+        )
+    }
 }
 
 pub struct LogBuffer {
@@ -41,41 +77,230 @@ impl LogBuffer {
     }
 }
 
+/// Levels per target, matched on `::`-separated prefixes: `core::cpu` covers
+/// `core::cpu` and `core::cpu::arm` but not `core::cpux`. The longest match
+/// wins; targets matching none get the default level.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TargetFilter {
+    default: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl TargetFilter {
+    pub fn new(default: LevelFilter) -> Self { Self { default, targets: Vec::new() } }
+
+    pub fn with_target(mut self, target: &str, level: LevelFilter) -> Self {
+        self.targets.retain(|(t, _)| t != target);
+        self.targets.push((target.to_string(), level));
+        self
+    }
+
+    /// Parses `env_logger`-style specs such as `info,core::cpu=trace`: a bare
+    /// level sets the default, `target=level` one target.
+    pub fn parse(spec: &str) -> Option<Self> {
+        let mut filter = Self::new(LevelFilter::Info);
+        for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            match part.split_once('=') {
+                Some((target, level)) => filter = filter.with_target(target.trim(), level.trim().parse().ok()?),
+                None => filter.default = part.parse().ok()?,
+            }
+        }
+        Some(filter)
+    }
+
+    pub fn default_level(&self) -> LevelFilter { self.default }
+    pub fn targets(&self) -> &[(String, LevelFilter)] { &self.targets }
+
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .filter(|(prefix, _)| {
+                target.strip_prefix(prefix.as_str()).is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |&(_, level)| level)
+    }
+
+    /// The most verbose level any target gets.
+    pub fn max_level(&self) -> LevelFilter {
+        self.targets.iter().map(|&(_, level)| level).fold(self.default, Ord::max)
+    }
+}
+
+impl std::fmt::Display for TargetFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.default.as_str().to_ascii_lowercase())?;
+        for (target, level) in &self.targets {
+            write!(f, ",{}={}", target, level.as_str().to_ascii_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
+/// A log file that moves itself to `<path>.1` once it grows past
+/// `max_bytes`, replacing the previous one.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: BufWriter<File>,
+    written: u64,
+    max_bytes: u64,
+}
+
+impl RotatingFile {
+    /// Appends to `path`.
+    pub fn open(path: &Path, max_bytes: u64) -> io::Result<Self> {
+        let file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+        Ok(Self { path: path.to_path_buf(), file: BufWriter::new(file), written, max_bytes })
+    }
+
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.written > 0 && self.written + line.len() as u64 + 1 > self.max_bytes {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.written += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> { self.file.flush() }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let mut old = self.path.clone().into_os_string();
+        old.push(".1");
+        fs::rename(&self.path, old)?;
+        self.file = BufWriter::new(File::create(&self.path)?);
+        self.written = 0;
+        Ok(())
+    }
+}
+
+enum Message {
+    Entry(LogEntry),
+    File(Option<RotatingFile>),
+    Flush(SyncSender<()>),
+}
+
+struct Logger {
+    filter: RwLock<TargetFilter>,
+    queue: SyncSender<Message>,
+    start: Instant,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
 static LOG_BUFFER: OnceLock<Mutex<LogBuffer>> = OnceLock::new();
+static FRAME: AtomicU64 = AtomicU64::new(0);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
 
 pub fn global_buffer() -> &'static Mutex<LogBuffer> {
     LOG_BUFFER.get_or_init(|| Mutex::new(LogBuffer::new(1024)))
 }
 
+fn logger() -> &'static Logger {
+    LOGGER.get_or_init(|| {
+        let (queue, rx) = mpsc::sync_channel(QUEUE_LEN);
+        std::thread::Builder::new()
+            .name("log writer".into())
+            .spawn(move || write_entries(rx))
+            .expect("failed to start the log writer thread");
+        Logger { filter: RwLock::new(TargetFilter::new(LevelFilter::Info)), queue, start: Instant::now() }
+    })
+}
+
+fn write_entries(rx: Receiver<Message>) {
+    let mut file: Option<RotatingFile> = None;
+    for message in rx {
+        match message {
+            Message::Entry(entry) => {
+                if let Some(f) = &mut file
+                    && let Err(e) = f.write_line(&entry.format_line())
+                {
+                    eprintln!("Log file write failed: {}", e);
+                    file = None;
+                }
+                if let Ok(mut buf) = global_buffer().lock() {
+                    buf.push(entry);
+                }
+            }
+            Message::File(new) => {
+                if let Some(f) = &mut file {
+                    let _ = f.flush();
+                }
+                file = new;
+            }
+            Message::Flush(done) => {
+                if let Some(f) = &mut file {
+                    let _ = f.flush();
+                }
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
 pub struct BufferLogger;
 
 impl log::Log for BufferLogger {
     fn enabled(&self, metadata: &log::Metadata) -> bool {
-        metadata.level() <= log::Level::Trace
+        let filter = logger().filter.read().unwrap_or_else(|e| e.into_inner());
+        metadata.level() <= filter.level_for(metadata.target())
     }
 
     fn log(&self, record: &log::Record) {
-        if self.enabled(record.metadata()) {
-            let entry = LogEntry {
-                level: record.level(),
-                target: record.target().to_string(),
-                message: format!("{}", record.args()),
-            };
-            if let Ok(mut buf) = global_buffer().lock() {
-                buf.push(entry);
-            }
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let logger = logger();
+        let entry = LogEntry {
+            level: record.level(),
+            target: record.target().to_string(),
+            message: format!("{}", record.args()),
+            time: logger.start.elapsed(),
+            frame: FRAME.load(Ordering::Relaxed),
+        };
+        if let Err(TrySendError::Full(_)) = logger.queue.try_send(Message::Entry(entry)) {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
         }
     }
 
-    fn flush(&self) {}
+    /// Waits until the writer thread has handled every queued record.
+    fn flush(&self) {
+        let (done, wait) = mpsc::sync_channel(1);
+        if logger().queue.send(Message::Flush(done)).is_ok() {
+            let _ = wait.recv();
+        }
+    }
+}
+
+static BUFFER_LOGGER: BufferLogger = BufferLogger;
+
+pub fn init_logger(level: LevelFilter) -> Result<(), log::SetLoggerError> {
+    log::set_logger(&BUFFER_LOGGER).map(|()| set_filter(TargetFilter::new(level)))
+}
+
+/// Replaces the level filter and raises `log`'s global maximum to match.
+pub fn set_filter(filter: TargetFilter) {
+    log::set_max_level(filter.max_level());
+    *logger().filter.write().unwrap_or_else(|e| e.into_inner()) = filter;
 }
 
-static LOGGER: BufferLogger = BufferLogger;
+pub fn filter() -> TargetFilter { logger().filter.read().unwrap_or_else(|e| e.into_inner()).clone() }
 
-pub fn init_logger(level: log::LevelFilter) -> Result<(), log::SetLoggerError> {
-    log::set_logger(&LOGGER).map(|()| log::set_max_level(level))
+/// Mirrors every record to `path` from now on, rotating at `max_bytes`;
+/// `None` stops mirroring.
+pub fn set_log_file(path: Option<&Path>, max_bytes: u64) -> io::Result<()> {
+    let file = path.map(|p| RotatingFile::open(p, max_bytes)).transpose()?;
+    logger().queue.send(Message::File(file)).map_err(|_| io::Error::other("log writer stopped"))
 }
 
+/// Tags later records with `frame`. Called by the emulator at the start of
+/// each frame.
+pub fn set_frame(frame: u64) { FRAME.store(frame, Ordering::Relaxed); }
+
+/// Records dropped on a full queue since the last call.
+pub fn take_dropped() -> u64 { DROPPED.swap(0, Ordering::Relaxed) }
+
 pub fn drain_logs() -> Vec<LogEntry> {
     global_buffer()
         .lock()
@@ -89,3 +314,47 @@ pub fn clear_logs() {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn longest_target_prefix_wins() {
+        let filter = TargetFilter::parse("warn, core::cpu=trace, core::cpu::arm=off, core=info").unwrap();
+        assert_eq!(filter.level_for("core::cpu"), LevelFilter::Trace);
+        assert_eq!(filter.level_for("core::cpu::thumb"), LevelFilter::Trace);
+        assert_eq!(filter.level_for("core::cpu::arm"), LevelFilter::Off);
+        assert_eq!(filter.level_for("core::cpux"), LevelFilter::Info);
+        assert_eq!(filter.level_for("guest"), LevelFilter::Warn);
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+        assert_eq!(TargetFilter::parse(&filter.to_string()), Some(filter));
+        assert_eq!(TargetFilter::parse("core=loud"), None);
+    }
+
+    #[test]
+    fn log_file_rotates_past_its_size() {
+        let dir = std::env::temp_dir().join(format!("roba-log-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("roba.log");
+        let _ = fs::remove_file(&path);
+        let mut file = RotatingFile::open(&path, 16).unwrap();
+        file.write_line("first line").unwrap();
+        file.write_line("second line").unwrap();
+        file.flush().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second line\n");
+        assert_eq!(fs::read_to_string(dir.join("roba.log.1")).unwrap(), "first line\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn entries_format_with_time_and_frame() {
+        let entry = LogEntry {
+            level: log::Level::Warn,
+            target: "core::bus".into(),
+            message: "open bus".into(),
+            time: Duration::from_millis(12_345),
+            frame: 678,
+        };
+        assert_eq!(entry.format_line(), "12.345 #678 WARN core::bus: open bus");
+    }
+}
@@ -1,11 +1,39 @@
+use std::ops::Range;
+
+use crate::state::{Savestate, StateError, StateReader, StateWriter};
+
 pub const BIOS_SIZE: usize = 16 * 1024;
 pub const EWRAM_SIZE: usize = 256 * 1024;
 pub const IWRAM_SIZE: usize = 32 * 1024;
 pub const VRAM_SIZE: usize = 96 * 1024;
-pub const PALETTE_SIZE: usize = 512;
+/// BG colors in the first half, OBJ colors in the second.
+pub const PALETTE_SIZE: usize = 1024;
 pub const OAM_SIZE: usize = 1024;
+/// 128 entries of four halfwords: attributes 0-2 and an affine parameter.
+pub const OAM_ENTRIES: usize = OAM_SIZE / 8;
 pub const ROM_MAX_SIZE: usize = 32 * 1024 * 1024;
 
+/// Granularity of the write tracking on EWRAM, IWRAM and VRAM.
+pub const PAGE_SIZE: usize = 1024;
+/// Tracked pages, numbered through EWRAM, then IWRAM, then VRAM.
+pub const TRACKED_PAGES: usize = (EWRAM_SIZE + IWRAM_SIZE + VRAM_SIZE) / PAGE_SIZE;
+const IWRAM_PAGE: usize = EWRAM_SIZE / PAGE_SIZE;
+const VRAM_PAGE: usize = IWRAM_PAGE + IWRAM_SIZE / PAGE_SIZE;
+
+/// Bitmap of the tracked pages written since it was last taken.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DirtyPages([u64; TRACKED_PAGES / 64]);
+
+impl DirtyPages {
+    pub fn mark(&mut self, page: usize) { self.0[page / 64] |= 1 << (page % 64); }
+
+    pub fn mark_all(&mut self) { self.0 = [!0; TRACKED_PAGES / 64]; }
+
+    pub fn contains(&self, page: usize) -> bool { self.0[page / 64] & (1 << (page % 64)) != 0 }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ { (0..TRACKED_PAGES).filter(|&page| self.contains(page)) }
+}
+
 pub struct Mem {
     pub bios: Vec<u8>,
     pub ewram: Vec<u8>,
@@ -14,7 +42,43 @@ pub struct Mem {
     pub palette: Vec<u8>,
     pub oam: Vec<u8>,
     pub rom: Vec<u8>,
-    pub sram: Vec<u8>,
+    // Only CPU and DMA stores (`store_tracked`), power-on and full state
+    // loads mark pages; code writing the RAM vectors directly must mark
+    // them itself.
+    pub dirty: DirtyPages,
+}
+
+// BIOS and ROM are loaded from files, not restored from states. Snapshot
+// writers leave out the tracked RAM, which the rewind history keeps by page.
+impl Savestate for Mem {
+    fn save_state(&self, w: &mut StateWriter) {
+        if w.with_ram() {
+            w.put(&self.ewram);
+            w.put(&self.iwram);
+            w.put(&self.vram);
+        }
+        w.put(&self.palette);
+        w.put(&self.oam);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        if r.with_ram() {
+            r.take(&mut self.ewram)?;
+            r.take(&mut self.iwram)?;
+            r.take(&mut self.vram)?;
+            self.dirty.mark_all();
+        }
+        r.take(&mut self.palette)?;
+        r.take(&mut self.oam)
+    }
+}
+
+/// A RAM that `DirtyPages` covers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tracked {
+    Ewram,
+    Iwram,
+    Vram,
 }
 
 impl Default for Mem {
@@ -27,7 +91,7 @@ impl Default for Mem {
             palette: vec![0u8; PALETTE_SIZE],
             oam: vec![0u8; OAM_SIZE],
             rom: Vec::new(),
-            sram: vec![0u8; 64 * 1024],
+            dirty: DirtyPages::default(),
         }
     }
 }
@@ -35,6 +99,88 @@ impl Default for Mem {
 impl Mem {
     pub fn new() -> Self { Self::default() }
 
+    /// Clears the RAMs, but not BIOS or ROM, as on power-on. Real chips come
+    /// up holding noise; zeros keep runs reproducible.
+    pub fn power_on(&mut self) {
+        for region in [&mut self.ewram, &mut self.iwram, &mut self.vram, &mut self.palette, &mut self.oam] {
+            region.fill(0);
+        }
+        self.dirty.mark_all();
+    }
+
+    /// Stores a byte at `off` (mirrored) in a tracked RAM and marks its page.
+    pub fn store_tracked(&mut self, region: Tracked, off: usize, value: u8) {
+        let (data, first_page) = match region {
+            Tracked::Ewram => (&mut self.ewram, 0),
+            Tracked::Iwram => (&mut self.iwram, IWRAM_PAGE),
+            Tracked::Vram => (&mut self.vram, VRAM_PAGE),
+        };
+        let Some(i) = off.checked_rem(data.len()) else {
+            return;
+        };
+        data[i] = value;
+        self.dirty.mark(first_page + i / PAGE_SIZE);
+    }
+
+    /// Contents of a tracked page.
+    pub fn page(&self, page: usize) -> &[u8] {
+        let (region, off) = locate(page);
+        let data = match region {
+            Tracked::Ewram => &self.ewram,
+            Tracked::Iwram => &self.iwram,
+            Tracked::Vram => &self.vram,
+        };
+        &data[off..][..PAGE_SIZE]
+    }
+
+    pub fn page_mut(&mut self, page: usize) -> &mut [u8] {
+        let (region, off) = locate(page);
+        let data = match region {
+            Tracked::Ewram => &mut self.ewram,
+            Tracked::Iwram => &mut self.iwram,
+            Tracked::Vram => &mut self.vram,
+        };
+        &mut data[off..][..PAGE_SIZE]
+    }
+
+    // Video memory by offset from the start of each region. Offsets past the
+    // end wrap as the bus mirrors them, so the bus, DMA and the debug viewers
+    // agree on what each address holds.
+
+    /// Byte `off` of palette RAM, which repeats every 1 KiB.
+    pub fn palette8(&self, off: usize) -> u8 { mirrored(&self.palette, off) }
+    pub fn store_palette8(&mut self, off: usize, value: u8) { store_mirrored(&mut self.palette, off, value); }
+
+    /// BGR555 color of palette entry `index`: 0-255 for BGs, 256-511 for
+    /// OBJs.
+    pub fn palette_color(&self, index: usize) -> u16 { read16(|off| self.palette8(off), index * 2) }
+
+    /// Byte `off` of VRAM, mirrored as in `vram_offset`.
+    pub fn vram8(&self, off: usize) -> u8 { mirrored(&self.vram, vram_offset(off as u32)) }
+    pub fn vram16(&self, off: usize) -> u16 { read16(|off| self.vram8(off), off & !1) }
+    pub fn store_vram8(&mut self, off: usize, value: u8) {
+        self.store_tracked(Tracked::Vram, vram_offset(off as u32), value);
+    }
+
+    /// `range` of VRAM without mirroring, for bulk reads such as tile
+    /// sheets. Ranges must lie in the 96 KiB; release builds cut off what
+    /// does not.
+    pub fn vram_slice(&self, range: Range<usize>) -> &[u8] {
+        debug_assert!(range.start <= range.end && range.end <= self.vram.len(), "VRAM range {:X?}", range);
+        let end = range.end.min(self.vram.len());
+        &self.vram[range.start.min(end)..end]
+    }
+
+    /// Byte `off` of OAM, which repeats every 1 KiB.
+    pub fn oam8(&self, off: usize) -> u8 { mirrored(&self.oam, off) }
+    pub fn store_oam8(&mut self, off: usize, value: u8) { store_mirrored(&mut self.oam, off, value); }
+    pub fn oam16(&self, off: usize) -> u16 { read16(|off| self.oam8(off), off & !1) }
+
+    /// The OAM entries in order, as their four halfwords.
+    pub fn oam_entries(&self) -> impl Iterator<Item = [u16; 4]> + '_ {
+        (0..OAM_ENTRIES).map(|i| std::array::from_fn(|half| self.oam16(i * 8 + half * 2)))
+    }
+
     pub fn load_bios(&mut self, data: &[u8]) {
         let len = data.len().min(BIOS_SIZE);
         self.bios[..len].copy_from_slice(&data[..len]);
@@ -44,3 +190,103 @@ impl Mem {
         self.rom = data.to_vec();
     }
 }
+
+// The RAM a tracked page is in, and its offset there.
+fn locate(page: usize) -> (Tracked, usize) {
+    if page < IWRAM_PAGE {
+        (Tracked::Ewram, page * PAGE_SIZE)
+    } else if page < VRAM_PAGE {
+        (Tracked::Iwram, (page - IWRAM_PAGE) * PAGE_SIZE)
+    } else {
+        (Tracked::Vram, (page - VRAM_PAGE) * PAGE_SIZE)
+    }
+}
+
+/// Byte `off` of a region that repeats over its length; 0 for an empty one.
+pub fn mirrored(region: &[u8], off: usize) -> u8 {
+    off.checked_rem(region.len()).map_or(0, |i| region[i])
+}
+
+pub fn mirrored_mut(region: &mut [u8], off: usize) -> Option<&mut u8> {
+    let i = off.checked_rem(region.len())?;
+    region.get_mut(i)
+}
+
+fn store_mirrored(region: &mut [u8], off: usize, value: u8) {
+    if let Some(byte) = mirrored_mut(region, off) {
+        *byte = value;
+    }
+}
+
+fn read16(byte: impl Fn(usize) -> u8, off: usize) -> u16 { u16::from_le_bytes([byte(off), byte(off + 1)]) }
+
+/// Offset of `addr` in VRAM: the 96 KiB repeat every 128 KiB, with the last
+/// 32 KiB of each step mirroring the OBJ tiles at 0x10000.
+pub fn vram_offset(addr: u32) -> usize {
+    let off = addr as usize & 0x1_FFFF;
+    if off >= VRAM_SIZE { off - 0x8000 } else { off }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vram_mirrors_in_128k_steps() {
+        assert_eq!(vram_offset(0x0600_0000), 0);
+        assert_eq!(vram_offset(0x0601_7FFF), 0x1_7FFF);
+        assert_eq!(vram_offset(0x0601_8000), 0x1_0000);
+        assert_eq!(vram_offset(0x0601_FFFF), 0x1_7FFF);
+        assert_eq!(vram_offset(0x0602_0004), 4);
+        assert_eq!(vram_offset(0x06FF_FFFF), 0x1_7FFF);
+    }
+
+    #[test]
+    fn video_regions_wrap_like_the_bus() {
+        let mut mem = Mem::new();
+        mem.store_palette8(0x200, 0x1F);
+        mem.store_palette8(0x601, 0x7C);
+        assert_eq!(mem.palette_color(256), 0x7C1F, "OBJ colors follow the BG ones");
+        assert_eq!(mem.palette_color(0), 0);
+        assert_eq!(mem.palette8(0x1200), 0x1F);
+
+        mem.store_vram8(0x1_8002, 0x34);
+        mem.store_vram8(0x1_0003, 0x12);
+        assert_eq!(mem.vram16(0x1_0002), 0x1234);
+        assert_eq!(mem.vram16(0x3_8003), 0x1234);
+        assert_eq!(mem.vram_slice(0x1_0002..0x1_0004), [0x34, 0x12]);
+
+        mem.store_oam8(0x400 + 8 * 3 + 6, 0x80);
+        let entry = mem.oam_entries().nth(3).unwrap();
+        assert_eq!(entry, [0, 0, 0, 0x80]);
+        assert_eq!(mem.oam_entries().count(), 128);
+    }
+
+    #[test]
+    #[should_panic(expected = "VRAM range")]
+    #[cfg(debug_assertions)]
+    fn vram_slices_past_the_end_are_caught() { Mem::new().vram_slice(VRAM_SIZE - 2..VRAM_SIZE + 2); }
+
+    #[test]
+    fn tracked_stores_mark_their_page() {
+        let mut mem = Mem::new();
+        mem.dirty = DirtyPages::default();
+        mem.store_tracked(Tracked::Ewram, EWRAM_SIZE + 5, 1);
+        mem.store_tracked(Tracked::Iwram, 0x7FFF, 2);
+        mem.store_tracked(Tracked::Vram, 0x1_0400, 3);
+        let pages: Vec<usize> = mem.dirty.iter().collect();
+        assert_eq!(pages, [0, VRAM_PAGE - 1, VRAM_PAGE + 0x41]);
+        assert_eq!(mem.page(0)[5], 1);
+        assert_eq!(mem.page(VRAM_PAGE - 1)[PAGE_SIZE - 1], 2);
+        assert_eq!(mem.page(VRAM_PAGE + 0x41)[0], 3);
+        assert_eq!(TRACKED_PAGES, VRAM_PAGE + 96);
+    }
+
+    #[test]
+    fn empty_regions_read_zero_and_ignore_writes() {
+        let mut empty: Vec<u8> = Vec::new();
+        assert_eq!(mirrored(&empty, 5), 0);
+        assert!(mirrored_mut(&mut empty, 5).is_none());
+        assert_eq!(mirrored(&[1, 2, 3], 5), 3);
+    }
+}
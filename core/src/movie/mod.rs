@@ -0,0 +1,168 @@
+// Input movies: the buttons held on every frame, plus where emulation
+// started (power-on or a savestate). Replaying one on the same ROM reproduces
+// the run exactly, which makes them usable as regression tests.
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use crate::input::KeyState;
+use crate::state::{StateError, StateReader, StateWriter};
+
+const MOVIE_MAGIC: [u8; 4] = *b"RBMV";
+const MOVIE_VERSION: u16 = 1;
+
+#[derive(Debug)]
+pub enum MovieError {
+    Io(io::Error),
+    BadMagic,
+    Version(u16),
+    RomMismatch { expected: u32, found: u32 },
+    Truncated,
+    /// The embedded start state could not be read or loaded.
+    State(StateError),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::Io(e) => write!(f, "{}", e),
+            MovieError::BadMagic => write!(f, "not a RoBA movie"),
+            MovieError::Version(v) => write!(f, "movie version {} is not supported", v),
+            MovieError::RomMismatch { expected, found } => write!(
+                f,
+                "movie was recorded on a different ROM (CRC32 {:08X}, loaded {:08X})",
+                found, expected
+            ),
+            MovieError::Truncated => write!(f, "movie is truncated"),
+            MovieError::State(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<io::Error> for MovieError {
+    fn from(e: io::Error) -> Self { MovieError::Io(e) }
+}
+
+impl From<StateError> for MovieError {
+    fn from(e: StateError) -> Self {
+        match e {
+            StateError::Truncated => MovieError::Truncated,
+            e => MovieError::State(e),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_crc32: u32,
+    /// Savestate the movie starts from; `None` starts from power-on.
+    pub start_state: Option<Vec<u8>>,
+    pub frames: Vec<KeyState>,
+}
+
+impl Movie {
+    pub fn new(rom_crc32: u32, start_state: Option<Vec<u8>>) -> Self {
+        Self { rom_crc32, start_state, frames: Vec::new() }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.bytes(&MOVIE_MAGIC);
+        w.put(&MOVIE_VERSION);
+        w.put(&self.rom_crc32);
+        match &self.start_state {
+            Some(state) => {
+                w.put(&true);
+                w.put(state);
+            }
+            None => w.put(&false),
+        }
+        w.put(&(self.frames.len() as u32));
+        for keys in &self.frames {
+            w.put(&keys.bits());
+        }
+        w.finish()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, MovieError> {
+        let mut r = StateReader::new(data);
+        if r.bytes(MOVIE_MAGIC.len())? != MOVIE_MAGIC {
+            return Err(MovieError::BadMagic);
+        }
+        let mut version = 0u16;
+        r.take(&mut version)?;
+        if version != MOVIE_VERSION {
+            return Err(MovieError::Version(version));
+        }
+        let mut movie = Movie::default();
+        r.take(&mut movie.rom_crc32)?;
+        let mut has_state = false;
+        r.take(&mut has_state)?;
+        if has_state {
+            let mut len = 0u32;
+            r.take(&mut len)?;
+            movie.start_state = Some(r.bytes(len as usize)?.to_vec());
+        }
+        let mut count = 0u32;
+        r.take(&mut count)?;
+        movie.frames = r
+            .bytes(count as usize * 2)?
+            .chunks_exact(2)
+            .map(|b| KeyState::from_bits(u16::from_le_bytes([b[0], b[1]])))
+            .collect();
+        Ok(movie)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, MovieError> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+}
+
+// Movie attached to the emulator. Frame `i` of the movie is the input for
+// emulated frame `start_frame + i`, so loading a savestate while recording
+// rewinds the movie with it (re-recording).
+pub(crate) struct MovieSession {
+    pub movie: Movie,
+    pub start_frame: u64,
+    pub playing: bool,
+}
+
+/// What the emulator is doing with a movie.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MovieStatus {
+    Idle,
+    Recording { frames: usize },
+    Playing { frame: usize, frames: usize },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn movie_round_trips_through_bytes() {
+        let mut movie = Movie::new(0xDEAD_BEEF, Some(vec![1, 2, 3]));
+        movie.frames = vec![KeyState::A, KeyState::NONE, KeyState::START | KeyState::L];
+        assert_eq!(Movie::from_bytes(&movie.to_bytes()).unwrap(), movie);
+
+        let power_on = Movie::new(1, None);
+        assert_eq!(Movie::from_bytes(&power_on.to_bytes()).unwrap(), power_on);
+    }
+
+    #[test]
+    fn rejects_foreign_and_short_files() {
+        assert!(matches!(Movie::from_bytes(b"RBSTxxxx"), Err(MovieError::BadMagic)));
+        let data = Movie::new(1, None).to_bytes();
+        assert!(matches!(
+            Movie::from_bytes(&data[..data.len() - 1]),
+            Err(MovieError::Truncated)
+        ));
+    }
+}
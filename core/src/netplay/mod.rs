@@ -0,0 +1,678 @@
+// Netplay between two instances. Each side runs the whole game and sends the
+// other the keys it holds on every frame; both players' keys are merged into
+// the one keypad, as on a shared controller. A frame delay holds local input
+// back a few frames so it reaches the peer before it is needed.
+//
+// Lockstep runs a frame only once the peer's input for it has arrived.
+// Rollback runs ahead on a guess (the peer's last confirmed input), keeps a
+// savestate for every guessed frame, and when the real input disagrees it
+// reloads the first wrong frame and re-runs to the present.
+//
+// Every HASH_INTERVAL frames each side hashes the machine (`state_hash`) and
+// sends the hash along with its input, so desyncs are caught. Both instances
+// have to start from the same state: same ROM, BIOS, battery save and
+// settings, with deterministic mode on.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::input::KeyState;
+use crate::state::StateError;
+use crate::Emulator;
+
+mod udp;
+
+pub use udp::UdpTransport;
+
+/// Frames between desync checks.
+pub const HASH_INTERVAL: u32 = 60;
+
+const MAGIC: [u8; 2] = *b"RN";
+const PROTOCOL_VERSION: u8 = 1;
+const TAG_HELLO: u8 = 1;
+const TAG_INPUT: u8 = 2;
+const HEADER_LEN: usize = 4;
+// Inputs one packet carries at most; the rest go out with the next frame.
+const MAX_PACKET_INPUTS: usize = 64;
+const MAX_PACKET_LEN: usize = 512;
+// Marks an input packet without a hash.
+const NO_HASH: u32 = u32::MAX;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum NetplayMode {
+    /// Waits for the peer's input every frame; stalls with the connection.
+    #[default]
+    Lockstep,
+    /// Runs ahead on predicted input and corrects mispredictions from
+    /// savestates; smooth over latency, at the cost of CPU when guesses miss.
+    Rollback,
+}
+
+impl NetplayMode {
+    pub const ALL: [NetplayMode; 2] = [NetplayMode::Lockstep, NetplayMode::Rollback];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NetplayMode::Lockstep => "lockstep",
+            NetplayMode::Rollback => "rollback",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|m| m.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            NetplayMode::Lockstep => "Lockstep",
+            NetplayMode::Rollback => "Rollback",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct NetplayConfig {
+    pub mode: NetplayMode,
+    /// Frames between pressing a key and it taking effect, on both sides.
+    pub delay: u32,
+    /// Frames rollback may run ahead of the peer's input before waiting.
+    pub max_rollback: u32,
+}
+
+impl Default for NetplayConfig {
+    fn default() -> Self { Self { mode: NetplayMode::Lockstep, delay: 2, max_rollback: 8 } }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NetplayError {
+    Version(u8),
+    RomMismatch { local: u32, remote: u32 },
+    /// The machines differed at the start of `frame`.
+    Desync { frame: u32, local: u64, remote: u64 },
+    TimedOut,
+    /// A rollback savestate failed to load.
+    State(StateError),
+}
+
+impl fmt::Display for NetplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetplayError::Version(v) => {
+                write!(f, "peer speaks netplay protocol {} (expected {})", v, PROTOCOL_VERSION)
+            }
+            NetplayError::RomMismatch { local, remote } => {
+                write!(f, "peer runs a different ROM (CRC32 {:08X}, loaded {:08X})", remote, local)
+            }
+            NetplayError::Desync { frame, local, remote } => {
+                write!(f, "desync at frame {} (state {:016X}, peer {:016X})", frame, local, remote)
+            }
+            NetplayError::TimedOut => write!(f, "peer stopped responding"),
+            NetplayError::State(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for NetplayError {}
+
+/// Carries packets to the peer. Packets may be lost, duplicated or
+/// reordered; the session copes.
+pub trait Transport: Send {
+    fn send(&mut self, packet: &[u8]);
+    /// The next packet received, without blocking.
+    fn recv(&mut self, buf: &mut [u8]) -> Option<usize>;
+    /// Called when the last packet `recv` returned was a valid hello for
+    /// this game; a transport that listens to anyone until then can settle
+    /// on its sender as the peer.
+    fn confirm_peer(&mut self) {}
+    /// Whether packets can only be coming from the peer. While this is
+    /// false, packets that don't fit the session are a stranger's and are
+    /// dropped instead of ending it.
+    fn has_peer(&self) -> bool { true }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Packet {
+    /// Sent until answered; `ready` tells the peer its own hello arrived.
+    Hello { rom_crc32: u32, ready: bool },
+    /// The sender's inputs for `start..`, how many of the receiver's inputs
+    /// it has (`ack`), and its latest hash.
+    Input { ack: u32, start: u32, keys: Vec<KeyState>, hash: Option<(u32, u64)> },
+}
+
+impl Packet {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(PROTOCOL_VERSION);
+        match self {
+            Packet::Hello { rom_crc32, ready } => {
+                out.push(TAG_HELLO);
+                out.extend_from_slice(&rom_crc32.to_le_bytes());
+                out.push(*ready as u8);
+            }
+            Packet::Input { ack, start, keys, hash } => {
+                out.push(TAG_INPUT);
+                out.extend_from_slice(&ack.to_le_bytes());
+                out.extend_from_slice(&start.to_le_bytes());
+                out.push(keys.len() as u8);
+                for keys in keys {
+                    out.extend_from_slice(&keys.bits().to_le_bytes());
+                }
+                let (frame, hash) = hash.unwrap_or((NO_HASH, 0));
+                out.extend_from_slice(&frame.to_le_bytes());
+                out.extend_from_slice(&hash.to_le_bytes());
+            }
+        }
+        out
+    }
+
+    /// Expects the header to have been checked.
+    fn decode(data: &[u8]) -> Option<Packet> {
+        let body = data.get(HEADER_LEN..)?;
+        let u32_at = |at: usize| Some(u32::from_le_bytes(body.get(at..at + 4)?.try_into().ok()?));
+        match data[3] {
+            TAG_HELLO => Some(Packet::Hello { rom_crc32: u32_at(0)?, ready: *body.get(4)? != 0 }),
+            TAG_INPUT => {
+                let count = *body.get(8)? as usize;
+                let keys = body.get(9..9 + count * 2)?;
+                let keys = keys.chunks_exact(2).map(|k| KeyState::from_bits(u16::from_le_bytes([k[0], k[1]])));
+                let tail = 9 + count * 2;
+                let frame = u32_at(tail)?;
+                let hash = u64::from_le_bytes(body.get(tail + 4..tail + 12)?.try_into().ok()?);
+                Some(Packet::Input {
+                    ack: u32_at(0)?,
+                    start: u32_at(4)?,
+                    keys: keys.collect(),
+                    hash: (frame != NO_HASH).then_some((frame, hash)),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// One netplay connection. Call `advance` once per frame the frontend wants
+/// to run, instead of `Emulator::run_frame`.
+pub struct Session {
+    transport: Box<dyn Transport>,
+    config: NetplayConfig,
+    rom_crc32: u32,
+    peer_ready: bool,
+    last_heard: Instant,
+    // Next frame to run, counted from the start of the session.
+    frame: u32,
+    // Frames before this ran with the peer's real input.
+    confirmed: u32,
+    local: Vec<KeyState>,
+    remote: Vec<Option<KeyState>>,
+    // Leading frames of `remote` that are all known.
+    remote_known: u32,
+    // Leading frames of `local` the peer has.
+    peer_ack: u32,
+    // State at the start of, and guessed peer input for, every frame from
+    // `confirmed` on.
+    guesses: VecDeque<(Vec<u8>, KeyState)>,
+    local_hashes: BTreeMap<u32, u64>,
+    remote_hashes: BTreeMap<u32, u64>,
+    rollbacks: u64,
+}
+
+impl Session {
+    /// Starts talking to the peer on `transport`. `emu` should be at
+    /// power-on, or otherwise in the same state as the peer's.
+    pub fn new(transport: Box<dyn Transport>, config: NetplayConfig, emu: &Emulator) -> Self {
+        let mut session = Self {
+            transport,
+            config,
+            rom_crc32: emu.rom_crc32(),
+            peer_ready: false,
+            last_heard: Instant::now(),
+            frame: 0,
+            confirmed: 0,
+            local: vec![KeyState::NONE; config.delay as usize],
+            remote: Vec::new(),
+            remote_known: 0,
+            peer_ack: 0,
+            guesses: VecDeque::new(),
+            local_hashes: BTreeMap::new(),
+            remote_hashes: BTreeMap::new(),
+            rollbacks: 0,
+        };
+        session.send(&Packet::Hello { rom_crc32: session.rom_crc32, ready: false });
+        session
+    }
+
+    pub fn config(&self) -> &NetplayConfig { &self.config }
+    /// Whether the peer has answered and frames can run.
+    pub fn is_ready(&self) -> bool { self.peer_ready }
+    /// Frames run since the session started.
+    pub fn frame(&self) -> u32 { self.frame }
+    /// Frames run on a guess of the peer's input, not yet confirmed.
+    pub fn frames_ahead(&self) -> u32 { self.frame - self.confirmed }
+    /// How often a misprediction forced a rollback.
+    pub fn rollbacks(&self) -> u64 { self.rollbacks }
+
+    /// Sends `keys` as the local input and runs the next frame if the mode
+    /// allows it. Returns whether a frame ran; when it did not, call again
+    /// on the next tick (the keys are only recorded once per frame).
+    pub fn advance(&mut self, emu: &mut Emulator, keys: KeyState) -> Result<bool, NetplayError> {
+        self.poll()?;
+        if !self.peer_ready {
+            return Ok(false);
+        }
+        if self.local.len() as u32 == self.frame + self.config.delay {
+            self.local.push(keys);
+        }
+        self.send_inputs();
+        self.reconcile(emu)?;
+        let ran = self.step(emu, self.config.mode == NetplayMode::Rollback);
+        self.check_hashes()?;
+        Ok(ran)
+    }
+
+    /// Handles incoming packets without running anything.
+    pub fn poll(&mut self) -> Result<(), NetplayError> {
+        if !self.peer_ready {
+            self.send(&Packet::Hello { rom_crc32: self.rom_crc32, ready: false });
+        }
+        let mut buf = [0u8; MAX_PACKET_LEN];
+        while let Some(n) = self.transport.recv(&mut buf) {
+            let data = &buf[..n.min(buf.len())];
+            if data.len() < HEADER_LEN || data[..2] != MAGIC {
+                continue;
+            }
+            if data[2] != PROTOCOL_VERSION {
+                if !self.transport.has_peer() {
+                    log::debug!("Netplay: dropping a version {} packet from a stranger", data[2]);
+                    continue;
+                }
+                return Err(NetplayError::Version(data[2]));
+            }
+            let Some(packet) = Packet::decode(data) else {
+                log::debug!("Netplay: dropping a malformed packet");
+                continue;
+            };
+            self.last_heard = Instant::now();
+            self.receive(packet)?;
+        }
+        if self.peer_ready && self.last_heard.elapsed() > TIMEOUT {
+            return Err(NetplayError::TimedOut);
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self, packet: Packet) -> Result<(), NetplayError> {
+        match packet {
+            Packet::Hello { rom_crc32, ready } => {
+                if rom_crc32 != self.rom_crc32 {
+                    if !self.transport.has_peer() {
+                        log::debug!("Netplay: dropping a hello for ROM {:08X} from a stranger", rom_crc32);
+                        return Ok(());
+                    }
+                    return Err(NetplayError::RomMismatch { local: self.rom_crc32, remote: rom_crc32 });
+                }
+                self.transport.confirm_peer();
+                if !self.peer_ready {
+                    log::info!("Netplay: connected ({}, {} frames delay)", self.config.mode.label(), self.config.delay);
+                    self.peer_ready = true;
+                }
+                if !ready {
+                    self.send(&Packet::Hello { rom_crc32: self.rom_crc32, ready: true });
+                }
+            }
+            // Until the hellos are through, input can only come from a
+            // stranger.
+            Packet::Input { .. } if !self.peer_ready => {}
+            Packet::Input { ack, start, keys, hash } => {
+                // The peer only sends from what we acknowledged, a packet's
+                // worth at a time; anything further ahead is bogus and would
+                // grow `remote` without bound.
+                let window = self.config.max_rollback.saturating_add(MAX_PACKET_INPUTS as u32);
+                if start > self.remote_known.saturating_add(window) {
+                    log::debug!("Netplay: dropping inputs from frame {}, {} are known", start, self.remote_known);
+                    return Ok(());
+                }
+                self.peer_ack = self.peer_ack.max(ack.min(self.local.len() as u32));
+                for (i, keys) in keys.into_iter().enumerate() {
+                    let frame = start as usize + i;
+                    if self.remote.len() <= frame {
+                        self.remote.resize(frame + 1, None);
+                    }
+                    self.remote[frame] = Some(keys);
+                }
+                while self.remote.get(self.remote_known as usize).is_some_and(Option::is_some) {
+                    self.remote_known += 1;
+                }
+                if let Some((frame, hash)) = hash {
+                    self.remote_hashes.insert(frame, hash);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, packet: &Packet) { self.transport.send(&packet.encode()); }
+
+    fn send_inputs(&mut self) {
+        let start = self.peer_ack as usize;
+        let end = self.local.len().min(start + MAX_PACKET_INPUTS);
+        // Only hashes of confirmed frames mean anything to the peer.
+        let hash = self.local_hashes.range(..=self.confirmed).next_back().map(|(&f, &h)| (f, h));
+        self.send(&Packet::Input {
+            ack: self.remote_known,
+            start: start as u32,
+            keys: self.local[start..end].to_vec(),
+            hash,
+        });
+    }
+
+    fn remote_input(&self, frame: u32) -> Option<KeyState> { self.remote.get(frame as usize).copied().flatten() }
+
+    // Confirms guessed frames whose real input has arrived, rolling back to
+    // the first one that was guessed wrong.
+    fn reconcile(&mut self, emu: &mut Emulator) -> Result<(), NetplayError> {
+        while let Some(&(_, guess)) = self.guesses.front() {
+            let Some(actual) = self.remote_input(self.confirmed) else {
+                break;
+            };
+            if actual != guess {
+                let (state, _) = self.guesses.front().expect("checked above");
+                emu.load_state(state).map_err(NetplayError::State)?;
+                let present = self.frame;
+                log::debug!("Netplay: rolling back {} frames", present - self.confirmed);
+                self.rollbacks += 1;
+                self.frame = self.confirmed;
+                self.guesses.clear();
+                while self.frame < present && self.step(emu, true) {}
+                continue;
+            }
+            self.guesses.pop_front();
+            self.confirmed += 1;
+        }
+        Ok(())
+    }
+
+    // Runs the next frame with the peer's input, or with a guess if allowed.
+    fn step(&mut self, emu: &mut Emulator, may_guess: bool) -> bool {
+        let Some(&local) = self.local.get(self.frame as usize) else {
+            return false;
+        };
+        let remote = match self.remote_input(self.frame) {
+            Some(remote) if self.frame == self.confirmed => {
+                self.confirmed += 1;
+                remote
+            }
+            _ if !may_guess || self.frames_ahead() >= self.config.max_rollback => return false,
+            known => {
+                let guess = known.unwrap_or_else(|| {
+                    self.confirmed.checked_sub(1).and_then(|f| self.remote_input(f)).unwrap_or_default()
+                });
+                self.guesses.push_back((emu.save_state(), guess));
+                guess
+            }
+        };
+        if self.frame.is_multiple_of(HASH_INTERVAL) {
+            self.local_hashes.insert(self.frame, emu.state_hash());
+        }
+        emu.set_keys(local | remote);
+        emu.run_frame();
+        self.frame += 1;
+        true
+    }
+
+    fn check_hashes(&mut self) -> Result<(), NetplayError> {
+        // Local hashes are final once every frame before them is confirmed.
+        let checkable: Vec<u32> = self.remote_hashes.range(..self.confirmed).map(|(&f, _)| f).collect();
+        for frame in checkable {
+            let remote = self.remote_hashes.remove(&frame).expect("listed above");
+            if let Some(&local) = self.local_hashes.get(&frame)
+                && local != remote
+            {
+                return Err(NetplayError::Desync { frame, local, remote });
+            }
+            self.local_hashes.retain(|&f, _| f >= frame);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::BusAccess;
+    use std::sync::{Arc, Mutex};
+
+    type Queue = Arc<Mutex<VecDeque<Vec<u8>>>>;
+
+    // One end of an in-memory link, optionally losing every nth packet.
+    struct Pipe {
+        tx: Queue,
+        rx: Queue,
+        sent: usize,
+        drop_every: usize,
+    }
+
+    impl Transport for Pipe {
+        fn send(&mut self, packet: &[u8]) {
+            self.sent += 1;
+            if self.drop_every == 0 || !self.sent.is_multiple_of(self.drop_every) {
+                self.tx.lock().unwrap().push_back(packet.to_vec());
+            }
+        }
+
+        fn recv(&mut self, buf: &mut [u8]) -> Option<usize> {
+            let packet = self.rx.lock().unwrap().pop_front()?;
+            buf[..packet.len()].copy_from_slice(&packet);
+            Some(packet.len())
+        }
+    }
+
+    fn lossy_pipe(drop_every: usize) -> (Box<Pipe>, Box<Pipe>) {
+        let (a, b) = (Queue::default(), Queue::default());
+        (
+            Box::new(Pipe { tx: a.clone(), rx: b.clone(), sent: 0, drop_every }),
+            Box::new(Pipe { tx: b, rx: a, sent: 0, drop_every }),
+        )
+    }
+
+    fn pipe() -> (Box<Pipe>, Box<Pipe>) { lossy_pipe(0) }
+
+    // Adds KEYINPUT to a sum in IWRAM once per frame, halting in between.
+    const KEY_SUM_PROGRAM: [u32; 16] = [
+        0xE3A0_1301, // mov r1, #0x04000000
+        0xE3A0_0008, // mov r0, #8
+        0xE1C1_00B4, // strh r0, [r1, #4]
+        0xE281_3C02, // add r3, r1, #0x200
+        0xE3A0_0001, // mov r0, #1
+        0xE1C3_00B0, // strh r0, [r3]
+        0xE281_4E13, // add r4, r1, #0x130
+        0xE3A0_6403, // mov r6, #0x03000000
+        0xE3A0_5000, // mov r5, #0
+        0xE1D4_00B0, // loop: ldrh r0, [r4]
+        0xE082_2000, // add r2, r2, r0
+        0xE586_2000, // str r2, [r6]
+        0xE3A0_7001, // mov r7, #1
+        0xE1C3_70B2, // strh r7, [r3, #2]
+        0xE5C3_5101, // strb r5, [r3, #0x101]
+        0xEAFF_FFF8, // b loop
+    ];
+
+    fn emulator() -> Emulator {
+        let rom: Vec<u8> = KEY_SUM_PROGRAM.iter().flat_map(|w| w.to_le_bytes()).collect();
+        let mut emu = Emulator::builder().deterministic(true).build();
+        emu.load_rom_bytes(&rom).unwrap();
+        emu
+    }
+
+    // Player 1 taps A every third frame, player 2 holds directions in turn.
+    fn keys(player: usize, frame: u32) -> KeyState {
+        match player {
+            0 if frame.is_multiple_of(3) => KeyState::A,
+            0 => KeyState::NONE,
+            _ => [KeyState::UP, KeyState::RIGHT, KeyState::DOWN, KeyState::LEFT][(frame / 5 % 4) as usize],
+        }
+    }
+
+    fn sessions(config: NetplayConfig) -> ([Emulator; 2], [Session; 2]) { sessions_over(config, pipe()) }
+
+    fn sessions_over(config: NetplayConfig, (a, b): (Box<Pipe>, Box<Pipe>)) -> ([Emulator; 2], [Session; 2]) {
+        let emus = [emulator(), emulator()];
+        let sessions = [Session::new(a, config, &emus[0]), Session::new(b, config, &emus[1])];
+        (emus, sessions)
+    }
+
+    // Runs both sides to `frames`, player 1 taking `speed` turns for every
+    // one of player 2's so that it keeps running ahead.
+    fn play(emus: &mut [Emulator; 2], sessions: &mut [Session; 2], frames: u32, speed: usize) -> Result<(), NetplayError> {
+        let mut stalls = 0;
+        while sessions.iter().any(|s| s.frame() < frames) {
+            let mut ran = false;
+            for (player, turns) in [(0, speed), (1, 1)] {
+                for _ in 0..turns {
+                    let session = &mut sessions[player];
+                    if session.frame() < frames {
+                        let keys = keys(player, session.frame());
+                        ran |= session.advance(&mut emus[player], keys)?;
+                    }
+                }
+            }
+            stalls = if ran { 0 } else { stalls + 1 };
+            assert!(stalls < 10, "sessions stopped at frames {} and {}", sessions[0].frame(), sessions[1].frame());
+        }
+        // Let rollback settle the last guesses.
+        for _ in 0..3 {
+            for (session, emu) in sessions.iter_mut().zip(emus.iter_mut()) {
+                session.poll()?;
+                session.reconcile(emu)?;
+                session.send_inputs();
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn packets_round_trip() {
+        let packets = [
+            Packet::Hello { rom_crc32: 0xDEAD_BEEF, ready: true },
+            Packet::Input { ack: 7, start: 3, keys: vec![KeyState::A, KeyState::L | KeyState::UP], hash: Some((60, 42)) },
+            Packet::Input { ack: 0, start: 0, keys: Vec::new(), hash: None },
+        ];
+        for packet in packets {
+            let data = packet.encode();
+            assert_eq!(Packet::decode(&data), Some(packet));
+            assert_eq!(Packet::decode(&data[..data.len() - 1]), None);
+        }
+    }
+
+    #[test]
+    fn lockstep_sessions_stay_in_sync() {
+        let (mut emus, mut sessions) = sessions(NetplayConfig::default());
+        play(&mut emus, &mut sessions, 70, 1).unwrap();
+        assert_eq!(emus[0].state_hash(), emus[1].state_hash());
+        assert_eq!(emus[0].state_hash(), offline_hash(70, 2));
+        assert_eq!(sessions[0].rollbacks(), 0);
+    }
+
+    // The machine after `frames` frames of both players' keys, `delay`
+    // frames late, without netplay.
+    fn offline_hash(frames: u32, delay: u32) -> u64 {
+        let mut emu = emulator();
+        for frame in 0..frames {
+            let keys = match frame.checked_sub(delay) {
+                Some(pressed) => keys(0, pressed) | keys(1, pressed),
+                None => KeyState::NONE,
+            };
+            emu.set_keys(keys);
+            emu.run_frame();
+        }
+        emu.state_hash()
+    }
+
+    #[test]
+    fn rollback_corrects_mispredictions() {
+        let config = NetplayConfig { mode: NetplayMode::Rollback, delay: 0, max_rollback: 6 };
+        let (mut emus, mut sessions) = sessions(config);
+        play(&mut emus, &mut sessions, 70, 3).unwrap();
+        assert!(sessions[0].rollbacks() > 0);
+        assert_eq!(sessions[0].frames_ahead(), 0);
+        assert_eq!(emus[0].state_hash(), emus[1].state_hash());
+        assert_eq!(emus[0].state_hash(), offline_hash(70, 0));
+    }
+
+    #[test]
+    fn lost_packets_are_made_up_for() {
+        for mode in NetplayMode::ALL {
+            let config = NetplayConfig { mode, ..NetplayConfig::default() };
+            let (mut emus, mut sessions) = sessions_over(config, lossy_pipe(3));
+            play(&mut emus, &mut sessions, 40, 2).unwrap();
+            assert_eq!(emus[0].state_hash(), emus[1].state_hash(), "{:?}", mode);
+            assert_eq!(emus[0].state_hash(), offline_hash(40, 2), "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn desyncs_are_detected() {
+        let (mut emus, mut sessions) = sessions(NetplayConfig::default());
+        play(&mut emus, &mut sessions, 10, 1).unwrap();
+        emus[1].bus_mut().write32(0x0300_0100, 1);
+        let error = play(&mut emus, &mut sessions, 2 * HASH_INTERVAL, 1).unwrap_err();
+        assert!(matches!(error, NetplayError::Desync { frame: HASH_INTERVAL, .. }), "{}", error);
+    }
+
+    #[test]
+    fn inputs_far_ahead_are_dropped() {
+        let (a, b) = pipe();
+        let to_host = b.tx.clone();
+        let (mut emus, mut sessions) = sessions_over(NetplayConfig::default(), (a, b));
+        play(&mut emus, &mut sessions, 5, 1).unwrap();
+
+        let bogus = Packet::Input { ack: 0, start: u32::MAX - 8, keys: vec![KeyState::A; 4], hash: None };
+        to_host.lock().unwrap().push_back(bogus.encode());
+        sessions[0].poll().unwrap();
+        assert!(sessions[0].remote.len() < 100, "remote inputs grew to {}", sessions[0].remote.len());
+
+        play(&mut emus, &mut sessions, 20, 1).unwrap();
+        assert_eq!(emus[0].state_hash(), offline_hash(20, 2));
+    }
+
+    #[test]
+    fn different_roms_are_refused() {
+        let mut other = Emulator::new();
+        other.load_rom_bytes(&[0xFE, 0xFF, 0xFF, 0xEA]).unwrap();
+        let emu = emulator();
+        let (a, b) = pipe();
+        let mut host = Session::new(a, NetplayConfig::default(), &emu);
+        let _client = Session::new(b, NetplayConfig::default(), &other);
+        assert!(matches!(host.poll(), Err(NetplayError::RomMismatch { .. })));
+    }
+
+    #[test]
+    fn udp_sessions_connect_over_loopback() {
+        let host = UdpTransport::host("127.0.0.1:0").unwrap();
+        // Stray datagrams first must neither take the peer's place nor end
+        // the session, even ones that look like another version or game.
+        let stray = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut other_version = Packet::Hello { rom_crc32: 0, ready: false }.encode();
+        other_version[2] = PROTOCOL_VERSION.wrapping_add(1);
+        let other_rom = Packet::Hello { rom_crc32: !emulator().rom_crc32(), ready: false }.encode();
+        for packet in [&b"hello?"[..], &other_version, &other_rom] {
+            stray.send_to(packet, host.local_addr().unwrap()).unwrap();
+        }
+        let client = UdpTransport::connect(host.local_addr().unwrap()).unwrap();
+        let mut emus = [emulator(), emulator()];
+        let config = NetplayConfig::default();
+        let mut sessions = [Session::new(Box::new(host), config, &emus[0]), Session::new(Box::new(client), config, &emus[1])];
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while sessions.iter().any(|s| s.frame() < 5) {
+            assert!(Instant::now() < deadline, "no progress over UDP");
+            for (player, (session, emu)) in sessions.iter_mut().zip(emus.iter_mut()).enumerate() {
+                if session.frame() < 5 {
+                    session.advance(emu, keys(player, session.frame())).unwrap();
+                }
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(emus[0].state_hash(), emus[1].state_hash());
+    }
+}
@@ -0,0 +1,87 @@
+// UDP transport for netplay. Non-blocking. A host passes up packets from
+// anyone until the session confirms a sender with a valid hello; from then
+// on, packets from anyone but that peer are ignored.
+
+use std::io::{self, ErrorKind};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+
+use super::Transport;
+
+pub struct UdpTransport {
+    socket: UdpSocket,
+    peer: Option<SocketAddr>,
+    // Sender of the last packet passed up while the peer is unknown.
+    last_from: Option<SocketAddr>,
+}
+
+impl UdpTransport {
+    /// Binds `addr` and plays with whoever first sends a valid hello.
+    pub fn host<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        log::info!("Netplay: waiting for a peer on {}", socket.local_addr()?);
+        Ok(Self { socket, peer: None, last_from: None })
+    }
+
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let peer = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "no address to connect to"))?;
+        let local: SocketAddr = match peer {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.set_nonblocking(true)?;
+        log::info!("Netplay: connecting to {}", peer);
+        Ok(Self { socket, peer: Some(peer), last_from: None })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> { self.socket.local_addr() }
+    pub fn peer_addr(&self) -> Option<SocketAddr> { self.peer }
+}
+
+impl Transport for UdpTransport {
+    fn send(&mut self, packet: &[u8]) {
+        let Some(peer) = self.peer else {
+            return;
+        };
+        if let Err(e) = self.socket.send_to(packet, peer) {
+            log::debug!("Netplay: send to {} failed: {}", peer, e);
+        }
+    }
+
+    fn recv(&mut self, buf: &mut [u8]) -> Option<usize> {
+        loop {
+            match self.socket.recv_from(buf) {
+                Ok((n, from)) => match self.peer {
+                    None => {
+                        self.last_from = Some(from);
+                        return Some(n);
+                    }
+                    Some(peer) if peer == from => return Some(n),
+                    Some(_) => log::debug!("Netplay: ignoring packet from {}", from),
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return None,
+                // Windows reports ICMP port-unreachable from earlier sends
+                // here; the peer may simply not be up yet.
+                Err(e) => {
+                    log::debug!("Netplay: receive failed: {}", e);
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn confirm_peer(&mut self) {
+        if self.peer.is_none()
+            && let Some(from) = self.last_from.take()
+        {
+            log::info!("Netplay: peer {} joined", from);
+            self.peer = Some(from);
+        }
+    }
+
+    fn has_peer(&self) -> bool { self.peer.is_some() }
+}
@@ -0,0 +1,37 @@
+// e-Reader. The reader sits in the cartridge slot and hands scanned card
+// strips to its own software through registers in the SRAM region. Only the
+// host side exists so far: cards are queued, and the register interface
+// claims no addresses until it is emulated.
+
+use std::collections::VecDeque;
+
+use super::CartridgeDevice;
+
+#[derive(Default)]
+pub struct EReader {
+    cards: VecDeque<Vec<u8>>,
+}
+
+impl EReader {
+    pub fn new() -> Self { Self::default() }
+
+    /// Cards scanned but not yet read by the game.
+    pub fn pending_cards(&self) -> usize { self.cards.len() }
+}
+
+impl CartridgeDevice for EReader {
+    fn name(&self) -> &'static str { "e-Reader" }
+
+    fn handles(&self, _addr: u32) -> bool { false }
+    fn read8(&mut self, _addr: u32) -> u8 { 0xFF }
+    fn write8(&mut self, _addr: u32, _value: u8) {}
+
+    fn scan_dot_code(&mut self, data: &[u8]) -> bool {
+        if data.is_empty() {
+            return false;
+        }
+        log::warn!("e-Reader: card queued ({} bytes), but card reading is not emulated yet", data.len());
+        self.cards.push_back(data.to_vec());
+        true
+    }
+}
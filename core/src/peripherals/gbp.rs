@@ -0,0 +1,96 @@
+// Game Boy Player boot detection. While a Player-aware game shows the
+// Player logo it polls the keypad, and the Player answers by holding all
+// four directions, which a real pad can't, on two frames out of three. The
+// game then switches the link port to 32-bit normal mode for the rumble
+// handshake that `sio::gbp::GameBoyPlayer` answers.
+//
+// The logo itself isn't recognized: the signature is posted from reset until
+// the game starts that handshake or DETECTION_FRAMES have passed.
+
+use super::Peripheral;
+use crate::io::Io;
+use crate::sio::SioMode;
+
+// Games show the logo right after the BIOS intro.
+pub const DETECTION_FRAMES: u32 = 600;
+// KEYINPUT bits of Right, Left, Up and Down; cleared means held.
+const DIRECTIONS: u16 = 0x00F0;
+
+pub struct BootDetector {
+    frame: u32,
+    detecting: bool,
+}
+
+impl Default for BootDetector {
+    fn default() -> Self {
+        Self { frame: 0, detecting: true }
+    }
+}
+
+impl BootDetector {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn is_detecting(&self) -> bool { self.detecting }
+}
+
+impl Peripheral for BootDetector {
+    fn name(&self) -> &'static str { "Game Boy Player" }
+
+    fn reset(&mut self) { *self = Self::default(); }
+
+    fn keyinput(&self, keyinput: u16) -> u16 {
+        if self.detecting && self.frame % 3 < 2 { keyinput & !DIRECTIONS } else { keyinput }
+    }
+
+    fn end_frame(&mut self, io: &Io) {
+        if !self.detecting {
+            return;
+        }
+        self.frame += 1;
+        if SioMode::from_registers(io.siocnt, io.rcnt) == SioMode::Normal32 {
+            log::info!("Game Boy Player: game started the link handshake after {} frames", self.frame);
+            self.detecting = false;
+        } else if self.frame >= DETECTION_FRAMES {
+            log::debug!("Game Boy Player: no handshake, stopping detection");
+            self.detecting = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_is_posted_until_the_handshake_starts() {
+        let mut detector = BootDetector::new();
+        let mut io = Io::new();
+        let pressed: Vec<u16> = (0..6)
+            .map(|_| {
+                let keyinput = detector.keyinput(0x03FF);
+                detector.end_frame(&io);
+                keyinput
+            })
+            .collect();
+        assert_eq!(pressed, [0x030F, 0x030F, 0x03FF, 0x030F, 0x030F, 0x03FF]);
+
+        io.siocnt = 0x1000;
+        detector.end_frame(&io);
+        assert!(!detector.is_detecting());
+        assert_eq!(detector.keyinput(0x03FF), 0x03FF);
+
+        detector.reset();
+        assert_eq!(detector.keyinput(0x03FF), 0x030F);
+    }
+
+    #[test]
+    fn detection_gives_up_after_the_window() {
+        let mut detector = BootDetector::new();
+        let io = Io::new();
+        for _ in 0..DETECTION_FRAMES {
+            assert!(detector.is_detecting());
+            detector.end_frame(&io);
+        }
+        assert!(!detector.is_detecting());
+    }
+}
@@ -0,0 +1,66 @@
+// Add-on hardware. Devices on the link port implement `SerialDevice` and are
+// connected with `Emulator::set_serial_device`; devices in the cartridge slot
+// implement `CartridgeDevice`. Hardware that also watches the screen or
+// drives the keypad, like the Game Boy Player, attaches a `Peripheral`.
+
+pub mod ereader;
+pub mod gbp;
+
+use crate::io::Io;
+
+pub use crate::sio::SerialDevice;
+
+/// Hardware between the cartridge slot and the game pak. Sees accesses to the
+/// SRAM region (0x0E000000-0x0FFFFFFF) it `handles` before the cartridge.
+pub trait CartridgeDevice: Send {
+    fn name(&self) -> &'static str;
+
+    fn handles(&self, addr: u32) -> bool;
+    fn read8(&mut self, addr: u32) -> u8;
+    fn write8(&mut self, addr: u32, value: u8);
+
+    /// Feeds a scanned card strip to a card reader. Returns false if the
+    /// device has no reader or rejected the data.
+    fn scan_dot_code(&mut self, _data: &[u8]) -> bool { false }
+}
+
+/// Host-side hooks run once per frame, for hardware that reacts to what the
+/// game does rather than to bus accesses.
+pub trait Peripheral: Send {
+    fn name(&self) -> &'static str;
+
+    /// Called on emulator reset.
+    fn reset(&mut self) {}
+
+    /// KEYINPUT as the game reads it, given the held buttons' value.
+    fn keyinput(&self, keyinput: u16) -> u16 { keyinput }
+
+    /// Called after every frame with the I/O registers the game left.
+    fn end_frame(&mut self, _io: &Io) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{Bus, BusAccess};
+
+    struct Latch(u8);
+
+    impl CartridgeDevice for Latch {
+        fn name(&self) -> &'static str { "latch" }
+        fn handles(&self, addr: u32) -> bool { addr & 0xFFFF == 0xFF00 }
+        fn read8(&mut self, _addr: u32) -> u8 { self.0 }
+        fn write8(&mut self, _addr: u32, value: u8) { self.0 = value; }
+    }
+
+    #[test]
+    fn cartridge_device_sees_only_the_addresses_it_handles() {
+        let mut bus = Bus::new();
+        bus.cart_device = Some(Box::new(Latch(0)));
+        bus.write8(0x0E00_FF00, 0x42);
+        bus.write8(0x0E00_0000, 0x17);
+        assert_eq!(bus.read8(0x0E00_FF00), 0x42);
+        assert_eq!(bus.read8(0x0E00_0000), 0x17);
+        assert_eq!(bus.cart.sram.data()[0x7F00], 0);
+    }
+}
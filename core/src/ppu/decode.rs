@@ -0,0 +1,285 @@
+//! Decodes tiles, background maps and sprites from VRAM, palette RAM and
+//! OAM into RGBA images, independent of the renderer and of any register
+//! state beyond what is passed in. Meant for debugger viewers, screenshot
+//! tooling and asset rippers.
+//!
+//! Colors are expanded from BGR555 without a color profile, and palette
+//! index 0 comes out fully transparent.
+
+use super::Ppu;
+use super::obj::{ObjAttr, Oam};
+use crate::mem::{Mem, VRAM_SIZE};
+use crate::video::{Image, bgr555_to_rgba8888};
+
+// First OBJ palette entry.
+const OBJ_PALETTE: usize = 256;
+const OBJ_VRAM: usize = 0x1_0000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorDepth {
+    /// 16 colors, 32 bytes a tile.
+    Bpp4,
+    /// 256 colors, 64 bytes a tile.
+    Bpp8,
+}
+
+impl ColorDepth {
+    pub fn tile_bytes(self) -> usize {
+        match self {
+            ColorDepth::Bpp4 => 32,
+            ColorDepth::Bpp8 => 64,
+        }
+    }
+}
+
+/// Palette to color tiles with. The 16-color bank only matters for 4bpp
+/// tiles.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Palette {
+    Bg(u8),
+    Obj(u8),
+}
+
+/// The graphics memory to decode from, usually the bus's. Offsets are
+/// relative to the start of each region and mirror as on the bus.
+#[derive(Copy, Clone)]
+pub struct Gfx<'a> {
+    mem: &'a Mem,
+}
+
+impl<'a> Gfx<'a> {
+    pub fn new(mem: &'a Mem) -> Self { Self { mem } }
+
+    /// Palette indices of the tile at `offset`, row by row.
+    pub fn tile_indices(&self, offset: usize, depth: ColorDepth) -> [u8; 64] {
+        let byte = |i: usize| self.mem.vram8(offset + i);
+        let mut indices = [0u8; 64];
+        for (i, index) in indices.iter_mut().enumerate() {
+            *index = match depth {
+                ColorDepth::Bpp4 => (byte(i / 2) >> ((i & 1) * 4)) & 0xF,
+                ColorDepth::Bpp8 => byte(i),
+            };
+        }
+        indices
+    }
+
+    /// Palette indices of every whole tile from `start` to `end`.
+    pub fn tiles(&self, start: usize, end: usize, depth: ColorDepth) -> impl Iterator<Item = [u8; 64]> + '_ {
+        let end = end.min(VRAM_SIZE);
+        let bytes = depth.tile_bytes();
+        (start..end)
+            .step_by(bytes)
+            .take_while(move |&offset| offset + bytes <= end)
+            .map(move |offset| self.tile_indices(offset, depth))
+    }
+
+    pub fn tile(&self, offset: usize, depth: ColorDepth, palette: Palette) -> Image {
+        let mut image = blank(8, 8);
+        self.draw_tile(&mut image, 0, 0, offset, depth, palette, false, false);
+        image
+    }
+
+    /// `count` tiles from `offset`, laid out `columns` to a row.
+    pub fn tile_sheet(&self, offset: usize, count: usize, columns: usize, depth: ColorDepth, pal: Palette) -> Image {
+        let columns = columns.max(1);
+        let rows = count.div_ceil(columns);
+        let mut image = blank(columns * 8, rows * 8);
+        for i in 0..count {
+            let tile = offset + i * depth.tile_bytes();
+            self.draw_tile(&mut image, (i % columns) * 8, (i / columns) * 8, tile, depth, pal, false, false);
+        }
+        image
+    }
+
+    /// The whole map of a text background with control register `bgcnt`,
+    /// 256 or 512 pixels a side, ignoring scrolling.
+    pub fn text_map(&self, bgcnt: u16) -> Image {
+        let (screen_base, char_base) = bg_bases(bgcnt);
+        let depth = if bgcnt & (1 << 7) != 0 { ColorDepth::Bpp8 } else { ColorDepth::Bpp4 };
+        let size = (bgcnt >> 14) & 3;
+        let (w, h) = (256 << (size & 1), 256 << (size >> 1));
+        let mut image = blank(w, h);
+        for ty in 0..h / 8 {
+            for tx in 0..w / 8 {
+                // 32x32-entry screen blocks, left to right, then top to bottom.
+                let block = (ty / 32) * (w / 256) + tx / 32;
+                let entry_offset = screen_base + block * 0x800 + ((ty % 32) * 32 + tx % 32) * 2;
+                let entry = self.vram16(entry_offset);
+                let tile = char_base + (entry & 0x3FF) as usize * depth.tile_bytes();
+                let palette = Palette::Bg((entry >> 12) as u8);
+                let (h_flip, v_flip) = (entry & (1 << 10) != 0, entry & (1 << 11) != 0);
+                self.draw_tile(&mut image, tx * 8, ty * 8, tile, depth, palette, h_flip, v_flip);
+            }
+        }
+        image
+    }
+
+    /// The whole map of an affine background with control register
+    /// `bgcnt`, 128 to 1024 pixels a side, untransformed.
+    pub fn affine_map(&self, bgcnt: u16) -> Image {
+        let (screen_base, char_base) = bg_bases(bgcnt);
+        let tiles = 16 << ((bgcnt >> 14) & 3);
+        let mut image = blank(tiles * 8, tiles * 8);
+        for ty in 0..tiles {
+            for tx in 0..tiles {
+                let entry = self.mem.vram8(screen_base + ty * tiles + tx);
+                let tile = char_base + entry as usize * 64;
+                self.draw_tile(&mut image, tx * 8, ty * 8, tile, ColorDepth::Bpp8, Palette::Bg(0), false, false);
+            }
+        }
+        image
+    }
+
+    /// Sprite `index` at its tile size, flipped as it would be on screen but
+    /// without its affine transform. `one_dimensional` is DISPCNT bit 6.
+    pub fn obj(&self, index: usize, one_dimensional: bool) -> Image {
+        let obj = Oam::from_mem(self.mem).objs[index];
+        let (w, h) = obj.size();
+        let mut image = blank(w, h);
+        let depth = if obj.color_256 { ColorDepth::Bpp8 } else { ColorDepth::Bpp4 };
+        let palette = Palette::Obj(obj.palette);
+        for ty in 0..h / 8 {
+            for tx in 0..w / 8 {
+                let Some(tile) = obj_tile(&obj, one_dimensional, tx, ty) else {
+                    continue;
+                };
+                let x = if obj.h_flip { w - 8 - tx * 8 } else { tx * 8 };
+                let y = if obj.v_flip { h - 8 - ty * 8 } else { ty * 8 };
+                self.draw_tile(&mut image, x, y, tile, depth, palette, obj.h_flip, obj.v_flip);
+            }
+        }
+        image
+    }
+
+    /// The BGR555 color of `index` in `palette`.
+    pub fn color(&self, palette: Palette, depth: ColorDepth, index: u8) -> u16 {
+        let (base, bank) = match palette {
+            Palette::Bg(bank) => (0, bank),
+            Palette::Obj(bank) => (OBJ_PALETTE, bank),
+        };
+        let entry = match depth {
+            ColorDepth::Bpp4 => (bank as usize & 0xF) * 16 + index as usize,
+            ColorDepth::Bpp8 => index as usize,
+        };
+        self.mem.palette_color(base + entry)
+    }
+
+    fn vram16(&self, offset: usize) -> u16 { self.mem.vram16(offset) }
+
+    #[allow(clippy::too_many_arguments)]
+    fn draw_tile(
+        &self,
+        image: &mut Image,
+        x: usize,
+        y: usize,
+        offset: usize,
+        depth: ColorDepth,
+        palette: Palette,
+        h_flip: bool,
+        v_flip: bool,
+    ) {
+        let indices = self.tile_indices(offset, depth);
+        for py in 0..8 {
+            for px in 0..8 {
+                let index = indices[py * 8 + px];
+                if index == 0 {
+                    continue;
+                }
+                let dx = if h_flip { 7 - px } else { px };
+                let dy = if v_flip { 7 - py } else { py };
+                let at = ((y + dy) * image.width + x + dx) * 4;
+                image.rgba[at..at + 4].copy_from_slice(&bgr555_to_rgba8888(self.color(palette, depth, index)));
+            }
+        }
+    }
+}
+
+fn blank(width: usize, height: usize) -> Image { Image { width, height, rgba: vec![0; width * height * 4] } }
+
+// VRAM offsets of the screen (map) and character (tile) blocks.
+fn bg_bases(bgcnt: u16) -> (usize, usize) {
+    (((bgcnt >> 8) & 0x1F) as usize * 0x800, ((bgcnt >> 2) & 3) as usize * 0x4000)
+}
+
+// VRAM offset of the sprite tile at (tx, ty), laid out as the renderer
+// does.
+fn obj_tile(obj: &ObjAttr, one_dimensional: bool, tx: usize, ty: usize) -> Option<usize> {
+    let base = super::OBJ_VRAM_START_MODE012;
+    let addr = Ppu::obj_tile_row_addr(base, one_dimensional, obj.color_256, obj.tile, obj.size().0, tx, ty, 0)?;
+    Some(OBJ_VRAM + (addr - base) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mem() -> Mem {
+        let mut mem = Mem::new();
+        for i in 0..512u16 {
+            mem.palette[i as usize * 2..][..2].copy_from_slice(&i.to_le_bytes());
+        }
+        mem
+    }
+
+    fn pixel(image: &Image, x: usize, y: usize) -> [u8; 4] {
+        let at = (y * image.width + x) * 4;
+        image.rgba[at..at + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn decodes_4bpp_and_8bpp_tiles() {
+        let mut mem = mem();
+        // Row 0 of a 4bpp tile: pixels 1, 2, 0, ...
+        mem.vram[0] = 0x21;
+        mem.vram[64..128].fill(5);
+        let gfx = Gfx::new(&mem);
+
+        assert_eq!(gfx.tile_indices(0, ColorDepth::Bpp4)[..3], [1, 2, 0]);
+        let tile = gfx.tile(0, ColorDepth::Bpp4, Palette::Bg(1));
+        assert_eq!((tile.width, tile.height), (8, 8));
+        assert_eq!(pixel(&tile, 0, 0), bgr555_to_rgba8888(17));
+        assert_eq!(pixel(&tile, 2, 0), [0; 4]);
+        assert_eq!(pixel(&gfx.tile(64, ColorDepth::Bpp8, Palette::Bg(0)), 7, 7), bgr555_to_rgba8888(5));
+
+        assert_eq!(gfx.tiles(0, 0x200, ColorDepth::Bpp8).count(), 8);
+        let sheet = gfx.tile_sheet(0, 5, 4, ColorDepth::Bpp4, Palette::Bg(0));
+        assert_eq!((sheet.width, sheet.height), (32, 16));
+    }
+
+    #[test]
+    fn text_map_applies_flips_palettes_and_screen_blocks() {
+        let mut mem = mem();
+        mem.vram[32] = 0x01; // Tile 1, pixel (0, 0) = 1.
+        // Screen block 1: entry (0, 0) is tile 1, flipped both ways, bank 2.
+        mem.vram[0x800..0x802].copy_from_slice(&(1 | (3 << 10) | (2 << 12) as u16).to_le_bytes());
+        // Second block of a 512x256 map, at x 256.
+        mem.vram[0x1000..0x1002].copy_from_slice(&1u16.to_le_bytes());
+        let gfx = Gfx::new(&mem);
+
+        let map = gfx.text_map((1 << 14) | (1 << 8));
+        assert_eq!((map.width, map.height), (512, 256));
+        assert_eq!(pixel(&map, 7, 7), bgr555_to_rgba8888(33));
+        assert_eq!(pixel(&map, 0, 0), [0; 4]);
+        assert_eq!(pixel(&map, 256, 0), bgr555_to_rgba8888(1));
+    }
+
+    #[test]
+    fn obj_uses_sprite_tile_mapping() {
+        let mut mem = mem();
+        // Sprite 0: 16x16, 4bpp, tile 2, palette bank 3.
+        mem.oam[..6].copy_from_slice(&[0, 0, 0, 1 << 6, 2, 3 << 4]);
+        // Tile 3 (right of tile 2 in both mappings) and the tile below:
+        // 2 in 1D mapping, 32 in 2D mapping.
+        mem.vram[OBJ_VRAM + 3 * 32] = 0x04;
+        mem.vram[OBJ_VRAM + 4 * 32] = 0x05;
+        mem.vram[OBJ_VRAM + 34 * 32] = 0x06;
+        let gfx = Gfx::new(&mem);
+        let color = |index| bgr555_to_rgba8888(gfx.color(Palette::Obj(3), ColorDepth::Bpp4, index));
+
+        let obj = gfx.obj(0, true);
+        assert_eq!((obj.width, obj.height), (16, 16));
+        assert_eq!(pixel(&obj, 8, 0), color(4));
+        assert_eq!(pixel(&obj, 0, 8), color(5));
+        assert_eq!(pixel(&gfx.obj(0, false), 0, 8), color(6));
+    }
+}
@@ -0,0 +1,53 @@
+//! Per-pixel compositor metadata for debugging overlays.
+//!
+//! While enabled with [`Ppu::set_pixel_info`](super::Ppu::set_pixel_info),
+//! the renderer records next to each framebuffer pixel the layer that won
+//! it, that layer's priority and whether a color effect changed it, so a
+//! frontend can show false-color views of the frame.
+
+/// The layer that drew a pixel.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PixelSource {
+    #[default]
+    Backdrop,
+    /// BG 0-3.
+    Bg(u8),
+    Obj,
+}
+
+impl PixelSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            PixelSource::Backdrop => "Backdrop",
+            PixelSource::Bg(0) => "BG0",
+            PixelSource::Bg(1) => "BG1",
+            PixelSource::Bg(2) => "BG2",
+            PixelSource::Bg(_) => "BG3",
+            PixelSource::Obj => "OBJ",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PixelInfo {
+    pub source: PixelSource,
+    /// 0 (front) to 3, or 4 for the backdrop.
+    pub priority: u8,
+    /// Alpha blending, a semi-transparent OBJ or a brightness change
+    /// applied to the pixel.
+    pub blended: bool,
+}
+
+impl PixelInfo {
+    pub const BACKDROP: PixelInfo = PixelInfo { source: PixelSource::Backdrop, priority: 4, blended: false };
+
+    pub(crate) fn bg(bg_num: usize, priority: u8) -> Self {
+        PixelInfo { source: PixelSource::Bg(bg_num as u8), priority, blended: false }
+    }
+
+    pub(crate) fn obj(priority: u8) -> Self { PixelInfo { source: PixelSource::Obj, priority, blended: false } }
+}
+
+impl Default for PixelInfo {
+    fn default() -> Self { Self::BACKDROP }
+}
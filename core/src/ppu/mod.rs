@@ -5,6 +5,16 @@
 //! It defines the PPU's state, memory-mapped registers, and rendering pipeline.
 //! The acceptance tests serve as a scaffold for implementing the PPU's behavior step-by-step.
 
+use crate::state::impl_savestate;
+use std::ops::Range;
+
+pub mod decode;
+pub mod inspect;
+pub mod obj;
+
+pub use inspect::{PixelInfo, PixelSource};
+pub use obj::{ObjAffine, ObjAttr, ObjMode, Oam};
+
 // Constants for PPU memory-mapped I/O registers.
 // These are defined in hexadecimal format and represent the memory addresses
 // that the CPU uses to interact with the PPU.
@@ -60,12 +70,51 @@ pub struct Ppu {
     framebuffer: Vec<u16>,
     cycles: usize,
     vcount: u8,
+    // Screen lines the render in progress draws; the rest of the
+    // framebuffer is left as it is.
+    lines: Range<usize>,
+    // Per-pixel layer stacks for mode 0, kept between renders.
+    layers: Vec<Vec<PixelLayer>>,
+    // OAM as of the start of the render in progress.
+    oam: Oam,
+    // Per line, one bit for each sprite that fits in the OBJ cycle budget.
+    obj_lines: Vec<u128>,
+    // Pixels covered by the OBJ window, for the lines being rendered.
+    obj_window: Vec<bool>,
+    // Scratch buffers the modes render into, kept so that rendering does
+    // not allocate.
+    window_regions: Vec<u8>,
+    scratch: Vec<u16>,
+    // BG2 and BG3 parameters as of the start of the render in progress.
+    affine: [AffineBg; 2],
+    // What drew each pixel, while a debugger asks for it.
+    pixel_info: Option<Vec<PixelInfo>>,
+    // Layers a debugger lets through, on top of DISPCNT; see `set_layer_mask`.
+    layer_mask: u8,
 }
 
+impl_savestate!(Ppu { dispcnt, dispstat, palette, framebuffer, cycles, vcount });
+
+/// `set_layer_mask` bits: bit n is BGn, then OBJ.
+pub const LAYER_OBJ: u8 = 1 << 4;
+pub const LAYER_ALL: u8 = 0x1F;
+
 const SCREEN_W: usize = 240;
 const SCREEN_H: usize = 160;
 const FRAME_PIXELS: usize = SCREEN_W * SCREEN_H;
 
+// An affine BG's parameters and its reference point on the first line of a
+// render, fixed point with 8 fraction bits; each line below starts PB/PD on.
+#[derive(Clone, Copy, Default)]
+struct AffineBg {
+    pa: i32,
+    pb: i32,
+    pc: i32,
+    pd: i32,
+    x: i32,
+    y: i32,
+}
+
 #[derive(Clone)]
 struct PixelLayer {
     color: u16,
@@ -75,6 +124,12 @@ struct PixelLayer {
     is_backdrop: bool,
     is_semi_transparent: bool,
 }
+
+impl PixelLayer {
+    fn info(&self) -> PixelInfo {
+        if self.is_obj { PixelInfo::obj(self.priority) } else { PixelInfo::bg(self.layer, self.priority) }
+    }
+}
 const DISPCNT_FORCED_BLANK: u16 = 1 << 7;
 const DISPCNT_BG0_ENABLE: u16 = 1 << 8;
 const DISPCNT_BG1_ENABLE: u16 = 1 << 9;
@@ -100,6 +155,10 @@ const CYCLES_VISIBLE: usize = 960;
 const CYCLES_HBLANK: usize = 272;
 const SCANLINES_VISIBLE: usize = 160;
 const SCANLINES_PER_FRAME: usize = 228;
+// OBJ rendering cycles available per line, fewer when DISPCNT bit 5 frees
+// OAM during HBlank.
+const OBJ_CYCLES_PER_LINE: usize = 1210;
+const OBJ_CYCLES_HBLANK_FREE: usize = 954;
 
 impl Default for Ppu {
     fn default() -> Self {
@@ -110,6 +169,16 @@ impl Default for Ppu {
             framebuffer: vec![0u16; FRAME_PIXELS],
             cycles: 0,
             vcount: 0,
+            lines: 0..SCREEN_H,
+            layers: vec![Vec::new(); FRAME_PIXELS],
+            oam: Oam::default(),
+            obj_lines: vec![0; SCREEN_H],
+            obj_window: vec![false; FRAME_PIXELS],
+            window_regions: vec![3; FRAME_PIXELS],
+            scratch: vec![0; FRAME_PIXELS],
+            affine: [AffineBg::default(); 2],
+            pixel_info: None,
+            layer_mask: LAYER_ALL,
         }
     }
 }
@@ -133,10 +202,40 @@ impl Ppu {
             self.palette[index] = color;
         }
     }
+
+    /// Sprite attributes used by the last render.
+    pub fn oam(&self) -> &Oam {
+        &self.oam
+    }
+
     pub fn framebuffer(&self) -> &[u16] {
         &self.framebuffer
     }
 
+    /// Records the layer, priority and blending behind each pixel from the
+    /// next render on; off by default, as it costs a little per pixel.
+    pub fn set_pixel_info(&mut self, enabled: bool) {
+        if enabled != self.pixel_info.is_some() {
+            self.pixel_info = enabled.then(|| vec![PixelInfo::BACKDROP; FRAME_PIXELS]);
+        }
+    }
+
+    /// What drew each framebuffer pixel, when enabled with `set_pixel_info`.
+    pub fn pixel_info(&self) -> Option<&[PixelInfo]> {
+        self.pixel_info.as_deref()
+    }
+
+    /// Hides the layers whose bit is clear, whatever DISPCNT says, from the
+    /// next render on. For isolating a layer while debugging; the OBJ window
+    /// still applies with OBJ hidden, and the mask is not saved in states.
+    pub fn set_layer_mask(&mut self, mask: u8) {
+        self.layer_mask = mask & LAYER_ALL;
+    }
+
+    pub fn layer_mask(&self) -> u8 {
+        self.layer_mask
+    }
+
     pub fn cycles_until_vblank(&self) -> usize {
         CYCLES_PER_SCANLINE * SCANLINES_VISIBLE
     }
@@ -268,12 +367,21 @@ impl Ppu {
     }
 
     pub fn render_frame_with_bus<B: crate::bus::BusAccess>(&mut self, bus: &mut B) {
+        self.render_lines_with_bus(bus, 0..SCREEN_H);
+    }
+
+    /// Renders only `lines` of the frame with the current register and
+    /// memory contents, for drawing each scanline as it is reached.
+    pub fn render_lines_with_bus<B: crate::bus::BusAccess>(&mut self, bus: &mut B, lines: Range<usize>) {
+        self.lines = lines.start.min(SCREEN_H)..lines.end.min(SCREEN_H);
+        let pixels = self.line_pixels();
         bus.set_ppu_rendering(true);
+        if let Some(info) = &mut self.pixel_info {
+            info[pixels.clone()].fill(PixelInfo::BACKDROP);
+        }
 
         if (self.dispcnt & DISPCNT_FORCED_BLANK) != 0 {
-            for p in self.framebuffer.iter_mut() {
-                *p = 0;
-            }
+            self.framebuffer[pixels].fill(0);
             bus.set_ppu_rendering(false);
             return;
         }
@@ -281,10 +389,11 @@ impl Ppu {
         let lo = bus.read8(REG_DISPCNT) as u16;
         let hi = bus.read8(REG_DISPCNT + 1) as u16;
         self.dispcnt = lo | (hi << 8);
+        self.oam.load(bus);
+        self.evaluate_objs(bus);
+        self.load_affine(bus);
 
-        for p in self.framebuffer.iter_mut() {
-            *p = 0;
-        }
+        self.framebuffer[pixels].fill(0);
 
         let mode = self.dispcnt & DISPCNT_MODE_MASK;
         match mode {
@@ -300,16 +409,54 @@ impl Ppu {
         bus.set_ppu_rendering(false);
     }
 
+    // Reads the affine BG parameters once per render. The reference point is
+    // the bus's internal one, which the emulator latches at VBlank and moves
+    // down after each line, so mid-frame writes to BGxX/BGxY take effect.
+    fn load_affine<B: crate::bus::BusAccess>(&mut self, bus: &mut B) {
+        for bg_num in 2..4 {
+            let base = ((bg_num - 2) * 0x10) as u32;
+            let param = |bus: &mut B, addr: u32| bus.read16(addr + base) as i16 as i32;
+            let reference = |bus: &mut B, addr: u32| ((bus.read32(addr + base) as i32) << 4) >> 4;
+            let (x, y) = match bus.affine_ref(bg_num) {
+                Some(point) => point,
+                None => (reference(bus, REG_BG2X), reference(bus, REG_BG2Y)),
+            };
+            self.affine[bg_num - 2] = AffineBg {
+                pa: param(bus, REG_BG2PA),
+                pb: param(bus, REG_BG2PB),
+                pc: param(bus, REG_BG2PC),
+                pd: param(bus, REG_BG2PD),
+                x,
+                y,
+            };
+        }
+    }
+
+    // Notes the BG that drew pixel `idx`, or the backdrop for `None`.
+    fn record_bg_pixel(&mut self, idx: usize, bg_num: Option<usize>, priority: u8) {
+        if let Some(info) = &mut self.pixel_info {
+            info[idx] = bg_num.map_or(PixelInfo::BACKDROP, |bg| PixelInfo::bg(bg, priority));
+        }
+    }
+
+    // Framebuffer indices of the lines being rendered.
+    fn line_pixels(&self) -> Range<usize> {
+        self.lines.start * SCREEN_W..self.lines.end * SCREEN_W
+    }
+
     fn render_mode0<B: crate::bus::BusAccess>(&mut self, bus: &mut B) {
         let backdrop = self.read_backdrop_color(bus);
         let mosaic = self.read_mosaic(bus);
-        let obj_window_mask = self.build_obj_window_mask(bus);
-        let mut layer_buffer: Vec<Vec<PixelLayer>> = vec![vec![]; FRAME_PIXELS];
+        let mut layer_buffer = std::mem::take(&mut self.layers);
+        layer_buffer.resize_with(FRAME_PIXELS, Vec::new);
+        layer_buffer[self.line_pixels()].iter_mut().for_each(Vec::clear);
+        let mut window_regions = std::mem::take(&mut self.window_regions);
 
-        for y in 0..SCREEN_H {
+        for y in self.lines.clone() {
             for x in 0..SCREEN_W {
-                let window_region = self.get_window_region(bus, x, y, &obj_window_mask);
+                let window_region = self.get_window_region(bus, x, y);
                 let idx = y * SCREEN_W + x;
+                window_regions[idx] = window_region;
 
                 for bg_num in 0..4 {
                     if !self.is_bg_enabled(bg_num) {
@@ -349,10 +496,10 @@ impl Ppu {
 
         {
             let mut fb = layer_buffer.as_mut_slice();
-            self.render_objs_with_windows_layers(bus, fb, &obj_window_mask);
+            self.render_objs_with_windows_layers(bus, fb);
         }
 
-        for layer in layer_buffer.iter_mut().take(FRAME_PIXELS) {
+        for layer in layer_buffer[self.line_pixels()].iter_mut() {
             layer.sort_by(|a, b| {
                 a.priority.cmp(&b.priority).then_with(|| {
                     if a.is_obj && !b.is_obj {
@@ -367,27 +514,35 @@ impl Ppu {
         }
 
 
-        for y in 0..SCREEN_H {
+        for y in self.lines.clone() {
             for x in 0..SCREEN_W {
                 let idx = y * SCREEN_W + x;
                 let top = layer_buffer[idx].first().cloned();
                 let second = layer_buffer[idx].get(1).cloned();
-                self.framebuffer[idx] = self.combine_pixel_layers(bus, top, second, backdrop);
+                let effects = self.effects_enabled_in_window(bus, window_regions[idx]);
+                let info = top.as_ref().map_or(PixelInfo::BACKDROP, |t| t.info());
+                let (color, blended) = self.combine_pixel_layers(bus, top, second, backdrop, effects);
+                self.framebuffer[idx] = color;
+                if let Some(pixel_info) = &mut self.pixel_info {
+                    pixel_info[idx] = PixelInfo { blended, ..info };
+                }
             }
         }
+        self.layers = layer_buffer;
+        self.window_regions = window_regions;
     }
 
     fn render_mode1<B: crate::bus::BusAccess>(&mut self, bus: &mut B) {
         let backdrop = self.read_backdrop_color(bus);
         let mosaic = self.read_mosaic(bus);
-        let obj_window_mask = self.build_obj_window_mask(bus);
-        let mut temp_buffer = vec![0u16; FRAME_PIXELS];
+        let mut temp_buffer = std::mem::take(&mut self.scratch);
 
-        for y in 0..SCREEN_H {
+        for y in self.lines.clone() {
             for x in 0..SCREEN_W {
-                let window_region = self.get_window_region(bus, x, y, &obj_window_mask);
+                let window_region = self.get_window_region(bus, x, y);
                 let mut pixel = backdrop;
                 let mut priority = 4u8;
+                let mut source = None;
 
                 for bg_num in 0..3 {
                     if !self.is_bg_enabled(bg_num) {
@@ -423,31 +578,37 @@ impl Ppu {
                     if let Some(p) = p {
                         pixel = p;
                         priority = bg_priority;
+                        source = Some(bg_num);
                     }
                 }
 
                 temp_buffer[y * SCREEN_W + x] = pixel;
+                self.record_bg_pixel(y * SCREEN_W + x, source, priority);
             }
         }
 
         {
             let mut fb = temp_buffer.as_mut_slice();
-            self.render_objs_with_windows(bus, fb, &obj_window_mask);
+            let mut info = self.pixel_info.take();
+            self.render_objs_with_windows(bus, fb, info.as_deref_mut());
+            self.pixel_info = info;
         }
-        self.framebuffer.copy_from_slice(&temp_buffer);
+        let pixels = self.line_pixels();
+        self.framebuffer[pixels.clone()].copy_from_slice(&temp_buffer[pixels]);
+        self.scratch = temp_buffer;
     }
 
     fn render_mode2<B: crate::bus::BusAccess>(&mut self, bus: &mut B) {
         let backdrop = self.read_backdrop_color(bus);
         let mosaic = self.read_mosaic(bus);
-        let obj_window_mask = self.build_obj_window_mask(bus);
-        let mut temp_buffer = vec![0u16; FRAME_PIXELS];
+        let mut temp_buffer = std::mem::take(&mut self.scratch);
 
-        for y in 0..SCREEN_H {
+        for y in self.lines.clone() {
             for x in 0..SCREEN_W {
-                let window_region = self.get_window_region(bus, x, y, &obj_window_mask);
+                let window_region = self.get_window_region(bus, x, y);
                 let mut pixel = backdrop;
                 let mut priority = 4u8;
+                let mut source = None;
 
                 for bg_num in 2..4 {
                     if !self.is_bg_enabled(bg_num) {
@@ -477,31 +638,39 @@ impl Ppu {
                     if let Some(p) = self.render_affine_bg_pixel(bus, bg_num, src_x, src_y) {
                         pixel = p;
                         priority = bg_priority;
+                        source = Some(bg_num);
                     }
                 }
 
                 temp_buffer[y * SCREEN_W + x] = pixel;
+                self.record_bg_pixel(y * SCREEN_W + x, source, priority);
             }
         }
 
         {
             let mut fb = temp_buffer.as_mut_slice();
-            self.render_objs_with_windows(bus, fb, &obj_window_mask);
+            let mut info = self.pixel_info.take();
+            self.render_objs_with_windows(bus, fb, info.as_deref_mut());
+            self.pixel_info = info;
         }
-        self.framebuffer.copy_from_slice(&temp_buffer);
+        let pixels = self.line_pixels();
+        self.framebuffer[pixels.clone()].copy_from_slice(&temp_buffer[pixels]);
+        self.scratch = temp_buffer;
     }
 
     fn render_mode3<B: crate::bus::BusAccess>(&mut self, bus: &mut B) {
         if !self.is_bg_enabled(2) {
             return;
         }
+        let priority = (self.read_bgcnt(bus, 2) & 0x3) as u8;
 
-        for y in 0..SCREEN_H {
+        for y in self.lines.clone() {
             for x in 0..SCREEN_W {
                 let addr = VRAM_START + ((y * SCREEN_W + x) * 2) as u32;
                 let lo = bus.read8(addr) as u16;
                 let hi = bus.read8(addr + 1) as u16;
                 self.framebuffer[y * SCREEN_W + x] = lo | (hi << 8);
+                self.record_bg_pixel(y * SCREEN_W + x, Some(2), priority);
             }
         }
         self.render_objs_direct(bus);
@@ -511,11 +680,12 @@ impl Ppu {
         if !self.is_bg_enabled(2) {
             return;
         }
+        let priority = (self.read_bgcnt(bus, 2) & 0x3) as u8;
 
         let frame_select = (self.dispcnt >> 4) & 1;
         let frame_base = if frame_select == 0 { 0 } else { 0x0A000 };
 
-        for y in 0..SCREEN_H {
+        for y in self.lines.clone() {
             for x in 0..SCREEN_W {
                 let addr = VRAM_START + frame_base + ((y * SCREEN_W + x) as u32);
                 let palette_idx = bus.read8(addr) as usize;
@@ -527,6 +697,7 @@ impl Ppu {
                 let lo = bus.read8(pal_addr) as u16;
                 let hi = bus.read8(pal_addr + 1) as u16;
                 self.framebuffer[y * SCREEN_W + x] = lo | (hi << 8);
+                self.record_bg_pixel(y * SCREEN_W + x, Some(2), priority);
             }
         }
         self.render_objs_direct(bus);
@@ -536,19 +707,21 @@ impl Ppu {
         if !self.is_bg_enabled(2) {
             return;
         }
+        let priority = (self.read_bgcnt(bus, 2) & 0x3) as u8;
 
         let frame_select = (self.dispcnt >> 4) & 1;
         let frame_base = if frame_select == 0 { 0 } else { 0x0A000 };
         const MODE5_W: usize = 160;
         const MODE5_H: usize = 128;
 
-        for y in 0..MODE5_H {
+        for y in self.lines.start..self.lines.end.min(MODE5_H) {
             for x in 0..MODE5_W {
                 let addr = VRAM_START + frame_base + ((y * MODE5_W + x) * 2) as u32;
                 let lo = bus.read8(addr) as u16;
                 let hi = bus.read8(addr + 1) as u16;
                 if y < SCREEN_H && x < SCREEN_W {
                     self.framebuffer[y * SCREEN_W + x] = lo | (hi << 8);
+                    self.record_bg_pixel(y * SCREEN_W + x, Some(2), priority);
                 }
             }
         }
@@ -556,7 +729,7 @@ impl Ppu {
     }
 
     fn render_objs<B: crate::bus::BusAccess>(&self, bus: &mut B, framebuffer: &mut [u16]) {
-        if (self.dispcnt & DISPCNT_OBJ_ENABLE) == 0 {
+        if !self.is_obj_enabled() {
             return;
         }
         let dispcnt = self.dispcnt;
@@ -583,9 +756,9 @@ impl Ppu {
         &self,
         bus: &mut B,
         framebuffer: &mut [u16],
-        obj_window_mask: &[bool],
+        pixel_info: Option<&mut [PixelInfo]>,
     ) {
-        if (self.dispcnt & DISPCNT_OBJ_ENABLE) == 0 {
+        if !self.is_obj_enabled() {
             return;
         }
         let dispcnt = self.dispcnt;
@@ -600,12 +773,12 @@ impl Ppu {
         self.render_objs_internal_with_windows(
             bus,
             framebuffer,
+            pixel_info,
             dispcnt,
             mode,
             mosaic,
             obj_vram_base,
             one_dimensional,
-            obj_window_mask,
         );
     }
 
@@ -614,76 +787,30 @@ impl Ppu {
         &self,
         bus: &mut B,
         framebuffer: &mut [u16],
+        mut pixel_info: Option<&mut [PixelInfo]>,
         dispcnt: u16,
         mode: u16,
         mosaic: u16,
         obj_vram_base: u32,
         one_dimensional: bool,
-        obj_window_mask: &[bool],
     ) {
-        for obj_num in (0..128).rev() {
-            let oam_addr = OAM_START + (obj_num * 8) as u32;
-            let attr0_lo = bus.read8(oam_addr) as u16;
-            let attr0_hi = bus.read8(oam_addr + 1) as u16;
-            let attr0 = attr0_lo | (attr0_hi << 8);
-            let attr1_lo = bus.read8(oam_addr + 2) as u16;
-            let attr1_hi = bus.read8(oam_addr + 3) as u16;
-            let attr1 = attr1_lo | (attr1_hi << 8);
-            let attr2_lo = bus.read8(oam_addr + 4) as u16;
-            let attr2_hi = bus.read8(oam_addr + 5) as u16;
-            let attr2 = attr2_lo | (attr2_hi << 8);
-
-            let y = (attr0 & 0xFF) as usize;
-            let x = (attr1 & 0x1FF) as usize;
-            let rotation_scaling = (attr0 >> 8) & 1 != 0;
-            let obj_disable = !rotation_scaling && ((attr0 >> 9) & 1 != 0);
-            let obj_mode = (attr0 >> 10) & 0x3;
-            let obj_mosaic = (attr0 >> 12) & 1 != 0;
-            let is_256_color = (attr0 >> 13) & 1 != 0;
-            let shape = (attr0 >> 14) & 0x3;
-            let size = (attr1 >> 14) & 0x3;
-            let tile_num = attr2 & 0x3FF;
-            let priority = ((attr2 >> 10) & 0x3) as u8;
-            let palette_num = (attr2 >> 12) & 0xF;
-
-            if obj_disable || obj_mode == 3 {
-                continue;
-            }
-
-            if obj_mode == 2 {
+        for obj_num in (0..obj::OBJ_COUNT).rev() {
+            let obj = &self.oam.objs[obj_num];
+            if !obj.is_shown() || obj.mode == ObjMode::Window {
                 continue;
             }
 
-            let (obj_w, obj_h) = self.get_obj_size(shape, size);
-            let display_w = if rotation_scaling && ((attr0 >> 9) & 1 != 0) {
-                obj_w * 2
-            } else {
-                obj_w
-            };
-            let display_h = if rotation_scaling && ((attr0 >> 9) & 1 != 0) {
-                obj_h * 2
-            } else {
-                obj_h
-            };
-
-            let screen_y = if y >= 160 { y.wrapping_sub(256) } else { y };
-            let screen_x = if x >= 240 { x.wrapping_sub(512) } else { x };
+            let (display_w, display_h) = obj.display_size();
+            let (screen_x, screen_y) = obj.screen_pos();
 
             for py in 0..display_h {
                 let fy = screen_y.wrapping_add(py);
-                if fy >= SCREEN_H {
+                if !self.obj_on_line(obj_num, fy) {
                     continue;
                 }
 
-                let src_y = if obj_mosaic {
-                    self.apply_mosaic_y(fy, mosaic)
-                } else {
-                    fy
-                };
-                let src_y = src_y.wrapping_sub(screen_y);
-                if src_y >= display_h {
-                    continue;
-                }
+                // OBJ mosaic blocks start at the sprite's top-left corner.
+                let src_y = if obj.mosaic { self.apply_obj_mosaic_y(py, mosaic) } else { py };
 
                 for px in 0..display_w {
                     let fx = screen_x.wrapping_add(px);
@@ -691,62 +818,27 @@ impl Ppu {
                         continue;
                     }
 
-                    let src_x = if obj_mosaic {
-                        self.apply_mosaic_x(fx, mosaic)
-                    } else {
-                        fx
-                    };
-                    let src_x = src_x.wrapping_sub(screen_x);
-                    if src_x >= display_w {
-                        continue;
-                    }
+                    let src_x = if obj.mosaic { self.apply_obj_mosaic_x(px, mosaic) } else { px };
 
-                    let window_region = self.get_window_region(bus, fx, fy, obj_window_mask);
+                    let window_region = self.get_window_region(bus, fx, fy);
                     if !self.is_layer_enabled_in_window(bus, window_region, 0, true) {
                         continue;
                     }
 
-                    let pixel = if rotation_scaling {
-                        let param_group = ((attr1 >> 9) & 0x1F) as usize;
-                        self.render_affine_obj_pixel(
-                            bus,
-                            obj_vram_base,
-                            one_dimensional,
-                            is_256_color,
-                            tile_num,
-                            palette_num,
-                            param_group,
-                            obj_w,
-                            obj_h,
-                            display_w,
-                            display_h,
-                            src_x,
-                            src_y,
-                        )
+                    let pixel = if obj.affine {
+                        self.render_affine_obj_pixel(bus, obj_vram_base, one_dimensional, obj, src_x, src_y)
                     } else {
-                        let h_flip = (attr1 >> 12) & 1 != 0;
-                        let v_flip = (attr1 >> 13) & 1 != 0;
-                        self.render_regular_obj_pixel(
-                            bus,
-                            obj_vram_base,
-                            one_dimensional,
-                            is_256_color,
-                            tile_num,
-                            palette_num,
-                            obj_w,
-                            obj_h,
-                            src_x,
-                            src_y,
-                            h_flip,
-                            v_flip,
-                        )
+                        self.render_regular_obj_pixel(bus, obj_vram_base, one_dimensional, obj, src_x, src_y)
                     };
 
                     if let Some(p) = pixel {
                         let idx = fy * SCREEN_W + fx;
-                        let bg_priority = self.get_bg_priority_at_safe(bus, fx, fy, mode, dispcnt);
-                        if priority < bg_priority || (priority == bg_priority && obj_num < 64) {
+                        let bg_priority = self.get_bg_priority_at_safe(bus, fx, fy, mode);
+                        if obj.priority < bg_priority || (obj.priority == bg_priority && obj_num < 64) {
                             framebuffer[idx] = p;
+                            if let Some(info) = pixel_info.as_deref_mut() {
+                                info[idx] = PixelInfo::obj(obj.priority);
+                            }
                         }
                     }
                 }
@@ -758,9 +850,8 @@ impl Ppu {
         &self,
         bus: &mut B,
         layer_buffer: &mut [Vec<PixelLayer>],
-        obj_window_mask: &[bool],
     ) {
-        if (self.dispcnt & DISPCNT_OBJ_ENABLE) == 0 {
+        if !self.is_obj_enabled() {
             return;
         }
         let dispcnt = self.dispcnt;
@@ -773,70 +864,24 @@ impl Ppu {
         };
         let one_dimensional = (dispcnt & DISPCNT_OBJ_VRAM_MAPPING) != 0;
 
-        for obj_num in (0..128).rev() {
-            let oam_addr = OAM_START + (obj_num * 8) as u32;
-            let attr0_lo = bus.read8(oam_addr) as u16;
-            let attr0_hi = bus.read8(oam_addr + 1) as u16;
-            let attr0 = attr0_lo | (attr0_hi << 8);
-            let attr1_lo = bus.read8(oam_addr + 2) as u16;
-            let attr1_hi = bus.read8(oam_addr + 3) as u16;
-            let attr1 = attr1_lo | (attr1_hi << 8);
-            let attr2_lo = bus.read8(oam_addr + 4) as u16;
-            let attr2_hi = bus.read8(oam_addr + 5) as u16;
-            let attr2 = attr2_lo | (attr2_hi << 8);
-
-            let y = (attr0 & 0xFF) as usize;
-            let x = (attr1 & 0x1FF) as usize;
-            let rotation_scaling = (attr0 >> 8) & 1 != 0;
-            let obj_disable = !rotation_scaling && ((attr0 >> 9) & 1 != 0);
-            let obj_mode = (attr0 >> 10) & 0x3;
-            let obj_mosaic = (attr0 >> 12) & 1 != 0;
-            let is_256_color = (attr0 >> 13) & 1 != 0;
-            let shape = (attr0 >> 14) & 0x3;
-            let size = (attr1 >> 14) & 0x3;
-            let tile_num = attr2 & 0x3FF;
-            let priority = ((attr2 >> 10) & 0x3) as u8;
-            let palette_num = (attr2 >> 12) & 0xF;
-            let is_semi_transparent = obj_mode == 1;
-
-            if obj_disable || obj_mode == 3 {
+        for obj_num in (0..obj::OBJ_COUNT).rev() {
+            let obj = &self.oam.objs[obj_num];
+            if !obj.is_shown() || obj.mode == ObjMode::Window {
                 continue;
             }
+            let is_semi_transparent = obj.mode == ObjMode::SemiTransparent;
 
-            if obj_mode == 2 {
-                continue;
-            }
-
-            let (obj_w, obj_h) = self.get_obj_size(shape, size);
-            let display_w = if rotation_scaling && ((attr0 >> 9) & 1 != 0) {
-                obj_w * 2
-            } else {
-                obj_w
-            };
-            let display_h = if rotation_scaling && ((attr0 >> 9) & 1 != 0) {
-                obj_h * 2
-            } else {
-                obj_h
-            };
-
-            let screen_y = if y >= 160 { y.wrapping_sub(256) } else { y };
-            let screen_x = if x >= 240 { x.wrapping_sub(512) } else { x };
+            let (display_w, display_h) = obj.display_size();
+            let (screen_x, screen_y) = obj.screen_pos();
 
             for py in 0..display_h {
                 let fy = screen_y.wrapping_add(py);
-                if fy >= SCREEN_H {
+                if !self.obj_on_line(obj_num, fy) {
                     continue;
                 }
 
-                let src_y = if obj_mosaic {
-                    self.apply_mosaic_y(fy, mosaic)
-                } else {
-                    fy
-                };
-                let src_y = src_y.wrapping_sub(screen_y);
-                if src_y >= display_h {
-                    continue;
-                }
+                // OBJ mosaic blocks start at the sprite's top-left corner.
+                let src_y = if obj.mosaic { self.apply_obj_mosaic_y(py, mosaic) } else { py };
 
                 for px in 0..display_w {
                     let fx = screen_x.wrapping_add(px);
@@ -844,64 +889,26 @@ impl Ppu {
                         continue;
                     }
 
-                    let src_x = if obj_mosaic {
-                        self.apply_mosaic_x(fx, mosaic)
-                    } else {
-                        fx
-                    };
-                    let src_x = src_x.wrapping_sub(screen_x);
-                    if src_x >= display_w {
-                        continue;
-                    }
+                    let src_x = if obj.mosaic { self.apply_obj_mosaic_x(px, mosaic) } else { px };
 
-                    let window_region = self.get_window_region(bus, fx, fy, obj_window_mask);
+                    let window_region = self.get_window_region(bus, fx, fy);
                     if !self.is_layer_enabled_in_window(bus, window_region, 0, true) {
                         continue;
                     }
 
-                    let pixel = if rotation_scaling {
-                        let param_group = ((attr1 >> 9) & 0x1F) as usize;
-                        self.render_affine_obj_pixel(
-                            bus,
-                            obj_vram_base,
-                            one_dimensional,
-                            is_256_color,
-                            tile_num,
-                            palette_num,
-                            param_group,
-                            obj_w,
-                            obj_h,
-                            display_w,
-                            display_h,
-                            src_x,
-                            src_y,
-                        )
+                    let pixel = if obj.affine {
+                        self.render_affine_obj_pixel(bus, obj_vram_base, one_dimensional, obj, src_x, src_y)
                     } else {
-                        let h_flip = (attr1 >> 12) & 1 != 0;
-                        let v_flip = (attr1 >> 13) & 1 != 0;
-                        self.render_regular_obj_pixel(
-                            bus,
-                            obj_vram_base,
-                            one_dimensional,
-                            is_256_color,
-                            tile_num,
-                            palette_num,
-                            obj_w,
-                            obj_h,
-                            src_x,
-                            src_y,
-                            h_flip,
-                            v_flip,
-                        )
+                        self.render_regular_obj_pixel(bus, obj_vram_base, one_dimensional, obj, src_x, src_y)
                     };
 
                     if let Some(p) = pixel {
                         let idx = fy * SCREEN_W + fx;
-                        let bg_priority = self.get_bg_priority_at_safe(bus, fx, fy, mode, dispcnt);
-                        if priority < bg_priority || (priority == bg_priority && obj_num < 64) {
+                        let bg_priority = self.get_bg_priority_at_safe(bus, fx, fy, mode);
+                        if obj.priority < bg_priority || (obj.priority == bg_priority && obj_num < 64) {
                             layer_buffer[idx].push(PixelLayer {
                                 color: p,
-                                priority,
+                                priority: obj.priority,
                                 layer: 0,
                                 is_obj: true,
                                 is_backdrop: false,
@@ -915,7 +922,7 @@ impl Ppu {
     }
 
     fn render_objs_direct<B: crate::bus::BusAccess>(&mut self, bus: &mut B) {
-        if (self.dispcnt & DISPCNT_OBJ_ENABLE) == 0 {
+        if !self.is_obj_enabled() {
             return;
         }
         let dispcnt = self.dispcnt;
@@ -947,69 +954,23 @@ impl Ppu {
         obj_vram_base: u32,
         one_dimensional: bool,
     ) {
-        for obj_num in (0..128).rev() {
-            let oam_addr = OAM_START + (obj_num * 8) as u32;
-            let attr0_lo = bus.read8(oam_addr) as u16;
-            let attr0_hi = bus.read8(oam_addr + 1) as u16;
-            let attr0 = attr0_lo | (attr0_hi << 8);
-            let attr1_lo = bus.read8(oam_addr + 2) as u16;
-            let attr1_hi = bus.read8(oam_addr + 3) as u16;
-            let attr1 = attr1_lo | (attr1_hi << 8);
-            let attr2_lo = bus.read8(oam_addr + 4) as u16;
-            let attr2_hi = bus.read8(oam_addr + 5) as u16;
-            let attr2 = attr2_lo | (attr2_hi << 8);
-
-            let y = (attr0 & 0xFF) as usize;
-            let x = (attr1 & 0x1FF) as usize;
-            let rotation_scaling = (attr0 >> 8) & 1 != 0;
-            let obj_disable = !rotation_scaling && ((attr0 >> 9) & 1 != 0);
-            let obj_mode = (attr0 >> 10) & 0x3;
-            let obj_mosaic = (attr0 >> 12) & 1 != 0;
-            let is_256_color = (attr0 >> 13) & 1 != 0;
-            let shape = (attr0 >> 14) & 0x3;
-            let size = (attr1 >> 14) & 0x3;
-            let tile_num = attr2 & 0x3FF;
-            let priority = ((attr2 >> 10) & 0x3) as u8;
-            let palette_num = (attr2 >> 12) & 0xF;
-
-            if obj_disable || obj_mode == 3 {
+        for obj_num in (0..obj::OBJ_COUNT).rev() {
+            let obj = &self.oam.objs[obj_num];
+            if !obj.is_shown() || obj.mode == ObjMode::Window {
                 continue;
             }
 
-            if obj_mode == 2 {
-                continue;
-            }
-
-            let (obj_w, obj_h) = self.get_obj_size(shape, size);
-            let display_w = if rotation_scaling && ((attr0 >> 9) & 1 != 0) {
-                obj_w * 2
-            } else {
-                obj_w
-            };
-            let display_h = if rotation_scaling && ((attr0 >> 9) & 1 != 0) {
-                obj_h * 2
-            } else {
-                obj_h
-            };
-
-            let screen_y = if y >= 160 { y.wrapping_sub(256) } else { y };
-            let screen_x = if x >= 240 { x.wrapping_sub(512) } else { x };
+            let (display_w, display_h) = obj.display_size();
+            let (screen_x, screen_y) = obj.screen_pos();
 
             for py in 0..display_h {
                 let fy = screen_y.wrapping_add(py);
-                if fy >= SCREEN_H {
+                if !self.obj_on_line(obj_num, fy) {
                     continue;
                 }
 
-                let src_y = if obj_mosaic {
-                    self.apply_mosaic_y(fy, mosaic)
-                } else {
-                    fy
-                };
-                let src_y = src_y.wrapping_sub(screen_y);
-                if src_y >= display_h {
-                    continue;
-                }
+                // OBJ mosaic blocks start at the sprite's top-left corner.
+                let src_y = if obj.mosaic { self.apply_obj_mosaic_y(py, mosaic) } else { py };
 
                 for px in 0..display_w {
                     let fx = screen_x.wrapping_add(px);
@@ -1017,57 +978,22 @@ impl Ppu {
                         continue;
                     }
 
-                    let src_x = if obj_mosaic {
-                        self.apply_mosaic_x(fx, mosaic)
-                    } else {
-                        fx
-                    };
-                    let src_x = src_x.wrapping_sub(screen_x);
-                    if src_x >= display_w {
-                        continue;
-                    }
+                    let src_x = if obj.mosaic { self.apply_obj_mosaic_x(px, mosaic) } else { px };
 
-                    let pixel = if rotation_scaling {
-                        let param_group = ((attr1 >> 9) & 0x1F) as usize;
-                        self.render_affine_obj_pixel(
-                            bus,
-                            obj_vram_base,
-                            one_dimensional,
-                            is_256_color,
-                            tile_num,
-                            palette_num,
-                            param_group,
-                            obj_w,
-                            obj_h,
-                            display_w,
-                            display_h,
-                            src_x,
-                            src_y,
-                        )
+                    let pixel = if obj.affine {
+                        self.render_affine_obj_pixel(bus, obj_vram_base, one_dimensional, obj, src_x, src_y)
                     } else {
-                        let h_flip = (attr1 >> 12) & 1 != 0;
-                        let v_flip = (attr1 >> 13) & 1 != 0;
-                        self.render_regular_obj_pixel(
-                            bus,
-                            obj_vram_base,
-                            one_dimensional,
-                            is_256_color,
-                            tile_num,
-                            palette_num,
-                            obj_w,
-                            obj_h,
-                            src_x,
-                            src_y,
-                            h_flip,
-                            v_flip,
-                        )
+                        self.render_regular_obj_pixel(bus, obj_vram_base, one_dimensional, obj, src_x, src_y)
                     };
 
                     if let Some(p) = pixel {
                         let idx = fy * SCREEN_W + fx;
-                        let bg_priority = self.get_bg_priority_at_safe(bus, fx, fy, mode, dispcnt);
-                        if priority < bg_priority || (priority == bg_priority && obj_num < 64) {
+                        let bg_priority = self.get_bg_priority_at_safe(bus, fx, fy, mode);
+                        if obj.priority < bg_priority || (obj.priority == bg_priority && obj_num < 64) {
                             self.framebuffer[idx] = p;
+                            if let Some(info) = &mut self.pixel_info {
+                                info[idx] = PixelInfo::obj(obj.priority);
+                            }
                         }
                     }
                 }
@@ -1086,69 +1012,23 @@ impl Ppu {
         obj_vram_base: u32,
         one_dimensional: bool,
     ) {
-        for obj_num in (0..128).rev() {
-            let oam_addr = OAM_START + (obj_num * 8) as u32;
-            let attr0_lo = bus.read8(oam_addr) as u16;
-            let attr0_hi = bus.read8(oam_addr + 1) as u16;
-            let attr0 = attr0_lo | (attr0_hi << 8);
-            let attr1_lo = bus.read8(oam_addr + 2) as u16;
-            let attr1_hi = bus.read8(oam_addr + 3) as u16;
-            let attr1 = attr1_lo | (attr1_hi << 8);
-            let attr2_lo = bus.read8(oam_addr + 4) as u16;
-            let attr2_hi = bus.read8(oam_addr + 5) as u16;
-            let attr2 = attr2_lo | (attr2_hi << 8);
-
-            let y = (attr0 & 0xFF) as usize;
-            let x = (attr1 & 0x1FF) as usize;
-            let rotation_scaling = (attr0 >> 8) & 1 != 0;
-            let obj_disable = !rotation_scaling && ((attr0 >> 9) & 1 != 0);
-            let obj_mode = (attr0 >> 10) & 0x3;
-            let obj_mosaic = (attr0 >> 12) & 1 != 0;
-            let is_256_color = (attr0 >> 13) & 1 != 0;
-            let shape = (attr0 >> 14) & 0x3;
-            let size = (attr1 >> 14) & 0x3;
-            let tile_num = attr2 & 0x3FF;
-            let priority = ((attr2 >> 10) & 0x3) as u8;
-            let palette_num = (attr2 >> 12) & 0xF;
-
-            if obj_disable || obj_mode == 3 {
-                continue;
-            }
-
-            if obj_mode == 2 {
+        for obj_num in (0..obj::OBJ_COUNT).rev() {
+            let obj = &self.oam.objs[obj_num];
+            if !obj.is_shown() || obj.mode == ObjMode::Window {
                 continue;
             }
 
-            let (obj_w, obj_h) = self.get_obj_size(shape, size);
-            let display_w = if rotation_scaling && ((attr0 >> 9) & 1 != 0) {
-                obj_w * 2
-            } else {
-                obj_w
-            };
-            let display_h = if rotation_scaling && ((attr0 >> 9) & 1 != 0) {
-                obj_h * 2
-            } else {
-                obj_h
-            };
-
-            let screen_y = if y >= 160 { y.wrapping_sub(256) } else { y };
-            let screen_x = if x >= 240 { x.wrapping_sub(512) } else { x };
+            let (display_w, display_h) = obj.display_size();
+            let (screen_x, screen_y) = obj.screen_pos();
 
             for py in 0..display_h {
                 let fy = screen_y.wrapping_add(py);
-                if fy >= SCREEN_H {
+                if !self.obj_on_line(obj_num, fy) {
                     continue;
                 }
 
-                let src_y = if obj_mosaic {
-                    self.apply_mosaic_y(fy, mosaic)
-                } else {
-                    fy
-                };
-                let src_y = src_y.wrapping_sub(screen_y);
-                if src_y >= display_h {
-                    continue;
-                }
+                // OBJ mosaic blocks start at the sprite's top-left corner.
+                let src_y = if obj.mosaic { self.apply_obj_mosaic_y(py, mosaic) } else { py };
 
                 for px in 0..display_w {
                     let fx = screen_x.wrapping_add(px);
@@ -1156,56 +1036,18 @@ impl Ppu {
                         continue;
                     }
 
-                    let src_x = if obj_mosaic {
-                        self.apply_mosaic_x(fx, mosaic)
-                    } else {
-                        fx
-                    };
-                    let src_x = src_x.wrapping_sub(screen_x);
-                    if src_x >= display_w {
-                        continue;
-                    }
+                    let src_x = if obj.mosaic { self.apply_obj_mosaic_x(px, mosaic) } else { px };
 
-                    let pixel = if rotation_scaling {
-                        let param_group = ((attr1 >> 9) & 0x1F) as usize;
-                        self.render_affine_obj_pixel(
-                            bus,
-                            obj_vram_base,
-                            one_dimensional,
-                            is_256_color,
-                            tile_num,
-                            palette_num,
-                            param_group,
-                            obj_w,
-                            obj_h,
-                            display_w,
-                            display_h,
-                            src_x,
-                            src_y,
-                        )
+                    let pixel = if obj.affine {
+                        self.render_affine_obj_pixel(bus, obj_vram_base, one_dimensional, obj, src_x, src_y)
                     } else {
-                        let h_flip = (attr1 >> 12) & 1 != 0;
-                        let v_flip = (attr1 >> 13) & 1 != 0;
-                        self.render_regular_obj_pixel(
-                            bus,
-                            obj_vram_base,
-                            one_dimensional,
-                            is_256_color,
-                            tile_num,
-                            palette_num,
-                            obj_w,
-                            obj_h,
-                            src_x,
-                            src_y,
-                            h_flip,
-                            v_flip,
-                        )
+                        self.render_regular_obj_pixel(bus, obj_vram_base, one_dimensional, obj, src_x, src_y)
                     };
 
                     if let Some(p) = pixel {
                         let idx = fy * SCREEN_W + fx;
-                        let bg_priority = self.get_bg_priority_at_safe(bus, fx, fy, mode, dispcnt);
-                        if priority < bg_priority || (priority == bg_priority && obj_num < 64) {
+                        let bg_priority = self.get_bg_priority_at_safe(bus, fx, fy, mode);
+                        if obj.priority < bg_priority || (obj.priority == bg_priority && obj_num < 64) {
                             framebuffer[idx] = p;
                         }
                     }
@@ -1214,75 +1056,46 @@ impl Ppu {
         }
     }
 
-    fn get_obj_size(&self, shape: u16, size: u16) -> (usize, usize) {
-        match (shape, size) {
-            (0, 0) => (8, 8),
-            (0, 1) => (16, 16),
-            (0, 2) => (32, 32),
-            (0, 3) => (64, 64),
-            (1, 0) => (16, 8),
-            (1, 1) => (32, 8),
-            (1, 2) => (32, 16),
-            (1, 3) => (64, 32),
-            (2, 0) => (8, 16),
-            (2, 1) => (8, 32),
-            (2, 2) => (16, 32),
-            (2, 3) => (32, 64),
-            _ => (8, 8),
-        }
-    }
-
-    #[allow(clippy::too_many_arguments)]
     fn render_regular_obj_pixel<B: crate::bus::BusAccess>(
         &self,
         bus: &mut B,
         obj_vram_base: u32,
         one_dimensional: bool,
-        is_256_color: bool,
-        tile_num: u16,
-        palette_num: u16,
-        obj_w: usize,
-        obj_h: usize,
+        obj: &ObjAttr,
         src_x: usize,
         src_y: usize,
-        h_flip: bool,
-        v_flip: bool,
     ) -> Option<u16> {
+        let (obj_w, obj_h) = obj.size();
         let tile_x = src_x / 8;
         let tile_y = src_y / 8;
         let pixel_x = src_x % 8;
         let pixel_y = src_y % 8;
 
-        let final_tile_x = if h_flip {
+        let final_tile_x = if obj.h_flip {
             (obj_w / 8) - 1 - tile_x
         } else {
             tile_x
         };
-        let final_tile_y = if v_flip {
+        let final_tile_y = if obj.v_flip {
             (obj_h / 8) - 1 - tile_y
         } else {
             tile_y
         };
-        let final_pixel_x = if h_flip { 7 - pixel_x } else { pixel_x };
-        let final_pixel_y = if v_flip { 7 - pixel_y } else { pixel_y };
-
-        let base_tile = tile_num as u32;
-        let tile_offset = if one_dimensional {
-            final_tile_y * (obj_w / 8) + final_tile_x
-        } else {
-            final_tile_y * 32 + final_tile_x
-        } as u32;
-
-        let actual_tile = if is_256_color {
-            base_tile + tile_offset * 2
-        } else {
-            base_tile + tile_offset
-        };
+        let final_pixel_x = if obj.h_flip { 7 - pixel_x } else { pixel_x };
+        let final_pixel_y = if obj.v_flip { 7 - pixel_y } else { pixel_y };
 
-        let tile_addr = obj_vram_base + actual_tile * (if is_256_color { 64 } else { 32 });
-        let row_addr = tile_addr + final_pixel_y as u32 * (if is_256_color { 8 } else { 4 });
-
-        if is_256_color {
+        let row_addr = Self::obj_tile_row_addr(
+            obj_vram_base,
+            one_dimensional,
+            obj.color_256,
+            obj.tile,
+            obj_w,
+            final_tile_x,
+            final_tile_y,
+            final_pixel_y,
+        )?;
+
+        if obj.color_256 {
             let pixel_addr = row_addr + final_pixel_x as u32;
             let palette_idx = bus.read8(pixel_addr) as usize;
             if palette_idx == 0 {
@@ -1303,49 +1116,63 @@ impl Ppu {
             if palette_idx == 0 {
                 return None;
             }
-            let pal_addr = OBJ_PALETTE_START + (palette_num as usize * 32 + palette_idx * 2) as u32;
+            let pal_addr = OBJ_PALETTE_START + (obj.palette as usize * 32 + palette_idx * 2) as u32;
             let lo = bus.read8(pal_addr) as u16;
             let hi = bus.read8(pal_addr + 1) as u16;
             Some(lo | (hi << 8))
         }
     }
 
+    /// Address of row `pixel_y` of the sprite tile at (`tile_x`, `tile_y`).
+    /// Tile numbers count 32-byte units from the start of OBJ VRAM whatever
+    /// the color depth, so a 256-color tile takes two. With 2D mapping the
+    /// sprite's tile rows are 32 units apart and bit 0 of a 256-color tile
+    /// number is ignored. Tiles below `obj_vram_base` overlap the bitmap in
+    /// modes 3-5 and are not displayed.
     #[allow(clippy::too_many_arguments)]
-    fn render_affine_obj_pixel<B: crate::bus::BusAccess>(
-        &self,
-        bus: &mut B,
+    fn obj_tile_row_addr(
         obj_vram_base: u32,
         one_dimensional: bool,
         is_256_color: bool,
         tile_num: u16,
-        palette_num: u16,
-        param_group: usize,
         obj_w: usize,
-        obj_h: usize,
-        display_w: usize,
-        display_h: usize,
+        tile_x: usize,
+        tile_y: usize,
+        pixel_y: usize,
+    ) -> Option<u32> {
+        let units = if is_256_color { 2 } else { 1 };
+        let tile = if one_dimensional {
+            tile_num as u32 + (tile_y * (obj_w / 8) + tile_x) as u32 * units
+        } else {
+            let base = if is_256_color { tile_num as u32 & !1 } else { tile_num as u32 };
+            base + tile_y as u32 * 32 + tile_x as u32 * units
+        };
+
+        let tile_addr = OBJ_VRAM_START_MODE012 + (tile & 0x3FF) * 32;
+        if tile_addr < obj_vram_base {
+            return None;
+        }
+        Some(tile_addr + pixel_y as u32 * units * 4)
+    }
+
+    fn render_affine_obj_pixel<B: crate::bus::BusAccess>(
+        &self,
+        bus: &mut B,
+        obj_vram_base: u32,
+        one_dimensional: bool,
+        obj: &ObjAttr,
         src_x: usize,
         src_y: usize,
     ) -> Option<u16> {
+        let (obj_w, obj_h) = obj.size();
+        let (display_w, display_h) = obj.display_size();
         let center_x = (obj_w / 2) as i32;
         let center_y = (obj_h / 2) as i32;
 
         let dx = src_x as i32 - (display_w / 2) as i32;
         let dy = src_y as i32 - (display_h / 2) as i32;
 
-        let param_base = OAM_START + (param_group * 32 + 6) as u32;
-        let pa_lo = bus.read8(param_base) as u16;
-        let pa_hi = bus.read8(param_base + 1) as u16;
-        let pa = (pa_lo | (pa_hi << 8)) as i16;
-        let pb_lo = bus.read8(param_base + 8) as u16;
-        let pb_hi = bus.read8(param_base + 9) as u16;
-        let pb = (pb_lo | (pb_hi << 8)) as i16;
-        let pc_lo = bus.read8(param_base + 16) as u16;
-        let pc_hi = bus.read8(param_base + 17) as u16;
-        let pc = (pc_lo | (pc_hi << 8)) as i16;
-        let pd_lo = bus.read8(param_base + 24) as u16;
-        let pd_hi = bus.read8(param_base + 25) as u16;
-        let pd = (pd_lo | (pd_hi << 8)) as i16;
+        let ObjAffine { pa, pb, pc, pd } = self.oam.affine[obj.affine_group as usize];
 
         let tex_x = center_x + ((pa as i32 * dx + pb as i32 * dy) >> 8);
         let tex_y = center_y + ((pc as i32 * dx + pd as i32 * dy) >> 8);
@@ -1359,23 +1186,18 @@ impl Ppu {
         let pixel_x = (tex_x as usize) % 8;
         let pixel_y = (tex_y as usize) % 8;
 
-        let base_tile = tile_num as u32;
-        let tile_offset = if one_dimensional {
-            tile_y * (obj_w / 8) + tile_x
-        } else {
-            tile_y * 32 + tile_x
-        } as u32;
-
-        let actual_tile = if is_256_color {
-            base_tile + tile_offset * 2
-        } else {
-            base_tile + tile_offset
-        };
-
-        let tile_addr = obj_vram_base + actual_tile * (if is_256_color { 64 } else { 32 });
-        let row_addr = tile_addr + pixel_y as u32 * (if is_256_color { 8 } else { 4 });
-
-        if is_256_color {
+        let row_addr = Self::obj_tile_row_addr(
+            obj_vram_base,
+            one_dimensional,
+            obj.color_256,
+            obj.tile,
+            obj_w,
+            tile_x,
+            tile_y,
+            pixel_y,
+        )?;
+
+        if obj.color_256 {
             let pixel_addr = row_addr + pixel_x as u32;
             let palette_idx = bus.read8(pixel_addr) as usize;
             if palette_idx == 0 {
@@ -1396,7 +1218,7 @@ impl Ppu {
             if palette_idx == 0 {
                 return None;
             }
-            let pal_addr = OBJ_PALETTE_START + (palette_num as usize * 32 + palette_idx * 2) as u32;
+            let pal_addr = OBJ_PALETTE_START + (obj.palette as usize * 32 + palette_idx * 2) as u32;
             let lo = bus.read8(pal_addr) as u16;
             let hi = bus.read8(pal_addr + 1) as u16;
             Some(lo | (hi << 8))
@@ -1409,15 +1231,13 @@ impl Ppu {
         x: usize,
         y: usize,
         mode: u16,
-        dispcnt: u16,
     ) -> u8 {
         let mut min_priority = 4u8;
 
         match mode {
             0 => {
                 for bg_num in 0..4 {
-                    let bit = 8 + bg_num;
-                    if (dispcnt >> bit) & 1 == 0 {
+                    if !self.is_bg_enabled(bg_num) {
                         continue;
                     }
                     if self.render_text_bg_pixel(bus, bg_num, x, y).is_some() {
@@ -1431,8 +1251,7 @@ impl Ppu {
             }
             1 => {
                 for bg_num in 0..3 {
-                    let bit = 8 + bg_num;
-                    if (dispcnt >> bit) & 1 == 0 {
+                    if !self.is_bg_enabled(bg_num) {
                         continue;
                     }
                     let has_pixel = if bg_num < 2 {
@@ -1451,8 +1270,7 @@ impl Ppu {
             }
             2 => {
                 for bg_num in 2..4 {
-                    let bit = 8 + bg_num;
-                    if (dispcnt >> bit) & 1 == 0 {
+                    if !self.is_bg_enabled(bg_num) {
                         continue;
                     }
                     if self.render_affine_bg_pixel(bus, bg_num, x, y).is_some() {
@@ -1472,7 +1290,11 @@ impl Ppu {
 
     fn is_bg_enabled(&self, bg_num: usize) -> bool {
         let bit = 8 + bg_num;
-        (self.dispcnt >> bit) & 1 != 0
+        (self.dispcnt >> bit) & 1 != 0 && self.layer_mask & (1 << bg_num) != 0
+    }
+
+    fn is_obj_enabled(&self) -> bool {
+        (self.dispcnt & DISPCNT_OBJ_ENABLE) != 0 && self.layer_mask & LAYER_OBJ != 0
     }
 
     fn read_backdrop_color<B: crate::bus::BusAccess>(&self, bus: &mut B) -> u16 {
@@ -1497,6 +1319,16 @@ impl Ppu {
         (y / v_size) * v_size
     }
 
+    fn apply_obj_mosaic_x(&self, x: usize, mosaic: u16) -> usize {
+        let h_size = (((mosaic >> 8) & 0xF) + 1) as usize;
+        (x / h_size) * h_size
+    }
+
+    fn apply_obj_mosaic_y(&self, y: usize, mosaic: u16) -> usize {
+        let v_size = (((mosaic >> 12) & 0xF) + 1) as usize;
+        (y / v_size) * v_size
+    }
+
     fn read_bgcnt<B: crate::bus::BusAccess>(&self, bus: &mut B, bg_num: usize) -> u16 {
         let addr = REG_BG0CNT + (bg_num * 2) as u32;
         let lo = bus.read8(addr) as u16;
@@ -1609,72 +1441,29 @@ impl Ppu {
         let char_base = (((bgcnt >> 2) & 0x3) * 0x4000) as u32;
         let wrap = (bgcnt >> 13) & 1 != 0;
 
-        let bg_size = match screen_size {
-            0 => 128,
-            1 => 256,
-            2 => 512,
-            3 => 1024,
-            _ => 128,
-        };
+        // Square map, 128 to 1024 pixels across.
+        let bg_size = 128i32 << screen_size;
 
-        let pa_addr = REG_BG2PA + ((bg_num - 2) * 0x10) as u32;
-        let pb_addr = REG_BG2PB + ((bg_num - 2) * 0x10) as u32;
-        let pc_addr = REG_BG2PC + ((bg_num - 2) * 0x10) as u32;
-        let pd_addr = REG_BG2PD + ((bg_num - 2) * 0x10) as u32;
-        let x_addr = REG_BG2X + ((bg_num - 2) * 0x10) as u32;
-        let y_addr = REG_BG2Y + ((bg_num - 2) * 0x10) as u32;
-
-        let pa_lo = bus.read8(pa_addr) as u16;
-        let pa_hi = bus.read8(pa_addr + 1) as u16;
-        let pa = (pa_lo | (pa_hi << 8)) as i16;
-
-        let pb_lo = bus.read8(pb_addr) as u16;
-        let pb_hi = bus.read8(pb_addr + 1) as u16;
-        let pb = (pb_lo | (pb_hi << 8)) as i16;
-
-        let pc_lo = bus.read8(pc_addr) as u16;
-        let pc_hi = bus.read8(pc_addr + 1) as u16;
-        let pc = (pc_lo | (pc_hi << 8)) as i16;
-
-        let pd_lo = bus.read8(pd_addr) as u16;
-        let pd_hi = bus.read8(pd_addr + 1) as u16;
-        let pd = (pd_lo | (pd_hi << 8)) as i16;
-
-        let x_lo = bus.read8(x_addr) as u32;
-        let x_mid = bus.read8(x_addr + 1) as u32;
-        let x_hi = bus.read8(x_addr + 2) as u32;
-        let x_top = bus.read8(x_addr + 3) as u32;
-        let mut ref_x = (x_lo | (x_mid << 8) | (x_hi << 16) | (x_top << 24)) as i32;
-        ref_x = (ref_x << 4) >> 4;
-
-        let y_lo = bus.read8(y_addr) as u32;
-        let y_mid = bus.read8(y_addr + 1) as u32;
-        let y_hi = bus.read8(y_addr + 2) as u32;
-        let y_top = bus.read8(y_addr + 3) as u32;
-        let mut ref_y = (y_lo | (y_mid << 8) | (y_hi << 16) | (y_top << 24)) as i32;
-        ref_y = (ref_y << 4) >> 4;
-
-        let src_x = ref_x + (pa as i32 * x as i32) + (pb as i32 * y as i32);
-        let src_y = ref_y + (pc as i32 * x as i32) + (pd as i32 * y as i32);
-
-        if !wrap
-            && (src_x < 0
-                || src_x >= (bg_size * 8) as i32
-                || src_y < 0
-                || src_y >= (bg_size * 8) as i32)
-        {
+        // The reference point is for the first line rendered; the lines
+        // below it start PB/PD further on.
+        let bg = self.affine[bg_num - 2];
+        let line = y as i32 - self.lines.start as i32;
+        let src_x = (bg.x + bg.pa * x as i32 + bg.pb * line) >> 8;
+        let src_y = (bg.y + bg.pc * x as i32 + bg.pd * line) >> 8;
+
+        if !wrap && (src_x < 0 || src_x >= bg_size || src_y < 0 || src_y >= bg_size) {
             return None;
         }
 
-        let bg_x = (src_x as u32) % (bg_size * 8);
-        let bg_y = (src_y as u32) % (bg_size * 8);
+        let bg_x = src_x.rem_euclid(bg_size) as u32;
+        let bg_y = src_y.rem_euclid(bg_size) as u32;
 
         let tile_x = bg_x / 8;
         let tile_y = bg_y / 8;
         let pixel_x = bg_x % 8;
         let pixel_y = bg_y % 8;
 
-        let map_addr = VRAM_START + screen_base + (tile_y * bg_size + tile_x);
+        let map_addr = VRAM_START + screen_base + tile_y * (bg_size as u32 / 8) + tile_x;
         let tile_num = bus.read8(map_addr) as u32;
 
         let tile_addr = VRAM_START + char_base + tile_num * 64;
@@ -1697,55 +1486,62 @@ impl Ppu {
         bus: &mut B,
         x: usize,
         y: usize,
-        obj_window_mask: &[bool],
     ) -> u8 {
         let win0_enable = (self.dispcnt & DISPCNT_WIN0_ENABLE) != 0;
         let win1_enable = (self.dispcnt & DISPCNT_WIN1_ENABLE) != 0;
         let obj_win_enable = (self.dispcnt & DISPCNT_OBJ_WIN_ENABLE) != 0;
 
-        if win0_enable {
-            let win0h_lo = bus.read8(REG_WIN0H) as u16;
-            let win0h_hi = bus.read8(REG_WIN0H + 1) as u16;
-            let win0h = win0h_lo | (win0h_hi << 8);
-            let win0v_lo = bus.read8(REG_WIN0V) as u16;
-            let win0v_hi = bus.read8(REG_WIN0V + 1) as u16;
-            let win0v = win0v_lo | (win0v_hi << 8);
-
-            let x1 = ((win0h >> 8) & 0xFF) as usize;
-            let x2 = ((win0h & 0xFF) as usize).min(240);
-            let y1 = ((win0v >> 8) & 0xFF) as usize;
-            let y2 = ((win0v & 0xFF) as usize).min(160);
-
-            if x1 <= x2 && x >= x1 && x < x2 && y >= y1 && y < y2 {
-                return 0;
-            }
+        if win0_enable && self.in_window(bus, REG_WIN0H, REG_WIN0V, x, y) {
+            return 0;
         }
 
-        if win1_enable {
-            let win1h_lo = bus.read8(REG_WIN1H) as u16;
-            let win1h_hi = bus.read8(REG_WIN1H + 1) as u16;
-            let win1h = win1h_lo | (win1h_hi << 8);
-            let win1v_lo = bus.read8(REG_WIN1V) as u16;
-            let win1v_hi = bus.read8(REG_WIN1V + 1) as u16;
-            let win1v = win1v_lo | (win1v_hi << 8);
-
-            let x1 = ((win1h >> 8) & 0xFF) as usize;
-            let x2 = ((win1h & 0xFF) as usize).min(240);
-            let y1 = ((win1v >> 8) & 0xFF) as usize;
-            let y2 = ((win1v & 0xFF) as usize).min(160);
-
-            if x1 <= x2 && x >= x1 && x < x2 && y >= y1 && y < y2 {
-                return 1;
-            }
+        if win1_enable && self.in_window(bus, REG_WIN1H, REG_WIN1V, x, y) {
+            return 1;
         }
 
-        if obj_win_enable && obj_window_mask[y * SCREEN_W + x] {
+        if obj_win_enable && self.obj_window[y * SCREEN_W + x] {
             return 2;
         }
 
         3
     }
 
+    fn in_window<B: crate::bus::BusAccess>(&self, bus: &mut B, h_reg: u32, v_reg: u32, x: usize, y: usize) -> bool {
+        let h_lo = bus.read8(h_reg) as usize;
+        let h_hi = bus.read8(h_reg + 1) as usize;
+        let v_lo = bus.read8(v_reg) as usize;
+        let v_hi = bus.read8(v_reg + 1) as usize;
+        Self::in_window_span(x, h_hi, h_lo) && Self::in_window_span(y, v_hi, v_lo)
+    }
+
+    // A window covers [start, end); when start > end it wraps around the
+    // screen edge and covers everything outside [end, start).
+    fn in_window_span(pos: usize, start: usize, end: usize) -> bool {
+        if start <= end {
+            pos >= start && pos < end
+        } else {
+            pos >= start || pos < end
+        }
+    }
+
+    /// WININ/WINOUT control bits for a window region: bits 0-3 enable BG0-3,
+    /// bit 4 OBJ and bit 5 color effects. Everything is enabled when no
+    /// window is.
+    fn window_control<B: crate::bus::BusAccess>(&self, bus: &mut B, window_region: u8) -> u8 {
+        let any_window_enabled = (self.dispcnt & (DISPCNT_WIN0_ENABLE | DISPCNT_WIN1_ENABLE | DISPCNT_OBJ_WIN_ENABLE)) != 0;
+        if !any_window_enabled {
+            return 0x3F;
+        }
+
+        let reg = match window_region {
+            0 => REG_WININ,
+            1 => REG_WININ + 1,
+            2 => REG_WINOUT + 1,
+            _ => REG_WINOUT,
+        };
+        bus.read8(reg) & 0x3F
+    }
+
     fn is_layer_enabled_in_window<B: crate::bus::BusAccess>(
         &self,
         bus: &mut B,
@@ -1753,55 +1549,22 @@ impl Ppu {
         layer: usize,
         is_obj: bool,
     ) -> bool {
-        let any_window_enabled = (self.dispcnt & (DISPCNT_WIN0_ENABLE | DISPCNT_WIN1_ENABLE | DISPCNT_OBJ_WIN_ENABLE)) != 0;
-        if !any_window_enabled {
-            return true;
-        }
-
-        let winin_lo = bus.read8(REG_WININ) as u16;
-        let winin_hi = bus.read8(REG_WININ + 1) as u16;
-        let winin = winin_lo | (winin_hi << 8);
-        let winout_lo = bus.read8(REG_WINOUT) as u16;
-        let winout_hi = bus.read8(REG_WINOUT + 1) as u16;
-        let winout = winout_lo | (winout_hi << 8);
-
-        let (mask, _effect_mask) = match window_region {
-            0 => {
-                let bg_mask = (winin >> layer) & 1;
-                let obj_mask = (winin >> 4) & 1;
-                let effect = (winin >> 5) & 1;
-                (if is_obj { obj_mask } else { bg_mask }, effect)
-            }
-            1 => {
-                let bg_mask = (winin >> (8 + layer)) & 1;
-                let obj_mask = (winin >> 12) & 1;
-                let effect = (winin >> 13) & 1;
-                (if is_obj { obj_mask } else { bg_mask }, effect)
-            }
-            2 => {
-                let bg_mask = (winout >> (8 + layer)) & 1;
-                let obj_mask = (winout >> 12) & 1;
-                let effect = (winout >> 13) & 1;
-                (if is_obj { obj_mask } else { bg_mask }, effect)
-            }
-            _ => {
-                let bg_mask = (winout >> layer) & 1;
-                let obj_mask = (winout >> 4) & 1;
-                let effect = (winout >> 5) & 1;
-                (if is_obj { obj_mask } else { bg_mask }, effect)
-            }
-        };
-
-        mask != 0
+        let bit = if is_obj { 4 } else { layer };
+        (self.window_control(bus, window_region) >> bit) & 1 != 0
     }
 
-    fn build_obj_window_mask<B: crate::bus::BusAccess>(&self, bus: &mut B) -> Vec<bool> {
-        let mut mask = vec![false; FRAME_PIXELS];
+    fn effects_enabled_in_window<B: crate::bus::BusAccess>(&self, bus: &mut B, window_region: u8) -> bool {
+        (self.window_control(bus, window_region) >> 5) & 1 != 0
+    }
 
-        if (self.dispcnt & DISPCNT_OBJ_ENABLE) == 0 || (self.dispcnt & DISPCNT_OBJ_WIN_ENABLE) == 0
-        {
-            return mask;
-        }
+    /// Scans OAM for each line being rendered, in OAM order as the hardware
+    /// does. Sprites covering the line use up its OBJ cycle budget; the ones
+    /// that fit are marked in `obj_lines`, and the OBJ window sprites among
+    /// them are drawn into `obj_window`.
+    fn evaluate_objs<B: crate::bus::BusAccess>(&mut self, bus: &mut B) {
+        let obj_enabled = (self.dispcnt & DISPCNT_OBJ_ENABLE) != 0;
+        let obj_window = obj_enabled && (self.dispcnt & DISPCNT_OBJ_WIN_ENABLE) != 0;
+        let budget = if self.is_hblank_interval_free() { OBJ_CYCLES_HBLANK_FREE } else { OBJ_CYCLES_PER_LINE };
 
         let mode = self.dispcnt & DISPCNT_MODE_MASK;
         let obj_vram_base = if mode >= 3 {
@@ -1811,60 +1574,27 @@ impl Ppu {
         };
         let one_dimensional = (self.dispcnt & DISPCNT_OBJ_VRAM_MAPPING) != 0;
 
-        for obj_num in 0..128 {
-            let oam_addr = OAM_START + (obj_num * 8) as u32;
-            let attr0_lo = bus.read8(oam_addr) as u16;
-            let attr0_hi = bus.read8(oam_addr + 1) as u16;
-            let attr0 = attr0_lo | (attr0_hi << 8);
-            let attr1_lo = bus.read8(oam_addr + 2) as u16;
-            let attr1_hi = bus.read8(oam_addr + 3) as u16;
-            let attr1 = attr1_lo | (attr1_hi << 8);
-            let attr2_lo = bus.read8(oam_addr + 4) as u16;
-            let attr2_hi = bus.read8(oam_addr + 5) as u16;
-            let attr2 = attr2_lo | (attr2_hi << 8);
-
-            let obj_mode = (attr0 >> 10) & 0x3;
-            if obj_mode != 2 {
-                continue;
-            }
-
-            let y = (attr0 & 0xFF) as usize;
-            let x = (attr1 & 0x1FF) as usize;
-            let rotation_scaling = (attr0 >> 8) & 1 != 0;
-            let obj_disable = !rotation_scaling && ((attr0 >> 9) & 1 != 0);
-            let is_256_color = (attr0 >> 13) & 1 != 0;
-            let shape = (attr0 >> 14) & 0x3;
-            let size = (attr1 >> 14) & 0x3;
-            let tile_num = attr2 & 0x3FF;
-            let palette_num = (attr2 >> 12) & 0xF;
-
-            if obj_disable {
-                continue;
-            }
+        for y in self.lines.clone() {
+            let mut drawn = 0u128;
+            let mut window_row = [false; SCREEN_W];
+            let mut cycles = 0;
 
-            let (obj_w, obj_h) = self.get_obj_size(shape, size);
-            let display_w = if rotation_scaling && ((attr0 >> 9) & 1 != 0) {
-                obj_w * 2
-            } else {
-                obj_w
-            };
-            let display_h = if rotation_scaling && ((attr0 >> 9) & 1 != 0) {
-                obj_h * 2
-            } else {
-                obj_h
-            };
-
-            let screen_y = if y >= 160 { y.wrapping_sub(256) } else { y };
-            let screen_x = if x >= 240 { x.wrapping_sub(512) } else { x };
-
-            for py in 0..display_h {
-                let fy = screen_y.wrapping_add(py);
-                if fy >= SCREEN_H {
+            for (obj_num, obj) in self.oam.objs.iter().enumerate() {
+                if !obj_enabled {
+                    break;
+                }
+                let (display_w, display_h) = obj.display_size();
+                let (screen_x, screen_y) = obj.screen_pos();
+                let py = y.wrapping_sub(screen_y);
+                if !obj.is_shown() || py >= display_h {
                     continue;
                 }
-
-                let src_y = py;
-                if src_y >= display_h {
+                cycles += obj.render_cycles();
+                if cycles > budget {
+                    break;
+                }
+                drawn |= 1 << obj_num;
+                if !obj_window || obj.mode != ObjMode::Window {
                     continue;
                 }
 
@@ -1874,56 +1604,23 @@ impl Ppu {
                         continue;
                     }
 
-                    let src_x = px;
-                    if src_x >= display_w {
-                        continue;
-                    }
-
-                    let pixel = if rotation_scaling {
-                        let param_group = ((attr1 >> 9) & 0x1F) as usize;
-                        self.render_affine_obj_pixel(
-                            bus,
-                            obj_vram_base,
-                            one_dimensional,
-                            is_256_color,
-                            tile_num,
-                            palette_num,
-                            param_group,
-                            obj_w,
-                            obj_h,
-                            display_w,
-                            display_h,
-                            src_x,
-                            src_y,
-                        )
+                    let pixel = if obj.affine {
+                        self.render_affine_obj_pixel(bus, obj_vram_base, one_dimensional, obj, px, py)
                     } else {
-                        let h_flip = (attr1 >> 12) & 1 != 0;
-                        let v_flip = (attr1 >> 13) & 1 != 0;
-                        self.render_regular_obj_pixel(
-                            bus,
-                            obj_vram_base,
-                            one_dimensional,
-                            is_256_color,
-                            tile_num,
-                            palette_num,
-                            obj_w,
-                            obj_h,
-                            src_x,
-                            src_y,
-                            h_flip,
-                            v_flip,
-                        )
+                        self.render_regular_obj_pixel(bus, obj_vram_base, one_dimensional, obj, px, py)
                     };
-
-                    if pixel.is_some() {
-                        let idx = fy * SCREEN_W + fx;
-                        mask[idx] = true;
-                    }
+                    window_row[fx] |= pixel.is_some();
                 }
             }
+
+            self.obj_lines[y] = drawn;
+            self.obj_window[y * SCREEN_W..(y + 1) * SCREEN_W].copy_from_slice(&window_row);
         }
+    }
 
-        mask
+    // Whether sprite `obj_num` is drawn on line `y` of the render in progress.
+    fn obj_on_line(&self, obj_num: usize, y: usize) -> bool {
+        self.lines.contains(&y) && (self.obj_lines[y] >> obj_num) & 1 != 0
     }
 
     fn read_bldcnt<B: crate::bus::BusAccess>(&self, bus: &mut B) -> u16 {
@@ -2059,14 +1756,21 @@ impl Ppu {
         top: Option<PixelLayer>,
         second: Option<PixelLayer>,
         backdrop: u16,
-    ) -> u16 {
+        effects_enabled: bool,
+    ) -> (u16, bool) {
         let top = match top {
             Some(t) => t,
             None => {
-                return backdrop;
+                return (backdrop, false);
             }
         };
 
+        // The window's effect-enable bit also stops semi-transparent OBJs
+        // from blending.
+        if !effects_enabled {
+            return (top.color, false);
+        }
+
         if top.is_semi_transparent {
             let second_pixel = second.as_ref().map(|s| s.color).or(Some(backdrop));
             let bldalpha = self.read_bldalpha(bus);
@@ -2086,7 +1790,7 @@ impl Ppu {
                 let g = ((g1 * eva + g2 * evb) / 16).min(31) as u16;
                 let b = ((b1 * eva + b2 * evb) / 16).min(31) as u16;
 
-                return r | (g << 5) | (b << 10);
+                return (r | (g << 5) | (b << 10), true);
             }
         }
 
@@ -2094,12 +1798,12 @@ impl Ppu {
         let effect_mode = (bldcnt >> 6) & 0x3;
 
         if effect_mode == 0 {
-            return top.color;
+            return (top.color, false);
         }
 
         let is_1st = self.is_1st_target(bus, top.layer, top.is_obj, top.is_backdrop);
         if !is_1st {
-            return top.color;
+            return (top.color, false);
         }
 
         let second_pixel = match second {
@@ -2127,9 +1831,9 @@ impl Ppu {
                     let g = ((g1 * eva + g2 * evb) / 16).min(31) as u16;
                     let b = ((b1 * eva + b2 * evb) / 16).min(31) as u16;
 
-                    r | (g << 5) | (b << 10)
+                    (r | (g << 5) | (b << 10), true)
                 } else {
-                    top.color
+                    (top.color, false)
                 }
             }
             2 => {
@@ -2144,7 +1848,7 @@ impl Ppu {
                 let g = (g1 + ((31 - g1) * evy / 16)).min(31) as u16;
                 let b = (b1 + ((31 - b1) * evy / 16)).min(31) as u16;
 
-                r | (g << 5) | (b << 10)
+                (r | (g << 5) | (b << 10), true)
             }
             3 => {
                 let bldy = self.read_bldy(bus);
@@ -2158,15 +1862,16 @@ impl Ppu {
                 let g = (g1 - (g1 * evy / 16)).min(31) as u16;
                 let b = (b1 - (b1 * evy / 16)).min(31) as u16;
 
-                r | (g << 5) | (b << 10)
+                (r | (g << 5) | (b << 10), true)
             }
-            _ => top.color,
+            _ => (top.color, false),
         }
     }
 }
 
 /// The main test module for the PPU.
 #[cfg(test)]
+#[allow(clippy::identity_op, clippy::assertions_on_constants)]
 mod tests {
     use super::*;
     use crate::bus::{Bus, BusAccess};
@@ -2374,6 +2079,352 @@ mod tests {
         );
     }
 
+    #[test]
+    fn rendering_lines_leaves_the_rest_of_the_frame() {
+        let mut ppu = Ppu::new();
+        let mut bus = Bus::new();
+        bus.mem.vram[..FRAME_PIXELS * 2].fill(0x1F);
+        bus.write16(REG_DISPCNT, 3 | DISPCNT_BG2_ENABLE);
+
+        ppu.render_lines_with_bus(&mut bus, 10..12);
+        let fb = ppu.framebuffer();
+        assert!(fb[10 * SCREEN_W..12 * SCREEN_W].iter().all(|&p| p == 0x1F1F));
+        assert!(fb[..10 * SCREEN_W].iter().chain(&fb[12 * SCREEN_W..]).all(|&p| p == 0));
+
+        bus.mem.vram[..FRAME_PIXELS * 2].fill(0x03);
+        ppu.render_lines_with_bus(&mut bus, 11..12);
+        assert_eq!(ppu.framebuffer()[10 * SCREEN_W], 0x1F1F);
+        assert_eq!(ppu.framebuffer()[11 * SCREEN_W], 0x0303);
+    }
+
+    #[test]
+    fn affine_bg_mosaic_repeats_source_pixels() {
+        let mut ppu = Ppu::new();
+        let mut bus = Bus::new();
+        // Tile 1 (8bpp): palette index 1 + x + 8 * y; every map entry uses it.
+        for i in 0..64 {
+            bus.mem.vram[64 + i] = 1 + i as u8;
+            bus.mem.palette[2 + i * 2] = 1 + i as u8;
+        }
+        bus.mem.vram[0x800..0x900].fill(1);
+
+        bus.write16(REG_DISPCNT, 2 | (1 << 10));
+        bus.write16(REG_BG2CNT, (1 << 6) | (1 << 8));
+        bus.write16(REG_BG2PA, 0x100);
+        bus.write16(REG_BG2PD, 0x100);
+        bus.write32(REG_BG2X, 2 << 8);
+        // 4x2 blocks.
+        bus.write16(REG_MOSAIC, 3 | (1 << 4));
+        ppu.render_frame_with_bus(&mut bus);
+
+        let fb = ppu.framebuffer();
+        let expected = |x: usize, y: usize| 1 + ((x + 2) % 8 + 8 * (y % 8)) as u16;
+        for y in 0..4 {
+            for x in 0..12 {
+                assert_eq!(fb[y * SCREEN_W + x], expected(x / 4 * 4, y / 2 * 2), "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn affine_bg_lines_start_at_the_internal_reference_point() {
+        let mut ppu = Ppu::new();
+        let mut bus = Bus::new();
+        // Tile 1 (8bpp): palette index 1 + x + 8 * y; every map entry uses it.
+        for i in 0..64 {
+            bus.mem.vram[64 + i] = 1 + i as u8;
+            bus.mem.palette[2 + i * 2] = 1 + i as u8;
+        }
+        bus.mem.vram[0x800..0x900].fill(1);
+
+        bus.write16(REG_DISPCNT, 2 | (1 << 10));
+        bus.write16(REG_BG2CNT, 1 << 8);
+        bus.write16(REG_BG2PA, 0x100);
+        bus.write16(REG_BG2PD, 0x100);
+        for y in 0..4 {
+            // A mid-frame write moves the lines below it; BG2Y keeps
+            // counting from the top of the frame.
+            if y == 2 {
+                bus.write32(REG_BG2X, 3 << 8);
+            }
+            ppu.render_lines_with_bus(&mut bus, y..y + 1);
+            bus.io.advance_affine_refs();
+        }
+
+        let fb = ppu.framebuffer();
+        for y in 0..4 {
+            let scroll = if y < 2 { 0 } else { 3 };
+            for x in 0..8 {
+                let expected = 1 + ((x + scroll) % 8 + 8 * y) as u16;
+                assert_eq!(fb[y * SCREEN_W + x], expected, "pixel ({}, {})", x, y);
+            }
+        }
+
+        // VBlank reloads the registers, so the next frame starts over.
+        bus.io.latch_affine_refs();
+        assert_eq!(bus.io.affine_ref(2), (3 << 8, 0));
+    }
+
+    #[test]
+    fn obj_mosaic_blocks_start_at_the_sprite() {
+        let mut ppu = Ppu::new();
+        let mut bus = Bus::new();
+        // 4bpp tile 0: palette index 1 + x.
+        for row in 0..8 {
+            for pair in 0..4u8 {
+                bus.mem.vram[0x1_0000 + row * 4 + pair as usize] = (1 + pair * 2) | ((2 + pair * 2) << 4);
+            }
+        }
+        for i in 1..9 {
+            bus.write16(OBJ_PALETTE_START + i * 2, i as u16);
+        }
+        for i in 1..128 {
+            bus.write16(OAM_START + i * 8, 1 << 9);
+        }
+        bus.write16(OAM_START, 5 | (1 << 12));
+        bus.write16(OAM_START + 2, 3);
+
+        bus.write16(REG_DISPCNT, (1 << 6) | (1 << 12));
+        bus.write16(REG_MOSAIC, 3 << 8);
+        ppu.render_frame_with_bus(&mut bus);
+
+        let row = &ppu.framebuffer()[5 * SCREEN_W..6 * SCREEN_W];
+        assert_eq!(&row[2..12], &[0, 1, 1, 1, 1, 5, 5, 5, 5, 0]);
+    }
+
+    #[test]
+    fn pixel_info_records_the_winning_layer_and_blending() {
+        let mut ppu = Ppu::new();
+        let mut bus = Bus::new();
+        // BG1 and the sprite both draw with a solid 4bpp tile 0.
+        bus.mem.vram[0..32].fill(0x11);
+        bus.mem.vram[0x1_0000..0x1_0020].fill(0x11);
+        bus.write16(PALETTE_RAM_START + 2, 0x001F);
+        bus.write16(OBJ_PALETTE_START + 2, 0x03E0);
+        for i in 1..128 {
+            bus.write16(OAM_START + i * 8, 1 << 9);
+        }
+        bus.write16(OAM_START, 5);
+        bus.write16(OAM_START + 2, 3);
+
+        bus.write16(REG_DISPCNT, (1 << 9) | (1 << 12));
+        bus.write16(REG_BG1CNT, 1 | (8 << 8));
+        // Brighten BG1 only.
+        bus.write16(REG_BLDCNT, (1 << 1) | (2 << 6));
+        bus.write16(REG_BLDY, 8);
+        ppu.render_frame_with_bus(&mut bus);
+        assert!(ppu.pixel_info().is_none());
+
+        ppu.set_pixel_info(true);
+        ppu.render_frame_with_bus(&mut bus);
+        let info = ppu.pixel_info().unwrap();
+        assert_eq!(info[0], PixelInfo { source: PixelSource::Bg(1), priority: 1, blended: true });
+        assert_eq!(info[6 * SCREEN_W + 4], PixelInfo::obj(0));
+
+        bus.write16(REG_DISPCNT, 1 << 12);
+        ppu.render_lines_with_bus(&mut bus, 0..8);
+        let info = ppu.pixel_info().unwrap();
+        assert_eq!(info[0], PixelInfo::BACKDROP);
+        assert_eq!(info[6 * SCREEN_W + 4], PixelInfo::obj(0));
+    }
+
+    #[test]
+    fn layer_mask_hides_layers_dispcnt_enables() {
+        let mut ppu = Ppu::new();
+        let mut bus = Bus::new();
+        // BG0 (priority 0) over BG1 (priority 1), both solid, and a sprite
+        // behind BG0 at (0, 0).
+        bus.mem.vram[0..32].fill(0x11);
+        bus.mem.vram[0x1_0000..0x1_0020].fill(0x11);
+        bus.write16(PALETTE_RAM_START + 2, 0x001F);
+        bus.write16(OBJ_PALETTE_START + 2, 0x03E0);
+        for i in 1..128 {
+            bus.write16(OAM_START + i * 8, 1 << 9);
+        }
+        bus.write16(OAM_START + 4, 1 << 10);
+        bus.write16(REG_BG0CNT, 8 << 8);
+        bus.write16(REG_BG1CNT, 1 | (9 << 8));
+        bus.write16(PALETTE_RAM_START, 0x7C00);
+        bus.write16(REG_DISPCNT, (1 << 8) | (1 << 9) | (1 << 12));
+        ppu.set_pixel_info(true);
+
+        let mut source_at = |mask: u8, x: usize| {
+            ppu.set_layer_mask(mask);
+            ppu.render_frame_with_bus(&mut bus);
+            ppu.pixel_info().unwrap()[x].source
+        };
+        assert_eq!(source_at(LAYER_ALL, 0), PixelSource::Bg(0));
+        assert_eq!(source_at(LAYER_ALL & !1, 0), PixelSource::Obj);
+        assert_eq!(source_at(LAYER_ALL & !1, 100), PixelSource::Bg(1));
+        assert_eq!(source_at(0b0010, 0), PixelSource::Bg(1));
+        assert_eq!(source_at(0, 0), PixelSource::Backdrop);
+        // BG2 is off in DISPCNT, so the mask cannot show it.
+        assert_eq!(source_at(0b0100, 0), PixelSource::Backdrop);
+        assert_eq!(ppu.layer_mask(), 0b0100);
+        assert_eq!(ppu.framebuffer()[0], 0x7C00);
+    }
+
+    #[test]
+    fn window_effect_bit_and_wraparound() {
+        let mut ppu = Ppu::new();
+        let mut bus = Bus::new();
+        // BG0 is tile 0 everywhere, a solid palette index 1.
+        bus.mem.vram[..32].fill(0x11);
+        bus.write16(PALETTE_RAM_START + 2, 0x0010);
+        bus.write16(REG_BG0CNT, 31 << 8);
+        bus.write16(REG_DISPCNT, (1 << 8) | (1 << 13));
+
+        // WIN0 wraps in both directions: x >= 200 or x < 40, y >= 150 or y < 10.
+        bus.write16(REG_WIN0H, (200 << 8) | 40);
+        bus.write16(REG_WIN0V, (150 << 8) | 10);
+        bus.write16(REG_WININ, 1);
+        bus.write16(REG_WINOUT, 1 | (1 << 5));
+        bus.write16(REG_BLDCNT, 1 | (2 << 6));
+        bus.write16(REG_BLDY, 16);
+        ppu.render_frame_with_bus(&mut bus);
+
+        let fb = ppu.framebuffer();
+        let at = |x: usize, y: usize| fb[y * SCREEN_W + x];
+        for (x, y) in [(10, 5), (210, 5), (10, 155), (239, 159)] {
+            assert_eq!(at(x, y), 0x0010, "inside WIN0 at ({}, {})", x, y);
+        }
+        for (x, y) in [(100, 5), (10, 80), (40, 10), (199, 149)] {
+            assert_eq!(at(x, y), 0x7FFF, "outside WIN0 at ({}, {})", x, y);
+        }
+    }
+
+    // Places sprite 0 at the top-left corner and hides the rest.
+    fn single_sprite(bus: &mut Bus, attr0: u16, attr1: u16, attr2: u16) {
+        for i in 1..128 {
+            bus.write16(OAM_START + i * 8, 1 << 9);
+        }
+        bus.write16(OAM_START, attr0);
+        bus.write16(OAM_START + 2, attr1);
+        bus.write16(OAM_START + 4, attr2);
+        for i in 1..16 {
+            bus.write16(OBJ_PALETTE_START + i * 2, i as u16);
+        }
+    }
+
+    // Colors of the four 8x8 quadrants of a 16x16 sprite at the origin.
+    fn quadrants(ppu: &Ppu) -> [u16; 4] {
+        let fb = ppu.framebuffer();
+        [fb[0], fb[8], fb[8 * SCREEN_W], fb[8 * SCREEN_W + 8]]
+    }
+
+    // Shows sprites 0..count as 64x64 squares of color 1 at (i * 8, 0) and
+    // hides the rest.
+    fn sprite_row(bus: &mut Bus, count: u32) {
+        bus.mem.vram[0x1_0000..].fill(0x11);
+        bus.write16(OBJ_PALETTE_START + 2, 1);
+        for i in 0..128 {
+            let (attr0, attr1) = if i < count { (0, (3 << 14) | (i * 8) as u16) } else { (1 << 9, 0) };
+            bus.write16(OAM_START + i * 8, attr0);
+            bus.write16(OAM_START + i * 8 + 2, attr1);
+        }
+    }
+
+    #[test]
+    fn sprites_past_the_line_cycle_budget_are_dropped() {
+        let mut ppu = Ppu::new();
+        let mut bus = Bus::new();
+        sprite_row(&mut bus, 30);
+        bus.write16(REG_DISPCNT, (1 << 6) | (1 << 12));
+        ppu.render_frame_with_bus(&mut bus);
+        // 18 sprites 64 pixels wide fit in 1210 cycles; sprite 17 ends at 199.
+        assert_eq!(ppu.framebuffer()[199], 1);
+        assert_eq!(ppu.framebuffer()[200], 0);
+
+        // Freeing OAM during HBlank leaves 954 cycles, enough for 14.
+        bus.write16(REG_DISPCNT, (1 << 6) | (1 << 5) | (1 << 12));
+        ppu.render_frame_with_bus(&mut bus);
+        assert_eq!(ppu.framebuffer()[167], 1);
+        assert_eq!(ppu.framebuffer()[168], 0);
+    }
+
+    #[test]
+    fn obj_window_sprites_share_the_line_budget() {
+        let mut ppu = Ppu::new();
+        let mut bus = Bus::new();
+        sprite_row(&mut bus, 19);
+        // Only OBJ shows, and only inside the OBJ window.
+        bus.write16(REG_WINOUT, 0x10 << 8);
+        bus.write16(REG_DISPCNT, (1 << 6) | (1 << 12) | (1 << 15));
+
+        // Sprite 0 is the window, covering x 0-63.
+        bus.write16(OAM_START, 2 << 10);
+        ppu.render_frame_with_bus(&mut bus);
+        assert_eq!(ppu.framebuffer()[8], 1);
+        assert_eq!(ppu.framebuffer()[64], 0);
+
+        // Sprite 18 would cover x 144-207 but does not fit the budget.
+        bus.write16(OAM_START, 0);
+        bus.write16(OAM_START + 18 * 8, 2 << 10);
+        ppu.render_frame_with_bus(&mut bus);
+        assert_eq!(ppu.framebuffer()[150], 0);
+        assert!(ppu.framebuffer()[..SCREEN_W].iter().all(|&p| p == 0));
+    }
+
+    #[test]
+    fn obj_256_color_tile_mapping() {
+        // Fills 256-color tiles (pairs of 32-byte units) with their quadrant color.
+        let fill = |bus: &mut Bus, units: [usize; 4]| {
+            for (color, unit) in units.into_iter().enumerate() {
+                bus.mem.vram[0x1_0000 + unit * 32..][..64].fill(color as u8 + 1);
+            }
+        };
+
+        let mut ppu = Ppu::new();
+        let mut bus = Bus::new();
+        fill(&mut bus, [3, 5, 7, 9]);
+        single_sprite(&mut bus, 1 << 13, 1 << 14, 3);
+        bus.write16(REG_DISPCNT, (1 << 6) | (1 << 12));
+        ppu.render_frame_with_bus(&mut bus);
+        assert_eq!(quadrants(&ppu), [1, 2, 3, 4], "1D mapping");
+
+        // 2D: bit 0 of the tile number is ignored and rows are 32 units apart.
+        let mut bus = Bus::new();
+        fill(&mut bus, [2, 4, 34, 36]);
+        single_sprite(&mut bus, 1 << 13, 1 << 14, 3);
+        bus.write16(REG_DISPCNT, 1 << 12);
+        ppu.render_frame_with_bus(&mut bus);
+        assert_eq!(quadrants(&ppu), [1, 2, 3, 4], "2D mapping");
+    }
+
+    #[test]
+    fn obj_4bpp_2d_mapping_rows_are_32_tiles_apart() {
+        let mut ppu = Ppu::new();
+        let mut bus = Bus::new();
+        for (color, unit) in [1usize, 2, 33, 34].into_iter().enumerate() {
+            bus.mem.vram[0x1_0000 + unit * 32..][..32].fill((color as u8 + 1) * 0x11);
+        }
+        single_sprite(&mut bus, 0, 1 << 14, 1);
+        bus.write16(REG_DISPCNT, 1 << 12);
+        ppu.render_frame_with_bus(&mut bus);
+        assert_eq!(quadrants(&ppu), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn bitmap_modes_only_show_obj_tiles_from_512() {
+        let mut ppu = Ppu::new();
+        let mut bus = Bus::new();
+        bus.mem.vram[0x1_0000..0x1_0020].fill(0x11);
+        bus.mem.vram[0x1_4000..0x1_4020].fill(0x22);
+        single_sprite(&mut bus, 0, 0, 0);
+        bus.write16(REG_DISPCNT, 3 | (1 << 10) | (1 << 12));
+        ppu.render_frame_with_bus(&mut bus);
+        assert_eq!(ppu.framebuffer()[0], 0, "tile 0 overlaps the bitmap");
+
+        bus.write16(OAM_START + 4, 512);
+        ppu.render_frame_with_bus(&mut bus);
+        assert_eq!(ppu.framebuffer()[0], 2);
+
+        bus.write16(REG_DISPCNT, 1 << 12);
+        bus.write16(OAM_START + 4, 0);
+        ppu.render_frame_with_bus(&mut bus);
+        assert_eq!(ppu.framebuffer()[0], 1, "tile 0 is usable in tiled modes");
+    }
+
     /// Test Suite for Color Effects (Alpha Blending, Brightness).
     #[test]
     fn alpha_blending_is_applied_correctly() {
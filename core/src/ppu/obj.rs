@@ -0,0 +1,232 @@
+//! Sprite (OBJ) attributes parsed from OAM.
+//!
+//! The renderer reads OAM once per render call into an [`Oam`] and works
+//! from the typed entries afterwards; debug views can build one from the
+//! raw bytes with [`Oam::from_bytes`].
+
+use crate::bus::BusAccess;
+use crate::mem::Mem;
+
+pub const OBJ_COUNT: usize = 128;
+pub const AFFINE_GROUPS: usize = 32;
+
+const OAM_START: u32 = 0x0700_0000;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ObjMode {
+    #[default]
+    Normal,
+    SemiTransparent,
+    /// Draws into the OBJ window mask instead of the screen.
+    Window,
+    Prohibited,
+}
+
+/// Attributes 0-2 of one OAM entry.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ObjAttr {
+    pub y: u8,
+    /// 9 bits.
+    pub x: u16,
+    pub affine: bool,
+    /// Attribute 0 bit 9 on an affine sprite.
+    pub double_size: bool,
+    /// Attribute 0 bit 9 on a regular sprite.
+    pub disabled: bool,
+    pub mode: ObjMode,
+    pub mosaic: bool,
+    pub color_256: bool,
+    pub shape: u8,
+    pub size: u8,
+    /// Affine parameter group; only meaningful when `affine` is set.
+    pub affine_group: u8,
+    pub h_flip: bool,
+    pub v_flip: bool,
+    pub tile: u16,
+    pub priority: u8,
+    pub palette: u8,
+}
+
+impl ObjAttr {
+    pub fn parse(attr0: u16, attr1: u16, attr2: u16) -> Self {
+        let affine = attr0 & (1 << 8) != 0;
+        let bit9 = attr0 & (1 << 9) != 0;
+        Self {
+            y: attr0 as u8,
+            x: attr1 & 0x1FF,
+            affine,
+            double_size: affine && bit9,
+            disabled: !affine && bit9,
+            mode: match (attr0 >> 10) & 3 {
+                0 => ObjMode::Normal,
+                1 => ObjMode::SemiTransparent,
+                2 => ObjMode::Window,
+                _ => ObjMode::Prohibited,
+            },
+            mosaic: attr0 & (1 << 12) != 0,
+            color_256: attr0 & (1 << 13) != 0,
+            shape: (attr0 >> 14) as u8,
+            size: (attr1 >> 14) as u8,
+            affine_group: ((attr1 >> 9) & 0x1F) as u8,
+            h_flip: !affine && attr1 & (1 << 12) != 0,
+            v_flip: !affine && attr1 & (1 << 13) != 0,
+            tile: attr2 & 0x3FF,
+            priority: ((attr2 >> 10) & 3) as u8,
+            palette: (attr2 >> 12) as u8,
+        }
+    }
+
+    /// Whether the sprite is drawn at all, to the screen or the OBJ window.
+    pub fn is_shown(&self) -> bool { !self.disabled && self.mode != ObjMode::Prohibited }
+
+    /// Width and height of the sprite's tiles in pixels.
+    pub fn size(&self) -> (usize, usize) {
+        match (self.shape, self.size) {
+            (0, 0) => (8, 8),
+            (0, 1) => (16, 16),
+            (0, 2) => (32, 32),
+            (0, 3) => (64, 64),
+            (1, 0) => (16, 8),
+            (1, 1) => (32, 8),
+            (1, 2) => (32, 16),
+            (1, 3) => (64, 32),
+            (2, 0) => (8, 16),
+            (2, 1) => (8, 32),
+            (2, 2) => (16, 32),
+            (2, 3) => (32, 64),
+            _ => (8, 8),
+        }
+    }
+
+    /// Width and height of the area the sprite covers on screen.
+    pub fn display_size(&self) -> (usize, usize) {
+        let (w, h) = self.size();
+        if self.double_size { (w * 2, h * 2) } else { (w, h) }
+    }
+
+    /// OBJ cycles the sprite takes from the line budget on each line it
+    /// covers: one per pixel across, or 10 plus two per pixel when affine.
+    pub fn render_cycles(&self) -> usize {
+        let (w, _) = self.display_size();
+        if self.affine { 10 + 2 * w } else { w }
+    }
+
+    /// Top-left corner on screen. Coordinates past the right or bottom edge
+    /// wrap around to negative ones, which come out as huge `usize`s that
+    /// `wrapping_add` brings back on screen.
+    pub fn screen_pos(&self) -> (usize, usize) {
+        let x = self.x as usize;
+        let y = self.y as usize;
+        let x = if x >= 240 { x.wrapping_sub(512) } else { x };
+        let y = if y >= 160 { y.wrapping_sub(256) } else { y };
+        (x, y)
+    }
+}
+
+/// One affine parameter group, in 8.8 fixed point.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ObjAffine {
+    pub pa: i16,
+    pub pb: i16,
+    pub pc: i16,
+    pub pd: i16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Oam {
+    pub objs: [ObjAttr; OBJ_COUNT],
+    pub affine: [ObjAffine; AFFINE_GROUPS],
+}
+
+impl Default for Oam {
+    fn default() -> Self {
+        Self { objs: [ObjAttr::default(); OBJ_COUNT], affine: [ObjAffine::default(); AFFINE_GROUPS] }
+    }
+}
+
+impl Oam {
+    /// Parses the 1 KiB of OAM. Missing bytes read as zero.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let mut oam = Self::default();
+        oam.parse(|offset| {
+            let lo = bytes.get(offset).copied().unwrap_or(0) as u16;
+            let hi = bytes.get(offset + 1).copied().unwrap_or(0) as u16;
+            lo | (hi << 8)
+        });
+        oam
+    }
+
+    /// Parses OAM straight from memory, without the bus's access rules.
+    pub fn from_mem(mem: &Mem) -> Self {
+        let mut oam = Self::default();
+        oam.parse(|offset| mem.oam16(offset));
+        oam
+    }
+
+    /// Re-reads OAM through the bus.
+    pub(crate) fn load<B: BusAccess>(&mut self, bus: &mut B) {
+        self.parse(|offset| {
+            let addr = OAM_START + offset as u32;
+            bus.read8(addr) as u16 | ((bus.read8(addr + 1) as u16) << 8)
+        });
+    }
+
+    // Attributes 0-2 of entry n are at n * 8; the affine parameters are the
+    // fourth halfword of each entry, four entries to a group.
+    fn parse(&mut self, mut read16: impl FnMut(usize) -> u16) {
+        for (i, obj) in self.objs.iter_mut().enumerate() {
+            let base = i * 8;
+            *obj = ObjAttr::parse(read16(base), read16(base + 2), read16(base + 4));
+        }
+        for (i, group) in self.affine.iter_mut().enumerate() {
+            let base = i * 32 + 6;
+            *group = ObjAffine {
+                pa: read16(base) as i16,
+                pb: read16(base + 8) as i16,
+                pc: read16(base + 16) as i16,
+                pd: read16(base + 24) as i16,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_attributes() {
+        // Affine, double size, semi-transparent, 256 colors, wide 32x16.
+        let obj = ObjAttr::parse(0x6700 | 200, 0x8000 | (5 << 9) | 300, 0x9000 | (2 << 10) | 0x155);
+        assert!(obj.affine && obj.double_size && !obj.disabled);
+        assert_eq!(obj.mode, ObjMode::SemiTransparent);
+        assert!(obj.color_256 && !obj.mosaic);
+        assert_eq!(obj.size(), (32, 16));
+        assert_eq!(obj.display_size(), (64, 32));
+        assert_eq!(obj.affine_group, 5);
+        assert!(!obj.h_flip && !obj.v_flip);
+        assert_eq!((obj.tile, obj.priority, obj.palette), (0x155, 2, 9));
+        let (x, y) = obj.screen_pos();
+        assert_eq!((x, y.wrapping_add(56)), (300usize.wrapping_sub(512), 0));
+
+        let hidden = ObjAttr::parse(1 << 9, 0x3000, 0);
+        assert!(hidden.disabled && !hidden.is_shown());
+        assert!(hidden.h_flip && hidden.v_flip);
+    }
+
+    #[test]
+    fn parses_affine_groups_from_entry_fillers() {
+        let mut bytes = vec![0u8; 0x400];
+        for (k, value) in [0x0100u16, 0xFF80, 0x0040, 0x0200].into_iter().enumerate() {
+            let offset = 32 + 6 + k * 8;
+            bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+        }
+        bytes[8..10].copy_from_slice(&0x4010u16.to_le_bytes());
+
+        let oam = Oam::from_bytes(&bytes);
+        assert_eq!(oam.affine[1], ObjAffine { pa: 0x100, pb: -0x80, pc: 0x40, pd: 0x200 });
+        assert_eq!(oam.affine[0], ObjAffine::default());
+        assert_eq!(oam.objs[1].y, 0x10);
+        assert_eq!(oam.objs[1].shape, 1);
+    }
+}
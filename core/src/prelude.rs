@@ -0,0 +1,12 @@
+//! What a frontend embedding the emulator usually needs, for a glob import:
+//! `use roba_core::prelude::*;`.
+
+pub use crate::audio::{AudioSink, NullSink, StereoSample};
+pub use crate::cart::{BackupType, RomHeader};
+pub use crate::config::{Accuracy, AccuracyPreset, BootMode, EmulatorBuilder, EmulatorConfig, RtcClock};
+pub use crate::error::CoreError;
+pub use crate::frame_report::FrameReport;
+pub use crate::input::KeyState;
+pub use crate::state::StateError;
+pub use crate::video::{ColorProfile, Frame, FrameSink, GBA_SCREEN_H, GBA_SCREEN_W};
+pub use crate::Emulator;
@@ -0,0 +1,208 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use crate::state::{Savestate, StateError, StateReader, StateWriter};
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum EventKind {
+    HBlank,
+    HDraw,
+    TimerOverflow(usize),
+    SerialTransfer,
+    /// Starts the DMA channels flagged by an HBlank, VBlank or video capture
+    /// trigger, after the startup delay.
+    Dma,
+}
+
+// Savestates keep room for this many events, one of each kind, so that their
+// size does not change with what is pending (libretro frontends ask for the
+// size once and reuse it).
+const EVENT_SLOTS: usize = 8;
+// Time, sequence number and kind.
+const EVENT_LEN: usize = 17;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+struct Event {
+    time: u64,
+    seq: u64,
+    kind: EventKind,
+}
+
+impl Ord for Event {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.time.cmp(&other.time).then(self.seq.cmp(&other.seq))
+    }
+}
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
+/// Timestamped event queue driving the emulator. The CPU runs in slices up to
+/// the next event; events due at the same cycle fire in scheduling order.
+#[derive(Default)]
+pub struct Scheduler {
+    now: u64,
+    seq: u64,
+    events: BinaryHeap<Reverse<Event>>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn now(&self) -> u64 { self.now }
+
+    pub fn advance(&mut self, cycles: u64) { self.now += cycles; }
+
+    pub fn advance_to(&mut self, time: u64) { self.now = self.now.max(time); }
+
+    pub fn schedule(&mut self, delay: u64, kind: EventKind) {
+        self.schedule_at(self.now + delay, kind);
+    }
+
+    pub fn schedule_at(&mut self, time: u64, kind: EventKind) {
+        self.seq += 1;
+        self.events.push(Reverse(Event { time, seq: self.seq, kind }));
+    }
+
+    pub fn cancel(&mut self, kind: EventKind) {
+        self.events.retain(|Reverse(e)| e.kind != kind);
+    }
+
+    pub fn next_event_time(&self) -> u64 {
+        self.events.peek().map_or(u64::MAX, |Reverse(e)| e.time)
+    }
+
+    /// When the earliest pending event of `kind` is due.
+    pub fn next_time_of(&self, kind: EventKind) -> Option<u64> {
+        self.events.iter().filter(|Reverse(e)| e.kind == kind).map(|Reverse(e)| e.time).min()
+    }
+
+    /// Pops the earliest event if it is due, returning it with its timestamp.
+    pub fn pop_due(&mut self) -> Option<(EventKind, u64)> {
+        if self.next_event_time() > self.now {
+            return None;
+        }
+        self.events.pop().map(|Reverse(e)| (e.kind, e.time))
+    }
+}
+
+impl EventKind {
+    fn encode(self) -> u8 {
+        match self {
+            EventKind::HBlank => 0,
+            EventKind::HDraw => 1,
+            EventKind::TimerOverflow(i) => 2 + i as u8,
+            EventKind::SerialTransfer => 6,
+            EventKind::Dma => 7,
+        }
+    }
+
+    fn decode(code: u8) -> Result<Self, StateError> {
+        Ok(match code {
+            0 => EventKind::HBlank,
+            1 => EventKind::HDraw,
+            2..=5 => EventKind::TimerOverflow((code - 2) as usize),
+            6 => EventKind::SerialTransfer,
+            7 => EventKind::Dma,
+            _ => return Err(StateError::Corrupt("event kind")),
+        })
+    }
+}
+
+impl Savestate for Scheduler {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.put(&self.now);
+        w.put(&self.seq);
+        w.put(&(self.events.len() as u32));
+        for Reverse(e) in &self.events {
+            w.put(&e.time);
+            w.put(&e.seq);
+            w.put(&e.kind.encode());
+        }
+        for _ in self.events.len()..EVENT_SLOTS {
+            w.bytes(&[0; EVENT_LEN]);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.take(&mut self.now)?;
+        r.take(&mut self.seq)?;
+        let mut len = 0u32;
+        r.take(&mut len)?;
+        self.events.clear();
+        for _ in 0..len {
+            let (mut time, mut seq, mut kind) = (0u64, 0u64, 0u8);
+            r.take(&mut time)?;
+            r.take(&mut seq)?;
+            r.take(&mut kind)?;
+            self.events.push(Reverse(Event { time, seq, kind: EventKind::decode(kind)? }));
+        }
+        r.bytes(EVENT_SLOTS.saturating_sub(len as usize) * EVENT_LEN)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_fire_in_time_then_insertion_order() {
+        let mut s = Scheduler::new();
+        s.schedule(10, EventKind::HDraw);
+        s.schedule(5, EventKind::HBlank);
+        s.schedule(10, EventKind::TimerOverflow(0));
+        assert_eq!(s.next_event_time(), 5);
+        assert_eq!(s.pop_due(), None);
+
+        s.advance_to(10);
+        assert_eq!(s.pop_due(), Some((EventKind::HBlank, 5)));
+        assert_eq!(s.pop_due(), Some((EventKind::HDraw, 10)));
+        assert_eq!(s.pop_due(), Some((EventKind::TimerOverflow(0), 10)));
+        assert_eq!(s.pop_due(), None);
+        assert_eq!(s.next_event_time(), u64::MAX);
+    }
+
+    #[test]
+    fn cancel_removes_only_matching_kind() {
+        let mut s = Scheduler::new();
+        s.schedule(3, EventKind::TimerOverflow(1));
+        s.schedule(4, EventKind::TimerOverflow(2));
+        s.cancel(EventKind::TimerOverflow(1));
+        assert_eq!(s.next_event_time(), 4);
+    }
+
+    #[test]
+    fn next_time_of_finds_earliest_of_kind() {
+        let mut s = Scheduler::new();
+        s.schedule(8, EventKind::HDraw);
+        s.schedule(2, EventKind::HBlank);
+        s.schedule(5, EventKind::HDraw);
+        assert_eq!(s.next_time_of(EventKind::HDraw), Some(5));
+        assert_eq!(s.next_time_of(EventKind::Dma), None);
+    }
+
+    #[test]
+    fn saved_size_does_not_depend_on_pending_events() {
+        let saved = |s: &Scheduler| {
+            let mut w = StateWriter::new();
+            w.put(s);
+            w.finish()
+        };
+        let mut s = Scheduler::new();
+        let empty = saved(&s).len();
+        s.schedule(8, EventKind::HDraw);
+        s.schedule(2, EventKind::Dma);
+        let state = saved(&s);
+        assert_eq!(state.len(), empty);
+
+        let mut loaded = Scheduler::new();
+        let mut r = StateReader::new(&state);
+        r.take(&mut loaded).unwrap();
+        assert!(r.is_empty());
+        loaded.advance_to(8);
+        assert_eq!(loaded.pop_due(), Some((EventKind::Dma, 2)));
+        assert_eq!(loaded.pop_due(), Some((EventKind::HDraw, 8)));
+    }
+}
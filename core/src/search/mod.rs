@@ -0,0 +1,212 @@
+// RAM search and watches. A search starts with every aligned address in the
+// chosen work RAM regions and narrows them down by comparing each candidate's
+// current value against a constant or the value it had at the last step.
+
+use crate::mem::{Mem, EWRAM_SIZE, IWRAM_SIZE};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SearchRegion {
+    Ewram,
+    Iwram,
+}
+
+impl SearchRegion {
+    pub const ALL: [SearchRegion; 2] = [SearchRegion::Ewram, SearchRegion::Iwram];
+
+    pub fn base(self) -> u32 {
+        match self {
+            SearchRegion::Ewram => 0x0200_0000,
+            SearchRegion::Iwram => 0x0300_0000,
+        }
+    }
+
+    pub fn size(self) -> usize {
+        match self {
+            SearchRegion::Ewram => EWRAM_SIZE,
+            SearchRegion::Iwram => IWRAM_SIZE,
+        }
+    }
+
+    fn of(addr: u32) -> Option<(SearchRegion, usize)> {
+        Self::ALL.into_iter().find_map(|r| {
+            let offset = addr.wrapping_sub(r.base()) as usize;
+            (offset < r.size()).then_some((r, offset))
+        })
+    }
+
+    fn bytes(self, mem: &Mem) -> &[u8] {
+        match self {
+            SearchRegion::Ewram => &mem.ewram,
+            SearchRegion::Iwram => &mem.iwram,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ValueSize {
+    Byte,
+    Half,
+    Word,
+}
+
+impl ValueSize {
+    pub const ALL: [ValueSize; 3] = [ValueSize::Byte, ValueSize::Half, ValueSize::Word];
+
+    pub fn bytes(self) -> usize {
+        match self {
+            ValueSize::Byte => 1,
+            ValueSize::Half => 2,
+            ValueSize::Word => 4,
+        }
+    }
+
+    pub fn mask(self) -> u32 {
+        match self {
+            ValueSize::Byte => 0xFF,
+            ValueSize::Half => 0xFFFF,
+            ValueSize::Word => 0xFFFF_FFFF,
+        }
+    }
+}
+
+/// Reads a little-endian value from EWRAM or IWRAM, without side effects.
+/// Returns `None` for addresses outside both.
+pub fn read(mem: &Mem, addr: u32, size: ValueSize) -> Option<u32> {
+    let (region, offset) = SearchRegion::of(addr)?;
+    let bytes = region.bytes(mem).get(offset..offset + size.bytes())?;
+    Some(bytes.iter().rev().fold(0, |v, &b| (v << 8) | b as u32))
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Equal(u32),
+    NotEqual(u32),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+impl Comparison {
+    fn matches(self, previous: u32, current: u32) -> bool {
+        match self {
+            Comparison::Equal(v) => current == v,
+            Comparison::NotEqual(v) => current != v,
+            Comparison::Changed => current != previous,
+            Comparison::Unchanged => current == previous,
+            Comparison::Increased => current > previous,
+            Comparison::Decreased => current < previous,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    pub addr: u32,
+    /// Value at the last search step.
+    pub value: u32,
+}
+
+pub struct MemorySearch {
+    size: ValueSize,
+    candidates: Vec<Candidate>,
+}
+
+impl MemorySearch {
+    /// Snapshots every `size`-aligned address of `regions`.
+    pub fn new(mem: &Mem, size: ValueSize, regions: &[SearchRegion]) -> Self {
+        let step = size.bytes();
+        let candidates = regions
+            .iter()
+            .flat_map(|&r| (0..r.size()).step_by(step).map(move |offset| r.base() + offset as u32))
+            .map(|addr| Candidate { addr, value: read(mem, addr, size).unwrap() })
+            .collect();
+        Self { size, candidates }
+    }
+
+    pub fn size(&self) -> ValueSize { self.size }
+    pub fn candidates(&self) -> &[Candidate] { &self.candidates }
+    pub fn len(&self) -> usize { self.candidates.len() }
+    pub fn is_empty(&self) -> bool { self.candidates.is_empty() }
+
+    /// Keeps the candidates whose current value passes `comparison`, and
+    /// makes the current values the baseline for the next step.
+    pub fn refine(&mut self, mem: &Mem, comparison: Comparison) {
+        let size = self.size;
+        let comparison = match comparison {
+            Comparison::Equal(v) => Comparison::Equal(v & size.mask()),
+            Comparison::NotEqual(v) => Comparison::NotEqual(v & size.mask()),
+            c => c,
+        };
+        self.candidates.retain_mut(|c| {
+            let current = read(mem, c.addr, size).unwrap();
+            let keep = comparison.matches(c.value, current);
+            c.value = current;
+            keep
+        });
+    }
+}
+
+/// An address whose value is shown every frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watch {
+    pub label: String,
+    pub addr: u32,
+    pub size: ValueSize,
+}
+
+impl Watch {
+    pub fn new(label: &str, addr: u32, size: ValueSize) -> Self {
+        Self { label: label.to_string(), addr, size }
+    }
+
+    pub fn value(&self, mem: &Mem) -> Option<u32> { read(mem, self.addr, self.size) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_little_endian_values() {
+        let mut mem = Mem::new();
+        mem.iwram[0x10..0x14].copy_from_slice(&[0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(read(&mem, 0x0300_0010, ValueSize::Word), Some(0x1234_5678));
+        assert_eq!(read(&mem, 0x0300_0012, ValueSize::Half), Some(0x1234));
+        assert_eq!(read(&mem, 0x0300_7FFF, ValueSize::Word), None);
+        assert_eq!(read(&mem, 0x0600_0000, ValueSize::Byte), None);
+    }
+
+    #[test]
+    fn narrows_down_a_changing_value() {
+        let mut mem = Mem::new();
+        mem.ewram[0x100] = 100;
+        let mut search = MemorySearch::new(&mem, ValueSize::Byte, &SearchRegion::ALL);
+        assert_eq!(search.len(), EWRAM_SIZE + IWRAM_SIZE);
+
+        search.refine(&mem, Comparison::Equal(100));
+        assert_eq!(search.len(), 1);
+
+        mem.ewram[0x100] = 90;
+        mem.ewram[0x200] = 1;
+        let mut search = MemorySearch::new(&mem, ValueSize::Byte, &[SearchRegion::Ewram]);
+        mem.ewram[0x100] = 80;
+        mem.ewram[0x200] = 2;
+        search.refine(&mem, Comparison::Decreased);
+        assert_eq!(search.candidates(), &[Candidate { addr: 0x0200_0100, value: 80 }]);
+
+        search.refine(&mem, Comparison::Unchanged);
+        assert_eq!(search.len(), 1);
+        mem.ewram[0x100] = 81;
+        search.refine(&mem, Comparison::Decreased);
+        assert!(search.is_empty());
+    }
+
+    #[test]
+    fn aligns_candidates_to_the_value_size() {
+        let mem = Mem::new();
+        let search = MemorySearch::new(&mem, ValueSize::Word, &[SearchRegion::Iwram]);
+        assert_eq!(search.len(), IWRAM_SIZE / 4);
+        assert!(search.candidates().iter().all(|c| c.addr % 4 == 0));
+    }
+}
@@ -0,0 +1,71 @@
+// Game Boy Player rumble over the link port. After the boot handshake the
+// game sends one 32-bit normal-mode word per transfer, whose low bits carry
+// the motor command.
+
+use super::SerialDevice;
+
+// Replies the Player gives to the handshake, one per transfer; the last one
+// repeats once the handshake is done.
+const HANDSHAKE: [u32; 13] = [
+    0x0000_494E, 0x0000_494E,
+    0xB6B1_494E, 0xB6B1_544E,
+    0xABB1_544E, 0xABB1_4E45,
+    0xB1BA_4E45, 0xB1BA_4F44,
+    0xB0BB_4F44, 0xB0BB_8002,
+    0x1000_0010, 0x2000_0013,
+    0x3000_0003,
+];
+const CYCLE_LEN: usize = 17;
+const RUMBLE_MASK: u32 = 0x33;
+const RUMBLE_START: u32 = 0x22;
+
+#[derive(Default)]
+pub struct GameBoyPlayer {
+    position: usize,
+    rumble: bool,
+}
+
+impl GameBoyPlayer {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn handshake_done(&self) -> bool { self.position >= HANDSHAKE.len() - 1 }
+}
+
+impl SerialDevice for GameBoyPlayer {
+    fn transfer_normal(&mut self, data: u32, _bits: u32) -> Option<u32> {
+        if self.handshake_done() {
+            // 0x00 stop, 0x11 hard stop, 0x22 start
+            self.rumble = data & RUMBLE_MASK == RUMBLE_START;
+        }
+        let reply = HANDSHAKE[self.position.min(HANDSHAKE.len() - 1)];
+        self.position = (self.position + 1) % CYCLE_LEN;
+        Some(reply)
+    }
+
+    fn transfer_multi(&mut self, send: u16) -> Option<[u16; 4]> { Some([send, 0xFFFF, 0xFFFF, 0xFFFF]) }
+
+    fn provides_clock(&self) -> bool { true }
+
+    fn is_connected(&self) -> bool { true }
+
+    fn rumble(&self) -> bool { self.rumble }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rumble_follows_commands_after_handshake() {
+        let mut gbp = GameBoyPlayer::new();
+        for (i, &expected) in HANDSHAKE.iter().take(12).enumerate() {
+            assert_eq!(gbp.transfer_normal(0x0000_0022, 32), Some(expected), "transfer {}", i);
+            assert!(!gbp.rumble());
+        }
+        assert!(gbp.handshake_done());
+        gbp.transfer_normal(0x4000_0026, 32);
+        assert!(gbp.rumble());
+        gbp.transfer_normal(0x4000_0004, 32);
+        assert!(!gbp.rumble());
+    }
+}
@@ -0,0 +1,329 @@
+pub mod gbp;
+pub mod net;
+
+use crate::io::Io;
+use crate::scheduler::{EventKind, Scheduler};
+use crate::state::impl_savestate;
+
+pub const SIOCNT: u32 = 0x0400_0128;
+
+const SIO_IRQ: u16 = 0x0080;
+const MULTI_BAUD: [u64; 4] = [9600, 38400, 57600, 115200];
+const CPU_CLOCK: u64 = 16_777_216;
+
+/// Something on the other end of the link port.
+pub trait SerialDevice: Send {
+    /// Normal mode: shifts `data` (8 or 32 `bits`) out and returns what the
+    /// peer shifted in. `None` means the answer is still on its way: the
+    /// transfer keeps running and `finish_normal` is asked every scanline.
+    fn transfer_normal(&mut self, data: u32, bits: u32) -> Option<u32>;
+
+    /// Multi-player mode: sends this unit's word and returns SIOMULTI0-3.
+    /// Slots without a connected unit read 0xFFFF. `None` works as for
+    /// `transfer_normal`, with `finish_multi`.
+    fn transfer_multi(&mut self, send: u16) -> Option<[u16; 4]>;
+
+    /// The answer to a normal-mode transfer that returned `None`, once it
+    /// arrived.
+    fn finish_normal(&mut self) -> Option<u32> { Some(0xFFFF_FFFF) }
+
+    /// The answer to a multi-player transfer that returned `None`, once it
+    /// arrived.
+    fn finish_multi(&mut self) -> Option<[u16; 4]> { Some([0xFFFF; 4]) }
+
+    /// Multi-player children don't start transfers; the parent clocks them.
+    /// Polled regularly, returns SIOMULTI0-3 once a parent transfer arrived.
+    fn poll_multi(&mut self, _send: u16) -> Option<[u16; 4]> { None }
+
+    /// Whether the peer drives the clock, so external-clock transfers finish.
+    fn provides_clock(&self) -> bool { false }
+
+    /// Multi-player ID of this unit (0 = parent).
+    fn player_id(&self) -> u8 { 0 }
+
+    fn is_connected(&self) -> bool { false }
+
+    /// Whether the device currently has a rumble motor running.
+    fn rumble(&self) -> bool { false }
+}
+
+/// Stand-in peer answering every transfer with a fixed value. The default
+/// behaves like an empty link port (all ones).
+pub struct DummyPeer {
+    pub response: u32,
+    pub provides_clock: bool,
+}
+
+impl Default for DummyPeer {
+    fn default() -> Self {
+        Self { response: 0xFFFF_FFFF, provides_clock: false }
+    }
+}
+
+impl SerialDevice for DummyPeer {
+    fn transfer_normal(&mut self, _data: u32, bits: u32) -> Option<u32> {
+        Some(if bits == 8 { self.response & 0xFF } else { self.response })
+    }
+
+    fn transfer_multi(&mut self, send: u16) -> Option<[u16; 4]> {
+        Some([send, self.response as u16, 0xFFFF, 0xFFFF])
+    }
+
+    fn provides_clock(&self) -> bool { self.provides_clock }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum SioMode {
+    Normal8,
+    Normal32,
+    Multi,
+    Uart,
+    General,
+}
+
+impl SioMode {
+    pub fn from_registers(siocnt: u16, rcnt: u16) -> Self {
+        if rcnt & 0x8000 != 0 {
+            return SioMode::General;
+        }
+        match (siocnt >> 12) & 3 {
+            0 => SioMode::Normal8,
+            1 => SioMode::Normal32,
+            2 => SioMode::Multi,
+            _ => SioMode::Uart,
+        }
+    }
+}
+
+// What a device answered to a transfer.
+enum Answer {
+    Normal(u32),
+    Multi([u16; 4]),
+    Nothing,
+}
+
+/// Drives transfers for the SIO registers held in `Io`.
+pub struct Sio {
+    device: Box<dyn SerialDevice>,
+    busy: bool,
+    // The transfer's time is up but the device's answer hasn't come yet.
+    waiting: bool,
+}
+
+impl Default for Sio {
+    fn default() -> Self {
+        Self { device: Box::new(DummyPeer::default()), busy: false, waiting: false }
+    }
+}
+
+// The attached device (link peer, Game Boy Player) is host-side and keeps
+// its own state.
+impl_savestate!(Sio { busy, waiting });
+
+impl Sio {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn set_device(&mut self, device: Box<dyn SerialDevice>) { self.device = device; }
+
+    pub fn is_busy(&self) -> bool { self.busy }
+
+    pub fn reset(&mut self) {
+        self.busy = false;
+        self.waiting = false;
+    }
+
+    pub fn rumble(&self) -> bool { self.device.rumble() }
+
+    /// Called after SIOCNT is written; schedules completion when the start
+    /// bit was set and the transfer can be clocked.
+    pub fn check_start(&mut self, io: &Io, scheduler: &mut Scheduler) {
+        if self.busy || io.siocnt & 0x80 == 0 {
+            return;
+        }
+        let cycles = match SioMode::from_registers(io.siocnt, io.rcnt) {
+            mode @ (SioMode::Normal8 | SioMode::Normal32) => {
+                let internal = io.siocnt & 1 != 0;
+                if !internal && !self.device.provides_clock() {
+                    return;
+                }
+                let bits = if mode == SioMode::Normal8 { 8 } else { 32 };
+                let per_bit = if internal && io.siocnt & 2 != 0 { 8 } else { 64 };
+                bits * per_bit
+            }
+            // Start, 16 data and stop bit for each of the four slots.
+            SioMode::Multi => CPU_CLOCK / MULTI_BAUD[(io.siocnt & 3) as usize] * 18 * 4,
+            SioMode::Uart | SioMode::General => return,
+        };
+        self.busy = true;
+        scheduler.schedule(cycles, EventKind::SerialTransfer);
+    }
+
+    /// Completes the running transfer, or leaves it waiting for the device's
+    /// answer. Returns the IF bits to raise.
+    pub fn complete(&mut self, io: &mut Io) -> u16 {
+        let answer = match SioMode::from_registers(io.siocnt, io.rcnt) {
+            SioMode::Normal8 => self.device.transfer_normal(io.siodata8 as u32 & 0xFF, 8).map(Answer::Normal),
+            SioMode::Normal32 => {
+                let out = io.siomulti[0] as u32 | ((io.siomulti[1] as u32) << 16);
+                self.device.transfer_normal(out, 32).map(Answer::Normal)
+            }
+            SioMode::Multi => self.device.transfer_multi(io.siodata8).map(Answer::Multi),
+            SioMode::Uart | SioMode::General => Some(Answer::Nothing),
+        };
+        match answer {
+            Some(answer) => self.finish(io, answer),
+            None => {
+                self.waiting = true;
+                0
+            }
+        }
+    }
+
+    /// Picks up a waiting transfer's answer, and lets a multi-player child
+    /// pick up transfers started by its parent. Returns the IF bits to raise.
+    pub fn poll(&mut self, io: &mut Io) -> u16 {
+        if self.waiting {
+            let answer = match SioMode::from_registers(io.siocnt, io.rcnt) {
+                SioMode::Normal8 | SioMode::Normal32 => self.device.finish_normal().map(Answer::Normal),
+                SioMode::Multi => self.device.finish_multi().map(Answer::Multi),
+                SioMode::Uart | SioMode::General => Some(Answer::Nothing),
+            };
+            return answer.map_or(0, |answer| self.finish(io, answer));
+        }
+        if self.busy || SioMode::from_registers(io.siocnt, io.rcnt) != SioMode::Multi {
+            return 0;
+        }
+        let Some(slots) = self.device.poll_multi(io.siodata8) else {
+            return 0;
+        };
+        self.finish_multi(io, slots);
+        if io.siocnt & 0x4000 != 0 { SIO_IRQ } else { 0 }
+    }
+
+    fn finish(&mut self, io: &mut Io, answer: Answer) -> u16 {
+        self.busy = false;
+        self.waiting = false;
+        match answer {
+            Answer::Normal(data) if SioMode::from_registers(io.siocnt, io.rcnt) == SioMode::Normal8 => {
+                io.siodata8 = (io.siodata8 & 0xFF00) | (data as u16 & 0xFF);
+            }
+            Answer::Normal(data) => {
+                io.siomulti[0] = data as u16;
+                io.siomulti[1] = (data >> 16) as u16;
+            }
+            Answer::Multi(slots) => self.finish_multi(io, slots),
+            Answer::Nothing => {}
+        }
+        io.siocnt &= !0x80;
+        if io.siocnt & 0x4000 != 0 { SIO_IRQ } else { 0 }
+    }
+
+    fn finish_multi(&self, io: &mut Io, slots: [u16; 4]) {
+        let id = self.device.player_id() as u16 & 3;
+        io.siomulti = slots;
+        io.siocnt = (io.siocnt & !0x3C)
+            | (id << 4)
+            | (if id != 0 { 0x04 } else { 0 })
+            | (if self.device.is_connected() { 0x08 } else { 0 });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Echo;
+
+    impl SerialDevice for Echo {
+        fn transfer_normal(&mut self, data: u32, _bits: u32) -> Option<u32> { Some(!data) }
+        fn transfer_multi(&mut self, send: u16) -> Option<[u16; 4]> { Some([send, send, 0xFFFF, 0xFFFF]) }
+    }
+
+    // Answers the second time it is asked, like a peer over the network.
+    struct Late(u32);
+
+    impl SerialDevice for Late {
+        fn transfer_normal(&mut self, _data: u32, _bits: u32) -> Option<u32> { None }
+        fn transfer_multi(&mut self, _send: u16) -> Option<[u16; 4]> { None }
+        fn finish_normal(&mut self) -> Option<u32> {
+            self.0 += 1;
+            (self.0 == 2).then_some(0x5A)
+        }
+    }
+
+    #[test]
+    fn normal8_internal_clock_completes_with_irq() {
+        let mut io = Io::new();
+        let mut s = Scheduler::new();
+        let mut sio = Sio::new();
+        sio.set_device(Box::new(Echo));
+
+        io.siodata8 = 0x5A;
+        io.siocnt = 0x4083; // IRQ, start, 2MHz, internal clock
+        sio.check_start(&io, &mut s);
+        assert!(sio.is_busy());
+        assert_eq!(s.next_event_time(), 64);
+
+        assert_eq!(sio.complete(&mut io), SIO_IRQ);
+        assert_eq!(io.siodata8, 0xA5);
+        assert_eq!(io.siocnt & 0x80, 0);
+    }
+
+    #[test]
+    fn late_answer_keeps_the_transfer_running() {
+        let mut io = Io::new();
+        let mut s = Scheduler::new();
+        let mut sio = Sio::new();
+        sio.set_device(Box::new(Late(0)));
+
+        io.siocnt = 0x4083;
+        sio.check_start(&io, &mut s);
+        assert_eq!(sio.complete(&mut io), 0);
+        assert!(sio.is_busy());
+        assert_eq!(io.siocnt & 0x80, 0x80);
+        assert_eq!(sio.poll(&mut io), 0);
+        assert_eq!(sio.poll(&mut io), SIO_IRQ);
+        assert!(!sio.is_busy());
+        assert_eq!(io.siodata8 & 0xFF, 0x5A);
+        assert_eq!(io.siocnt & 0x80, 0);
+    }
+
+    #[test]
+    fn normal32_uses_dummy_response() {
+        let mut io = Io::new();
+        let mut s = Scheduler::new();
+        let mut sio = Sio::new();
+
+        io.siocnt = 0x1081; // 32-bit, start, 256KHz, internal clock
+        sio.check_start(&io, &mut s);
+        assert_eq!(s.next_event_time(), 32 * 64);
+        assert_eq!(sio.complete(&mut io), 0);
+        assert_eq!(io.siomulti[0], 0xFFFF);
+        assert_eq!(io.siomulti[1], 0xFFFF);
+    }
+
+    #[test]
+    fn external_clock_waits_for_peer() {
+        let io = Io { siocnt: 0x0080, ..Io::default() };
+        let mut s = Scheduler::new();
+        let mut sio = Sio::new();
+        sio.check_start(&io, &mut s);
+        assert!(!sio.is_busy());
+
+        sio.set_device(Box::new(DummyPeer { response: 0x12, provides_clock: true }));
+        sio.check_start(&io, &mut s);
+        assert!(sio.is_busy());
+    }
+
+    #[test]
+    fn multiplayer_fills_all_slots() {
+        let mut io = Io::new();
+        let mut s = Scheduler::new();
+        let mut sio = Sio::new();
+        io.siodata8 = 0x1234;
+        io.siocnt = 0x2083;
+        sio.check_start(&io, &mut s);
+        sio.complete(&mut io);
+        assert_eq!(io.siomulti, [0x1234, 0xFFFF, 0xFFFF, 0xFFFF]);
+    }
+}
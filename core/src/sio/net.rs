@@ -0,0 +1,309 @@
+// TCP link cable between two emulator instances. The hosting side is the
+// multi-player parent (and normal-mode clock master). Each side numbers its
+// transfers, and sends its word tagged with the number when a transfer
+// starts; the n-th transfer on one side pairs with the n-th on the other.
+// Nothing blocks on the peer: the answer is picked up when it has arrived,
+// and a transfer the peer took too long for reads all ones. Its frame is
+// dropped when it turns up late, so the sides stay paired.
+
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use super::SerialDevice;
+
+// Tag, transfer number (u16) and data (u32), little-endian.
+const FRAME_LEN: usize = 7;
+const TAG_NORMAL: u8 = 1;
+const TAG_MULTI: u8 = 2;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum LinkRole {
+    Parent,
+    Child,
+}
+
+struct Frame {
+    tag: u8,
+    seq: u16,
+    data: u32,
+}
+
+// A transfer this side started and has no answer for yet.
+#[derive(Copy, Clone)]
+struct Pending {
+    tag: u8,
+    seq: u16,
+    sent: u32,
+    mask: u32,
+    since: Instant,
+}
+
+pub struct NetLink {
+    stream: TcpStream,
+    role: LinkRole,
+    rx: Vec<u8>,
+    frames: VecDeque<Frame>,
+    // Number of the next transfer; a multi-player child follows its parent's.
+    seq: u16,
+    pending: Option<Pending>,
+    timeout: Duration,
+    connected: bool,
+}
+
+impl NetLink {
+    /// Listens on `addr` and blocks until the other instance connects.
+    pub fn host<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        Self::accept(&listener)
+    }
+
+    pub fn accept(listener: &TcpListener) -> io::Result<Self> {
+        let (stream, peer) = listener.accept()?;
+        log::info!("Link cable: peer {} connected", peer);
+        Self::from_stream(stream, LinkRole::Parent)
+    }
+
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        log::info!("Link cable: connected to {}", stream.peer_addr()?);
+        Self::from_stream(stream, LinkRole::Child)
+    }
+
+    fn from_stream(stream: TcpStream, role: LinkRole) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            stream,
+            role,
+            rx: Vec::new(),
+            frames: VecDeque::new(),
+            seq: 0,
+            pending: None,
+            timeout: DEFAULT_TIMEOUT,
+            connected: true,
+        })
+    }
+
+    pub fn role(&self) -> LinkRole { self.role }
+    pub fn is_connected(&self) -> bool { self.connected }
+
+    /// How long a transfer waits for the peer before treating it as absent.
+    pub fn set_timeout(&mut self, timeout: Duration) { self.timeout = timeout; }
+
+    fn send(&mut self, tag: u8, seq: u16, data: u32) {
+        let mut frame = [0u8; FRAME_LEN];
+        frame[0] = tag;
+        frame[1..3].copy_from_slice(&seq.to_le_bytes());
+        frame[3..].copy_from_slice(&data.to_le_bytes());
+        if let Err(e) = self.stream.write_all(&frame) {
+            self.disconnect(e);
+        }
+    }
+
+    // Reads whatever the peer has sent so far, without waiting.
+    fn fill(&mut self) {
+        if !self.connected {
+            return;
+        }
+        let _ = self.stream.set_nonblocking(true);
+        let mut buf = [0u8; 64];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    self.disconnect(io::Error::new(ErrorKind::UnexpectedEof, "peer closed the link"));
+                    break;
+                }
+                Ok(n) => self.rx.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.disconnect(e);
+                    break;
+                }
+            }
+        }
+        let _ = self.stream.set_nonblocking(false);
+        let whole = self.rx.len() / FRAME_LEN * FRAME_LEN;
+        for frame in self.rx.drain(..whole).collect::<Vec<_>>().chunks_exact(FRAME_LEN) {
+            self.frames.push_back(Frame {
+                tag: frame[0],
+                seq: u16::from_le_bytes([frame[1], frame[2]]),
+                data: u32::from_le_bytes([frame[3], frame[4], frame[5], frame[6]]),
+            });
+        }
+    }
+
+    // The oldest `tag` frame from transfer `seq` or later. Older ones are
+    // dropped: they answer transfers this side already gave up on.
+    fn next_frame(&mut self, tag: u8, seq: u16) -> Option<&Frame> {
+        self.fill();
+        while let Some(frame) = self.frames.front() {
+            if frame.tag != tag {
+                log::warn!("Link cable: dropping unexpected frame tag {}", frame.tag);
+            } else if (frame.seq.wrapping_sub(seq) as i16) < 0 {
+                log::debug!("Link cable: dropping late frame from transfer {}", frame.seq);
+            } else {
+                break;
+            }
+            self.frames.pop_front();
+        }
+        self.frames.front()
+    }
+
+    fn start(&mut self, tag: u8, data: u32, mask: u32) {
+        let seq = self.seq;
+        self.seq = seq.wrapping_add(1);
+        self.send(tag, seq, data);
+        self.pending = Some(Pending { tag, seq, sent: data, mask, since: Instant::now() });
+    }
+
+    // The peer's word for the pending transfer: all ones without a peer, or
+    // once it took longer than the timeout.
+    fn answer(&mut self) -> Option<u32> {
+        let Some(pending) = self.pending else {
+            return Some(0xFFFF_FFFF);
+        };
+        let data = if let Some(frame) = self.next_frame(pending.tag, pending.seq)
+            && frame.seq == pending.seq
+        {
+            self.frames.pop_front().map(|frame| frame.data)
+        } else if !self.connected {
+            Some(0xFFFF_FFFF)
+        } else if pending.since.elapsed() >= self.timeout {
+            log::debug!("Link cable: peer did not answer transfer {} in time", pending.seq);
+            Some(0xFFFF_FFFF)
+        } else {
+            None
+        }?;
+        self.pending = None;
+        Some(data & pending.mask)
+    }
+
+    fn disconnect(&mut self, e: io::Error) {
+        if self.connected {
+            log::warn!("Link cable: disconnected ({})", e);
+        }
+        self.connected = false;
+    }
+}
+
+impl SerialDevice for NetLink {
+    fn transfer_normal(&mut self, data: u32, bits: u32) -> Option<u32> {
+        let mask = if bits == 8 { 0xFF } else { 0xFFFF_FFFF };
+        self.start(TAG_NORMAL, data, mask);
+        None
+    }
+
+    fn transfer_multi(&mut self, send: u16) -> Option<[u16; 4]> {
+        if self.role == LinkRole::Child {
+            // Children are clocked by the parent; see `poll_multi`.
+            return Some([0xFFFF, send, 0xFFFF, 0xFFFF]);
+        }
+        self.start(TAG_MULTI, send as u32, 0xFFFF);
+        None
+    }
+
+    fn finish_normal(&mut self) -> Option<u32> { self.answer() }
+
+    fn finish_multi(&mut self) -> Option<[u16; 4]> {
+        let sent = self.pending.map_or(0xFFFF, |pending| pending.sent as u16);
+        let child = self.answer()? as u16;
+        Some([sent, child, 0xFFFF, 0xFFFF])
+    }
+
+    fn poll_multi(&mut self, send: u16) -> Option<[u16; 4]> {
+        if self.role != LinkRole::Child {
+            return None;
+        }
+        let frame = self.next_frame(TAG_MULTI, self.seq)?;
+        let (seq, parent) = (frame.seq, frame.data as u16);
+        self.frames.pop_front();
+        self.seq = seq.wrapping_add(1);
+        self.send(TAG_MULTI, seq, send as u32);
+        Some([parent, send, 0xFFFF, 0xFFFF])
+    }
+
+    fn provides_clock(&self) -> bool { self.role == LinkRole::Child && self.connected }
+
+    fn player_id(&self) -> u8 {
+        match self.role {
+            LinkRole::Parent => 0,
+            LinkRole::Child => 1,
+        }
+    }
+
+    fn is_connected(&self) -> bool { self.connected }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn pair() -> (NetLink, NetLink) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || NetLink::connect(addr).unwrap());
+        let host = NetLink::accept(&listener).unwrap();
+        (host, client.join().unwrap())
+    }
+
+    // Polls like `Sio` does each scanline until the answer is there.
+    fn wait<T>(mut answer: impl FnMut() -> Option<T>) -> T {
+        loop {
+            if let Some(value) = answer() {
+                return value;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn multiplayer_transfer_reaches_both_sides() {
+        let (mut parent, mut child) = pair();
+        let child_thread = thread::spawn(move || wait(|| child.poll_multi(0xBEEF)));
+        assert_eq!(parent.transfer_multi(0x1234), None);
+        let parent_slots = wait(|| parent.finish_multi());
+        let child_slots = child_thread.join().unwrap();
+        assert_eq!(parent_slots, [0x1234, 0xBEEF, 0xFFFF, 0xFFFF]);
+        assert_eq!(child_slots, [0x1234, 0xBEEF, 0xFFFF, 0xFFFF]);
+    }
+
+    #[test]
+    fn normal_transfer_swaps_data() {
+        let (mut parent, mut child) = pair();
+        assert_eq!(parent.transfer_normal(0x0102_0304, 32), None);
+        assert_eq!(child.transfer_normal(0xCAFE_F00D, 32), None);
+        assert_eq!(wait(|| parent.finish_normal()), 0xCAFE_F00D);
+        assert_eq!(wait(|| child.finish_normal()), 0x0102_0304);
+    }
+
+    #[test]
+    fn late_answers_are_dropped() {
+        let (mut parent, mut child) = pair();
+        parent.set_timeout(Duration::from_millis(20));
+        parent.transfer_normal(1, 32);
+        assert_eq!(wait(|| parent.finish_normal()), 0xFFFF_FFFF);
+        // The child's first transfer still pairs with the parent's first,
+        // and its answer no longer reaches the parent's second.
+        child.transfer_normal(0xAAAA, 32);
+        assert_eq!(wait(|| child.finish_normal()), 1);
+        parent.transfer_normal(2, 32);
+        child.transfer_normal(0xBBBB, 32);
+        assert_eq!(wait(|| parent.finish_normal()), 0xBBBB);
+        assert_eq!(wait(|| child.finish_normal()), 2);
+    }
+
+    #[test]
+    fn missing_peer_reads_all_ones() {
+        let (mut parent, child) = pair();
+        parent.set_timeout(Duration::from_millis(20));
+        parent.transfer_multi(0x0001);
+        assert_eq!(wait(|| parent.finish_multi()), [0x0001, 0xFFFF, 0xFFFF, 0xFFFF]);
+        drop(child);
+        parent.transfer_normal(0, 8);
+        assert_eq!(wait(|| parent.finish_normal()), 0xFF);
+    }
+}
@@ -0,0 +1,267 @@
+// Binary savestates. Each component writes its fields in a fixed order; the
+// format is only meant to be read back by the same build of the core, so the
+// version is bumped whenever a component's field list changes.
+
+use std::fmt;
+
+pub mod rewind;
+
+pub use rewind::RewindBuffer;
+
+pub const STATE_MAGIC: [u8; 4] = *b"RBST";
+pub const STATE_VERSION: u16 = 19;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StateError {
+    BadMagic,
+    Version(u16),
+    /// The state was made with a different ROM (CRC32 of the image).
+    RomMismatch { expected: u32, found: u32 },
+    Truncated,
+    /// A buffer or enum value that cannot belong to this build.
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a RoBA savestate"),
+            StateError::Version(v) => {
+                write!(f, "savestate version {} is not supported (expected {})", v, STATE_VERSION)
+            }
+            StateError::RomMismatch { expected, found } => write!(
+                f,
+                "savestate is for a different ROM (CRC32 {:08X}, loaded {:08X})",
+                found, expected
+            ),
+            StateError::Truncated => write!(f, "savestate is truncated"),
+            StateError::Corrupt(what) => write!(f, "savestate is corrupt: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// 64-bit FNV-1a. Unlike the std hashers its output is fixed, so values can
+/// be stored as golden results in tests.
+pub fn hash64(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF2_9CE4_8422_2325, |h, &b| (h ^ b as u64).wrapping_mul(0x0000_0100_0000_01B3))
+}
+
+/// State that can be written to and restored from a savestate.
+pub trait Savestate {
+    fn save_state(&self, w: &mut StateWriter);
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError>;
+}
+
+#[derive(Default)]
+pub struct StateWriter {
+    buf: Vec<u8>,
+    // Leaves out EWRAM, IWRAM and VRAM, for rewind snapshots.
+    without_ram: bool,
+}
+
+impl StateWriter {
+    pub fn new() -> Self { Self::default() }
+
+    pub(crate) fn without_ram() -> Self { Self { without_ram: true, ..Self::default() } }
+
+    pub fn with_ram(&self) -> bool { !self.without_ram }
+
+    pub fn put<T: Savestate + ?Sized>(&mut self, value: &T) { value.save_state(self); }
+
+    pub fn bytes(&mut self, data: &[u8]) { self.buf.extend_from_slice(data); }
+
+    pub fn finish(self) -> Vec<u8> { self.buf }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    without_ram: bool,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self { Self { data, without_ram: false } }
+
+    /// Reads what `StateWriter::without_ram` wrote.
+    pub(crate) fn without_ram(data: &'a [u8]) -> Self { Self { data, without_ram: true } }
+
+    pub fn with_ram(&self) -> bool { !self.without_ram }
+
+    pub fn take<T: Savestate + ?Sized>(&mut self, value: &mut T) -> Result<(), StateError> {
+        value.load_state(self)
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    pub fn is_empty(&self) -> bool { self.data.is_empty() }
+}
+
+macro_rules! savestate_int {
+    ($($ty:ty),*) => {$(
+        impl Savestate for $ty {
+            fn save_state(&self, w: &mut StateWriter) { w.bytes(&self.to_le_bytes()); }
+            fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+                *self = <$ty>::from_le_bytes(r.array()?);
+                Ok(())
+            }
+        }
+    )*};
+}
+
+savestate_int!(u8, u16, u32, u64, u128, i8, i16, i32);
+
+impl Savestate for usize {
+    fn save_state(&self, w: &mut StateWriter) { w.put(&(*self as u64)); }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let mut v = 0u64;
+        r.take(&mut v)?;
+        *self = usize::try_from(v).map_err(|_| StateError::Corrupt("usize out of range"))?;
+        Ok(())
+    }
+}
+
+impl Savestate for bool {
+    fn save_state(&self, w: &mut StateWriter) { w.put(&(*self as u8)); }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        *self = match r.array::<1>()? {
+            [0] => false,
+            [1] => true,
+            _ => return Err(StateError::Corrupt("bool")),
+        };
+        Ok(())
+    }
+}
+
+impl<T: Savestate, const N: usize> Savestate for [T; N] {
+    fn save_state(&self, w: &mut StateWriter) {
+        for v in self {
+            w.put(v);
+        }
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        for v in self {
+            r.take(v)?;
+        }
+        Ok(())
+    }
+}
+
+// Memory buffers have a fixed size per build, so a length mismatch means the
+// state does not belong here.
+impl Savestate for Vec<u8> {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.put(&(self.len() as u32));
+        w.bytes(self);
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let mut len = 0u32;
+        r.take(&mut len)?;
+        if len as usize != self.len() {
+            return Err(StateError::Corrupt("buffer size"));
+        }
+        self.copy_from_slice(r.bytes(len as usize)?);
+        Ok(())
+    }
+}
+
+impl Savestate for Vec<u16> {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.put(&(self.len() as u32));
+        for v in self {
+            w.put(v);
+        }
+    }
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let mut len = 0u32;
+        r.take(&mut len)?;
+        if len as usize != self.len() {
+            return Err(StateError::Corrupt("buffer size"));
+        }
+        for v in self.iter_mut() {
+            r.take(v)?;
+        }
+        Ok(())
+    }
+}
+
+/// Implements `Savestate` by saving the listed fields in order.
+macro_rules! impl_savestate {
+    ($ty:ty { $($field:tt),* $(,)? }) => {
+        impl $crate::state::Savestate for $ty {
+            fn save_state(&self, w: &mut $crate::state::StateWriter) {
+                $( w.put(&self.$field); )*
+            }
+            fn load_state(
+                &mut self,
+                r: &mut $crate::state::StateReader,
+            ) -> Result<(), $crate::state::StateError> {
+                $( r.take(&mut self.$field)?; )*
+                Ok(())
+            }
+        }
+    };
+}
+
+pub(crate) use impl_savestate;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash64_is_fnv1a() {
+        assert_eq!(hash64(b""), 0xCBF2_9CE4_8422_2325);
+        assert_eq!(hash64(b"a"), 0xAF63_DC4C_8601_EC8C);
+    }
+
+    #[derive(Default, Debug, PartialEq)]
+    struct Sample {
+        a: u16,
+        b: bool,
+        c: [i32; 2],
+        d: Vec<u8>,
+    }
+
+    impl_savestate!(Sample { a, b, c, d });
+
+    #[test]
+    fn fields_round_trip() {
+        let sample = Sample { a: 0xBEEF, b: true, c: [-1, 7], d: vec![1, 2, 3] };
+        let mut w = StateWriter::new();
+        w.put(&sample);
+        let data = w.finish();
+
+        let mut loaded = Sample { d: vec![0; 3], ..Default::default() };
+        let mut r = StateReader::new(&data);
+        r.take(&mut loaded).unwrap();
+        assert!(r.is_empty());
+        assert_eq!(loaded, sample);
+    }
+
+    #[test]
+    fn short_or_mismatched_data_is_rejected() {
+        let mut w = StateWriter::new();
+        w.put(&vec![0u8; 4]);
+        let data = w.finish();
+
+        let mut wrong_size = vec![0u8; 8];
+        assert_eq!(
+            StateReader::new(&data).take(&mut wrong_size),
+            Err(StateError::Corrupt("buffer size"))
+        );
+        let mut right_size = vec![0u8; 4];
+        assert_eq!(StateReader::new(&data[..5]).take(&mut right_size), Err(StateError::Truncated));
+    }
+}
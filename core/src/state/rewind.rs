@@ -0,0 +1,281 @@
+// Rewind history. The machine is captured in two parts: EWRAM, IWRAM and
+// VRAM page by page, copying only the pages the bus marked as written since
+// the last capture, and everything else as a `save_snapshot`. Only the
+// newest capture is kept whole; each older one is stored as the XOR against
+// its successor with runs of unchanged bytes collapsed, which is a few KiB
+// per frame for typical games.
+
+use super::StateError;
+use crate::mem::{PAGE_SIZE, TRACKED_PAGES};
+use crate::Emulator;
+use std::collections::VecDeque;
+
+pub struct RewindBuffer {
+    interval: u32,
+    capacity: usize,
+    frames: u32,
+    // Tracked RAM as of the newest capture, by page number; empty until the
+    // first one.
+    ram: Vec<u8>,
+    // The rest of the machine as of the newest capture.
+    newest: Vec<u8>,
+    // Oldest first; applying the last step to `ram` and `newest` yields the
+    // capture taken before it.
+    steps: VecDeque<Step>,
+}
+
+struct Step {
+    // Delta of every page that changed, by page number.
+    pages: Vec<(u16, Vec<u8>)>,
+    rest: Vec<u8>,
+}
+
+impl Step {
+    fn len(&self) -> usize { self.rest.len() + self.pages.iter().map(|(_, delta)| 2 + delta.len()).sum::<usize>() }
+}
+
+impl RewindBuffer {
+    /// Keeps one snapshot every `interval` frames, up to `capacity` steps back.
+    pub fn new(interval: u32, capacity: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            capacity,
+            frames: 0,
+            ram: Vec::new(),
+            newest: Vec::new(),
+            steps: VecDeque::new(),
+        }
+    }
+
+    /// Sized for `seconds` of history at 60 fps.
+    pub fn with_duration(interval: u32, seconds: u32) -> Self {
+        Self::new(interval, (seconds * 60 / interval.max(1)) as usize)
+    }
+
+    /// Number of steps `pop` can go back.
+    pub fn len(&self) -> usize { self.steps.len() }
+
+    pub fn is_empty(&self) -> bool { self.steps.is_empty() }
+
+    /// Bytes held by the history.
+    pub fn memory_usage(&self) -> usize {
+        self.ram.len() + self.newest.len() + self.steps.iter().map(Step::len).sum::<usize>()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.ram.clear();
+        self.newest.clear();
+        self.steps.clear();
+    }
+
+    /// Called once per emulated frame; captures `core` every `interval`
+    /// frames.
+    pub fn on_frame(&mut self, core: &mut Emulator) {
+        self.frames += 1;
+        if self.frames >= self.interval {
+            self.frames = 0;
+            self.push(core);
+        }
+    }
+
+    /// Captures `core`. The history owns its dirty pages from here on; see
+    /// `Emulator::take_dirty_pages`.
+    pub fn push(&mut self, core: &mut Emulator) {
+        let dirty = core.take_dirty_pages();
+        let rest = core.save_snapshot();
+        let mem = &core.bus().mem;
+        if self.ram.is_empty() || self.newest.len() != rest.len() {
+            // First capture, or a different ROM/core layout.
+            self.steps.clear();
+            self.ram = (0..TRACKED_PAGES).flat_map(|page| mem.page(page)).copied().collect();
+        } else if self.capacity > 0 {
+            let mut pages = Vec::new();
+            for page in dirty.iter() {
+                let (old, new) = (&mut self.ram[page * PAGE_SIZE..][..PAGE_SIZE], mem.page(page));
+                if old != new {
+                    pages.push((page as u16, encode_delta(old, new)));
+                    old.copy_from_slice(new);
+                }
+            }
+            self.steps.push_back(Step { pages, rest: encode_delta(&self.newest, &rest) });
+            if self.steps.len() > self.capacity {
+                self.steps.pop_front();
+            }
+        } else {
+            for page in dirty.iter() {
+                self.ram[page * PAGE_SIZE..][..PAGE_SIZE].copy_from_slice(mem.page(page));
+            }
+        }
+        self.newest = rest;
+    }
+
+    /// Steps back one snapshot and loads it into `core`; `None` when the
+    /// history is empty. A failed load leaves `core` as it was.
+    pub fn pop(&mut self, core: &mut Emulator) -> Option<Result<(), StateError>> {
+        let step = self.steps.pop_back()?;
+        for (page, delta) in &step.pages {
+            apply_delta(&mut self.ram[*page as usize * PAGE_SIZE..][..PAGE_SIZE], delta);
+        }
+        apply_delta(&mut self.newest, &step.rest);
+        self.frames = 0;
+        Some(core.load_snapshot(&self.newest, &self.ram))
+    }
+}
+
+// Delta format: repeated (unchanged run, changed run, changed run XOR bytes),
+// run lengths as LEB128.
+fn encode_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < new.len() {
+        let same = old[i..].iter().zip(&new[i..]).take_while(|(a, b)| a == b).count();
+        i += same;
+        let changed = old[i..].iter().zip(&new[i..]).take_while(|(a, b)| a != b).count();
+        put_varint(&mut out, same);
+        put_varint(&mut out, changed);
+        out.extend(old[i..i + changed].iter().zip(&new[i..]).map(|(a, b)| a ^ b));
+        i += changed;
+    }
+    out
+}
+
+fn apply_delta(state: &mut [u8], delta: &[u8]) {
+    let mut pos = 0;
+    let mut i = 0;
+    while pos < delta.len() {
+        i += get_varint(delta, &mut pos);
+        let changed = get_varint(delta, &mut pos);
+        for (s, d) in state[i..i + changed].iter_mut().zip(&delta[pos..pos + changed]) {
+            *s ^= d;
+        }
+        pos += changed;
+        i += changed;
+    }
+}
+
+fn put_varint(out: &mut Vec<u8>, mut v: usize) {
+    while v >= 0x80 {
+        out.push(v as u8 | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn get_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut v = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        v |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return v;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(seed: u8) -> Vec<u8> {
+        let mut state = vec![0u8; 4096];
+        state[10] = seed;
+        state[2000..2300].fill(seed.wrapping_mul(3));
+        state[4095] = !seed;
+        state
+    }
+
+    // Leaves `seed` in a register, EWRAM and VRAM.
+    fn run_to(emu: &mut Emulator, seed: u8) {
+        emu.cpu_mut().write_reg(0, seed as u32);
+        emu.bus_mut().poke8(0x0200_1000, seed);
+        emu.bus_mut().poke16(0x0601_0000 + seed as u32 * 2, seed as u16);
+    }
+
+    fn seed_of(emu: &Emulator) -> (u32, u8, u16) {
+        let mem = &emu.bus().mem;
+        let seed = emu.cpu().read_reg(0);
+        let vram = u16::from_le_bytes([mem.vram[0x1_0000 + seed as usize * 2], mem.vram[0x1_0001 + seed as usize * 2]]);
+        (seed, mem.ewram[0x1000], vram)
+    }
+
+    #[test]
+    fn delta_restores_previous_snapshot() {
+        let (a, b) = (snapshot(1), snapshot(2));
+        let delta = encode_delta(&a, &b);
+        assert!(delta.len() < 400);
+        let mut restored = b.clone();
+        apply_delta(&mut restored, &delta);
+        assert_eq!(restored, a);
+    }
+
+    #[test]
+    fn pops_back_through_history_and_drops_oldest() {
+        let mut emu = Emulator::new();
+        let mut rewind = RewindBuffer::new(2, 3);
+        for seed in 0..10u8 {
+            run_to(&mut emu, seed);
+            rewind.on_frame(&mut emu);
+        }
+        // Captured on every second frame: seeds 1, 3, 5, 7, 9.
+        assert_eq!(rewind.len(), 3);
+        for seed in [7u8, 5, 3] {
+            assert_eq!(rewind.pop(&mut emu), Some(Ok(())));
+            assert_eq!(seed_of(&emu), (seed as u32, seed, seed as u16));
+        }
+        // Pages the later seeds wrote are back as they were.
+        assert_eq!(emu.bus().mem.vram[0x1_0000 + 5 * 2], 0);
+        assert_eq!(rewind.pop(&mut emu), None);
+    }
+
+    #[test]
+    fn steps_hold_only_the_written_pages() {
+        let mut emu = Emulator::new();
+        let mut rewind = RewindBuffer::new(1, 8);
+        rewind.push(&mut emu);
+        let full = rewind.memory_usage();
+        assert!(full > (TRACKED_PAGES * PAGE_SIZE));
+        for seed in 1..=4 {
+            run_to(&mut emu, seed);
+            rewind.push(&mut emu);
+        }
+        assert!(rewind.memory_usage() - full < 4 * 1024, "{} bytes", rewind.memory_usage() - full);
+        // Writes that change nothing store no page.
+        run_to(&mut emu, 4);
+        rewind.push(&mut emu);
+        assert!(rewind.steps.back().unwrap().pages.is_empty());
+    }
+
+    #[test]
+    fn resets_and_state_loads_are_captured_in_full() {
+        let mut emu = Emulator::new();
+        let mut rewind = RewindBuffer::new(1, 8);
+        run_to(&mut emu, 1);
+        let state = emu.save_state();
+        rewind.push(&mut emu);
+        emu.bus_mut().mem.iwram[0x100] = 0xAA;
+        emu.hard_reset();
+        rewind.push(&mut emu);
+        run_to(&mut emu, 2);
+        emu.load_state(&state).unwrap();
+        rewind.push(&mut emu);
+        assert_eq!(rewind.pop(&mut emu), Some(Ok(())));
+        assert_eq!(seed_of(&emu).1, 0);
+        assert_eq!(rewind.pop(&mut emu), Some(Ok(())));
+        assert_eq!(seed_of(&emu), (1, 1, 1));
+        assert_eq!(emu.bus().mem.iwram[0x100], 0);
+    }
+
+    #[test]
+    fn another_rom_is_refused() {
+        let mut emu = Emulator::new();
+        let mut rewind = RewindBuffer::new(1, 8);
+        rewind.push(&mut emu);
+        rewind.push(&mut emu);
+        emu.load_rom_bytes(&[1; 0x200]).unwrap();
+        assert!(matches!(rewind.pop(&mut emu), Some(Err(StateError::RomMismatch { .. }))));
+    }
+}
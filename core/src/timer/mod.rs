@@ -0,0 +1,200 @@
+use crate::scheduler::{EventKind, Scheduler};
+use crate::state::impl_savestate;
+
+pub const TIMER_BASE: u32 = 0x0400_0100;
+pub const TIMER_END: u32 = 0x0400_0110;
+
+const PRESCALER_SHIFT: [u32; 4] = [0, 6, 8, 10];
+
+#[derive(Default, Clone, Copy)]
+pub struct Timer {
+    reload: u16,
+    counter: u16,
+    control: u16,
+    start: u64,
+}
+
+impl_savestate!(Timer { reload, counter, control, start });
+
+impl Timer {
+    pub fn reload(&self) -> u16 { self.reload }
+    pub fn control(&self) -> u16 { self.control }
+    pub fn enabled(&self) -> bool { self.control & 0x80 != 0 }
+    pub fn irq_enabled(&self) -> bool { self.control & 0x40 != 0 }
+    fn cascade(&self) -> bool { self.control & 0x04 != 0 }
+    fn shift(&self) -> u32 { PRESCALER_SHIFT[(self.control & 3) as usize] }
+
+    fn counter_at(&self, now: u64, free_running: bool) -> u16 {
+        if free_running {
+            let ticks = (now - self.start) >> self.shift();
+            self.counter.wrapping_add(ticks as u16)
+        } else {
+            self.counter
+        }
+    }
+
+    fn overflow_time(&self) -> u64 {
+        self.start + ((0x1_0000 - self.counter as u64) << self.shift())
+    }
+}
+
+/// The four hardware timers. Free-running timers keep a start timestamp and
+/// derive their counter lazily; overflows are delivered as scheduler events.
+#[derive(Default)]
+pub struct Timers {
+    timers: [Timer; 4],
+    counter_reads: u64,
+}
+
+impl_savestate!(Timers { timers, counter_reads });
+
+impl Timers {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn timer(&self, index: usize) -> &Timer { &self.timers[index] }
+
+    /// Number of counter reads from running timers, used to tell apart loops
+    /// that poll a value changing between scheduled events.
+    pub fn counter_reads(&self) -> u64 { self.counter_reads }
+
+    fn free_running(&self, index: usize) -> bool {
+        let t = &self.timers[index];
+        t.enabled() && (index == 0 || !t.cascade())
+    }
+
+    pub fn counter(&self, index: usize, now: u64) -> u16 {
+        self.timers[index].counter_at(now, self.free_running(index))
+    }
+
+    pub fn read8(&mut self, addr: u32, now: u64) -> u8 {
+        let index = ((addr - TIMER_BASE) >> 2) as usize;
+        if addr & 2 == 0 && self.timers[index].enabled() {
+            self.counter_reads += 1;
+        }
+        self.peek8(addr, now)
+    }
+
+    /// `read8` for debuggers, leaving the read statistics alone.
+    pub fn peek8(&self, addr: u32, now: u64) -> u8 {
+        let index = ((addr - TIMER_BASE) >> 2) as usize;
+        match addr & 3 {
+            0 | 1 => (self.counter(index, now) >> ((addr & 1) * 8)) as u8,
+            2 => self.timers[index].control as u8,
+            _ => 0,
+        }
+    }
+
+    pub fn write8(&mut self, addr: u32, value: u8, scheduler: &mut Scheduler) {
+        let index = ((addr - TIMER_BASE) >> 2) as usize;
+        match addr & 3 {
+            0 => self.timers[index].reload = (self.timers[index].reload & 0xFF00) | value as u16,
+            1 => self.timers[index].reload = (self.timers[index].reload & 0x00FF) | ((value as u16) << 8),
+            2 => self.write_control(index, value as u16 & 0xC7, scheduler),
+            _ => {}
+        }
+    }
+
+    fn write_control(&mut self, index: usize, control: u16, scheduler: &mut Scheduler) {
+        let now = scheduler.now();
+        let was_enabled = self.timers[index].enabled();
+        let counter = self.counter(index, now);
+
+        let t = &mut self.timers[index];
+        t.counter = if !was_enabled && control & 0x80 != 0 { t.reload } else { counter };
+        t.control = control;
+        t.start = now;
+
+        scheduler.cancel(EventKind::TimerOverflow(index));
+        if self.free_running(index) {
+            scheduler.schedule_at(self.timers[index].overflow_time(), EventKind::TimerOverflow(index));
+        }
+    }
+
+    /// Handles the overflow event of a free-running timer at `time`, including
+    /// count-up cascades. Returns the IF bits to raise.
+    pub fn handle_overflow(&mut self, index: usize, time: u64, scheduler: &mut Scheduler) -> u16 {
+        let t = &mut self.timers[index];
+        t.counter = t.reload;
+        t.start = time;
+        scheduler.schedule_at(t.overflow_time(), EventKind::TimerOverflow(index));
+
+        let mut irq = if t.irq_enabled() { 0x0008 << index } else { 0 };
+        for next in index + 1..4 {
+            let t = &mut self.timers[next];
+            if !(t.enabled() && t.cascade()) {
+                break;
+            }
+            t.counter = t.counter.wrapping_add(1);
+            if t.counter != 0 {
+                break;
+            }
+            t.counter = t.reload;
+            if t.irq_enabled() {
+                irq |= 0x0008 << next;
+            }
+        }
+        irq
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start_timer(timers: &mut Timers, s: &mut Scheduler, index: usize, reload: u16, control: u8) {
+        let base = TIMER_BASE + index as u32 * 4;
+        timers.write8(base, reload as u8, s);
+        timers.write8(base + 1, (reload >> 8) as u8, s);
+        timers.write8(base + 2, control, s);
+    }
+
+    #[test]
+    fn counter_advances_with_prescaler() {
+        let mut s = Scheduler::new();
+        let mut timers = Timers::new();
+        start_timer(&mut timers, &mut s, 0, 0xFF00, 0x81); // /64
+        s.advance(64 * 3 + 10);
+        assert_eq!(timers.counter(0, s.now()), 0xFF03);
+        assert_eq!(s.next_event_time(), 64 * 0x100);
+    }
+
+    #[test]
+    fn overflow_reloads_and_raises_irq() {
+        let mut s = Scheduler::new();
+        let mut timers = Timers::new();
+        start_timer(&mut timers, &mut s, 2, 0xFFF0, 0xC0);
+        s.advance_to(s.next_event_time());
+        let (kind, time) = s.pop_due().unwrap();
+        assert_eq!(kind, EventKind::TimerOverflow(2));
+        assert_eq!(time, 0x10);
+        assert_eq!(timers.handle_overflow(2, time, &mut s), 0x0020);
+        assert_eq!(timers.counter(2, s.now()), 0xFFF0);
+        assert_eq!(s.next_event_time(), 0x20);
+    }
+
+    #[test]
+    fn cascade_counts_overflows() {
+        let mut s = Scheduler::new();
+        let mut timers = Timers::new();
+        start_timer(&mut timers, &mut s, 1, 0xFFFE, 0xC4);
+        start_timer(&mut timers, &mut s, 0, 0xFFFE, 0x80);
+        assert_eq!(s.next_event_time(), 2);
+
+        assert_eq!(timers.handle_overflow(0, 2, &mut s), 0);
+        assert_eq!(timers.counter(1, 2), 0xFFFF);
+        assert_eq!(timers.handle_overflow(0, 4, &mut s), 0x0010);
+        assert_eq!(timers.counter(1, 4), 0xFFFE);
+    }
+
+    #[test]
+    fn disabling_latches_counter() {
+        let mut s = Scheduler::new();
+        let mut timers = Timers::new();
+        start_timer(&mut timers, &mut s, 3, 0x1000, 0x80);
+        s.advance(5);
+        timers.write8(TIMER_BASE + 14, 0x00, &mut s);
+        s.advance(100);
+        assert_eq!(timers.counter(3, s.now()), 0x1005);
+        assert_eq!(s.next_event_time(), u64::MAX);
+    }
+}
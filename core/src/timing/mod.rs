@@ -1,6 +1,182 @@
+use crate::bus::BusAccess;
+use crate::cpu::{Cpu, CpuState};
+
+// Longest loop body (in bytes) the idle loop detector will consider.
+const IDLE_LOOP_MAX_BYTES: u32 = 64;
+
+#[derive(Clone, Copy)]
+struct LoopCandidate {
+    head: u32,
+    tail: u32,
+    snapshot: [u32; 17],
+    external: (u64, u64),
+    arrived_at: u64,
+    // Cycles of the last pass, once two consecutive passes started from the
+    // same state.
+    period: Option<u64>,
+}
+
+/// Recognizes loops that cannot make progress until an external event
+/// (PPU phase change, timer overflow, interrupt) changes what they read.
+///
+/// A loop qualifies when its body is a short backward branch target whose
+/// instructions never write memory or touch mode state, and the machine state
+/// at the loop head is identical on consecutive passes. Such a loop is
+/// periodic, so whole iterations can be skipped without any observable
+/// difference as long as no event lands inside the skipped window.
 #[derive(Default)]
-pub struct Timing;
+pub struct IdleLoopDetector {
+    candidate: Option<LoopCandidate>,
+    prev_pc: u32,
+    skipped_cycles: u64,
+}
+
+impl IdleLoopDetector {
+    pub fn new() -> Self { Self::default() }
+
+    pub fn reset(&mut self) { *self = Self::default(); }
+
+    pub fn skipped_cycles(&self) -> u64 { self.skipped_cycles }
+
+    pub fn record_skip(&mut self, cycles: u64) {
+        self.skipped_cycles += cycles;
+        if let Some(c) = self.candidate.as_mut() {
+            c.arrived_at += cycles;
+        }
+    }
+
+    /// Called before each CPU step. Returns the loop period in cycles when the
+    /// CPU is at the head of a loop that is known to repeat unchanged.
+    /// `external` is state outside the CPU that must repeat as well: reads of
+    /// values changing between events (running timer counters) and the bus
+    /// timing state.
+    pub fn observe<B: BusAccess>(&mut self, cpu: &Cpu, bus: &mut B, now: u64, external: (u64, u64)) -> Option<u64> {
+        let pc = cpu.pc();
+        let prev_pc = self.prev_pc;
+        self.prev_pc = pc;
+
+        if let Some(c) = self.candidate.as_mut() {
+            if pc < c.head || pc > c.tail {
+                self.candidate = None;
+            } else {
+                if pc != c.head {
+                    return None;
+                }
+                let snapshot = Self::snapshot(cpu);
+                let period = now - c.arrived_at;
+                let repeated = snapshot == c.snapshot && external == c.external;
+                let stable = repeated && c.period == Some(period);
+                c.snapshot = snapshot;
+                c.external = external;
+                c.arrived_at = now;
+                c.period = repeated.then_some(period);
+                return stable.then_some(period);
+            }
+        }
+
+        if pc <= prev_pc
+            && prev_pc - pc <= IDLE_LOOP_MAX_BYTES
+            && Self::body_is_pure(bus, cpu.state(), pc, prev_pc)
+        {
+            self.candidate = Some(LoopCandidate {
+                head: pc,
+                tail: prev_pc,
+                snapshot: Self::snapshot(cpu),
+                external,
+                arrived_at: now,
+                period: None,
+            });
+        }
+        None
+    }
+
+    fn snapshot(cpu: &Cpu) -> [u32; 17] {
+        let mut regs = [0u32; 17];
+        for (i, r) in regs.iter_mut().take(16).enumerate() {
+            *r = cpu.read_reg(i);
+        }
+        regs[16] = cpu.cpsr().raw();
+        regs
+    }
+
+    fn body_is_pure<B: BusAccess>(bus: &mut B, state: CpuState, head: u32, tail: u32) -> bool {
+        match state {
+            CpuState::Arm => (head..=tail)
+                .step_by(4)
+                .all(|addr| !arm_has_side_effects(bus.peek32(addr))),
+            CpuState::Thumb => (head..=tail)
+                .step_by(2)
+                .all(|addr| !thumb_has_side_effects(bus.peek16(addr))),
+        }
+    }
+}
+
+/// Conservative check for ARM instructions that write memory, change mode or
+/// state, or trap.
+pub fn arm_has_side_effects(instr: u32) -> bool {
+    let load = (instr >> 20) & 1 != 0;
+    if (instr & 0x0FFF_FFF0) == 0x012F_FF10 {
+        return true; // BX
+    }
+    if (instr & 0x0DB0_F000) == 0x0120_F000 {
+        return true; // MSR
+    }
+    if (instr & 0x0FB0_0FF0) == 0x0100_0090 {
+        return true; // SWP
+    }
+    match (instr >> 25) & 0x7 {
+        0b000 if (instr & 0x90) == 0x90 && (instr & 0x60) != 0 => !load,
+        0b000 | 0b001 => {
+            let opcode = (instr >> 21) & 0xF;
+            let is_test = (0x8..=0xB).contains(&opcode);
+            let rd = (instr >> 12) & 0xF;
+            !is_test && rd == 15 && load
+        }
+        0b010 | 0b011 => !load,
+        0b100 => !load,
+        0b101 => false,
+        _ => true,
+    }
+}
+
+/// Thumb counterpart of [`arm_has_side_effects`].
+pub fn thumb_has_side_effects(instr: u16) -> bool {
+    let load = (instr >> 11) & 1 != 0;
+    match instr >> 12 {
+        0x4 if (instr >> 8) & 0xF == 0x7 => true, // BX
+        0x5 if (instr >> 9) & 1 == 0 => !load,
+        0x5 => !load && (instr >> 10) & 1 == 0,
+        0x6 | 0x7 | 0x8 | 0x9 | 0xC => !load,
+        0xB if (instr >> 9) & 0x3 == 0x2 => !load,
+        0xD => (instr >> 8) == 0xDF,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arm_classification() {
+        assert!(!arm_has_side_effects(0xE1D0_10B6)); // LDRH r1, [r0, #6]
+        assert!(!arm_has_side_effects(0xE351_0064)); // CMP r1, #100
+        assert!(!arm_has_side_effects(0x1AFF_FFFC)); // BNE
+        assert!(arm_has_side_effects(0xE581_0000)); // STR r0, [r1]
+        assert!(arm_has_side_effects(0xE1C0_10B6)); // STRH r1, [r0, #6]
+        assert!(arm_has_side_effects(0xE92D_4000)); // STMFD sp!, {lr}
+        assert!(arm_has_side_effects(0xEF00_0005)); // SWI 5
+        assert!(arm_has_side_effects(0xE12F_FF1E)); // BX lr
+    }
 
-impl Timing {
-    pub fn new() -> Self { Self }
+    #[test]
+    fn thumb_classification() {
+        assert!(!thumb_has_side_effects(0x8841)); // LDRH r1, [r0, #2]
+        assert!(!thumb_has_side_effects(0x2964)); // CMP r1, #100
+        assert!(!thumb_has_side_effects(0xD1FC)); // BNE
+        assert!(thumb_has_side_effects(0x6008)); // STR r0, [r1]
+        assert!(thumb_has_side_effects(0xB500)); // PUSH {lr}
+        assert!(thumb_has_side_effects(0xDF05)); // SWI 5
+        assert!(thumb_has_side_effects(0x4770)); // BX lr
+    }
 }
@@ -1,3 +1,7 @@
+pub mod y4m;
+
+pub use y4m::Y4mWriter;
+
 #[derive(Default)]
 pub struct Video;
 
@@ -8,6 +12,76 @@ impl Video {
 pub const GBA_SCREEN_W: usize = 240;
 pub const GBA_SCREEN_H: usize = 160;
 
+/// Owned RGBA8 image, e.g. a screenshot of the last frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>,
+}
+
+/// Pixel layouts a `FrameSink` can take frames in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// The PPU's native 16-bit `xBBBBBGGGGGRRRRR`, before color correction.
+    Bgr555,
+    /// Four bytes per pixel through the configured color profile.
+    Rgba8888,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Bgr555 => 2,
+            PixelFormat::Rgba8888 => 4,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum FramePixels<'a> {
+    Bgr555(&'a [u16]),
+    Rgba8888(&'a [u8]),
+}
+
+/// A completed frame, borrowed from the emulator. Rows are `stride` pixels
+/// apart; frontends copying into their own buffers should use `pitch`.
+#[derive(Copy, Clone, Debug)]
+pub struct Frame<'a> {
+    pub pixels: FramePixels<'a>,
+    pub width: usize,
+    pub height: usize,
+    pub stride: usize,
+}
+
+impl<'a> Frame<'a> {
+    pub fn bgr555(pixels: &'a [u16]) -> Self {
+        Self { pixels: FramePixels::Bgr555(pixels), width: GBA_SCREEN_W, height: GBA_SCREEN_H, stride: GBA_SCREEN_W }
+    }
+
+    pub fn rgba8888(pixels: &'a [u8]) -> Self {
+        Self { pixels: FramePixels::Rgba8888(pixels), width: GBA_SCREEN_W, height: GBA_SCREEN_H, stride: GBA_SCREEN_W }
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        match self.pixels {
+            FramePixels::Bgr555(_) => PixelFormat::Bgr555,
+            FramePixels::Rgba8888(_) => PixelFormat::Rgba8888,
+        }
+    }
+
+    /// Bytes from one row to the next.
+    pub fn pitch(&self) -> usize { self.stride * self.format().bytes_per_pixel() }
+}
+
+/// Receives every completed frame, in the format it asks for. While a sink
+/// wants BGR555, the emulator skips the RGBA conversion, and
+/// `Emulator::framebuffer_rgba` is not updated.
+pub trait FrameSink: Send {
+    fn format(&self) -> PixelFormat;
+    fn present(&mut self, frame: Frame<'_>);
+}
+
 pub fn bgr555_to_rgba8888(bgr555: u16) -> [u8; 4] {
     let r5 = (bgr555 & 0x1F) as u8;
     let g5 = ((bgr555 >> 5) & 0x1F) as u8;
@@ -18,11 +92,157 @@ pub fn bgr555_to_rgba8888(bgr555: u16) -> [u8; 4] {
     [r, g, b, 0xFF]
 }
 
-pub fn framebuffer_rgb555_to_rgba(dst: &mut [u8], src_bgr555: &[u16]) {
+/// Screen the output colors are tuned to mimic. Raw BGR555 expanded to RGB8
+/// is far more saturated than any real handheld panel.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ColorProfile {
+    #[default]
+    Raw,
+    /// Original unlit AGB screen.
+    GbaLcd,
+    /// Backlit GBA SP (AGS-101).
+    GbaSp,
+    /// Nintendo DS running GBA software.
+    Nds,
+}
+
+// Approximate panel responses: display gamma, brightness and a channel mix
+// (rows are output R, G, B) in linear light.
+struct PanelResponse {
+    gamma: f32,
+    luminance: f32,
+    mix: [[f32; 3]; 3],
+}
+
+impl ColorProfile {
+    pub const ALL: [ColorProfile; 4] =
+        [ColorProfile::Raw, ColorProfile::GbaLcd, ColorProfile::GbaSp, ColorProfile::Nds];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorProfile::Raw => "raw",
+            ColorProfile::GbaLcd => "gba",
+            ColorProfile::GbaSp => "gba-sp",
+            ColorProfile::Nds => "nds",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name().eq_ignore_ascii_case(name))
+    }
+
+    fn response(self) -> Option<PanelResponse> {
+        let (gamma, luminance, mix) = match self {
+            ColorProfile::Raw => return None,
+            ColorProfile::GbaLcd => {
+                (2.2, 0.94, [[0.82, 0.125, 0.195], [0.24, 0.665, 0.075], [-0.06, 0.21, 0.73]])
+            }
+            ColorProfile::GbaSp => {
+                (2.2, 1.0, [[0.955, 0.11, -0.065], [0.0325, 0.875, 0.0925], [0.0125, 0.0475, 0.94]])
+            }
+            ColorProfile::Nds => {
+                (2.2, 0.905, [[0.835, 0.27, -0.105], [0.1, 0.6375, 0.2625], [0.105, 0.175, 0.72]])
+            }
+        };
+        Some(PanelResponse { gamma, luminance, mix })
+    }
+}
+
+const COLORS: usize = 0x8000;
+
+/// BGR555 to RGBA8 lookup for one color profile.
+pub struct ColorTable {
+    profile: ColorProfile,
+    // Sized so a masked index needs no bounds check.
+    entries: Box<[[u8; 4]; COLORS]>,
+}
+
+impl ColorTable {
+    pub fn new(profile: ColorProfile) -> Self {
+        let response = profile.response();
+        let entries: Box<[[u8; 4]]> = (0..COLORS as u16)
+            .map(|bgr555| {
+                let raw = bgr555_to_rgba8888(bgr555);
+                match &response {
+                    Some(response) => response.apply(raw),
+                    None => raw,
+                }
+            })
+            .collect();
+        let entries = entries.try_into().expect("one entry per color");
+        Self { profile, entries }
+    }
+
+    pub fn profile(&self) -> ColorProfile { self.profile }
+
+    pub fn rgba(&self, bgr555: u16) -> [u8; 4] { self.entries[(bgr555 & 0x7FFF) as usize] }
+}
+
+impl Default for ColorTable {
+    fn default() -> Self { Self::new(ColorProfile::Raw) }
+}
+
+impl PanelResponse {
+    fn apply(&self, [r, g, b, a]: [u8; 4]) -> [u8; 4] {
+        let linear = [r, g, b].map(|c| (c as f32 / 255.0).powf(self.gamma));
+        let mut out = [0u8; 4];
+        for (o, row) in out.iter_mut().zip(&self.mix) {
+            let v = row.iter().zip(&linear).map(|(m, c)| m * c).sum::<f32>() * self.luminance;
+            *o = (v.clamp(0.0, 1.0).powf(1.0 / 2.2) * 255.0).round() as u8;
+        }
+        out[3] = a;
+        out
+    }
+}
+
+/// Converts a BGR555 framebuffer to RGBA8 through `colors`. `dst` holds four
+/// bytes per pixel, e.g. a texture's staging buffer.
+pub fn framebuffer_rgb555_to_rgba(dst: &mut [u8], src_bgr555: &[u16], colors: &ColorTable) {
     assert_eq!(dst.len(), src_bgr555.len() * 4);
-    for (i, &px) in src_bgr555.iter().enumerate() {
-        let rgba = bgr555_to_rgba8888(px);
-        let o = i * 4;
-        dst[o..o + 4].copy_from_slice(&rgba);
+    // Exact chunks and the fixed-size table keep bounds checks out of the loop.
+    for (out, &px) in dst.chunks_exact_mut(4).zip(src_bgr555) {
+        out.copy_from_slice(&colors.entries[(px & 0x7FFF) as usize]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_profile_matches_plain_expansion() {
+        let table = ColorTable::new(ColorProfile::Raw);
+        for px in [0x0000, 0x001F, 0x03E0, 0x7C00, 0x7FFF, 0x1234] {
+            assert_eq!(table.rgba(px), bgr555_to_rgba8888(px));
+        }
+    }
+
+    #[test]
+    fn lcd_profiles_desaturate_primaries() {
+        for profile in [ColorProfile::GbaLcd, ColorProfile::GbaSp, ColorProfile::Nds] {
+            let table = ColorTable::new(profile);
+            let [_, g, _, a] = table.rgba(0x001F);
+            assert!(g > 0, "{:?} left pure red untouched", profile);
+            assert_eq!(a, 0xFF);
+            assert_eq!(table.rgba(0x0000), [0, 0, 0, 0xFF]);
+        }
+    }
+
+    #[test]
+    fn framebuffer_conversion_uses_the_table() {
+        let table = ColorTable::new(ColorProfile::GbaSp);
+        let src = [0x0000, 0x7FFF, 0x801F, 0x1234];
+        let mut dst = [0u8; 16];
+        framebuffer_rgb555_to_rgba(&mut dst, &src, &table);
+        for (out, &px) in dst.chunks(4).zip(&src) {
+            assert_eq!(out, table.rgba(px));
+        }
+    }
+
+    #[test]
+    fn profile_names_round_trip() {
+        for profile in ColorProfile::ALL {
+            assert_eq!(ColorProfile::from_name(profile.name()), Some(profile));
+        }
     }
 }
@@ -0,0 +1,75 @@
+// YUV4MPEG2 writer for raw frame dumps. Y4M is a header line followed by
+// uncompressed planes, so any frame can be diffed byte for byte and ffmpeg
+// or mpv read the file as is. Frames go out as BT.601 limited-range 4:4:4.
+
+use std::io::{self, Write};
+
+use super::{GBA_SCREEN_H, GBA_SCREEN_W};
+
+// 16777216 / 280896 Hz in lowest terms.
+const FRAME_RATE: (u32, u32) = (262_144, 4_389);
+
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    frames: u32,
+    planes: Vec<u8>,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writeln!(
+            writer,
+            "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444",
+            GBA_SCREEN_W, GBA_SCREEN_H, FRAME_RATE.0, FRAME_RATE.1
+        )?;
+        Ok(Self { writer, frames: 0, planes: vec![0; GBA_SCREEN_W * GBA_SCREEN_H * 3] })
+    }
+
+    /// Appends a frame of the PPU's BGR555 pixels, before color correction.
+    pub fn push_bgr555(&mut self, pixels: &[u16]) -> io::Result<()> {
+        let area = GBA_SCREEN_W * GBA_SCREEN_H;
+        let (y, uv) = self.planes.split_at_mut(area);
+        let (u, v) = uv.split_at_mut(area);
+        for (i, &pixel) in pixels.iter().take(area).enumerate() {
+            let [r, g, b, _] = super::bgr555_to_rgba8888(pixel).map(i32::from);
+            y[i] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+            u[i] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+            v[i] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+        }
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&self.planes)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn frames(&self) -> u32 { self.frames }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_header_and_planes() {
+        let mut y4m = Y4mWriter::new(Vec::new()).unwrap();
+        let mut frame = vec![0u16; GBA_SCREEN_W * GBA_SCREEN_H];
+        frame[1] = 0x7FFF;
+        y4m.push_bgr555(&frame).unwrap();
+        assert_eq!(y4m.frames(), 1);
+        let bytes = y4m.finish().unwrap();
+
+        let header = b"YUV4MPEG2 W240 H160 F262144:4389 Ip A1:1 C444\nFRAME\n";
+        assert_eq!(&bytes[..header.len()], header);
+        let planes = &bytes[header.len()..];
+        assert_eq!(planes.len(), 240 * 160 * 3);
+        // Black and white at the limits of the limited range, no chroma.
+        assert_eq!(&planes[..2], &[16, 235]);
+        assert_eq!(&planes[240 * 160..240 * 160 + 2], &[128, 128]);
+        assert_eq!(&planes[2 * 240 * 160..2 * 240 * 160 + 2], &[128, 128]);
+    }
+}
@@ -0,0 +1,104 @@
+//! Counts heap allocations made while rendering, which should be none once
+//! the PPU's scratch buffers exist. A counting global allocator tracks the
+//! allocations of the current thread only, so tests running in parallel do
+//! not disturb each other.
+
+use roba_core::Emulator;
+use roba_core::bus::{Bus, BusAccess};
+use roba_core::config::{Accuracy, EmulatorConfig};
+use roba_core::ppu::Ppu;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|n| n.set(n.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) { unsafe { System.dealloc(ptr, layout) } }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|n| n.set(n.get() + 1));
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn allocations_during(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+// Every background, a few sprites (one of them an OBJ window), both windows
+// and alpha blending, so each render path runs.
+fn busy_scene(mode: u16) -> Bus {
+    let mut bus = Bus::new();
+    for i in 0..0x1_8000 {
+        bus.poke8(0x0600_0000 + i, (i % 7) as u8);
+    }
+    for i in 0..512 {
+        bus.write16(0x0500_0000 + i * 2, (i as u16 & 0x1F) * 0x0421);
+    }
+    for i in 0..128u32 {
+        let attr0 = match i {
+            0 => 2 << 10,
+            1..=8 => (i * 16) as u16 | (1 << 10),
+            _ => 1 << 9,
+        };
+        bus.write16(0x0700_0000 + i * 8, attr0);
+        bus.write16(0x0700_0000 + i * 8 + 2, (1 << 14) | (i * 24) as u16);
+    }
+    bus.write16(0x0400_0020, 0x100);
+    bus.write16(0x0400_0026, 0x100);
+    bus.write16(0x0400_0040, 0x1060);
+    bus.write16(0x0400_0044, 0x1060);
+    bus.write16(0x0400_0048, 0x3F3F);
+    bus.write16(0x0400_004A, 0x1F15);
+    bus.write16(0x0400_0050, 0x3F41);
+    bus.write16(0x0400_0052, 0x0808);
+    bus.write16(0x0400_0000, mode | 0xFF00);
+    bus
+}
+
+#[test]
+fn rendering_does_not_allocate() {
+    let mut ppu = Ppu::new();
+    for mode in 0..=5 {
+        let mut bus = busy_scene(mode);
+        ppu.render_frame_with_bus(&mut bus);
+        let count = allocations_during(|| {
+            ppu.render_frame_with_bus(&mut bus);
+            for line in 0..160 {
+                ppu.render_lines_with_bus(&mut bus, line..line + 1);
+            }
+        });
+        assert_eq!(count, 0, "mode {}", mode);
+    }
+}
+
+#[test]
+fn running_frames_does_not_allocate() {
+    // b .
+    let mut emu = Emulator::new();
+    emu.load_rom_bytes(&[0xFE, 0xFF, 0xFF, 0xEA]).unwrap();
+    for accuracy in [Accuracy::Fast, Accuracy::Accurate] {
+        emu.set_config(EmulatorConfig { accuracy, ..Default::default() });
+        emu.run_frame();
+        let count = allocations_during(|| {
+            for _ in 0..10 {
+                emu.run_frame();
+            }
+        });
+        assert_eq!(count, 0, "{:?}", accuracy);
+    }
+}
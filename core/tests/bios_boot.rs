@@ -0,0 +1,45 @@
+//! Boots a ROM through the real BIOS: the logo animation, the header checks
+//! and the jump to the cartridge. Needs `assets/gba_bios.bin` and
+//! `test-roms/hello.gba`; a missing file skips the test, like the other ROM
+//! tests.
+
+use roba_core::Emulator;
+use std::path::Path;
+
+const BIOS: &str = "assets/gba_bios.bin";
+const ROM: &str = "../test-roms/hello.gba";
+// The animation takes about 4.5 seconds.
+const MAX_FRAMES: u32 = 300;
+
+fn in_rom(emu: &Emulator) -> bool { (0x0800_0000..0x0E00_0000).contains(&emu.reg(15)) }
+
+#[test]
+fn bios_boot_hands_over_to_the_rom() {
+    if !Path::new(BIOS).exists() || !Path::new(ROM).exists() {
+        eprintln!("skipping: {} or {} not found", BIOS, ROM);
+        return;
+    }
+    let mut emu = Emulator::new();
+    emu.load_bios(Path::new(BIOS)).unwrap();
+    emu.load_rom(Path::new(ROM)).unwrap();
+    assert_eq!(emu.reg(15), 0, "should start at the reset vector");
+
+    // Whole frames until the ROM runs, then the last one again an
+    // instruction at a time to catch the jump itself.
+    let mut state = emu.save_state();
+    let mut frames = 0;
+    while !in_rom(&emu) {
+        assert!(frames < MAX_FRAMES, "still in the BIOS after {} frames (PC {:08X})", frames, emu.reg(15));
+        state = emu.save_state();
+        emu.run_frame();
+        frames += 1;
+    }
+    emu.load_state(&state).unwrap();
+    while !in_rom(&emu) {
+        emu.run_cycles(1);
+    }
+
+    assert_eq!(emu.reg(15), 0x0800_0000);
+    assert_eq!(emu.cpsr() & 0x3F, 0x1F, "should enter in ARM System mode");
+    assert_eq!(emu.reg(13), 0x0300_7F00);
+}
@@ -0,0 +1,319 @@
+//! Golden-image tests for the PPU. Each scene is built by writing VRAM,
+//! palette, OAM and I/O registers directly, rendered, and compared with
+//! `tests/golden/<scene>.png`. On a mismatch the rendered frame and a diff
+//! (differing pixels in red) are written next to the test binaries and the
+//! paths are printed.
+//!
+//! After an intended rendering change, review the diffs and refresh the
+//! goldens with `ROBA_BLESS=1 cargo test -p roba-core --test ppu_golden`.
+
+use roba_core::bus::{Bus, BusAccess};
+use roba_core::ppu::Ppu;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+const W: usize = 240;
+const H: usize = 160;
+
+const DISPCNT: u32 = 0x0400_0000;
+const BG0CNT: u32 = 0x0400_0008;
+const BG1CNT: u32 = 0x0400_000A;
+const BG2CNT: u32 = 0x0400_000C;
+const BG0HOFS: u32 = 0x0400_0010;
+const BG0VOFS: u32 = 0x0400_0012;
+const BG1HOFS: u32 = 0x0400_0014;
+const BG2PA: u32 = 0x0400_0020;
+const BG2X: u32 = 0x0400_0028;
+const WIN0H: u32 = 0x0400_0040;
+const WIN1H: u32 = 0x0400_0042;
+const WIN0V: u32 = 0x0400_0044;
+const WIN1V: u32 = 0x0400_0046;
+const WININ: u32 = 0x0400_0048;
+const WINOUT: u32 = 0x0400_004A;
+const BLDCNT: u32 = 0x0400_0050;
+const BLDALPHA: u32 = 0x0400_0052;
+const BLDY: u32 = 0x0400_0054;
+
+const BG_PALETTE: u32 = 0x0500_0000;
+const OBJ_PALETTE: u32 = 0x0500_0200;
+const VRAM: u32 = 0x0600_0000;
+const OBJ_VRAM: u32 = 0x0601_0000;
+const OAM: u32 = 0x0700_0000;
+
+fn rgb(r: u16, g: u16, b: u16) -> u16 { r | (g << 5) | (b << 10) }
+
+struct Scene {
+    bus: Bus,
+}
+
+impl Scene {
+    /// A blank machine with every sprite disabled.
+    fn new() -> Self {
+        let mut bus = Bus::new();
+        for i in 0..128 {
+            bus.write16(OAM + i * 8, 1 << 9);
+        }
+        Self { bus }
+    }
+
+    fn io(&mut self, reg: u32, value: u16) { self.bus.write16(reg, value); }
+
+    fn halfwords(&mut self, addr: u32, data: &[u16]) {
+        for (i, &value) in data.iter().enumerate() {
+            self.bus.write16(addr + i as u32 * 2, value);
+        }
+    }
+
+    /// A 4bpp tile; each row holds eight palette indices, pixel 0 in the
+    /// low nibble.
+    fn tile4(&mut self, base: u32, tile: u32, rows: [u32; 8]) {
+        for (row, &pixels) in rows.iter().enumerate() {
+            self.bus.write32(base + tile * 32 + row as u32 * 4, pixels);
+        }
+    }
+
+    /// An 8bpp tile from a function of the pixel position.
+    fn tile8(&mut self, base: u32, tile: u32, pixel: impl Fn(u32, u32) -> u8) {
+        for y in 0..8 {
+            for x in (0..8).step_by(2) {
+                let pair = pixel(x, y) as u16 | (pixel(x + 1, y) as u16) << 8;
+                self.bus.write16(base + tile * 64 + y * 8 + x, pair);
+            }
+        }
+    }
+
+    fn sprite(&mut self, index: u32, attrs: [u16; 3]) { self.halfwords(OAM + index * 8, &attrs); }
+
+    fn render(&mut self) -> Vec<u16> {
+        let mut ppu = Ppu::new();
+        ppu.render_frame_with_bus(&mut self.bus);
+        ppu.framebuffer().to_vec()
+    }
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{}.png", name))
+}
+
+// 5-bit channels are widened by repeating their top bits, which reading
+// back undoes exactly.
+fn write_png(path: &Path, frame: &[u16]) {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path).unwrap()), W as u32, H as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let widen = |c: u16| ((c << 3) | (c >> 2)) as u8;
+    let data: Vec<u8> =
+        frame.iter().flat_map(|&p| [widen(p & 0x1F), widen((p >> 5) & 0x1F), widen((p >> 10) & 0x1F)]).collect();
+    encoder.write_header().unwrap().write_image_data(&data).unwrap();
+}
+
+fn read_png(path: &Path) -> Result<Vec<u16>, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut reader = png::Decoder::new(BufReader::new(file)).read_info().map_err(|e| e.to_string())?;
+    let mut data = vec![0; reader.output_buffer_size().unwrap_or(0)];
+    let info = reader.next_frame(&mut data).map_err(|e| e.to_string())?;
+    if (info.width as usize, info.height as usize) != (W, H) || info.color_type != png::ColorType::Rgb {
+        return Err(format!("expected a {}x{} RGB image", W, H));
+    }
+    let narrow = |c: u8| (c >> 3) as u16;
+    Ok(data[..W * H * 3].chunks_exact(3).map(|p| narrow(p[0]) | narrow(p[1]) << 5 | narrow(p[2]) << 10).collect())
+}
+
+fn check(name: &str, frame: &[u16]) {
+    let path = golden_path(name);
+    if std::env::var_os("ROBA_BLESS").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        write_png(&path, frame);
+        return;
+    }
+    let expected = read_png(&path)
+        .unwrap_or_else(|e| panic!("{}: {}; run with ROBA_BLESS=1 to create it", path.display(), e));
+    let differing: Vec<usize> = (0..W * H).filter(|&i| frame[i] != expected[i]).collect();
+    let Some(&first) = differing.first() else {
+        return;
+    };
+
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("ppu-golden");
+    fs::create_dir_all(&out).unwrap();
+    let actual_path = out.join(format!("{}.actual.png", name));
+    let diff_path = out.join(format!("{}.diff.png", name));
+    write_png(&actual_path, frame);
+    let dim = |p: u16| (p >> 2) & 0b00111_00111_00111;
+    let diff: Vec<u16> = (0..W * H).map(|i| if frame[i] == expected[i] { dim(expected[i]) } else { 0x001F }).collect();
+    write_png(&diff_path, &diff);
+    panic!(
+        "{}: {} pixels differ, first at ({}, {}): expected {:04X}, got {:04X}\n  actual: {}\n  diff:   {}",
+        name,
+        differing.len(),
+        first % W,
+        first / W,
+        expected[first],
+        frame[first],
+        actual_path.display(),
+        diff_path.display()
+    );
+}
+
+// Shared by the tiled scenes: a checkerboard, a diagonal and a frame tile in
+// 4bpp at tiles 1-3 of character block 0, and a 16-color gradient palette.
+fn basic_tiles(scene: &mut Scene) {
+    let (a, b) = (0x1122_1122, 0x2211_2211);
+    scene.tile4(VRAM, 1, [a, a, b, b, a, a, b, b]);
+    let mut diagonal = [0; 8];
+    for (y, row) in diagonal.iter_mut().enumerate() {
+        for x in 0..8 {
+            *row |= (if x == y { 3 } else if x > y { 4 } else { 0 }) << (x * 4);
+        }
+    }
+    scene.tile4(VRAM, 2, diagonal);
+    let (side, dot) = (0x6000_0005, 0x6007_7005);
+    scene.tile4(VRAM, 3, [0x5555_5555, side, side, dot, dot, side, side, 0x6666_6666]);
+    let palette: Vec<u16> = (0..16).map(|i| rgb(i * 2, 31 - i * 2, (i * 5) % 32)).collect();
+    scene.halfwords(BG_PALETTE, &palette);
+}
+
+#[test]
+fn text_background_with_scrolling() {
+    let mut scene = Scene::new();
+    basic_tiles(&mut scene);
+    // 32x32 map in screen block 8: tiles 1-3 in a repeating pattern, every
+    // fourth entry flipped and on palette bank 1.
+    let map: Vec<u16> = (0..1024u16)
+        .map(|i| {
+            let tile = 1 + (i + i / 32) % 3;
+            if i % 4 == 0 { tile | (0b11 << 10) | (1 << 12) } else { tile }
+        })
+        .collect();
+    scene.halfwords(VRAM + 8 * 0x800, &map);
+    let bank1: Vec<u16> = (0..16).map(|i| rgb(31 - i, i, 16)).collect();
+    scene.halfwords(BG_PALETTE + 32, &bank1);
+    scene.io(BG0CNT, 8 << 8);
+    scene.io(BG0HOFS, 3);
+    scene.io(BG0VOFS, 509);
+    scene.io(DISPCNT, 1 << 8);
+    check("text_background_with_scrolling", &scene.render());
+}
+
+#[test]
+fn background_priority_and_alpha_blending() {
+    let mut scene = Scene::new();
+    basic_tiles(&mut scene);
+    scene.halfwords(VRAM + 8 * 0x800, &[1; 1024]);
+    // BG1 covers a band in the middle, in front of BG0 and blended with it.
+    let band: Vec<u16> = (0..1024).map(|i| if (8..14).contains(&(i / 32)) { 3 } else { 0 }).collect();
+    scene.halfwords(VRAM + 9 * 0x800, &band);
+    scene.io(BG0CNT, (8 << 8) | 1);
+    scene.io(BG1CNT, 9 << 8);
+    scene.io(BG1HOFS, 4);
+    scene.io(BLDCNT, (1 << 1) | (1 << 6) | (1 << 8));
+    scene.io(BLDALPHA, 10 | (6 << 8));
+    scene.io(DISPCNT, (1 << 8) | (1 << 9));
+    check("background_priority_and_alpha_blending", &scene.render());
+}
+
+#[test]
+fn sprites_with_flips_and_semi_transparency() {
+    let mut scene = Scene::new();
+    basic_tiles(&mut scene);
+    scene.halfwords(VRAM + 8 * 0x800, &[2; 1024]);
+    scene.io(BG0CNT, (8 << 8) | 2);
+
+    // A 16x16 4bpp sprite (tiles 0-3, 1D mapping) and an 8x8 8bpp one.
+    for tile in 0..4 {
+        let rows: [u32; 8] = std::array::from_fn(|y| {
+            (0..8).fold(0, |row, x| row | (((x + y as u32 + tile) % 15 + 1) << (x * 4)))
+        });
+        scene.tile4(OBJ_VRAM, tile, rows);
+    }
+    scene.tile8(OBJ_VRAM, 4, |x, y| if (x + y) % 3 == 0 { 0 } else { 16 + (x * 8 + y) as u8 });
+    let obj_palette: Vec<u16> = (0..80).map(|i| rgb(31 - i % 32, (i * 3) % 32, i % 32)).collect();
+    scene.halfwords(OBJ_PALETTE, &obj_palette);
+
+    let size16 = 1 << 14;
+    scene.sprite(0, [20, size16 | 16, 0]);
+    scene.sprite(1, [20, size16 | 40 | (1 << 12), 0]);
+    scene.sprite(2, [20, size16 | 64 | (1 << 13), 0]);
+    // Priority 2, level with BG0.
+    scene.sprite(3, [60, size16 | 16, 2 << 10]);
+    // Semi-transparent, blended with BG0 below.
+    scene.sprite(4, [60 | (1 << 10), size16 | 40, 0]);
+    scene.sprite(5, [100 | (1 << 13), 16, 8]);
+    // Partly off the left edge and wrapping past the bottom.
+    scene.sprite(6, [150, size16 | 500, 0]);
+    scene.io(BLDCNT, 1 << 8);
+    scene.io(BLDALPHA, 8 | (8 << 8));
+    scene.io(DISPCNT, (1 << 6) | (1 << 8) | (1 << 12));
+    check("sprites_with_flips_and_semi_transparency", &scene.render());
+}
+
+#[test]
+fn rotated_affine_background() {
+    let mut scene = Scene::new();
+    for tile in 0..4 {
+        scene.tile8(VRAM, tile, |x, y| 1 + (tile * 16) as u8 + ((x / 2) + (y / 2) * 4) as u8);
+    }
+    let palette: Vec<u16> = (0..=64).map(|i| rgb(i % 32, (i * 2) % 32, 31 - i % 32)).collect();
+    scene.halfwords(BG_PALETTE, &palette);
+    // 16x16 map (128x128 pixels) of 8-bit entries in screen block 8.
+    let map: Vec<u16> = (0..128u16).map(|i| (i % 4) | ((i + 1) % 4) << 8).collect();
+    scene.halfwords(VRAM + 8 * 0x800, &map);
+
+    // 30 degrees, scaled by 1.25, with wraparound.
+    let (sin, cos) = (30f64.to_radians().sin() / 1.25, 30f64.to_radians().cos() / 1.25);
+    let fixed = |v: f64| (v * 256.0).round() as i16 as u16;
+    scene.halfwords(BG2PA, &[fixed(cos), fixed(-sin), fixed(sin), fixed(cos)]);
+    scene.bus.write32(BG2X, (-40i32 << 8) as u32);
+    scene.bus.write32(BG2X + 4, (20i32 << 8) as u32);
+    scene.io(BG2CNT, (1 << 7) | (8 << 8) | (1 << 13));
+    scene.io(DISPCNT, 1 | (1 << 10));
+    check("rotated_affine_background", &scene.render());
+}
+
+#[test]
+fn windows_and_brightness() {
+    let mut scene = Scene::new();
+    basic_tiles(&mut scene);
+    scene.halfwords(VRAM + 8 * 0x800, &[1; 1024]);
+    scene.halfwords(VRAM + 9 * 0x800, &[2; 1024]);
+    scene.io(BG0CNT, 8 << 8);
+    scene.io(BG1CNT, (9 << 8) | 1);
+    scene.io(WIN0H, (20 << 8) | 120);
+    scene.io(WIN0V, (10 << 8) | 90);
+    scene.io(WIN1H, (100 << 8) | 220);
+    scene.io(WIN1V, (60 << 8) | 150);
+    // WIN0 shows BG0 only, WIN1 BG1 with effects; outside both, BG0
+    // darkened.
+    scene.io(WININ, 1 | ((0b10 | (1 << 5)) << 8));
+    scene.io(WINOUT, 1 | (1 << 5));
+    scene.io(BLDCNT, 0b11 | (3 << 6));
+    scene.io(BLDY, 10);
+    scene.io(DISPCNT, (1 << 8) | (1 << 9) | (1 << 13) | (1 << 14));
+    check("windows_and_brightness", &scene.render());
+}
+
+#[test]
+fn bitmap_modes() {
+    let mut mode3 = Scene::new();
+    let gradient: Vec<u16> = (0..W * H)
+        .map(|i| rgb((i % W / 8) as u16, (i / W / 5) as u16, (i % 7 * 4) as u16))
+        .collect();
+    mode3.halfwords(VRAM, &gradient);
+    mode3.io(DISPCNT, 3 | (1 << 10));
+    check("bitmap_mode3", &mode3.render());
+
+    // Mode 4 showing the second page, with a 64-color palette.
+    let mut mode4 = Scene::new();
+    let pixels: Vec<u16> = (0..(W * H / 2) as u32)
+        .map(|i| {
+            let (x, y) = (i * 2 % W as u32, i * 2 / W as u32);
+            let index = |x: u32| (((x / 4) ^ (y / 4)) % 64) as u16;
+            index(x) | index(x + 1) << 8
+        })
+        .collect();
+    mode4.halfwords(VRAM + 0xA000, &pixels);
+    let palette: Vec<u16> = (0..64).map(|i| rgb(i / 2, 31 - i / 2, (i * 7) % 32)).collect();
+    mode4.halfwords(BG_PALETTE, &palette);
+    mode4.io(DISPCNT, 4 | (1 << 4) | (1 << 10));
+    check("bitmap_mode4_page1", &mode4.render());
+}
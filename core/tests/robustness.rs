@@ -0,0 +1,133 @@
+//! Arbitrary ROMs, addresses and savestates must not panic the core. These
+//! are fixed-seed versions of the cargo-fuzz targets in `core/fuzz/`; run
+//! those for longer campaigns.
+
+use roba_core::bus::{Bus, BusAccess};
+use roba_core::cpu::{Cpu, CpuState};
+use roba_core::Emulator;
+
+// xorshift64*, so runs are reproducible without a rand dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> { (0..len).map(|_| self.next() as u8).collect() }
+}
+
+// First and last bytes of every region, the unmapped gaps between them and
+// the ends of the address space.
+const EDGES: [u32; 24] = [
+    0x0000_0000, 0x0000_3FFF, 0x0000_4000, 0x01FF_FFFF, 0x0200_0000, 0x0203_FFFF, 0x0204_0000, 0x02FF_FFFF,
+    0x0300_7FFF, 0x03FF_FFFF, 0x0400_03FF, 0x0400_0400, 0x04FF_FFFF, 0x0500_03FF, 0x05FF_FFFF, 0x0601_7FFF,
+    0x0601_8000, 0x06FF_FFFF, 0x07FF_FFFF, 0x0DFF_FFFF, 0x0E00_FFFF, 0x0FFF_FFFF, 0x1000_0000, 0xFFFF_FFFF,
+];
+
+#[test]
+fn bus_accepts_any_address() {
+    for rom in [Vec::new(), vec![0xAA; 1], vec![0x55; 0x0200_0001]] {
+        // Straight into the bus: the emulator rejects empty and oversized ROMs.
+        let mut bus = Bus::new();
+        bus.load_rom(&rom);
+        for edge in EDGES {
+            for addr in (0..4).map(|d| edge.wrapping_add(d)).chain((1..4).map(|d| edge.wrapping_sub(d))) {
+                bus.write8(addr, 0xA5);
+                bus.write16(addr, 0xA5A5);
+                bus.write32(addr, 0xA5A5_A5A5);
+                bus.read8(addr);
+                bus.read16(addr);
+                bus.read32(addr);
+            }
+        }
+    }
+}
+
+#[test]
+fn random_roms_run_without_panicking() {
+    let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+    for _ in 0..8 {
+        let len = 0x100 << (rng.next() % 12);
+        let rom = rng.bytes(len);
+        let mut emu = Emulator::new();
+        emu.load_rom_bytes(&rom).unwrap();
+        for _ in 0..3 {
+            emu.run_frame();
+        }
+    }
+}
+
+#[test]
+fn random_video_memory_renders_without_panicking() {
+    let mut rng = Rng(0x6A09_E667_F3BC_C908);
+    let mut emu = Emulator::new();
+    // A ROM that spins in place: `b .`
+    emu.load_rom_bytes(&[0xFE, 0xFF, 0xFF, 0xEA]).unwrap();
+    for _ in 0..12 {
+        // Palette, VRAM and OAM, then the display, background, window,
+        // blending and mosaic registers.
+        let regions = [(0x0500_0000, 0x400), (0x0600_0000, 0x1_8000), (0x0700_0000, 0x400), (0x0400_0000, 0x56)];
+        for (base, len) in regions {
+            for addr in (base..base + len).step_by(2) {
+                emu.write16(addr, rng.next() as u16);
+            }
+        }
+        emu.run_frame();
+    }
+}
+
+#[test]
+fn random_instructions_with_random_registers() {
+    let mut rng = Rng(0xD1B5_4A32_D192_ED03);
+    let mut bus = Bus::new();
+    bus.load_rom(&rng.bytes(0x1000));
+    let mut cpu = Cpu::new();
+    cpu.set_swi_hle(true);
+    for i in 0..50_000 {
+        // Edge values as often as random ones: division overflow, address
+        // wrap-around, empty and full register lists.
+        for reg in 0..15 {
+            let value = match rng.next() % 4 {
+                0 => [0, 1, u32::MAX, i32::MIN as u32, 0x7FFF_FFFF, 0xFFFF_FFFC][rng.next() as usize % 6],
+                _ => rng.next() as u32,
+            };
+            cpu.write_reg(reg, value);
+        }
+        cpu.set_state(if i % 2 == 0 { CpuState::Arm } else { CpuState::Thumb });
+        cpu.set_entry_point(&mut bus, 0x0800_0000 | (rng.next() as u32 & 0xFFC));
+        cpu.step(&mut bus);
+    }
+}
+
+#[test]
+fn corrupt_states_are_rejected_or_loaded() {
+    let mut rng = Rng(0x2545_F491_4F6C_DD1D);
+    let mut emu = Emulator::new();
+    emu.load_rom_bytes(&rng.bytes(0x400)).unwrap();
+    emu.run_frame();
+    let state = emu.save_state();
+    for i in 0..64 {
+        let mut data = state.clone();
+        match i % 3 {
+            0 => data.truncate(rng.next() as usize % data.len()),
+            1 => {
+                for _ in 0..16 {
+                    let at = rng.next() as usize % data.len();
+                    data[at] = rng.next() as u8;
+                }
+            }
+            _ => {
+                // Keep the header so the machine fields are what gets garbled.
+                let body = rng.bytes(data.len() - 10);
+                data[10..].copy_from_slice(&body);
+            }
+        }
+        if emu.load_state(&data).is_ok() {
+            emu.run_frame();
+        }
+    }
+}
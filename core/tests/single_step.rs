@@ -0,0 +1,274 @@
+//! Single-instruction CPU test vectors in the SingleStepTests ARM7TDMI JSON
+//! format (https://github.com/SingleStepTests/ARM7TDMI). Each file holds the
+//! tests for one encoding: initial registers and pipeline, the bus
+//! transactions the instruction makes, and the expected final state.
+//!
+//! Put the (decompressed) `.json` files in `test-roms/single-step/`, or point
+//! `ROBA_SINGLE_STEP_DIR` at them, and run
+//! `cargo test -p roba-core --test single_step -- --nocapture` for a pass rate per
+//! file. Missing files skip the run; failures are reported, not asserted,
+//! while the CPU is incomplete.
+
+use roba_core::bus::BusAccess;
+use roba_core::cpu::{Cpu, CpuMode, CpuState};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+const KIND_WRITE: u64 = 2;
+
+/// Sparse memory serving the values the vector says each address holds, and
+/// recording writes.
+#[derive(Default)]
+struct VectorBus {
+    mem: HashMap<u32, u8>,
+    writes: Vec<(u32, u32, u32)>,
+}
+
+impl VectorBus {
+    fn set(&mut self, addr: u32, size: u32, value: u32) {
+        for i in 0..size {
+            self.mem.insert(addr.wrapping_add(i), (value >> (i * 8)) as u8);
+        }
+    }
+
+    fn get(&self, addr: u32, size: u32) -> u32 {
+        (0..size).fold(0, |v, i| v | (*self.mem.get(&addr.wrapping_add(i)).unwrap_or(&0) as u32) << (i * 8))
+    }
+
+    fn write(&mut self, addr: u32, size: u32, value: u32) {
+        self.writes.push((addr, size, value));
+        self.set(addr, size, value);
+    }
+}
+
+impl BusAccess for VectorBus {
+    fn read32(&mut self, addr: u32) -> u32 { self.get(addr & !3, 4) }
+    fn read16(&mut self, addr: u32) -> u16 { self.get(addr & !1, 2) as u16 }
+    fn read8(&mut self, addr: u32) -> u8 { self.get(addr, 1) as u8 }
+    fn write32(&mut self, addr: u32, value: u32) { self.write(addr & !3, 4, value); }
+    fn write16(&mut self, addr: u32, value: u16) { self.write(addr & !1, 2, value as u32); }
+    fn write8(&mut self, addr: u32, value: u8) { self.write(addr, 1, value as u32); }
+}
+
+// Banked registers as listed in the vectors: (mode, first register, key).
+const BANKS: [(CpuMode, usize, &str); 5] = [
+    (CpuMode::Fiq, 8, "R_fiq"),
+    (CpuMode::Supervisor, 13, "R_svc"),
+    (CpuMode::Abort, 13, "R_abt"),
+    (CpuMode::Irq, 13, "R_irq"),
+    (CpuMode::Undefined, 13, "R_und"),
+];
+
+fn u32s(state: &Value, key: &str) -> Vec<u32> {
+    state[key].as_array().map_or(Vec::new(), |a| a.iter().map(|v| v.as_u64().unwrap_or(0) as u32).collect())
+}
+
+fn mode_of(cpsr: u32) -> CpuMode {
+    match cpsr & 0x1F {
+        0x11 => CpuMode::Fiq,
+        0x12 => CpuMode::Irq,
+        0x13 => CpuMode::Supervisor,
+        0x17 => CpuMode::Abort,
+        0x1B => CpuMode::Undefined,
+        0x1F => CpuMode::System,
+        _ => CpuMode::User,
+    }
+}
+
+// The vectors give R15 with the pipeline full, two instructions ahead.
+fn pc_offset(cpsr: u32) -> u32 { if cpsr & 0x20 != 0 { 4 } else { 8 } }
+
+fn setup(test: &Value) -> (Cpu, VectorBus) {
+    let initial = &test["initial"];
+    let cpsr = initial["CPSR"].as_u64().unwrap_or(0) as u32;
+    let regs = u32s(initial, "R");
+    let spsrs = u32s(initial, "SPSR");
+
+    let mut cpu = Cpu::new();
+    for (i, &(mode, first, key)) in BANKS.iter().enumerate() {
+        cpu.set_mode(mode);
+        for (j, value) in u32s(initial, key).into_iter().enumerate() {
+            cpu.write_reg(first + j, value);
+        }
+        cpu.set_spsr(spsrs.get(i).copied().unwrap_or(0));
+    }
+    cpu.set_mode(CpuMode::System);
+    for (i, &value) in regs.iter().enumerate().take(15) {
+        cpu.write_reg(i, value);
+    }
+    cpu.set_mode(mode_of(cpsr));
+    cpu.cpsr_mut().set_raw(cpsr);
+
+    let pc = regs[15].wrapping_sub(pc_offset(cpsr));
+    let mut bus = VectorBus::default();
+    let size = if cpu.state() == CpuState::Thumb { 2 } else { 4 };
+    for (i, opcode) in u32s(initial, "pipeline").into_iter().enumerate() {
+        bus.set(pc.wrapping_add(i as u32 * size), size, opcode);
+    }
+    for t in test["transactions"].as_array().into_iter().flatten() {
+        if t["kind"].as_u64() != Some(KIND_WRITE) {
+            let addr = t["addr"].as_u64().unwrap_or(0) as u32;
+            let size = t["size"].as_u64().unwrap_or(4) as u32;
+            bus.set(addr, size, t["data"].as_u64().unwrap_or(0) as u32);
+        }
+    }
+    cpu.set_pc(pc);
+    (cpu, bus)
+}
+
+/// Runs one vector; returns a description of the first mismatch.
+fn run_test(test: &Value) -> Result<(), String> {
+    let (mut cpu, mut bus) = setup(test);
+    cpu.step(&mut bus);
+
+    let expected = &test["final"];
+    let cpsr = expected["CPSR"].as_u64().unwrap_or(0) as u32;
+    if cpu.cpsr().raw() != cpsr {
+        return Err(format!("CPSR {:08X}, expected {:08X}", cpu.cpsr().raw(), cpsr));
+    }
+    let regs = u32s(expected, "R");
+    let pc = cpu.pc().wrapping_add(pc_offset(cpsr));
+    if pc != regs[15] {
+        return Err(format!("R15 {:08X}, expected {:08X}", pc, regs[15]));
+    }
+
+    let mode = cpu.mode();
+    let banked = BANKS.iter().find(|b| b.0 == mode);
+    cpu.set_mode(CpuMode::System);
+    for (i, &value) in regs.iter().enumerate().take(15) {
+        if cpu.read_reg(i) != value {
+            return Err(format!("R{} {:08X}, expected {:08X}", i, cpu.read_reg(i), value));
+        }
+    }
+    if let Some(&(mode, first, key)) = banked {
+        cpu.set_mode(mode);
+        for (j, value) in u32s(expected, key).into_iter().enumerate() {
+            if cpu.read_reg(first + j) != value {
+                return Err(format!("{}[{}] {:08X}, expected {:08X}", key, j, cpu.read_reg(first + j), value));
+            }
+        }
+    }
+
+    let writes: Vec<(u32, u32, u32)> = test["transactions"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|t| t["kind"].as_u64() == Some(KIND_WRITE))
+        .map(|t| {
+            let field = |k: &str| t[k].as_u64().unwrap_or(0) as u32;
+            (field("addr"), field("size"), field("data"))
+        })
+        .collect();
+    if bus.writes != writes {
+        return Err(format!("writes {:X?}, expected {:X?}", bus.writes, writes));
+    }
+    Ok(())
+}
+
+/// Runs every vector in one file; returns (passed, total, first failure).
+fn run_file(path: &Path) -> (usize, usize, Option<String>) {
+    let text = std::fs::read_to_string(path).unwrap_or_default();
+    let tests: Vec<Value> = serde_json::from_str(&text).unwrap_or_default();
+    let mut passed = 0;
+    let mut first_failure = None;
+    for test in &tests {
+        match run_test(test) {
+            Ok(()) => passed += 1,
+            Err(e) if first_failure.is_none() => {
+                first_failure = Some(format!("opcode {:08X}: {}", test["opcode"].as_u64().unwrap_or(0), e));
+            }
+            Err(_) => {}
+        }
+    }
+    (passed, tests.len(), first_failure)
+}
+
+#[test]
+fn single_step_vectors() {
+    let dir = std::env::var_os("ROBA_SINGLE_STEP_DIR")
+        .map_or_else(|| PathBuf::from("../test-roms/single-step"), PathBuf::from);
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        eprintln!("skipping: no test vectors in {:?}", dir);
+        return;
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|e| e == "json"))
+        .collect();
+    files.sort();
+
+    let (mut passed, mut total) = (0, 0);
+    for file in &files {
+        let (p, t, failure) = run_file(file);
+        let name = file.file_stem().unwrap_or_default().to_string_lossy();
+        println!("{:<40} {:>6}/{:<6} {:>5.1}%", name, p, t, 100.0 * p as f64 / t.max(1) as f64);
+        if let Some(failure) = failure {
+            println!("    first failure: {}", failure);
+        }
+        passed += p;
+        total += t;
+    }
+    println!("{:<40} {:>6}/{:<6} {:>5.1}%", "total", passed, total, 100.0 * passed as f64 / total.max(1) as f64);
+}
+
+// A vector written by hand in the same format, so the runner itself is
+// checked without the downloaded files: `mov r0, #5` then `str r0, [r1]`.
+const SAMPLE: &str = r#"[
+  {
+    "initial": {
+      "R": [0, 4096, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 264],
+      "R_fiq": [0, 0, 0, 0, 0, 0, 0], "R_svc": [0, 0], "R_abt": [0, 0],
+      "R_irq": [0, 0], "R_und": [0, 0],
+      "CPSR": 31, "SPSR": [0, 0, 0, 0, 0],
+      "pipeline": [3818913797, 3850436608], "access": 0
+    },
+    "final": {
+      "R": [5, 4096, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 268],
+      "R_fiq": [0, 0, 0, 0, 0, 0, 0], "R_svc": [0, 0], "R_abt": [0, 0],
+      "R_irq": [0, 0], "R_und": [0, 0],
+      "CPSR": 31, "SPSR": [0, 0, 0, 0, 0],
+      "pipeline": [3850436608, 0], "access": 0
+    },
+    "transactions": [{"kind": 0, "size": 4, "addr": 264, "data": 0, "cycle": 1, "access": 0}],
+    "opcode": 3818913797,
+    "base_addr": 256
+  },
+  {
+    "initial": {
+      "R": [5, 4096, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 264],
+      "R_fiq": [0, 0, 0, 0, 0, 0, 0], "R_svc": [0, 0], "R_abt": [0, 0],
+      "R_irq": [0, 0], "R_und": [0, 0],
+      "CPSR": 31, "SPSR": [0, 0, 0, 0, 0],
+      "pipeline": [3850436608, 0], "access": 0
+    },
+    "final": {
+      "R": [5, 4096, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 268],
+      "R_fiq": [0, 0, 0, 0, 0, 0, 0], "R_svc": [0, 0], "R_abt": [0, 0],
+      "R_irq": [0, 0], "R_und": [0, 0],
+      "CPSR": 31, "SPSR": [0, 0, 0, 0, 0],
+      "pipeline": [0, 0], "access": 0
+    },
+    "transactions": [
+      {"kind": 0, "size": 4, "addr": 264, "data": 0, "cycle": 1, "access": 0},
+      {"kind": 2, "size": 4, "addr": 4096, "data": 5, "cycle": 2, "access": 0}
+    ],
+    "opcode": 3850436608,
+    "base_addr": 256
+  }
+]"#;
+
+#[test]
+fn runner_checks_registers_and_writes() {
+    let tests: Vec<Value> = serde_json::from_str(SAMPLE).unwrap();
+    for test in &tests {
+        assert_eq!(run_test(test), Ok(()));
+    }
+
+    let mut wrong = tests[1].clone();
+    wrong["transactions"][1]["data"] = 6.into();
+    assert!(run_test(&wrong).unwrap_err().starts_with("writes"));
+    let mut wrong = tests[0].clone();
+    wrong["final"]["R"][0] = 4.into();
+    assert_eq!(run_test(&wrong), Err("R0 00000005, expected 00000004".to_string()));
+}
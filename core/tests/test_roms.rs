@@ -0,0 +1,87 @@
+//! Runs the open test ROMs in `test-roms/` for a bounded number of frames and
+//! checks the outcome. ROMs are optional: a missing file skips its test, like
+//! the stripes.gba tests in the crate.
+//!
+//! jsmolka's gba-tests leave the number of the first failing test in r12 (0
+//! when all passed) and then spin in a loop of a few instructions. Other ROMs
+//! are checked against a golden `Emulator::frame_hash`.
+
+use roba_core::Emulator;
+use std::path::PathBuf;
+
+// Frames the final loop is watched for, and how far apart the PCs seen at
+// their ends may be.
+const END_FRAMES: u64 = 8;
+const END_LOOP_BYTES: u32 = 0x20;
+
+enum Check {
+    /// `Emulator::frame_hash` of the last frame.
+    Screen(u64),
+    /// jsmolka's convention: finished with r12 == 0.
+    R12Zero,
+}
+
+fn run(rom: &str, frames: u64, check: Check) {
+    let path = PathBuf::from("../test-roms").join(rom);
+    if !path.exists() {
+        eprintln!("skipping {}: not found", rom);
+        return;
+    }
+    let mut emu = Emulator::new();
+    emu.load_rom(&path).unwrap();
+    for _ in 0..frames {
+        emu.run_frame();
+    }
+    match check {
+        Check::Screen(expected) => {
+            let found = emu.frame_hash();
+            assert_eq!(found, expected, "{}: frame hash {:016X}, expected {:016X}", rom, found, expected);
+        }
+        Check::R12Zero => {
+            // Finished: from one frame to the next, r12 holds still and PC
+            // stays in one short loop in the ROM. Interrupt handlers may
+            // run in between, so only frame ends are looked at.
+            let failed = emu.reg(12);
+            let (mut low, mut high) = (u32::MAX, 0);
+            for _ in 0..END_FRAMES {
+                emu.run_frame();
+                let pc = emu.reg(15);
+                (low, high) = (low.min(pc), high.max(pc));
+                assert_eq!(emu.reg(12), failed, "{}: r12 still changing after {} frames", rom, frames);
+            }
+            let finished = high - low <= END_LOOP_BYTES && (0x0800_0000..0x0A00_0000).contains(&low);
+            assert!(finished, "{}: did not finish (PC {:08X}-{:08X} after {} frames)", rom, low, high, frames);
+            assert_eq!(failed, 0, "{}: test {} failed", rom, failed);
+        }
+    }
+}
+
+macro_rules! rom_tests {
+    ($($(#[$meta:meta])* $name:ident: $rom:literal, $frames:literal, $check:expr;)*) => {
+        $(
+            #[test]
+            $(#[$meta])*
+            fn $name() { run($rom, $frames, $check); }
+        )*
+    };
+}
+
+rom_tests! {
+    stripes: "stripes.gba", 10, Check::Screen(0xB804_D1BE_99FC_7525);
+    shades: "shades.gba", 10, Check::Screen(0x06DA_45BF_8441_2325);
+
+    // jsmolka/gba-tests. The ignored ones do not pass yet; run them with `--ignored`.
+    #[ignore = "stops at test 234"]
+    arm: "arm.gba", 60, Check::R12Zero;
+    thumb: "thumb.gba", 60, Check::R12Zero;
+    memory: "memory.gba", 60, Check::R12Zero;
+    #[ignore = "stops at test 1"]
+    bios: "bios.gba", 60, Check::R12Zero;
+    #[ignore = "ends up running from VRAM"]
+    nes: "nes.gba", 60, Check::R12Zero;
+    unsafe_: "unsafe.gba", 60, Check::R12Zero;
+    save_none: "none.gba", 60, Check::R12Zero;
+    save_sram: "sram.gba", 60, Check::R12Zero;
+    save_flash64: "flash64.gba", 60, Check::R12Zero;
+    save_flash128: "flash128.gba", 60, Check::R12Zero;
+}
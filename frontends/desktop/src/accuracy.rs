@@ -0,0 +1,105 @@
+// Per-game accuracy overrides, stored as `overrides.toml` in the game's save
+// directory. Each option left out follows the global setting, so a file can
+// change just one subsystem by hand; the Emulation menu writes whole presets.
+
+use crate::saves::GameSaves;
+use roba_core::config::{Accuracy, AccuracyPreset, EmulatorConfig};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct GameOverrides {
+    #[serde(with = "accuracy_name::option", skip_serializing_if = "Option::is_none")]
+    pub accuracy: Option<Accuracy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cycle_timing: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_loop_skip: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub open_bus: Option<bool>,
+}
+
+impl GameOverrides {
+    /// Overrides for every option `preset` covers.
+    pub fn from_preset(preset: AccuracyPreset) -> Self {
+        let mut config = EmulatorConfig::default();
+        preset.apply(&mut config);
+        Self {
+            accuracy: Some(config.accuracy),
+            cycle_timing: Some(config.cycle_timing),
+            idle_loop_skip: Some(config.idle_loop_skip),
+            open_bus: Some(config.open_bus),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool { *self == Self::default() }
+
+    pub fn apply(&self, config: &mut EmulatorConfig) {
+        config.accuracy = self.accuracy.unwrap_or(config.accuracy);
+        config.cycle_timing = self.cycle_timing.unwrap_or(config.cycle_timing);
+        config.idle_loop_skip = self.idle_loop_skip.unwrap_or(config.idle_loop_skip);
+        config.open_bus = self.open_bus.unwrap_or(config.open_bus);
+    }
+
+    /// Reads `path`; a missing or broken file overrides nothing.
+    pub fn load(path: &Path) -> Self {
+        let Ok(text) = fs::read_to_string(path) else {
+            return Self::default();
+        };
+        match toml::from_str(&text) {
+            Ok(overrides) => {
+                log::info!("Loaded game overrides from {:?}", path);
+                overrides
+            }
+            Err(e) => {
+                log::error!("Failed to read game overrides {:?}: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, saves: &GameSaves) {
+        let path = saves.overrides_path();
+        let result = toml::to_string(self)
+            .map_err(|e| e.to_string())
+            .and_then(|text| saves.write(&path, text.as_bytes()).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            log::error!("Failed to save game overrides {:?}: {}", path, e);
+        }
+    }
+}
+
+// Stores the accuracy by its core name, e.g. `accuracy = "accurate"`.
+pub mod accuracy_name {
+    use roba_core::config::Accuracy;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(accuracy: &Accuracy, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(accuracy.name())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Accuracy, D::Error> {
+        let name = String::deserialize(d)?;
+        Accuracy::from_name(&name).ok_or_else(|| de::Error::custom(format!("unknown accuracy {:?}", name)))
+    }
+
+    pub mod option {
+        use roba_core::config::Accuracy;
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(accuracy: &Option<Accuracy>, s: S) -> Result<S::Ok, S::Error> {
+            match accuracy {
+                Some(accuracy) => super::serialize(accuracy, s),
+                None => s.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Accuracy>, D::Error> {
+            #[derive(Deserialize)]
+            struct Named(#[serde(with = "super")] Accuracy);
+            Ok(Option::<Named>::deserialize(d)?.map(|Named(accuracy)| accuracy))
+        }
+    }
+}
@@ -0,0 +1,62 @@
+// Audio channel section of the debug panel: mute and solo buttons for each
+// of the six sound sources, with a level meter and a small oscilloscope of
+// what the channel played most recently.
+
+use eframe::egui;
+use roba_core::apu::{Channel, Channels};
+
+const SCOPE_SIZE: egui::Vec2 = egui::vec2(160.0, 28.0);
+const METER_SIZE: egui::Vec2 = egui::vec2(6.0, 28.0);
+const WAVE_COLOR: egui::Color32 = egui::Color32::from_rgb(120, 220, 120);
+const MUTED_COLOR: egui::Color32 = egui::Color32::from_gray(110);
+
+fn scope(ui: &mut egui::Ui, channels: &Channels, channel: Channel, color: egui::Color32) {
+    let (rect, _) = ui.allocate_exact_size(SCOPE_SIZE, egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, egui::Color32::from_black_alpha(160));
+    painter.hline(rect.x_range(), rect.center().y, egui::Stroke::new(1.0, egui::Color32::from_gray(60)));
+
+    let levels = channels.scope(channel);
+    if levels.len() < 2 {
+        return;
+    }
+    let scale = rect.height() / 2.0 / channel.full_scale() as f32;
+    let step = rect.width() / (levels.len() - 1) as f32;
+    let points: Vec<egui::Pos2> = levels
+        .iter()
+        .enumerate()
+        .map(|(i, &level)| egui::pos2(rect.min.x + i as f32 * step, rect.center().y - level as f32 * scale))
+        .collect();
+    painter.add(egui::Shape::line(points, egui::Stroke::new(1.0, color)));
+}
+
+fn meter(ui: &mut egui::Ui, peak: f32, color: egui::Color32) {
+    let (rect, _) = ui.allocate_exact_size(METER_SIZE, egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 1.0, egui::Color32::from_black_alpha(160));
+    let mut level = rect;
+    level.min.y = rect.max.y - rect.height() * peak;
+    painter.rect_filled(level, 1.0, color);
+}
+
+pub fn show(ui: &mut egui::Ui, channels: &mut Channels) {
+    egui::Grid::new("audio_channels").num_columns(4).show(ui, |ui| {
+        for channel in Channel::ALL {
+            ui.label(channel.name());
+            ui.horizontal(|ui| {
+                let mut muted = channels.is_muted(channel);
+                if ui.toggle_value(&mut muted, "M").on_hover_text("Mute").changed() {
+                    channels.set_muted(channel, muted);
+                }
+                let mut solo = channels.solo() == Some(channel);
+                if ui.toggle_value(&mut solo, "S").on_hover_text("Play only this channel").changed() {
+                    channels.set_solo(solo.then_some(channel));
+                }
+            });
+            let color = if channels.is_audible(channel) { WAVE_COLOR } else { MUTED_COLOR };
+            meter(ui, channels.peak(channel), color);
+            scope(ui, channels, channel, color);
+            ui.end_row();
+        }
+    });
+}
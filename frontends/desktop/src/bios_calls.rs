@@ -0,0 +1,38 @@
+// BIOS call counts for the debug panel: which SWIs the game made during the
+// last frame and since the counts were reset, by name.
+
+use eframe::egui;
+use roba_core::cpu::{swi_name, SwiStats};
+
+fn rows<T: std::fmt::Display>(ui: &mut egui::Ui, id: &str, calls: &[(u8, T)]) {
+    if calls.is_empty() {
+        ui.label("None");
+        return;
+    }
+    egui::Grid::new(id).striped(true).show(ui, |ui| {
+        for (number, count) in calls {
+            ui.label(egui::RichText::new(format!("{:02X}", number)).monospace());
+            ui.label(swi_name(*number).unwrap_or("(invalid)"));
+            ui.label(count.to_string());
+            ui.end_row();
+        }
+    });
+}
+
+/// Returns true when the logging checkbox was toggled.
+pub fn show(ui: &mut egui::Ui, stats: &mut SwiStats, log: &mut bool) -> bool {
+    let toggled = ui
+        .checkbox(log, "Log every call")
+        .on_hover_text("Writes each SWI with r0-r3 to the debug log")
+        .changed();
+    ui.label("Last frame:");
+    rows(ui, "swi_frame", &stats.last_frame());
+    ui.horizontal(|ui| {
+        ui.label("Since reset:");
+        if ui.small_button("Reset").clicked() {
+            stats.clear();
+        }
+    });
+    rows(ui, "swi_total", &stats.totals());
+    toggled
+}
@@ -0,0 +1,91 @@
+// PNG screenshots and GIF clips of the emulated screen.
+
+use roba_core::video::Image;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// GBA refresh rate is 16777216 / 280896 Hz; GIF delays are in 1/100 s.
+const FRAME_CENTISECONDS: f64 = 100.0 * 280_896.0 / 16_777_216.0;
+// Clips stop on their own after this many emulated frames (~30 s).
+const MAX_CLIP_FRAMES: u32 = 30 * 60;
+// Only every other frame is stored; 30 fps is plenty for a GIF.
+const CLIP_FRAME_STEP: u32 = 2;
+// NeuQuant speed, 1 (best) to 30 (fastest).
+const GIF_QUANT_SPEED: i32 = 10;
+
+/// Default output directory when none is configured.
+pub fn default_dir() -> Option<PathBuf> {
+    directories::ProjectDirs::from("com", "RoBA", "RoBA")
+        .map(|dirs| dirs.data_dir().join("captures"))
+}
+
+/// Returns an unused `<dir>/<stem>-<unix time>[-n].<ext>`, creating `dir`.
+pub fn output_path(dir: &Path, stem: &str, ext: &str) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let mut path = dir.join(format!("{}-{}.{}", stem, secs, ext));
+    let mut n = 1;
+    while path.exists() {
+        path = dir.join(format!("{}-{}-{}.{}", stem, secs, n, ext));
+        n += 1;
+    }
+    Ok(path)
+}
+
+pub fn save_png(path: &Path, image: &Image) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, image.width as u32, image.height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(&image.rgba).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+pub struct GifRecorder {
+    path: PathBuf,
+    encoder: gif::Encoder<BufWriter<File>>,
+    frames: u32,
+    // Delay owed to the next written frame, in centiseconds.
+    pending_delay: f64,
+}
+
+impl GifRecorder {
+    pub fn start(path: PathBuf, width: usize, height: usize) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(&path)?);
+        let mut encoder =
+            gif::Encoder::new(file, width as u16, height as u16, &[]).map_err(io::Error::other)?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(io::Error::other)?;
+        Ok(Self { path, encoder, frames: 0, pending_delay: 0.0 })
+    }
+
+    pub fn path(&self) -> &Path { &self.path }
+
+    /// Adds one emulated frame. Returns false once the clip is full.
+    pub fn push(&mut self, image: &Image) -> io::Result<bool> {
+        self.frames += 1;
+        self.pending_delay += FRAME_CENTISECONDS;
+        if self.frames.is_multiple_of(CLIP_FRAME_STEP) {
+            let mut rgba = image.rgba.clone();
+            let mut frame = gif::Frame::from_rgba_speed(
+                image.width as u16,
+                image.height as u16,
+                &mut rgba,
+                GIF_QUANT_SPEED,
+            );
+            let delay = self.pending_delay.floor();
+            frame.delay = delay as u16;
+            self.pending_delay -= delay;
+            self.encoder.write_frame(&frame).map_err(io::Error::other)?;
+        }
+        Ok(self.frames < MAX_CLIP_FRAMES)
+    }
+
+    pub fn finish(self) -> io::Result<PathBuf> {
+        let mut file = self.encoder.into_inner().map_err(io::Error::other)?;
+        io::Write::flush(&mut file)?;
+        Ok(self.path)
+    }
+}
@@ -0,0 +1,192 @@
+// Cheat list editor. Cheats are stored per game in `cheats.toml` in the
+// game's save directory.
+
+use crate::saves::GameSaves;
+use eframe::egui;
+use roba_core::cheats::{Cheat, CheatFormat};
+use roba_core::Emulator;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Default)]
+struct CheatFile {
+    #[serde(default, rename = "cheat")]
+    cheats: Vec<CheatEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CheatEntry {
+    name: String,
+    format: String,
+    #[serde(default)]
+    encrypted: bool,
+    #[serde(default = "enabled_default")]
+    enabled: bool,
+    code: String,
+}
+
+fn enabled_default() -> bool { true }
+
+/// Adds the cheats saved in `path` to `core`.
+pub fn load(core: &mut Emulator, path: &Path) {
+    let Ok(text) = fs::read_to_string(path) else {
+        return;
+    };
+    let file: CheatFile = match toml::from_str(&text) {
+        Ok(file) => file,
+        Err(e) => {
+            log::error!("Failed to read cheats {:?}: {}", path, e);
+            return;
+        }
+    };
+    for entry in file.cheats {
+        let Some(format) = CheatFormat::from_name(&entry.format) else {
+            log::warn!("Cheat {:?}: unknown format {:?}", entry.name, entry.format);
+            continue;
+        };
+        match Cheat::parse(&entry.name, format, entry.encrypted, &entry.code) {
+            Ok(mut cheat) => {
+                cheat.enabled = entry.enabled;
+                core.cheats_mut().add(cheat);
+            }
+            Err(e) => log::warn!("Cheat {:?}: {}", entry.name, e),
+        }
+    }
+    log::info!("Loaded {} cheats from {:?}", core.cheats().len(), path);
+}
+
+pub fn save(core: &Emulator, saves: &GameSaves) {
+    let path = saves.cheats_path();
+    let file = CheatFile {
+        cheats: core
+            .cheats()
+            .iter()
+            .map(|c| CheatEntry {
+                name: c.name.clone(),
+                format: c.format().name().to_string(),
+                encrypted: c.encrypted(),
+                enabled: c.enabled,
+                code: c.code().to_string(),
+            })
+            .collect(),
+    };
+    let result = toml::to_string(&file)
+        .map_err(|e| e.to_string())
+        .and_then(|text| saves.write(&path, text.as_bytes()).map_err(|e| e.to_string()));
+    if let Err(e) = result {
+        log::error!("Failed to save cheats {:?}: {}", path, e);
+    }
+}
+
+fn format_label(format: CheatFormat) -> &'static str {
+    match format {
+        CheatFormat::GameSharkV1 => "GameShark / Action Replay v1-v2",
+        CheatFormat::GameSharkV3 => "GameShark v3 / Action Replay MAX",
+        CheatFormat::CodeBreaker => "CodeBreaker",
+    }
+}
+
+pub struct CheatWindow {
+    pub open: bool,
+    name: String,
+    format: CheatFormat,
+    encrypted: bool,
+    code: String,
+    error: Option<String>,
+}
+
+impl Default for CheatWindow {
+    fn default() -> Self {
+        Self {
+            open: false,
+            name: String::new(),
+            format: CheatFormat::GameSharkV1,
+            encrypted: true,
+            code: String::new(),
+            error: None,
+        }
+    }
+}
+
+impl CheatWindow {
+    /// Draws the window if open. Returns true when the cheat list changed.
+    pub fn show(&mut self, ctx: &egui::Context, core: &mut Emulator) -> bool {
+        let mut changed = false;
+        let mut open = self.open;
+        egui::Window::new("Cheats").open(&mut open).show(ctx, |ui| {
+            changed |= Self::list(ui, core);
+            ui.separator();
+            changed |= self.editor(ui, core);
+        });
+        self.open = open;
+        changed
+    }
+
+    fn list(ui: &mut egui::Ui, core: &mut Emulator) -> bool {
+        let mut changed = false;
+        if core.cheats().is_empty() {
+            ui.label("No cheats for this game.");
+        }
+        let mut remove = None;
+        for i in 0..core.cheats().len() {
+            let cheat = core.cheats_mut().get_mut(i).unwrap();
+            ui.horizontal(|ui| {
+                changed |= ui.checkbox(&mut cheat.enabled, &cheat.name).changed();
+                ui.weak(format_label(cheat.format()));
+                if ui.small_button("Remove").clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
+        if let Some(i) = remove {
+            core.cheats_mut().remove(i);
+            changed = true;
+        }
+        changed
+    }
+
+    fn editor(&mut self, ui: &mut egui::Ui, core: &mut Emulator) -> bool {
+        ui.horizontal(|ui| {
+            ui.label("Name:");
+            ui.text_edit_singleline(&mut self.name);
+        });
+        egui::ComboBox::from_label("Format")
+            .selected_text(format_label(self.format))
+            .show_ui(ui, |ui| {
+                for format in CheatFormat::ALL {
+                    ui.selectable_value(&mut self.format, format, format_label(format));
+                }
+            });
+        if self.format != CheatFormat::CodeBreaker {
+            ui.checkbox(&mut self.encrypted, "Encrypted");
+        }
+        ui.add(
+            egui::TextEdit::multiline(&mut self.code)
+                .code_editor()
+                .desired_rows(4)
+                .hint_text("XXXXXXXX YYYYYYYY"),
+        );
+        if let Some(error) = &self.error {
+            ui.colored_label(egui::Color32::from_rgb(255, 100, 100), error);
+        }
+        if !ui.button("Add").clicked() {
+            return false;
+        }
+        let name = if self.name.trim().is_empty() { "Unnamed" } else { self.name.trim() };
+        let encrypted = self.encrypted && self.format != CheatFormat::CodeBreaker;
+        match Cheat::parse(name, self.format, encrypted, &self.code) {
+            Ok(cheat) => {
+                core.cheats_mut().add(cheat);
+                self.name.clear();
+                self.code.clear();
+                self.error = None;
+                true
+            }
+            Err(e) => {
+                self.error = Some(e.to_string());
+                false
+            }
+        }
+    }
+}
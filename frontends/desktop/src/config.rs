@@ -0,0 +1,175 @@
+use crate::accuracy::accuracy_name;
+use crate::input::{GamepadConfig, InputMap};
+use crate::sync::SyncMode;
+use crate::touch::TouchConfig;
+use crate::video::VideoConfig;
+use roba_core::config::{Accuracy, AccuracyPreset, BootMode, EmulatorConfig};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+// Configuration struct for serialization.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub recent_files: Vec<PathBuf>,
+    /// Folders the library scans for ROMs.
+    pub rom_dirs: Vec<PathBuf>,
+    /// Box art shown in the library, as `<game code>.png` or `<ROM name>.png`.
+    pub cover_dir: Option<PathBuf>,
+    pub bios_path: Option<PathBuf>,
+    /// Open-source BIOS (e.g. Cult-of-GBA) used when no official dump is set.
+    /// Without either, BIOS calls are emulated.
+    pub replacement_bios: Option<PathBuf>,
+    pub idle_loop_skip: bool,
+    /// Per-scanline drawing and hardware display timing.
+    #[serde(with = "accuracy_name")]
+    pub accuracy: Accuracy,
+    pub cycle_timing: bool,
+    pub open_bus: bool,
+    pub skip_bios: bool,
+    /// Fixes the Nintendo logo and header checksum of ROMs the BIOS would
+    /// refuse to boot.
+    pub patch_header: bool,
+    /// Runs from a fixed RTC time instead of the wall clock, so movies and
+    /// link sessions replay identically.
+    pub deterministic: bool,
+    /// Logs every BIOS call (SWI) to the debug log.
+    pub swi_log: bool,
+    /// Root of the per-game save directories; defaults to the data directory.
+    pub save_dir: Option<PathBuf>,
+    /// Seconds of rewind history; 0 disables rewind.
+    pub rewind_seconds: u32,
+    /// Frames the "Step frames" hotkey runs while paused.
+    pub frame_step: u32,
+    /// Frames emulated per repaint while fast-forward is held.
+    pub fast_forward_speed: u32,
+    /// Draws only the last of those frames, which is the one shown.
+    pub fast_forward_frame_skip: bool,
+    /// Screenshots and GIF clips; defaults to the data directory.
+    pub capture_dir: Option<PathBuf>,
+    /// VSync follows this and changes with it only after a restart.
+    pub sync_mode: SyncMode,
+    /// Repaints per second while nothing runs (the library, or paused), when
+    /// gamepads and new log lines are picked up; mouse and keyboard input
+    /// repaint at once. 0 repaints only on that input.
+    pub idle_fps: u32,
+    pub video: VideoConfig,
+    pub audio: AudioConfig,
+    pub input: InputMap,
+    pub gamepad: GamepadConfig,
+    /// On-screen controls, pressed by touch or mouse.
+    pub touch: TouchConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            recent_files: Vec::new(),
+            rom_dirs: Vec::new(),
+            cover_dir: None,
+            bios_path: None,
+            replacement_bios: None,
+            idle_loop_skip: false,
+            accuracy: Accuracy::default(),
+            cycle_timing: true,
+            open_bus: false,
+            skip_bios: false,
+            patch_header: false,
+            deterministic: false,
+            swi_log: false,
+            save_dir: None,
+            rewind_seconds: 10,
+            frame_step: 10,
+            fast_forward_speed: 4,
+            fast_forward_frame_skip: true,
+            capture_dir: None,
+            sync_mode: SyncMode::default(),
+            idle_fps: 10,
+            video: VideoConfig::default(),
+            audio: AudioConfig::default(),
+            input: InputMap::default(),
+            gamepad: GamepadConfig::default(),
+            touch: TouchConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn bios_to_load(&self) -> Option<&PathBuf> { self.bios_path.as_ref().or(self.replacement_bios.as_ref()) }
+
+    pub fn emulator_config(&self) -> EmulatorConfig {
+        EmulatorConfig {
+            boot_mode: if self.skip_bios { BootMode::SkipBios } else { BootMode::Bios },
+            patch_header: self.patch_header,
+            idle_loop_skip: self.idle_loop_skip,
+            accuracy: self.accuracy,
+            cycle_timing: self.cycle_timing,
+            open_bus: self.open_bus,
+            save_dir: self.save_dir.clone(),
+            color_profile: self.video.color_profile,
+            deterministic: self.deterministic,
+            swi_log: self.swi_log,
+            ..EmulatorConfig::default()
+        }
+    }
+
+    pub fn preset(&self) -> Option<AccuracyPreset> { self.emulator_config().preset() }
+
+    pub fn set_preset(&mut self, preset: AccuracyPreset) {
+        let mut config = self.emulator_config();
+        preset.apply(&mut config);
+        self.accuracy = config.accuracy;
+        self.cycle_timing = config.cycle_timing;
+        self.idle_loop_skip = config.idle_loop_skip;
+        self.open_bus = config.open_bus;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct AudioConfig {
+    /// Output volume, 0.0 to 1.0.
+    pub volume: f32,
+    pub latency_ms: u32,
+    /// Largest resampling change rate control may make to keep the buffer
+    /// half full, as a fraction of the sample rate.
+    pub rate_control: f64,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self { volume: 1.0, latency_ms: 64, rate_control: 0.005 }
+    }
+}
+
+// Function to get the configuration directory.
+pub fn config_dir() -> Option<PathBuf> {
+    directories::ProjectDirs::from("com", "RoBA", "RoBA")
+        .map(|dirs| dirs.config_dir().to_path_buf())
+}
+
+// Function to load the configuration from a file.
+pub fn load_config() -> Config {
+    let Some(mut path) = config_dir() else {
+        return Config::default();
+    };
+    path.push("config.toml");
+    let Ok(config_str) = fs::read_to_string(&path) else {
+        return Config::default();
+    };
+    toml::from_str(&config_str).unwrap_or_default()
+}
+
+// Function to save the configuration to a file.
+pub fn save_config(config: &Config) -> io::Result<()> {
+    if let Some(mut path) = config_dir() {
+        fs::create_dir_all(&path)?;
+        path.push("config.toml");
+        let config_str =
+            toml::to_string(config).map_err(io::Error::other)?;
+        fs::write(&path, config_str)?;
+    }
+    Ok(())
+}
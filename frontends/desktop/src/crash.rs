@@ -0,0 +1,168 @@
+// Crash handling: a panic hook that remembers why the emulator panicked, a
+// boundary around each emulated frame, and a report written to the data
+// directory when a frame panics or the game runs an undefined instruction:
+//
+//     crashes/<unix time>/
+//         report.txt   the reason, CPU registers and the last instructions
+//         state.ss1    a savestate of the machine as it was left
+//
+// A window then shows the PC and opcode and lets the user continue or reset
+// instead of losing the app.
+
+use eframe::egui;
+use roba_core::cpu::TraceEntry;
+use roba_core::Emulator;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+// Message and location of the last panic, set by the hook on the panicking
+// thread before it unwinds.
+static LAST_PANIC: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn default_dir() -> Option<PathBuf> {
+    directories::ProjectDirs::from("com", "RoBA", "RoBA").map(|dirs| dirs.data_dir().join("crashes"))
+}
+
+/// Records panics for `guard` while still printing them as the default hook
+/// does.
+pub fn install_hook() {
+    let default = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let message = match info.payload().downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => info.payload().downcast_ref::<String>().cloned().unwrap_or_else(|| "unknown panic".to_string()),
+        };
+        let location = info.location().map(|l| format!(" at {}:{}", l.file(), l.line())).unwrap_or_default();
+        if let Ok(mut last) = LAST_PANIC.lock() {
+            *last = Some(format!("{}{}", message, location));
+        }
+        default(info);
+    }));
+}
+
+/// Runs `f`, turning a panic into an error with the panic message.
+pub fn guard<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f))
+        .map_err(|_| LAST_PANIC.lock().ok().and_then(|mut last| last.take()).unwrap_or_else(|| "panic".to_string()))
+}
+
+pub struct CrashReport {
+    pub reason: String,
+    /// The instruction that faulted, or the last one run before a panic.
+    pub instruction: Option<TraceEntry>,
+    /// Where the report went, or why it could not be written.
+    pub saved: Result<PathBuf, String>,
+}
+
+impl CrashReport {
+    /// Describes the machine as `reason` left it and writes the report.
+    /// `instruction` is the faulting one, when known.
+    pub fn capture(core: &mut Emulator, reason: String, instruction: Option<TraceEntry>) -> Self {
+        let instruction = instruction.or_else(|| core.cpu().trace().and_then(|t| t.last().copied()));
+        let text = describe(core, &reason, instruction);
+        // The machine may be half way through an instruction after a panic;
+        // a state that cannot be written is left out.
+        let state = guard(|| core.save_state()).ok();
+        let saved = write(&text, state.as_deref()).map_err(|e| e.to_string());
+        match &saved {
+            Ok(dir) => log::error!("Emulation stopped: {}; report written to {:?}", reason, dir),
+            Err(e) => log::error!("Emulation stopped: {}; failed to write the report: {}", reason, e),
+        }
+        Self { reason, instruction, saved }
+    }
+}
+
+fn describe(core: &Emulator, reason: &str, instruction: Option<TraceEntry>) -> String {
+    let cpu = core.cpu();
+    let mut text = String::new();
+    let _ = writeln!(text, "{}", reason);
+    if let Some(header) = core.rom_header() {
+        let _ = writeln!(text, "ROM: {} ({}) CRC32 {:08X}", header.title, header.game_code, core.rom_crc32());
+    }
+    let _ = writeln!(text, "Frame {}", core.frame_count());
+    if let Some(instruction) = instruction {
+        let _ = writeln!(text, "Instruction: {}", instruction);
+    }
+
+    let _ = writeln!(text, "\nRegisters:");
+    for row in 0..4 {
+        let regs: Vec<String> = (0..4).map(|i| row * 4 + i).map(|r| format!("r{:<2} {:08X}", r, cpu.read_reg(r))).collect();
+        let _ = writeln!(text, "  {}", regs.join("  "));
+    }
+    let _ = writeln!(text, "  CPSR {:08X} ({:?}, {:?})", cpu.cpsr().raw(), cpu.mode(), cpu.state());
+
+    match cpu.trace() {
+        Some(trace) => {
+            let _ = writeln!(text, "\nLast instructions, oldest first:");
+            for entry in trace.entries() {
+                let _ = writeln!(text, "  {}", entry);
+            }
+        }
+        None => {
+            let _ = writeln!(text, "\nNo instruction trace was recorded.");
+        }
+    }
+    text
+}
+
+fn write(text: &str, state: Option<&[u8]>) -> io::Result<PathBuf> {
+    let root = default_dir().ok_or_else(|| io::Error::other("no data directory"))?;
+    let secs = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+    let dir = root.join(secs.to_string());
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join("report.txt"), text)?;
+    if let Some(state) = state {
+        fs::write(dir.join("state.ss1"), state)?;
+    }
+    Ok(dir)
+}
+
+pub enum CrashAction {
+    Continue,
+    Reset,
+}
+
+/// Draws the report until the user picks what to do with the paused game.
+pub fn show(ctx: &egui::Context, report: &CrashReport) -> Option<CrashAction> {
+    let mut action = None;
+    egui::Window::new("Emulation stopped").collapsible(false).resizable(false).show(ctx, |ui| {
+        ui.label(&report.reason);
+        if let Some(instruction) = report.instruction {
+            let state = if instruction.thumb { "Thumb" } else { "ARM" };
+            let opcode = if instruction.thumb {
+                format!("{:04X}", instruction.opcode)
+            } else {
+                format!("{:08X}", instruction.opcode)
+            };
+            ui.monospace(format!("PC {:08X}  opcode {} ({})", instruction.pc, opcode, state));
+        }
+        match &report.saved {
+            Ok(dir) => {
+                ui.horizontal(|ui| {
+                    ui.label(format!("Report and savestate: {}", dir.display()));
+                    if ui.small_button("Copy").clicked() {
+                        ui.output_mut(|o| o.copied_text = dir.display().to_string());
+                    }
+                });
+            }
+            Err(e) => {
+                ui.colored_label(ui.visuals().warn_fg_color, format!("The report could not be written: {}", e));
+            }
+        }
+        ui.label("Continuing may misbehave; resetting restarts the game.");
+        ui.horizontal(|ui| {
+            if ui.button("Continue").clicked() {
+                action = Some(CrashAction::Continue);
+            }
+            if ui.button("Reset").clicked() {
+                action = Some(CrashAction::Reset);
+            }
+        });
+    });
+    action
+}
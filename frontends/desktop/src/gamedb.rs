@@ -0,0 +1,99 @@
+// User-maintained game database (`gamedb.toml` in the config directory).
+// Entries take priority over the database built into the core:
+//
+//     [[game]]
+//     code = "AXVE"
+//     backup = "flash128k"
+//     flash_chip = "macronix"
+//     rtc = true
+//     quirks = ["rumble"]
+
+use roba_core::cart::{BackupType, CartConfig, FlashChip, Quirks};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct GameDbFile {
+    game: Vec<GameEntry>,
+}
+
+#[derive(Deserialize)]
+struct GameEntry {
+    code: String,
+    backup: Option<String>,
+    flash_chip: Option<String>,
+    #[serde(default)]
+    rtc: bool,
+    #[serde(default)]
+    quirks: Vec<String>,
+}
+
+#[derive(Default)]
+pub struct GameDb {
+    entries: HashMap<String, CartConfig>,
+}
+
+impl GameDb {
+    pub fn load() -> Self {
+        let Some(mut path) = crate::config::config_dir() else {
+            return Self::default();
+        };
+        path.push("gamedb.toml");
+        let Ok(text) = fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        match Self::parse(&text) {
+            Ok(db) => {
+                log::info!("Loaded {} game database entries from {:?}", db.entries.len(), path);
+                db
+            }
+            Err(e) => {
+                log::warn!("Ignoring game database {:?}: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let file: GameDbFile = toml::from_str(text).map_err(|e| e.to_string())?;
+        let mut entries = HashMap::new();
+        for game in file.game {
+            let backup = match game.backup.as_deref() {
+                None | Some("auto") => None,
+                Some(name) => Some(
+                    BackupType::from_name(name)
+                        .ok_or_else(|| format!("{}: unknown backup type {:?}", game.code, name))?,
+                ),
+            };
+            let flash_chip = match game.flash_chip.as_deref() {
+                None => None,
+                Some(name) => Some(
+                    FlashChip::from_name(name)
+                        .ok_or_else(|| format!("{}: unknown flash chip {:?}", game.code, name))?,
+                ),
+            };
+            let mut quirks = Quirks::NONE;
+            for name in &game.quirks {
+                quirks = quirks
+                    | Quirks::from_name(name)
+                        .ok_or_else(|| format!("{}: unknown quirk {:?}", game.code, name))?;
+            }
+            entries.insert(game.code, CartConfig { backup, flash_chip, rtc: game.rtc, quirks });
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn lookup(&self, game_code: &str) -> Option<CartConfig> {
+        self.entries.get(game_code).copied()
+    }
+
+    /// Overrides the cart configuration of the ROM loaded in `core`, if listed.
+    pub fn apply(&self, core: &mut roba_core::Emulator) {
+        let code = core.rom_header().map(|h| h.game_code.clone());
+        if let Some(config) = code.and_then(|c| self.lookup(&c)) {
+            core.set_cart_config(config);
+        }
+    }
+}
@@ -0,0 +1,139 @@
+// Windowless movie playback, for regression tests in scripts and CI.
+
+use crate::config::Config;
+use crate::gamedb::GameDb;
+use roba_core::capture::Capture;
+use roba_core::cart::crc32;
+use roba_core::cpu::coverage::FormatStats;
+use roba_core::cpu::{ArmFormat, Coverage, ThumbFormat};
+use roba_core::movie::{Movie, MovieStatus};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Files to record the playback to.
+pub struct Dump {
+    pub wav: Option<PathBuf>,
+    pub video: Option<PathBuf>,
+    /// Instruction format coverage report, as JSON.
+    pub coverage: Option<PathBuf>,
+}
+
+/// Plays `movie` on `rom` to the end and prints the CRC32 and hash of the
+/// final frame and the machine state hash. Fails when `expect_crc` or
+/// `expect_hash` (the state hash) is given and does not match.
+pub fn run(
+    config: &Config,
+    rom: &Path,
+    bios: Option<PathBuf>,
+    movie: &Path,
+    dump: &Dump,
+    expect_crc: Option<u32>,
+    expect_hash: Option<u64>,
+) -> Result<(), String> {
+    let mut core = roba_core::Emulator::new();
+    core.set_config(config.emulator_config());
+    if let Some(bios) = &bios {
+        core.load_bios(bios).map_err(|e| format!("Failed to load BIOS {:?}: {}", bios, e))?;
+    }
+    core.load_rom(rom).map_err(|e| format!("Failed to load ROM {:?}: {}", rom, e))?;
+    core.hard_reset();
+    GameDb::load().apply(&mut core);
+
+    let movie = Movie::load(movie).map_err(|e| format!("Failed to load movie {:?}: {}", movie, e))?;
+    let frames = movie.frames.len();
+    if dump.coverage.is_some() {
+        core.cpu_mut().set_coverage(Coverage::new());
+    }
+    core.play_movie(movie).map_err(|e| e.to_string())?;
+    if dump.wav.is_some() || dump.video.is_some() {
+        core.start_capture(capture(dump, core.apu().sample_rate())?).map_err(|e| e.to_string())?;
+    }
+    while let MovieStatus::Playing { frame, frames } = core.movie_status()
+        && frame < frames
+    {
+        core.run_frame();
+    }
+    core.stop_capture().map_err(|e| format!("Failed to write the dump: {}", e))?;
+    if let (Some(path), Some(coverage)) = (&dump.coverage, core.cpu_mut().take_coverage()) {
+        let json = serde_json::to_string_pretty(&CoverageReport::new(&coverage)).map_err(|e| e.to_string())?;
+        std::fs::write(path, json).map_err(|e| format!("Failed to write {:?}: {}", path, e))?;
+    }
+
+    let crc = crc32(core.framebuffer_rgba());
+    let state_hash = core.state_hash();
+    println!("Played {} frames; framebuffer CRC32 {:08X}", frames, crc);
+    println!("Frame hash {:016X}; state hash {:016X}", core.frame_hash(), state_hash);
+    if let Some(expected) = expect_crc
+        && expected != crc
+    {
+        return Err(format!("Framebuffer CRC32 mismatch: expected {:08X}", expected));
+    }
+    if let Some(expected) = expect_hash
+        && expected != state_hash
+    {
+        return Err(format!("State hash mismatch: expected {:016X}", expected));
+    }
+    Ok(())
+}
+
+fn capture(dump: &Dump, sample_rate: u32) -> Result<Capture, String> {
+    let mut capture = Capture::new();
+    if let Some(path) = &dump.wav {
+        capture = capture.audio_file(path, sample_rate).map_err(|e| format!("Failed to create {:?}: {}", path, e))?;
+    }
+    if let Some(path) = &dump.video {
+        capture = capture.video_file(path).map_err(|e| format!("Failed to create {:?}: {}", path, e))?;
+    }
+    Ok(capture)
+}
+
+#[derive(Serialize)]
+struct CoverageReport {
+    instructions: u64,
+    arm: Vec<FormatReport>,
+    thumb: Vec<FormatReport>,
+}
+
+#[derive(Serialize)]
+struct FormatReport {
+    format: String,
+    count: u64,
+    examples: Vec<Example>,
+}
+
+#[derive(Serialize)]
+struct Example {
+    pc: String,
+    opcode: String,
+}
+
+impl CoverageReport {
+    // Every format is listed, including the ones that never ran.
+    fn new(coverage: &Coverage) -> Self {
+        Self {
+            instructions: coverage.total(),
+            arm: ArmFormat::ALL.iter().map(|&f| FormatReport::new(format!("{:?}", f), coverage.arm(f), 8)).collect(),
+            thumb: ThumbFormat::ALL
+                .iter()
+                .map(|&f| {
+                    let name = match f.number() {
+                        Some(n) => format!("{} ({:?})", n, f),
+                        None => format!("{:?}", f),
+                    };
+                    FormatReport::new(name, coverage.thumb(f), 4)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl FormatReport {
+    fn new(format: String, stats: &FormatStats, digits: usize) -> Self {
+        let examples = stats
+            .examples
+            .iter()
+            .map(|&(pc, opcode)| Example { pc: format!("{:08X}", pc), opcode: format!("{:0digits$X}", opcode) })
+            .collect();
+        Self { format, count: stats.count, examples }
+    }
+}
@@ -0,0 +1,443 @@
+// Maps keyboard keys and gamepad buttons/axes to GBA buttons and frontend
+// hotkeys. Gamepads need the `gamepad` feature (on by default); without it
+// pad bindings are kept in the config but never fire. Pads can be plugged in
+// and out while running, limited to one chosen pad, and given bindings of
+// their own.
+
+use eframe::egui;
+use roba_core::input::KeyState;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+#[cfg(feature = "gamepad")]
+use gilrs::{Axis, Button, EventType, Gilrs};
+
+// Stick deflection that binds an axis in the capture-to-bind UI.
+#[cfg(feature = "gamepad")]
+const CAPTURE_THRESHOLD: f32 = 0.5;
+// Outside this fraction of the stick's deflection the other axis counts too,
+// which splits a stick used as the D-pad into eight equal sectors (sin 22.5°).
+#[cfg(feature = "gamepad")]
+const DIAGONAL: f32 = 0.38;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Hotkey {
+    FastForward,
+    Rewind,
+    SaveState,
+    LoadState,
+    Screenshot,
+    RecordClip,
+    Fullscreen,
+    Pause,
+    FrameAdvance,
+    StepFrames,
+    SoftReset,
+    HardReset,
+}
+
+impl Hotkey {
+    pub const ALL: [Hotkey; 12] = [
+        Hotkey::FastForward,
+        Hotkey::Rewind,
+        Hotkey::SaveState,
+        Hotkey::LoadState,
+        Hotkey::Screenshot,
+        Hotkey::RecordClip,
+        Hotkey::Fullscreen,
+        Hotkey::Pause,
+        Hotkey::FrameAdvance,
+        Hotkey::StepFrames,
+        Hotkey::SoftReset,
+        Hotkey::HardReset,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Hotkey::FastForward => "Fast forward",
+            Hotkey::Rewind => "Rewind",
+            Hotkey::SaveState => "Save state",
+            Hotkey::LoadState => "Load state",
+            Hotkey::Screenshot => "Screenshot",
+            Hotkey::RecordClip => "Record GIF",
+            Hotkey::Fullscreen => "Fullscreen",
+            Hotkey::Pause => "Pause",
+            Hotkey::FrameAdvance => "Frame advance",
+            Hotkey::StepFrames => "Step frames",
+            Hotkey::SoftReset => "Soft reset",
+            Hotkey::HardReset => "Hard reset",
+        }
+    }
+}
+
+/// One physical input. Serialized as `"Key X"`, `"Button South"` or
+/// `"Axis LeftStickX+"`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Binding {
+    Key(egui::Key),
+    Button(String),
+    Axis { axis: String, positive: bool },
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Binding::Key(key) => write!(f, "Key {}", key.name()),
+            Binding::Button(button) => write!(f, "Button {}", button),
+            Binding::Axis { axis, positive } => {
+                write!(f, "Axis {}{}", axis, if *positive { '+' } else { '-' })
+            }
+        }
+    }
+}
+
+impl FromStr for Binding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (kind, name) = s.split_once(' ').ok_or_else(|| format!("bad binding {:?}", s))?;
+        match kind {
+            "Key" => egui::Key::from_name(name)
+                .map(Binding::Key)
+                .ok_or_else(|| format!("unknown key {:?}", name)),
+            "Button" => Ok(Binding::Button(name.to_string())),
+            "Axis" => {
+                let (axis, positive) = match name.strip_suffix('+') {
+                    Some(axis) => (axis, true),
+                    None => (name.strip_suffix('-').ok_or("axis needs a +/- suffix")?, false),
+                };
+                Ok(Binding::Axis { axis: axis.to_string(), positive })
+            }
+            _ => Err(format!("unknown binding kind {:?}", kind)),
+        }
+    }
+}
+
+impl TryFrom<String> for Binding {
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, String> { s.parse() }
+}
+
+impl From<Binding> for String {
+    fn from(binding: Binding) -> String { binding.to_string() }
+}
+
+/// Action name (GBA button or hotkey name) to its bindings.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(transparent)]
+pub struct InputMap(BTreeMap<String, Vec<Binding>>);
+
+impl Default for InputMap {
+    fn default() -> Self {
+        use Binding::Key;
+        use egui::Key as K;
+
+        let button = |name: &str| Binding::Button(name.to_string());
+        let axis = |name: &str, positive| Binding::Axis { axis: name.to_string(), positive };
+        let defaults = [
+            ("A", vec![Key(K::X), button("East")]),
+            ("B", vec![Key(K::Z), button("South")]),
+            ("Select", vec![Key(K::Backspace), button("Select")]),
+            ("Start", vec![Key(K::Enter), button("Start")]),
+            ("Right", vec![Key(K::ArrowRight), button("DPadRight"), axis("LeftStickX", true)]),
+            ("Left", vec![Key(K::ArrowLeft), button("DPadLeft"), axis("LeftStickX", false)]),
+            ("Up", vec![Key(K::ArrowUp), button("DPadUp"), axis("LeftStickY", true)]),
+            ("Down", vec![Key(K::ArrowDown), button("DPadDown"), axis("LeftStickY", false)]),
+            ("R", vec![Key(K::S), button("RightTrigger")]),
+            ("L", vec![Key(K::A), button("LeftTrigger")]),
+            (Hotkey::FastForward.name(), vec![Key(K::Tab), button("RightTrigger2")]),
+            (Hotkey::Rewind.name(), vec![Key(K::Backtick), button("LeftTrigger2")]),
+            (Hotkey::SaveState.name(), vec![Key(K::F5)]),
+            (Hotkey::LoadState.name(), vec![Key(K::F7)]),
+            (Hotkey::Screenshot.name(), vec![Key(K::F12)]),
+            (Hotkey::RecordClip.name(), vec![Key(K::F10)]),
+            (Hotkey::Fullscreen.name(), vec![Key(K::F11)]),
+            (Hotkey::Pause.name(), vec![Key(K::P)]),
+            (Hotkey::FrameAdvance.name(), vec![Key(K::N)]),
+            (Hotkey::StepFrames.name(), vec![Key(K::M)]),
+            (Hotkey::SoftReset.name(), vec![Key(K::F3)]),
+            (Hotkey::HardReset.name(), vec![Key(K::F4)]),
+        ];
+        Self(defaults.into_iter().map(|(name, b)| (name.to_string(), b)).collect())
+    }
+}
+
+impl InputMap {
+    pub fn bindings(&self, action: &str) -> &[Binding] {
+        self.0.get(action).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn add(&mut self, action: &str, binding: Binding) {
+        let list = self.0.entry(action.to_string()).or_default();
+        if !list.contains(&binding) {
+            list.push(binding);
+        }
+    }
+
+    pub fn remove(&mut self, action: &str, binding: &Binding) {
+        if let Some(list) = self.0.get_mut(action) {
+            list.retain(|b| b != binding);
+        }
+    }
+
+    /// The button and axis bindings alone, to start a pad's own map from.
+    pub fn pad_bindings(&self) -> Self {
+        let pad = |list: &Vec<Binding>| list.iter().filter(|b| !matches!(b, Binding::Key(_))).cloned().collect();
+        Self(self.0.iter().map(|(action, list)| (action.clone(), pad(list))).collect())
+    }
+
+    fn is_down(&self, action: &str, input: &egui::InputState, pads: &Gamepads, config: &GamepadConfig) -> bool {
+        let key = self.bindings(action).iter().any(|binding| matches!(binding, Binding::Key(key) if input.key_down(*key)));
+        key || pads.action_down(action, self, config)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DpadStick {
+    #[default]
+    Off,
+    Left,
+    Right,
+}
+
+impl DpadStick {
+    pub const ALL: [DpadStick; 3] = [DpadStick::Off, DpadStick::Left, DpadStick::Right];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DpadStick::Off => "Off",
+            DpadStick::Left => "Left stick",
+            DpadStick::Right => "Right stick",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct GamepadConfig {
+    /// Name of the only pad read; None reads every connected pad.
+    pub device: Option<String>,
+    /// Stick deflection, 0.0 to 1.0, below which axis bindings stay up.
+    pub deadzone: f32,
+    /// Stick that also drives the D-pad, diagonals included.
+    pub dpad_stick: DpadStick,
+    /// Button and axis bindings of particular pads, by name, used for those
+    /// pads instead of the ones in the shared map. Keys stay shared.
+    pub bindings: BTreeMap<String, InputMap>,
+}
+
+impl Default for GamepadConfig {
+    fn default() -> Self {
+        Self { device: None, deadzone: 0.5, dpad_stick: DpadStick::Off, bindings: BTreeMap::new() }
+    }
+}
+
+/// Input sampled for one frame.
+#[derive(Default)]
+pub struct InputFrame {
+    pub keys: KeyState,
+    pub fast_forward: bool,
+    pub rewind: bool,
+    /// Hotkeys that went down since the previous frame.
+    pub triggered: Vec<Hotkey>,
+}
+
+#[derive(Default)]
+pub struct InputHandler {
+    pub pads: Gamepads,
+    held: Vec<Hotkey>,
+}
+
+impl InputHandler {
+    pub fn new() -> Self {
+        Self { pads: Gamepads::new(), held: Vec::new() }
+    }
+
+    pub fn poll(&mut self, ctx: &egui::Context, map: &InputMap, pads: &GamepadConfig) -> InputFrame {
+        self.pads.poll();
+        if ctx.wants_keyboard_input() {
+            self.held.clear();
+            return InputFrame::default();
+        }
+        ctx.input(|input| {
+            let mut frame = InputFrame::default();
+            for (name, button) in KeyState::BUTTONS {
+                frame.keys.set(button, map.is_down(name, input, &self.pads, pads));
+            }
+            let held: Vec<Hotkey> = Hotkey::ALL
+                .into_iter()
+                .filter(|hk| map.is_down(hk.name(), input, &self.pads, pads))
+                .collect();
+            frame.fast_forward = held.contains(&Hotkey::FastForward);
+            frame.rewind = held.contains(&Hotkey::Rewind);
+            frame.triggered = held.iter().copied().filter(|hk| !self.held.contains(hk)).collect();
+            self.held = held;
+            frame
+        })
+    }
+
+    /// Drops pad input seen before the capture-to-bind UI started listening.
+    pub fn begin_capture(&mut self) {
+        self.pads.poll();
+        self.pads.take_captured();
+    }
+
+    /// Returns the next key or pad input for the capture-to-bind UI. With
+    /// `pad`, only that pad's input counts, though Escape still cancels.
+    pub fn capture(&mut self, ctx: &egui::Context, pad: Option<&str>) -> Option<Binding> {
+        self.pads.poll();
+        let key = ctx.input(|input| {
+            input.events.iter().find_map(|event| match event {
+                egui::Event::Key { key, pressed: true, repeat: false, .. } => Some(*key),
+                _ => None,
+            })
+        });
+        let key = key.filter(|&key| pad.is_none() || key == egui::Key::Escape);
+        key.map(Binding::Key).or_else(|| {
+            let (from, binding) = self.pads.take_captured()?;
+            pad.is_none_or(|pad| pad == from).then_some(binding)
+        })
+    }
+}
+
+#[cfg(feature = "gamepad")]
+pub struct Gamepads {
+    gilrs: Option<Gilrs>,
+    // The last button or stick moved, and the pad it came from.
+    captured: Option<(String, Binding)>,
+    notices: Vec<String>,
+}
+
+#[cfg(feature = "gamepad")]
+impl Default for Gamepads {
+    fn default() -> Self { Self::new() }
+}
+
+#[cfg(feature = "gamepad")]
+impl Gamepads {
+    pub fn new() -> Self {
+        let gilrs = Gilrs::new().map_err(|e| log::warn!("Gamepad support unavailable: {}", e)).ok();
+        Self { gilrs, captured: None, notices: Vec::new() }
+    }
+
+    pub fn gilrs_mut(&mut self) -> Option<&mut Gilrs> { self.gilrs.as_mut() }
+
+    pub fn poll(&mut self) {
+        let Some(gilrs) = &mut self.gilrs else {
+            return;
+        };
+        while let Some(event) = gilrs.next_event() {
+            let name = gilrs.gamepad(event.id).name().to_string();
+            match event.event {
+                EventType::ButtonPressed(button, _) if button != Button::Unknown => {
+                    self.captured = Some((name, Binding::Button(format!("{:?}", button))));
+                }
+                EventType::AxisChanged(axis, value, _)
+                    if axis != Axis::Unknown && value.abs() > CAPTURE_THRESHOLD =>
+                {
+                    let binding = Binding::Axis { axis: format!("{:?}", axis), positive: value > 0.0 };
+                    self.captured = Some((name, binding));
+                }
+                EventType::Connected => {
+                    log::info!("Gamepad connected: {}", name);
+                    self.notices.push(format!("{} connected", name));
+                }
+                EventType::Disconnected => {
+                    log::info!("Gamepad disconnected: {}", name);
+                    self.notices.push(format!("{} disconnected", name));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Names of the connected pads, in connection order.
+    pub fn connected(&self) -> Vec<String> {
+        self.gilrs.iter().flat_map(|gilrs| gilrs.gamepads().map(|(_, pad)| pad.name().to_string())).collect()
+    }
+
+    /// Pads plugged in or out since the last call, as messages for the user.
+    pub fn take_notices(&mut self) -> Vec<String> { std::mem::take(&mut self.notices) }
+
+    fn take_captured(&mut self) -> Option<(String, Binding)> { self.captured.take() }
+
+    // Whether any pad in use holds `action` down, through its own bindings
+    // or `shared`, or through the stick standing in for the D-pad.
+    fn action_down(&self, action: &str, shared: &InputMap, config: &GamepadConfig) -> bool {
+        let Some(gilrs) = &self.gilrs else {
+            return false;
+        };
+        let dpad = ["Right", "Left", "Up", "Down"].iter().position(|&d| d == action);
+        gilrs
+            .gamepads()
+            .filter(|(_, pad)| config.device.as_deref().is_none_or(|device| device == pad.name()))
+            .any(|(_, pad)| {
+                let map = config.bindings.get(pad.name()).unwrap_or(shared);
+                let bound = map.bindings(action).iter().any(|binding| match binding {
+                    Binding::Key(_) => false,
+                    Binding::Button(name) => button_from_name(name).is_some_and(|b| pad.is_pressed(b)),
+                    Binding::Axis { axis, positive } => axis_from_name(axis).is_some_and(|axis| {
+                        let value = pad.value(axis);
+                        if *positive { value > config.deadzone } else { value < -config.deadzone }
+                    }),
+                });
+                let stick = match config.dpad_stick {
+                    DpadStick::Off => None,
+                    DpadStick::Left => Some((Axis::LeftStickX, Axis::LeftStickY)),
+                    DpadStick::Right => Some((Axis::RightStickX, Axis::RightStickY)),
+                };
+                let steered = dpad.zip(stick).is_some_and(|(direction, (x, y))| {
+                    stick_directions(pad.value(x), pad.value(y), config.deadzone)[direction]
+                });
+                bound || steered
+            })
+    }
+}
+
+// Right, left, up and down for a stick at (x, y), up positive; nothing
+// inside the deadzone.
+#[cfg(feature = "gamepad")]
+fn stick_directions(x: f32, y: f32, deadzone: f32) -> [bool; 4] {
+    let length = x.hypot(y);
+    if length <= deadzone {
+        return [false; 4];
+    }
+    let (x, y) = (x / length, y / length);
+    [x > DIAGONAL, x < -DIAGONAL, y > DIAGONAL, y < -DIAGONAL]
+}
+
+#[cfg(feature = "gamepad")]
+fn button_from_name(name: &str) -> Option<Button> {
+    use Button::*;
+    [
+        South, East, North, West, C, Z, LeftTrigger, LeftTrigger2, RightTrigger, RightTrigger2,
+        Select, Start, Mode, LeftThumb, RightThumb, DPadUp, DPadDown, DPadLeft, DPadRight,
+    ]
+    .into_iter()
+    .find(|b| format!("{:?}", b) == name)
+}
+
+#[cfg(feature = "gamepad")]
+fn axis_from_name(name: &str) -> Option<Axis> {
+    use Axis::*;
+    [LeftStickX, LeftStickY, LeftZ, RightStickX, RightStickY, RightZ, DPadX, DPadY]
+        .into_iter()
+        .find(|a| format!("{:?}", a) == name)
+}
+
+#[cfg(not(feature = "gamepad"))]
+#[derive(Default)]
+pub struct Gamepads;
+
+#[cfg(not(feature = "gamepad"))]
+impl Gamepads {
+    pub fn new() -> Self { Self }
+    pub fn poll(&mut self) {}
+    pub fn connected(&self) -> Vec<String> { Vec::new() }
+    pub fn take_notices(&mut self) -> Vec<String> { Vec::new() }
+    fn take_captured(&mut self) -> Option<(String, Binding)> { None }
+    fn action_down(&self, _action: &str, _shared: &InputMap, _config: &GamepadConfig) -> bool { false }
+}
@@ -0,0 +1,159 @@
+// PPU debugging overlay: false colors over the game showing which layer drew
+// each pixel, its priority, or where a color effect applied, from the
+// per-pixel metadata the core records while an overlay is chosen. The debug
+// panel section also reads out the pixel under the mouse and can hide layers
+// to isolate a glitch to one of them.
+
+use eframe::egui;
+use egui::Color32;
+use roba_core::ppu::{PixelInfo, PixelSource, LAYER_ALL, LAYER_OBJ};
+use roba_core::video::{GBA_SCREEN_H, GBA_SCREEN_W};
+use roba_core::Emulator;
+
+const BG_COLORS: [Color32; 4] = [
+    Color32::from_rgb(230, 70, 70),
+    Color32::from_rgb(80, 200, 80),
+    Color32::from_rgb(70, 120, 240),
+    Color32::from_rgb(230, 210, 60),
+];
+const OBJ_COLOR: Color32 = Color32::from_rgb(230, 80, 230);
+const BLEND_COLOR: Color32 = Color32::from_rgb(60, 220, 230);
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum Overlay {
+    #[default]
+    Off,
+    /// One color per BG, OBJ in magenta.
+    Layer,
+    /// Brighter for layers in front.
+    Priority,
+    /// Pixels a color effect changed.
+    Blend,
+    /// Only the pixels sprites won.
+    Obj,
+}
+
+impl Overlay {
+    pub const ALL: [Overlay; 5] = [Overlay::Off, Overlay::Layer, Overlay::Priority, Overlay::Blend, Overlay::Obj];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Overlay::Off => "Off",
+            Overlay::Layer => "Layer",
+            Overlay::Priority => "Priority",
+            Overlay::Blend => "Blending",
+            Overlay::Obj => "OBJ pixels",
+        }
+    }
+
+    // Transparent where the overlay has nothing to show.
+    fn color(self, info: &PixelInfo) -> Color32 {
+        match self {
+            Overlay::Off => Color32::TRANSPARENT,
+            Overlay::Layer => match info.source {
+                PixelSource::Backdrop => Color32::TRANSPARENT,
+                PixelSource::Bg(n) => BG_COLORS[n as usize & 3],
+                PixelSource::Obj => OBJ_COLOR,
+            },
+            Overlay::Priority if info.priority < 4 => Color32::from_gray(255 - info.priority * 60),
+            Overlay::Blend if info.blended => BLEND_COLOR,
+            Overlay::Obj if info.source == PixelSource::Obj => OBJ_COLOR,
+            _ => Color32::TRANSPARENT,
+        }
+    }
+}
+
+pub struct LayerOverlay {
+    overlay: Overlay,
+    opacity: f32,
+    texture: Option<egui::TextureHandle>,
+    // What drew the pixel under the mouse, as of the last paint.
+    hovered: Option<(usize, usize, PixelInfo)>,
+    // Layers shown, as a core layer mask.
+    shown: u8,
+}
+
+impl Default for LayerOverlay {
+    fn default() -> Self { Self { overlay: Overlay::Off, opacity: 0.6, texture: None, hovered: None, shown: LAYER_ALL } }
+}
+
+impl LayerOverlay {
+    /// Builds the overlay from the frame the core just drew; call once per
+    /// repaint while a game runs.
+    pub fn update(&mut self, ctx: &egui::Context, core: &mut Emulator) {
+        core.ppu_mut().set_layer_mask(self.shown);
+        let enabled = self.overlay != Overlay::Off;
+        core.ppu_mut().set_pixel_info(enabled);
+        let Some(info) = core.ppu().pixel_info().filter(|_| enabled) else {
+            self.texture = None;
+            self.hovered = None;
+            return;
+        };
+        let image = egui::ColorImage {
+            size: [GBA_SCREEN_W, GBA_SCREEN_H],
+            pixels: info.iter().map(|p| self.overlay.color(p)).collect(),
+        };
+        match &mut self.texture {
+            Some(texture) => texture.set(image, egui::TextureOptions::NEAREST),
+            None => self.texture = Some(ctx.load_texture("layer_overlay", image, egui::TextureOptions::NEAREST)),
+        }
+    }
+
+    /// Paints the overlay over the game `image` and notes the pixel under
+    /// the mouse.
+    pub fn paint(&mut self, ui: &egui::Ui, image: egui::Rect, core: &Emulator) {
+        let Some(texture) = &self.texture else {
+            return;
+        };
+        let uv = egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0));
+        ui.painter_at(image).image(texture.id(), image, uv, Color32::WHITE.gamma_multiply(self.opacity));
+
+        self.hovered = ui.input(|i| i.pointer.hover_pos()).filter(|pos| image.contains(*pos)).and_then(|pos| {
+            let x = ((pos.x - image.min.x) / image.width() * GBA_SCREEN_W as f32) as usize;
+            let y = ((pos.y - image.min.y) / image.height() * GBA_SCREEN_H as f32) as usize;
+            let (x, y) = (x.min(GBA_SCREEN_W - 1), y.min(GBA_SCREEN_H - 1));
+            core.ppu().pixel_info().map(|info| (x, y, info[y * GBA_SCREEN_W + x]))
+        });
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal_wrapped(|ui| {
+            ui.label("Show");
+            for (bit, name) in [(1, "BG0"), (1 << 1, "BG1"), (1 << 2, "BG2"), (1 << 3, "BG3"), (LAYER_OBJ, "OBJ")] {
+                let mut shown = self.shown & bit != 0;
+                if ui.checkbox(&mut shown, name).changed() {
+                    self.shown ^= bit;
+                }
+            }
+            if self.shown != LAYER_ALL && ui.small_button("All").clicked() {
+                self.shown = LAYER_ALL;
+            }
+        });
+        egui::ComboBox::from_label("Overlay").selected_text(self.overlay.name()).show_ui(ui, |ui| {
+            for overlay in Overlay::ALL {
+                ui.selectable_value(&mut self.overlay, overlay, overlay.name());
+            }
+        });
+        ui.add(egui::Slider::new(&mut self.opacity, 0.1..=1.0).text("Opacity"));
+
+        if self.overlay == Overlay::Layer {
+            ui.horizontal_wrapped(|ui| {
+                for (n, color) in BG_COLORS.iter().enumerate() {
+                    ui.colored_label(*color, format!("BG{}", n));
+                }
+                ui.colored_label(OBJ_COLOR, "OBJ");
+            });
+        }
+        match self.hovered {
+            Some((x, y, info)) => {
+                let priority = if info.priority < 4 { info.priority.to_string() } else { "-".to_string() };
+                let blended = if info.blended { ", blended" } else { "" };
+                ui.label(format!("({}, {}): {}, priority {}{}", x, y, info.source.name(), priority, blended));
+            }
+            None if self.overlay != Overlay::Off => {
+                ui.label("Hover the game to inspect a pixel");
+            }
+            None => {}
+        }
+    }
+}
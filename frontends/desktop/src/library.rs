@@ -0,0 +1,402 @@
+// ROM library shown while no game is running: every ROM (or zipped/gzipped
+// ROM) under the configured folders plus the recently opened ones, with the header title and
+// region, when each was last played and for how long, and optional box art.
+//
+// Play history is kept in `library.toml` in the data directory. Box art is
+// looked up in the cover folder as `<game code>.png`, then `<ROM stem>.png`.
+
+use crate::config::Config;
+use eframe::egui;
+use roba_core::archive;
+use roba_core::cart::RomHeader;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Folder levels searched below each ROM directory.
+const SCAN_DEPTH: usize = 4;
+const HEADER_LEN: usize = 0xC0;
+
+/// Extensions opened as ROMs; archives are extracted by the core.
+pub const ROM_EXTENSIONS: [&str; 4] = ["gba", "mb", "zip", "gz"];
+const COVER_SIZE: egui::Vec2 = egui::vec2(64.0, 64.0);
+
+pub struct RomInfo {
+    pub path: PathBuf,
+    /// Header title, or the file name when the header has none.
+    pub title: String,
+    pub game_code: String,
+    pub region: &'static str,
+}
+
+impl RomInfo {
+    /// Reads just the header of plain ROMs, so scanning large folders stays
+    /// quick; archives are extracted in full.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        File::open(path)?.take(HEADER_LEN as u64).read_to_end(&mut header)?;
+        if archive::is_archive(&header) {
+            let data = fs::read(path)?;
+            header = archive::extract_rom(&data).map_err(io::Error::other)?.into_owned();
+        }
+        let stem = || path.file_stem().map_or_else(String::new, |s| s.to_string_lossy().into_owned());
+        Ok(match RomHeader::parse(&header) {
+            Some(h) => Self {
+                path: path.to_path_buf(),
+                title: if h.title.trim().is_empty() { stem() } else { h.title.trim().to_string() },
+                region: region_name(&h.game_code),
+                game_code: h.game_code,
+            },
+            None => Self { path: path.to_path_buf(), title: stem(), game_code: String::new(), region: "Unknown" },
+        })
+    }
+}
+
+/// Region from the last letter of a game code (`AXVE` is a US release).
+pub fn region_name(game_code: &str) -> &'static str {
+    match game_code.chars().nth(3) {
+        Some('J') => "Japan",
+        Some('E') => "USA",
+        Some('P' | 'X' | 'Y' | 'Z') => "Europe",
+        Some('D') => "Germany",
+        Some('F') => "France",
+        Some('I') => "Italy",
+        Some('S') => "Spain",
+        Some('H') => "Netherlands",
+        Some('U') => "Australia",
+        Some('K') => "Korea",
+        Some('C') => "China",
+        _ => "Unknown",
+    }
+}
+
+pub fn is_rom_file(path: &Path) -> bool {
+    path.extension().is_some_and(|e| ROM_EXTENSIONS.iter().any(|ext| e.eq_ignore_ascii_case(ext)))
+}
+
+/// ROM files under `dir`, at most `depth` folders down.
+pub fn scan_dir(dir: &Path, depth: usize, out: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        log::warn!("Cannot read ROM directory {:?}", dir);
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            if depth > 0 {
+                scan_dir(&path, depth - 1, out);
+            }
+        } else if is_rom_file(&path) {
+            out.push(path);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(default)]
+pub struct PlayRecord {
+    /// Unix time the game was last started.
+    pub last_played: u64,
+    pub play_seconds: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct HistoryFile {
+    games: BTreeMap<String, PlayRecord>,
+}
+
+/// Per-ROM play history, keyed by path.
+#[derive(Default)]
+pub struct PlayHistory {
+    games: BTreeMap<String, PlayRecord>,
+}
+
+impl PlayHistory {
+    fn file() -> Option<PathBuf> {
+        directories::ProjectDirs::from("com", "RoBA", "RoBA").map(|dirs| dirs.data_dir().join("library.toml"))
+    }
+
+    pub fn load() -> Self {
+        let Some(path) = Self::file() else {
+            return Self::default();
+        };
+        let Ok(text) = fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        match toml::from_str::<HistoryFile>(&text) {
+            Ok(file) => Self { games: file.games },
+            Err(e) => {
+                log::warn!("Ignoring play history {:?}: {}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = Self::file() else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = HistoryFile { games: self.games.clone() };
+        fs::write(&path, toml::to_string(&file).map_err(io::Error::other)?)
+    }
+
+    pub fn get(&self, rom: &Path) -> Option<PlayRecord> { self.games.get(&key(rom)).copied() }
+
+    /// Records a session of `rom` that began at `started` and ran for `played`.
+    pub fn add_session(&mut self, rom: &Path, started: SystemTime, played: Duration) {
+        let record = self.games.entry(key(rom)).or_default();
+        record.last_played = unix_seconds(started);
+        record.play_seconds += played.as_secs();
+    }
+}
+
+fn key(rom: &Path) -> String { rom.to_string_lossy().into_owned() }
+
+fn unix_seconds(time: SystemTime) -> u64 { time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()) }
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SortKey {
+    Title,
+    LastPlayed,
+    PlayTime,
+    Path,
+}
+
+impl SortKey {
+    const ALL: [Self; 4] = [Self::Title, Self::LastPlayed, Self::PlayTime, Self::Path];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Title => "Title",
+            Self::LastPlayed => "Last played",
+            Self::PlayTime => "Play time",
+            Self::Path => "Path",
+        }
+    }
+}
+
+pub struct Library {
+    roms: Vec<RomInfo>,
+    history: PlayHistory,
+    // Cleared to rescan on the next frame.
+    scanned: bool,
+    search: String,
+    sort: SortKey,
+    // Box art per ROM; `None` once looked up and not found.
+    covers: HashMap<PathBuf, Option<egui::TextureHandle>>,
+}
+
+impl Library {
+    pub fn new() -> Self {
+        Self {
+            roms: Vec::new(),
+            history: PlayHistory::load(),
+            scanned: false,
+            search: String::new(),
+            sort: SortKey::LastPlayed,
+            covers: HashMap::new(),
+        }
+    }
+
+    pub fn add_session(&mut self, rom: &Path, started: SystemTime, played: Duration) {
+        self.history.add_session(rom, started, played);
+        if let Err(e) = self.history.save() {
+            log::warn!("Failed to save play history: {}", e);
+        }
+    }
+
+    fn rescan(&mut self, config: &Config) {
+        let mut paths = Vec::new();
+        for dir in &config.rom_dirs {
+            scan_dir(dir, SCAN_DEPTH, &mut paths);
+        }
+        paths.extend(config.recent_files.iter().filter(|p| p.is_file()).cloned());
+        paths.sort();
+        paths.dedup();
+        self.roms = paths
+            .iter()
+            .filter_map(|path| match RomInfo::read(path) {
+                Ok(info) => Some(info),
+                Err(e) => {
+                    log::warn!("Skipping {:?}: {}", path, e);
+                    None
+                }
+            })
+            .collect();
+        self.covers.clear();
+        self.scanned = true;
+        log::info!("Library: {} ROMs", self.roms.len());
+    }
+
+    fn sort(&mut self) {
+        let history = &self.history;
+        let played = |rom: &RomInfo| history.get(&rom.path).unwrap_or_default();
+        match self.sort {
+            SortKey::Title => self.roms.sort_by_key(|r| r.title.to_lowercase()),
+            SortKey::LastPlayed => self.roms.sort_by_key(|r| std::cmp::Reverse(played(r).last_played)),
+            SortKey::PlayTime => self.roms.sort_by_key(|r| std::cmp::Reverse(played(r).play_seconds)),
+            SortKey::Path => self.roms.sort_by(|a, b| a.path.cmp(&b.path)),
+        }
+    }
+
+    /// Draws the library; returns the ROM the user picked.
+    pub fn show(&mut self, ui: &mut egui::Ui, config: &mut Config) -> Option<PathBuf> {
+        if !self.scanned {
+            self.rescan(config);
+        }
+        let mut picked = None;
+
+        ui.horizontal(|ui| {
+            ui.heading("Library");
+            ui.add(egui::TextEdit::singleline(&mut self.search).hint_text("Search").desired_width(200.0));
+            egui::ComboBox::from_id_source("library_sort")
+                .selected_text(format!("Sort: {}", self.sort.label()))
+                .show_ui(ui, |ui| {
+                    for key in SortKey::ALL {
+                        ui.selectable_value(&mut self.sort, key, key.label());
+                    }
+                });
+            if ui.button("Add folder...").clicked()
+                && let Some(dir) = rfd::FileDialog::new().set_title("Add ROM folder").pick_folder()
+                && !config.rom_dirs.contains(&dir)
+            {
+                config.rom_dirs.push(dir);
+                self.scanned = false;
+            }
+            if ui.button("Rescan").clicked() {
+                self.scanned = false;
+            }
+        });
+        let mut removed = None;
+        for (i, dir) in config.rom_dirs.iter().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(dir.display().to_string());
+                if ui.small_button("Remove").clicked() {
+                    removed = Some(i);
+                }
+            });
+        }
+        if let Some(i) = removed {
+            config.rom_dirs.remove(i);
+            self.scanned = false;
+        }
+        ui.separator();
+
+        if self.roms.is_empty() {
+            ui.label("No ROMs found. Add a folder above, drop a ROM onto the window or use 'File -> Open ROM...' to get started.");
+            return None;
+        }
+
+        self.sort();
+        let search = self.search.to_lowercase();
+        let now = unix_seconds(SystemTime::now());
+        let cover_dir = config.cover_dir.clone();
+        let ctx = ui.ctx().clone();
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for rom in &self.roms {
+                if !search.is_empty()
+                    && !rom.title.to_lowercase().contains(&search)
+                    && !rom.game_code.to_lowercase().contains(&search)
+                    && !rom.path.to_string_lossy().to_lowercase().contains(&search)
+                {
+                    continue;
+                }
+                let cover = cover_dir.as_deref().and_then(|dir| cover(&mut self.covers, &ctx, dir, rom));
+                let record = self.history.get(&rom.path);
+                ui.horizontal(|ui| {
+                    if cover_dir.is_some() {
+                        match &cover {
+                            Some(texture) => {
+                                ui.add(egui::Image::new((texture.id(), COVER_SIZE)));
+                            }
+                            None => {
+                                ui.allocate_space(COVER_SIZE);
+                            }
+                        }
+                    }
+                    ui.vertical(|ui| {
+                        if ui.button(egui::RichText::new(&rom.title).strong()).clicked() {
+                            picked = Some(rom.path.clone());
+                        }
+                        let code = if rom.game_code.is_empty() { "----" } else { &rom.game_code };
+                        ui.label(format!("{} · {}", code, rom.region));
+                        ui.label(match record {
+                            Some(r) if r.last_played > 0 => format!(
+                                "Last played {} · {} played",
+                                format_ago(now.saturating_sub(r.last_played)),
+                                format_play_time(r.play_seconds)
+                            ),
+                            _ => "Never played".to_string(),
+                        });
+                        ui.weak(rom.path.display().to_string());
+                    });
+                });
+                ui.separator();
+            }
+        });
+        picked
+    }
+}
+
+// Box art for `rom`, loaded on first use.
+fn cover(
+    covers: &mut HashMap<PathBuf, Option<egui::TextureHandle>>,
+    ctx: &egui::Context,
+    cover_dir: &Path,
+    rom: &RomInfo,
+) -> Option<egui::TextureHandle> {
+    covers
+        .entry(rom.path.clone())
+        .or_insert_with(|| {
+            let stem = rom.path.file_stem()?.to_string_lossy().into_owned();
+            let path = [rom.game_code.as_str(), &stem]
+                .into_iter()
+                .filter(|name| !name.is_empty())
+                .map(|name| cover_dir.join(format!("{}.png", name)))
+                .find(|path| path.is_file())?;
+            match load_png(&path) {
+                Ok(image) => Some(ctx.load_texture(path.display().to_string(), image, Default::default())),
+                Err(e) => {
+                    log::warn!("Cannot load box art {:?}: {}", path, e);
+                    None
+                }
+            }
+        })
+        .clone()
+}
+
+fn load_png(path: &Path) -> Result<egui::ColorImage, png::DecodingError> {
+    let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size().unwrap_or(0)];
+    let info = reader.next_frame(&mut buf)?;
+    let pixels = &buf[..info.buffer_size()];
+    let size = [info.width as usize, info.height as usize];
+    let rgba: Vec<u8> = match info.color_type {
+        png::ColorType::Rgba => pixels.to_vec(),
+        png::ColorType::Rgb => pixels.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 0xFF]).collect(),
+        png::ColorType::GrayscaleAlpha => pixels.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale | png::ColorType::Indexed => pixels.iter().flat_map(|&v| [v, v, v, 0xFF]).collect(),
+    };
+    Ok(egui::ColorImage::from_rgba_unmultiplied(size, &rgba))
+}
+
+fn format_ago(secs: u64) -> String {
+    match secs {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{} min ago", secs / 60),
+        3600..86_400 => format!("{} h ago", secs / 3600),
+        _ => format!("{} days ago", secs / 86_400),
+    }
+}
+
+// H:MM
+fn format_play_time(secs: u64) -> String { format!("{}:{:02}", secs / 3600, secs / 60 % 60) }
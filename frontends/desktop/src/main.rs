@@ -1,10 +1,68 @@
+mod accuracy;
+mod audio_channels;
+mod bios_calls;
+mod capture;
+mod cheats;
+mod config;
+mod crash;
+mod gamedb;
+mod headless;
+mod input;
+mod layers;
+mod library;
+mod netplay;
+mod perf;
+mod registers;
+mod rumble;
+mod saves;
+mod script;
+mod search;
+mod settings;
+mod soak;
+mod sync;
+mod touch;
+mod video;
+mod watches;
+
+use accuracy::GameOverrides;
+use capture::GifRecorder;
+use cheats::CheatWindow;
 use clap::Parser;
+use config::{config_dir, load_config, save_config, Config};
+use crash::{CrashAction, CrashReport};
 use eframe::egui;
 use egui::IconData;
-use serde::{Deserialize, Serialize};
-use std::fs;
-use std::io;
-use std::path::PathBuf;
+use gamedb::GameDb;
+use input::{Hotkey, InputHandler};
+use layers::LayerOverlay;
+use library::Library;
+use netplay::{NetplayCommand, NetplayWindow};
+use perf::PerfMonitor;
+use rumble::Rumble;
+use saves::GameSaves;
+use registers::RegisterView;
+use script::{ScriptWindow, Scripts};
+use search::SearchWindow;
+use settings::SettingsWindow;
+use sync::{precise_sleep, rate_adjust, AudioBuffer, FramePacer, GBA_FPS, OUTPUT_RATE, PRECISE_SLEEP};
+use touch::TouchControls;
+use video::VideoOutput;
+use watches::WatchView;
+use roba_core::bios::BiosKind;
+use roba_core::cart::patch::PATCH_EXTENSIONS;
+use roba_core::config::{AccuracyPreset, EmulatorConfig};
+use roba_core::cpu::{Trace, TraceEntry};
+use roba_core::error::CoreError;
+use roba_core::cart::{PeripheralInput, Quirks};
+use roba_core::guest_log::GUEST_LOG_TARGET;
+use roba_core::log_buffer::TargetFilter;
+use roba_core::movie::{Movie, MovieStatus};
+use roba_core::netplay::Session;
+use roba_core::sio::net::NetLink;
+use roba_core::sio::SerialDevice;
+use roba_core::state::RewindBuffer;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 
 #[derive(Parser, Debug)]
 #[command(version, about = "A Game Boy Advance emulator.", long_about = None)]
@@ -14,6 +72,110 @@ struct Args {
 
     #[arg(short, long, name = "BIOS_PATH")]
     bios: Option<PathBuf>,
+
+    /// Host a link cable session on ADDR (e.g. 0.0.0.0:5738) as player 1.
+    #[arg(long, value_name = "ADDR", conflicts_with = "link_connect")]
+    link_host: Option<String>,
+
+    /// Join a link cable session hosted at ADDR as player 2.
+    #[arg(long, value_name = "ADDR")]
+    link_connect: Option<String>,
+
+    /// Attach a Game Boy Player (boot detection and rumble in supported games).
+    #[arg(long, conflicts_with_all = ["link_host", "link_connect"])]
+    gb_player: bool,
+
+    /// Play an input movie once the ROM starts.
+    #[arg(long, value_name = "FILE")]
+    movie: Option<PathBuf>,
+
+    /// Play --movie without opening a window and print the final frame and state hashes.
+    #[arg(long, requires_all = ["ROM_PATH", "movie"])]
+    headless: bool,
+
+    /// With --headless, fail unless the final frame has this CRC32 (hex).
+    #[arg(long, value_name = "CRC", requires = "headless", value_parser = parse_hex)]
+    expect_crc: Option<u32>,
+
+    /// With --headless, fail unless the final state hash matches (hex).
+    #[arg(long, value_name = "HASH", requires = "headless", value_parser = parse_hex64)]
+    expect_hash: Option<u64>,
+
+    /// With --headless, write the audio of the whole movie to a WAV file.
+    #[arg(long, value_name = "FILE", requires = "headless")]
+    dump_wav: Option<PathBuf>,
+
+    /// With --headless, write every frame of the movie to a Y4M video file.
+    #[arg(long, value_name = "FILE", requires = "headless")]
+    dump_video: Option<PathBuf>,
+
+    /// With --headless, write which ARM and Thumb instruction formats ran,
+    /// with counts and example addresses, to a JSON file.
+    #[arg(long, value_name = "FILE", requires = "headless")]
+    coverage_report: Option<PathBuf>,
+
+    /// Run the ROM without a window at full speed for DURATION (e.g. 90s,
+    /// 30m, 4h), failing on a panic, a frozen picture or steady memory growth.
+    #[arg(long, value_name = "DURATION", requires = "ROM_PATH", conflicts_with = "headless")]
+    #[arg(value_parser = parse_duration)]
+    soak: Option<Duration>,
+
+    /// Log levels: a default level, then target=level pairs
+    /// (e.g. "info,core::cpu=trace").
+    #[arg(long, value_name = "FILTER", value_parser = parse_log_filter)]
+    log: Option<TargetFilter>,
+
+    /// Also write the log to FILE, moving it to FILE.1 past 16 MiB.
+    #[arg(long, value_name = "FILE")]
+    log_file: Option<PathBuf>,
+}
+
+// Size at which --log-file starts over.
+const LOG_FILE_MAX_BYTES: u64 = 16 << 20;
+
+fn parse_log_filter(s: &str) -> Result<TargetFilter, String> {
+    TargetFilter::parse(s).ok_or_else(|| format!("invalid log filter {:?}", s))
+}
+
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => return Err(format!("unknown unit {:?}; use s, m or h", unit)),
+    };
+    let number: u64 = number.parse().map_err(|e: std::num::ParseIntError| e.to_string())?;
+    Ok(Duration::from_secs(number * secs))
+}
+
+fn parse_hex(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s.trim_start_matches("0x"), 16).map_err(|e| e.to_string())
+}
+
+fn parse_hex64(s: &str) -> Result<u64, String> {
+    u64::from_str_radix(s.trim_start_matches("0x"), 16).map_err(|e| e.to_string())
+}
+
+fn open_link(args: &Args) -> Option<Box<dyn SerialDevice>> {
+    let result = if let Some(addr) = &args.link_host {
+        println!("Waiting for link cable peer on {}...", addr);
+        NetLink::host(addr.as_str())
+    } else if let Some(addr) = &args.link_connect {
+        NetLink::connect(addr.as_str())
+    } else {
+        return None;
+    };
+    match result {
+        Ok(link) => Some(Box::new(link)),
+        Err(e) => {
+            log::error!("Failed to open link cable: {}", e);
+            None
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -21,54 +183,24 @@ struct DisplayLogEntry {
     level: log::Level,
     target: String,
     message: String,
+    time: Duration,
+    frame: u64,
 }
 
-impl From<core::log_buffer::LogEntry> for DisplayLogEntry {
-    fn from(entry: core::log_buffer::LogEntry) -> Self {
+impl From<roba_core::log_buffer::LogEntry> for DisplayLogEntry {
+    fn from(entry: roba_core::log_buffer::LogEntry) -> Self {
         Self {
             level: entry.level,
             target: entry.target,
             message: entry.message,
+            time: entry.time,
+            frame: entry.frame,
         }
     }
 }
 
-// Configuration struct for serialization.
-#[derive(Serialize, Deserialize, Default)]
-struct Config {
-    recent_files: Vec<PathBuf>,
-    bios_path: Option<PathBuf>,
-}
-
-// Function to get the configuration directory.
-fn config_dir() -> Option<PathBuf> {
-    directories::ProjectDirs::from("com", "RoBA", "RoBA")
-        .map(|dirs| dirs.config_dir().to_path_buf())
-}
-
-// Function to load the configuration from a file.
-fn load_config() -> Config {
-    let Some(mut path) = config_dir() else {
-        return Config::default();
-    };
-    path.push("config.toml");
-    let Ok(config_str) = fs::read_to_string(&path) else {
-        return Config::default();
-    };
-    toml::from_str(&config_str).unwrap_or_default()
-}
-
-// Function to save the configuration to a file.
-fn save_config(config: &Config) -> io::Result<()> {
-    if let Some(mut path) = config_dir() {
-        fs::create_dir_all(&path)?;
-        path.push("config.toml");
-        let config_str =
-            toml::to_string(config).map_err(io::Error::other)?;
-        fs::write(&path, config_str)?;
-    }
-    Ok(())
-}
+// Frames between rewind snapshots.
+const REWIND_INTERVAL: u32 = 2;
 
 enum AppState {
     FileSelection,
@@ -77,15 +209,95 @@ enum AppState {
 
 struct GbaApp {
     state: AppState,
-    recent_files: Vec<PathBuf>,
-    bios_path: Option<PathBuf>,
-    bios_loaded: bool,
-    core: core::Emulator,
-    texture: Option<egui::TextureHandle>,
+    config: Config,
+    settings: SettingsWindow,
+    cheat_window: CheatWindow,
+    search_window: SearchWindow,
+    scripts: Scripts,
+    script_window: ScriptWindow,
+    netplay_window: NetplayWindow,
+    // Why emulation stopped, until the user continues or resets.
+    crash: Option<CrashReport>,
+    // Replaces the normal frame loop while connected.
+    netplay: Option<Session>,
+    input: InputHandler,
+    // Last gamepad plugged in or out, and when.
+    pad_notice: Option<(String, Instant)>,
+    recorder: Option<GifRecorder>,
+    // BIOS path edited in settings; reloaded with the next ROM.
+    bios_changed: bool,
+    core: roba_core::Emulator,
+    game_db: GameDb,
+    // Save directory of the running game.
+    saves: Option<GameSaves>,
+    // Accuracy settings of the running game, on top of the global ones.
+    overrides: GameOverrides,
+    sensors: SensorInputs,
+    rumble: Rumble,
+    rewind: RewindBuffer,
+    video: VideoOutput,
+    pacer: FramePacer,
+    audio_buffer: AudioBuffer,
+    perf: PerfMonitor,
+    touch: TouchControls,
+    // Whether the ROM in `AppState::Emulation` has been loaded yet.
+    rom_started: bool,
+    // Paused with nothing queued, so only the idle rate repaints.
+    idle: bool,
+    library: Library,
+    // ROM being played and when it started, for the library's play history.
+    play_session: Option<(PathBuf, SystemTime, Instant)>,
+    // Movie to play once the ROM has started (from --movie).
+    pending_movie: Option<PathBuf>,
+    // Patch picked with File > Apply Patch, used instead of the one next to
+    // the ROM until another ROM is opened.
+    patch: Option<PathBuf>,
+    // Where the movie being recorded is written when recording stops.
+    movie_path: Option<PathBuf>,
+    // Fullscreen state last sent to the window.
+    applied_fullscreen: Option<bool>,
     show_debug_panel: bool,
+    registers: RegisterView,
+    watches: WatchView,
+    layers: LayerOverlay,
     log_entries: Vec<DisplayLogEntry>,
     auto_scroll_logs: bool,
     log_filter: LogFilter,
+    // Per-target levels being edited, as `info,core::cpu=trace`.
+    log_targets: String,
+    log_targets_error: bool,
+    // Records the logger dropped because the viewer fell behind.
+    logs_dropped: u64,
+}
+
+// Slider positions for cartridge sensors, shown only for carts that have them.
+#[derive(Default)]
+struct SensorInputs {
+    solar_level: u8,
+    gyro_rate: i16,
+    tilt_x: i16,
+    tilt_y: i16,
+}
+
+impl SensorInputs {
+    fn show(&mut self, ui: &mut egui::Ui, quirks: Quirks) {
+        if quirks.contains(Quirks::SOLAR_SENSOR) {
+            ui.add(egui::Slider::new(&mut self.solar_level, 0..=255).text("Sunlight"));
+        }
+        if quirks.contains(Quirks::GYRO) {
+            ui.add(egui::Slider::new(&mut self.gyro_rate, i16::MIN..=i16::MAX).text("Rotation"));
+        }
+        if quirks.contains(Quirks::TILT) {
+            ui.add(egui::Slider::new(&mut self.tilt_x, i16::MIN..=i16::MAX).text("Tilt X"));
+            ui.add(egui::Slider::new(&mut self.tilt_y, i16::MIN..=i16::MAX).text("Tilt Y"));
+        }
+    }
+
+    fn apply(&self, core: &mut roba_core::Emulator) {
+        core.set_peripheral_input(PeripheralInput::SolarLevel(self.solar_level));
+        core.set_peripheral_input(PeripheralInput::Gyro(self.gyro_rate));
+        core.set_peripheral_input(PeripheralInput::Tilt { x: self.tilt_x, y: self.tilt_y });
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -99,55 +311,89 @@ enum LogFilter {
 }
 
 impl GbaApp {
-    fn new(rom_path: Option<PathBuf>, cli_bios_path: Option<PathBuf>) -> Self {
-        let config = load_config();
-        let mut core = core::Emulator::new();
+    fn new(
+        rom_path: Option<PathBuf>,
+        cli_bios_path: Option<PathBuf>,
+        link: Option<Box<dyn SerialDevice>>,
+        gb_player: bool,
+        movie: Option<PathBuf>,
+    ) -> Self {
+        let mut config = load_config();
+        let mut core = roba_core::Emulator::new();
+        if let Some(link) = link {
+            core.set_serial_device(link);
+        } else if gb_player {
+            core.attach_game_boy_player();
+        }
+        core.set_config(config.emulator_config());
+        let rumble = Rumble::attach(&mut core);
 
-        let bios_path = cli_bios_path
-            .or(config.bios_path.clone())
+        config.bios_path = cli_bios_path
+            .or(config.bios_path.take())
             .or_else(Self::find_default_bios);
 
-        let bios_loaded = if let Some(ref path) = bios_path {
-            match core.load_bios(path.as_path()) {
-                Ok(()) => true,
-                Err(e) => {
-                    log::warn!("Failed to load BIOS from {:?}: {}", path, e);
-                    false
-                }
+        if let Some(path) = config.bios_to_load() {
+            if let Err(e) = core.load_bios(path.as_path()) {
+                log::warn!("Failed to load BIOS from {:?}: {}", path, e);
             }
         } else {
             log::info!("No BIOS path specified, running without BIOS");
-            false
-        };
+        }
 
-        if let Some(path) = rom_path {
-            let mut recent_files = config.recent_files;
-            Self::add_to_recent(&mut recent_files, path.clone());
-            Self {
-                state: AppState::Emulation(path),
-                recent_files,
-                bios_path,
-                bios_loaded,
-                core,
-                texture: None,
-                show_debug_panel: cfg!(debug_assertions),
-                log_entries: Vec::new(),
-                auto_scroll_logs: true,
-                log_filter: LogFilter::All,
-            }
-        } else {
-            Self {
-                state: AppState::FileSelection,
-                recent_files: config.recent_files,
-                bios_path,
-                bios_loaded,
-                core,
-                texture: None,
-                show_debug_panel: cfg!(debug_assertions),
-                log_entries: Vec::new(),
-                auto_scroll_logs: true,
-                log_filter: LogFilter::All,
+        let state = match rom_path {
+            Some(path) => {
+                Self::add_to_recent(&mut config.recent_files, path.clone());
+                AppState::Emulation(path)
             }
+            None => AppState::FileSelection,
+        };
+        let rewind = RewindBuffer::with_duration(REWIND_INTERVAL, config.rewind_seconds);
+        let audio_buffer = AudioBuffer::attach(&mut core, OUTPUT_RATE, config.audio.latency_ms);
+        Self {
+            state,
+            config,
+            settings: SettingsWindow::default(),
+            cheat_window: CheatWindow::default(),
+            search_window: SearchWindow::default(),
+            scripts: Scripts::new(),
+            script_window: ScriptWindow::default(),
+            netplay_window: NetplayWindow::default(),
+            crash: None,
+            netplay: None,
+            input: InputHandler::new(),
+            pad_notice: None,
+            recorder: None,
+            bios_changed: false,
+            core,
+            game_db: GameDb::load(),
+            saves: None,
+            overrides: GameOverrides::default(),
+            sensors: SensorInputs::default(),
+            rumble,
+            rewind,
+            video: VideoOutput::new(),
+            pacer: FramePacer::default(),
+            audio_buffer,
+            perf: PerfMonitor::default(),
+            touch: TouchControls::default(),
+            rom_started: false,
+            idle: false,
+            library: Library::new(),
+            play_session: None,
+            pending_movie: movie,
+            patch: None,
+            movie_path: None,
+            applied_fullscreen: None,
+            show_debug_panel: cfg!(debug_assertions),
+            registers: RegisterView::default(),
+            watches: WatchView::default(),
+            layers: LayerOverlay::default(),
+            log_entries: Vec::new(),
+            auto_scroll_logs: true,
+            log_filter: LogFilter::All,
+            log_targets: roba_core::log_buffer::filter().to_string(),
+            log_targets_error: false,
+            logs_dropped: 0,
         }
     }
 
@@ -160,14 +406,14 @@ impl GbaApp {
             PathBuf::from("gba_bios.bin"),
         ];
 
-        if let Ok(exe_path) = std::env::current_exe() {
-            if let Some(exe_dir) = exe_path.parent() {
-                let exe_relative = exe_dir.join("gba_bios.bin");
-                log::debug!("Checking exe-relative: {:?}", exe_relative);
-                if exe_relative.exists() {
-                    log::info!("Found BIOS at {:?}", exe_relative);
-                    return Some(exe_relative);
-                }
+        if let Ok(exe_path) = std::env::current_exe()
+            && let Some(exe_dir) = exe_path.parent()
+        {
+            let exe_relative = exe_dir.join("gba_bios.bin");
+            log::debug!("Checking exe-relative: {:?}", exe_relative);
+            if exe_relative.exists() {
+                log::info!("Found BIOS at {:?}", exe_relative);
+                return Some(exe_relative);
             }
         }
 
@@ -204,19 +450,377 @@ impl GbaApp {
         recent.truncate(10);
     }
 
+    // Applies the current settings and (re)starts `rom_path` from power-on.
+    // On error the previous game stays loaded.
+    fn start_rom(&mut self, rom_path: &Path) -> Result<(), CoreError> {
+        self.core.set_config(self.emulator_config());
+        if self.bios_changed {
+            self.bios_changed = false;
+            if let Some(path) = self.config.bios_to_load()
+                && let Err(e) = self.core.load_bios(path)
+            {
+                log::warn!("Failed to load BIOS from {:?}: {}", path, e);
+            }
+        }
+        self.stop_movie();
+        self.flush_battery();
+        match &self.patch {
+            Some(patch) => self.core.load_rom_patched(rom_path, Some(patch))?,
+            None => self.core.load_rom(rom_path)?,
+        }
+        self.end_play_session();
+        self.play_session = Some((rom_path.to_path_buf(), SystemTime::now(), Instant::now()));
+        let root = self.config.save_dir.clone().or_else(saves::default_root).unwrap_or_default();
+        let mut saves = GameSaves::new(&root, &self.core);
+        self.overrides = GameOverrides::load(&saves.overrides_path());
+        self.core.set_config(self.emulator_config());
+        self.core.hard_reset();
+        if self.core.cpu().trace().is_none() {
+            self.core.cpu_mut().set_trace(Trace::default());
+        }
+        self.crash = None;
+        self.rewind.clear();
+        self.game_db.apply(&mut self.core);
+        saves.load_battery(&mut self.core);
+        cheats::load(&mut self.core, &saves.cheats_to_load());
+        self.saves = Some(saves);
+        self.search_window.reset();
+        self.watches.load_for_rom(rom_path);
+        if let Some(path) = self.pending_movie.take() {
+            self.play_movie(&path);
+        }
+        Ok(())
+    }
+
+    // The global settings with the running game's overrides applied.
+    fn emulator_config(&self) -> EmulatorConfig {
+        let mut config = self.config.emulator_config();
+        self.overrides.apply(&mut config);
+        config
+    }
+
+    // Gives the running game `preset`'s accuracy settings, or the global
+    // ones for `None`, and saves the choice with the game.
+    fn set_game_preset(&mut self, preset: Option<AccuracyPreset>) {
+        let Some(saves) = &self.saves else {
+            return;
+        };
+        self.overrides = preset.map(GameOverrides::from_preset).unwrap_or_default();
+        self.overrides.save(saves);
+        self.core.set_config(self.emulator_config());
+    }
+
+    // Adds the time spent in the running game to its play history.
+    fn end_play_session(&mut self) {
+        if let Some((path, started, since)) = self.play_session.take() {
+            self.library.add_session(&path, started, since.elapsed());
+        }
+    }
+
+    fn record_movie(&mut self, from_power_on: bool) {
+        let Some(path) = rfd::FileDialog::new()
+            .set_title("Record Movie")
+            .add_filter("RoBA movie", &["rbm"])
+            .save_file()
+        else {
+            return;
+        };
+        self.stop_movie();
+        self.core.record_movie(from_power_on);
+        self.rewind.clear();
+        log::info!("Recording movie to {:?}", path);
+        self.movie_path = Some(path);
+    }
+
+    fn play_movie(&mut self, path: &PathBuf) {
+        self.stop_movie();
+        let result = Movie::load(path).and_then(|movie| self.core.play_movie(movie));
+        match result {
+            Ok(()) => {
+                self.rewind.clear();
+                log::info!("Playing movie {:?}", path);
+            }
+            Err(e) => log::error!("Failed to play movie {:?}: {}", path, e),
+        }
+    }
+
+    // Ends playback or recording, writing out a recorded movie.
+    fn stop_movie(&mut self) {
+        let movie = self.core.stop_movie();
+        if let (Some(movie), Some(path)) = (movie, self.movie_path.take()) {
+            match movie.save(&path) {
+                Ok(()) => log::info!("Movie saved to {:?} ({} frames)", path, movie.frames.len()),
+                Err(e) => log::error!("Failed to save movie {:?}: {}", path, e),
+            }
+        }
+    }
+
+    fn handle_hotkey(&mut self, hotkey: Hotkey) {
+        match hotkey {
+            Hotkey::Screenshot => self.take_screenshot(),
+            Hotkey::Fullscreen => self.config.video.fullscreen ^= true,
+            Hotkey::RecordClip => self.toggle_recording(),
+            Hotkey::SaveState => self.save_state(),
+            Hotkey::LoadState => self.load_state(),
+            Hotkey::Pause => self.toggle_pause(),
+            Hotkey::FrameAdvance => self.advance_frames(1),
+            Hotkey::StepFrames => self.advance_frames(self.config.frame_step),
+            Hotkey::SoftReset => self.reset(false),
+            Hotkey::HardReset => self.reset(true),
+            // Held; handled by the frame loop.
+            Hotkey::FastForward | Hotkey::Rewind => {}
+        }
+    }
+
+    // Restarts the game so both players begin from the same state, with the
+    // RTC on emulated time.
+    fn start_netplay(&mut self, transport: Box<dyn roba_core::netplay::Transport>, config: roba_core::netplay::NetplayConfig) {
+        self.stop_movie();
+        let mut emulator_config = self.emulator_config();
+        emulator_config.deterministic = true;
+        self.core.set_config(emulator_config);
+        self.core.set_paused(false);
+        self.core.hard_reset();
+        self.rewind.clear();
+        self.netplay = Some(Session::new(transport, config, &self.core));
+    }
+
+    fn stop_netplay(&mut self) {
+        if self.netplay.take().is_some() {
+            log::info!("Netplay: disconnected");
+            self.core.set_config(self.emulator_config());
+        }
+    }
+
+    // Restarts the running game; a hard reset also clears memory. Either one
+    // would desync a movie or a netplay peer, so movies stop and netplay
+    // refuses.
+    fn reset(&mut self, hard: bool) {
+        if !self.rom_started {
+            return;
+        }
+        if self.netplay.is_some() {
+            log::warn!("Reset is not available during netplay");
+            return;
+        }
+        self.stop_movie();
+        self.rewind.clear();
+        if hard {
+            self.core.hard_reset();
+        } else {
+            self.core.soft_reset();
+        }
+    }
+
+    // Pauses the game and writes a crash report, shown until the user
+    // continues or resets.
+    fn stop_with_crash(&mut self, reason: String, instruction: Option<TraceEntry>) {
+        self.core.set_paused(true);
+        self.crash = Some(CrashReport::capture(&mut self.core, reason, instruction));
+    }
+
+    fn toggle_pause(&mut self) {
+        let paused = !self.core.is_paused();
+        self.core.set_paused(paused);
+        log::info!("{} at frame {}", if paused { "Paused" } else { "Resumed" }, self.core.frame_count());
+    }
+
+    // Pauses first when running, so the hotkey also stops the game.
+    fn advance_frames(&mut self, frames: u32) {
+        if !self.core.is_paused() {
+            self.core.set_paused(true);
+        }
+        self.core.advance_frames(frames);
+    }
+
+    fn flush_battery(&mut self) {
+        if let Some(saves) = &mut self.saves {
+            saves.flush(&mut self.core);
+        }
+    }
+
+    fn import_sav(&mut self) {
+        let Some(saves) = &mut self.saves else {
+            return;
+        };
+        let Some(path) = rfd::FileDialog::new()
+            .set_title("Import Battery Save")
+            .add_filter("Battery save", &["sav"])
+            .pick_file()
+        else {
+            return;
+        };
+        match saves.import_sav(&mut self.core, &path) {
+            Ok(()) => {
+                log::info!("Imported battery save {:?}; restarting", path);
+                self.rom_started = false;
+            }
+            Err(e) => {
+                log::error!("Failed to import battery save {:?}: {}", path, e);
+                show_error("Failed to import battery save", &e.to_string());
+            }
+        }
+    }
+
+    fn export_sav(&mut self) {
+        let Some(saves) = &self.saves else {
+            return;
+        };
+        let name = match &self.state {
+            AppState::Emulation(rom) => rom.with_extension("sav").file_name().map(|n| n.to_string_lossy().into_owned()),
+            AppState::FileSelection => None,
+        };
+        let Some(path) = rfd::FileDialog::new()
+            .set_title("Export Battery Save")
+            .add_filter("Battery save", &["sav"])
+            .set_file_name(name.as_deref().unwrap_or("battery.sav"))
+            .save_file()
+        else {
+            return;
+        };
+        match saves.export_sav(&self.core, &path) {
+            Ok(()) => log::info!("Battery save exported to {:?}", path),
+            Err(e) => log::error!("Failed to export battery save {:?}: {}", path, e),
+        }
+    }
+
+    // Single state slot, in the game's save directory.
+    fn save_state(&mut self) {
+        let Some(saves) = &self.saves else {
+            return;
+        };
+        let path = saves.state_path();
+        match saves.write(&path, &self.core.save_state()) {
+            Ok(()) => log::info!("State saved to {:?}", path),
+            Err(e) => log::error!("Failed to save state {:?}: {}", path, e),
+        }
+    }
+
+    fn load_state(&mut self) {
+        if self.netplay.is_some() {
+            log::warn!("States cannot be loaded during netplay");
+            return;
+        }
+        let Some(path) = self.saves.as_ref().map(GameSaves::state_to_load) else {
+            return;
+        };
+        let result = std::fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|data| self.core.load_state(&data).map_err(|e| e.to_string()));
+        match result {
+            Ok(()) => {
+                self.rewind.clear();
+                log::info!("State loaded from {:?}", path);
+            }
+            Err(e) => log::error!("Failed to load state {:?}: {}", path, e),
+        }
+    }
+
+    fn capture_path(&self, ext: &str) -> Option<PathBuf> {
+        let dir = self
+            .config
+            .capture_dir
+            .clone()
+            .or_else(|| self.saves.as_ref().map(GameSaves::screenshot_dir))
+            .or_else(capture::default_dir)?;
+        let stem = match &self.state {
+            AppState::Emulation(rom) => rom.file_stem().map(|s| s.to_string_lossy().into_owned()),
+            AppState::FileSelection => None,
+        };
+        capture::output_path(&dir, stem.as_deref().unwrap_or("roba"), ext)
+            .map_err(|e| log::error!("Cannot create capture directory {:?}: {}", dir, e))
+            .ok()
+    }
+
+    fn take_screenshot(&mut self) {
+        let Some(path) = self.capture_path("png") else {
+            return;
+        };
+        match capture::save_png(&path, &self.core.screenshot()) {
+            Ok(()) => log::info!("Screenshot saved to {:?}", path),
+            Err(e) => log::error!("Failed to save screenshot {:?}: {}", path, e),
+        }
+    }
+
+    fn toggle_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            match recorder.finish() {
+                Ok(path) => log::info!("GIF saved to {:?}", path),
+                Err(e) => log::error!("Failed to finish GIF: {}", e),
+            }
+            return;
+        }
+        let Some(path) = self.capture_path("gif") else {
+            return;
+        };
+        let (w, h) = (roba_core::video::GBA_SCREEN_W, roba_core::video::GBA_SCREEN_H);
+        match GifRecorder::start(path, w, h) {
+            Ok(recorder) => {
+                log::info!("Recording GIF to {:?}", recorder.path());
+                self.recorder = Some(recorder);
+            }
+            Err(e) => log::error!("Failed to start GIF recording: {}", e),
+        }
+    }
+
+    fn record_frame(&mut self) {
+        let Some(recorder) = &mut self.recorder else {
+            return;
+        };
+        match recorder.push(&self.core.screenshot()) {
+            Ok(true) => {}
+            Ok(false) => self.toggle_recording(),
+            Err(e) => {
+                log::error!("GIF recording failed: {}", e);
+                self.recorder = None;
+            }
+        }
+    }
+
     fn open_rom(&mut self) {
         if let Some(path) = rfd::FileDialog::new()
             .set_title("Open GBA ROM")
-            .add_filter("Game Boy Advance ROM", &["gba"])
+            .add_filter("Game Boy Advance ROM", &library::ROM_EXTENSIONS)
+            .pick_file()
+        {
+            self.select_rom(path);
+        }
+    }
+
+    // Restarts the running ROM with a patch picked by the user.
+    fn apply_patch(&mut self) {
+        if let Some(path) = rfd::FileDialog::new()
+            .set_title("Apply Patch")
+            .add_filter("ROM patch", &PATCH_EXTENSIONS)
             .pick_file()
         {
-            Self::add_to_recent(&mut self.recent_files, path.clone());
-            self.state = AppState::Emulation(path);
+            self.patch = Some(path);
+            self.rom_started = false;
+        }
+    }
+
+    // Switches to `path`; it starts on the next frame.
+    fn select_rom(&mut self, path: PathBuf) {
+        Self::add_to_recent(&mut self.config.recent_files, path.clone());
+        self.patch = None;
+        self.state = AppState::Emulation(path);
+        self.rom_started = false;
+    }
+
+    // Loads the first ROM dropped onto the window.
+    fn handle_dropped_files(&mut self, ctx: &egui::Context) {
+        let dropped = ctx.input(|i| {
+            i.raw.dropped_files.iter().filter_map(|f| f.path.clone()).find(|p| library::is_rom_file(p))
+        });
+        if let Some(path) = dropped {
+            log::info!("Opening dropped ROM {:?}", path);
+            self.select_rom(path);
         }
     }
 
     fn poll_logs(&mut self) {
-        let new_logs = core::log_buffer::drain_logs();
+        self.logs_dropped += roba_core::log_buffer::take_dropped();
+        let new_logs = roba_core::log_buffer::drain_logs();
         for entry in new_logs {
             self.log_entries.push(entry.into());
         }
@@ -227,6 +831,20 @@ impl GbaApp {
         }
     }
 
+    // Picks up pads plugged in or out, whatever is on screen, and keeps the
+    // latest notice in the status bar for a few seconds.
+    fn poll_gamepads(&mut self, ctx: &egui::Context) {
+        const NOTICE_TIME: Duration = Duration::from_secs(4);
+        self.input.pads.poll();
+        if let Some(notice) = self.input.pads.take_notices().pop() {
+            self.pad_notice = Some((notice, Instant::now()));
+            ctx.request_repaint_after(NOTICE_TIME);
+        }
+        if self.pad_notice.as_ref().is_some_and(|(_, at)| at.elapsed() >= NOTICE_TIME) {
+            self.pad_notice = None;
+        }
+    }
+
     fn level_color(level: log::Level) -> egui::Color32 {
         match level {
             log::Level::Error => egui::Color32::from_rgb(255, 100, 100),
@@ -252,6 +870,8 @@ impl GbaApp {
 impl eframe::App for GbaApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         self.poll_logs();
+        self.handle_dropped_files(ctx);
+        self.poll_gamepads(ctx);
 
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             egui::menu::bar(ui, |ui| {
@@ -260,12 +880,162 @@ impl eframe::App for GbaApp {
                         self.open_rom();
                         ui.close_menu();
                     }
+                    let running = self.rom_started;
+                    if ui.add_enabled(running, egui::Button::new("Apply Patch...")).clicked() {
+                        self.apply_patch();
+                        ui.close_menu();
+                    }
+                    if ui.add_enabled(running, egui::Button::new("Save State")).clicked() {
+                        self.save_state();
+                        ui.close_menu();
+                    }
+                    if ui.add_enabled(running, egui::Button::new("Load State")).clicked() {
+                        self.load_state();
+                        ui.close_menu();
+                    }
+                    ui.menu_button("Battery Save", |ui| {
+                        if ui.add_enabled(running, egui::Button::new("Import .sav...")).clicked() {
+                            self.import_sav();
+                            ui.close_menu();
+                        }
+                        if ui.add_enabled(running, egui::Button::new("Export .sav...")).clicked() {
+                            self.export_sav();
+                            ui.close_menu();
+                        }
+                    });
+                    ui.menu_button("Movie", |ui| {
+                        let status = self.core.movie_status();
+                        let idle = running && status == MovieStatus::Idle;
+                        if ui.add_enabled(idle, egui::Button::new("Record from Power-On...")).clicked() {
+                            self.record_movie(true);
+                            ui.close_menu();
+                        }
+                        if ui.add_enabled(idle, egui::Button::new("Record from Here...")).clicked() {
+                            self.record_movie(false);
+                            ui.close_menu();
+                        }
+                        if ui.add_enabled(idle, egui::Button::new("Play...")).clicked() {
+                            if let Some(path) = rfd::FileDialog::new()
+                                .set_title("Play Movie")
+                                .add_filter("RoBA movie", &["rbm"])
+                                .pick_file()
+                            {
+                                self.play_movie(&path);
+                            }
+                            ui.close_menu();
+                        }
+                        if ui.add_enabled(status != MovieStatus::Idle, egui::Button::new("Stop")).clicked() {
+                            self.stop_movie();
+                            ui.close_menu();
+                        }
+                    });
                     if ui.button("Quit").clicked() {
                         ctx.send_viewport_cmd(egui::ViewportCommand::Close);
                     }
                 });
+                ui.menu_button("Emulation", |ui| {
+                    let running = self.rom_started;
+                    let label = if self.core.is_paused() { "Resume" } else { "Pause" };
+                    if ui.add_enabled(running, egui::Button::new(label)).clicked() {
+                        self.toggle_pause();
+                        ui.close_menu();
+                    }
+                    if ui.add_enabled(running, egui::Button::new("Frame Advance")).clicked() {
+                        self.advance_frames(1);
+                        ui.close_menu();
+                    }
+                    let step = format!("Step {} Frames", self.config.frame_step);
+                    if ui.add_enabled(running, egui::Button::new(step)).clicked() {
+                        self.advance_frames(self.config.frame_step);
+                        ui.close_menu();
+                    }
+                    ui.separator();
+                    let resettable = running && self.netplay.is_none();
+                    if ui.add_enabled(resettable, egui::Button::new("Soft Reset")).clicked() {
+                        self.reset(false);
+                        ui.close_menu();
+                    }
+                    if ui.add_enabled(resettable, egui::Button::new("Hard Reset")).clicked() {
+                        self.reset(true);
+                        ui.close_menu();
+                    }
+                    if ui
+                        .add_enabled(resettable, egui::Button::new("Reload ROM"))
+                        .on_hover_text("Reads the ROM file again and restarts it with the current settings.")
+                        .clicked()
+                    {
+                        self.rom_started = false;
+                        ui.close_menu();
+                    }
+                    ui.separator();
+                    ui.menu_button("Accuracy", |ui| {
+                        ui.label("All games");
+                        let global = self.config.preset();
+                        for preset in AccuracyPreset::ALL {
+                            if ui.radio(global == Some(preset), format!("{:?}", preset)).clicked() {
+                                self.config.set_preset(preset);
+                                self.core.set_config(self.emulator_config());
+                                ui.close_menu();
+                            }
+                        }
+                        ui.separator();
+                        ui.label("This game");
+                        let game = (!self.overrides.is_empty()).then(|| self.emulator_config().preset());
+                        ui.add_enabled_ui(self.saves.is_some(), |ui| {
+                            if ui.radio(game.is_none(), "Same as all games").clicked() {
+                                self.set_game_preset(None);
+                                ui.close_menu();
+                            }
+                            for preset in AccuracyPreset::ALL {
+                                if ui.radio(game == Some(Some(preset)), format!("{:?}", preset)).clicked() {
+                                    self.set_game_preset(Some(preset));
+                                    ui.close_menu();
+                                }
+                            }
+                        });
+                    });
+                });
+                ui.menu_button("Capture", |ui| {
+                    if ui.button("Screenshot").clicked() {
+                        self.take_screenshot();
+                        ui.close_menu();
+                    }
+                    let label = if self.recorder.is_some() { "Stop GIF recording" } else { "Record GIF" };
+                    if ui.button(label).clicked() {
+                        self.toggle_recording();
+                        ui.close_menu();
+                    }
+                });
                 ui.menu_button("Window", |ui| {
-                    let _ = ui.button("Settings");
+                    if ui.button("Settings").clicked() {
+                        self.settings.open = true;
+                        ui.close_menu();
+                    }
+                    if ui.add_enabled(self.rom_started, egui::Button::new("Cheats")).clicked() {
+                        self.cheat_window.open = true;
+                        ui.close_menu();
+                    }
+                    if ui.add_enabled(self.rom_started, egui::Button::new("Cheat Search")).clicked() {
+                        self.search_window.open = true;
+                        ui.close_menu();
+                    }
+                    if ui.add_enabled(self.rom_started, egui::Button::new("Scripts")).clicked() {
+                        self.script_window.open = true;
+                        ui.close_menu();
+                    }
+                    if ui.add_enabled(self.rom_started, egui::Button::new("Netplay")).clicked() {
+                        self.netplay_window.open = true;
+                        ui.close_menu();
+                    }
+                    if ui.checkbox(&mut self.config.video.fullscreen, "Fullscreen").clicked() {
+                        ui.close_menu();
+                    }
+                    if ui.checkbox(&mut self.config.video.perf_overlay, "Performance Overlay").clicked() {
+                        ui.close_menu();
+                    }
+                    if ui.checkbox(&mut self.config.touch.enabled, "Touch Controls").clicked() {
+                        ui.close_menu();
+                    }
                     if ui.checkbox(&mut self.show_debug_panel, "Debug Panel").clicked() {
                         ui.close_menu();
                     }
@@ -273,6 +1043,41 @@ impl eframe::App for GbaApp {
             });
         });
 
+        let bios_paths = (self.config.bios_path.clone(), self.config.replacement_bios.clone());
+        if (self.cheat_window.show(ctx, &mut self.core) | self.search_window.show(ctx, &mut self.core))
+            && let Some(saves) = &self.saves
+        {
+            cheats::save(&self.core, saves);
+        }
+        self.script_window.show(ctx, &mut self.scripts, &mut self.core);
+        if let Some(report) = &self.crash
+            && let Some(action) = crash::show(ctx, report)
+        {
+            self.crash = None;
+            if let CrashAction::Reset = action {
+                self.reset(true);
+            }
+            self.core.set_paused(false);
+        }
+        match self.netplay_window.show(ctx, self.netplay.as_ref()) {
+            Some(NetplayCommand::Start(transport, config)) => self.start_netplay(transport, config),
+            Some(NetplayCommand::Stop) => self.stop_netplay(),
+            None => {}
+        }
+
+        let rewind_seconds = self.config.rewind_seconds;
+        let latency_ms = self.config.audio.latency_ms;
+        if self.settings.show(ctx, &mut self.config, &mut self.input) {
+            self.core.set_config(self.emulator_config());
+            self.bios_changed |= (&self.config.bios_path, &self.config.replacement_bios) != (&bios_paths.0, &bios_paths.1);
+            if self.config.rewind_seconds != rewind_seconds {
+                self.rewind = RewindBuffer::with_duration(REWIND_INTERVAL, self.config.rewind_seconds);
+            }
+            if self.config.audio.latency_ms != latency_ms {
+                self.audio_buffer = AudioBuffer::attach(&mut self.core, OUTPUT_RATE, self.config.audio.latency_ms);
+            }
+        }
+
         if self.show_debug_panel {
             egui::SidePanel::right("debug_panel")
                 .resizable(true)
@@ -280,6 +1085,40 @@ impl eframe::App for GbaApp {
                 .default_width(350.0)
                 .max_width(500.0)
                 .show(ctx, |ui| {
+                    ui.heading("Performance");
+                    ui.separator();
+                    let report = self.core.last_frame_report();
+                    let fill = self.audio_buffer.fill();
+                    ui.label(self.config.sync_mode.label());
+                    ui.label(format!("Speed: {:.0}%", report.speed() * 100.0));
+                    ui.label(format!(
+                        "Audio buffer: {:.0}% (rate {:+.2}%)",
+                        fill * 100.0,
+                        (rate_adjust(fill, self.config.audio.rate_control) - 1.0) * 100.0
+                    ));
+                    ui.separator();
+
+                    egui::CollapsingHeader::new("I/O Registers").show(ui, |ui| {
+                        egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| self.registers.show(ui));
+                    });
+                    egui::CollapsingHeader::new("BIOS Calls").show(ui, |ui| {
+                        egui::ScrollArea::vertical().id_source("bios_calls").max_height(300.0).show(ui, |ui| {
+                            if bios_calls::show(ui, self.core.cpu_mut().swi_stats_mut(), &mut self.config.swi_log) {
+                                self.core.set_config(self.emulator_config());
+                            }
+                        });
+                    });
+                    egui::CollapsingHeader::new("Symbols & Watches").show(ui, |ui| {
+                        egui::ScrollArea::vertical()
+                            .id_source("watches")
+                            .max_height(300.0)
+                            .show(ui, |ui| self.watches.show(ui, &mut self.core));
+                    });
+                    egui::CollapsingHeader::new("PPU Layers").show(ui, |ui| self.layers.show(ui));
+                    egui::CollapsingHeader::new("Audio Channels")
+                        .show(ui, |ui| audio_channels::show(ui, self.core.apu_mut().channels_mut()));
+                    ui.separator();
+
                     ui.heading("Debug Log");
                     ui.separator();
 
@@ -293,10 +1132,34 @@ impl eframe::App for GbaApp {
                         ui.selectable_value(&mut self.log_filter, LogFilter::Trace, "Trace");
                     });
 
+                    ui.horizontal(|ui| {
+                        ui.label("Levels:");
+                        let edit = ui
+                            .text_edit_singleline(&mut self.log_targets)
+                            .on_hover_text("Default level, then target=level pairs, e.g. info,core::cpu=trace");
+                        let apply = ui.button("Apply").clicked();
+                        if apply || (edit.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter))) {
+                            match TargetFilter::parse(&self.log_targets) {
+                                Some(filter) => {
+                                    roba_core::log_buffer::set_filter(filter);
+                                    self.log_targets_error = false;
+                                }
+                                None => self.log_targets_error = true,
+                            }
+                        }
+                        if self.log_targets_error {
+                            ui.colored_label(egui::Color32::from_rgb(255, 100, 100), "Invalid");
+                        }
+                    });
+
                     ui.horizontal(|ui| {
                         ui.checkbox(&mut self.auto_scroll_logs, "Auto-scroll");
                         if ui.button("Clear").clicked() {
                             self.log_entries.clear();
+                            self.logs_dropped = 0;
+                        }
+                        if self.logs_dropped > 0 {
+                            ui.label(format!("{} dropped", self.logs_dropped));
                         }
                     });
                     ui.separator();
@@ -318,8 +1181,19 @@ impl eframe::App for GbaApp {
                                     let color = Self::level_color(entry.level);
                                     let short_target = entry.target.split("::").last().unwrap_or(&entry.target);
                                     ui.horizontal(|ui| {
+                                        ui.weak(format!(
+                                            "{:>4}.{:03} #{:<6}",
+                                            entry.time.as_secs(),
+                                            entry.time.subsec_millis(),
+                                            entry.frame
+                                        ));
                                         ui.colored_label(color, format!("[{:5}]", entry.level));
-                                        ui.colored_label(egui::Color32::GRAY, format!("{:>8}", short_target));
+                                        let target_color = if entry.target == GUEST_LOG_TARGET {
+                                            egui::Color32::from_rgb(120, 200, 255)
+                                        } else {
+                                            egui::Color32::GRAY
+                                        };
+                                        ui.colored_label(target_color, format!("{:>8}", short_target));
                                         ui.label(&entry.message);
                                     });
                                 }
@@ -328,84 +1202,305 @@ impl eframe::App for GbaApp {
                 });
         }
 
+        if matches!(self.state, AppState::Emulation(_)) {
+            egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    if self.core.is_paused() {
+                        ui.strong("Paused");
+                        ui.separator();
+                    }
+                    ui.label(format!("Frame {}", self.core.frame_count()));
+                    ui.separator();
+                    ui.label(format_emulated_time(self.core.emulated_time()));
+                    if let Some(problem) = self.core.header_problem() {
+                        ui.separator();
+                        ui.colored_label(ui.visuals().warn_fg_color, "Invalid ROM header").on_hover_text(format!(
+                            "The BIOS will not start this ROM: {}. Enable \"Fix invalid cartridge headers\" or \
+                             \"Skip BIOS intro\" in the settings and reload it.",
+                            problem
+                        ));
+                    }
+                    if let Some(session) = &self.netplay {
+                        ui.separator();
+                        ui.label(if session.is_ready() { "Netplay" } else { "Netplay: waiting for peer" });
+                    }
+                    if let Some((notice, _)) = &self.pad_notice {
+                        ui.separator();
+                        ui.label(notice);
+                    }
+                });
+            });
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
             match &self.state {
                 AppState::FileSelection => {
-                    ui.heading("Recently Opened GBA ROMs");
-                    ui.separator();
-
-                    if self.recent_files.is_empty() {
-                        ui.label(
-                            "No recent files found. Use 'File -> Open ROM...' to get started.",
-                        );
-                    } else {
-                        egui::ScrollArea::vertical().show(ui, |ui| {
-                            for file in &self.recent_files {
-                                if ui.button(file.display().to_string()).clicked() {
-                                    self.state = AppState::Emulation(file.clone());
-                                }
-                            }
-                        });
+                    if let Some(path) = self.library.show(ui, &mut self.config) {
+                        self.select_rom(path);
                     }
                 }
                 AppState::Emulation(rom_path) => {
-                    ui.heading("Emulating GBA ROM");
-                    ui.label(format!("Now emulating: {}", rom_path.display()));
-                    ui.separator();
+                    if !self.config.video.fullscreen {
+                        ui.heading("Emulating GBA ROM");
+                        ui.label(format!("Now emulating: {}", rom_path.display()));
+                        ui.label(match self.core.bios_kind() {
+                            Some(BiosKind::Official) => "BIOS: official",
+                            Some(BiosKind::Other) => "BIOS: replacement or unverified dump",
+                            None => "BIOS: none (emulated calls)",
+                        });
+                        if let Some(patch) = self.core.rom_patch() {
+                            ui.label(format!("Patch: {}", patch.display()));
+                        }
+                        if let Some(saves) = &self.saves {
+                            ui.label(format!("Saves: {}", saves.dir().display()));
+                        }
+                        ui.separator();
+                    }
 
-                    if self.texture.is_none() {
-                        self.core.load_rom(rom_path);
+                    if !self.rom_started {
+                        let rom_path = rom_path.clone();
+                        if let Err(e) = self.start_rom(&rom_path) {
+                            log::error!("Failed to load ROM {:?}: {}", rom_path, e);
+                            show_error("Failed to load ROM", &format!("{}\n\n{}", rom_path.display(), e));
+                            self.state = AppState::FileSelection;
+                            return;
+                        }
+                        self.rom_started = true;
                     }
 
-                    self.core.run_frame();
+                    let mut input = self.input.poll(ctx, &self.config.input, &self.config.gamepad);
+                    if self.config.touch.enabled {
+                        input.keys = input.keys | self.touch.poll(ctx);
+                    }
+                    for hotkey in input.triggered {
+                        self.handle_hotkey(hotkey);
+                    }
+                    // A playing movie supplies its own input.
+                    let playing = matches!(self.core.movie_status(), MovieStatus::Playing { .. });
+                    self.sensors.apply(&mut self.core);
+                    if input.rewind && self.netplay.is_none() {
+                        if let Some(Err(e)) = self.rewind.pop(&mut self.core) {
+                            log::error!("Rewind failed: {}", e);
+                            self.rewind.clear();
+                        }
+                    } else {
+                        let now = Instant::now();
+                        self.audio_buffer.drain(now);
+                        let frames = if self.netplay.is_some() {
+                            self.pacer.frames_due(self.config.sync_mode, now, &self.audio_buffer)
+                        } else if self.core.is_paused() {
+                            self.core.queued_frames() as usize
+                        } else if input.fast_forward {
+                            self.config.fast_forward_speed.max(1) as usize
+                        } else {
+                            self.pacer.frames_due(self.config.sync_mode, now, &self.audio_buffer)
+                        };
+                        // Only the last frame of the batch reaches the screen.
+                        let skip = input.fast_forward
+                            && self.config.fast_forward_frame_skip
+                            && self.netplay.is_none()
+                            && self.recorder.is_none();
+                        for i in 0..frames {
+                            self.core.set_skip_render(skip && i + 1 < frames);
+                            if let Some(session) = &mut self.netplay {
+                                match crash::guard(|| session.advance(&mut self.core, input.keys)) {
+                                    Ok(Ok(true)) => self.perf.on_frame(self.core.last_frame_report(), Instant::now()),
+                                    Ok(Ok(false)) => break,
+                                    Ok(Err(e)) => {
+                                        log::error!("Netplay: {}", e);
+                                        self.stop_netplay();
+                                        break;
+                                    }
+                                    Err(reason) => {
+                                        self.stop_netplay();
+                                        self.stop_with_crash(reason, None);
+                                        break;
+                                    }
+                                }
+                            } else {
+                                let keys = self.scripts.frame_start(&mut self.core, input.keys);
+                                if !playing {
+                                    self.core.set_keys(keys);
+                                }
+                                match crash::guard(|| self.core.step_frame()) {
+                                    Ok(Some(report)) => self.perf.on_frame(&report, Instant::now()),
+                                    Ok(None) => {}
+                                    Err(reason) => {
+                                        self.stop_with_crash(reason, None);
+                                        break;
+                                    }
+                                }
+                                self.scripts.frame_end(&mut self.core);
+                            }
+                            // Takes effect from the next frame.
+                            let ratio = rate_adjust(self.audio_buffer.fill(), self.config.audio.rate_control);
+                            self.audio_buffer.set_ratio(&mut self.core, ratio);
+                            self.record_frame();
+                            if self.show_debug_panel {
+                                self.registers.update(&self.core);
+                                self.watches.update(&mut self.core);
+                            }
+                            if self.config.rewind_seconds > 0 && self.netplay.is_none() {
+                                self.rewind.on_frame(&mut self.core);
+                            }
+                            if let Some(instruction) = self.core.cpu_mut().take_undefined() {
+                                let reason = format!("Undefined instruction at {:08X}", instruction.pc);
+                                self.stop_netplay();
+                                self.stop_with_crash(reason, Some(instruction));
+                                break;
+                            }
+                        }
+                    }
+                    self.idle = self.core.is_paused()
+                        && self.core.queued_frames() == 0
+                        && !input.rewind
+                        && self.netplay.is_none();
+                    self.rumble.update(&mut self.input.pads);
+                    if let Some(saves) = &mut self.saves {
+                        saves.update(&mut self.core, Instant::now());
+                    }
 
-                    let rgba = self.core.framebuffer_rgba();
-                    let size = [core::video::GBA_SCREEN_W, core::video::GBA_SCREEN_H];
-                    let image = egui::ColorImage::from_rgba_unmultiplied(size, rgba);
-                    let tex = self.texture.get_or_insert_with(|| {
-                        ui.ctx().load_texture(
-                            "framebuffer",
-                            image.clone(),
-                            egui::TextureOptions::NEAREST,
-                        )
-                    });
-                    tex.set(image, egui::TextureOptions::NEAREST);
-
-                    let scale = 2.0;
-                    let desired = egui::Vec2::new(
-                        core::video::GBA_SCREEN_W as f32 * scale,
-                        core::video::GBA_SCREEN_H as f32 * scale,
-                    );
-                    ui.image((tex.id(), desired));
+                    let present_start = Instant::now();
+                    self.video.upload(ctx, self.core.framebuffer_rgba(), &self.config.video);
+                    self.layers.update(ctx, &mut self.core);
+                    self.sensors.show(ui, self.core.cart_config().quirks);
+                    if let Some(rect) = self.video.show(ui, &self.config.video) {
+                        self.layers.paint(ui, rect, &self.core);
+                        self.scripts.paint(ui, rect);
+                        if self.config.touch.enabled {
+                            self.touch.paint(ui, rect, self.config.touch.opacity);
+                        }
+                        if self.config.video.perf_overlay {
+                            let target = if input.fast_forward {
+                                GBA_FPS * self.config.fast_forward_speed.max(1) as f64
+                            } else {
+                                GBA_FPS
+                            };
+                            self.perf.paint(ui, rect, target);
+                        }
+                    }
+                    self.perf.set_present_time(present_start.elapsed());
                 }
             }
         });
 
-        ctx.request_repaint();
+        let fullscreen = self.config.video.fullscreen;
+        if self.applied_fullscreen != Some(fullscreen) {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Fullscreen(fullscreen));
+            self.applied_fullscreen = Some(fullscreen);
+        }
+
+        // Nothing changes on screen while idle except through input, which
+        // repaints by itself; the idle rate catches gamepads and logs.
+        let idle = match self.state {
+            AppState::Emulation(_) => self.idle,
+            AppState::FileSelection => true,
+        };
+        if idle {
+            if self.config.idle_fps > 0 {
+                ctx.request_repaint_after(Duration::from_secs_f64(1.0 / self.config.idle_fps as f64));
+            }
+        } else {
+            match self.pacer.next_repaint(self.config.sync_mode, &self.audio_buffer) {
+                Some(delay) if delay < PRECISE_SLEEP => {
+                    precise_sleep(delay);
+                    ctx.request_repaint();
+                }
+                Some(delay) => ctx.request_repaint_after(delay),
+                None => ctx.request_repaint(),
+            }
+        }
     }
 
     fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
-        let config = Config {
-            recent_files: self.recent_files.clone(),
-            bios_path: self.bios_path.clone(),
-        };
-        if let Err(e) = save_config(&config) {
+        self.stop_movie();
+        self.flush_battery();
+        self.end_play_session();
+        if self.recorder.is_some() {
+            self.toggle_recording();
+        }
+        if let Err(e) = save_config(&self.config) {
             eprintln!("Failed to save config: {}", e);
         }
     }
 }
 
+// Blocks until the user dismisses the message, like the file dialogs do.
+fn show_error(title: &str, message: &str) {
+    rfd::MessageDialog::new()
+        .set_level(rfd::MessageLevel::Error)
+        .set_title(title)
+        .set_description(message)
+        .show();
+}
+
+// H:MM:SS.mmm
+fn format_emulated_time(time: Duration) -> String {
+    let secs = time.as_secs();
+    format!("{}:{:02}:{:02}.{:03}", secs / 3600, secs / 60 % 60, secs % 60, time.subsec_millis())
+}
+
 fn main() -> eframe::Result<()> {
     let log_level = if cfg!(debug_assertions) {
         log::LevelFilter::Debug
     } else {
         log::LevelFilter::Info
     };
-    let _ = core::log_buffer::init_logger(log_level);
+    let _ = roba_core::log_buffer::init_logger(log_level);
+    crash::install_hook();
 
     let args = Args::parse();
+    if let Some(filter) = &args.log {
+        roba_core::log_buffer::set_filter(filter.clone());
+    }
+    if let Some(path) = &args.log_file
+        && let Err(e) = roba_core::log_buffer::set_log_file(Some(path), LOG_FILE_MAX_BYTES)
+    {
+        eprintln!("Failed to open log file {:?}: {}", path, e);
+    }
+    if args.headless {
+        let config = load_config();
+        let bios = args
+            .bios
+            .clone()
+            .or(config.bios_path.clone())
+            .or_else(GbaApp::find_default_bios)
+            .or(config.replacement_bios.clone());
+        let (rom, movie) = (args.rom_path.as_deref().unwrap(), args.movie.as_deref().unwrap());
+        let dump = headless::Dump {
+            wav: args.dump_wav.clone(),
+            video: args.dump_video.clone(),
+            coverage: args.coverage_report.clone(),
+        };
+        let result = headless::run(&config, rom, bios, movie, &dump, args.expect_crc, args.expect_hash);
+        log::logger().flush();
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if let Some(duration) = args.soak {
+        let config = load_config();
+        let bios = args
+            .bios
+            .clone()
+            .or(config.bios_path.clone())
+            .or_else(GbaApp::find_default_bios)
+            .or(config.replacement_bios.clone());
+        let result = soak::run(&config, args.rom_path.as_deref().unwrap(), bios, duration);
+        log::logger().flush();
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    let link = open_link(&args);
     let icon = IconData::default();
+    let vsync = load_config().sync_mode.vsync();
     let native_options = eframe::NativeOptions {
+        vsync,
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([1024.0, 768.0])
             .with_title("RoBA - GBA Emulator")
@@ -414,9 +1509,11 @@ fn main() -> eframe::Result<()> {
         ..Default::default()
     };
 
-    eframe::run_native(
+    let result = eframe::run_native(
         "RoBA",
         native_options,
-        Box::new(|_cc| Ok(Box::new(GbaApp::new(args.rom_path, args.bios)))),
-    )
+        Box::new(move |_cc| Ok(Box::new(GbaApp::new(args.rom_path, args.bios, link, args.gb_player, args.movie)))),
+    );
+    log::logger().flush();
+    result
 }
@@ -0,0 +1,99 @@
+// Netplay host/join window. The running session lives in `GbaApp`; this
+// collects the address and options and opens the socket.
+
+use eframe::egui;
+use roba_core::netplay::{NetplayConfig, NetplayMode, Session, Transport, UdpTransport};
+
+const DEFAULT_PORT: u16 = 5739;
+
+pub enum NetplayCommand {
+    Start(Box<dyn Transport>, NetplayConfig),
+    Stop,
+}
+
+pub struct NetplayWindow {
+    pub open: bool,
+    address: String,
+    config: NetplayConfig,
+    error: Option<String>,
+}
+
+impl Default for NetplayWindow {
+    fn default() -> Self {
+        Self {
+            open: false,
+            address: format!("0.0.0.0:{}", DEFAULT_PORT),
+            config: NetplayConfig::default(),
+            error: None,
+        }
+    }
+}
+
+impl NetplayWindow {
+    /// Draws the window if open; `session` is the running one, if any.
+    pub fn show(&mut self, ctx: &egui::Context, session: Option<&Session>) -> Option<NetplayCommand> {
+        let mut command = None;
+        let mut open = self.open;
+        egui::Window::new("Netplay").open(&mut open).resizable(false).show(ctx, |ui| {
+            command = match session {
+                Some(session) => Self::status(ui, session),
+                None => self.setup(ui),
+            };
+        });
+        self.open = open;
+        command
+    }
+
+    fn status(ui: &mut egui::Ui, session: &Session) -> Option<NetplayCommand> {
+        let config = session.config();
+        if session.is_ready() {
+            ui.label(format!("Connected: {}, {} frames delay", config.mode.label(), config.delay));
+            ui.label(format!("Frame {}", session.frame()));
+            if config.mode == NetplayMode::Rollback {
+                ui.label(format!("{} frames ahead, {} rollbacks", session.frames_ahead(), session.rollbacks()));
+            }
+        } else {
+            ui.label("Waiting for the other player...");
+        }
+        ui.button("Disconnect").clicked().then_some(NetplayCommand::Stop)
+    }
+
+    fn setup(&mut self, ui: &mut egui::Ui) -> Option<NetplayCommand> {
+        ui.horizontal(|ui| {
+            ui.label("Address:");
+            ui.text_edit_singleline(&mut self.address);
+        });
+        egui::ComboBox::from_label("Mode").selected_text(self.config.mode.label()).show_ui(ui, |ui| {
+            for mode in NetplayMode::ALL {
+                ui.selectable_value(&mut self.config.mode, mode, mode.label());
+            }
+        });
+        ui.add(egui::Slider::new(&mut self.config.delay, 0..=10).text("Input delay (frames)"))
+            .on_hover_text("Higher delays hide more latency; both players should use the same.");
+        if self.config.mode == NetplayMode::Rollback {
+            ui.add(egui::Slider::new(&mut self.config.max_rollback, 1..=30).text("Max rollback (frames)"));
+        }
+        ui.label("Both players need the same ROM, BIOS and battery save. The game restarts when the session begins.");
+        if let Some(error) = &self.error {
+            ui.colored_label(egui::Color32::from_rgb(255, 100, 100), error);
+        }
+        let (host, join) = ui.horizontal(|ui| (ui.button("Host").clicked(), ui.button("Join").clicked())).inner;
+        let result = if host {
+            UdpTransport::host(self.address.as_str())
+        } else if join {
+            UdpTransport::connect(self.address.as_str())
+        } else {
+            return None;
+        };
+        match result {
+            Ok(transport) => {
+                self.error = None;
+                Some(NetplayCommand::Start(Box::new(transport), self.config))
+            }
+            Err(e) => {
+                self.error = Some(e.to_string());
+                None
+            }
+        }
+    }
+}
@@ -0,0 +1,120 @@
+// Performance overlay drawn over the game image: emulated FPS against the
+// target, and the host time of the last frame split into CPU, PPU, APU and
+// present segments. Frames over the real-time budget are logged, at most
+// once per `WARNING_INTERVAL`, so regressions show up without the overlay.
+
+use crate::sync::GBA_FPS;
+use eframe::egui;
+use roba_core::frame_report::FrameReport;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+const FPS_WINDOW: Duration = Duration::from_secs(1);
+const WARNING_INTERVAL: Duration = Duration::from_secs(5);
+const BAR_HEIGHT: f32 = 6.0;
+const BACKGROUND: egui::Color32 = egui::Color32::from_black_alpha(160);
+const SEGMENTS: [(&str, egui::Color32); 4] = [
+    ("CPU", egui::Color32::from_rgb(90, 160, 255)),
+    ("PPU", egui::Color32::from_rgb(120, 220, 120)),
+    ("APU", egui::Color32::from_rgb(255, 200, 90)),
+    ("Present", egui::Color32::from_rgb(220, 120, 220)),
+];
+
+fn frame_budget() -> Duration { Duration::from_secs_f64(1.0 / GBA_FPS) }
+
+#[derive(Default)]
+pub struct PerfMonitor {
+    // When each frame of the last `FPS_WINDOW` finished.
+    frames: VecDeque<Instant>,
+    report: FrameReport,
+    present_time: Duration,
+    // Frames over budget since the last warning.
+    slow_frames: u32,
+    last_warning: Option<Instant>,
+}
+
+impl PerfMonitor {
+    /// Records a frame the core finished at `now`, warning if it ran over
+    /// the real-time budget.
+    pub fn on_frame(&mut self, report: &FrameReport, now: Instant) {
+        self.frames.push_back(now);
+        while self.frames.front().is_some_and(|&t| now - t > FPS_WINDOW) {
+            self.frames.pop_front();
+        }
+        self.report = *report;
+
+        if report.duration <= frame_budget() {
+            return;
+        }
+        self.slow_frames += 1;
+        if self.last_warning.is_some_and(|t| now - t < WARNING_INTERVAL) {
+            return;
+        }
+        log::warn!(
+            "Slow frame: {:.2} ms of a {:.2} ms budget (CPU {:.2}, PPU {:.2}, convert {:.2}, APU {:.2}); \
+             {} slow frame(s) since the last warning",
+            ms(report.duration),
+            ms(frame_budget()),
+            ms(report.cpu_time),
+            ms(report.ppu_time),
+            ms(report.convert_time),
+            ms(report.apu_time),
+            self.slow_frames
+        );
+        self.slow_frames = 0;
+        self.last_warning = Some(now);
+    }
+
+    /// Host time spent uploading and drawing the last frame.
+    pub fn set_present_time(&mut self, time: Duration) { self.present_time = time; }
+
+    /// Frames finished over the last second.
+    pub fn fps(&self) -> usize { self.frames.len() }
+
+    // Host time per segment, in `SEGMENTS` order.
+    fn segments(&self) -> [Duration; 4] {
+        let r = &self.report;
+        [r.cpu_time, r.ppu_time + r.convert_time, r.apu_time, self.present_time]
+    }
+
+    /// Paints the overlay in the top-left corner of `image`. `target_fps` is
+    /// the frame rate the pacer aims for.
+    pub fn paint(&self, ui: &egui::Ui, image: egui::Rect, target_fps: f64) {
+        let painter = ui.painter_at(image);
+        let font = egui::FontId::monospace(12.0);
+        let color = egui::Color32::WHITE;
+        let segments = self.segments();
+        let total: Duration = segments.iter().sum();
+        let mut lines = vec![
+            format!("FPS {:>3} / {:.1}", self.fps(), target_fps),
+            format!("Frame {:.2} / {:.2} ms", ms(total), ms(frame_budget())),
+        ];
+        lines.extend(SEGMENTS.iter().zip(segments).map(|((name, _), t)| format!("{:<8}{:>6.2} ms", name, ms(t))));
+
+        let line_height = ui.fonts(|f| f.row_height(&font));
+        let width = 150.0;
+        let origin = image.min + egui::vec2(4.0, 4.0);
+        let height = line_height * lines.len() as f32 + BAR_HEIGHT + 12.0;
+        painter.rect_filled(egui::Rect::from_min_size(origin, egui::vec2(width, height)), 3.0, BACKGROUND);
+
+        let mut pos = origin + egui::vec2(4.0, 4.0);
+        for (i, line) in lines.iter().enumerate() {
+            let line_color = if i >= 2 { SEGMENTS[i - 2].1 } else { color };
+            painter.text(pos, egui::Align2::LEFT_TOP, line, font.clone(), line_color);
+            pos.y += line_height;
+        }
+
+        // Segments stacked against the budget, which spans the whole bar.
+        let bar = egui::Rect::from_min_size(pos + egui::vec2(0.0, 4.0), egui::vec2(width - 8.0, BAR_HEIGHT));
+        painter.rect_stroke(bar, 0.0, egui::Stroke::new(1.0, color));
+        let mut x = bar.min.x;
+        for ((_, segment_color), t) in SEGMENTS.iter().zip(segments) {
+            let w = (t.as_secs_f32() / frame_budget().as_secs_f32() * bar.width()).min(bar.max.x - x);
+            let rect = egui::Rect::from_min_size(egui::pos2(x, bar.min.y), egui::vec2(w, bar.height()));
+            painter.rect_filled(rect, 0.0, *segment_color);
+            x += w;
+        }
+    }
+}
+
+fn ms(time: Duration) -> f64 { time.as_secs_f64() * 1000.0 }
@@ -0,0 +1,44 @@
+// I/O register viewer for the debug panel: the PPU, DMA, timer and interrupt
+// registers with their decoded fields, highlighting those that changed
+// during the last emulated frame.
+
+use eframe::egui;
+use roba_core::io::describe::RegisterGroup;
+use roba_core::io::IoSnapshot;
+use roba_core::Emulator;
+
+const CHANGED_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 200, 100);
+
+#[derive(Default)]
+pub struct RegisterView {
+    snapshot: IoSnapshot,
+    // Bits that changed per register, in snapshot order.
+    changed: Vec<u32>,
+}
+
+impl RegisterView {
+    /// Takes a new snapshot; call after every emulated frame.
+    pub fn update(&mut self, core: &Emulator) {
+        let snapshot = core.io_snapshot();
+        self.changed = snapshot.diff(&self.snapshot);
+        self.snapshot = snapshot;
+    }
+
+    pub fn show(&self, ui: &mut egui::Ui) {
+        for group in RegisterGroup::ALL {
+            egui::CollapsingHeader::new(group.label()).show(ui, |ui| {
+                egui::Grid::new(group.label()).striped(true).show(ui, |ui| {
+                    let rows = self.snapshot.iter().zip(self.changed.iter().copied());
+                    for ((reg, value), changed) in rows.filter(|((reg, _), _)| reg.group == group) {
+                        let color = if changed != 0 { CHANGED_COLOR } else { ui.visuals().text_color() };
+                        ui.colored_label(color, reg.name).on_hover_text(format!("{:08X}", reg.addr));
+                        let hex = if reg.size == 4 { format!("{:08X}", value) } else { format!("{:04X}", value) };
+                        ui.colored_label(color, egui::RichText::new(hex).monospace());
+                        ui.label(reg.describe(value));
+                        ui.end_row();
+                    }
+                });
+            });
+        }
+    }
+}
@@ -0,0 +1,79 @@
+// Forwards cartridge / Game Boy Player rumble to gamepad force feedback.
+// Without the `gamepad` feature the motor state is only logged by the core.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::input::Gamepads;
+
+#[cfg(feature = "gamepad")]
+use gilrs::ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder, Replay, Ticks};
+
+pub struct Rumble {
+    active: Arc<AtomicBool>,
+    playing: bool,
+    #[cfg(feature = "gamepad")]
+    effect: Option<Effect>,
+}
+
+impl Rumble {
+    pub fn attach(core: &mut roba_core::Emulator) -> Self {
+        let active = Arc::new(AtomicBool::new(false));
+        let flag = active.clone();
+        core.set_rumble_callback(move |on| flag.store(on, Ordering::Relaxed));
+        Self {
+            active,
+            playing: false,
+            #[cfg(feature = "gamepad")]
+            effect: None,
+        }
+    }
+
+    /// Starts or stops the motor to match the emulated one. Call once per frame.
+    #[cfg_attr(not(feature = "gamepad"), allow(unused_variables))]
+    pub fn update(&mut self, pads: &mut Gamepads) {
+        let active = self.active.load(Ordering::Relaxed);
+        if active == self.playing {
+            return;
+        }
+        self.playing = active;
+        #[cfg(feature = "gamepad")]
+        self.set_motor(pads, active);
+    }
+
+    #[cfg(feature = "gamepad")]
+    fn set_motor(&mut self, pads: &mut Gamepads, on: bool) {
+        let Some(gilrs) = pads.gilrs_mut() else {
+            return;
+        };
+        if on {
+            let pads: Vec<_> = gilrs
+                .gamepads()
+                .filter(|(_, pad)| pad.is_ff_supported())
+                .map(|(id, _)| id)
+                .collect();
+            if pads.is_empty() {
+                return;
+            }
+            let effect = EffectBuilder::new()
+                .add_effect(BaseEffect {
+                    kind: BaseEffectType::Strong { magnitude: 0xC000 },
+                    scheduling: Replay { play_for: Ticks::from_ms(100), ..Default::default() },
+                    ..Default::default()
+                })
+                .gamepads(&pads)
+                .finish(gilrs);
+            match effect {
+                Ok(effect) => {
+                    if let Err(e) = effect.play() {
+                        log::warn!("Failed to start rumble: {}", e);
+                    }
+                    self.effect = Some(effect);
+                }
+                Err(e) => log::warn!("Failed to create rumble effect: {}", e),
+            }
+        } else if let Some(effect) = self.effect.take() {
+            let _ = effect.stop();
+        }
+    }
+}
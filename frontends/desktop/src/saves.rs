@@ -0,0 +1,138 @@
+// Per-game save directory. Everything kept for a game lives together in
+// `<save root>/<game code>-<ROM CRC32>/`, so other revisions or hacks of the
+// same game never share a battery save:
+//
+//     AXVE-1F1C08FB/
+//         battery.sav     raw SRAM/flash/EEPROM, as mGBA and VBA write it
+//         state.ss1
+//         cheats.toml
+//         overrides.toml  accuracy settings for this game only
+//         screenshots/
+//
+// The root is `save_dir` from the config, or `saves` in the data directory.
+// States, cheats and `.sav` files kept next to the ROM by earlier versions
+// or other emulators are still read until the per-game copy exists.
+
+use roba_core::Emulator;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// Games write a save in many small steps; it goes to disk once they have
+// been quiet this long.
+const FLUSH_DELAY: Duration = Duration::from_secs(1);
+
+pub fn default_root() -> Option<PathBuf> {
+    directories::ProjectDirs::from("com", "RoBA", "RoBA").map(|dirs| dirs.data_dir().join("saves"))
+}
+
+pub struct GameSaves {
+    dir: PathBuf,
+    // `<rom>.sav` per the core config; other legacy files share its stem.
+    legacy: Option<PathBuf>,
+    // First unsaved write to the backup chip since the last flush.
+    dirty_since: Option<Instant>,
+}
+
+impl GameSaves {
+    pub fn new(root: &Path, core: &Emulator) -> Self {
+        let code = core
+            .rom_header()
+            .map(|h| h.game_code.as_str())
+            .filter(|code| code.len() == 4 && code.chars().all(|c| c.is_ascii_alphanumeric()))
+            .unwrap_or("XXXX");
+        Self {
+            dir: root.join(format!("{}-{:08X}", code, core.rom_crc32())),
+            legacy: core.save_path(),
+            dirty_since: None,
+        }
+    }
+
+    pub fn dir(&self) -> &Path { &self.dir }
+    pub fn battery_path(&self) -> PathBuf { self.dir.join("battery.sav") }
+    pub fn state_path(&self) -> PathBuf { self.dir.join("state.ss1") }
+    pub fn cheats_path(&self) -> PathBuf { self.dir.join("cheats.toml") }
+    pub fn overrides_path(&self) -> PathBuf { self.dir.join("overrides.toml") }
+    pub fn screenshot_dir(&self) -> PathBuf { self.dir.join("screenshots") }
+
+    pub fn state_to_load(&self) -> PathBuf { self.existing(self.state_path(), "ss1") }
+    pub fn cheats_to_load(&self) -> PathBuf { self.existing(self.cheats_path(), "cheats.toml") }
+
+    // `path`, or the legacy file with `ext` while `path` does not exist.
+    fn existing(&self, path: PathBuf, ext: &str) -> PathBuf {
+        if !path.exists()
+            && let Some(old) = self.legacy.as_ref().map(|p| p.with_extension(ext))
+            && old.exists()
+        {
+            return old;
+        }
+        path
+    }
+
+    /// Creates the directory and writes `data` to `path` inside it.
+    pub fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(path, data)
+    }
+
+    /// Loads the battery save into `core`; call after the cart configuration
+    /// is final, since changing it clears the backup chip.
+    pub fn load_battery(&mut self, core: &mut Emulator) {
+        let path = self.existing(self.battery_path(), "sav");
+        match fs::read(&path) {
+            Ok(data) => match core.load_battery_save(&data) {
+                Ok(()) => log::info!("Battery save loaded from {:?}", path),
+                Err(e) => log::warn!("Ignoring battery save {:?}: {}", path, e),
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => log::error!("Failed to read battery save {:?}: {}", path, e),
+        }
+        core.take_battery_dirty();
+        self.dirty_since = None;
+    }
+
+    /// Writes the battery save once the game has stopped writing to it.
+    pub fn update(&mut self, core: &mut Emulator, now: Instant) {
+        if core.take_battery_dirty() {
+            self.dirty_since = Some(now);
+        }
+        if self.dirty_since.is_some_and(|since| now - since >= FLUSH_DELAY) {
+            self.flush(core);
+        }
+    }
+
+    /// Writes the battery save now if it has unsaved changes.
+    pub fn flush(&mut self, core: &mut Emulator) {
+        if core.take_battery_dirty() | self.dirty_since.take().is_some() {
+            self.write_battery(core);
+        }
+    }
+
+    fn write_battery(&self, core: &Emulator) {
+        let data = core.battery_save();
+        if data.is_empty() {
+            return;
+        }
+        let path = self.battery_path();
+        match self.write(&path, &data) {
+            Ok(()) => log::info!("Battery save written to {:?}", path),
+            Err(e) => log::error!("Failed to write battery save {:?}: {}", path, e),
+        }
+    }
+
+    /// Replaces the battery save with a raw `.sav` from another emulator.
+    /// The game only sees it after a restart.
+    pub fn import_sav(&mut self, core: &mut Emulator, path: &Path) -> io::Result<()> {
+        let data = fs::read(path)?;
+        core.load_battery_save(&data).map_err(io::Error::other)?;
+        core.take_battery_dirty();
+        self.dirty_since = None;
+        self.write(&self.battery_path(), &core.battery_save())
+    }
+
+    /// Writes the battery save as a raw `.sav`, sized for the backup chip.
+    pub fn export_sav(&self, core: &Emulator, path: &Path) -> io::Result<()> {
+        fs::write(path, core.battery_save())
+    }
+}
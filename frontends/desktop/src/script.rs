@@ -0,0 +1,359 @@
+// Rhai scripting. A script's top level runs once when it is loaded and
+// registers callbacks with `on_frame_start`, `on_frame_end`, `on_read` and
+// `on_write`; the callbacks then run around every emulated frame.
+//
+// While callbacks run, the emulator is swapped into the shared host so the
+// functions registered with each engine can reach it.
+
+use eframe::egui;
+use rhai::{Engine, FnPtr, AST, INT};
+use roba_core::bus::BusAccess;
+use roba_core::bus::watch::{AccessKind, MemoryAccess, Watchpoint};
+use roba_core::input::KeyState;
+use roba_core::Emulator;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+// Bounds each callback so a runaway loop reports an error instead of
+// freezing the UI.
+const MAX_OPERATIONS: u64 = 10_000_000;
+
+#[derive(Clone)]
+enum Hook {
+    FrameStart,
+    FrameEnd,
+    Access(Watchpoint),
+}
+
+enum Shape {
+    Text { x: f32, y: f32, text: String, color: egui::Color32 },
+    Rect { x: f32, y: f32, w: f32, h: f32, color: egui::Color32, filled: bool },
+}
+
+struct Host {
+    core: Emulator,
+    keys: KeyState,
+    shapes: Vec<Shape>,
+    hooks: Vec<(usize, Hook, FnPtr)>,
+}
+
+struct Script {
+    id: usize,
+    path: PathBuf,
+    engine: Engine,
+    ast: Option<AST>,
+    error: Option<String>,
+}
+
+pub struct Scripts {
+    host: Rc<RefCell<Host>>,
+    scripts: Vec<Script>,
+    next_id: usize,
+}
+
+fn color(rgb: INT) -> egui::Color32 {
+    let [_, r, g, b] = (rgb as u32).to_be_bytes();
+    egui::Color32::from_rgb(r, g, b)
+}
+
+fn button(name: &str) -> Result<KeyState, Box<rhai::EvalAltResult>> {
+    KeyState::BUTTONS
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|&(_, key)| key)
+        .ok_or_else(|| format!("unknown button {:?}", name).into())
+}
+
+fn new_engine(host: &Rc<RefCell<Host>>, id: usize, name: &str) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    let name = name.to_string();
+    engine.on_print(move |s| log::info!("[{}] {}", name, s));
+
+    let h = host.clone();
+    engine.register_fn("read8", move |addr: INT| h.borrow_mut().core.bus_mut().peek8(addr as u32) as INT);
+    let h = host.clone();
+    engine.register_fn("read16", move |addr: INT| h.borrow_mut().core.bus_mut().peek16(addr as u32) as INT);
+    let h = host.clone();
+    engine.register_fn("read32", move |addr: INT| h.borrow_mut().core.bus_mut().peek32(addr as u32) as INT);
+    let h = host.clone();
+    engine.register_fn("write8", move |addr: INT, v: INT| h.borrow_mut().core.bus_mut().poke8(addr as u32, v as u8));
+    let h = host.clone();
+    engine.register_fn("write16", move |addr: INT, v: INT| h.borrow_mut().core.bus_mut().poke16(addr as u32, v as u16));
+    let h = host.clone();
+    engine.register_fn("write32", move |addr: INT, v: INT| h.borrow_mut().core.bus_mut().poke32(addr as u32, v as u32));
+
+    let h = host.clone();
+    engine.register_fn("reg", move |i: INT| match i {
+        0..=15 => h.borrow().core.cpu().read_reg(i as usize) as INT,
+        _ => 0,
+    });
+    let h = host.clone();
+    engine.register_fn("set_reg", move |i: INT, v: INT| {
+        if (0..=15).contains(&i) {
+            h.borrow_mut().core.cpu_mut().write_reg(i as usize, v as u32);
+        }
+    });
+    let h = host.clone();
+    engine.register_fn("cpsr", move || h.borrow().core.cpu().cpsr().raw() as INT);
+    let h = host.clone();
+    engine.register_fn("frame", move || h.borrow().core.frame_count() as INT);
+
+    let h = host.clone();
+    engine.register_fn("press", move |name: &str| {
+        h.borrow_mut().keys.set(button(name)?, true);
+        Ok::<_, Box<rhai::EvalAltResult>>(())
+    });
+    let h = host.clone();
+    engine.register_fn("release", move |name: &str| {
+        h.borrow_mut().keys.set(button(name)?, false);
+        Ok::<_, Box<rhai::EvalAltResult>>(())
+    });
+    let h = host.clone();
+    engine.register_fn("held", move |name: &str| Ok::<_, Box<rhai::EvalAltResult>>(h.borrow().keys.contains(button(name)?)));
+
+    let h = host.clone();
+    engine.register_fn("text", move |x: INT, y: INT, text: &str| {
+        let text = text.to_string();
+        h.borrow_mut().shapes.push(Shape::Text { x: x as f32, y: y as f32, text, color: egui::Color32::WHITE });
+    });
+    let h = host.clone();
+    engine.register_fn("text", move |x: INT, y: INT, text: &str, rgb: INT| {
+        let text = text.to_string();
+        h.borrow_mut().shapes.push(Shape::Text { x: x as f32, y: y as f32, text, color: color(rgb) });
+    });
+    for (name, filled) in [("rect", false), ("fill", true)] {
+        let h = host.clone();
+        engine.register_fn(name, move |x: INT, y: INT, w: INT, hgt: INT, rgb: INT| {
+            let (x, y, w, h_) = (x as f32, y as f32, w as f32, hgt as f32);
+            h.borrow_mut().shapes.push(Shape::Rect { x, y, w, h: h_, color: color(rgb), filled });
+        });
+    }
+
+    let h = host.clone();
+    engine.register_fn("on_frame_start", move |f: FnPtr| h.borrow_mut().hooks.push((id, Hook::FrameStart, f)));
+    let h = host.clone();
+    engine.register_fn("on_frame_end", move |f: FnPtr| h.borrow_mut().hooks.push((id, Hook::FrameEnd, f)));
+    for (name, kind) in [("on_read", AccessKind::Read), ("on_write", AccessKind::Write)] {
+        let h = host.clone();
+        engine.register_fn(name, move |addr: INT, len: INT, f: FnPtr| {
+            let point = Watchpoint::new(addr as u32, len as u32, kind);
+            let mut host = h.borrow_mut();
+            host.core.bus_mut().watchpoints.add(point);
+            host.hooks.push((id, Hook::Access(point), f));
+        });
+    }
+    engine
+}
+
+impl Scripts {
+    pub fn new() -> Self {
+        let host = Host { core: Emulator::new(), keys: KeyState::NONE, shapes: Vec::new(), hooks: Vec::new() };
+        Self { host: Rc::new(RefCell::new(host)), scripts: Vec::new(), next_id: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool { self.scripts.is_empty() }
+
+    pub fn load(&mut self, core: &mut Emulator, path: &Path) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let engine = new_engine(&self.host, id, &name);
+        self.scripts.push(Script { id, path: path.to_path_buf(), engine, ast: None, error: None });
+        self.start(core, self.scripts.len() - 1);
+    }
+
+    pub fn reload(&mut self, core: &mut Emulator, index: usize) {
+        self.drop_hooks(core, self.scripts[index].id);
+        self.start(core, index);
+    }
+
+    pub fn remove(&mut self, core: &mut Emulator, index: usize) {
+        let script = self.scripts.remove(index);
+        self.drop_hooks(core, script.id);
+    }
+
+    // Compiles the script and runs its top level.
+    fn start(&mut self, core: &mut Emulator, index: usize) {
+        let script = &mut self.scripts[index];
+        script.ast = None;
+        script.error = None;
+        let result = std::fs::read_to_string(&script.path)
+            .map_err(|e| e.to_string())
+            .and_then(|src| script.engine.compile(src).map_err(|e| e.to_string()));
+        let ast = match result {
+            Ok(ast) => ast,
+            Err(e) => {
+                log::error!("Script {:?}: {}", script.path, e);
+                script.error = Some(e);
+                return;
+            }
+        };
+        let id = script.id;
+        std::mem::swap(core, &mut self.host.borrow_mut().core);
+        let result = self.scripts[index].engine.run_ast(&ast);
+        std::mem::swap(core, &mut self.host.borrow_mut().core);
+        let script = &mut self.scripts[index];
+        script.ast = Some(ast);
+        if let Err(e) = result {
+            log::error!("Script {:?}: {}", script.path, e);
+            script.error = Some(e.to_string());
+            self.drop_hooks(core, id);
+        } else {
+            log::info!("Loaded script {:?}", script.path);
+        }
+    }
+
+    fn drop_hooks(&mut self, core: &mut Emulator, id: usize) {
+        let mut host = self.host.borrow_mut();
+        host.hooks.retain(|(owner, _, _)| *owner != id);
+        let watchpoints = &mut core.bus_mut().watchpoints;
+        watchpoints.clear();
+        for (_, hook, _) in &host.hooks {
+            if let Hook::Access(point) = hook {
+                watchpoints.add(*point);
+            }
+        }
+    }
+
+    /// Runs the frame start callbacks. Returns `keys` with the buttons
+    /// scripts pressed or released.
+    pub fn frame_start(&mut self, core: &mut Emulator, keys: KeyState) -> KeyState {
+        let mut host = self.host.borrow_mut();
+        host.keys = keys;
+        host.shapes.clear();
+        if host.hooks.is_empty() {
+            return keys;
+        }
+        drop(host);
+        self.run_hooks(core, &[], |hook, _| matches!(hook, Hook::FrameStart));
+        self.host.borrow().keys
+    }
+
+    /// Runs the memory access callbacks for the frame just emulated, then the
+    /// frame end callbacks.
+    pub fn frame_end(&mut self, core: &mut Emulator) {
+        let hits = core.bus_mut().watchpoints.take_hits();
+        if self.host.borrow().hooks.is_empty() {
+            return;
+        }
+        self.run_hooks(core, &hits, |hook, hit| match (hook, hit) {
+            (Hook::Access(point), Some(hit)) => point.matches(hit),
+            _ => false,
+        });
+        self.run_hooks(core, &[], |hook, _| matches!(hook, Hook::FrameEnd));
+    }
+
+    // Calls the hooks selected by `filter`, once per hit (with the address
+    // and value as arguments), or once without arguments when `hits` is empty.
+    fn run_hooks(
+        &mut self,
+        core: &mut Emulator,
+        hits: &[MemoryAccess],
+        filter: impl Fn(&Hook, Option<&MemoryAccess>) -> bool,
+    ) {
+        let hooks = self.host.borrow().hooks.clone();
+        let mut failed = Vec::new();
+        std::mem::swap(core, &mut self.host.borrow_mut().core);
+        let calls: Vec<Option<&MemoryAccess>> = if hits.is_empty() { vec![None] } else { hits.iter().map(Some).collect() };
+        for hit in calls {
+            for (id, hook, f) in &hooks {
+                if failed.contains(id) || !filter(hook, hit) {
+                    continue;
+                }
+                let Some(script) = self.scripts.iter_mut().find(|s| s.id == *id) else {
+                    continue;
+                };
+                let Some(ast) = &script.ast else {
+                    continue;
+                };
+                let result = match hit {
+                    Some(hit) => f.call::<rhai::Dynamic>(&script.engine, ast, (hit.addr as INT, hit.value as INT)),
+                    None => f.call::<rhai::Dynamic>(&script.engine, ast, ()),
+                };
+                if let Err(e) = result {
+                    log::error!("Script {:?}: {}", script.path, e);
+                    script.error = Some(e.to_string());
+                    failed.push(*id);
+                }
+            }
+        }
+        std::mem::swap(core, &mut self.host.borrow_mut().core);
+        for id in failed {
+            self.drop_hooks(core, id);
+        }
+    }
+
+    /// Draws the shapes scripts queued this frame over the game image.
+    pub fn paint(&self, ui: &egui::Ui, image: egui::Rect) {
+        let host = self.host.borrow();
+        if host.shapes.is_empty() {
+            return;
+        }
+        let scale = image.width() / roba_core::video::GBA_SCREEN_W as f32;
+        let at = |x: f32, y: f32| image.min + egui::vec2(x, y) * scale;
+        let painter = ui.painter_at(image);
+        for shape in &host.shapes {
+            match shape {
+                Shape::Text { x, y, text, color } => {
+                    let font = egui::FontId::monospace(8.0 * scale);
+                    painter.text(at(*x, *y), egui::Align2::LEFT_TOP, text, font, *color);
+                }
+                Shape::Rect { x, y, w, h, color, filled } => {
+                    let rect = egui::Rect::from_min_max(at(*x, *y), at(x + w, y + h));
+                    if *filled {
+                        painter.rect_filled(rect, 0.0, *color);
+                    } else {
+                        painter.rect_stroke(rect, 0.0, egui::Stroke::new(scale.max(1.0), *color));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ScriptWindow {
+    pub open: bool,
+}
+
+impl ScriptWindow {
+    pub fn show(&mut self, ctx: &egui::Context, scripts: &mut Scripts, core: &mut Emulator) {
+        let mut open = self.open;
+        egui::Window::new("Scripts").open(&mut open).show(ctx, |ui| {
+            if scripts.is_empty() {
+                ui.label("No scripts loaded.");
+            }
+            let (mut reload, mut remove) = (None, None);
+            for (i, script) in scripts.scripts.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    let name = script.path.file_name().unwrap_or_default().to_string_lossy();
+                    ui.label(name).on_hover_text(script.path.display().to_string());
+                    if ui.small_button("Reload").clicked() {
+                        reload = Some(i);
+                    }
+                    if ui.small_button("Remove").clicked() {
+                        remove = Some(i);
+                    }
+                });
+                if let Some(error) = &script.error {
+                    ui.colored_label(egui::Color32::from_rgb(255, 100, 100), error);
+                }
+            }
+            if let Some(i) = reload {
+                scripts.reload(core, i);
+            }
+            if let Some(i) = remove {
+                scripts.remove(core, i);
+            }
+            ui.separator();
+            if ui.button("Load Script...").clicked()
+                && let Some(path) = rfd::FileDialog::new().add_filter("Rhai script", &["rhai"]).pick_file()
+            {
+                scripts.load(core, &path);
+            }
+        });
+        self.open = open;
+    }
+}
@@ -0,0 +1,204 @@
+// Cheat search: narrows work RAM down to the address holding a value, then
+// watches it live or freezes it with a cheat.
+
+use eframe::egui;
+use roba_core::cheats::{Cheat, CheatFormat};
+use roba_core::search::{Comparison, MemorySearch, SearchRegion, ValueSize, Watch};
+use roba_core::Emulator;
+
+// Listing more candidates than this is not useful; keep narrowing instead.
+const MAX_LISTED: usize = 200;
+
+fn size_label(size: ValueSize) -> &'static str {
+    match size {
+        ValueSize::Byte => "8-bit",
+        ValueSize::Half => "16-bit",
+        ValueSize::Word => "32-bit",
+    }
+}
+
+fn parse_value(text: &str) -> Option<u32> {
+    let text = text.trim();
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+// Raw GameShark v1 code writing `value` every frame.
+fn freeze_code(addr: u32, size: ValueSize, value: u32) -> String {
+    let kind = match size {
+        ValueSize::Byte => 0,
+        ValueSize::Half => 1,
+        ValueSize::Word => 2,
+    };
+    format!("{:X}{:07X} {:08X}", kind, addr & 0x0FFF_FFFF, value)
+}
+
+pub struct SearchWindow {
+    pub open: bool,
+    size: ValueSize,
+    ewram: bool,
+    iwram: bool,
+    value: String,
+    search: Option<MemorySearch>,
+    watches: Vec<Watch>,
+}
+
+impl Default for SearchWindow {
+    fn default() -> Self {
+        Self {
+            open: false,
+            size: ValueSize::Byte,
+            ewram: true,
+            iwram: true,
+            value: String::new(),
+            search: None,
+            watches: Vec::new(),
+        }
+    }
+}
+
+impl SearchWindow {
+    /// Drops the search and watches of the previous game.
+    pub fn reset(&mut self) {
+        self.search = None;
+        self.watches.clear();
+    }
+
+    /// Draws the window if open. Returns true when a cheat was added.
+    pub fn show(&mut self, ctx: &egui::Context, core: &mut Emulator) -> bool {
+        let mut added = false;
+        let mut open = self.open;
+        egui::Window::new("Cheat Search").open(&mut open).show(ctx, |ui| {
+            self.controls(ui, core);
+            ui.separator();
+            self.results(ui, core);
+            ui.separator();
+            added = self.watch_list(ui, core);
+        });
+        self.open = open;
+        added
+    }
+
+    fn controls(&mut self, ui: &mut egui::Ui, core: &Emulator) {
+        let mem = core.mem();
+        ui.horizontal(|ui| {
+            let searching = self.search.is_some();
+            ui.add_enabled_ui(!searching, |ui| {
+                egui::ComboBox::from_id_source("search_size")
+                    .selected_text(size_label(self.size))
+                    .show_ui(ui, |ui| {
+                        for size in ValueSize::ALL {
+                            ui.selectable_value(&mut self.size, size, size_label(size));
+                        }
+                    });
+                ui.checkbox(&mut self.ewram, "EWRAM");
+                ui.checkbox(&mut self.iwram, "IWRAM");
+            });
+            if ui.button(if searching { "Restart" } else { "Start" }).clicked() {
+                let regions: Vec<SearchRegion> = [(self.ewram, SearchRegion::Ewram), (self.iwram, SearchRegion::Iwram)]
+                    .into_iter()
+                    .filter_map(|(on, r)| on.then_some(r))
+                    .collect();
+                self.search = Some(MemorySearch::new(mem, self.size, &regions));
+            }
+        });
+
+        let Some(search) = &mut self.search else {
+            ui.label("Start a search, then narrow it down as the value changes in game.");
+            return;
+        };
+        ui.horizontal(|ui| {
+            ui.label("Value:");
+            ui.add(egui::TextEdit::singleline(&mut self.value).desired_width(80.0).hint_text("100 or 0x64"));
+            let value = parse_value(&self.value);
+            if ui.add_enabled(value.is_some(), egui::Button::new("=")).clicked() {
+                search.refine(mem, Comparison::Equal(value.unwrap()));
+            }
+            if ui.add_enabled(value.is_some(), egui::Button::new("≠")).clicked() {
+                search.refine(mem, Comparison::NotEqual(value.unwrap()));
+            }
+        });
+        ui.horizontal(|ui| {
+            for (label, comparison) in [
+                ("Changed", Comparison::Changed),
+                ("Unchanged", Comparison::Unchanged),
+                ("Increased", Comparison::Increased),
+                ("Decreased", Comparison::Decreased),
+            ] {
+                if ui.button(label).clicked() {
+                    search.refine(mem, comparison);
+                }
+            }
+        });
+    }
+
+    fn results(&mut self, ui: &mut egui::Ui, core: &Emulator) {
+        let Some(search) = &self.search else {
+            return;
+        };
+        let mem = core.mem();
+        ui.label(format!("{} candidates", search.len()));
+        if search.len() > MAX_LISTED {
+            return;
+        }
+        let size = search.size();
+        let mut watch = None;
+        egui::ScrollArea::vertical().id_source("search_results").max_height(200.0).show(ui, |ui| {
+            egui::Grid::new("search_results_grid").striped(true).show(ui, |ui| {
+                for c in search.candidates() {
+                    let current = roba_core::search::read(mem, c.addr, size).unwrap_or(0);
+                    ui.monospace(format!("{:08X}", c.addr));
+                    ui.monospace(format!("{}", current));
+                    ui.weak(format!("was {}", c.value));
+                    if ui.small_button("Watch").clicked() {
+                        watch = Some(c.addr);
+                    }
+                    ui.end_row();
+                }
+            });
+        });
+        if let Some(addr) = watch
+            && !self.watches.iter().any(|w| w.addr == addr && w.size == size)
+        {
+            self.watches.push(Watch::new(&format!("{:08X}", addr), addr, size));
+        }
+    }
+
+    fn watch_list(&mut self, ui: &mut egui::Ui, core: &mut Emulator) -> bool {
+        ui.strong("Watches");
+        if self.watches.is_empty() {
+            ui.label("No watches.");
+            return false;
+        }
+        let mut added = false;
+        let mut remove = None;
+        egui::Grid::new("watch_grid").striped(true).show(ui, |ui| {
+            for (i, watch) in self.watches.iter_mut().enumerate() {
+                let value = watch.value(core.mem()).unwrap_or(0);
+                ui.add(egui::TextEdit::singleline(&mut watch.label).desired_width(100.0));
+                ui.monospace(format!("{:08X}", watch.addr));
+                ui.monospace(format!("{} (0x{:X})", value, value));
+                if ui.small_button("Freeze").on_hover_text("Add a cheat holding the current value").clicked() {
+                    let code = freeze_code(watch.addr, watch.size, value);
+                    match Cheat::parse(&watch.label, CheatFormat::GameSharkV1, false, &code) {
+                        Ok(cheat) => {
+                            core.cheats_mut().add(cheat);
+                            added = true;
+                        }
+                        Err(e) => log::error!("Failed to create cheat {:?}: {}", code, e),
+                    }
+                }
+                if ui.small_button("Remove").clicked() {
+                    remove = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(i) = remove {
+            self.watches.remove(i);
+        }
+        added
+    }
+}
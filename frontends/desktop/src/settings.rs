@@ -0,0 +1,355 @@
+use crate::config::Config;
+use crate::input::{Binding, DpadStick, Hotkey, InputHandler, InputMap};
+use crate::sync::SyncMode;
+use crate::video::{ScaleMode, Shader};
+use eframe::egui;
+use roba_core::config::{Accuracy, AccuracyPreset};
+use roba_core::input::KeyState;
+use roba_core::video::ColorProfile;
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+enum Tab {
+    #[default]
+    General,
+    Video,
+    Audio,
+    Input,
+}
+
+#[derive(Default)]
+pub struct SettingsWindow {
+    pub open: bool,
+    tab: Tab,
+    // Action waiting for its next key/pad input.
+    capturing: Option<String>,
+    // Pad whose bindings the input tab shows; None for the shared ones.
+    pad_profile: Option<String>,
+}
+
+impl SettingsWindow {
+    /// Draws the window if open. Returns true when `config` was edited.
+    pub fn show(&mut self, ctx: &egui::Context, config: &mut Config, input: &mut InputHandler) -> bool {
+        let mut changed = false;
+        let mut open = self.open;
+        egui::Window::new("Settings")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut self.tab, Tab::General, "General");
+                    ui.selectable_value(&mut self.tab, Tab::Video, "Video");
+                    ui.selectable_value(&mut self.tab, Tab::Audio, "Audio");
+                    ui.selectable_value(&mut self.tab, Tab::Input, "Input");
+                });
+                ui.separator();
+                changed = match self.tab {
+                    Tab::General => Self::general(ui, config),
+                    Tab::Video => Self::video(ui, config),
+                    Tab::Audio => Self::audio(ui, config),
+                    Tab::Input => self.input(ui, config, input),
+                };
+            });
+        self.open = open;
+        changed
+    }
+
+    fn general(ui: &mut egui::Ui, config: &mut Config) -> bool {
+        let mut changed = false;
+        changed |= path_row(ui, "BIOS", &mut config.bios_path, false);
+        changed |= path_row(ui, "Replacement BIOS", &mut config.replacement_bios, false);
+        changed |= ui.checkbox(&mut config.skip_bios, "Skip BIOS intro").changed();
+        changed |= ui
+            .checkbox(&mut config.patch_header, "Fix invalid cartridge headers")
+            .on_hover_text(
+                "Writes the Nintendo logo and header checksum into ROMs the BIOS would refuse, \
+                 as homebrew often lacks them. Applies to ROMs loaded afterwards.",
+            )
+            .changed();
+        changed |= path_row(ui, "Save directory", &mut config.save_dir, true);
+        changed |= path_row(ui, "Capture directory", &mut config.capture_dir, true);
+        changed |= path_row(ui, "Box art directory", &mut config.cover_dir, true);
+        changed |= ui
+            .add(egui::Slider::new(&mut config.rewind_seconds, 0..=60).suffix(" s").text("Rewind history"))
+            .on_hover_text("0 disables rewind. Longer histories use more memory.")
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut config.frame_step, 1..=600).text("Frames per step"))
+            .on_hover_text("Frames the \"Step frames\" hotkey runs while paused.")
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut config.fast_forward_speed, 2..=32).suffix("x").text("Fast-forward speed"))
+            .on_hover_text("Frames run per displayed frame while fast-forward is held.")
+            .changed();
+        changed |= ui
+            .checkbox(&mut config.fast_forward_frame_skip, "Skip drawing while fast-forwarding")
+            .on_hover_text("Draws only the frames that get displayed; allows higher speeds on slow machines.")
+            .changed();
+        egui::ComboBox::from_label("Synchronization")
+            .selected_text(config.sync_mode.label())
+            .show_ui(ui, |ui| {
+                for mode in SyncMode::ALL {
+                    changed |= ui.selectable_value(&mut config.sync_mode, mode, mode.label()).changed();
+                }
+            });
+        changed |= ui
+            .add(egui::Slider::new(&mut config.idle_fps, 0..=60).suffix(" fps").text("Idle refresh"))
+            .on_hover_text(
+                "Refresh rate while paused or in the library, which also sets how quickly gamepad buttons \
+                 and new log lines show up. 0 refreshes only on mouse and keyboard input.",
+            )
+            .changed();
+        egui::ComboBox::from_label("Accuracy preset")
+            .selected_text(config.preset().map_or("Custom".to_string(), |p| format!("{:?}", p)))
+            .show_ui(ui, |ui| {
+                for preset in AccuracyPreset::ALL {
+                    if ui.selectable_label(config.preset() == Some(preset), format!("{:?}", preset)).clicked() {
+                        config.set_preset(preset);
+                        changed = true;
+                    }
+                }
+            });
+        egui::ComboBox::from_label("Rendering")
+            .selected_text(accuracy_label(config.accuracy))
+            .show_ui(ui, |ui| {
+                for accuracy in Accuracy::ALL {
+                    changed |= ui.selectable_value(&mut config.accuracy, accuracy, accuracy_label(accuracy)).changed();
+                }
+            });
+        changed |= ui
+            .checkbox(&mut config.cycle_timing, "Cycle-accurate bus timing")
+            .on_hover_text("Models gamepak bursts and the prefetch buffer. Off, every ROM access has a fixed cost.")
+            .changed();
+        changed |= ui
+            .checkbox(&mut config.idle_loop_skip, "Skip idle loops")
+            .on_hover_text("Fast-forwards through loops that only wait for an interrupt.")
+            .changed();
+        changed |= ui
+            .checkbox(&mut config.open_bus, "Open bus")
+            .on_hover_text("Reads from unmapped addresses return the last opcode fetched, as on hardware.")
+            .changed();
+        changed |= ui
+            .checkbox(&mut config.deterministic, "Deterministic mode")
+            .on_hover_text("Starts the cartridge clock at a fixed time instead of the host's, so movies replay the same.")
+            .changed();
+        ui.label("BIOS and skip-BIOS changes apply the next time a ROM is loaded. The replacement BIOS is used when no official dump is set.");
+        ui.label("Switching VSync on or off needs a restart.");
+        ui.label("Games with their own accuracy settings (Emulation > Accuracy) ignore the ones here.");
+        changed
+    }
+
+    fn video(ui: &mut egui::Ui, config: &mut Config) -> bool {
+        let video = &mut config.video;
+        let mut changed = false;
+        egui::ComboBox::from_label("Scaling")
+            .selected_text(format!("{:?}", video.scale_mode))
+            .show_ui(ui, |ui| {
+                for mode in ScaleMode::ALL {
+                    changed |= ui.selectable_value(&mut video.scale_mode, mode, format!("{:?}", mode)).changed();
+                }
+            });
+        if video.scale_mode == ScaleMode::Fixed {
+            changed |= ui
+                .add(egui::Slider::new(&mut video.scale, 1.0..=6.0).step_by(1.0).text("Scale"))
+                .changed();
+        }
+        egui::ComboBox::from_label("Shader")
+            .selected_text(format!("{:?}", video.shader))
+            .show_ui(ui, |ui| {
+                for shader in Shader::ALL {
+                    changed |= ui.selectable_value(&mut video.shader, shader, format!("{:?}", shader)).changed();
+                }
+            });
+        egui::ComboBox::from_label("Colors")
+            .selected_text(profile_label(video.color_profile))
+            .show_ui(ui, |ui| {
+                for profile in ColorProfile::ALL {
+                    changed |= ui
+                        .selectable_value(&mut video.color_profile, profile, profile_label(profile))
+                        .changed();
+                }
+            });
+        changed |= ui.checkbox(&mut video.smooth, "Bilinear filtering").changed();
+        changed |= ui.checkbox(&mut video.fullscreen, "Fullscreen").changed();
+        changed |= ui.checkbox(&mut video.perf_overlay, "Performance overlay").changed();
+        changed
+    }
+
+    fn audio(ui: &mut egui::Ui, config: &mut Config) -> bool {
+        let audio = &mut config.audio;
+        let mut changed = ui
+            .add(egui::Slider::new(&mut audio.volume, 0.0..=1.0).text("Volume"))
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut audio.latency_ms, 16..=256).suffix(" ms").text("Latency"))
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut audio.rate_control, 0.0..=0.02).text("Rate control"))
+            .on_hover_text("Largest pitch change used to keep the audio buffer from draining or filling up.")
+            .changed();
+        changed
+    }
+
+    fn input(&mut self, ui: &mut egui::Ui, config: &mut Config, input: &mut InputHandler) -> bool {
+        let mut changed = false;
+        changed |= ui.checkbox(&mut config.touch.enabled, "On-screen touch controls").changed();
+        if config.touch.enabled {
+            changed |= ui.add(egui::Slider::new(&mut config.touch.opacity, 0.0..=1.0).text("Opacity")).changed();
+        }
+        ui.separator();
+        changed |= self.gamepads(ui, config, input);
+        ui.separator();
+
+        // A pad without bindings of its own shows and edits the shared ones.
+        let own = self.pad_profile.clone().filter(|pad| config.gamepad.bindings.contains_key(pad));
+        let map = match &own {
+            Some(pad) => config.gamepad.bindings.get_mut(pad).unwrap(),
+            None => &mut config.input,
+        };
+        if let Some(action) = &self.capturing
+            && let Some(binding) = input.capture(ui.ctx(), own.as_deref())
+        {
+            if binding != Binding::Key(egui::Key::Escape) {
+                map.add(action, binding);
+                changed = true;
+            }
+            self.capturing = None;
+        }
+
+        let actions = KeyState::BUTTONS
+            .iter()
+            .map(|&(name, _)| name)
+            .chain(Hotkey::ALL.iter().map(|hk| hk.name()));
+        let prompt = if own.is_some() { "Press a button on the pad (Esc cancels)..." } else { "Press a key or button (Esc cancels)..." };
+        egui::Grid::new("input_bindings").num_columns(2).striped(true).show(ui, |ui| {
+            for action in actions {
+                ui.label(action);
+                ui.horizontal_wrapped(|ui| {
+                    for binding in map.bindings(action).to_vec() {
+                        if ui.button(binding.to_string()).on_hover_text("Click to remove").clicked() {
+                            map.remove(action, &binding);
+                            changed = true;
+                        }
+                    }
+                    if self.capturing.as_deref() == Some(action) {
+                        ui.label(prompt);
+                    } else if ui.button("+").clicked() {
+                        input.begin_capture();
+                        self.capturing = Some(action.to_string());
+                    }
+                });
+                ui.end_row();
+            }
+        });
+        if ui.button("Restore defaults").clicked() {
+            *map = if own.is_some() { InputMap::default().pad_bindings() } else { InputMap::default() };
+            changed = true;
+        }
+        changed
+    }
+
+    fn gamepads(&mut self, ui: &mut egui::Ui, config: &mut Config, input: &mut InputHandler) -> bool {
+        if !cfg!(feature = "gamepad") {
+            ui.label("Gamepads need a build with the `gamepad` feature.");
+            return false;
+        }
+        let pads = &mut config.gamepad;
+        let mut changed = false;
+        let connected = input.pads.connected();
+        if connected.is_empty() {
+            ui.label("No gamepad connected.");
+        }
+        // Pads not plugged in stay listed while the settings name them.
+        let mut known = connected.clone();
+        known.extend(pads.device.iter().chain(pads.bindings.keys()).cloned());
+        known.sort();
+        known.dedup();
+        let label = |pad: &str| {
+            if connected.iter().any(|c| c == pad) { pad.to_string() } else { format!("{} (not connected)", pad) }
+        };
+
+        if connected.len() > 1 || pads.device.is_some() {
+            egui::ComboBox::from_label("Gamepad")
+                .selected_text(pads.device.as_deref().map_or("All connected pads".to_string(), label))
+                .show_ui(ui, |ui| {
+                    changed |= ui.selectable_value(&mut pads.device, None, "All connected pads").changed();
+                    for pad in &known {
+                        changed |= ui.selectable_value(&mut pads.device, Some(pad.clone()), label(pad)).changed();
+                    }
+                });
+        }
+        changed |= ui
+            .add(egui::Slider::new(&mut pads.deadzone, 0.05..=0.95).text("Stick deadzone"))
+            .on_hover_text("How far a stick must move before its bindings press.")
+            .changed();
+        egui::ComboBox::from_label("Stick as D-pad").selected_text(pads.dpad_stick.name()).show_ui(ui, |ui| {
+            for stick in DpadStick::ALL {
+                changed |= ui.selectable_value(&mut pads.dpad_stick, stick, stick.name()).changed();
+            }
+        });
+
+        if known.is_empty() {
+            return changed;
+        }
+        let shown = self.pad_profile.as_deref().map_or("Shared".to_string(), label);
+        egui::ComboBox::from_label("Bindings for").selected_text(shown).show_ui(ui, |ui| {
+            ui.selectable_value(&mut self.pad_profile, None, "Shared");
+            for pad in &known {
+                ui.selectable_value(&mut self.pad_profile, Some(pad.clone()), label(pad));
+            }
+        });
+        if let Some(pad) = &self.pad_profile {
+            if pads.bindings.contains_key(pad) {
+                if ui.button("Use the shared bindings").clicked() {
+                    pads.bindings.remove(pad);
+                    changed = true;
+                }
+            } else {
+                ui.label("This pad uses the shared bindings.");
+                if ui.button("Give this pad its own bindings").clicked() {
+                    pads.bindings.insert(pad.clone(), config.input.pad_bindings());
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+}
+
+fn profile_label(profile: ColorProfile) -> &'static str {
+    match profile {
+        ColorProfile::Raw => "Raw",
+        ColorProfile::GbaLcd => "GBA LCD",
+        ColorProfile::GbaSp => "GBA SP (AGS-101)",
+        ColorProfile::Nds => "Nintendo DS",
+    }
+}
+
+fn accuracy_label(accuracy: Accuracy) -> &'static str {
+    match accuracy {
+        Accuracy::Fast => "Whole frame at VBlank",
+        Accuracy::Accurate => "Per scanline",
+    }
+}
+
+fn path_row(ui: &mut egui::Ui, label: &str, path: &mut Option<PathBuf>, folder: bool) -> bool {
+    let mut changed = false;
+    ui.horizontal(|ui| {
+        ui.label(format!("{}:", label));
+        ui.label(path.as_ref().map_or("(none)".to_string(), |p| p.display().to_string()));
+        if ui.button("Browse...").clicked() {
+            let dialog = rfd::FileDialog::new().set_title(label);
+            let picked = if folder { dialog.pick_folder() } else { dialog.pick_file() };
+            if picked.is_some() {
+                *path = picked;
+                changed = true;
+            }
+        }
+        if path.is_some() && ui.button("Clear").clicked() {
+            *path = None;
+            changed = true;
+        }
+    });
+    changed
+}
@@ -0,0 +1,122 @@
+// Soak testing: runs a ROM without a window at full speed for a long time
+// and fails when the emulator panics, the picture stops changing (frozen
+// emulation) or the process keeps growing (a leak in the per-frame work).
+// Progress is printed at each sample so a CI log shows how far it got.
+
+use crate::config::Config;
+use crate::crash;
+use crate::gamedb::GameDb;
+use roba_core::audio::NullSink;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Frames between samples, 10 seconds of emulated time.
+const SAMPLE_FRAMES: u64 = 600;
+/// Samples with an unchanged frame before the emulation counts as frozen,
+/// 10 minutes of emulated time; long enough for static menus and cutscenes.
+const STUCK_SAMPLES: usize = 60;
+/// Samples to skip before watching memory, while caches and buffers fill.
+const WARMUP_SAMPLES: usize = 6;
+/// Growing samples in a row that count as a leak, and the least it must
+/// have grown by over them.
+const GROWTH_SAMPLES: usize = 30;
+const GROWTH_MIN_BYTES: u64 = 4 << 20;
+
+/// Runs `rom` for `duration` of host time.
+pub fn run(config: &Config, rom: &Path, bios: Option<PathBuf>, duration: Duration) -> Result<(), String> {
+    let mut core = roba_core::Emulator::new();
+    core.set_config(config.emulator_config());
+    if let Some(bios) = &bios {
+        core.load_bios(bios).map_err(|e| format!("Failed to load BIOS {:?}: {}", bios, e))?;
+    }
+    core.load_rom(rom).map_err(|e| format!("Failed to load ROM {:?}: {}", rom, e))?;
+    core.hard_reset();
+    GameDb::load().apply(&mut core);
+    // Mix audio as a frontend would, so its allocations are exercised too.
+    core.set_audio_sink(Box::new(NullSink));
+
+    let start = Instant::now();
+    let mut monitor = Monitor::default();
+    while start.elapsed() < duration {
+        for _ in 0..SAMPLE_FRAMES {
+            crash::guard(|| core.run_frame())
+                .map_err(|e| format!("Panic at frame {}: {}", core.frame_count(), e))?;
+            if let Some(instruction) = core.cpu_mut().take_undefined() {
+                return Err(format!("Undefined instruction at frame {}: {}", core.frame_count(), instruction));
+            }
+        }
+        let sample = Sample { frame_hash: core.frame_hash(), resident: resident_bytes() };
+        let elapsed = start.elapsed().as_secs_f64();
+        println!(
+            "{:>8.0}s  frame {:>9}  {:>6.1} fps  hash {:016X}  {}",
+            elapsed,
+            core.frame_count(),
+            core.frame_count() as f64 / elapsed,
+            sample.frame_hash,
+            sample.resident.map(|r| format!("{:.1} MiB", r as f64 / (1 << 20) as f64)).unwrap_or_default(),
+        );
+        monitor.push(sample).map_err(|e| format!("{} at frame {}", e, core.frame_count()))?;
+    }
+    println!("Soaked {} frames in {:.0}s without a failure", core.frame_count(), start.elapsed().as_secs_f64());
+    Ok(())
+}
+
+struct Sample {
+    frame_hash: u64,
+    resident: Option<u64>,
+}
+
+#[derive(Default)]
+struct Monitor {
+    samples: usize,
+    last_hash: Option<u64>,
+    same_hash: usize,
+    // Resident size at the start of the current growing run, the last one
+    // seen, and how many samples in a row it has grown.
+    growth_start: u64,
+    last_resident: u64,
+    growing: usize,
+}
+
+impl Monitor {
+    fn push(&mut self, sample: Sample) -> Result<(), String> {
+        self.samples += 1;
+        if self.last_hash == Some(sample.frame_hash) {
+            self.same_hash += 1;
+            if self.same_hash >= STUCK_SAMPLES {
+                return Err(format!("The frame has not changed in {} samples", self.same_hash));
+            }
+        } else {
+            self.same_hash = 0;
+        }
+        self.last_hash = Some(sample.frame_hash);
+
+        let Some(resident) = sample.resident.filter(|_| self.samples > WARMUP_SAMPLES) else {
+            return Ok(());
+        };
+        if resident > self.last_resident && self.growing > 0 {
+            self.growing += 1;
+        } else {
+            self.growing = 1;
+            self.growth_start = resident;
+        }
+        self.last_resident = resident;
+        let grown = resident - self.growth_start;
+        if self.growing > GROWTH_SAMPLES && grown >= GROWTH_MIN_BYTES {
+            return Err(format!("Memory grew in each of the last {} samples, by {} KiB", self.growing - 1, grown >> 10));
+        }
+        Ok(())
+    }
+}
+
+// Resident set size of this process, where the platform makes it cheap to
+// read.
+#[cfg(target_os = "linux")]
+fn resident_bytes() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(pages * 4096)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn resident_bytes() -> Option<u64> { None }
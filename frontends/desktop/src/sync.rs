@@ -0,0 +1,182 @@
+// Frame pacing: which clock decides when the next frame is emulated, and
+// dynamic rate control that nudges the audio sample rate so the output
+// buffer neither underruns nor slowly fills during long sessions. Waits
+// between frames sleep; nothing spins a core while paced.
+
+use roba_core::audio::{ring, RingReader};
+use roba_core::Emulator;
+use serde::{Deserialize, Serialize};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The GBA's refresh rate: 280,896 cycles per frame at 16.78 MHz.
+pub const GBA_FPS: f64 = 16_777_216.0 / 280_896.0;
+pub const OUTPUT_RATE: u32 = 48_000;
+// Frames emulated at most per repaint when catching up after a stall.
+const MAX_CATCH_UP: usize = 4;
+// Audio buffer fill the pacing and rate control aim for.
+const TARGET_FILL: f64 = 0.5;
+// Shortest interval between VSync'd repaints on any real display; quicker
+// ones mean VSync is not blocking (a minimized window, or a driver forcing
+// it off), and video sync paces by the system clock instead of spinning.
+const MIN_VSYNC_INTERVAL: Duration = Duration::from_millis(4);
+// How long video sync keeps to the system clock before trying VSync again.
+const VSYNC_RETRY: Duration = Duration::from_secs(2);
+/// Repaint delays shorter than this are slept off on the UI thread, as the
+/// event loop's timers can fire a few milliseconds late.
+pub const PRECISE_SLEEP: Duration = Duration::from_millis(3);
+// Tail of a precise sleep spent yielding rather than trusting the OS sleep
+// to wake up in time.
+const SPIN_MARGIN: Duration = Duration::from_micros(500);
+
+fn frame_time() -> Duration { Duration::from_secs_f64(1.0 / GBA_FPS) }
+
+/// Sleeps for `delay`, waking within microseconds of it: the OS sleep
+/// covers all but the last `SPIN_MARGIN`, which yields the thread instead.
+pub fn precise_sleep(delay: Duration) {
+    let deadline = Instant::now() + delay;
+    if let Some(coarse) = delay.checked_sub(SPIN_MARGIN) {
+        thread::sleep(coarse);
+    }
+    while Instant::now() < deadline {
+        thread::yield_now();
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum SyncMode {
+    /// Emulate whenever the audio buffer drains below half full; the display
+    /// shows the newest frame.
+    Audio,
+    /// One emulated frame per display refresh, with VSync. Meant for ~60 Hz
+    /// displays; rate control absorbs the difference to 59.73 Hz. Paces by
+    /// the system clock while VSync does not block.
+    #[default]
+    Video,
+    /// Emulate at 59.73 Hz by the system clock, ignoring display and audio.
+    FreeRun,
+}
+
+impl SyncMode {
+    pub const ALL: [SyncMode; 3] = [SyncMode::Audio, SyncMode::Video, SyncMode::FreeRun];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SyncMode::Audio => "Sync to audio",
+            SyncMode::Video => "Sync to video (VSync)",
+            SyncMode::FreeRun => "Free-run",
+        }
+    }
+
+    /// Whether the window is created with VSync; fixed at startup.
+    pub fn vsync(self) -> bool { self == SyncMode::Video }
+}
+
+/// Latency buffer between emulation and the audio device: the core's sample
+/// ring. There is no output device yet, so playback is modelled by draining
+/// the ring at the output rate in real time; pacing and rate control behave
+/// as they will with real output.
+pub struct AudioBuffer {
+    reader: RingReader,
+    last_drain: Option<Instant>,
+    // Fraction of a sample the device has played but not yet taken.
+    owed: f64,
+}
+
+impl AudioBuffer {
+    /// Installs a ring holding `latency_ms` of audio as the core's sink.
+    pub fn attach(core: &mut Emulator, output_rate: u32, latency_ms: u32) -> Self {
+        let (sink, reader) = ring(output_rate, Duration::from_millis(latency_ms as u64));
+        core.set_audio_sink(Box::new(sink));
+        Self { reader, last_drain: None, owed: 0.0 }
+    }
+
+    /// Fraction of the buffer holding samples not yet played.
+    pub fn fill(&self) -> f64 { self.reader.queued() as f64 / self.reader.capacity() as f64 }
+
+    /// Steers the core's output rate by `ratio`, the resampling the rate
+    /// control asks for.
+    pub fn set_ratio(&self, core: &mut Emulator, ratio: f64) {
+        core.apu_mut().set_sample_rate((self.reader.sample_rate() as f64 * ratio).round() as u32);
+    }
+
+    /// Removes what the device played since the last call.
+    pub fn drain(&mut self, now: Instant) {
+        if let Some(last) = self.last_drain {
+            self.owed += (now - last).as_secs_f64() * self.output_rate();
+            let played = self.owed.floor();
+            self.owed -= played;
+            self.reader.skip(played as usize);
+        }
+        self.last_drain = Some(now);
+    }
+
+    fn output_rate(&self) -> f64 { self.reader.sample_rate() as f64 }
+
+    fn time_until_fill(&self, fill: f64) -> Duration {
+        let excess = self.reader.queued() as f64 - fill * self.reader.capacity() as f64;
+        Duration::from_secs_f64((excess / self.output_rate()).max(0.0))
+    }
+}
+
+/// Resampling ratio that steers the buffer back to half full, changing the
+/// pitch by at most `max_delta` (0.005 is inaudible).
+pub fn rate_adjust(fill: f64, max_delta: f64) -> f64 {
+    1.0 + max_delta * (1.0 - fill.clamp(0.0, 1.0) / TARGET_FILL)
+}
+
+/// Decides how many frames each repaint emulates and when to repaint next.
+#[derive(Default)]
+pub struct FramePacer {
+    last: Option<Instant>,
+    // Frames the system clock is owed in free-run mode.
+    owed: f64,
+    // Since when video sync has found VSync not blocking.
+    vsync_failed: Option<Instant>,
+}
+
+impl FramePacer {
+    pub fn frames_due(&mut self, mode: SyncMode, now: Instant, audio: &AudioBuffer) -> usize {
+        let interval = self.last.map(|last| now - last);
+        let elapsed = interval.map_or(0.0, |i| i.as_secs_f64());
+        self.last = Some(now);
+        match mode {
+            SyncMode::Video => {
+                match self.vsync_failed {
+                    Some(since) if now - since >= VSYNC_RETRY => self.vsync_failed = None,
+                    Some(_) => {}
+                    None if interval.is_some_and(|i| i < MIN_VSYNC_INTERVAL) => {
+                        log::debug!("VSync is not blocking; pacing video sync by the system clock");
+                        self.vsync_failed = Some(now);
+                    }
+                    None => {}
+                }
+                1
+            }
+            SyncMode::FreeRun => {
+                self.owed = (self.owed + elapsed * GBA_FPS).min(MAX_CATCH_UP as f64);
+                let frames = self.owed.floor();
+                self.owed -= frames;
+                frames as usize
+            }
+            SyncMode::Audio => {
+                let samples_per_frame = audio.output_rate() / GBA_FPS;
+                let queued = audio.reader.queued() as f64;
+                let missing = (TARGET_FILL * audio.reader.capacity() as f64 - queued) / samples_per_frame;
+                (missing.ceil().max(0.0) as usize).min(MAX_CATCH_UP)
+            }
+        }
+    }
+
+    /// Delay before the next repaint; None repaints on the next VSync.
+    pub fn next_repaint(&self, mode: SyncMode, audio: &AudioBuffer) -> Option<Duration> {
+        match mode {
+            SyncMode::Video => self.vsync_failed.map(|_| {
+                let elapsed = self.last.map_or(Duration::ZERO, |last| last.elapsed());
+                frame_time().saturating_sub(elapsed)
+            }),
+            SyncMode::FreeRun => Some(Duration::from_secs_f64((1.0 - self.owed) / GBA_FPS)),
+            SyncMode::Audio => Some(audio.time_until_fill(TARGET_FILL)),
+        }
+    }
+}
@@ -0,0 +1,171 @@
+// On-screen controls for touchscreens: a D-pad, A/B, L/R and Start/Select
+// drawn over the game image. Every finger counts, so the D-pad and a face
+// button can be held together; the mouse presses them too, which makes the
+// layout usable without a touchscreen.
+
+use eframe::egui;
+use roba_core::input::KeyState;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Outside this fraction of the D-pad radius the other axis counts too, which
+// splits the pad into eight equal sectors (sin 22.5°).
+const DIAGONAL: f32 = 0.38;
+// Touches this close to the D-pad centre press nothing.
+const DEAD_ZONE: f32 = 0.25;
+// Buttons take touches a little outside what is drawn.
+const SLOP: f32 = 1.2;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct TouchConfig {
+    pub enabled: bool,
+    /// 0.0 (invisible, still pressable) to 1.0.
+    pub opacity: f32,
+}
+
+impl Default for TouchConfig {
+    fn default() -> Self { Self { enabled: false, opacity: 0.5 } }
+}
+
+enum Shape {
+    Circle(egui::Pos2, f32),
+    Rect(egui::Rect),
+}
+
+impl Shape {
+    fn hit(&self, pos: egui::Pos2) -> bool {
+        match *self {
+            Shape::Circle(center, radius) => center.distance(pos) <= radius * SLOP,
+            Shape::Rect(rect) => rect.expand(rect.height() * (SLOP - 1.0)).contains(pos),
+        }
+    }
+}
+
+// Control positions for one image size, scaled to its shorter side so they
+// stay thumb-sized in both orientations.
+struct Layout {
+    dpad: (egui::Pos2, f32),
+    buttons: [(&'static str, KeyState, Shape); 6],
+}
+
+impl Layout {
+    fn new(area: egui::Rect) -> Self {
+        let u = area.height().min(area.width() / 1.5) / 8.0;
+        let (bottom_left, bottom_right) = (area.left_bottom(), area.right_bottom());
+        let bar = |x: f32, y: f32, w: f32, h: f32| Shape::Rect(egui::Rect::from_min_size(egui::pos2(x, y), egui::vec2(w, h)));
+        Self {
+            dpad: (bottom_left + egui::vec2(2.0 * u, -2.2 * u), 1.6 * u),
+            buttons: [
+                ("A", KeyState::A, Shape::Circle(bottom_right + egui::vec2(-1.2 * u, -2.8 * u), 0.75 * u)),
+                ("B", KeyState::B, Shape::Circle(bottom_right + egui::vec2(-2.9 * u, -1.9 * u), 0.75 * u)),
+                ("L", KeyState::L, bar(area.left() + 0.3 * u, area.top() + 0.3 * u, 2.6 * u, 0.9 * u)),
+                ("R", KeyState::R, bar(area.right() - 2.9 * u, area.top() + 0.3 * u, 2.6 * u, 0.9 * u)),
+                ("Select", KeyState::SELECT, bar(area.center().x - 1.5 * u, area.bottom() - 0.9 * u, 1.3 * u, 0.55 * u)),
+                ("Start", KeyState::START, bar(area.center().x + 0.2 * u, area.bottom() - 0.9 * u, 1.3 * u, 0.55 * u)),
+            ],
+        }
+    }
+
+    fn dpad_keys(&self, pos: egui::Pos2) -> KeyState {
+        let (center, radius) = self.dpad;
+        let d = pos - center;
+        let len = d.length();
+        let mut keys = KeyState::NONE;
+        if len > radius * SLOP || len < radius * DEAD_ZONE {
+            return keys;
+        }
+        keys.set(KeyState::RIGHT, d.x > DIAGONAL * len);
+        keys.set(KeyState::LEFT, d.x < -DIAGONAL * len);
+        keys.set(KeyState::DOWN, d.y > DIAGONAL * len);
+        keys.set(KeyState::UP, d.y < -DIAGONAL * len);
+        keys
+    }
+
+    fn keys_at(&self, pos: egui::Pos2) -> KeyState {
+        self.buttons
+            .iter()
+            .filter(|(_, _, shape)| shape.hit(pos))
+            .fold(self.dpad_keys(pos), |keys, &(_, key, _)| keys | key)
+    }
+}
+
+#[derive(Default)]
+pub struct TouchControls {
+    // Fingers down, by (device, touch id).
+    touches: BTreeMap<(u64, u64), egui::Pos2>,
+    // Game image the controls were last drawn over; touches are tested
+    // against it.
+    area: Option<egui::Rect>,
+    held: KeyState,
+}
+
+impl TouchControls {
+    /// Buttons under the fingers and the pressed mouse this frame.
+    pub fn poll(&mut self, ctx: &egui::Context) -> KeyState {
+        let mouse = ctx.input(|input| {
+            for event in &input.events {
+                if let egui::Event::Touch { device_id, id, phase, pos, .. } = *event {
+                    match phase {
+                        egui::TouchPhase::Start | egui::TouchPhase::Move => {
+                            self.touches.insert((device_id.0, id.0), pos);
+                        }
+                        egui::TouchPhase::End | egui::TouchPhase::Cancel => {
+                            self.touches.remove(&(device_id.0, id.0));
+                        }
+                    }
+                }
+            }
+            input.pointer.interact_pos().filter(|_| input.pointer.primary_down())
+        });
+        self.held = match self.area {
+            Some(area) => {
+                let layout = Layout::new(area);
+                self.touches.values().copied().chain(mouse).fold(KeyState::NONE, |keys, pos| keys | layout.keys_at(pos))
+            }
+            None => KeyState::NONE,
+        };
+        self.held
+    }
+
+    /// Draws the controls over `image`, which later polls test against.
+    pub fn paint(&mut self, ui: &egui::Ui, image: egui::Rect, opacity: f32) {
+        self.area = Some(image);
+        let layout = Layout::new(image);
+        let painter = ui.painter_at(image);
+        let idle = egui::Color32::from_gray(40).gamma_multiply(opacity);
+        let fill = |key: KeyState| {
+            if self.held.contains(key) { egui::Color32::from_gray(200).gamma_multiply(opacity) } else { idle }
+        };
+        let text = egui::Color32::WHITE.gamma_multiply(opacity);
+        let font = egui::FontId::proportional(layout.dpad.1 * 0.35);
+
+        let (center, radius) = layout.dpad;
+        let arm = radius * 0.36;
+        for (key, offset) in [
+            (KeyState::UP, egui::vec2(0.0, -1.0)),
+            (KeyState::DOWN, egui::vec2(0.0, 1.0)),
+            (KeyState::LEFT, egui::vec2(-1.0, 0.0)),
+            (KeyState::RIGHT, egui::vec2(1.0, 0.0)),
+        ] {
+            let size = if offset.x == 0.0 { egui::vec2(arm * 2.0, radius - arm) } else { egui::vec2(radius - arm, arm * 2.0) };
+            let rect = egui::Rect::from_center_size(center + offset * (radius + arm) / 2.0, size);
+            painter.rect_filled(rect, arm * 0.3, fill(key));
+        }
+        painter.rect_filled(egui::Rect::from_center_size(center, egui::vec2(arm * 2.0, arm * 2.0)), 0.0, idle);
+
+        for (label, key, shape) in &layout.buttons {
+            let at = match *shape {
+                Shape::Circle(c, r) => {
+                    painter.circle_filled(c, r, fill(*key));
+                    c
+                }
+                Shape::Rect(rect) => {
+                    painter.rect_filled(rect, rect.height() / 2.0, fill(*key));
+                    rect.center()
+                }
+            };
+            painter.text(at, egui::Align2::CENTER_CENTER, label, font.clone(), text);
+        }
+    }
+}
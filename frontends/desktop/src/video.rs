@@ -0,0 +1,194 @@
+// Presents the emulated frame: optional post-processing on the RGBA buffer,
+// texture upload, and sizing the image to the window.
+
+use eframe::egui;
+use roba_core::video::{ColorProfile, GBA_SCREEN_H, GBA_SCREEN_W};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum ScaleMode {
+    /// `VideoConfig::scale` times the native size.
+    Fixed,
+    /// Largest whole multiple that fits the window.
+    Integer,
+    /// Fills the window while keeping the 3:2 aspect ratio.
+    #[default]
+    Fit,
+    Stretch,
+}
+
+impl ScaleMode {
+    pub const ALL: [ScaleMode; 4] =
+        [ScaleMode::Fixed, ScaleMode::Integer, ScaleMode::Fit, ScaleMode::Stretch];
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum Shader {
+    #[default]
+    None,
+    /// Dark gaps between subpixel cells, like the unlit AGB screen.
+    LcdGrid,
+    Scanlines,
+}
+
+impl Shader {
+    pub const ALL: [Shader; 3] = [Shader::None, Shader::LcdGrid, Shader::Scanlines];
+
+    // Texture upscale the effect needs to be visible.
+    fn upscale(self) -> usize {
+        match self {
+            Shader::None => 1,
+            Shader::LcdGrid => 3,
+            Shader::Scanlines => 2,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct VideoConfig {
+    pub scale_mode: ScaleMode,
+    pub scale: f32,
+    /// Bilinear instead of nearest-neighbour filtering.
+    pub smooth: bool,
+    pub shader: Shader,
+    /// Applied by the core when converting the frame; see `ColorProfile`.
+    #[serde(with = "color_profile")]
+    pub color_profile: ColorProfile,
+    pub fullscreen: bool,
+    /// Draws FPS and frame time over the game; see `PerfMonitor`.
+    pub perf_overlay: bool,
+}
+
+impl Default for VideoConfig {
+    fn default() -> Self {
+        Self {
+            scale_mode: ScaleMode::default(),
+            scale: 2.0,
+            smooth: false,
+            shader: Shader::default(),
+            color_profile: ColorProfile::default(),
+            fullscreen: false,
+            perf_overlay: false,
+        }
+    }
+}
+
+impl VideoConfig {
+    fn texture_options(&self) -> egui::TextureOptions {
+        if self.smooth { egui::TextureOptions::LINEAR } else { egui::TextureOptions::NEAREST }
+    }
+
+    /// On-screen size of the frame within `available` space.
+    pub fn display_size(&self, available: egui::Vec2) -> egui::Vec2 {
+        let native = egui::vec2(GBA_SCREEN_W as f32, GBA_SCREEN_H as f32);
+        let fit = (available.x / native.x).min(available.y / native.y);
+        match self.scale_mode {
+            ScaleMode::Fixed => native * self.scale,
+            ScaleMode::Integer => native * fit.floor().max(1.0),
+            ScaleMode::Fit => native * fit.max(0.0),
+            ScaleMode::Stretch => available,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct VideoOutput {
+    texture: Option<egui::TextureHandle>,
+    buffer: Vec<u8>,
+    // The frame and settings behind the current texture, so repaints that
+    // show the same frame skip building and uploading a new image.
+    uploaded: Vec<u8>,
+    uploaded_with: Option<(Shader, egui::TextureOptions)>,
+}
+
+impl VideoOutput {
+    pub fn new() -> Self { Self::default() }
+
+    /// Post-processes `rgba` (a native-size frame) and uploads it.
+    pub fn upload(&mut self, ctx: &egui::Context, rgba: &[u8], config: &VideoConfig) {
+        let shader = config.shader;
+        let options = config.texture_options();
+        if self.texture.is_some() && self.uploaded_with == Some((shader, options)) && self.uploaded == rgba {
+            return;
+        }
+        self.uploaded.clear();
+        self.uploaded.extend_from_slice(rgba);
+        self.uploaded_with = Some((shader, options));
+
+        let factor = shader.upscale();
+        let (w, h) = (GBA_SCREEN_W * factor, GBA_SCREEN_H * factor);
+        self.buffer.resize(w * h * 4, 0);
+        upscale(rgba, &mut self.buffer, factor);
+        match shader {
+            Shader::None => {}
+            Shader::LcdGrid => shade_cells(&mut self.buffer, w, factor, |x, y| {
+                if x == factor - 1 || y == factor - 1 { 0.55 } else { 1.0 }
+            }),
+            Shader::Scanlines => shade_cells(&mut self.buffer, w, factor, |_, y| {
+                if y == factor - 1 { 0.6 } else { 1.0 }
+            }),
+        }
+
+        let image = egui::ColorImage::from_rgba_unmultiplied([w, h], &self.buffer);
+        match &mut self.texture {
+            Some(texture) => texture.set(image, options),
+            None => self.texture = Some(ctx.load_texture("framebuffer", image, options)),
+        }
+    }
+
+    /// Draws the last uploaded frame, centered in the remaining space, and
+    /// returns where it was drawn.
+    pub fn show(&self, ui: &mut egui::Ui, config: &VideoConfig) -> Option<egui::Rect> {
+        let texture = self.texture.as_ref()?;
+        let size = config.display_size(ui.available_size());
+        let response = ui.vertical_centered(|ui| ui.image((texture.id(), size)));
+        Some(response.inner.rect)
+    }
+}
+
+fn upscale(src: &[u8], dst: &mut [u8], factor: usize) {
+    if factor == 1 {
+        dst.copy_from_slice(src);
+        return;
+    }
+    let dst_w = GBA_SCREEN_W * factor;
+    for (i, px) in src.chunks_exact(4).enumerate() {
+        let (x, y) = (i % GBA_SCREEN_W, i / GBA_SCREEN_W);
+        for dy in 0..factor {
+            let row = (y * factor + dy) * dst_w;
+            for dx in 0..factor {
+                let o = (row + x * factor + dx) * 4;
+                dst[o..o + 4].copy_from_slice(px);
+            }
+        }
+    }
+}
+
+// Scales each pixel by `weight(x, y)` of its position inside its source cell.
+fn shade_cells(buf: &mut [u8], width: usize, factor: usize, weight: impl Fn(usize, usize) -> f32) {
+    for (i, px) in buf.chunks_exact_mut(4).enumerate() {
+        let w = weight(i % width % factor, i / width % factor);
+        if w < 1.0 {
+            for c in &mut px[..3] {
+                *c = (*c as f32 * w) as u8;
+            }
+        }
+    }
+}
+
+// Stores the profile by its core name, e.g. `color_profile = "gba-sp"`.
+mod color_profile {
+    use roba_core::video::ColorProfile;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(profile: &ColorProfile, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(profile.name())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<ColorProfile, D::Error> {
+        let name = String::deserialize(d)?;
+        ColorProfile::from_name(&name)
+            .ok_or_else(|| de::Error::custom(format!("unknown color profile {:?}", name)))
+    }
+}
@@ -0,0 +1,145 @@
+// Homebrew debugging section of the debug panel: the symbols loaded with the
+// ROM (its `.elf` or no$gba `.sym` file) and watch expressions, evaluated
+// after every emulated frame with values named by the symbol they point into.
+
+use eframe::egui;
+use roba_core::debug::{SymbolTable, WatchExpr};
+use roba_core::Emulator;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const CHANGED_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 200, 100);
+const ERROR_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 100, 100);
+
+pub const SYMBOL_EXTENSIONS: [&str; 2] = ["elf", "sym"];
+
+struct Watch {
+    expr: WatchExpr,
+    value: u32,
+    changed: bool,
+}
+
+#[derive(Default)]
+pub struct WatchView {
+    symbols: SymbolTable,
+    symbols_path: Option<PathBuf>,
+    watches: Vec<Watch>,
+    pc: u32,
+    input: String,
+    error: Option<String>,
+}
+
+impl WatchView {
+    /// Loads the symbols next to `rom`, `game.elf` or `game.sym` for
+    /// `game.gba`, dropping those of the previous game.
+    pub fn load_for_rom(&mut self, rom: &Path) {
+        self.symbols = SymbolTable::new();
+        self.symbols_path = None;
+        if let Some(path) = SYMBOL_EXTENSIONS.iter().map(|ext| rom.with_extension(ext)).find(|p| p.is_file()) {
+            self.load_symbols(&path);
+        }
+    }
+
+    pub fn load_symbols(&mut self, path: &Path) {
+        let result = fs::read(path).map_err(|e| e.to_string());
+        match result.and_then(|data| SymbolTable::parse(&data).map_err(|e| e.to_string())) {
+            Ok(symbols) => {
+                log::info!("Loaded {} symbols from {:?}", symbols.len(), path);
+                self.symbols = symbols;
+                self.symbols_path = Some(path.to_path_buf());
+                self.reparse();
+            }
+            Err(e) => log::error!("Failed to load symbols from {:?}: {}", path, e),
+        }
+    }
+
+    // Symbol names are resolved when a watch is parsed; watches naming a
+    // symbol the new table lacks keep their old address.
+    fn reparse(&mut self) {
+        for watch in &mut self.watches {
+            if let Ok(expr) = WatchExpr::parse(watch.expr.text(), &self.symbols) {
+                watch.expr = expr;
+            }
+        }
+    }
+
+    /// Evaluates the watches; call after every emulated frame.
+    pub fn update(&mut self, core: &mut Emulator) {
+        self.pc = core.cpu().pc();
+        for watch in &mut self.watches {
+            let value = watch.expr.eval(core);
+            watch.changed = value != watch.value;
+            watch.value = value;
+        }
+    }
+
+    fn symbol_label(&self, ui: &mut egui::Ui, addr: u32) {
+        if let Some(name) = self.symbols.describe(addr) {
+            ui.label(name);
+        } else {
+            ui.label("");
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, core: &mut Emulator) {
+        ui.horizontal(|ui| {
+            match &self.symbols_path {
+                Some(path) => {
+                    let name = path.file_name().unwrap_or_default().to_string_lossy();
+                    ui.label(format!("Symbols: {} ({})", name, self.symbols.len()));
+                }
+                None => {
+                    ui.label("Symbols: none");
+                }
+            }
+            if ui.button("Load...").clicked()
+                && let Some(path) = rfd::FileDialog::new()
+                    .set_title("Load Symbols")
+                    .add_filter("ELF image or no$gba symbols", &SYMBOL_EXTENSIONS)
+                    .pick_file()
+            {
+                self.load_symbols(&path);
+            }
+        });
+
+        let mut remove = None;
+        egui::Grid::new("watches").striped(true).show(ui, |ui| {
+            ui.label("pc");
+            ui.label(egui::RichText::new(format!("{:08X}", self.pc)).monospace());
+            self.symbol_label(ui, self.pc);
+            ui.end_row();
+            for (i, watch) in self.watches.iter().enumerate() {
+                let color = if watch.changed { CHANGED_COLOR } else { ui.visuals().text_color() };
+                ui.label(watch.expr.text()).on_hover_text(format!("{} ({})", watch.value, watch.value as i32));
+                ui.colored_label(color, egui::RichText::new(format!("{:08X}", watch.value)).monospace());
+                self.symbol_label(ui, watch.value);
+                if ui.small_button("x").on_hover_text("Remove watch").clicked() {
+                    remove = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(i) = remove {
+            self.watches.remove(i);
+        }
+
+        ui.horizontal(|ui| {
+            let field = ui.add(egui::TextEdit::singleline(&mut self.input).hint_text("[0x03001234]+r2").desired_width(180.0));
+            let entered = field.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if (ui.button("Watch").clicked() || entered) && !self.input.trim().is_empty() {
+                match WatchExpr::parse(self.input.trim(), &self.symbols) {
+                    Ok(expr) => {
+                        let value = expr.eval(core);
+                        self.watches.push(Watch { expr, value, changed: false });
+                        self.input.clear();
+                        self.error = None;
+                    }
+                    Err(e) => self.error = Some(e.to_string()),
+                }
+            }
+        });
+        if let Some(error) = &self.error {
+            ui.colored_label(ERROR_COLOR, error);
+        }
+    }
+}
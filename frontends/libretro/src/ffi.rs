@@ -0,0 +1,83 @@
+// The parts of libretro.h this core uses, transcribed by hand. Names follow
+// the header with the `retro_`/`RETRO_` prefixes dropped.
+
+use std::ffi::{c_char, c_void};
+
+pub const API_VERSION: u32 = 1;
+
+pub const ENVIRONMENT_GET_SYSTEM_DIRECTORY: u32 = 9;
+pub const ENVIRONMENT_SET_PIXEL_FORMAT: u32 = 10;
+pub const ENVIRONMENT_SET_INPUT_DESCRIPTORS: u32 = 11;
+
+pub const PIXEL_FORMAT_RGB565: u32 = 2;
+
+pub const DEVICE_JOYPAD: u32 = 1;
+
+pub const DEVICE_ID_JOYPAD_B: u32 = 0;
+pub const DEVICE_ID_JOYPAD_SELECT: u32 = 2;
+pub const DEVICE_ID_JOYPAD_START: u32 = 3;
+pub const DEVICE_ID_JOYPAD_UP: u32 = 4;
+pub const DEVICE_ID_JOYPAD_DOWN: u32 = 5;
+pub const DEVICE_ID_JOYPAD_LEFT: u32 = 6;
+pub const DEVICE_ID_JOYPAD_RIGHT: u32 = 7;
+pub const DEVICE_ID_JOYPAD_A: u32 = 8;
+pub const DEVICE_ID_JOYPAD_L: u32 = 10;
+pub const DEVICE_ID_JOYPAD_R: u32 = 11;
+
+pub const REGION_NTSC: u32 = 0;
+
+pub const MEMORY_SAVE_RAM: u32 = 0;
+
+pub type EnvironmentFn = unsafe extern "C" fn(cmd: u32, data: *mut c_void) -> bool;
+pub type VideoRefreshFn = unsafe extern "C" fn(data: *const c_void, width: u32, height: u32, pitch: usize);
+pub type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+pub type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type InputPollFn = unsafe extern "C" fn();
+pub type InputStateFn = unsafe extern "C" fn(port: u32, device: u32, index: u32, id: u32) -> i16;
+
+#[repr(C)]
+pub struct SystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct GameGeometry {
+    pub base_width: u32,
+    pub base_height: u32,
+    pub max_width: u32,
+    pub max_height: u32,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct SystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct SystemAvInfo {
+    pub geometry: GameGeometry,
+    pub timing: SystemTiming,
+}
+
+#[repr(C)]
+pub struct GameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+#[repr(C)]
+pub struct InputDescriptor {
+    pub port: u32,
+    pub device: u32,
+    pub index: u32,
+    pub id: u32,
+    pub description: *const c_char,
+}
@@ -0,0 +1,479 @@
+// libretro core around `roba_core::Emulator`, so RoBA can be loaded by
+// RetroArch and other libretro frontends. Video goes out as RGB565, converted
+// straight from the PPU's BGR555 framebuffer. Audio is whatever the APU
+// mixed during the frame, at its sample rate; frontends pace frames by it.
+
+use std::ffi::{c_char, c_void, CStr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use roba_core::archive;
+use roba_core::audio::{ring, RingReader, StereoSample};
+use roba_core::cheats::{Cheat, CheatFormat};
+use roba_core::error::CoreError;
+use roba_core::input::KeyState;
+use roba_core::video::{Frame, FramePixels, FrameSink, PixelFormat, GBA_SCREEN_H, GBA_SCREEN_W};
+use roba_core::Emulator;
+
+pub mod ffi;
+
+use ffi::{
+    AudioSampleBatchFn, AudioSampleFn, EnvironmentFn, GameInfo, InputDescriptor, InputPollFn, InputStateFn,
+    SystemAvInfo, SystemInfo, VideoRefreshFn,
+};
+
+// 280 896 cycles per frame (228 lines of 1232).
+const FPS: f64 = 16_777_216.0 / 280_896.0;
+const BIOS_FILE: &str = "gba_bios.bin";
+// 128 KiB flash, the largest backup chip.
+const SAVE_RAM_MAX: usize = 0x2_0000;
+
+/// libretro joypad buttons and the GBA keys they press.
+pub const JOYPAD_MAP: [(u32, KeyState); 10] = [
+    (ffi::DEVICE_ID_JOYPAD_A, KeyState::A),
+    (ffi::DEVICE_ID_JOYPAD_B, KeyState::B),
+    (ffi::DEVICE_ID_JOYPAD_SELECT, KeyState::SELECT),
+    (ffi::DEVICE_ID_JOYPAD_START, KeyState::START),
+    (ffi::DEVICE_ID_JOYPAD_RIGHT, KeyState::RIGHT),
+    (ffi::DEVICE_ID_JOYPAD_LEFT, KeyState::LEFT),
+    (ffi::DEVICE_ID_JOYPAD_UP, KeyState::UP),
+    (ffi::DEVICE_ID_JOYPAD_DOWN, KeyState::DOWN),
+    (ffi::DEVICE_ID_JOYPAD_R, KeyState::R),
+    (ffi::DEVICE_ID_JOYPAD_L, KeyState::L),
+];
+
+/// Keys held, given whether each libretro joypad button is pressed.
+pub fn keys_from_joypad(pressed: impl Fn(u32) -> bool) -> KeyState {
+    JOYPAD_MAP.iter().filter(|&&(id, _)| pressed(id)).fold(KeyState::NONE, |keys, &(_, key)| keys | key)
+}
+
+pub fn bgr555_to_rgb565(px: u16) -> u16 {
+    let r = px & 0x1F;
+    let g = (px >> 5) & 0x1F;
+    let b = (px >> 10) & 0x1F;
+    // Green gets a sixth bit; repeat its top bit so white stays white.
+    r << 11 | g << 6 | (g >> 4) << 5 | b
+}
+
+/// Keeps the last frame as RGB565 for `retro_run` to hand to the frontend.
+/// Asking for BGR555 also spares the core its RGBA conversion.
+struct Rgb565Sink(Arc<Mutex<Vec<u16>>>);
+
+impl FrameSink for Rgb565Sink {
+    fn format(&self) -> PixelFormat { PixelFormat::Bgr555 }
+
+    fn present(&mut self, frame: Frame<'_>) {
+        let FramePixels::Bgr555(pixels) = frame.pixels else { return };
+        let mut out = lock(&self.0);
+        for (row, dst) in out.chunks_exact_mut(frame.width).take(frame.height).enumerate() {
+            let src = &pixels[row * frame.stride..][..frame.width];
+            for (d, &s) in dst.iter_mut().zip(src) {
+                *d = bgr555_to_rgb565(s);
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample: Option<AudioSampleFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+struct Core {
+    emu: Emulator,
+    video: Arc<Mutex<Vec<u16>>>,
+    // Handed to the frontend by `retro_get_memory_data`, which writes the
+    // .srm file into it after loading the game and reads it back to save.
+    // Allocated once for the largest chip, as the frontend keeps the pointer
+    // while the backup size may still change (EEPROM is sized by use).
+    save_ram: Box<[u8]>,
+    save_ram_len: usize,
+    save_ram_loaded: bool,
+    audio: RingReader,
+    samples: Vec<StereoSample>,
+}
+
+impl Core {
+    fn new(mut emu: Emulator) -> Self {
+        let video = Arc::new(Mutex::new(vec![0u16; GBA_SCREEN_W * GBA_SCREEN_H]));
+        emu.set_frame_sink(Box::new(Rgb565Sink(video.clone())));
+        // Sent on every frame; a quarter second is far more than one holds.
+        let (sink, audio) = ring(emu.apu().sample_rate(), Duration::from_millis(250));
+        emu.set_audio_sink(Box::new(sink));
+        let mut core = Self {
+            emu,
+            video,
+            save_ram: vec![0; SAVE_RAM_MAX].into_boxed_slice(),
+            save_ram_len: 0,
+            save_ram_loaded: false,
+            audio,
+            samples: Vec::new(),
+        };
+        let data = core.emu.battery_save();
+        core.sync_save_ram(&data);
+        core
+    }
+
+    fn run_frame(&mut self, callbacks: &Callbacks) {
+        // The frontend fills `save_ram` between `retro_load_game` and the
+        // first `retro_run`.
+        if !self.save_ram_loaded {
+            if self.save_ram_len != 0
+                && let Err(e) = self.emu.load_battery_save(&self.save_ram[..self.save_ram_len])
+            {
+                log::warn!("Ignoring save RAM: {}", e);
+            }
+            self.save_ram_loaded = true;
+        }
+
+        let keys = match callbacks.input_state {
+            // SAFETY: the frontend's callback, called as libretro.h documents.
+            Some(state) => keys_from_joypad(|id| unsafe { state(0, ffi::DEVICE_JOYPAD, 0, id) } != 0),
+            None => KeyState::NONE,
+        };
+        self.emu.set_keys(keys);
+        self.emu.run_frame();
+
+        if let Some(data) = self.emu.take_dirty_battery_save() {
+            self.sync_save_ram(&data);
+        }
+
+        if let Some(video_refresh) = callbacks.video_refresh {
+            let frame = lock(&self.video);
+            // SAFETY: `frame` holds 240x160 pixels and outlives the call.
+            unsafe {
+                video_refresh(
+                    frame.as_ptr().cast(),
+                    GBA_SCREEN_W as u32,
+                    GBA_SCREEN_H as u32,
+                    GBA_SCREEN_W * size_of::<u16>(),
+                )
+            };
+        }
+
+        self.samples.resize(self.audio.queued(), [0; 2]);
+        let frames = self.audio.pull(&mut self.samples);
+        if let Some(batch) = callbacks.audio_sample_batch {
+            let mut sent = 0;
+            while sent < frames {
+                // SAFETY: `samples` holds `frames` interleaved stereo frames.
+                let n = unsafe { batch(self.samples[sent..].as_ptr().cast(), frames - sent) };
+                if n == 0 {
+                    break;
+                }
+                sent += n;
+            }
+        } else if let Some(sample) = callbacks.audio_sample {
+            for &[left, right] in &self.samples[..frames] {
+                // SAFETY: the frontend's callback, called as libretro.h documents.
+                unsafe { sample(left, right) };
+            }
+        }
+    }
+
+    // Copied in place: the frontend may hold on to the pointer it was given.
+    fn sync_save_ram(&mut self, data: &[u8]) {
+        self.save_ram_len = data.len().min(SAVE_RAM_MAX);
+        self.save_ram[..self.save_ram_len].copy_from_slice(&data[..self.save_ram_len]);
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> { mutex.lock().unwrap_or_else(|e| e.into_inner()) }
+
+fn callbacks() -> Callbacks { *lock(&CALLBACKS) }
+
+fn environment(cmd: u32, data: *mut c_void) -> bool {
+    match callbacks().environment {
+        // SAFETY: `data` points to what `cmd` expects; the callers below
+        // pass the types libretro.h lists for each command.
+        Some(env) => unsafe { env(cmd, data) },
+        None => false,
+    }
+}
+
+fn system_directory() -> Option<PathBuf> {
+    let mut dir: *const c_char = std::ptr::null();
+    if !environment(ffi::ENVIRONMENT_GET_SYSTEM_DIRECTORY, (&raw mut dir).cast()) || dir.is_null() {
+        return None;
+    }
+    // SAFETY: the frontend returned a NUL-terminated path.
+    let dir = unsafe { CStr::from_ptr(dir) };
+    Some(PathBuf::from(dir.to_string_lossy().into_owned()))
+}
+
+fn set_input_descriptors() {
+    const NAMES: [&CStr; 10] = [c"A", c"B", c"Select", c"Start", c"Right", c"Left", c"Up", c"Down", c"R", c"L"];
+    let mut descriptors: Vec<InputDescriptor> = JOYPAD_MAP
+        .iter()
+        .zip(NAMES)
+        .map(|(&(id, _), name)| InputDescriptor {
+            port: 0,
+            device: ffi::DEVICE_JOYPAD,
+            index: 0,
+            id,
+            description: name.as_ptr(),
+        })
+        .collect();
+    descriptors.push(InputDescriptor { port: 0, device: 0, index: 0, id: 0, description: std::ptr::null() });
+    environment(ffi::ENVIRONMENT_SET_INPUT_DESCRIPTORS, descriptors.as_mut_ptr().cast());
+}
+
+/// Splits a cheat from the frontend (codes joined with `+`) into lines.
+/// CodeBreaker codes have four-digit values; anything else is taken to be an
+/// encrypted GameShark code, as published codes usually are.
+pub fn parse_cheat(index: usize, code: &str) -> Option<Cheat> {
+    let code = code.replace('+', "\n");
+    let first = code.lines().map(str::trim).find(|line| !line.is_empty())?;
+    let value_digits = first.split_whitespace().nth(1).map_or(first.len().saturating_sub(8), str::len);
+    let format = if value_digits == 4 { CheatFormat::CodeBreaker } else { CheatFormat::GameSharkV1 };
+    match Cheat::parse(&format!("Cheat {}", index), format, format != CheatFormat::CodeBreaker, &code) {
+        Ok(cheat) => Some(cheat),
+        Err(e) => {
+            log::warn!("Ignoring cheat {}: {}", index, e);
+            None
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_api_version() -> u32 { ffi::API_VERSION }
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_environment(cb: Option<EnvironmentFn>) {
+    lock(&CALLBACKS).environment = cb;
+    let mut format = ffi::PIXEL_FORMAT_RGB565;
+    if !environment(ffi::ENVIRONMENT_SET_PIXEL_FORMAT, (&raw mut format).cast()) {
+        log::warn!("Frontend rejected RGB565 output");
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_video_refresh(cb: Option<VideoRefreshFn>) { lock(&CALLBACKS).video_refresh = cb; }
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_audio_sample(cb: Option<AudioSampleFn>) { lock(&CALLBACKS).audio_sample = cb; }
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_audio_sample_batch(cb: Option<AudioSampleBatchFn>) {
+    lock(&CALLBACKS).audio_sample_batch = cb;
+}
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_input_poll(cb: Option<InputPollFn>) { lock(&CALLBACKS).input_poll = cb; }
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_input_state(cb: Option<InputStateFn>) { lock(&CALLBACKS).input_state = cb; }
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_init() {}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_deinit() { *lock(&CORE) = None; }
+
+/// # Safety
+/// `info` must point to a writable `retro_system_info`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    // SAFETY: guaranteed by the caller.
+    let Some(info) = (unsafe { info.as_mut() }) else { return };
+    *info = SystemInfo {
+        library_name: c"RoBA".as_ptr(),
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast(),
+        valid_extensions: c"gba|bin|agb|mb|zip|gz".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+/// `info` must point to a writable `retro_system_av_info`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    let sample_rate = match lock(&CORE).as_ref() {
+        Some(core) => core.emu.apu().sample_rate(),
+        None => roba_core::config::DEFAULT_SAMPLE_RATE,
+    };
+    // SAFETY: guaranteed by the caller.
+    let Some(info) = (unsafe { info.as_mut() }) else { return };
+    *info = SystemAvInfo {
+        geometry: ffi::GameGeometry {
+            base_width: GBA_SCREEN_W as u32,
+            base_height: GBA_SCREEN_H as u32,
+            max_width: GBA_SCREEN_W as u32,
+            max_height: GBA_SCREEN_H as u32,
+            aspect_ratio: GBA_SCREEN_W as f32 / GBA_SCREEN_H as f32,
+        },
+        timing: ffi::SystemTiming { fps: FPS, sample_rate: f64::from(sample_rate) },
+    };
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_set_controller_port_device(_port: u32, _device: u32) {}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_reset() {
+    if let Some(core) = lock(&CORE).as_mut() {
+        core.emu.hard_reset();
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_run() {
+    let callbacks = callbacks();
+    if let Some(poll) = callbacks.input_poll {
+        // SAFETY: the frontend's callback, called as libretro.h documents.
+        unsafe { poll() };
+    }
+    if let Some(core) = lock(&CORE).as_mut() {
+        core.run_frame(&callbacks);
+    }
+}
+
+// States are the same size for the whole run of a game; frontends ask once
+// and keep the answer.
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_serialize_size() -> usize { lock(&CORE).as_ref().map_or(0, |core| core.emu.save_state().len()) }
+
+/// # Safety
+/// `data` must point to `size` writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let guard = lock(&CORE);
+    let Some(core) = guard.as_ref() else { return false };
+    let state = core.emu.save_state();
+    if data.is_null() || state.len() > size {
+        return false;
+    }
+    // SAFETY: guaranteed by the caller.
+    let out = unsafe { std::slice::from_raw_parts_mut(data.cast::<u8>(), size) };
+    // Loading ignores anything past the state, so the rest is zeroed.
+    let (head, tail) = out.split_at_mut(state.len());
+    head.copy_from_slice(&state);
+    tail.fill(0);
+    true
+}
+
+/// # Safety
+/// `data` must point to `size` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let mut guard = lock(&CORE);
+    let Some(core) = guard.as_mut() else { return false };
+    if data.is_null() {
+        return false;
+    }
+    // SAFETY: guaranteed by the caller.
+    let state = unsafe { std::slice::from_raw_parts(data.cast::<u8>(), size) };
+    match core.emu.load_state(state) {
+        Ok(()) => {
+            let data = core.emu.battery_save();
+            core.sync_save_ram(&data);
+            true
+        }
+        Err(e) => {
+            log::error!("Failed to load state: {}", e);
+            false
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_cheat_reset() {
+    if let Some(core) = lock(&CORE).as_mut() {
+        core.emu.cheats_mut().clear();
+    }
+}
+
+/// # Safety
+/// `code` must be NULL or a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_cheat_set(index: u32, enabled: bool, code: *const c_char) {
+    if code.is_null() {
+        return;
+    }
+    // SAFETY: guaranteed by the caller.
+    let code = unsafe { CStr::from_ptr(code) }.to_string_lossy();
+    if let Some(core) = lock(&CORE).as_mut()
+        && let Some(mut cheat) = parse_cheat(index as usize, &code)
+    {
+        cheat.enabled = enabled;
+        core.emu.cheats_mut().add(cheat);
+    }
+}
+
+/// # Safety
+/// `game` must be NULL or point to a `retro_game_info` whose `path` and
+/// `data` are valid as libretro.h describes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    // SAFETY: guaranteed by the caller.
+    let Some(game) = (unsafe { game.as_ref() }) else { return false };
+    let mut emu = Emulator::new();
+    if let Some(bios) = system_directory().map(|dir| dir.join(BIOS_FILE)).filter(|path| path.exists())
+        && let Err(e) = emu.load_bios(&bios)
+    {
+        log::warn!("Failed to load BIOS {:?}: {}", bios, e);
+    }
+
+    let loaded = if !game.data.is_null() {
+        // SAFETY: guaranteed by the caller.
+        let data = unsafe { std::slice::from_raw_parts(game.data.cast::<u8>(), game.size) };
+        // SAFETY: guaranteed by the caller.
+        let multiboot = !game.path.is_null() && unsafe { CStr::from_ptr(game.path) }.to_bytes().ends_with(b".mb");
+        archive::extract_rom(data).map_err(CoreError::from).and_then(|rom| {
+            if multiboot { emu.load_multiboot(&rom) } else { emu.load_rom_bytes(&rom) }
+        })
+    } else if !game.path.is_null() {
+        // SAFETY: guaranteed by the caller.
+        let path = unsafe { CStr::from_ptr(game.path) }.to_string_lossy().into_owned();
+        emu.load_rom(Path::new(&path))
+    } else {
+        return false;
+    };
+    if let Err(e) = loaded {
+        log::error!("Failed to load ROM: {}", e);
+        return false;
+    }
+
+    set_input_descriptors();
+    *lock(&CORE) = Some(Core::new(emu));
+    true
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_load_game_special(_game_type: u32, _info: *const GameInfo, _num_info: usize) -> bool { false }
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_unload_game() { *lock(&CORE) = None; }
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_get_region() -> u32 { ffi::REGION_NTSC }
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_get_memory_data(id: u32) -> *mut c_void {
+    match lock(&CORE).as_mut() {
+        Some(core) if id == ffi::MEMORY_SAVE_RAM && core.save_ram_len != 0 => core.save_ram.as_mut_ptr().cast(),
+        _ => std::ptr::null_mut(),
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn retro_get_memory_size(id: u32) -> usize {
+    match lock(&CORE).as_ref() {
+        Some(core) if id == ffi::MEMORY_SAVE_RAM => core.save_ram_len,
+        _ => 0,
+    }
+}